@@ -30,7 +30,7 @@ impl Encode for PortSegment {
             buf.put_u8(link_addr_len as u8);
         }
         if self.port > 14 {
-            buf.put_u16(self.port);
+            buf.put_u16_le(self.port);
         }
 
         buf.put_slice(&self.link);
@@ -13,7 +13,7 @@ pub fn cip_error<U: fmt::Display, E: Error>(msg: U) -> E {
 }
 
 pub fn cip_error_status<E: Error>(status: Status) -> E {
-    E::custom(format_args!("cip error: message reply status {}", status))
+    E::cip_status(status.general, status.extended, status)
 }
 
 pub fn cip_error_reply<E: Error>(reply_service: u8, expected_service: u8) -> E {
@@ -8,7 +8,7 @@ use crate::codec::decode::message_reply::decode_service_and_status;
 use crate::service::*;
 use crate::*;
 use crate::{epath::EPath, error::cip_error};
-use bytes::{Buf, BufMut, Bytes, BytesMut};
+use bytes::{Buf, BufMut, BytesMut};
 use rseip_core::codec::{BytesHolder, Decode, Decoder, Encode, Encoder, LittleEndianDecoder};
 use smallvec::SmallVec;
 
@@ -71,9 +71,8 @@ where
 
 pub struct ReplyIter<D> {
     buf: Option<D>,
-    offsets: Bytes,
+    offsets: Vec<u16>,
     count: Option<u16>,
-    last: Option<u16>,
     i: u16,
 }
 
@@ -81,9 +80,8 @@ impl<D> ReplyIter<D> {
     fn new(decoder: Option<D>) -> Self {
         Self {
             buf: decoder,
-            offsets: Bytes::new(),
+            offsets: Vec::new(),
             count: None,
-            last: None,
             i: 0,
         }
     }
@@ -99,6 +97,9 @@ where
     }
 
     /// decode next message reply from the multiple service reply
+    ///
+    /// each reply is decoded from its own slice of the buffer, so replies of
+    /// variable size or with an error status leave the following ones intact
     pub fn next<Item>(&mut self) -> Option<Result<MessageReply<Item>, D::Error>>
     where
         Item: Decode<'de>,
@@ -112,43 +113,35 @@ where
             }
             let count = buf.decode_u16();
             self.count = Some(count);
-            if count == 0 {
-                return None;
-            }
-            let data_offsets = 2 * (count) as usize;
-            if let Err(e) = buf.ensure_size(data_offsets) {
+            if let Err(e) = buf.ensure_size(2 * count as usize) {
                 return Some(Err(e));
             }
-            self.offsets = buf.buf_mut().copy_to_bytes(data_offsets);
+            self.offsets = (0..count).map(|_| buf.decode_u16()).collect();
             count
         };
-        if count == 0 {
+        if self.i >= count {
+            self.buf.take();
             return None;
         }
 
-        while self.i < count {
-            self.i += 1;
-            let offset = self.offsets.get_u16_le();
-            if let Some(last) = self.last.replace(offset) {
-                if offset <= last {
-                    return self.raise_err();
-                }
-                let size = (offset - last) as usize;
-                if buf.remaining() < size {
-                    return self.raise_err();
-                }
-                let res: Result<MessageReply<Item>, _> = buf.decode_any();
-                return Some(res);
-            }
-        }
-        // process remaining
-        if buf.remaining() > 0 {
-            let res: Result<MessageReply<Item>, _> = buf.decode_any();
-            self.buf.take();
-            return Some(res);
+        let i = self.i as usize;
+        self.i += 1;
+        // offsets count from the start of the reply, an item runs up to the
+        // next one or to the end of the reply
+        let size = match self.offsets.get(i + 1) {
+            Some(&next) => match next.checked_sub(self.offsets[i]) {
+                Some(size) => size as usize,
+                None => return self.raise_err(),
+            },
+            None => buf.buf().remaining(),
+        };
+        if buf.buf().remaining() < size {
+            return self.raise_err();
         }
-        self.buf.take();
-        None
+        let item = buf.buf_mut().copy_to_bytes(size);
+        let res: Result<MessageReply<Item>, _> =
+            LittleEndianDecoder::<D::Error>::new(item).decode_any();
+        Some(res)
     }
 }
 
@@ -260,3 +253,28 @@ where
         Ok(Self(MessageReply::new(reply_service, status, data)))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+    use rseip_core::tests::CodecError;
+
+    #[test]
+    fn test_reply_iter_variable_items() {
+        let reply = Bytes::from_static(&[
+            0x03, 0x00, // count
+            0x08, 0x00, 0x0E, 0x00, 0x12, 0x00, // offsets
+            0xCC, 0x00, 0x00, 0x00, 0xC4, 0x00, // DINT reply
+            0xCC, 0x00, 0x04, 0x00, // failed reply
+            0xCC, 0x00, 0x00, 0x00, 0xC1, 0x00, 0x01, // BOOL reply
+        ]);
+        let mut iter = ReplyIter::new(Some(LittleEndianDecoder::<CodecError>::new(reply)));
+        let first: MessageReply<BytesHolder> = iter.next().unwrap().unwrap();
+        assert_eq!(&Bytes::from(first.data)[..], &[0xC4, 0x00]);
+        assert!(iter.next::<BytesHolder>().unwrap().is_err());
+        let last: MessageReply<BytesHolder> = iter.next().unwrap().unwrap();
+        assert_eq!(&Bytes::from(last.data)[..], &[0xC1, 0x00, 0x01]);
+        assert!(iter.next::<BytesHolder>().is_none());
+    }
+}
@@ -48,60 +48,117 @@ impl Status {
     }
 }
 
+impl Status {
+    /// name of the general status, EIP-CIP-V1 Appendix B
+    pub fn name(&self) -> &'static str {
+        match self.general {
+            0x00 => "success",
+            0x01 => "connection failure",
+            0x02 => "resource unavailable",
+            0x03 => "invalid parameter value",
+            0x04 => "path segment error",
+            0x05 => "path destination unknown",
+            0x06 => "partial transfer",
+            0x07 => "connection lost",
+            0x08 => "service not supported",
+            0x09 => "invalid attribute value",
+            0x0A => "attribute list error",
+            0x0B => "already in requested mode or state",
+            0x0C => "object state conflict",
+            0x0D => "object already exists",
+            0x0E => "attribute not settable",
+            0x0F => "privilege violation",
+            0x10 => "device state conflict",
+            0x11 => "reply data too large",
+            0x12 => "fragmentation of a primitive value",
+            0x13 => "not enough data",
+            0x14 => "attribute not supported",
+            0x15 => "too much data",
+            0x16 => "object does not exist",
+            0x17 => "service fragmentation sequence not in progress",
+            0x18 => "no stored attribute data",
+            0x19 => "store operation failure",
+            0x1A => "routing failure, request too large",
+            0x1B => "routing failure, response too large",
+            0x1C => "missing attribute list entry data",
+            0x1D => "invalid attribute value list",
+            0x1E => "embedded service error",
+            0x1F => "vendor specific error",
+            0x20 => "invalid parameter",
+            0x21 => "write-once value already written",
+            0x22 => "invalid reply received",
+            0x25 => "key failure in path",
+            0x26 => "path size invalid",
+            0x27 => "unexpected attribute in list",
+            0x28 => "invalid member id",
+            0x29 => "member not settable",
+            0xFF => "general error",
+            _ => "unknown status",
+        }
+    }
+
+    /// what the status usually means for a request to a Logix controller
+    #[cfg(feature = "error-explain")]
+    pub fn explain(&self) -> Option<&'static str> {
+        let msg = match (self.general, self.extended) {
+            (0x01, Some(0x0100)) => "the connection is already in use",
+            (0x01, Some(0x0106)) => "another originator owns the connection",
+            (0x01, Some(0x0107)) => {
+                "the target doesn't know the connection, it was closed or timed out"
+            }
+            (0x01, Some(0x0108)) => "the connection type isn't supported",
+            (0x01, Some(0x0109)) => "the connection size isn't supported",
+            (0x01, Some(0x0111)) => "the requested packet interval isn't supported",
+            (0x01, Some(0x0113)) => "the target is out of connections",
+            (0x01, Some(0x0114)) => "the vendor id or product code doesn't match",
+            (0x01, Some(0x0116)) => "the revision doesn't match",
+            (0x01, Some(0x0117)) => "the connection point is invalid",
+            (0x01, Some(0x0203)) => "the connection timed out",
+            (0x01, Some(0x0204)) => "the request timed out on its route to the target",
+            (0x01, Some(0x0301)) => "the target has no buffer memory left",
+            (0x01, Some(0x0302)) => "the network bandwidth isn't available",
+            (0x01, Some(0x0311)) => "a port of the route doesn't exist",
+            (0x01, Some(0x0312)) => "a node address of the route doesn't exist",
+            (0x01, Some(0x0315)) => "a segment of the route is invalid",
+            (0x02, _) => "the target is out of resources for the request",
+            (0x04, _) => "the tag or object doesn't exist, or its path is malformed",
+            (0x05, _) => "the tag or object instance doesn't exist",
+            (0x07, _) => "the connection carrying the request was lost",
+            (0x08, _) => "the target doesn't support the service for this object",
+            (0x0A, _) => "an attribute of the list couldn't be read or written",
+            (0x0F, _) => {
+                "the target refused access, e.g. to a constant tag or one without external access"
+            }
+            (0x10, Some(0x2101)) => "the keyswitch is in RUN, which doesn't allow the change",
+            (0x10, Some(0x2802)) => "the safety memory can't change in the current safety state",
+            (0x10, _) => "the controller's mode or keyswitch doesn't allow the request",
+            (0x11, _) => "the reply doesn't fit in a message, request fewer elements",
+            (0x13, _) => "the request is too short for the service",
+            (0x14, _) => "the object doesn't have the attribute",
+            (0x15, _) => "the request carries more data than the service takes",
+            (0x1E, _) => "a service of the Multiple Service Packet failed",
+            (0x26, _) => "the path size doesn't match the path",
+            (0xFF, Some(0x2104)) => "the offset is beyond the end of the tag",
+            (0xFF, Some(0x2105)) => "the elements requested extend beyond the end of the tag",
+            (0xFF, Some(0x2107)) => "the data type of the request doesn't match the tag",
+            _ => return None,
+        };
+        Some(msg)
+    }
+}
+
 impl fmt::Display for Status {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "CIP general status: {}", self.general)?;
-        if let Some(v) = self.extended {
-            write!(f, ", extended status: {}", v)?;
-        }
+        write!(f, "{}", self.name())?;
         #[cfg(feature = "error-explain")]
-        {
-            let msg = match self.general {
-                0x00 => return Ok(()),
-                0x01 => match self.extended {
-                    Some(0x0103) => "Transport class and trigger combination not supported",
-                    Some(0x0204) => "timeout",
-                    Some(0x0205) => "Invalid SocketAddr Info item",
-                    Some(0x0302) => "Network bandwidth not available for data",
-                    Some(0x0311) => "Invalid Port ID specified in the Route_Path field",
-                    Some(0x0312) => "Invalid Node Address specified in the Route_Path field",
-                    Some(0x0315) =>"Invalid segment type in the Route_Path field",
-                    _ => "Connection failure",
-                },
-                0x02 => "Resource error", // processing unconnected send request
-                0x03 => "Bad parameter",
-                0x04 => "Request path segment error",
-                0x05 => "Request path destination unknown", //Probably instance number is not present
-                0x06 => "Partial transfer",
-                0x07 => "Connection lost",
-                0x08 => "Service not supported",
-                0x09 => "Invalid attribute value",
-                0x0A => "Attribute list error",
-                0x0B => "Already in requested mode/state",
-                0x0C => "Object state conflict",
-                0x0D => "Object already exists",
-                0x0E => "Attribute not settable",
-                0x0F => "Privilege violation",
-                0x10 => match self.extended {
-                    Some(0x2101) => "Device state conflict: keyswitch position: The requestor is changing force information in HARD RUN mode",
-                    Some(0x2802) => "Device state conflict: Safety Status: Unable to modify Safety Memory in the current controller state",
-                    _ => "Device state conflict",
-                },
-                0x11 => "Reply data too large",
-                0x13 => "Insufficient Request Data: Data too short for expected parameters",
-                0x014 => "Attribute not supported",
-                0x26 => "The Request Path Size received was shorter or longer than expected",
-                0xFF => match self.extended {
-                        Some(0x2104) =>  "Offset is beyond end of the requested tag",
-                        Some(0x2105) =>  "Number of Elements extends beyond the end of the requested tag",
-                        Some(0x2107) =>  "Tag type used in request does not match the data type of the target tag",
-                        _ =>  "General Error: Unknown",
-                },
-                _ => "General Error: Unknown",
-            };
-            write!(f, "\n\t{}", msg)?;
+        if let Some(msg) = self.explain() {
+            write!(f, ": {}", msg)?;
+        }
+        write!(f, " (CIP status {:#04x}", self.general)?;
+        if let Some(v) = self.extended {
+            write!(f, ", extended status {:#06x}", v)?;
         }
-        Ok(())
+        write!(f, ")")
     }
 }
 
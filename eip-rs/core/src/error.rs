@@ -14,6 +14,25 @@ pub trait Error: Sized + StdError + From<io::Error> {
     /// Raised when there is general error when decoding a type.
     fn custom<T: fmt::Display>(msg: T) -> Self;
 
+    /// Raised when the target replies with a CIP general status other than
+    /// success, described by `msg`.
+    fn cip_status<T: fmt::Display>(general: u8, extended: Option<u16>, msg: T) -> Self {
+        let _ = (general, extended);
+        Self::custom(msg)
+    }
+
+    /// Raised when the target replies with an encapsulation status other
+    /// than success, described by `msg`.
+    fn encapsulation_status<T: fmt::Display>(status: u16, msg: T) -> Self {
+        let _ = status;
+        Self::custom(msg)
+    }
+
+    /// Raised when the transport closes before the target replies.
+    fn transport_closed() -> Self {
+        Self::custom("transport closed")
+    }
+
     /// Raised when receives a type different from what it was expecting.
     fn invalid_type<U: fmt::Display, E: fmt::Display>(unexp: U, exp: E) -> Self {
         Self::custom(format_args!("invalid type: {}, expected {}", unexp, exp))
@@ -100,7 +100,7 @@ where
                 pkt.hdr.ensure_command::<E>(code)?;
                 Ok(pkt)
             }
-            None => Err(E::custom("transport closed")),
+            None => Err(E::transport_closed()),
         }
     }
 
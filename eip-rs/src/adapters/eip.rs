@@ -100,8 +100,13 @@ where
     where
         P: Encode + Send + Sync,
     {
+        let service_code = if request.large_open {
+            SERVICE_LARGE_FORWARD_OPEN
+        } else {
+            SERVICE_FORWARD_OPEN
+        };
         let req: MessageRequest<&[u8], _> = MessageRequest {
-            service_code: SERVICE_FORWARD_OPEN,
+            service_code,
             path: EPATH_CONNECTION_MANAGER,
             data: request,
         };
@@ -18,7 +18,7 @@ use rseip_cip::Status;
 pub use rseip_eip::EipContext;
 pub use service::*;
 use std::net::SocketAddrV4;
-pub use symbol::{GetInstanceAttributeList, SymbolInstance};
+pub use symbol::{GetInstanceAttributeList, SymbolInstance, SymbolType};
 pub use template::AbTemplateService;
 use tokio::net::TcpStream;
 pub use value::*;
@@ -46,7 +46,7 @@ pub struct AbEipDriver;
 
 impl Driver for AbEipDriver {
     type Endpoint = SocketAddrV4;
-    type Service = EipContext<TcpStream, ClientError>;
+    type Service = EipContext<tap::Tapped<TcpStream>, ClientError>;
 
     #[inline]
     fn build_service(addr: Self::Endpoint) -> BoxFuture<'static, Result<Self::Service>> {
@@ -54,8 +54,18 @@ pub enum TagType {
     Dint,
     /// atomic data type: LINT, 64-bit integer
     Lint,
+    /// atomic data type: USINT, unsigned 8-bit integer
+    Usint,
+    /// atomic data type: UINT, unsigned 16-bit integer
+    Uint,
+    /// atomic data type: UDINT, unsigned 32-bit integer
+    Udint,
+    /// atomic data type: ULINT, unsigned 64-bit integer
+    Ulint,
     /// atomic data type: REAL, 32-bit float
     Real,
+    /// atomic data type: LREAL, 64-bit float
+    Lreal,
     /// structured tag
     Structure(u16),
 }
@@ -71,7 +81,12 @@ impl TagType {
             Self::Int => 0xC3,
             Self::Dint => 0xC4,
             Self::Lint => 0xC5,
+            Self::Usint => 0xC6,
+            Self::Uint => 0xC7,
+            Self::Udint => 0xC8,
+            Self::Ulint => 0xC9,
             Self::Real => 0xCA,
+            Self::Lreal => 0xCB,
             Self::Structure { .. } => 0x02A0,
         }
     }
@@ -127,8 +142,23 @@ impl Encode for TagType {
             Self::Lint => {
                 encoder.encode_u16(0xC5, buf)?;
             }
+            Self::Usint => {
+                encoder.encode_u16(0xC6, buf)?;
+            }
+            Self::Uint => {
+                encoder.encode_u16(0xC7, buf)?;
+            }
+            Self::Udint => {
+                encoder.encode_u16(0xC8, buf)?;
+            }
+            Self::Ulint => {
+                encoder.encode_u16(0xC9, buf)?;
+            }
+            Self::Lreal => {
+                encoder.encode_u16(0xCB, buf)?;
+            }
             Self::Structure(handle) => {
-                encoder.encode(&[0xA0, 0x02], buf)?;
+                encoder.encode_u16(0x02A0, buf)?;
                 encoder.encode_u16(*handle, buf)?;
             }
         }
@@ -159,6 +189,11 @@ impl<'de> Decode<'de> for TagType {
             0xCA => TagType::Real,
             0xD3 => TagType::Dword,
             0xC5 => TagType::Lint,
+            0xC6 => TagType::Usint,
+            0xC7 => TagType::Uint,
+            0xC8 => TagType::Udint,
+            0xC9 => TagType::Ulint,
+            0xCB => TagType::Lreal,
             0xC1 => TagType::Bool,
             0x02A0 => {
                 decoder.ensure_size(2)?;
@@ -237,6 +272,7 @@ impl_atomic!(u32, 4);
 impl_atomic!(i64, 8);
 impl_atomic!(u64, 8);
 impl_atomic!(f32, 4);
+impl_atomic!(f64, 8);
 
 macro_rules! impl_seq {
     ($ty:tt) => {
@@ -4,7 +4,7 @@
 // Copyright: 2021, Joylei <leingliu@gmail.com>
 // License: MIT
 
-use super::*;
+use super::{tap::Tapped, *};
 use futures_util::future::BoxFuture;
 pub use rseip_eip::{consts::*, EipContext};
 use std::{
@@ -26,13 +26,13 @@ pub struct EipDriver;
 
 impl Driver for EipDriver {
     type Endpoint = SocketAddrV4;
-    type Service = EipContext<TcpStream, ClientError>;
+    type Service = EipContext<Tapped<TcpStream>, ClientError>;
 
     fn build_service(addr: Self::Endpoint) -> BoxFuture<'static, Result<Self::Service>> {
         let fut = async move {
             let socket = TcpSocket::new_v4()?;
             let stream = socket.connect(addr.into()).await?;
-            let service = EipContext::new(stream);
+            let service = EipContext::new(Tapped::new(stream, addr));
             Ok(service)
         };
         Box::pin(fut)
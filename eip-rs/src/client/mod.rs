@@ -8,6 +8,8 @@
 pub mod ab_eip;
 /// generic EIP
 pub mod eip;
+/// tap of the client traffic
+pub mod tap;
 
 use crate::{adapters::Service, ClientError, Result};
 pub use ab_eip::{AbEipClient, AbEipConnection, AbEipDriver, AbService, AbTemplateService};
@@ -151,6 +153,9 @@ impl<B: Driver> MessageService for Client<B> {
     }
 }
 
+/// largest connection size of a regular forward open
+const MAX_CONNECTION_SIZE: u16 = 505;
+
 /// explicit messaging connection
 #[derive(Debug)]
 pub struct Connection<B: Driver> {
@@ -238,8 +243,18 @@ impl<B: Driver> Connection<B> {
         self.ensure_service().await?;
         let service = self.service.as_mut().expect("expected service");
         if self.connected_options.is_none() {
-            let reply = service.forward_open(self.origin_options.clone()).await?;
-            match reply.into_value() {
+            let mut reply = service.forward_open(self.origin_options.clone()).await;
+            let opened = matches!(reply, Ok(ref reply) if reply.0.data.left().is_some());
+            if self.origin_options.large_open && !opened {
+                // target does not support large forward open, fall back to a regular one
+                self.origin_options = self
+                    .origin_options
+                    .clone()
+                    .large_open(false)
+                    .connection_size(MAX_CONNECTION_SIZE.min(self.origin_options.o_t_params.connection_size));
+                reply = service.forward_open(self.origin_options.clone()).await;
+            }
+            match reply?.into_value() {
                 Either::Left(reply) => {
                     let opts = self
                         .origin_options
@@ -330,6 +345,20 @@ impl<B: Driver> MessageService for Connection<B> {
 #[derive(Debug)]
 pub struct MaybeConnected<B: Driver>(Either<Client<B>, Connection<B>>);
 
+impl<B: Driver> From<Client<B>> for MaybeConnected<B> {
+    #[inline]
+    fn from(client: Client<B>) -> Self {
+        Self(Either::Left(client))
+    }
+}
+
+impl<B: Driver> From<Connection<B>> for MaybeConnected<B> {
+    #[inline]
+    fn from(connection: Connection<B>) -> Self {
+        Self(Either::Right(connection))
+    }
+}
+
 impl<B: Driver> Deref for MaybeConnected<B> {
     type Target = Either<Client<B>, Connection<B>>;
     fn deref(&self) -> &Self::Target {
@@ -0,0 +1,107 @@
+// rseip
+//
+// rseip - Ethernet/IP (CIP) in pure Rust.
+// Copyright: 2021, Joylei <leingliu@gmail.com>
+// License: MIT
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use std::{
+    io,
+    net::SocketAddrV4,
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        OnceLock,
+    },
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// what happened on a stream of an EIP client
+#[derive(Debug, Clone, Copy)]
+pub enum TapEvent<'a> {
+    /// the stream connected to the endpoint
+    Opened(SocketAddrV4),
+    /// bytes written to the stream
+    Sent(&'a [u8]),
+    /// bytes read from the stream
+    Received(&'a [u8]),
+}
+
+type Tap = Box<dyn Fn(u32, TapEvent<'_>) + Send + Sync>;
+
+static TAP: OnceLock<Tap> = OnceLock::new();
+static NEXT_STREAM: AtomicU32 = AtomicU32::new(1);
+
+/// hand the traffic of every EIP client stream opened from now on to `tap`,
+/// with the number of the stream, e.g. to record it;
+/// returns false when a tap is already set
+pub fn set_tap(tap: impl Fn(u32, TapEvent<'_>) + Send + Sync + 'static) -> bool {
+    TAP.set(Box::new(tap)).is_ok()
+}
+
+/// stream passing its traffic to the tap, if any
+#[derive(Debug)]
+pub struct Tapped<S> {
+    inner: S,
+    id: u32,
+}
+
+impl<S> Tapped<S> {
+    /// wrap a stream connected to `addr`
+    pub fn new(inner: S, addr: SocketAddrV4) -> Self {
+        let id = NEXT_STREAM.fetch_add(1, Ordering::Relaxed);
+        emit(id, TapEvent::Opened(addr));
+        Self { inner, id }
+    }
+}
+
+#[inline]
+fn emit(id: u32, event: TapEvent<'_>) {
+    if let Some(tap) = TAP.get() {
+        tap(id, event);
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for Tapped<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+        let res = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = res {
+            let read = &buf.filled()[filled..];
+            if !read.is_empty() {
+                emit(self.id, TapEvent::Received(read));
+            }
+        }
+        res
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for Tapped<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let res = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(n)) = res {
+            emit(self.id, TapEvent::Sent(&buf[..n]));
+        }
+        res
+    }
+
+    #[inline]
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    #[inline]
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -4,6 +4,7 @@
 // Copyright: 2021, Joylei <leingliu@gmail.com>
 // License: MIT
 
+use crate::cip::Status;
 use crate::client::ab_eip::PathError;
 use core::fmt;
 use rseip_core::{Error, String};
@@ -14,6 +15,16 @@ use std::io;
 pub enum ClientError {
     Io { kind: &'static str, err: io::Error },
     Custom { kind: &'static str, msg: String },
+    /// the target replied with a CIP error status
+    Status { kind: &'static str, status: Status },
+    /// the target replied with an encapsulation error status
+    Encapsulation {
+        kind: &'static str,
+        status: u16,
+        msg: String,
+    },
+    /// the transport closed before the target replied
+    Closed { kind: &'static str },
 }
 
 impl ClientError {
@@ -21,6 +32,9 @@ impl ClientError {
         match self {
             Self::Io { kind, .. } => kind,
             Self::Custom { kind, .. } => kind,
+            Self::Status { kind, .. } => kind,
+            Self::Encapsulation { kind, .. } => kind,
+            Self::Closed { kind } => kind,
         }
     }
 
@@ -28,6 +42,17 @@ impl ClientError {
         match self {
             Self::Io { kind, .. } => kind,
             Self::Custom { kind, .. } => kind,
+            Self::Status { kind, .. } => kind,
+            Self::Encapsulation { kind, .. } => kind,
+            Self::Closed { kind } => kind,
+        }
+    }
+
+    /// the CIP status the target replied with, if it replied with an error
+    pub fn status(&self) -> Option<Status> {
+        match self {
+            Self::Status { status, .. } => Some(*status),
+            _ => None,
         }
     }
 }
@@ -38,7 +63,19 @@ impl fmt::Display for ClientError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Self::Io { kind, err } => write!(f, "{} - {}", kind, err),
+            // plain custom errors carry their whole description
+            Self::Custom { kind: "custom", msg } => write!(f, "{}", msg),
             Self::Custom { kind, msg } => write!(f, "{} - {}", kind, msg),
+            Self::Status { kind: "custom", status } => write!(f, "{}", status),
+            Self::Status { kind, status } => write!(f, "{} - {}", kind, status),
+            Self::Encapsulation {
+                kind: "custom",
+                msg,
+                ..
+            } => write!(f, "{}", msg),
+            Self::Encapsulation { kind, msg, .. } => write!(f, "{} - {}", kind, msg),
+            Self::Closed { kind: "custom" } => write!(f, "transport closed"),
+            Self::Closed { kind } => write!(f, "{} - transport closed", kind),
         }
     }
 }
@@ -55,6 +92,25 @@ impl Error for ClientError {
             msg: msg.to_string().into(),
         }
     }
+
+    fn cip_status<T: core::fmt::Display>(general: u8, extended: Option<u16>, _msg: T) -> Self {
+        Self::Status {
+            kind: "custom",
+            status: Status { general, extended },
+        }
+    }
+
+    fn encapsulation_status<T: core::fmt::Display>(status: u16, msg: T) -> Self {
+        Self::Encapsulation {
+            kind: "custom",
+            status,
+            msg: msg.to_string().into(),
+        }
+    }
+
+    fn transport_closed() -> Self {
+        Self::Closed { kind: "custom" }
+    }
 }
 
 impl From<io::Error> for ClientError {
@@ -222,6 +222,7 @@ MIT
 
 #![allow(clippy::match_like_matches_macro)]
 
+pub extern crate bytes;
 pub extern crate futures_util;
 
 /// adapters
@@ -238,7 +239,7 @@ pub type Result<T> = core::result::Result<T, ClientError>;
 pub use core::result::Result as StdResult;
 pub use rseip_core::{
     codec::BytesHolder,
-    codec::{Decode, Encode},
+    codec::{Decode, Decoder, Encode, Encoder},
     Either, String, StringExt,
 };
 
@@ -0,0 +1,594 @@
+use crate::historian::Journal;
+#[cfg(feature = "historian")]
+use crate::historian::{self, ExportFormat};
+use crate::monitor::{Quality, Sample};
+use crate::notify::NotifyConfig;
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Utc};
+use clap::Subcommand;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, SystemTime};
+
+/// Default location of the alarm state file.
+pub const DEFAULT_STATE_FILE: &str = "cobalt-alarms.json";
+
+/// State file of the alarms of one controller of a multi-target monitor,
+/// e.g. `cobalt-alarms.line1.json`.
+pub fn target_state_file(path: &Path, target: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, target, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, target),
+    };
+    path.with_file_name(name)
+}
+
+/// Suffix of the alarm raised while the value of a tag is frozen.
+const FROZEN_SUFFIX: &str = " frozen";
+
+const FROZEN_MESSAGE: &str = "value frozen, check the transmitter and the communication path";
+
+/// Name of the alarm raised while the value of a tag is frozen.
+fn frozen_alarm_name(tag: &str) -> String {
+    format!("{}{}", tag, FROZEN_SUFFIX)
+}
+
+/// Comparison operator of an alarm condition.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+    Ne,
+}
+
+impl Comparison {
+    const ALL: [(&'static str, Comparison); 6] = [
+        (">=", Comparison::Ge),
+        ("<=", Comparison::Le),
+        ("==", Comparison::Eq),
+        ("!=", Comparison::Ne),
+        (">", Comparison::Gt),
+        ("<", Comparison::Lt),
+    ];
+
+    fn symbol(&self) -> &'static str {
+        Self::ALL.iter().find(|(_, c)| c == self).unwrap().0
+    }
+
+    /// Find the leftmost operator in `s`, preferring two-character ones.
+    /// Returns its position, its length and the comparison.
+    pub fn find(s: &str) -> Option<(usize, usize, Comparison)> {
+        Self::ALL
+            .iter()
+            .filter_map(|(symbol, comparison)| {
+                s.find(symbol).map(|idx| (idx, symbol.len(), *comparison))
+            })
+            .min_by_key(|(idx, len, _)| (*idx, usize::MAX - len))
+    }
+
+    pub fn compare(&self, left: f64, right: f64) -> bool {
+        match self {
+            Comparison::Gt => left > right,
+            Comparison::Ge => left >= right,
+            Comparison::Lt => left < right,
+            Comparison::Le => left <= right,
+            Comparison::Eq => left == right,
+            Comparison::Ne => left != right,
+        }
+    }
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.symbol())
+    }
+}
+
+/// A `tag <op> setpoint` condition, e.g. `PT_101 > 80`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    pub tag: String,
+    pub comparison: Comparison,
+    pub setpoint: f64,
+}
+
+impl Condition {
+    pub fn is_met(&self, value: f64) -> bool {
+        self.comparison.compare(value, self.setpoint)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (idx, len, comparison) = Comparison::find(s)
+            .ok_or_else(|| anyhow!("no comparison operator in condition `{}`", s))?;
+        let tag = s[..idx].trim();
+        let setpoint = s[idx + len..].trim();
+        if tag.is_empty() {
+            bail!("missing tag in condition `{}`", s);
+        }
+        let setpoint = match setpoint {
+            "true" => 1.0,
+            "false" => 0.0,
+            v => v
+                .parse()
+                .with_context(|| format!("invalid setpoint in condition `{}`", s))?,
+        };
+        Ok(Self {
+            tag: tag.to_owned(),
+            comparison,
+            setpoint,
+        })
+    }
+}
+
+impl Display for Condition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {} {}", self.tag, self.comparison, self.setpoint)
+    }
+}
+
+impl<'de> Deserialize<'de> for Condition {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// An alarm rule from the rules file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AlarmRule {
+    pub name: String,
+    pub condition: Condition,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// An alarm rules file: one `[[alarm]]` table per rule, and the
+/// notifications of their events.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RulesFile {
+    #[serde(default, rename = "alarm")]
+    pub alarms: Vec<AlarmRule>,
+    pub notify: Option<NotifyConfig>,
+}
+
+impl RulesFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read alarm rules {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("failed to parse alarm rules {}", path.display()))
+    }
+}
+
+/// Persisted state of a single alarm.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AlarmState {
+    pub active: bool,
+    pub acked: bool,
+    pub shelved_until: Option<DateTime<Utc>>,
+    pub changed_at: Option<DateTime<Utc>>,
+}
+
+impl Default for AlarmState {
+    /// An alarm that never went off has nothing to acknowledge.
+    fn default() -> Self {
+        Self {
+            active: false,
+            acked: true,
+            shelved_until: None,
+            changed_at: None,
+        }
+    }
+}
+
+impl AlarmState {
+    pub fn is_shelved(&self, now: DateTime<Utc>) -> bool {
+        self.shelved_until.map(|until| until > now).unwrap_or(false)
+    }
+}
+
+/// Alarm states keyed by alarm name, stored as JSON so they survive restarts
+/// and can be edited by `alarms ack`/`alarms shelve` while a monitor runs.
+struct StateStore {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl StateStore {
+    fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_owned(),
+            modified: None,
+        }
+    }
+
+    fn mtime(&self) -> Option<SystemTime> {
+        std::fs::metadata(&self.path)
+            .and_then(|m| m.modified())
+            .ok()
+    }
+
+    /// Load the states if the file changed since the last load or save.
+    fn load_if_changed(&mut self) -> Result<Option<BTreeMap<String, AlarmState>>> {
+        let mtime = self.mtime();
+        if mtime.is_some() && mtime == self.modified {
+            return Ok(None);
+        }
+        let states = self.load()?;
+        self.modified = mtime;
+        Ok(Some(states))
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, AlarmState>> {
+        match std::fs::read_to_string(&self.path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("failed to parse alarm state {}", self.path.display())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(e)
+                .with_context(|| format!("failed to read alarm state {}", self.path.display())),
+        }
+    }
+
+    fn save(&mut self, states: &BTreeMap<String, AlarmState>) -> Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(states)?)?;
+        std::fs::rename(&tmp, &self.path)
+            .with_context(|| format!("failed to write alarm state {}", self.path.display()))?;
+        self.modified = self.mtime();
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AlarmEventKind {
+    Raised,
+    Cleared,
+    Acknowledged,
+    Shelved,
+    Unshelved,
+}
+
+/// An alarm transition or operator action, as reported to the outputs.
+#[derive(Debug, Clone, Serialize)]
+pub struct AlarmEvent {
+    pub timestamp: DateTime<Utc>,
+    pub name: String,
+    pub event: AlarmEventKind,
+    pub message: String,
+    pub value: Option<f64>,
+    #[serde(flatten)]
+    pub state: AlarmState,
+}
+
+impl AlarmEvent {
+    /// Print the event as a highlighted line.
+    pub fn print(&self) {
+        let label = match self.event {
+            AlarmEventKind::Raised => "ALARM".bold().red(),
+            AlarmEventKind::Cleared => "CLEARED".bold().green(),
+            AlarmEventKind::Acknowledged => "ACKED".bold().yellow(),
+            AlarmEventKind::Shelved => "SHELVED".bold().cyan(),
+            AlarmEventKind::Unshelved => "UNSHELVED".bold().cyan(),
+        };
+        let value = self.value.map(|v| format!(" ({})", v)).unwrap_or_default();
+        println!(
+            "[{}]    {}    {}{}    {}",
+            self.timestamp.with_timezone(&chrono::Local),
+            label,
+            self.name.bold(),
+            value,
+            self.message
+        );
+    }
+}
+
+/// Evaluates alarm rules against polled samples and tracks their
+/// acknowledgment and shelving state.
+pub struct AlarmEngine {
+    rules: Vec<AlarmRule>,
+    states: BTreeMap<String, AlarmState>,
+    store: StateStore,
+}
+
+impl AlarmEngine {
+    pub fn new(rules: Vec<AlarmRule>, state_path: &Path) -> Result<Self> {
+        let mut store = StateStore::new(state_path);
+        let states = store.load_if_changed()?.unwrap_or_default();
+        Ok(Self {
+            rules,
+            states,
+            store,
+        })
+    }
+
+    /// Evaluate the rules against a polling cycle. Operator actions made
+    /// through the state file since the last cycle are reported as well.
+    /// Raised/cleared transitions of shelved alarms are tracked but not
+    /// reported.
+    pub fn evaluate(&mut self, samples: &[Sample]) -> Result<Vec<AlarmEvent>> {
+        let now = Utc::now();
+        let mut events = Vec::new();
+        let mut dirty = false;
+
+        if let Some(stored) = self.store.load_if_changed()? {
+            for (name, new) in &stored {
+                let message = match self.rules.iter().find(|rule| &rule.name == name) {
+                    Some(rule) => rule.message.as_str(),
+                    None if name.ends_with(FROZEN_SUFFIX) => FROZEN_MESSAGE,
+                    None => continue,
+                };
+                let old = self.states.entry(name.clone()).or_default();
+                let mut kinds = Vec::new();
+                if !old.acked && new.acked {
+                    kinds.push(AlarmEventKind::Acknowledged);
+                }
+                match (old.shelved_until, new.shelved_until) {
+                    (None, Some(_)) => kinds.push(AlarmEventKind::Shelved),
+                    (Some(_), None) => kinds.push(AlarmEventKind::Unshelved),
+                    _ => {}
+                }
+                old.acked = new.acked;
+                old.shelved_until = new.shelved_until;
+                for kind in kinds {
+                    events.push(Self::event(name, message, kind, None, old, now));
+                }
+            }
+        }
+
+        for rule in &self.rules {
+            let sample = match samples.iter().find(|s| s.tag == rule.condition.tag) {
+                Some(sample) => sample,
+                None => continue,
+            };
+            let value = sample.value.as_f64();
+            let met = rule.condition.is_met(value);
+            dirty |= Self::transition(
+                &mut self.states,
+                &rule.name,
+                &rule.message,
+                met,
+                value,
+                now,
+                &mut events,
+            );
+        }
+
+        // Quality downgrades raise an alarm of their own, which is only
+        // tracked once it went off.
+        for sample in samples {
+            let name = frozen_alarm_name(&sample.tag);
+            let frozen = sample.quality == Quality::Frozen;
+            if !frozen && !self.states.contains_key(&name) {
+                continue;
+            }
+            let value = sample.value.as_f64();
+            dirty |= Self::transition(
+                &mut self.states,
+                &name,
+                FROZEN_MESSAGE,
+                frozen,
+                value,
+                now,
+                &mut events,
+            );
+        }
+
+        if dirty {
+            self.store.save(&self.states)?;
+        }
+        Ok(events)
+    }
+
+    /// Update the state of an alarm from its condition, returning whether
+    /// it changed.
+    fn transition(
+        states: &mut BTreeMap<String, AlarmState>,
+        name: &str,
+        message: &str,
+        met: bool,
+        value: f64,
+        now: DateTime<Utc>,
+        events: &mut Vec<AlarmEvent>,
+    ) -> bool {
+        let state = states.entry(name.to_owned()).or_default();
+        let mut dirty = false;
+
+        if matches!(state.shelved_until, Some(until) if until <= now) {
+            state.shelved_until = None;
+            dirty = true;
+            events.push(Self::event(
+                name,
+                message,
+                AlarmEventKind::Unshelved,
+                Some(value),
+                state,
+                now,
+            ));
+        }
+
+        let kind = if met && !state.active {
+            state.active = true;
+            state.acked = false;
+            AlarmEventKind::Raised
+        } else if !met && state.active {
+            state.active = false;
+            AlarmEventKind::Cleared
+        } else {
+            return dirty;
+        };
+        state.changed_at = Some(now);
+        if !state.is_shelved(now) {
+            events.push(Self::event(name, message, kind, Some(value), state, now));
+        }
+        true
+    }
+
+    fn event(
+        name: &str,
+        message: &str,
+        event: AlarmEventKind,
+        value: Option<f64>,
+        state: &AlarmState,
+        timestamp: DateTime<Utc>,
+    ) -> AlarmEvent {
+        AlarmEvent {
+            timestamp,
+            name: name.to_owned(),
+            event,
+            message: message.to_owned(),
+            value,
+            state: state.clone(),
+        }
+    }
+}
+
+/// Operator actions on the persisted alarm states.
+#[derive(Subcommand)]
+pub enum AlarmAction {
+    /// Show the state of every known alarm.
+    Status,
+    /// Acknowledge an alarm.
+    Ack { name: String },
+    /// Suppress notifications of an alarm for a duration, e.g. `30m` or `8h`.
+    Shelve {
+        name: String,
+        #[arg(long = "for", value_parser = humantime::parse_duration)]
+        duration: Duration,
+    },
+    /// Remove the shelve of an alarm.
+    Unshelve { name: String },
+    /// Export alarm transitions and operator actions from the journal.
+    #[cfg(feature = "historian")]
+    Export {
+        /// Start of the exported period, as YYYY-MM-DD or RFC 3339.
+        #[arg(long, value_parser = historian::parse_time)]
+        from: Option<DateTime<Utc>>,
+        /// End of the exported period (exclusive), as YYYY-MM-DD or RFC 3339.
+        #[arg(long, value_parser = historian::parse_time)]
+        to: Option<DateTime<Utc>>,
+        #[arg(long, value_enum, default_value = "csv")]
+        format: ExportFormat,
+        /// Output file, standard output if omitted.
+        #[arg(long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// Run an operator action against the state file at `path`. Changes are
+/// recorded in the journal along with the user who made them.
+pub fn run_action(path: &Path, journal: &Journal, action: &AlarmAction) -> Result<()> {
+    #[cfg(feature = "historian")]
+    if let AlarmAction::Export {
+        from,
+        to,
+        format,
+        output,
+    } = action
+    {
+        let entries = journal.read_range(*from, *to)?;
+        match output {
+            Some(path) => {
+                let mut file = std::fs::File::create(path)?;
+                historian::export(&entries, *format, &mut file)?;
+                println!("Exported {} entries to {}.", entries.len(), path.display());
+            }
+            None => historian::export(&entries, *format, &mut std::io::stdout())?,
+        }
+        return Ok(());
+    }
+
+    let mut store = StateStore::new(path);
+    let mut states = store.load()?;
+    let now = Utc::now();
+
+    let name = match action {
+        AlarmAction::Status => {
+            for (name, state) in &states {
+                let status = match (state.active, state.acked) {
+                    (true, false) => "ACTIVE UNACKED".bold().red(),
+                    (true, true) => "ACTIVE".bold().yellow(),
+                    (false, false) => "CLEARED UNACKED".bold().yellow(),
+                    (false, true) => "NORMAL".bold().green(),
+                };
+                let shelved = match state.shelved_until {
+                    Some(until) if until > now => {
+                        format!("    shelved until {}", until.with_timezone(&chrono::Local))
+                    }
+                    _ => String::new(),
+                };
+                println!("    {:<24}{}{}", name.bold(), status, shelved);
+            }
+            return Ok(());
+        }
+        AlarmAction::Ack { name }
+        | AlarmAction::Shelve { name, .. }
+        | AlarmAction::Unshelve { name } => name,
+        #[cfg(feature = "historian")]
+        AlarmAction::Export { .. } => unreachable!(),
+    };
+
+    let state = states
+        .get_mut(name)
+        .ok_or_else(|| anyhow!("unknown alarm {}", name))?;
+    let (verb, detail) = match action {
+        AlarmAction::Ack { .. } => {
+            state.acked = true;
+            ("ack", None)
+        }
+        AlarmAction::Shelve { duration, .. } => {
+            state.shelved_until = Some(now + chrono::Duration::from_std(*duration)?);
+            (
+                "shelve",
+                Some(humantime::format_duration(*duration).to_string()),
+            )
+        }
+        AlarmAction::Unshelve { .. } => {
+            state.shelved_until = None;
+            ("unshelve", None)
+        }
+        AlarmAction::Status => unreachable!(),
+        #[cfg(feature = "historian")]
+        AlarmAction::Export { .. } => unreachable!(),
+    };
+    store.save(&states)?;
+    journal.record_operator(verb, name, detail)?;
+    println!("Alarm {} updated.", name.bold());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_condition() {
+        let c: Condition = "PT_101 >= 80.5".parse().unwrap();
+        assert_eq!(c.tag, "PT_101");
+        assert_eq!(c.comparison, Comparison::Ge);
+        assert_eq!(c.setpoint, 80.5);
+        assert!(c.is_met(80.5));
+
+        let c: Condition = "Program:Main.Run!=true".parse().unwrap();
+        assert_eq!(c.tag, "Program:Main.Run");
+        assert_eq!(c.comparison, Comparison::Ne);
+        assert_eq!(c.setpoint, 1.0);
+
+        assert!("PT_101 80".parse::<Condition>().is_err());
+        assert!("> 80".parse::<Condition>().is_err());
+    }
+}
@@ -0,0 +1,202 @@
+use crate::historian::current_user;
+use crate::value::PlcValue;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+
+/// Default location of the audit file.
+pub const DEFAULT_AUDIT_FILE: &str = "cobalt-audit.jsonl";
+
+/// A write to a controller tag, as stored in the audit file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: DateTime<Utc>,
+    pub user: String,
+    pub plc: String,
+    pub tag: String,
+    /// Value before the write, if it could be read.
+    pub old: Option<serde_json::Value>,
+    pub new: serde_json::Value,
+    /// `ok`, or `failed` with the error.
+    pub result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    pub fn new(
+        plc: &str,
+        tag: &str,
+        old: Option<PlcValue>,
+        new: PlcValue,
+        result: &Result<()>,
+    ) -> Self {
+        Self {
+            timestamp: Utc::now(),
+            user: current_user(),
+            plc: plc.to_owned(),
+            tag: tag.to_owned(),
+            old: old.map(Into::into),
+            new: new.into(),
+            result: if result.is_ok() { "ok" } else { "failed" }.to_owned(),
+            error: result.as_ref().err().map(|e| format!("{:#}", e)),
+        }
+    }
+}
+
+static AUDIT_FILE: OnceLock<PathBuf> = OnceLock::new();
+
+/// Value and outcome of the last write recorded for each controller and
+/// tag, for `Audit::record_change`.
+type LastWrites = HashMap<(String, String), (serde_json::Value, String)>;
+
+static LAST_WRITES: Mutex<Option<LastWrites>> = Mutex::new(None);
+
+/// Record the writes of this run to the audit file at `path`.
+pub fn init(path: &Path) {
+    AUDIT_FILE.get_or_init(|| path.to_owned());
+}
+
+/// Append-only audit file, opened before a write so that a write that
+/// couldn't be recorded isn't made at all.
+pub struct Audit {
+    file: Option<(PathBuf, File)>,
+}
+
+impl Audit {
+    pub fn open() -> Result<Self> {
+        let path = match AUDIT_FILE.get() {
+            Some(path) => path,
+            None => return Ok(Self { file: None }),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| {
+                format!(
+                    "failed to open audit file {}, nothing was written",
+                    path.display()
+                )
+            })?;
+        Ok(Self {
+            file: Some((path.clone(), file)),
+        })
+    }
+
+    /// Record a write made every cycle only when its value or its outcome
+    /// differs from the last write recorded for the tag, which the entry
+    /// gives as the value replaced. A loop writing the same value over and
+    /// over adds one entry, not one per cycle.
+    pub fn record_change(&mut self, plc: &str, tag: &str, new: &PlcValue, result: &Result<()>) {
+        let mut entry = AuditEntry::new(plc, tag, None, new.clone(), result);
+        let key = (entry.plc.clone(), entry.tag.clone());
+        let last = LAST_WRITES
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(|writes| writes.get(&key).cloned());
+        if let Some((value, outcome)) = last {
+            if value == entry.new && outcome == entry.result {
+                return;
+            }
+            entry.old = Some(value);
+        }
+        self.record(&entry);
+    }
+
+    pub fn record(&mut self, entry: &AuditEntry) {
+        LAST_WRITES
+            .lock()
+            .unwrap()
+            .get_or_insert_with(HashMap::new)
+            .insert(
+                (entry.plc.clone(), entry.tag.clone()),
+                (entry.new.clone(), entry.result.clone()),
+            );
+        if let Some((path, file)) = &mut self.file {
+            let written = serde_json::to_string(entry)
+                .map_err(std::io::Error::from)
+                .and_then(|line| writeln!(file, "{}", line));
+            if let Err(e) = written {
+                tracing::error!(
+                    "failed to audit the write of {} to {}: {}",
+                    entry.tag,
+                    path.display(),
+                    e
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+    use serde_json::json;
+
+    #[test]
+    fn audit_entries() {
+        let ok = AuditEntry::new(
+            "10.1.1.5",
+            "FT_201_SP",
+            Some(PlcValue::Real(12.5)),
+            PlcValue::Real(15.0),
+            &Ok(()),
+        );
+        assert_eq!(ok.old, Some(json!(12.5)));
+        let line = serde_json::to_value(&ok).unwrap();
+        assert_eq!(line["result"], "ok");
+        assert!(line.get("error").is_none());
+
+        let failed = AuditEntry::new(
+            "10.1.1.5",
+            "Pump_Run",
+            None,
+            PlcValue::Bool(true),
+            &Err(anyhow!("privilege violation")),
+        );
+        assert_eq!(failed.result, "failed");
+        assert_eq!(failed.error.as_deref(), Some("privilege violation"));
+    }
+
+    #[test]
+    fn changes_of_cyclic_writes() {
+        let path = std::env::temp_dir().join(format!("cobalt-audit-{}.jsonl", std::process::id()));
+        let file = File::create(&path).unwrap();
+        let mut audit = Audit {
+            file: Some((path.clone(), file)),
+        };
+        let plc = "cyclic";
+        for value in [12.5, 12.5, 15.0, 15.0] {
+            audit.record_change(plc, "FT_201", &PlcValue::Real(value), &Ok(()));
+        }
+        let refused = Err(anyhow!("privilege violation"));
+        audit.record_change(plc, "FT_201", &PlcValue::Real(15.0), &refused);
+        audit.record_change(plc, "FT_201", &PlcValue::Real(15.0), &refused);
+        let entries: Vec<AuditEntry> = std::fs::read_to_string(&path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        std::fs::remove_file(&path).unwrap();
+        let written: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.old.clone(), entry.new.clone(), entry.result.as_str()))
+            .collect();
+        assert_eq!(
+            written,
+            [
+                (None, json!(12.5), "ok"),
+                (Some(json!(12.5)), json!(15.0), "ok"),
+                (Some(json!(15.0)), json!(15.0), "failed"),
+            ]
+        );
+    }
+}
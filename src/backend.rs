@@ -0,0 +1,75 @@
+use crate::value::PlcValue;
+use crate::{modbus_tcp, s7};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use rseip::client::ab_eip::PathParser;
+use rseip::precludes::*;
+use serde::Deserialize;
+use std::fmt::Display;
+
+/// Protocol the controllers are reached over.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Protocol {
+    /// Allen-Bradley controllers, over EtherNet/IP.
+    #[default]
+    Ab,
+    /// Siemens S7-1200 and S7-1500, over ISO-on-TCP.
+    S7,
+    /// Modbus TCP devices.
+    ModbusTcp,
+}
+
+impl Protocol {
+    /// Check that a tag is addressed the way the protocol addresses tags,
+    /// without asking a controller.
+    pub fn check_tag(self, tag: &str) -> Result<()> {
+        match self {
+            Protocol::Ab => EPath::parse_tag(tag)
+                .map(|_| ())
+                .map_err(|e| anyhow!("{}", e)),
+            Protocol::S7 => s7::check_address(tag),
+            Protocol::ModbusTcp => modbus_tcp::check_address(tag),
+        }
+    }
+}
+
+impl Display for Protocol {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Protocol::Ab => "EtherNet/IP",
+            Protocol::S7 => "S7",
+            Protocol::ModbusTcp => "Modbus TCP",
+        })
+    }
+}
+
+/// Reads and writes of a controller reached over another protocol than
+/// EtherNet/IP. Tags are addresses in the syntax of the protocol, and every
+/// request goes through `plc::transaction` like the EtherNet/IP ones.
+#[async_trait]
+pub trait PlcBackend: Send {
+    /// Read the value at an address, typed by the address.
+    async fn read(&mut self, tag: &str) -> Result<PlcValue>;
+
+    /// Read several addresses, one request each unless the protocol can
+    /// pack them together.
+    async fn read_many(&mut self, tags: &[String]) -> Vec<Result<PlcValue>> {
+        let mut values = Vec::with_capacity(tags.len());
+        for tag in tags {
+            values.push(self.read(tag).await);
+        }
+        values
+    }
+
+    /// Requests `read_many` makes for the addresses, for load estimates.
+    fn request_count(&self, tags: &[String]) -> usize {
+        tags.len()
+    }
+
+    /// Write a value of the type of the address.
+    async fn write(&mut self, tag: &str, value: PlcValue) -> Result<()>;
+
+    async fn close(&mut self) -> Result<()>;
+}
@@ -0,0 +1,151 @@
+use crate::plc::{self, Plc};
+use crate::value;
+use anyhow::{bail, Result};
+use clap::Args;
+use colored::*;
+use std::time::{Duration, Instant};
+
+/// Options of the `bench` command.
+#[derive(Args)]
+pub struct BenchArgs {
+    /// Tag read every iteration, addressed like the tags of `read`.
+    #[arg(long)]
+    pub tag: String,
+    /// Number of timed requests.
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    pub iterations: u32,
+    /// Read the tag this many times per request, packed into Multiple
+    /// Service Packets like the reads of `monitor` and `bridge-write`.
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    pub multi: u32,
+    /// Also time writes, writing back the value the tag held.
+    #[arg(long)]
+    pub write: bool,
+}
+
+/// Round trips of one kind of request.
+#[derive(Debug, PartialEq)]
+struct Stats {
+    min: Duration,
+    avg: Duration,
+    p95: Duration,
+    max: Duration,
+    /// Requests answered per second, back to back.
+    rate: f64,
+}
+
+impl Stats {
+    fn new(mut samples: Vec<Duration>) -> Stats {
+        samples.sort();
+        let total: Duration = samples.iter().sum();
+        let p95 = (samples.len() * 95).div_ceil(100).max(1) - 1;
+        Stats {
+            min: samples[0],
+            avg: total / samples.len() as u32,
+            p95: samples[p95],
+            max: samples[samples.len() - 1],
+            rate: samples.len() as f64 / total.as_secs_f64(),
+        }
+    }
+}
+
+/// Time reads of a tag, then writes with `--write`, one request after the
+/// other, to size the polling of the controller in the messaging mode of the
+/// command line.
+pub async fn run(client: &mut Plc, args: &BenchArgs) -> Result<()> {
+    if args.write {
+        if plc::dry_run() {
+            bail!("--dry-run writes nothing to time, leave out --write");
+        }
+        client.check_writable(Some(&args.tag))?;
+    }
+    if args.multi > 1 && client.backend().is_none() && !client.family().packs_requests() {
+        bail!(
+            "{} controllers answer one service per request, leave out --multi",
+            client.family()
+        );
+    }
+    // Opens the session and the connection, which aren't timed.
+    let value = value::read_value(client, &args.tag).await?;
+    let tags = vec![args.tag.clone(); args.multi as usize];
+
+    let mut samples = Vec::with_capacity(args.iterations as usize);
+    for _ in 0..args.iterations {
+        let started = Instant::now();
+        if args.multi > 1 {
+            for result in value::read_values(client, &tags).await {
+                result?;
+            }
+        } else {
+            value::read_value(client, &args.tag).await?;
+        }
+        samples.push(started.elapsed());
+    }
+    println!("{}", mode(client).bold());
+    print("Read", &Stats::new(samples), args.multi);
+
+    if args.write {
+        let mut samples = Vec::with_capacity(args.iterations as usize);
+        for _ in 0..args.iterations {
+            let started = Instant::now();
+            value::write_unaudited(client, &args.tag, value.clone()).await?;
+            samples.push(started.elapsed());
+        }
+        print("Write", &Stats::new(samples), 1);
+    }
+    Ok(())
+}
+
+/// How the requests reached the controller.
+fn mode(client: &mut Plc) -> String {
+    if client.backend().is_some() {
+        return format!("{} requests to {}", client.protocol(), client.address());
+    }
+    let mode = if client.connected() {
+        "Connected"
+    } else {
+        "Unconnected"
+    };
+    format!("{} requests to {}", mode, client.address())
+}
+
+fn print(name: &str, stats: &Stats, multi: u32) {
+    let millis = |elapsed: Duration| elapsed.as_secs_f64() * 1000.0;
+    println!(
+        "{:<7}min {:.1} ms, avg {:.1} ms, p95 {:.1} ms, max {:.1} ms",
+        format!("{}:", name),
+        millis(stats.min),
+        millis(stats.avg),
+        millis(stats.p95),
+        millis(stats.max)
+    );
+    if multi > 1 {
+        println!(
+            "       {:.0} requests/s, {:.0} tags/s",
+            stats.rate,
+            stats.rate * multi as f64
+        );
+    } else {
+        println!("       {:.0} requests/s", stats.rate);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn stats() {
+        let samples = (1..=100).rev().map(Duration::from_millis).collect();
+        let stats = Stats::new(samples);
+        assert_eq!(stats.min, Duration::from_millis(1));
+        assert_eq!(stats.avg, Duration::from_micros(50_500));
+        assert_eq!(stats.p95, Duration::from_millis(95));
+        assert_eq!(stats.max, Duration::from_millis(100));
+        assert!((stats.rate - 100.0 / 5.05).abs() < 1e-9);
+
+        let stats = Stats::new(vec![Duration::from_millis(4)]);
+        assert_eq!(stats.p95, Duration::from_millis(4));
+        assert_eq!(stats.rate, 250.0);
+    }
+}
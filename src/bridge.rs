@@ -0,0 +1,1612 @@
+use crate::aga3::OrificeConfig;
+use crate::alarm;
+use crate::backend::Protocol;
+use crate::expr::Expr;
+use crate::gas::{self, Analysis, Gas};
+#[cfg(feature = "influx")]
+use crate::influx::{InfluxArgs, InfluxSink};
+#[cfg(feature = "influx")]
+use crate::monitor::{Quality, Sample};
+use crate::plc::{self, Plc};
+use crate::service;
+use crate::tags::{deserialize_duration, serialize_duration};
+use crate::timing::{self, CycleStats, StatsArgs};
+use crate::totalizer::{Totalizer, TotalizerConfig};
+use crate::value::{self, PlcValue};
+use anyhow::{anyhow, bail, Context as _, Result};
+use clap::{Args, ValueEnum};
+use colored::*;
+use rseip::client::ab_eip::TagType;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::f32::consts::PI;
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::*;
+use tokio_serial::{DataBits, SerialPortBuilder, SerialStream, StopBits};
+
+/// Pause between two bridge cycles.
+const CYCLE: Duration = Duration::from_millis(500);
+
+/// Time between two saves of the bridge state while it runs.
+const SAVE_EVERY: Duration = Duration::from_secs(10);
+
+/// Default location of the bridge state file.
+pub const DEFAULT_STATE_FILE: &str = "cobalt-bridge.json";
+
+/// Rate at base conditions when the settings don't give an expression.
+pub const DEFAULT_RATE_BASE: &str = "aga8_rate(velocity, diameter, pressure, temperature)";
+
+/// Rate at base conditions of a meter with an orifice plate when the
+/// settings don't give an expression.
+pub const DEFAULT_RATE_BASE_ORIFICE: &str =
+    "aga3_rate(differential_pressure, pressure, temperature, orifice_diameter, diameter)";
+
+/// Values expressions can use before any is derived.
+pub const SIGNALS: [&str; 5] = ["velocity", "rate", "pressure", "temperature", "diameter"];
+
+/// Further values of a meter with an orifice plate.
+pub const ORIFICE_SIGNALS: [&str; 2] = ["differential_pressure", "orifice_diameter"];
+
+/// Settings of the Modbus RTU bridge. The top level describes the first
+/// meter on the line, `[[meter]]` tables the others.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeConfig {
+    /// Serial port of the Modbus RTU link.
+    pub port: String,
+    /// Modbus slave id of the flow meter.
+    pub slave: u8,
+    pub baudrate: u32,
+    #[serde(default)]
+    pub parity: Parity,
+    /// 1 or 2.
+    #[serde(default = "default_stop_bits")]
+    pub stop_bits: u8,
+    /// 5 to 8.
+    #[serde(default = "default_data_bits")]
+    pub data_bits: u8,
+    #[serde(default)]
+    pub flow_control: FlowControl,
+    /// Holding register of the gas velocity, in m/s.
+    pub rtu_register_velocity: u16,
+    /// Holding register of the flow rate.
+    pub rtu_register_rate: u16,
+    /// REAL tag holding the line pressure, in barg.
+    pub pressure_tag: String,
+    /// REAL tag holding the line temperature, in degC.
+    pub temperature_tag: String,
+    /// Inner pipe diameter, in inches.
+    pub diameter: f32,
+    /// REAL tag receiving the rate at base conditions.
+    pub rate_tag_base: String,
+    /// REAL tag receiving the meter rate.
+    pub rate_tag: String,
+    /// Conversion of the register values to engineering units.
+    #[serde(default)]
+    pub scaling: BridgeScaling,
+    /// Expression of the rate at base conditions written to `rate_tag_base`.
+    #[serde(default = "default_rate_base")]
+    pub rate_base: String,
+    /// Further values derived each cycle, in order, and written to the
+    /// controller.
+    #[serde(default, rename = "output", skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<BridgeOutput>,
+    /// Whether the pressure tags read gauge or absolute pressures.
+    #[serde(default)]
+    pub pressure_mode: PressureMode,
+    /// Atmospheric pressure of the site, in bar.
+    #[serde(default = "default_atmospheric")]
+    pub atmospheric: f32,
+    /// What the bridge does when a meter can't be read.
+    #[serde(default)]
+    pub stale: StaleConfig,
+    /// BOOL tags set true while the value of a written tag is fresh and
+    /// false while it is stale, keyed by the written tag.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quality: BTreeMap<String, String>,
+    /// Total at base conditions accumulated from the rate at base
+    /// conditions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totalizer: Option<TotalizerConfig>,
+    /// Orifice plate the rate at base conditions is worked out for with
+    /// AGA3, rather than the velocity.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orifice: Option<OrificeConfig>,
+    /// Analysis of the gas the AGA8 values are worked out for, the default
+    /// one when not given.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub composition: Option<CompositionConfig>,
+    /// DINT tag incremented every cycle, so the controller can tell the
+    /// bridge is alive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heartbeat: Option<String>,
+    /// File keeping the last values, totals and heartbeat across restarts.
+    #[serde(default = "default_state_file")]
+    pub state: PathBuf,
+    /// Further meters on the same serial line, polled in turn after the
+    /// first.
+    #[serde(default, rename = "meter", skip_serializing_if = "Vec::is_empty")]
+    pub meters: Vec<MeterConfig>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    #[default]
+    None,
+    Odd,
+    Even,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum FlowControl {
+    #[default]
+    None,
+    /// XON/XOFF.
+    Software,
+    /// RTS/CTS.
+    Hardware,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PressureMode {
+    /// barg, above the atmosphere.
+    #[default]
+    Gauge,
+    /// bara, from which the atmospheric pressure is taken off.
+    Absolute,
+}
+
+fn default_atmospheric() -> f32 {
+    (gas::STANDARD_ATMOSPHERE / 100.0) as f32
+}
+
+fn default_state_file() -> PathBuf {
+    PathBuf::from(DEFAULT_STATE_FILE)
+}
+
+fn default_stop_bits() -> u8 {
+    1
+}
+
+fn default_data_bits() -> u8 {
+    8
+}
+
+/// Settings of one meter of the bridge, as in the top level of its
+/// settings.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MeterConfig {
+    pub slave: u8,
+    pub rtu_register_velocity: u16,
+    pub rtu_register_rate: u16,
+    pub pressure_tag: String,
+    pub temperature_tag: String,
+    pub diameter: f32,
+    pub rate_tag_base: String,
+    pub rate_tag: String,
+    #[serde(default)]
+    pub scaling: BridgeScaling,
+    #[serde(default = "default_rate_base")]
+    pub rate_base: String,
+    #[serde(default, rename = "output", skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<BridgeOutput>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub quality: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub totalizer: Option<TotalizerConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub orifice: Option<OrificeConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub composition: Option<CompositionConfig>,
+}
+
+/// Handling of a meter read that failed or timed out.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StaleConfig {
+    #[serde(default)]
+    pub action: StaleAction,
+    /// Value written in place of every written tag by the `fallback` action.
+    #[serde(default)]
+    pub fallback: f32,
+    /// Time to wait for the meter to answer, e.g. `1s`.
+    #[serde(
+        default = "default_meter_timeout",
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub timeout: Duration,
+}
+
+fn default_meter_timeout() -> Duration {
+    Duration::from_secs(1)
+}
+
+impl Default for StaleConfig {
+    fn default() -> Self {
+        Self {
+            action: StaleAction::default(),
+            fallback: 0.0,
+            timeout: default_meter_timeout(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleAction {
+    /// Stop the bridge, after clearing the quality flags.
+    #[default]
+    Abort,
+    /// Leave the last values in the controller and keep polling.
+    Hold,
+    /// Write the fallback value and keep polling.
+    Fallback,
+}
+
+fn default_rate_base() -> String {
+    DEFAULT_RATE_BASE.to_owned()
+}
+
+/// Analysis of the gas of a meter, in mole fractions or percent, with
+/// components fixed or read from tags, e.g. those a gas chromatograph
+/// writes to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CompositionConfig {
+    /// Time between two reads of the tags, e.g. `5m`.
+    #[serde(
+        default = "default_analysis_interval",
+        deserialize_with = "deserialize_duration",
+        serialize_with = "serialize_duration"
+    )]
+    pub interval: Duration,
+    /// Amount of each component, named like `methane` or `n_butane`.
+    pub components: BTreeMap<String, Component>,
+}
+
+fn default_analysis_interval() -> Duration {
+    Duration::from_secs(60)
+}
+
+/// Amount of a component of the gas, a number or the tag holding it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Component {
+    Fixed(f64),
+    Tag(String),
+}
+
+impl CompositionConfig {
+    /// The analysis with the amounts of the tagged components looked up.
+    fn analysis(&self, tagged: impl Fn(&str) -> f64) -> Result<Analysis> {
+        let amounts: Vec<(&str, f64)> = self
+            .components
+            .iter()
+            .map(|(name, component)| match component {
+                Component::Fixed(amount) => (name.as_str(), *amount),
+                Component::Tag(tag) => (name.as_str(), tagged(tag)),
+            })
+            .collect();
+        Analysis::from_fractions(&amounts)
+    }
+
+    fn tags(&self) -> impl Iterator<Item = &String> {
+        self.components
+            .values()
+            .filter_map(|component| match component {
+                Component::Tag(tag) => Some(tag),
+                Component::Fixed(_) => None,
+            })
+    }
+}
+
+/// A REAL tag written with the value of an expression over the signals,
+/// `rate_base` and the outputs before it, named by their tags.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeOutput {
+    pub tag: String,
+    pub expr: String,
+}
+
+/// A derived value, with the setting it comes from.
+pub struct Derived {
+    pub key: String,
+    /// Name later expressions use for the value.
+    pub name: String,
+    pub tag: String,
+    pub expr: Result<Expr>,
+}
+
+/// Linear conversion of a register value from device units to engineering
+/// units, `raw * scale + offset`, clamped to `min` and `max` when given.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scaling {
+    #[serde(default = "unit_scale")]
+    pub scale: f32,
+    #[serde(default)]
+    pub offset: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f32>,
+}
+
+fn unit_scale() -> f32 {
+    1.0
+}
+
+impl Default for Scaling {
+    fn default() -> Self {
+        Self {
+            scale: unit_scale(),
+            offset: 0.0,
+            min: None,
+            max: None,
+        }
+    }
+}
+
+impl Scaling {
+    pub fn apply(&self, raw: f32) -> f32 {
+        let mut value = raw * self.scale + self.offset;
+        // Comparisons keep a NaN from a bad reading visible.
+        if let Some(min) = self.min {
+            if value < min {
+                value = min;
+            }
+        }
+        if let Some(max) = self.max {
+            if value > max {
+                value = max;
+            }
+        }
+        value
+    }
+}
+
+/// Scaling of each register read from the meter.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BridgeScaling {
+    #[serde(default)]
+    pub velocity: Scaling,
+    #[serde(default)]
+    pub rate: Scaling,
+}
+
+impl BridgeConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read bridge settings {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("failed to parse bridge settings {}", path.display()))
+    }
+
+    /// Settings of the serial line.
+    pub fn serial(&self) -> Result<SerialPortBuilder> {
+        serial_port(
+            &self.port,
+            self.baudrate,
+            self.parity,
+            self.data_bits,
+            self.stop_bits,
+            self.flow_control,
+        )
+    }
+
+    /// Every meter of the line, the one of the top level first.
+    pub fn all_meters(&self) -> Vec<MeterConfig> {
+        let first = MeterConfig {
+            slave: self.slave,
+            rtu_register_velocity: self.rtu_register_velocity,
+            rtu_register_rate: self.rtu_register_rate,
+            pressure_tag: self.pressure_tag.clone(),
+            temperature_tag: self.temperature_tag.clone(),
+            diameter: self.diameter,
+            rate_tag_base: self.rate_tag_base.clone(),
+            rate_tag: self.rate_tag.clone(),
+            scaling: self.scaling.clone(),
+            rate_base: self.rate_base.clone(),
+            outputs: self.outputs.clone(),
+            quality: self.quality.clone(),
+            totalizer: self.totalizer.clone(),
+            orifice: self.orifice.clone(),
+            composition: self.composition.clone(),
+        };
+        std::iter::once(first)
+            .chain(self.meters.iter().cloned())
+            .collect()
+    }
+}
+
+impl MeterConfig {
+    /// Parse the rate at base conditions and the outputs in the order they
+    /// are evaluated, checking each only uses values known by then.
+    pub fn derived(&self) -> Vec<Derived> {
+        let mut known: Vec<&str> = SIGNALS.to_vec();
+        let mut rate_base = &self.rate_base;
+        let orifice_rate_base = DEFAULT_RATE_BASE_ORIFICE.to_owned();
+        if self.orifice.is_some() {
+            known.extend(ORIFICE_SIGNALS);
+            if self.rate_base == DEFAULT_RATE_BASE {
+                rate_base = &orifice_rate_base;
+            }
+        }
+        let mut derived = Vec::new();
+        let exprs = std::iter::once(("rate_base".to_owned(), "rate_base", rate_base)).chain(
+            self.outputs.iter().enumerate().map(|(idx, output)| {
+                (
+                    format!("output[{}]", idx),
+                    output.tag.as_str(),
+                    &output.expr,
+                )
+            }),
+        );
+        for (key, name, text) in exprs {
+            let expr = text.parse::<Expr>().and_then(|expr| {
+                match expr
+                    .variables()
+                    .into_iter()
+                    .find(|var| !known.contains(var))
+                {
+                    Some(var) => bail!("unknown value `{}`", var),
+                    None => Ok(expr),
+                }
+            });
+            known.push(name);
+            let tag = if key == "rate_base" {
+                self.rate_tag_base.clone()
+            } else {
+                name.to_owned()
+            };
+            derived.push(Derived {
+                key,
+                name: name.to_owned(),
+                tag,
+                expr,
+            });
+        }
+        derived
+    }
+}
+
+/// Settings of a serial line, checking the number of data and stop bits.
+pub fn serial_port(
+    port: &str,
+    baudrate: u32,
+    parity: Parity,
+    data_bits: u8,
+    stop_bits: u8,
+    flow_control: FlowControl,
+) -> Result<SerialPortBuilder> {
+    let data_bits = match data_bits {
+        5 => DataBits::Five,
+        6 => DataBits::Six,
+        7 => DataBits::Seven,
+        8 => DataBits::Eight,
+        other => bail!("{} data bits, use 5 to 8", other),
+    };
+    let stop_bits = match stop_bits {
+        1 => StopBits::One,
+        2 => StopBits::Two,
+        other => bail!("{} stop bits, use 1 or 2", other),
+    };
+    let parity = match parity {
+        Parity::None => tokio_serial::Parity::None,
+        Parity::Odd => tokio_serial::Parity::Odd,
+        Parity::Even => tokio_serial::Parity::Even,
+    };
+    let flow_control = match flow_control {
+        FlowControl::None => tokio_serial::FlowControl::None,
+        FlowControl::Software => tokio_serial::FlowControl::Software,
+        FlowControl::Hardware => tokio_serial::FlowControl::Hardware,
+    };
+    Ok(tokio_serial::new(port, baudrate)
+        .data_bits(data_bits)
+        .stop_bits(stop_bits)
+        .parity(parity)
+        .flow_control(flow_control))
+}
+
+/// Bridge settings, given either as arguments or as a settings file.
+#[derive(Args)]
+pub struct BridgeArgs {
+    /// Bridge settings file, replacing the positional arguments.
+    #[arg(long, conflicts_with = "port")]
+    pub config: Option<PathBuf>,
+    #[arg(required_unless_present = "config")]
+    pub port: Option<String>,
+    #[arg(required_unless_present = "config")]
+    pub slave: Option<u8>,
+    #[arg(required_unless_present = "config")]
+    pub baudrate: Option<u32>,
+    #[arg(required_unless_present = "config")]
+    pub rtu_register_velocity: Option<u16>,
+    #[arg(required_unless_present = "config")]
+    pub rtu_register_rate: Option<u16>,
+    #[arg(required_unless_present = "config")]
+    pub pressure_tag: Option<String>,
+    #[arg(required_unless_present = "config")]
+    pub temperature_tag: Option<String>,
+    #[arg(required_unless_present = "config")]
+    pub diameter: Option<f32>,
+    #[arg(required_unless_present = "config")]
+    pub rate_tag_base: Option<String>,
+    #[arg(required_unless_present = "config")]
+    pub rate_tag: Option<String>,
+    /// Parity of the serial line [default: none].
+    #[arg(long, value_enum, conflicts_with = "config")]
+    pub parity: Option<Parity>,
+    /// Stop bits of the serial line [default: 1].
+    #[arg(long, conflicts_with = "config", value_parser = clap::value_parser!(u8).range(1..=2))]
+    pub stop_bits: Option<u8>,
+    /// Data bits of the serial line [default: 8].
+    #[arg(long, conflicts_with = "config", value_parser = clap::value_parser!(u8).range(5..=8))]
+    pub data_bits: Option<u8>,
+    /// Flow control of the serial line [default: none].
+    #[arg(long, value_enum, conflicts_with = "config")]
+    pub flow_control: Option<FlowControl>,
+    /// Whether the pressure tag reads gauge or absolute pressure [default:
+    /// gauge].
+    #[arg(long, value_enum, conflicts_with = "config")]
+    pub pressure_mode: Option<PressureMode>,
+    /// Atmospheric pressure of the site in bar, lower at altitude [default:
+    /// 1.01325].
+    #[arg(long, conflicts_with = "config")]
+    pub atmospheric: Option<f32>,
+}
+
+impl BridgeArgs {
+    pub fn resolve(&self) -> Result<BridgeConfig> {
+        if let Some(path) = &self.config {
+            return BridgeConfig::load(path);
+        }
+        match self {
+            BridgeArgs {
+                port: Some(port),
+                slave: Some(slave),
+                baudrate: Some(baudrate),
+                rtu_register_velocity: Some(rtu_register_velocity),
+                rtu_register_rate: Some(rtu_register_rate),
+                pressure_tag: Some(pressure_tag),
+                temperature_tag: Some(temperature_tag),
+                diameter: Some(diameter),
+                rate_tag_base: Some(rate_tag_base),
+                rate_tag: Some(rate_tag),
+                parity,
+                stop_bits,
+                data_bits,
+                flow_control,
+                pressure_mode,
+                atmospheric,
+                ..
+            } => Ok(BridgeConfig {
+                port: port.clone(),
+                slave: *slave,
+                baudrate: *baudrate,
+                parity: parity.unwrap_or_default(),
+                stop_bits: stop_bits.unwrap_or_else(default_stop_bits),
+                data_bits: data_bits.unwrap_or_else(default_data_bits),
+                flow_control: flow_control.unwrap_or_default(),
+                rtu_register_velocity: *rtu_register_velocity,
+                rtu_register_rate: *rtu_register_rate,
+                pressure_tag: pressure_tag.clone(),
+                temperature_tag: temperature_tag.clone(),
+                diameter: *diameter,
+                rate_tag_base: rate_tag_base.clone(),
+                rate_tag: rate_tag.clone(),
+                scaling: BridgeScaling::default(),
+                rate_base: default_rate_base(),
+                outputs: Vec::new(),
+                pressure_mode: pressure_mode.unwrap_or_default(),
+                atmospheric: atmospheric.unwrap_or_else(default_atmospheric),
+                stale: StaleConfig::default(),
+                quality: BTreeMap::new(),
+                totalizer: None,
+                orifice: None,
+                composition: None,
+                heartbeat: None,
+                state: default_state_file(),
+                meters: Vec::new(),
+            }),
+            _ => bail!("the bridge needs either --config or all positional arguments"),
+        }
+    }
+}
+
+/// What the bridge keeps across restarts, in its state file.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct BridgeState {
+    /// Totals at base conditions, keyed by the tag they are written to.
+    #[serde(default)]
+    totals: BTreeMap<String, f64>,
+    /// Last values written from a good reading, keyed by their tag.
+    #[serde(default)]
+    values: BTreeMap<String, f32>,
+    /// Heartbeat counters, keyed by their tag.
+    #[serde(default)]
+    counters: BTreeMap<String, i32>,
+}
+
+impl BridgeState {
+    fn load(path: &Path) -> Result<Self> {
+        match std::fs::read_to_string(path) {
+            Ok(text) => serde_json::from_str(&text)
+                .with_context(|| format!("failed to parse bridge state {}", path.display())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => {
+                Err(e).with_context(|| format!("failed to read bridge state {}", path.display()))
+            }
+        }
+    }
+
+    /// Save the state through a temporary file, so a crash while saving
+    /// leaves the previous state whole.
+    fn save(&self, path: &Path) -> Result<()> {
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_string_pretty(self)?)
+            .and_then(|_| std::fs::rename(&tmp, path))
+            .with_context(|| format!("failed to write bridge state {}", path.display()))
+    }
+}
+
+/// The totalizer of a meter and the tag it writes the total to.
+struct MeterTotal {
+    tag: String,
+    tag_type: TagType,
+    totalizer: Totalizer,
+}
+
+impl MeterTotal {
+    /// Carry on from the total saved in the state, or from the value of
+    /// the tag when the state has none, e.g. on the first run.
+    async fn restore(&mut self, client: &mut Plc, state: &BridgeState) -> Result<()> {
+        let current = value::read_value(client, &self.tag).await?;
+        self.tag_type = current.tag_type();
+        if matches!(self.tag_type, TagType::Bool | TagType::Structure(_)) {
+            bail!(
+                "total tag {} is a {}, it must be numeric",
+                self.tag,
+                current.type_name()
+            );
+        }
+        let total = match state.totals.get(&self.tag) {
+            Some(total) => *total,
+            None => current.as_f64(),
+        };
+        tracing::info!(tag = %self.tag, total, "totalizing from the last total");
+        self.totalizer = Totalizer::new(total);
+        Ok(())
+    }
+
+    /// The total, in the type of its tag.
+    fn value(&self) -> Result<PlcValue> {
+        let total = match self.tag_type {
+            TagType::Real | TagType::Lreal => self.totalizer.total,
+            _ => self.totalizer.total.floor(),
+        };
+        PlcValue::from_json(self.tag_type, &crate::script::json(self.tag_type, total))
+    }
+}
+
+/// A meter polled by the bridge, with its tags parsed.
+struct Meter {
+    slave: u8,
+    registers: [u16; 2],
+    scaling: BridgeScaling,
+    diameter: f32,
+    pressure: String,
+    temperature: String,
+    /// Bore of the orifice plate, and the tag of the differential pressure
+    /// across it.
+    orifice: Option<(f32, String)>,
+    /// Tags written each cycle: the meter rate, then the derived values.
+    written: Vec<String>,
+    derived: Vec<(String, Expr)>,
+    flags: Vec<String>,
+    total: Option<MeterTotal>,
+    /// Analysis of the gas, and the settings it is read again from.
+    analysis: Analysis,
+    composition: Option<CompositionConfig>,
+    analysed: Option<Instant>,
+    /// Whether the last read of the meter failed.
+    failed: bool,
+}
+
+impl Meter {
+    fn new(config: &MeterConfig, protocol: Protocol) -> Result<Self> {
+        let check = |tag: &str| {
+            protocol
+                .check_tag(tag)
+                .with_context(|| format!("invalid tag `{}`", tag))
+        };
+        check(&config.rate_tag)?;
+        let mut written = vec![config.rate_tag.clone()];
+        let mut derived = Vec::new();
+        for Derived {
+            key,
+            name,
+            tag,
+            expr,
+        } in config.derived()
+        {
+            let expr = expr.with_context(|| {
+                format!("invalid {} of the meter of slave {}", key, config.slave)
+            })?;
+            check(&tag)?;
+            written.push(tag);
+            derived.push((name, expr));
+        }
+        let flags = config
+            .quality
+            .iter()
+            .map(|(tag, flag)| {
+                if !written.contains(tag) {
+                    bail!(
+                        "quality flag {} is given for {}, which the bridge doesn't write",
+                        flag,
+                        tag
+                    );
+                }
+                check(flag)?;
+                Ok(flag.clone())
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let total = match &config.totalizer {
+            Some(totalizer) => {
+                check(&totalizer.tag)?;
+                if written.contains(&totalizer.tag) {
+                    bail!("total tag {} is also written with a rate", totalizer.tag);
+                }
+                Some(MeterTotal {
+                    tag: totalizer.tag.clone(),
+                    tag_type: TagType::Real,
+                    totalizer: Totalizer::new(0.0),
+                })
+            }
+            None => None,
+        };
+        let analysis = match &config.composition {
+            Some(composition) => {
+                if let Some(name) = composition
+                    .components
+                    .keys()
+                    .find(|name| !gas::COMPONENTS.contains(&name.as_str()))
+                {
+                    bail!("unknown gas component `{}`", name);
+                }
+                for tag in composition.tags() {
+                    check(tag)?;
+                }
+                // An analysis with tagged components is known once they
+                // are read, when the bridge starts.
+                if composition.tags().next().is_some() {
+                    Analysis::default()
+                } else {
+                    composition.analysis(|_| 0.0).with_context(|| {
+                        format!("invalid composition of the meter of slave {}", config.slave)
+                    })?
+                }
+            }
+            None => Analysis::default(),
+        };
+        Ok(Self {
+            slave: config.slave,
+            registers: [config.rtu_register_velocity, config.rtu_register_rate],
+            scaling: config.scaling.clone(),
+            diameter: config.diameter,
+            pressure: {
+                check(&config.pressure_tag)?;
+                config.pressure_tag.clone()
+            },
+            temperature: {
+                check(&config.temperature_tag)?;
+                config.temperature_tag.clone()
+            },
+            orifice: match &config.orifice {
+                Some(orifice) => {
+                    check(&orifice.differential_pressure_tag)?;
+                    Some((orifice.diameter, orifice.differential_pressure_tag.clone()))
+                }
+                None => None,
+            },
+            written,
+            derived,
+            flags,
+            total,
+            analysis,
+            composition: config.composition.clone(),
+            analysed: None,
+            failed: false,
+        })
+    }
+
+    /// Read the tagged components of the analysis again, once its interval
+    /// is over. A bad analysis is refused, keeping the previous one.
+    async fn analyse(&mut self, client: &mut Plc) -> Result<()> {
+        let Some(composition) = &self.composition else {
+            return Ok(());
+        };
+        if composition.tags().next().is_none()
+            || self
+                .analysed
+                .is_some_and(|at| at.elapsed() < composition.interval)
+        {
+            return Ok(());
+        }
+        self.analysed = Some(Instant::now());
+        let mut amounts = HashMap::new();
+        for tag in composition.tags() {
+            amounts.insert(tag.as_str(), value::read_value(client, tag).await?.as_f64());
+        }
+        let analysis = composition.analysis(|tag| amounts[tag])?;
+        if analysis != self.analysis {
+            tracing::info!(slave = self.slave, "gas analysis updated");
+        }
+        self.analysis = analysis;
+        Ok(())
+    }
+
+    /// Tags read each cycle with the meter: the pressure, the temperature,
+    /// and the differential pressure of an orifice plate.
+    fn inputs(&self) -> Vec<String> {
+        let orifice = self.orifice.as_ref().map(|(_, tag)| tag.clone());
+        [self.pressure.clone(), self.temperature.clone()]
+            .into_iter()
+            .chain(orifice)
+            .collect()
+    }
+
+    /// Writes of a cycle: the written tags, the quality flags and the
+    /// total.
+    fn outputs(&self) -> usize {
+        self.written.len() + self.flags.len() + self.total.is_some() as usize
+    }
+
+    /// The last values of a good reading saved in the state, when there is
+    /// one for every written tag.
+    fn last_values(&self, state: &BridgeState) -> Option<Vec<f32>> {
+        self.written
+            .iter()
+            .map(|tag| state.values.get(tag).copied())
+            .collect()
+    }
+}
+
+/// Carry on from the heartbeat saved in the state, or from the value of
+/// the tag when the state has none.
+async fn restore_heartbeat(client: &mut Plc, tag: &str, state: &BridgeState) -> Result<i32> {
+    if let Some(count) = state.counters.get(tag) {
+        return Ok(*count);
+    }
+    match value::read_value(client, tag).await? {
+        PlcValue::Dint(count) => Ok(count),
+        other => bail!(
+            "heartbeat tag {} is a {}, it must be a DINT",
+            tag,
+            other.type_name()
+        ),
+    }
+}
+
+/// Poll the flow meters and write the rates they yield to the controller,
+/// forever. `target` names the controller when several are bridged at
+/// once; its status is then printed on lines of its own.
+pub async fn run(
+    client: &mut Plc,
+    config: BridgeConfig,
+    stats: &StatsArgs,
+    #[cfg(feature = "influx")] influx: &InfluxArgs,
+    target: Option<&str>,
+) -> Result<()> {
+    let mut meters = config
+        .all_meters()
+        .iter()
+        .map(|meter| Meter::new(meter, client.protocol()))
+        .collect::<Result<Vec<_>>>()?;
+    let several = meters.len() > 1;
+    let serial = config.serial()?;
+    let BridgeConfig {
+        port,
+        pressure_mode,
+        atmospheric,
+        stale,
+        heartbeat,
+        state,
+        ..
+    } = config;
+    // Expressions take gauge pressures, relative to the site atmosphere.
+    let gauge = |pressure: f32| match pressure_mode {
+        PressureMode::Gauge => pressure,
+        PressureMode::Absolute => pressure - atmospheric,
+    };
+    let pressure_unit = match pressure_mode {
+        PressureMode::Gauge => "barg",
+        PressureMode::Absolute => "bara",
+    };
+    if let Some(tag) = &heartbeat {
+        client
+            .protocol()
+            .check_tag(tag)
+            .with_context(|| format!("invalid tag `{}`", tag))?;
+    }
+    // Refuse read-only outputs before starting rather than every cycle.
+    for meter in &meters {
+        let total = meter.total.as_ref().map(|total| &total.tag);
+        for tag in meter.written.iter().chain(&meter.flags).chain(total) {
+            client.check_writable(Some(tag))?;
+        }
+    }
+    if let Some(tag) = &heartbeat {
+        client.check_writable(Some(tag))?;
+    }
+    let state_path = match target {
+        Some(target) => alarm::target_state_file(&state, target),
+        None => state,
+    };
+    let mut state = BridgeState::load(&state_path)?;
+    for total in meters.iter_mut().filter_map(|meter| meter.total.as_mut()) {
+        total.restore(client, &state).await?;
+    }
+    for meter in &mut meters {
+        meter
+            .analyse(client)
+            .await
+            .with_context(|| format!("failed to read the gas analysis of slave {}", meter.slave))?;
+    }
+    let mut heartbeat = match heartbeat {
+        Some(tag) => {
+            let count = restore_heartbeat(client, &tag, &state).await?;
+            Some((tag, count))
+        }
+        None => None,
+    };
+    let mut saved = Instant::now();
+    #[cfg(feature = "influx")]
+    let influx = InfluxSink::connect(influx, target)?;
+
+    let mut line = Some(open_meter(&port, &serial, Slave(meters[0].slave)).await?);
+
+    tracing::info!(port = %port, meters = meters.len(), "connected to the line, starting the bridge loop");
+    // The inputs of a meter are read with one request, and the writes of a
+    // cycle sent together.
+    let writes = meters.iter().map(Meter::outputs).sum::<usize>() + heartbeat.is_some() as usize;
+    let requests = if client.family().packs_requests() {
+        meters.len() + 1
+    } else {
+        meters
+            .iter()
+            .map(|meter| meter.inputs().len())
+            .sum::<usize>()
+            + writes
+    };
+    plc::check_request_rate("bridge-write", requests as f64 / CYCLE.as_secs_f64());
+    let prefix = target
+        .map(|target| format!("{}    ", target.cyan()))
+        .unwrap_or_default();
+    // Bridges of several controllers or meters can't share a status line,
+    // nor can a service log.
+    let status_line = target.is_none() && !several && io::stdout().is_terminal();
+    if plc::dry_run() {
+        tracing::warn!("dry run, the rates are computed but not written to the controller");
+    }
+    let mut stats = stats.stats.map(CycleStats::new);
+
+    loop {
+        if let Some(stats) = &mut stats {
+            stats.cycle(CYCLE, tokio::time::Instant::now());
+        }
+        let mut writes = Writes::default();
+        for meter in &mut meters {
+            // The controller answers while the meter does.
+            let inputs = meter.inputs();
+            let ((reading, meter_took), (readings, plc_took)) = tokio::join!(
+                timing::timed(read_meter(
+                    &mut line,
+                    &port,
+                    &serial,
+                    Slave(meter.slave),
+                    meter.registers,
+                    stale.timeout,
+                )),
+                timing::timed(value::read_values(client, &inputs)),
+            );
+            if let Some(stats) = &mut stats {
+                stats.latency(&format!("slave {}", meter.slave), meter_took);
+                stats.latency("controller read", plc_took);
+            }
+            let now = chrono::Local::now();
+            let prefix = if several {
+                format!("{}slave {}    ", prefix, meter.slave)
+            } else {
+                prefix.clone()
+            };
+            let (status, values) = match reading {
+                Ok([velocity, rate]) => {
+                    if meter.failed {
+                        tracing::info!(port = %port, slave = meter.slave, "the meter answers again");
+                        meter.failed = false;
+                    }
+                    let velocity = meter.scaling.velocity.apply(velocity);
+                    let rate = meter.scaling.rate.apply(rate);
+                    if let Err(e) = meter.analyse(client).await {
+                        tracing::warn!(
+                            slave = meter.slave,
+                            "keeping the last gas analysis: {:#}",
+                            e
+                        );
+                    }
+                    let readings = readings.into_iter().collect::<Result<Vec<_>>>()?;
+                    let pressure = readings[0].as_f64() as f32;
+                    let temperature = readings[1].as_f64() as f32;
+                    let mut inputs: HashMap<&str, f64> = HashMap::from([
+                        ("velocity", velocity as f64),
+                        ("rate", rate as f64),
+                        ("pressure", gauge(pressure) as f64),
+                        ("temperature", temperature as f64),
+                        ("diameter", meter.diameter as f64),
+                    ]);
+                    if let Some((diameter, _)) = &meter.orifice {
+                        inputs.insert("differential_pressure", readings[2].as_f64());
+                        inputs.insert("orifice_diameter", *diameter as f64);
+                    }
+                    let mut values = vec![rate];
+                    let gas = Gas {
+                        analysis: meter.analysis,
+                        atmosphere: atmospheric as f64 * 100.0,
+                    };
+                    for (name, expr) in &meter.derived {
+                        let value = expr.eval(&gas, &|var| inputs.get(var).copied())? as f32;
+                        inputs.insert(name, value as f64);
+                        values.push(value);
+                    }
+
+                    #[cfg(feature = "influx")]
+                    if let Some(influx) = &influx {
+                        // Each meter has a velocity, the tags tell the rest
+                        // apart.
+                        let velocity_name = if several {
+                            format!("velocity_{}", meter.slave)
+                        } else {
+                            "velocity".to_owned()
+                        };
+                        let names = [
+                            velocity_name,
+                            meter.pressure.clone(),
+                            meter.temperature.clone(),
+                        ]
+                        .into_iter()
+                        .chain(meter.written.iter().cloned());
+                        let samples: Vec<_> = names
+                            .zip(
+                                [velocity, pressure, temperature]
+                                    .into_iter()
+                                    .chain(values.iter().copied()),
+                            )
+                            .map(|(name, value)| Sample {
+                                timestamp: now,
+                                tag: name,
+                                value: PlcValue::Real(value),
+                                quality: Quality::Good,
+                            })
+                            .collect();
+                        influx.push(&samples);
+                    }
+                    let mut status = format!(
+                        "[{}] {}===> Velocity: {} m/s, P: {} {}, T: {} degC, Q: {} Sm3/d",
+                        now,
+                        prefix,
+                        velocity.to_string().bold().green(),
+                        pressure.to_string().bold().green(),
+                        pressure_unit,
+                        temperature.to_string().bold().green(),
+                        values[1].to_string().bold().green()
+                    );
+                    if let Some(total) = &mut meter.total {
+                        total.totalizer.add(values[1] as f64, Instant::now());
+                        state
+                            .totals
+                            .insert(total.tag.clone(), total.totalizer.total);
+                        let total = format!("{:.3}", total.totalizer.total);
+                        status += &format!(", Total: {} Sm3", total.bold().green());
+                    }
+                    for (tag, value) in meter.written.iter().zip(&values) {
+                        state.values.insert(tag.clone(), *value);
+                    }
+                    (status, Some(values))
+                }
+                Err(e) => {
+                    if !meter.failed {
+                        tracing::warn!(port = %port, slave = meter.slave, "failed to read the meter: {:#}", e);
+                        meter.failed = true;
+                    }
+                    if let Some(total) = &mut meter.total {
+                        total.totalizer.interrupt();
+                    }
+                    let values = match stale.action {
+                        StaleAction::Abort => {
+                            if !plc::dry_run() {
+                                for flag in &meter.flags {
+                                    writes.push(Output::Flag, flag, PlcValue::Bool(false));
+                                }
+                                if let Err(e) = writes.send(client).await {
+                                    tracing::warn!("{:#}", e);
+                                }
+                                state.save(&state_path)?;
+                            }
+                            return Err(e.context(format!(
+                                "failed to read the meter of slave {}",
+                                meter.slave
+                            )));
+                        }
+                        // The saved values are written again, in case the
+                        // controller lost them, e.g. on a restart of both.
+                        StaleAction::Hold => meter.last_values(&state),
+                        StaleAction::Fallback => Some(vec![stale.fallback; meter.written.len()]),
+                    };
+                    let held = match stale.action {
+                        StaleAction::Fallback => "writing the fallback",
+                        StaleAction::Hold | StaleAction::Abort => "holding the last values",
+                    };
+                    let status = format!(
+                        "[{}] {}===> {}, {}",
+                        now,
+                        prefix,
+                        "meter not answering".bold().red(),
+                        held
+                    );
+                    (status, values)
+                }
+            };
+            if status_line {
+                io::stdout().flush().unwrap();
+                print!("\r{}", status);
+            } else {
+                println!("{}", status);
+            }
+
+            for (tag, value) in meter.written.iter().zip(values.iter().flatten()) {
+                writes.push(Output::Value, tag, PlcValue::Real(*value));
+            }
+            for flag in &meter.flags {
+                writes.push(Output::Flag, flag, PlcValue::Bool(!meter.failed));
+            }
+            if let Some(total) = &meter.total {
+                writes.push(Output::Value, &total.tag, total.value()?);
+            }
+        }
+        if let Some((tag, count)) = &mut heartbeat {
+            *count = count.wrapping_add(1);
+            state.counters.insert(tag.clone(), *count);
+            writes.push(Output::Heartbeat, tag, PlcValue::Dint(*count));
+        }
+        if !plc::dry_run() {
+            let (sent, took) = timing::timed(writes.send(client)).await;
+            sent?;
+            if let Some(stats) = &mut stats {
+                stats.latency("controller write", took);
+            }
+        }
+        if let Some(stats) = &mut stats {
+            stats.tick();
+        }
+        if !plc::dry_run() && saved.elapsed() >= SAVE_EVERY {
+            state.save(&state_path)?;
+            saved = Instant::now();
+        }
+        // Stop between cycles, never halfway through the writes.
+        tokio::select! {
+            _ = tokio::time::sleep(CYCLE) => {}
+            _ = service::stopped() => break,
+        }
+    }
+    if let Some(stats) = &mut stats {
+        stats.report();
+    }
+    if !plc::dry_run() {
+        state.save(&state_path)?;
+    }
+    if status_line {
+        println!();
+    }
+    if let Some(mut ctx) = line {
+        ctx.disconnect().await?;
+    }
+    Ok(())
+}
+
+/// What a write of the bridge is, telling how its failure is handled.
+enum Output {
+    /// A rate, a derived value or a total.
+    Value,
+    Flag,
+    Heartbeat,
+}
+
+/// Writes of a bridge cycle, sent together once every meter is read.
+#[derive(Default)]
+struct Writes {
+    values: Vec<(String, PlcValue)>,
+    outputs: Vec<Output>,
+}
+
+impl Writes {
+    fn push(&mut self, output: Output, tag: &str, value: PlcValue) {
+        self.values.push((tag.to_owned(), value));
+        self.outputs.push(output);
+    }
+
+    /// Send the writes, batched into as few requests as they fit. A value
+    /// that isn't written fails the cycle, a quality flag or the heartbeat
+    /// only warns.
+    async fn send(self, client: &mut Plc) -> Result<()> {
+        let results = value::write_values(client, &self.values).await;
+        for (((tag, _), output), result) in self.values.iter().zip(self.outputs).zip(results) {
+            let Err(e) = result else {
+                continue;
+            };
+            match output {
+                Output::Value => return Err(e.context(format!("failed to write {}", tag))),
+                Output::Flag => tracing::warn!("failed to write quality flag {}: {:#}", tag, e),
+                Output::Heartbeat => tracing::warn!("failed to write heartbeat {}: {:#}", tag, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn open_meter(port: &str, serial: &SerialPortBuilder, slave: Slave) -> Result<Context> {
+    let stream = SerialStream::open(serial)
+        .with_context(|| format!("failed to open serial port {}", port))?;
+    Ok(rtu::connect_slave(stream, slave).await?)
+}
+
+/// Raw values of the registers of a slave, each a float spanning two
+/// registers. The port is reopened after a failed read, as leftover bytes
+/// of a late or garbled reply would be taken for the next one.
+async fn read_meter<const N: usize>(
+    line: &mut Option<Context>,
+    port: &str,
+    serial: &SerialPortBuilder,
+    slave: Slave,
+    registers: [u16; N],
+    timeout: Duration,
+) -> Result<[f32; N]> {
+    if line.is_none() {
+        *line = Some(open_meter(port, serial, slave).await?);
+    }
+    let ctx = line.as_mut().unwrap();
+    ctx.set_slave(slave);
+    let read = async {
+        let mut values = [0.0; N];
+        for (value, register) in values.iter_mut().zip(registers) {
+            let rsp = ctx.read_holding_registers(register, 2).await?;
+            *value = u16_to_f32(rsp[0], rsp[1]);
+        }
+        Ok::<_, io::Error>(values)
+    };
+    let reading = match tokio::time::timeout(timeout, read).await {
+        Ok(reading) => reading.map_err(anyhow::Error::from),
+        Err(_) => Err(anyhow!(
+            "no reply within {}",
+            humantime::format_duration(timeout)
+        )),
+    };
+    if reading.is_err() {
+        *line = None;
+    }
+    reading
+}
+
+fn u16_to_f32(first: u16, second: u16) -> f32 {
+    let data_32bit_rep = ((first as u32) << 16) | second as u32;
+    let data_32_array = data_32bit_rep.to_ne_bytes();
+    f32::from_ne_bytes(data_32_array)
+}
+
+pub(crate) fn velocity_to_rate(
+    gas: &Gas,
+    velocity: f32,
+    diameter: f32,
+    pressure: f32,
+    temperature: f32,
+) -> f32 {
+    let z_f = gas::properties_at_line(gas, pressure as f64, temperature as f64).z;
+    let z_b = gas::base_properties(&gas.analysis).z;
+    let atmosphere = (gas.atmosphere / 6.894_757_3) as f32;
+
+    let act_flow =
+        (PI * (diameter / 12.0) * (diameter / 12.0) / 4.0) * (velocity * 3.28083) * 3600.0;
+
+    ((act_flow * (((pressure / 0.068947573) + atmosphere) * 6894.7573) / (14.73 * 6894.7573))
+        * ((288.7056) / (temperature + 273.15))
+        * (z_b / z_f) as f32)
+        * 0.028_316_846
+        * 24.0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scaling() {
+        let config: BridgeScaling = toml::from_str(
+            r#"
+            [velocity]
+            scale = 0.01
+            offset = -1.0
+            min = 0.0
+            max = 40.0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rate, Scaling::default());
+        assert_eq!(config.rate.apply(12.5), 12.5);
+        assert_eq!(config.velocity.apply(1100.0), 10.0);
+        assert_eq!(config.velocity.apply(50.0), 0.0);
+        assert_eq!(config.velocity.apply(9000.0), 40.0);
+        assert!(config.velocity.apply(f32::NAN).is_nan());
+    }
+
+    #[test]
+    fn derived_values() {
+        let config: BridgeConfig = toml::from_str(
+            r#"
+            port = "/dev/ttyUSB0"
+            slave = 1
+            baudrate = 9600
+            rtu_register_velocity = 0
+            rtu_register_rate = 2
+            pressure_tag = "PT_101"
+            temperature_tag = "TT_101"
+            diameter = 4.0
+            rate_tag = "FT_101_Rate"
+            rate_tag_base = "FT_101_RateBase"
+
+            [[output]]
+            tag = "FT_101_Energy"
+            expr = "rate_base * 0.0381"
+
+            [[output]]
+            tag = "FT_101_EnergyHourly"
+            expr = "FT_101_Energy / 24 + flow"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.rate_base, DEFAULT_RATE_BASE);
+        let derived = config.all_meters()[0].derived();
+        assert_eq!(derived[0].tag, "FT_101_RateBase");
+        assert!(derived[0].expr.is_ok());
+        assert_eq!(derived[1].name, "FT_101_Energy");
+        assert!(derived[1].expr.is_ok());
+        let error = derived[2].expr.as_ref().unwrap_err();
+        assert_eq!(error.to_string(), "unknown value `flow`");
+
+        let values = |name: &str| match name {
+            "velocity" => Some(5.0),
+            "diameter" => Some(4.0),
+            "pressure" => Some(50.0),
+            "temperature" => Some(20.0),
+            _ => None,
+        };
+        let gas = Gas::default();
+        let expr = derived[0].expr.as_ref().unwrap();
+        let rate_base = expr.eval(&gas, &values).unwrap();
+        assert_eq!(
+            rate_base as f32,
+            velocity_to_rate(&gas, 5.0, 4.0, 50.0, 20.0)
+        );
+        // 5 m/s through 4 in is 5153.5 ft3/h, at 739.9 psia and 20 degC
+        // 254 940 scf/h for an ideal gas, times 0.9969 / 0.8490 for Z at
+        // base and flowing conditions, 8476 Sm3/h.
+        assert!((rate_base - 203_428.0).abs() < 1.0, "{}", rate_base);
+        // Flowing Z is that at 50 barg over the atmosphere, 51.01 bara.
+        let flowing = gas::properties(&gas.analysis, 5101.325, 293.15);
+        assert_eq!(gas::properties_at_line(&gas, 50.0, 20.0), flowing);
+    }
+
+    #[test]
+    fn meters_on_one_line() {
+        let config: BridgeConfig = toml::from_str(
+            r#"
+            port = "/dev/ttyUSB0"
+            slave = 1
+            baudrate = 9600
+            rtu_register_velocity = 0
+            rtu_register_rate = 2
+            pressure_tag = "PT_101"
+            temperature_tag = "TT_101"
+            diameter = 4.0
+            rate_tag = "FT_101_Rate"
+            rate_tag_base = "FT_101_RateBase"
+
+            [[meter]]
+            slave = 2
+            rtu_register_velocity = 100
+            rtu_register_rate = 102
+            pressure_tag = "PT_102"
+            temperature_tag = "TT_102"
+            diameter = 6.0
+            rate_tag = "FT_102_Rate"
+            rate_tag_base = "FT_102_RateBase"
+            quality = { FT_102_Rate = "FT_102_OK" }
+            totalizer = { tag = "FT_102_Total" }
+            "#,
+        )
+        .unwrap();
+        let meters = config.all_meters();
+        assert_eq!(meters.len(), 2);
+        assert_eq!(meters[0].slave, 1);
+        assert_eq!(meters[0].rate_base, DEFAULT_RATE_BASE);
+        assert_eq!(meters[1].slave, 2);
+        assert_eq!(meters[1].quality["FT_102_Rate"], "FT_102_OK");
+        let meter = Meter::new(&meters[1], Protocol::Ab).unwrap();
+        assert_eq!(meter.registers, [100, 102]);
+        assert_eq!(meter.inputs(), ["PT_102", "TT_102"]);
+        assert_eq!(meter.outputs(), 4);
+        assert!(meters[0].totalizer.is_none());
+        assert_eq!(config.state, Path::new(DEFAULT_STATE_FILE));
+
+        let mut state = BridgeState::default();
+        assert_eq!(meter.last_values(&state), None);
+        state.values.insert("FT_102_Rate".to_owned(), 12.0);
+        assert_eq!(meter.last_values(&state), None);
+        state.values.insert("FT_102_RateBase".to_owned(), 3400.0);
+        assert_eq!(meter.last_values(&state), Some(vec![12.0, 3400.0]));
+
+        assert_eq!(meter.analysis, Analysis::default());
+
+        let mut looped = meters[1].clone();
+        looped.totalizer = Some(TotalizerConfig {
+            tag: "FT_102_Rate".to_owned(),
+        });
+        assert!(Meter::new(&looped, Protocol::Ab).is_err());
+    }
+
+    #[test]
+    fn orifice_meter() {
+        let mut meter: MeterConfig = toml::from_str(
+            r#"
+            slave = 3
+            rtu_register_velocity = 0
+            rtu_register_rate = 2
+            pressure_tag = "PT_103"
+            temperature_tag = "TT_103"
+            diameter = 4.026
+            rate_tag = "FT_103_Rate"
+            rate_tag_base = "FT_103_RateBase"
+
+            [orifice]
+            diameter = 2.0
+            differential_pressure_tag = "DPT_103"
+            "#,
+        )
+        .unwrap();
+        let derived = meter.derived();
+        assert_eq!(
+            derived[0].expr.as_ref().unwrap(),
+            &DEFAULT_RATE_BASE_ORIFICE.parse::<Expr>().unwrap()
+        );
+        assert_eq!(
+            Meter::new(&meter, Protocol::Ab).unwrap().inputs(),
+            ["PT_103", "TT_103", "DPT_103"]
+        );
+
+        meter.rate_base =
+            "aga3_rate(differential_pressure, pressure, temperature, 2.5, diameter) * 0.99"
+                .to_owned();
+        assert!(meter.derived()[0].expr.is_ok());
+        meter.orifice = None;
+        let error = meter.derived()[0].expr.as_ref().unwrap_err().to_string();
+        assert_eq!(error, "unknown value `differential_pressure`");
+    }
+
+    #[test]
+    fn gas_composition() {
+        let composition: CompositionConfig = toml::from_str(
+            r#"
+            interval = "5m"
+
+            [components]
+            methane = "AT_101_C1"
+            ethane = "AT_101_C2"
+            nitrogen = 1.5
+            carbon_dioxide = 0.5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(composition.interval, Duration::from_secs(300));
+        assert_eq!(
+            composition.tags().collect::<Vec<_>>(),
+            ["AT_101_C2", "AT_101_C1"]
+        );
+        let analysis = composition
+            .analysis(|tag| if tag == "AT_101_C1" { 93.0 } else { 5.0 })
+            .unwrap();
+        assert_eq!(analysis.0[0], 0.93);
+        assert!(composition.analysis(|_| 0.0).is_err());
+        assert!(toml::from_str::<CompositionConfig>("[components]\nmethane = true").is_err());
+    }
+
+    #[test]
+    fn state_across_restarts() {
+        let path = std::env::temp_dir().join(format!("cobalt-bridge-{}.json", std::process::id()));
+        assert_eq!(BridgeState::load(&path).unwrap(), BridgeState::default());
+        let mut state = BridgeState::default();
+        state.totals.insert("FT_101_Total".to_owned(), 1234.5);
+        state.values.insert("FT_101_Rate".to_owned(), 42.0);
+        state.counters.insert("Bridge_Heartbeat".to_owned(), 17);
+        state.save(&path).unwrap();
+        assert_eq!(BridgeState::load(&path).unwrap(), state);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn serial_line() {
+        let mut config = BridgeArgs {
+            config: None,
+            port: Some("/dev/ttyUSB0".to_owned()),
+            slave: Some(1),
+            baudrate: Some(19200),
+            rtu_register_velocity: Some(0),
+            rtu_register_rate: Some(2),
+            pressure_tag: Some("PT_101".to_owned()),
+            temperature_tag: Some("TT_101".to_owned()),
+            diameter: Some(4.0),
+            rate_tag_base: Some("FT_101_RateBase".to_owned()),
+            rate_tag: Some("FT_101_Rate".to_owned()),
+            parity: Some(Parity::Even),
+            stop_bits: None,
+            data_bits: Some(7),
+            flow_control: None,
+            pressure_mode: Some(PressureMode::Absolute),
+            atmospheric: None,
+        }
+        .resolve()
+        .unwrap();
+        assert_eq!(config.stop_bits, 1);
+        assert_eq!(config.flow_control, FlowControl::None);
+        assert_eq!(config.pressure_mode, PressureMode::Absolute);
+        assert_eq!(config.atmospheric, 1.01325);
+        let serial = config.serial().unwrap();
+        let expected = tokio_serial::new("/dev/ttyUSB0", 19200)
+            .parity(tokio_serial::Parity::Even)
+            .data_bits(DataBits::Seven);
+        assert_eq!(serial, expected);
+
+        config.stop_bits = 3;
+        assert!(config.serial().is_err());
+    }
+
+    #[test]
+    fn stale_values() {
+        let stale: StaleConfig = toml::from_str("").unwrap();
+        assert_eq!(stale, StaleConfig::default());
+        assert_eq!(stale.action, StaleAction::Abort);
+        assert_eq!(stale.timeout, Duration::from_secs(1));
+
+        let stale: StaleConfig = toml::from_str(
+            r#"
+            action = "fallback"
+            fallback = -1.0
+            timeout = "300ms"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(stale.action, StaleAction::Fallback);
+        assert_eq!(stale.fallback, -1.0);
+        assert_eq!(stale.timeout, Duration::from_millis(300));
+        assert!(toml::from_str::<StaleConfig>(r#"action = "zero""#).is_err());
+    }
+}
@@ -0,0 +1,283 @@
+use crate::plc::Plc;
+use crate::value::{self, PlcValue};
+use anyhow::{anyhow, bail, Context, Result};
+use clap::Args;
+use std::fmt::Display;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+/// Options of the `check` command.
+#[derive(Args)]
+pub struct CheckArgs {
+    /// Tag to check. Can be repeated, the worst state of the tags being the
+    /// state of the check.
+    #[arg(long, required = true)]
+    pub tag: Vec<String>,
+    /// Range of values that are fine, beyond which the check warns, in the
+    /// plugin range syntax: `80` for 0 to 80, `10:` for 10 and up, `~:80`
+    /// for up to 80, `10:80`, or `@10:80` to warn inside it instead.
+    #[arg(long, allow_hyphen_values = true)]
+    pub warning: Option<Threshold>,
+    /// Range of values beyond which the check is critical, like
+    /// `--warning`.
+    #[arg(long, allow_hyphen_values = true)]
+    pub critical: Option<Threshold>,
+    /// Name of the check at the start of its output.
+    #[arg(long, default_value = "PLC")]
+    pub label: String,
+}
+
+/// State of a check, with the exit codes of monitoring plugins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum State {
+    Ok,
+    Warning,
+    Critical,
+    Unknown,
+}
+
+impl State {
+    pub fn code(self) -> u8 {
+        match self {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Critical => 2,
+            State::Unknown => 3,
+        }
+    }
+
+    /// Worse of two states, where a critical value outranks one that
+    /// couldn't be checked.
+    fn worst(self, other: State) -> State {
+        let rank = |state| match state {
+            State::Ok => 0,
+            State::Warning => 1,
+            State::Unknown => 2,
+            State::Critical => 3,
+        };
+        if rank(other) > rank(self) {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl Display for State {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            State::Ok => "OK",
+            State::Warning => "WARNING",
+            State::Critical => "CRITICAL",
+            State::Unknown => "UNKNOWN",
+        })
+    }
+}
+
+/// A check that didn't come out OK, its output already printed. Carried
+/// as an error so the command exits with the code of the state.
+#[derive(Debug)]
+pub struct Alert(pub State);
+
+impl Display for Alert {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the check is {}", self.0)
+    }
+}
+
+impl std::error::Error for Alert {}
+
+/// A warning or critical threshold: the range of values that are fine,
+/// or with `@` the range that isn't.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    start: f64,
+    end: f64,
+    inside: bool,
+    /// As given, for the performance data.
+    text: String,
+}
+
+impl Threshold {
+    fn alerts(&self, value: f64) -> bool {
+        let within = value >= self.start && value <= self.end;
+        within == self.inside
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let text = s.trim();
+        let (inside, range) = match text.strip_prefix('@') {
+            Some(range) => (true, range),
+            None => (false, text),
+        };
+        let number = |text: &str| -> Result<f64> {
+            text.parse()
+                .map_err(|_| anyhow!("`{}` is not a number in threshold `{}`", text, s))
+        };
+        let (start, end) = match range.split_once(':') {
+            Some(("~", end)) => (f64::NEG_INFINITY, number(end)?),
+            Some((start, "")) => (number(start)?, f64::INFINITY),
+            Some((start, end)) => (number(start)?, number(end)?),
+            None => (0.0, number(range)?),
+        };
+        if start > end {
+            bail!("threshold `{}` starts above its end", s);
+        }
+        Ok(Self {
+            start,
+            end,
+            inside,
+            text: text.to_owned(),
+        })
+    }
+}
+
+/// Read the tags and print one line of plugin output: the state, the
+/// values, and their performance data with the thresholds. A state other
+/// than OK is returned as an `Alert`.
+pub async fn run(client: &mut Plc, args: &CheckArgs) -> Result<()> {
+    let mut values = Vec::with_capacity(args.tag.len());
+    for tag in &args.tag {
+        let value = value::read_value(client, tag)
+            .await
+            .with_context(|| format!("failed to read {}", tag))?;
+        values.push((tag.as_str(), value));
+    }
+    let state = values
+        .iter()
+        .map(|(_, value)| evaluate(value, args))
+        .fold(State::Ok, State::worst);
+    println!("{}", output(&args.label, state, &values, args));
+    match state {
+        State::Ok => Ok(()),
+        state => Err(Alert(state).into()),
+    }
+}
+
+/// State of one value against the thresholds. Values that aren't numbers
+/// can't be checked against them.
+fn evaluate(value: &PlcValue, args: &CheckArgs) -> State {
+    let number = value.as_f64();
+    let thresholds = [
+        (&args.critical, State::Critical),
+        (&args.warning, State::Warning),
+    ];
+    for (threshold, state) in thresholds {
+        let Some(threshold) = threshold else {
+            continue;
+        };
+        if number.is_nan() {
+            return State::Unknown;
+        }
+        if threshold.alerts(number) {
+            return state;
+        }
+    }
+    State::Ok
+}
+
+fn output(label: &str, state: State, values: &[(&str, PlcValue)], args: &CheckArgs) -> String {
+    let text: Vec<String> = values
+        .iter()
+        .map(|(tag, value)| format!("{} = {}", tag, value))
+        .collect();
+    let threshold = |threshold: &Option<Threshold>| {
+        threshold
+            .as_ref()
+            .map(|threshold| threshold.text.clone())
+            .unwrap_or_default()
+    };
+    let perfdata: Vec<String> = values
+        .iter()
+        .filter(|(_, value)| !value.as_f64().is_nan())
+        .map(|(tag, value)| {
+            format!(
+                "{}={};{};{}",
+                perf_label(tag),
+                value.as_f64(),
+                threshold(&args.warning),
+                threshold(&args.critical)
+            )
+        })
+        .collect();
+    let mut line = format!("{} {} - {}", label, state, text.join(", "));
+    if !perfdata.is_empty() {
+        line.push_str(" | ");
+        line.push_str(&perfdata.join(" "));
+    }
+    line
+}
+
+/// Label of performance data, quoted when it holds spaces, `=` or quotes.
+fn perf_label(tag: &str) -> String {
+    if tag.contains([' ', '=', '\'']) {
+        format!("'{}'", tag.replace('\'', "''"))
+    } else {
+        tag.to_owned()
+    }
+}
+
+/// Print the output of a check that failed before its tags could be
+/// checked, e.g. when the controller doesn't answer, and return the
+/// UNKNOWN exit code. Alerts only return their code, already printed.
+pub fn report(label: &str, error: &anyhow::Error) -> ExitCode {
+    if let Some(Alert(state)) = error.downcast_ref::<Alert>() {
+        return ExitCode::from(state.code());
+    }
+    println!("{} {} - {:#}", label, State::Unknown, error);
+    ExitCode::from(State::Unknown.code())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn args(warning: &str, critical: &str) -> CheckArgs {
+        CheckArgs {
+            tag: vec!["TankLevel".to_owned()],
+            warning: Some(warning.parse().unwrap()),
+            critical: Some(critical.parse().unwrap()),
+            label: "PLC".to_owned(),
+        }
+    }
+
+    #[test]
+    fn thresholds() {
+        let above: Threshold = "80".parse().unwrap();
+        assert!(!above.alerts(80.0));
+        assert!(above.alerts(80.5));
+        assert!(above.alerts(-1.0));
+        let low: Threshold = "10:".parse().unwrap();
+        assert!(low.alerts(9.0));
+        assert!(!low.alerts(1e9));
+        let below: Threshold = "~:-5".parse().unwrap();
+        assert!(!below.alerts(-20.0));
+        assert!(below.alerts(0.0));
+        let inside: Threshold = "@10:20".parse().unwrap();
+        assert!(inside.alerts(15.0));
+        assert!(!inside.alerts(25.0));
+        assert!("20:10".parse::<Threshold>().is_err());
+        assert!("high".parse::<Threshold>().is_err());
+
+        let args = args("80", "90");
+        assert_eq!(evaluate(&PlcValue::Real(75.0), &args), State::Ok);
+        assert_eq!(evaluate(&PlcValue::Real(85.0), &args), State::Warning);
+        assert_eq!(evaluate(&PlcValue::Dint(95), &args), State::Critical);
+        assert_eq!(State::Unknown.worst(State::Critical), State::Critical);
+        assert_eq!(State::Warning.worst(State::Unknown), State::Unknown);
+        assert_eq!(
+            output(
+                "PLC",
+                State::Warning,
+                &[("TankLevel", PlcValue::Real(85.5))],
+                &args
+            ),
+            "PLC WARNING - TankLevel = 85.5 | TankLevel=85.5;80;90"
+        );
+        assert_eq!(perf_label("Flow Rate"), "'Flow Rate'");
+    }
+}
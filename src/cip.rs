@@ -0,0 +1,262 @@
+use crate::historian::Journal;
+use crate::plc::{self, Plc};
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use clap::Subcommand;
+use colored::*;
+use rseip::cip::{MessageReply, MessageRequest};
+use rseip::client::ab_eip::PathParser;
+use rseip::precludes::*;
+use rseip::BytesHolder;
+use std::fmt::Write as _;
+
+/// Get_Attribute_Single service.
+const SERVICE_GET_ATTRIBUTE_SINGLE: u8 = 0x0E;
+
+/// Bytes shown per line of a reply.
+const DUMP_WIDTH: usize = 16;
+
+#[derive(Subcommand)]
+pub enum CipAction {
+    /// Read one attribute of any object and print its raw bytes.
+    GetAttribute {
+        /// Class id, decimal or `0x` hex.
+        #[arg(long, value_parser = parse_id)]
+        class: u16,
+        #[arg(long, value_parser = parse_id)]
+        instance: u16,
+        #[arg(long, value_parser = parse_id)]
+        attribute: u16,
+    },
+    /// Send any service and print the raw bytes of the reply.
+    Raw {
+        /// Service code, decimal or `0x` hex, e.g. `0x4C`.
+        #[arg(long, value_parser = parse_service)]
+        service: u8,
+        /// Request path, as `class/instance[/attribute]` ids, e.g. `0x6B/1/2`,
+        /// or a tag name for the tag services.
+        #[arg(long, value_parser = parse_path)]
+        path: Box<EPath>,
+        /// Request data in hex, e.g. `01 00`.
+        #[arg(long, default_value = "", value_parser = parse_hex)]
+        data: Bytes,
+    },
+}
+
+pub async fn run_action(client: &mut Plc, action: &CipAction, journal: &Journal) -> Result<()> {
+    match action {
+        CipAction::GetAttribute {
+            class,
+            instance,
+            attribute,
+        } => {
+            let path = EPath::default()
+                .with_class(*class)
+                .with_instance(*instance)
+                .with_attribute(*attribute);
+            let data = send(client, SERVICE_GET_ATTRIBUTE_SINGLE, path, Bytes::new()).await?;
+            print!("{}", dump(&data));
+        }
+        CipAction::Raw {
+            service,
+            path,
+            data,
+        } => {
+            let target = describe_path(path);
+            client.check_writable(None)?;
+            if plc::dry_run() {
+                println!(
+                    "{}    service {:#04x} to {} with [{}]",
+                    "DRY RUN".bold().yellow(),
+                    service,
+                    target.bold(),
+                    hex(data)
+                );
+                return Ok(());
+            }
+            client.confirm_write(|address| {
+                format!("Send service {:#04x} to {}?", service, address)
+            })?;
+            // Raw services can change anything, so they are journaled
+            // whether or not they succeed.
+            let result = send(client, *service, (**path).clone(), data.clone()).await;
+            journal.record_operator(
+                "cip raw",
+                &target,
+                Some(format!("service {:#04x} [{}]", service, hex(data))),
+            )?;
+            print!("{}", dump(&result?));
+        }
+    }
+    Ok(())
+}
+
+/// Send a request and return the data of its reply.
+async fn send(client: &mut Plc, service: u8, path: EPath, data: Bytes) -> Result<Bytes> {
+    let target = describe_path(&path);
+    let reply: MessageReply<BytesHolder> = plc::transaction(
+        "raw",
+        &target,
+        client.eip()?.send(MessageRequest::new(service, path, data)),
+    )
+    .await
+    .map_err(|e| anyhow!("service {:#04x} to {} failed: {}", service, target, e))?;
+    Ok(reply.data.into())
+}
+
+/// Parse a decimal or `0x` hex id.
+fn parse_number(text: &str) -> Result<u32> {
+    let text = text.trim();
+    let parsed = match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    parsed.with_context(|| format!("`{}` is not a number", text))
+}
+
+fn parse_id(text: &str) -> Result<u16> {
+    let id = parse_number(text)?;
+    u16::try_from(id).map_err(|_| anyhow!("{} is out of range for an id", id))
+}
+
+fn parse_service(text: &str) -> Result<u8> {
+    let code = parse_number(text)?;
+    match u8::try_from(code) {
+        Ok(code) if code < 0x80 => Ok(code),
+        _ => bail!("{:#x} is not a request service code", code),
+    }
+}
+
+/// Parse `class/instance[/attribute]` ids, or a tag name.
+fn parse_path(text: &str) -> Result<Box<EPath>> {
+    if text.contains('/') {
+        let ids = text.split('/').map(parse_id).collect::<Result<Vec<_>>>()?;
+        let path = match ids[..] {
+            [class, instance] => EPath::default().with_class(class).with_instance(instance),
+            [class, instance, attribute] => EPath::default()
+                .with_class(class)
+                .with_instance(instance)
+                .with_attribute(attribute),
+            _ => bail!("expected class/instance or class/instance/attribute"),
+        };
+        return Ok(Box::new(path));
+    }
+    EPath::parse_tag(text)
+        .map(Box::new)
+        .map_err(|e| anyhow!("invalid path `{}`: {}", text, e))
+}
+
+/// Parse hex bytes, optionally separated by spaces.
+pub(crate) fn parse_hex(text: &str) -> Result<Bytes> {
+    let digits: Vec<char> = text.chars().filter(|c| !c.is_whitespace()).collect();
+    if !digits.len().is_multiple_of(2) {
+        bail!("odd number of hex digits");
+    }
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair: String = pair.iter().collect();
+            u8::from_str_radix(&pair, 16).with_context(|| format!("`{}` is not a hex byte", pair))
+        })
+        .collect::<Result<Vec<_>>>()
+        .map(Bytes::from)
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn describe_path(path: &EPath) -> String {
+    path.iter()
+        .map(|segment| match segment {
+            Segment::Symbol(name) => name.to_string(),
+            Segment::Class(id) => format!("class {:#x}", id),
+            Segment::Instance(id) => format!("instance {}", id),
+            Segment::Attribute(id) => format!("attribute {}", id),
+            Segment::Element(index) => format!("[{}]", index),
+            Segment::Port(port) => format!("port {:?}", port),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Offset, hex and printable characters of the bytes, a line per
+/// `DUMP_WIDTH` bytes.
+pub(crate) fn dump(data: &[u8]) -> String {
+    let mut text = format!("{} bytes\n", data.len());
+    for (line, chunk) in data.chunks(DUMP_WIDTH).enumerate() {
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        writeln!(
+            text,
+            "{:04x}  {:<width$}  {}",
+            line * DUMP_WIDTH,
+            hex(chunk),
+            ascii,
+            width = DUMP_WIDTH * 3 - 1
+        )
+        .unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_arguments() {
+        assert_eq!(parse_id("0x6B").unwrap(), 0x6B);
+        assert_eq!(parse_id("107").unwrap(), 0x6B);
+        assert!(parse_id("0x10000").is_err());
+        assert_eq!(parse_service("0x4c").unwrap(), 0x4C);
+        assert!(parse_service("0xCC").is_err());
+
+        assert_eq!(
+            *parse_path("0x6B/1/2").unwrap(),
+            EPath::default()
+                .with_class(0x6B)
+                .with_instance(1)
+                .with_attribute(2)
+        );
+        assert_eq!(
+            *parse_path("1/1").unwrap(),
+            EPath::default().with_class(1).with_instance(1)
+        );
+        assert!(parse_path("1/2/3/4").is_err());
+        assert_eq!(
+            *parse_path("Counts[2]").unwrap(),
+            EPath::parse_tag("Counts[2]").unwrap()
+        );
+
+        assert_eq!(&parse_hex("01 00").unwrap()[..], [1, 0]);
+        assert_eq!(&parse_hex("c3ff").unwrap()[..], [0xC3, 0xFF]);
+        assert!(parse_hex("123").is_err());
+        assert!(parse_hex("zz").is_err());
+    }
+
+    #[test]
+    fn dump_lines() {
+        let data: Vec<u8> = (0x30..0x30 + 18).collect();
+        let text = dump(&data);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "18 bytes");
+        assert_eq!(
+            lines[1],
+            "0000  30 31 32 33 34 35 36 37 38 39 3a 3b 3c 3d 3e 3f  0123456789:;<=>?"
+        );
+        assert_eq!(lines[2], format!("0010  40 41{}  @A", " ".repeat(42)));
+        assert_eq!(dump(&[]), "0 bytes\n");
+    }
+}
@@ -0,0 +1,181 @@
+use crate::historian::Journal;
+use crate::plc::{self, Plc};
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use clap::Subcommand;
+use colored::*;
+use rseip::precludes::*;
+use rseip::BytesHolder;
+
+/// WallClockTime object class.
+const CLASS_WALL_CLOCK_TIME: u16 = 0x8B;
+
+/// Attribute reading the controller time in microseconds since 1970 UTC.
+const ATTR_CURRENT_UTC_VALUE: u16 = 0x0B;
+
+/// Attribute setting the controller time in microseconds since 1970 UTC.
+const ATTR_CURRENT_VALUE: u16 = 0x06;
+
+/// Drift beyond which `clock get` highlights the controller time.
+const DRIFT_WARNING_SECS: f64 = 1.0;
+
+#[derive(Subcommand)]
+pub enum ClockAction {
+    /// Show the controller time and its drift from the system clock.
+    Get,
+    /// Set the controller time.
+    Set {
+        /// Time to set, e.g. `2024-03-01T08:00:00Z`, or `2024-03-01 08:00:00`
+        /// in local time.
+        #[arg(
+            required_unless_present = "from_system",
+            conflicts_with = "from_system"
+        )]
+        time: Option<String>,
+        /// Set the controller to the current system time.
+        #[arg(long)]
+        from_system: bool,
+    },
+}
+
+pub async fn run_action(client: &mut Plc, action: &ClockAction, journal: &Journal) -> Result<()> {
+    match action {
+        ClockAction::Get => {
+            let plc = read(client).await?;
+            let now = Utc::now();
+            let drift = (plc - now).num_milliseconds() as f64 / 1000.0;
+            let drift_text = format!("{:+.3} s", drift);
+            let drift_text = if drift.abs() >= DRIFT_WARNING_SECS {
+                drift_text.bold().yellow()
+            } else {
+                drift_text.bold().green()
+            };
+            println!(
+                "Controller time:    {}",
+                plc.with_timezone(&Local).to_string().bold()
+            );
+            println!("System time:        {}", now.with_timezone(&Local));
+            println!("Drift:              {}", drift_text);
+        }
+        ClockAction::Set { time, from_system } => {
+            let time = match time {
+                Some(text) if !from_system => parse_time(text)?,
+                _ => Utc::now(),
+            };
+            if time.timestamp_micros() < 0 {
+                bail!("controllers can't be set before 1970");
+            }
+            client.check_writable(None)?;
+            if plc::dry_run() {
+                println!(
+                    "{}    controller clock = {}",
+                    "DRY RUN".bold().yellow(),
+                    time.with_timezone(&Local).to_string().bold()
+                );
+                return Ok(());
+            }
+            client.confirm_write(|address| format!("Set the clock of {}?", address))?;
+            write(client, time).await?;
+            journal.record_operator("clock set", "controller", Some(time.to_rfc3339()))?;
+            println!(
+                "Controller clock set to {}.",
+                time.with_timezone(&Local).to_string().bold()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn clock_path() -> EPath {
+    EPath::default()
+        .with_class(CLASS_WALL_CLOCK_TIME)
+        .with_instance(1)
+}
+
+async fn read(client: &mut Plc) -> Result<DateTime<Utc>> {
+    let holder: BytesHolder = client
+        .eip()?
+        .get_attribute_list(clock_path(), &[ATTR_CURRENT_UTC_VALUE])
+        .await?;
+    let data = Bytes::from(holder);
+    let micros = parse_attribute_list(&data)?;
+    let micros: [u8; 8] = micros
+        .try_into()
+        .context("expected an 8 byte controller time")?;
+    from_micros(u64::from_le_bytes(micros))
+}
+
+async fn write(client: &mut Plc, time: DateTime<Utc>) -> Result<()> {
+    let micros = time.timestamp_micros();
+    let holder: BytesHolder = client
+        .eip()?
+        .set_attribute_list(clock_path(), (1u16, ATTR_CURRENT_VALUE, micros as u64))
+        .await?;
+    parse_attribute_list(&Bytes::from(holder))?;
+    Ok(())
+}
+
+/// Attribute list replies hold the attribute count, then for each attribute
+/// its id, its status and, for reads, its value. Only one attribute is ever
+/// asked for here; its value is returned.
+fn parse_attribute_list(data: &[u8]) -> Result<&[u8]> {
+    if data.len() < 6 {
+        bail!("attribute list reply too short ({} bytes)", data.len());
+    }
+    let status = u16::from_le_bytes([data[4], data[5]]);
+    if status != 0 {
+        bail!(
+            "the controller rejected the clock attribute (status {:#06x})",
+            status
+        );
+    }
+    Ok(&data[6..])
+}
+
+fn from_micros(micros: u64) -> Result<DateTime<Utc>> {
+    let secs = (micros / 1_000_000) as i64;
+    let nanos = (micros % 1_000_000) as u32 * 1000;
+    Utc.timestamp_opt(secs, nanos)
+        .single()
+        .context("controller time out of range")
+}
+
+/// Parse an RFC 3339 time, or a time without offset taken as local time.
+fn parse_time(text: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(text, "%Y-%m-%dT%H:%M:%S"))
+        .with_context(|| {
+            format!(
+                "invalid time `{}`, expected e.g. 2024-03-01T08:00:00Z or 2024-03-01 08:00:00",
+                text
+            )
+        })?;
+    match Local.from_local_datetime(&naive).earliest() {
+        Some(time) => Ok(time.with_timezone(&Utc)),
+        None => bail!("`{}` does not exist in the local time zone", text),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn clock_reply() {
+        let mut data = vec![0x01, 0x00, 0x0B, 0x00, 0x00, 0x00];
+        data.extend(1_700_000_000_123_456u64.to_le_bytes());
+        let value = parse_attribute_list(&data).unwrap();
+        let time = from_micros(u64::from_le_bytes(value.try_into().unwrap())).unwrap();
+        assert_eq!(time.to_rfc3339(), "2023-11-14T22:13:20.123456+00:00");
+        data[4] = 0x05;
+        assert!(parse_attribute_list(&data).is_err());
+        let time = parse_time("2024-03-01T08:00:00Z").unwrap();
+        assert_eq!(time.timestamp(), 1_709_280_000);
+        assert!(parse_time("2024-03-01 08:00:00").is_ok());
+        assert!(parse_time("yesterday").is_err());
+    }
+}
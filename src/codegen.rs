@@ -0,0 +1,465 @@
+use crate::list::TagRecord;
+use crate::plc::{Family, Plc};
+use crate::udt::{read_udts, Member, Udt};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use futures_util::TryStreamExt;
+use rseip::client::ab_eip::*;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+
+/// Words Rust reserves, which fields named after them are escaped from.
+const KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "crate",
+    "do", "dyn", "else", "enum", "extern", "false", "final", "fn", "for", "gen", "if", "impl",
+    "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override", "priv", "pub", "ref",
+    "return", "self", "static", "struct", "super", "trait", "true", "try", "type", "typeof",
+    "unsafe", "unsized", "use", "virtual", "where", "while", "yield",
+];
+
+/// Options of the `codegen` command.
+#[derive(Args)]
+pub struct CodegenArgs {
+    /// Controller tag of the structure type to generate, along with the
+    /// types of its members. Can be repeated.
+    #[arg(long, required = true)]
+    pub tag: Vec<String>,
+    /// Rust file to write, e.g. `src/udt.rs`. The source is printed
+    /// otherwise.
+    #[arg(long)]
+    pub out: Option<PathBuf>,
+}
+
+/// Generate Rust structs for the structure types of tags.
+pub async fn run(client: &mut Plc, args: &CodegenArgs) -> Result<()> {
+    if client.family() == Family::MicroLogix {
+        bail!("{} controllers have no structure types", client.family());
+    }
+    let tags: Vec<TagRecord> = client
+        .eip()?
+        .list_tag()
+        .call()
+        .map_ok(TagRecord::new)
+        .try_collect()
+        .await?;
+    let mut roots = Vec::with_capacity(args.tag.len());
+    for name in &args.tag {
+        // An element of an array of structures has the type of the array.
+        let name = name.split('[').next().unwrap_or(name);
+        let Some(tag) = tags.iter().find(|tag| tag.name.eq_ignore_ascii_case(name)) else {
+            bail!("no controller tag is named {}", name);
+        };
+        match tag.structure_handle {
+            Some(instance_id) => roots.push(instance_id),
+            None => bail!("{} is a {}, not a structure", tag.name, tag.r#type),
+        }
+    }
+    let udts = read_udts(client, roots).await?;
+    let source = to_rust(&udts, client.address())?;
+    match &args.out {
+        Some(path) => {
+            std::fs::write(path, source)
+                .with_context(|| format!("failed to write {}", path.display()))?;
+            println!("Generated {} types in {}.", udts.len(), path.display());
+        }
+        None => print!("{}", source),
+    }
+    Ok(())
+}
+
+/// Rust source of a struct for each type, with the `Decode` and `Encode`
+/// impls reading and writing it with rseip. Types come after the types of
+/// their members, as `read_udts` returns them.
+pub fn to_rust(udts: &[Udt], controller: &str) -> Result<String> {
+    let mut names: HashMap<&str, String> = HashMap::new();
+    let mut source = String::new();
+    let _ = writeln!(
+        source,
+        "//! Structure types of {}, generated by `cobalt codegen`.",
+        controller
+    );
+    let _ = writeln!(source, "//!");
+    let _ = writeln!(
+        source,
+        "//! Read a tag with `let (_, value): (TagType, T) = client.read_tag(tag).await?` and"
+    );
+    let _ = writeln!(
+        source,
+        "//! write it with `client.write_tag(tag, (TagType::Structure(T::HANDLE), 1_u16, value))`."
+    );
+    let _ = writeln!(source);
+    let _ = writeln!(source, "use rseip::bytes::{{BufMut, BytesMut}};");
+    let _ = writeln!(source, "use rseip::{{Decode, Decoder, Encode, Encoder}};");
+
+    for udt in udts {
+        let ident = type_ident(&udt.name);
+        let mut fields = Vec::new();
+        for member in udt.members.iter().filter(|member| !member.hidden) {
+            let field = Field::new(member, &names).with_context(|| {
+                format!("cannot generate member {} of {}", member.name, udt.name)
+            })?;
+            fields.push((member, field));
+        }
+
+        let _ = writeln!(source);
+        let _ = writeln!(source, "/// `{}`, {} bytes.", udt.name, udt.size);
+        let _ = writeln!(source, "#[derive(Debug, Clone, PartialEq)]");
+        let _ = writeln!(source, "pub struct {} {{", ident);
+        for (member, field) in &fields {
+            let at = match member.bit {
+                Some(bit) => format!("bit {} of the byte at offset {}", bit, member.offset),
+                None if member.dimension > 0 => format!(
+                    "{}[{}] at offset {}",
+                    member.data_type, member.dimension, member.offset
+                ),
+                None => format!("{} at offset {}", member.data_type, member.offset),
+            };
+            let _ = writeln!(source, "    /// `{}`, {}.", member.name, at);
+            let _ = writeln!(
+                source,
+                "    pub {}: {},",
+                field.ident,
+                field.rust_type(member)
+            );
+        }
+        let _ = writeln!(source, "}}");
+
+        let _ = writeln!(source);
+        let _ = writeln!(source, "impl {} {{", ident);
+        let _ = writeln!(source, "    /// Bytes of the data of the type.");
+        let _ = writeln!(source, "    pub const SIZE: usize = {};", udt.size);
+        let _ = writeln!(
+            source,
+            "    /// Structure handle, the tag type of the data in a write."
+        );
+        let _ = writeln!(source, "    pub const HANDLE: u16 = {:#06x};", udt.handle);
+        let _ = writeln!(source);
+        let _ = writeln!(
+            source,
+            "    /// Take a value from its data, which holds at least `SIZE` bytes."
+        );
+        let _ = writeln!(source, "    pub fn from_bytes(data: &[u8]) -> Self {{");
+        let _ = writeln!(source, "        Self {{");
+        for (member, field) in &fields {
+            let _ = writeln!(
+                source,
+                "            {}: {},",
+                field.ident,
+                field.read(member)
+            );
+        }
+        let _ = writeln!(source, "        }}");
+        let _ = writeln!(source, "    }}");
+        let _ = writeln!(source);
+        let _ = writeln!(source, "    /// The data of the value.");
+        let _ = writeln!(source, "    pub fn to_bytes(&self) -> [u8; Self::SIZE] {{");
+        let _ = writeln!(source, "        let mut data = [0; Self::SIZE];");
+        for (member, field) in &fields {
+            field.write(member, &mut source);
+        }
+        let _ = writeln!(source, "        data");
+        let _ = writeln!(source, "    }}");
+        let _ = writeln!(source, "}}");
+
+        let _ = writeln!(source);
+        let _ = writeln!(source, "impl<'de> Decode<'de> for {} {{", ident);
+        let _ = writeln!(
+            source,
+            "    fn decode<D: Decoder<'de>>(mut decoder: D) -> Result<Self, D::Error> {{"
+        );
+        let _ = writeln!(source, "        decoder.ensure_size(Self::SIZE)?;");
+        let _ = writeln!(source, "        let mut data = [0; Self::SIZE];");
+        let _ = writeln!(source, "        for byte in &mut data {{");
+        let _ = writeln!(source, "            *byte = decoder.decode_u8();");
+        let _ = writeln!(source, "        }}");
+        let _ = writeln!(source, "        Ok(Self::from_bytes(&data))");
+        let _ = writeln!(source, "    }}");
+        let _ = writeln!(source, "}}");
+
+        let _ = writeln!(source);
+        let _ = writeln!(source, "impl Encode for {} {{", ident);
+        let _ = writeln!(source, "    fn encode_by_ref<A: Encoder>(");
+        let _ = writeln!(source, "        &self,");
+        let _ = writeln!(source, "        buf: &mut BytesMut,");
+        let _ = writeln!(source, "        _encoder: &mut A,");
+        let _ = writeln!(source, "    ) -> Result<(), A::Error> {{");
+        let _ = writeln!(source, "        buf.put_slice(&self.to_bytes());");
+        let _ = writeln!(source, "        Ok(())");
+        let _ = writeln!(source, "    }}");
+        let _ = writeln!(source);
+        let _ = writeln!(source, "    fn bytes_count(&self) -> usize {{");
+        let _ = writeln!(source, "        Self::SIZE");
+        let _ = writeln!(source, "    }}");
+        let _ = writeln!(source, "}}");
+
+        names.insert(&udt.name, ident);
+    }
+    Ok(source)
+}
+
+/// How the value of a member is held in its struct.
+enum Kind {
+    /// A BOOL member, a bit of the byte at its offset.
+    Bit(u8),
+    /// An atomic type with its Rust type and size.
+    Atomic(&'static str, usize),
+    /// Another generated struct.
+    Struct(String),
+}
+
+/// A field of a generated struct.
+struct Field {
+    ident: String,
+    kind: Kind,
+}
+
+impl Field {
+    fn new(member: &Member, names: &HashMap<&str, String>) -> Result<Self> {
+        let kind = match (member.bit, member.data_type.as_str()) {
+            (Some(bit), _) => Kind::Bit(bit),
+            (None, "SINT") => Kind::Atomic("i8", 1),
+            (None, "INT") => Kind::Atomic("i16", 2),
+            (None, "DINT") => Kind::Atomic("i32", 4),
+            (None, "LINT") => Kind::Atomic("i64", 8),
+            (None, "USINT" | "BYTE") => Kind::Atomic("u8", 1),
+            (None, "UINT" | "WORD") => Kind::Atomic("u16", 2),
+            (None, "UDINT" | "DWORD") => Kind::Atomic("u32", 4),
+            (None, "ULINT" | "LWORD") => Kind::Atomic("u64", 8),
+            (None, "REAL") => Kind::Atomic("f32", 4),
+            (None, "LREAL") => Kind::Atomic("f64", 8),
+            (None, name) => match names.get(name) {
+                Some(ident) => Kind::Struct(ident.clone()),
+                None => bail!("{} is not a type cobalt knows the layout of", name),
+            },
+        };
+        Ok(Self {
+            ident: field_ident(&member.name),
+            kind,
+        })
+    }
+
+    fn element_type(&self) -> &str {
+        match &self.kind {
+            Kind::Bit(_) => "bool",
+            Kind::Atomic(rust_type, _) => rust_type,
+            Kind::Struct(ident) => ident,
+        }
+    }
+
+    /// Type of the field, an array of elements for an array member.
+    fn rust_type(&self, member: &Member) -> String {
+        match member.dimension {
+            0 => self.element_type().to_owned(),
+            n => format!("[{}; {}]", self.element_type(), n),
+        }
+    }
+
+    /// Bytes of an element, a literal or the `SIZE` of a struct.
+    fn element_size(&self) -> String {
+        match &self.kind {
+            Kind::Bit(_) => "1".to_owned(),
+            Kind::Atomic(_, size) => size.to_string(),
+            Kind::Struct(ident) => format!("{}::SIZE", ident),
+        }
+    }
+
+    /// Data of an element, `i` being its index in an array member.
+    fn slice(&self, member: &Member, array: bool) -> String {
+        let size = self.element_size();
+        match (array, &self.kind) {
+            (false, Kind::Atomic(_, size)) => {
+                format!("data[{}..{}]", member.offset, member.offset as usize + size)
+            }
+            (false, _) => format!("data[{}..][..{}]", member.offset, size),
+            (true, _) => format!("data[{} + i * {}..][..{}]", member.offset, size, size),
+        }
+    }
+
+    fn read_element(&self, member: &Member, array: bool) -> String {
+        let slice = self.slice(member, array);
+        match &self.kind {
+            Kind::Bit(bit) => format!("data[{}] & (1 << {}) != 0", member.offset, bit),
+            Kind::Atomic(rust_type, _) => {
+                format!(
+                    "{}::from_le_bytes({}.try_into().unwrap())",
+                    rust_type, slice
+                )
+            }
+            Kind::Struct(ident) => format!("{}::from_bytes(&{})", ident, slice),
+        }
+    }
+
+    /// Expression of the value of the field in `from_bytes`.
+    fn read(&self, member: &Member) -> String {
+        if member.dimension == 0 {
+            self.read_element(member, false)
+        } else {
+            format!(
+                "std::array::from_fn(|i| {})",
+                self.read_element(member, true)
+            )
+        }
+    }
+
+    /// Statements putting the value of the field in the data in `to_bytes`.
+    fn write(&self, member: &Member, source: &mut String) {
+        let bytes = match self.kind {
+            Kind::Bit(bit) => {
+                let _ = writeln!(
+                    source,
+                    "        data[{}] |= (self.{} as u8) << {};",
+                    member.offset, self.ident, bit
+                );
+                return;
+            }
+            Kind::Atomic(..) => "to_le_bytes",
+            Kind::Struct(_) => "to_bytes",
+        };
+        if member.dimension == 0 {
+            let _ = writeln!(
+                source,
+                "        {}.copy_from_slice(&self.{}.{}());",
+                self.slice(member, false),
+                self.ident,
+                bytes
+            );
+        } else {
+            let _ = writeln!(
+                source,
+                "        for (i, value) in self.{}.iter().enumerate() {{",
+                self.ident
+            );
+            let _ = writeln!(
+                source,
+                "            {}.copy_from_slice(&value.{}());",
+                self.slice(member, true),
+                bytes
+            );
+            let _ = writeln!(source, "        }}");
+        }
+    }
+}
+
+/// Name of the struct of a type, with the characters Rust doesn't take
+/// in identifiers, like the colons of I/O module types, replaced.
+fn type_ident(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Snake case name of the field of a member, e.g. `pid_gain` for
+/// `PIDGain`.
+fn field_ident(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut ident = String::with_capacity(name.len() + 4);
+    for (i, &c) in chars.iter().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 && !ident.ends_with('_') {
+            let prev = chars[i - 1];
+            let next_lower = chars
+                .get(i + 1)
+                .is_some_and(|next| next.is_ascii_lowercase());
+            if prev.is_ascii_lowercase()
+                || prev.is_ascii_digit()
+                || (prev.is_ascii_uppercase() && next_lower)
+            {
+                ident.push('_');
+            }
+        }
+        ident.push(if c.is_ascii_alphanumeric() {
+            c.to_ascii_lowercase()
+        } else {
+            '_'
+        });
+    }
+    match ident.as_str() {
+        // Raw identifiers can't name these.
+        "crate" | "self" | "super" => ident + "_",
+        keyword if KEYWORDS.contains(&keyword) => format!("r#{}", ident),
+        _ => ident,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(name: &str, data_type: &str, offset: u32) -> Member {
+        Member {
+            name: name.to_owned(),
+            data_type: data_type.to_owned(),
+            dimension: 0,
+            offset,
+            bit: None,
+            hidden: name.starts_with("ZZZZZZZZZZ"),
+        }
+    }
+
+    #[test]
+    fn rust() {
+        let fault = Udt {
+            name: "Fault".to_owned(),
+            instance_id: 0x0f3b,
+            handle: 0x1a2b,
+            size: 8,
+            predefined: false,
+            members: vec![member("Code", "DINT", 0), member("Time", "REAL", 4)],
+        };
+        let motor = Udt {
+            name: "Motor".to_owned(),
+            instance_id: 0x0f3c,
+            handle: 0x6d2e,
+            size: 28,
+            predefined: false,
+            members: vec![
+                member("ZZZZZZZZZZMotor0", "SINT", 0),
+                Member {
+                    bit: Some(1),
+                    ..member("Run", "BOOL", 0)
+                },
+                member("MaxSpeed", "REAL", 4),
+                Member {
+                    dimension: 2,
+                    ..member("Faults", "Fault", 8)
+                },
+                Member {
+                    dimension: 2,
+                    ..member("Type", "INT", 24)
+                },
+            ],
+        };
+        let source = to_rust(&[fault.clone(), motor.clone()], "10.0.0.5").unwrap();
+        assert!(source.contains("pub struct Motor {"));
+        assert!(!source.contains("zzzzzzzzzz"));
+        assert!(source.contains("    pub const HANDLE: u16 = 0x6d2e;"));
+        assert!(source.contains("    pub run: bool,"));
+        assert!(source.contains("            run: data[0] & (1 << 1) != 0,"));
+        assert!(source.contains("        data[0] |= (self.run as u8) << 1;"));
+        assert!(source.contains(
+            "            max_speed: f32::from_le_bytes(data[4..8].try_into().unwrap()),"
+        ));
+        assert!(
+            source.contains("        data[4..8].copy_from_slice(&self.max_speed.to_le_bytes());")
+        );
+        assert!(source.contains("    pub faults: [Fault; 2],"));
+        assert!(source.contains(
+            "            faults: std::array::from_fn(|i| Fault::from_bytes(&data[8 + i * Fault::SIZE..][..Fault::SIZE])),"
+        ));
+        assert!(source.contains("    /// `Type`, INT[2] at offset 24."));
+        assert!(source.contains("    pub r#type: [i16; 2],"));
+        assert!(source.contains(
+            "            data[24 + i * 2..][..2].copy_from_slice(&value.to_le_bytes());"
+        ));
+        assert!(source.contains("impl<'de> Decode<'de> for Motor {"));
+        assert!(source.contains("impl Encode for Motor {"));
+
+        // Members come after their types.
+        assert!(to_rust(&[motor, fault], "10.0.0.5").is_err());
+        assert_eq!(field_ident("PIDGain"), "pid_gain");
+        assert_eq!(field_ident("Speed2Max"), "speed2_max");
+        assert_eq!(field_ident("LEN"), "len");
+        assert_eq!(field_ident("Self"), "self_");
+        assert_eq!(type_ident("AB:1756_IF8:I:0"), "AB_1756_IF8_I_0");
+    }
+}
@@ -0,0 +1,140 @@
+use anyhow::{Context, Result};
+use clap::{Command, ValueEnum};
+use clap_complete::engine::{ArgValueCandidates, CompletionCandidate};
+use clap_complete::env::{CompleteEnv, Shells};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Environment variable through which the shell asks cobalt for the
+/// completions of a command line.
+const COMPLETE_VAR: &str = "COBALT_COMPLETE";
+
+/// Positional arguments taking controller tag names.
+const TAG_ARGS: [&str; 2] = ["tag", "tags"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+    Powershell,
+}
+
+/// Print the script registering the completions of cobalt in a shell. The
+/// script calls back into cobalt, so completions follow the installed
+/// version and tag arguments complete from the tags last exported.
+pub fn run(shell: Shell, command: &Command) -> Result<()> {
+    let name = shell.to_possible_value().unwrap();
+    let shells = Shells::builtins();
+    let completer = shells.completer(name.get_name()).expect("built-in shell");
+    let exe = std::env::current_exe().context("failed to locate the cobalt executable")?;
+    let mut script = Vec::new();
+    completer.write_registration(
+        COMPLETE_VAR,
+        command.get_name(),
+        command.get_name(),
+        &exe.to_string_lossy(),
+        &mut script,
+    )?;
+    std::io::stdout().write_all(&script)?;
+    Ok(())
+}
+
+/// Answer the completion request of a shell and exit, when cobalt was
+/// started for one.
+pub fn complete(command: fn() -> Command) {
+    CompleteEnv::with_factory(move || with_tag_candidates(command()))
+        .var(COMPLETE_VAR)
+        .complete();
+}
+
+/// Make the tag arguments of a command and its subcommands complete from
+/// the cached tag names.
+fn with_tag_candidates(mut command: Command) -> Command {
+    let subcommands: Vec<String> = command
+        .get_subcommands()
+        .map(|sub| sub.get_name().to_owned())
+        .collect();
+    for name in subcommands {
+        command = command.mut_subcommand(name, with_tag_candidates);
+    }
+    let tag_args: Vec<String> = command
+        .get_arguments()
+        .filter(|arg| arg.is_positional() && TAG_ARGS.contains(&arg.get_id().as_str()))
+        .map(|arg| arg.get_id().to_string())
+        .collect();
+    for id in tag_args {
+        command = command.mut_arg(id, |arg| arg.add(ArgValueCandidates::new(cached_tags)));
+    }
+    command
+}
+
+fn cached_tags() -> Vec<CompletionCandidate> {
+    cache_path()
+        .map(|path| read_tags(&path))
+        .unwrap_or_default()
+        .into_iter()
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+/// File holding the tag names of the last `list --export`, in the user
+/// cache directory.
+fn cache_path() -> Option<PathBuf> {
+    Some(crate::tagcache::cache_dir()?.join("tags.txt"))
+}
+
+/// Remember the exported tag names for completion. Failing to is only
+/// worth a warning, the export itself went fine.
+pub fn save_tags<'a>(names: impl IntoIterator<Item = &'a str>) {
+    let path = match cache_path() {
+        Some(path) => path,
+        None => return,
+    };
+    if let Err(e) = write_tags(&path, names) {
+        tracing::warn!("failed to cache the tag names for completion: {:#}", e);
+    }
+}
+
+fn write_tags<'a>(path: &Path, names: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("failed to create {}", dir.display()))?;
+    }
+    let mut text = String::new();
+    for name in names {
+        text.push_str(name);
+        text.push('\n');
+    }
+    std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+}
+
+fn read_tags(path: &Path) -> Vec<String> {
+    std::fs::read_to_string(path)
+        .map(|text| text.lines().map(str::to_owned).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::CommandFactory;
+
+    #[test]
+    fn tag_completion() {
+        let command = with_tag_candidates(crate::Args::command());
+        let read = command.find_subcommand("read").unwrap();
+        let tags = read.get_arguments().find(|arg| arg.get_id() == "tags");
+        assert!(tags.unwrap().get::<ArgValueCandidates>().is_some());
+        let list = command.find_subcommand("list").unwrap();
+        assert!(list
+            .get_arguments()
+            .all(|arg| arg.get::<ArgValueCandidates>().is_none()));
+
+        let path = std::env::temp_dir().join(format!("cobalt-tags-{}.txt", std::process::id()));
+        write_tags(&path, ["PT_101", "Program:Main.Flow"]).unwrap();
+        assert_eq!(read_tags(&path), ["PT_101", "Program:Main.Flow"]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(read_tags(&path).is_empty());
+    }
+}
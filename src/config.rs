@@ -0,0 +1,666 @@
+use crate::alarm;
+use crate::backend::Protocol;
+use crate::bridge::{BridgeConfig, MeterConfig};
+use crate::notify::{NotifyConfig, SmtpTls};
+use crate::tags::TagList;
+use anyhow::{bail, Result};
+use clap::Subcommand;
+use colored::*;
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+#[derive(Subcommand)]
+pub enum ConfigAction {
+    /// Validate configuration files without connecting to a controller.
+    Check {
+        /// Alarm rules file to check.
+        #[arg(long)]
+        alarms: Vec<PathBuf>,
+        /// Tag list file to check.
+        #[arg(long)]
+        tags: Vec<PathBuf>,
+        /// Bridge settings file to check.
+        #[arg(long)]
+        bridge: Vec<PathBuf>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Severity {
+    Warning,
+    Error,
+}
+
+/// Something wrong with a configuration file.
+struct Problem {
+    severity: Severity,
+    /// Entry of the file the problem is about, if not the whole file.
+    entry: Option<String>,
+    message: String,
+}
+
+impl Problem {
+    fn error(entry: impl Into<Option<String>>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Error,
+            entry: entry.into(),
+            message: message.into(),
+        }
+    }
+
+    fn warning(entry: impl Into<Option<String>>, message: impl Into<String>) -> Self {
+        Self {
+            severity: Severity::Warning,
+            entry: entry.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Check the files, with the tags addressed the way the protocol addresses
+/// them.
+pub fn run_action(action: &ConfigAction, protocol: Protocol) -> Result<()> {
+    match action {
+        ConfigAction::Check {
+            alarms,
+            tags,
+            bridge,
+        } => {
+            if alarms.is_empty() && tags.is_empty() && bridge.is_empty() {
+                bail!("nothing to check, pass --alarms, --tags or --bridge");
+            }
+            let mut errors = 0;
+            for path in alarms {
+                errors += report(path, &check_alarms(path, protocol));
+            }
+            for path in tags {
+                errors += report(path, &check_tags(path, protocol));
+            }
+            for path in bridge {
+                errors += report(path, &check_bridge(path, protocol));
+            }
+            if errors > 0 {
+                bail!("{} error(s) found in the configuration", errors);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Print the problems of a file, returning the number of errors.
+fn report(path: &Path, problems: &[Problem]) -> usize {
+    if problems.is_empty() {
+        println!("    {}    {}", "OK".bold().green(), path.display());
+        return 0;
+    }
+    for problem in problems {
+        let severity = match problem.severity {
+            Severity::Error => format!("{:<5}", "ERROR").bold().red(),
+            Severity::Warning => format!("{:<5}", "WARN").bold().yellow(),
+        };
+        let location = match &problem.entry {
+            Some(entry) => format!("{}: {}", path.display(), entry),
+            None => path.display().to_string(),
+        };
+        println!("    {}    {}: {}", severity, location, problem.message);
+    }
+    problems
+        .iter()
+        .filter(|p| p.severity == Severity::Error)
+        .count()
+}
+
+fn check_alarms(path: &Path, protocol: Protocol) -> Vec<Problem> {
+    let (rules, notify) = match alarm::RulesFile::load(path) {
+        Ok(file) => (file.alarms, file.notify),
+        Err(e) => return vec![Problem::error(None, format!("{:#}", e))],
+    };
+    let mut problems = Vec::new();
+    if let Some(notify) = &notify {
+        check_notify(notify, &mut problems);
+    }
+    if rules.is_empty() {
+        problems.push(Problem::warning(None, "no [[alarm]] rules defined"));
+    }
+    let mut names = BTreeSet::new();
+    for (idx, rule) in rules.iter().enumerate() {
+        let entry = format!("alarm #{} `{}`", idx + 1, rule.name);
+        if rule.name.trim().is_empty() {
+            problems.push(Problem::error(entry.clone(), "alarm name is empty"));
+        } else if !names.insert(rule.name.as_str()) {
+            problems.push(Problem::error(
+                entry.clone(),
+                "duplicate alarm name, acknowledgment and shelving would apply to both rules",
+            ));
+        }
+        if let Err(e) = protocol.check_tag(&rule.condition.tag) {
+            problems.push(Problem::error(
+                entry.clone(),
+                format!("invalid tag `{}`: {}", rule.condition.tag, e),
+            ));
+        }
+        if !rule.condition.setpoint.is_finite() {
+            problems.push(Problem::error(
+                entry.clone(),
+                format!("setpoint {} can never be reached", rule.condition.setpoint),
+            ));
+        }
+        if rule.message.is_empty() {
+            problems.push(Problem::warning(
+                entry,
+                "no message, operators will only see the alarm name",
+            ));
+        }
+    }
+    problems
+}
+
+fn check_notify(notify: &NotifyConfig, problems: &mut Vec<Problem>) {
+    let entry = || Some("notify".to_owned());
+    if notify.webhook.is_none() && notify.email.is_none() {
+        problems.push(Problem::error(entry(), "no webhook or email to notify"));
+    }
+    if notify.events.is_empty() {
+        problems.push(Problem::warning(entry(), "no events, nothing is notified"));
+    }
+    if notify.max_per_hour == 0 {
+        problems.push(Problem::error(
+            entry(),
+            "max_per_hour is 0, nothing is notified",
+        ));
+    }
+    if notify.delay.is_zero() && notify.holdoff.is_zero() {
+        problems.push(Problem::warning(
+            entry(),
+            "no delay or holdoff, a flapping alarm notifies every change",
+        ));
+    }
+    if let Some(webhook) = &notify.webhook {
+        let entry = || Some("notify.webhook".to_owned());
+        if !webhook.url.starts_with("http://") && !webhook.url.starts_with("https://") {
+            problems.push(Problem::error(
+                entry(),
+                format!("`{}` is not an http or https URL", webhook.url),
+            ));
+        }
+        for name in webhook.headers.keys() {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                problems.push(Problem::error(
+                    entry(),
+                    format!("invalid header name `{}`", name),
+                ));
+            }
+        }
+    }
+    if let Some(email) = &notify.email {
+        let entry = || Some("notify.email".to_owned());
+        if email.server.is_empty() || email.port() == 0 {
+            problems.push(Problem::error(entry(), "no mail server"));
+        }
+        if email.to.is_empty() {
+            problems.push(Problem::error(entry(), "no recipients in `to`"));
+        }
+        for address in std::iter::once(&email.from).chain(&email.to) {
+            if !address.contains('@') || address.contains(['<', '>', '\r', '\n']) {
+                problems.push(Problem::error(
+                    entry(),
+                    format!("invalid address `{}`", address),
+                ));
+            }
+        }
+        if email.password.is_some() && email.username.is_none() {
+            problems.push(Problem::warning(
+                entry(),
+                "password without username, the server is not logged into",
+            ));
+        }
+        if email.tls == SmtpTls::None && email.username.is_some() {
+            problems.push(Problem::warning(
+                entry(),
+                "credentials are sent in the clear without tls",
+            ));
+        }
+    }
+}
+
+fn check_tags(path: &Path, protocol: Protocol) -> Vec<Problem> {
+    let list = match TagList::load(path) {
+        Ok(list) => list,
+        Err(e) => return vec![Problem::error(None, format!("{:#}", e))],
+    };
+    let mut problems = Vec::new();
+    if list.tags.is_empty() {
+        problems.push(Problem::warning(None, "no [[tag]] entries defined"));
+    }
+    let mut names = BTreeSet::new();
+    for (idx, tag) in list.tags.iter().enumerate() {
+        let entry = format!("tag #{} `{}`", idx + 1, tag.name);
+        if !names.insert(tag.name.as_str()) {
+            problems.push(Problem::error(entry.clone(), "tag listed twice"));
+        }
+        if let Err(e) = protocol.check_tag(&tag.name) {
+            problems.push(Problem::error(entry.clone(), format!("invalid tag: {}", e)));
+        }
+        if let Some(freeze) = &tag.freeze {
+            if freeze.after.is_zero() {
+                problems.push(Problem::error(
+                    entry.clone(),
+                    "freeze period is zero, the tag would always be frozen",
+                ));
+            }
+            if !(freeze.tolerance.is_finite() && freeze.tolerance >= 0.0) {
+                problems.push(Problem::error(
+                    entry.clone(),
+                    format!("freeze tolerance {} must be zero or more", freeze.tolerance),
+                ));
+            }
+        }
+        for label in tag.labels.keys() {
+            if label == "tag" {
+                problems.push(Problem::error(
+                    entry.clone(),
+                    "label `tag` is reserved for the tag name",
+                ));
+            } else if !is_label_name(label) {
+                problems.push(Problem::error(
+                    entry.clone(),
+                    format!(
+                        "invalid label name `{}`, use letters, digits and underscores",
+                        label
+                    ),
+                ));
+            }
+        }
+    }
+    problems
+}
+
+fn check_bridge(path: &Path, protocol: Protocol) -> Vec<Problem> {
+    let config = match BridgeConfig::load(path) {
+        Ok(config) => config,
+        Err(e) => return vec![Problem::error(None, format!("{:#}", e))],
+    };
+    let mut problems = Vec::new();
+    // Tags written by the meters checked so far, and their slave ids.
+    let mut written = Vec::new();
+    let mut slaves = Vec::new();
+    for (idx, meter) in config.all_meters().iter().enumerate() {
+        let prefix = match idx {
+            0 => String::new(),
+            idx => format!("meter[{}].", idx - 1),
+        };
+        if slaves.contains(&meter.slave) {
+            problems.push(Problem::error(
+                format!("{}slave", prefix),
+                format!("slave {} is already polled on this line", meter.slave),
+            ));
+        }
+        slaves.push(meter.slave);
+        check_meter(&prefix, meter, protocol, &mut written, &mut problems);
+    }
+    if !(5..=8).contains(&config.data_bits) {
+        problems.push(Problem::error(
+            "data_bits".to_owned(),
+            format!("{} data bits, use 5 to 8", config.data_bits),
+        ));
+    }
+    if !(1..=2).contains(&config.stop_bits) {
+        problems.push(Problem::error(
+            "stop_bits".to_owned(),
+            format!("{} stop bits, use 1 or 2", config.stop_bits),
+        ));
+    }
+    // Even the highest sites are above 0.3 bar.
+    if !(0.3..=1.1).contains(&config.atmospheric) {
+        problems.push(Problem::error(
+            "atmospheric".to_owned(),
+            format!(
+                "{} is not an atmospheric pressure in bar",
+                config.atmospheric
+            ),
+        ));
+    }
+    if !config.stale.fallback.is_finite() {
+        problems.push(Problem::error(
+            "stale.fallback".to_owned(),
+            "fallback must be a number",
+        ));
+    }
+    if config.stale.timeout.is_zero() {
+        problems.push(Problem::error(
+            "stale.timeout".to_owned(),
+            "the meter is never given time to answer",
+        ));
+    }
+    problems
+}
+
+/// Check the settings of one meter of a bridge, whose keys start with
+/// `prefix`. `written` holds the tags already written by the others.
+fn check_meter(
+    prefix: &str,
+    meter: &MeterConfig,
+    protocol: Protocol,
+    written: &mut Vec<String>,
+    problems: &mut Vec<Problem>,
+) {
+    let key = |key: &str| format!("{}{}", prefix, key);
+    let tags = [
+        ("pressure_tag", &meter.pressure_tag),
+        ("temperature_tag", &meter.temperature_tag),
+        ("rate_tag", &meter.rate_tag),
+        ("rate_tag_base", &meter.rate_tag_base),
+    ];
+    for (name, tag) in tags {
+        if let Err(e) = protocol.check_tag(tag) {
+            problems.push(Problem::error(
+                key(name),
+                format!("invalid tag `{}`: {}", tag, e),
+            ));
+        }
+    }
+    if meter.slave == 0 || meter.slave > 247 {
+        problems.push(Problem::error(
+            key("slave"),
+            format!("{} is not a Modbus slave id, use 1 to 247", meter.slave),
+        ));
+    }
+    if meter
+        .rtu_register_velocity
+        .abs_diff(meter.rtu_register_rate)
+        < 2
+    {
+        problems.push(Problem::error(
+            key("rtu_register_rate"),
+            "overlaps the velocity register, each value spans two registers",
+        ));
+    }
+    // Pipe sizes are given in inches; millimetres are a common mistake.
+    if !(0.5..=60.0).contains(&meter.diameter) {
+        problems.push(Problem::error(
+            key("diameter"),
+            format!("{} is not a pipe diameter in inches", meter.diameter),
+        ));
+    }
+    if let Some(orifice) = &meter.orifice {
+        if !(orifice.diameter > 0.0 && orifice.diameter < meter.diameter) {
+            problems.push(Problem::error(
+                key("orifice.diameter"),
+                format!(
+                    "a bore of {} in doesn't fit the {} in pipe",
+                    orifice.diameter, meter.diameter
+                ),
+            ));
+        }
+        if let Err(e) = protocol.check_tag(&orifice.differential_pressure_tag) {
+            problems.push(Problem::error(
+                key("orifice.differential_pressure_tag"),
+                format!("invalid tag `{}`: {}", orifice.differential_pressure_tag, e),
+            ));
+        }
+    }
+    let scalings = [
+        ("scaling.velocity", &meter.scaling.velocity),
+        ("scaling.rate", &meter.scaling.rate),
+    ];
+    for (name, scaling) in scalings {
+        if scaling.scale == 0.0 || !scaling.scale.is_finite() || !scaling.offset.is_finite() {
+            problems.push(Problem::error(
+                key(name),
+                "scale must be a non-zero number and offset a number",
+            ));
+        }
+        if let (Some(min), Some(max)) = (scaling.min, scaling.max) {
+            if min > max {
+                problems.push(Problem::error(
+                    key(name),
+                    format!("min {} is above max {}", min, max),
+                ));
+            }
+        }
+    }
+    if meter.rate_tag == meter.rate_tag_base {
+        problems.push(Problem::error(
+            key("rate_tag_base"),
+            "same tag as rate_tag, one value would overwrite the other",
+        ));
+    }
+    let mut own = vec![meter.rate_tag.clone()];
+    for derived in meter.derived() {
+        if let Err(e) = &derived.expr {
+            problems.push(Problem::error(key(&derived.key), format!("{:#}", e)));
+        }
+        if derived.key != "rate_base" {
+            if let Err(e) = protocol.check_tag(&derived.tag) {
+                problems.push(Problem::error(
+                    key(&derived.key),
+                    format!("invalid tag `{}`: {}", derived.tag, e),
+                ));
+            }
+            if own.contains(&derived.tag) {
+                problems.push(Problem::error(
+                    key(&derived.key),
+                    format!("tag `{}` is already written by the bridge", derived.tag),
+                ));
+            }
+        }
+        own.push(derived.tag);
+    }
+    for tag in &own {
+        if written.contains(tag) {
+            let meter = (!prefix.is_empty()).then(|| prefix.trim_end_matches('.').to_owned());
+            problems.push(Problem::error(
+                meter,
+                format!("tag `{}` is also written for another meter", tag),
+            ));
+        }
+    }
+    for (tag, flag) in &meter.quality {
+        let name = key(&format!("quality.{}", tag));
+        if !own.contains(tag) {
+            problems.push(Problem::error(
+                name.clone(),
+                format!("`{}` is not written by the bridge", tag),
+            ));
+        }
+        if let Err(e) = protocol.check_tag(flag) {
+            problems.push(Problem::error(
+                name,
+                format!("invalid tag `{}`: {}", flag, e),
+            ));
+        }
+    }
+    written.extend(own);
+}
+
+/// Whether a label name is accepted by Prometheus.
+fn is_label_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Check a file with the given contents, listing its problems as
+    /// `<severity> <entry>: <message>`.
+    fn check(check: fn(&Path, Protocol) -> Vec<Problem>, text: &str) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!(
+            "cobalt-config-{}-{:?}.toml",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, text).unwrap();
+        let problems = check(&path, Protocol::Ab);
+        std::fs::remove_file(&path).unwrap();
+        problems
+            .into_iter()
+            .map(|problem| {
+                format!(
+                    "{:?} {}: {}",
+                    problem.severity,
+                    problem.entry.unwrap_or_default(),
+                    problem.message
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn alarms() {
+        let valid = r#"
+            [[alarm]]
+            name = "high_pressure"
+            condition = "PT_101 > 80"
+            message = "Pressure high"
+        "#;
+        assert!(check(check_alarms, valid).is_empty());
+        assert_eq!(
+            check(
+                check_alarms,
+                r#"
+                [[alarm]]
+                name = "high_pressure"
+                condition = "PT_101 > 80"
+                message = "Pressure high"
+
+                [[alarm]]
+                name = "high_pressure"
+                condition = "Motor[1 == true"
+                "#
+            ),
+            [
+                "Error alarm #2 `high_pressure`: duplicate alarm name, acknowledgment and \
+                 shelving would apply to both rules",
+                "Error alarm #2 `high_pressure`: invalid tag `Motor[1`: syntax error - unexpected end of input buffer",
+                "Warning alarm #2 `high_pressure`: no message, operators will only see the alarm name",
+            ]
+        );
+        assert_eq!(
+            check(check_alarms, ""),
+            ["Warning : no [[alarm]] rules defined"]
+        );
+        let unparsable = check(
+            check_alarms,
+            "[[alarm]]\nname = \"x\"\ncondition = \"PT_101\"",
+        );
+        assert_eq!(unparsable.len(), 1);
+        assert!(unparsable[0].contains("no comparison operator in condition `PT_101`"));
+    }
+
+    #[test]
+    fn notify() {
+        let problems = check(
+            check_alarms,
+            r#"
+            [[alarm]]
+            name = "high_pressure"
+            condition = "PT_101 > 80"
+            message = "Pressure high"
+
+            [notify]
+            max_per_hour = 0
+            holdoff = "0s"
+
+            [notify.webhook]
+            url = "ftp://example.com"
+            headers = { "X Token" = "secret" }
+            "#,
+        );
+        assert_eq!(
+            problems,
+            [
+                "Error notify: max_per_hour is 0, nothing is notified",
+                "Warning notify: no delay or holdoff, a flapping alarm notifies every change",
+                "Error notify.webhook: `ftp://example.com` is not an http or https URL",
+                "Error notify.webhook: invalid header name `X Token`",
+            ]
+        );
+    }
+
+    #[test]
+    fn tags() {
+        assert!(check(check_tags, "[[tag]]\nname = \"PT_101\"").is_empty());
+        assert_eq!(
+            check(
+                check_tags,
+                r#"
+                [[tag]]
+                name = "PT_101"
+                labels = { tag = "x", "2nd" = "y" }
+                freeze = { after = "0s", tolerance = -1.0 }
+
+                [[tag]]
+                name = "PT_101"
+                "#
+            ),
+            [
+                "Error tag #1 `PT_101`: freeze period is zero, the tag would always be frozen",
+                "Error tag #1 `PT_101`: freeze tolerance -1 must be zero or more",
+                "Error tag #1 `PT_101`: invalid label name `2nd`, use letters, digits and underscores",
+                "Error tag #1 `PT_101`: label `tag` is reserved for the tag name",
+                "Error tag #2 `PT_101`: tag listed twice",
+            ]
+        );
+        let unknown = check(check_tags, "[[tag]]\nname = \"PT_101\"\nunit = \"bar\"");
+        assert_eq!(unknown.len(), 1);
+        assert!(unknown[0].contains("unknown field `unit`"));
+        assert!(is_label_name("_unit2"));
+        assert!(!is_label_name("unit-2"));
+    }
+
+    #[test]
+    fn bridge() {
+        let line = r#"
+            port = "/dev/ttyUSB0"
+            slave = 1
+            baudrate = 9600
+            rtu_register_velocity = 0
+            rtu_register_rate = 2
+            pressure_tag = "PT_101"
+            temperature_tag = "TT_101"
+            diameter = 4.0
+            rate_tag = "FT_101_Rate"
+            rate_tag_base = "FT_101_RateBase"
+        "#;
+        assert!(check(check_bridge, line).is_empty());
+        let problems = check(
+            check_bridge,
+            &format!(
+                r#"
+                data_bits = 9
+                atmospheric = 101.325
+                {}
+
+                [[meter]]
+                slave = 1
+                rtu_register_velocity = 0
+                rtu_register_rate = 1
+                pressure_tag = "PT_102"
+                temperature_tag = "TT_102"
+                diameter = 150.0
+                rate_tag = "FT_101_Rate"
+                rate_tag_base = "FT_102_RateBase"
+                "#,
+                line
+            ),
+        );
+        assert_eq!(
+            problems,
+            [
+                "Error meter[0].slave: slave 1 is already polled on this line",
+                "Error meter[0].rtu_register_rate: overlaps the velocity register, each value \
+                 spans two registers",
+                "Error meter[0].diameter: 150 is not a pipe diameter in inches",
+                "Error meter[0]: tag `FT_101_Rate` is also written for another meter",
+                "Error data_bits: 9 data bits, use 5 to 8",
+                "Error atmospheric: 101.325 is not an atmospheric pressure in bar",
+            ]
+        );
+        let missing = check(check_bridge, "port = \"/dev/ttyUSB0\"");
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].contains("missing field `slave`"));
+    }
+}
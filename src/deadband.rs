@@ -0,0 +1,104 @@
+use crate::monitor::{Quality, Sample};
+use crate::value::PlcValue;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Change a floating point value has to exceed to be reported again.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Deadband {
+    Absolute(f64),
+    /// Percent of the last reported value.
+    Percent(f64),
+}
+
+impl FromStr for Deadband {
+    type Err = String;
+
+    /// Parse `0.5` as an absolute deadband or `2%` as a relative one.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (number, percent) = match s.trim().strip_suffix('%') {
+            Some(number) => (number, true),
+            None => (s.trim(), false),
+        };
+        let value: f64 = number
+            .trim()
+            .parse()
+            .map_err(|_| format!("invalid deadband `{}`, expected e.g. 0.5 or 2%", s))?;
+        if !(value.is_finite() && value >= 0.0) {
+            return Err(format!("deadband `{}` must be zero or more", s));
+        }
+        Ok(if percent {
+            Deadband::Percent(value)
+        } else {
+            Deadband::Absolute(value)
+        })
+    }
+}
+
+/// Drops REAL and LREAL samples that moved less than the deadband since the
+/// last one reported for their tag. Other types always pass.
+pub struct DeadbandFilter {
+    deadband: Deadband,
+    reported: HashMap<String, (f64, Quality)>,
+}
+
+impl DeadbandFilter {
+    pub fn new(deadband: Deadband) -> Self {
+        Self {
+            deadband,
+            reported: HashMap::new(),
+        }
+    }
+
+    /// Whether the sample should be reported. A change of quality is always
+    /// reported.
+    pub fn passes(&mut self, sample: &Sample) -> bool {
+        let value = match sample.value {
+            PlcValue::Real(v) => v as f64,
+            PlcValue::Lreal(v) => v,
+            _ => return true,
+        };
+        if let Some(&(last, quality)) = self.reported.get(&sample.tag) {
+            let band = match self.deadband {
+                Deadband::Absolute(band) => band,
+                Deadband::Percent(percent) => last.abs() * percent / 100.0,
+            };
+            if quality == sample.quality && (value - last).abs() <= band {
+                return false;
+            }
+        }
+        self.reported
+            .insert(sample.tag.clone(), (value, sample.quality));
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::Local;
+
+    #[test]
+    fn deadband() {
+        assert_eq!("0.5".parse(), Ok(Deadband::Absolute(0.5)));
+        assert_eq!("2 %".parse(), Ok(Deadband::Percent(2.0)));
+        assert!("-1".parse::<Deadband>().is_err());
+        assert!("%".parse::<Deadband>().is_err());
+
+        let mut filter = DeadbandFilter::new(Deadband::Percent(10.0));
+        let mut passes = |value, quality| {
+            filter.passes(&Sample {
+                timestamp: Local::now(),
+                tag: "FT_201".to_owned(),
+                value: PlcValue::Real(value),
+                quality,
+            })
+        };
+        assert!(passes(100.0, Quality::Good));
+        assert!(!passes(109.0, Quality::Good));
+        assert!(!passes(91.0, Quality::Good));
+        assert!(passes(111.0, Quality::Good));
+        assert!(passes(111.0, Quality::Frozen));
+        assert!(!passes(111.0, Quality::Frozen));
+    }
+}
@@ -0,0 +1,254 @@
+use crate::failure::Failure;
+use crate::list::{self, element_type_name, type_name};
+use crate::plc::{self, Family, Plc};
+use crate::udt;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use colored::*;
+use rseip::client::ab_eip::*;
+use rseip::precludes::*;
+use rseip::{BytesHolder, ClientError};
+use serde::Deserialize;
+use std::fmt::Display;
+
+/// Logix attributes of the Symbol object describing a tag beyond its type.
+const ATTR_DIMENSIONS: u16 = 8;
+const ATTR_CONSTANT: u16 = 9;
+const ATTR_EXTERNAL_ACCESS: u16 = 10;
+
+/// Who outside the controller may read and write a tag.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ExternalAccess {
+    #[default]
+    ReadWrite,
+    ReadOnly,
+    /// Left out of the tag list and refused to every client.
+    None,
+}
+
+impl ExternalAccess {
+    pub fn code(self) -> u8 {
+        match self {
+            ExternalAccess::ReadWrite => 0,
+            ExternalAccess::ReadOnly => 2,
+            ExternalAccess::None => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(ExternalAccess::ReadWrite),
+            2 => Some(ExternalAccess::ReadOnly),
+            3 => Some(ExternalAccess::None),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ExternalAccess {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ExternalAccess::ReadWrite => "Read/Write",
+            ExternalAccess::ReadOnly => "Read Only",
+            ExternalAccess::None => "None",
+        })
+    }
+}
+
+/// What the Symbol object tells of a tag, each attribute being unknown when
+/// the controller doesn't report it.
+#[derive(Debug, Default, PartialEq)]
+struct Metadata {
+    /// Length of each dimension of an array, outermost first.
+    dims: Option<Vec<u32>>,
+    external_access: Option<ExternalAccess>,
+    constant: Option<bool>,
+}
+
+/// Show the type, array dimensions, element count, external access and
+/// constant flag of a controller scope tag, and whether writes to it go
+/// through.
+pub async fn run(client: &mut Plc, tag: &str) -> Result<()> {
+    if client.family() == Family::MicroLogix {
+        bail!(
+            "{} controllers have no tag list, address their data files like N7:0",
+            client.family()
+        );
+    }
+    // Members, elements and bits are described by their tag.
+    let name = tag.split(['.', '[']).next().unwrap_or(tag);
+    if name.contains(':') {
+        bail!("describe takes controller scope tags, not {}", name);
+    }
+    let Some((name, instance, symbol_type)) = list::find_symbol(client, name).await? else {
+        return Err(Failure::TagNotFound.error(format!(
+            "{} has no controller scope tag {}, or its external access is None",
+            client.address(),
+            name
+        )));
+    };
+    let metadata = read_metadata(client, &name, instance, symbol_type.dims()).await?;
+
+    let data_type = match symbol_type.instance_id() {
+        Some(id) => match udt::read_udts(client, [id]).await {
+            Ok(udts) => match udts.iter().find(|udt| udt.instance_id == id) {
+                Some(udt) => udt.name.clone(),
+                None => type_name(symbol_type),
+            },
+            Err(e) => {
+                tracing::warn!("failed to read the structure type of {}: {:#}", name, e);
+                type_name(symbol_type)
+            }
+        },
+        None => element_type_name(symbol_type).to_owned(),
+    };
+    println!("{}", name.bold());
+    println!("  {:<17}{}", "Data type:", data_type);
+    match &metadata.dims {
+        Some(dims) if dims.is_empty() => println!("  {:<17}none", "Dimensions:"),
+        Some(dims) => {
+            let text: Vec<String> = dims.iter().map(u32::to_string).collect();
+            println!("  {:<17}{}", "Dimensions:", text.join(" x "));
+        }
+        None => println!("  {:<17}{}", "Dimensions:", unknown(symbol_type.dims())),
+    }
+    match &metadata.dims {
+        Some(dims) => println!("  {:<17}{}", "Elements:", dims.iter().product::<u32>()),
+        None if symbol_type.dims() == 0 => println!("  {:<17}1", "Elements:"),
+        None => println!("  {:<17}unknown", "Elements:"),
+    }
+    match metadata.external_access {
+        Some(access) => println!("  {:<17}{}", "External access:", access),
+        None => println!("  {:<17}unknown", "External access:"),
+    }
+    match metadata.constant {
+        Some(constant) => println!("  {:<17}{}", "Constant:", yes_no(constant)),
+        None => println!("  {:<17}unknown", "Constant:"),
+    }
+    println!("  {:<17}{}", "Instance:", instance);
+
+    let refused = if metadata.constant == Some(true) {
+        Some("it is a constant".to_owned())
+    } else if let Some(access @ (ExternalAccess::ReadOnly | ExternalAccess::None)) =
+        metadata.external_access
+    {
+        Some(format!("its external access is {}", access))
+    } else {
+        client
+            .check_writable(Some(&name))
+            .err()
+            .map(|e| e.to_string())
+    };
+    match refused {
+        Some(reason) => println!(
+            "  {:<17}{}",
+            "Writable:",
+            format!("no, {}", reason).yellow()
+        ),
+        None => println!("  {:<17}{}", "Writable:", "yes".green()),
+    }
+    Ok(())
+}
+
+fn unknown(dims: u8) -> String {
+    format!(
+        "{} dimension{}, lengths unknown",
+        dims,
+        if dims == 1 { "" } else { "s" }
+    )
+}
+
+fn yes_no(value: bool) -> &'static str {
+    if value {
+        "yes"
+    } else {
+        "no"
+    }
+}
+
+/// Read the attributes one at a time, so one the controller lacks leaves
+/// the others known.
+async fn read_metadata(client: &mut Plc, tag: &str, instance: u16, dims: u8) -> Result<Metadata> {
+    let mut metadata = Metadata::default();
+    if let Some(value) = attribute(client, tag, instance, ATTR_DIMENSIONS).await? {
+        metadata.dims = Some(dimensions(&value, dims));
+    }
+    if let Some(value) = attribute(client, tag, instance, ATTR_EXTERNAL_ACCESS).await? {
+        metadata.external_access = value.first().copied().and_then(ExternalAccess::from_code);
+    }
+    if let Some(value) = attribute(client, tag, instance, ATTR_CONSTANT).await? {
+        metadata.constant = value.first().map(|&constant| constant != 0);
+    }
+    Ok(metadata)
+}
+
+/// The value of an attribute of a symbol instance, none when the controller
+/// doesn't have the attribute.
+async fn attribute(client: &mut Plc, tag: &str, instance: u16, id: u16) -> Result<Option<Bytes>> {
+    let path = EPath::default()
+        .with_class(CLASS_SYMBOL)
+        .with_instance(instance);
+    let result: Result<BytesHolder, ClientError> = plc::transaction(
+        "get attribute list",
+        tag,
+        client.eip()?.get_attribute_list(path, &[id]),
+    )
+    .await;
+    match result {
+        Ok(holder) => Ok(attribute_value(Bytes::from(holder))),
+        Err(ClientError::Status { status, .. }) if matches!(status.general, 0x08 | 0x0A | 0x14) => {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// The value of the only attribute of an attribute list reply: the count,
+/// then the id, status and value of the attribute.
+fn attribute_value(data: Bytes) -> Option<Bytes> {
+    if data.len() < 6 || u16::from_le_bytes([data[4], data[5]]) != 0 {
+        return None;
+    }
+    Some(data.slice(6..))
+}
+
+/// Lengths of the dimensions used of the three the attribute holds.
+fn dimensions(value: &[u8], dims: u8) -> Vec<u32> {
+    value
+        .chunks_exact(4)
+        .take(dims as usize)
+        .map(|len| u32::from_le_bytes([len[0], len[1], len[2], len[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn attributes() {
+        let reply = |id: u16, status: u16, value: &[u8]| {
+            let mut data = 1u16.to_le_bytes().to_vec();
+            data.extend_from_slice(&id.to_le_bytes());
+            data.extend_from_slice(&status.to_le_bytes());
+            data.extend_from_slice(value);
+            Bytes::from(data)
+        };
+        let mut value = Vec::new();
+        for len in [10u32, 4, 0] {
+            value.extend_from_slice(&len.to_le_bytes());
+        }
+        let dims = attribute_value(reply(ATTR_DIMENSIONS, 0, &value)).unwrap();
+        assert_eq!(dimensions(&dims, 2), [10, 4]);
+        assert_eq!(dimensions(&dims, 0), Vec::<u32>::new());
+        assert_eq!(attribute_value(reply(ATTR_CONSTANT, 0x14, &[])), None);
+        assert_eq!(attribute_value(Bytes::from_static(&[1, 0])), None);
+
+        assert_eq!(ExternalAccess::from_code(2), Some(ExternalAccess::ReadOnly));
+        assert_eq!(ExternalAccess::from_code(1), None);
+        assert_eq!(ExternalAccess::None.to_string(), "None");
+        let access: ExternalAccess = toml::Value::from("read-only").try_into().unwrap();
+        assert_eq!(access.code(), 2);
+    }
+}
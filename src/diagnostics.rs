@@ -0,0 +1,627 @@
+use crate::plc::Plc;
+use crate::value;
+use anyhow::{bail, Result};
+use bytes::Bytes;
+use colored::*;
+use rseip::precludes::*;
+use rseip::BytesHolder;
+use std::time::{Duration, Instant};
+
+/// Connection Manager object class.
+const CLASS_CONNECTION_MANAGER: u16 = 0x06;
+
+/// Connection Manager instance attributes holding the open/close counters.
+const CONNECTION_COUNTERS: [(u16, &str); 8] = [
+    (1, "Open requests"),
+    (2, "Open format rejects"),
+    (3, "Open resource rejects"),
+    (4, "Open other rejects"),
+    (5, "Close requests"),
+    (6, "Close format requests"),
+    (7, "Close other requests"),
+    (8, "Connection timeouts"),
+];
+
+/// Connection Manager attribute holding the connection entry list.
+const ATTR_CONNECTION_ENTRY_LIST: u16 = 9;
+
+/// Connection slot usage of the controller, as reported by its Connection Manager.
+#[derive(Debug, Default)]
+pub struct ConnectionUsage {
+    /// Connection entries in use, if the entry list is readable.
+    pub in_use: Option<u16>,
+    /// Total connection entries supported, if the entry list is readable.
+    pub limit: Option<u16>,
+    /// Counters that could be read, with their labels.
+    pub counters: Vec<(&'static str, u16)>,
+}
+
+impl ConnectionUsage {
+    /// Read the connection usage from the controller.
+    ///
+    /// Controllers don't have to expose every attribute, so each one is read
+    /// separately and the ones that are rejected are left out of the report.
+    pub async fn read(client: &mut Plc) -> Result<Self> {
+        let session = client.eip()?;
+        let mut usage = ConnectionUsage::default();
+
+        let path = connection_manager_path(ATTR_CONNECTION_ENTRY_LIST);
+        if let Ok(holder) = session.get_attribute_single::<BytesHolder>(path).await {
+            if let Some((in_use, limit)) = parse_connection_entry_list(holder.into()) {
+                usage.in_use = Some(in_use);
+                usage.limit = Some(limit);
+            }
+        }
+
+        for (attribute, label) in CONNECTION_COUNTERS {
+            let path = connection_manager_path(attribute);
+            if let Ok(value) = session.get_attribute_single::<u16>(path).await {
+                usage.counters.push((label, value));
+            }
+        }
+
+        Ok(usage)
+    }
+
+    /// Print the usage report.
+    pub fn print(&self) {
+        match (self.in_use, self.limit) {
+            (Some(in_use), Some(limit)) => {
+                let used = format!("{}/{}", in_use, limit);
+                let used = if limit > 0 && in_use as u32 * 100 / limit as u32 >= 90 {
+                    used.bold().red()
+                } else {
+                    used.bold().green()
+                };
+                println!("Connections in use:    {}", used);
+            }
+            _ => println!(
+                "Connections in use:    {}",
+                "not readable on this controller".yellow()
+            ),
+        }
+        for (label, value) in &self.counters {
+            println!("    {:<24}{}", label, value);
+        }
+    }
+}
+
+fn connection_manager_path(attribute: u16) -> EPath {
+    EPath::default()
+        .with_class(CLASS_CONNECTION_MANAGER)
+        .with_instance(1)
+        .with_attribute(attribute)
+}
+
+/// The connection entry list is a UINT holding the number of entries followed
+/// by a bit string where each set bit is an open connection.
+fn parse_connection_entry_list(data: Bytes) -> Option<(u16, u16)> {
+    if data.len() < 2 {
+        return None;
+    }
+    let limit = u16::from_le_bytes([data[0], data[1]]);
+    let bits = &data[2..];
+    let in_use = bits
+        .iter()
+        .enumerate()
+        .flat_map(|(byte_idx, byte)| (0..8).map(move |bit| (byte_idx * 8 + bit, byte >> bit & 1)))
+        .filter(|(idx, set)| *idx < limit as usize && *set == 1)
+        .count();
+    Some((in_use as u16, limit))
+}
+
+/// Identity object class.
+const CLASS_IDENTITY: u16 = 0x01;
+
+/// Device identity and status, as reported by the Identity object.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub vendor_id: u16,
+    pub device_type: u16,
+    pub product_code: u16,
+    pub revision: (u8, u8),
+    pub status: u16,
+    pub serial_number: u32,
+    pub product_name: String,
+}
+
+impl Identity {
+    /// Read the identity of the controller with Get Attributes All.
+    pub async fn read(client: &mut Plc) -> Result<Self> {
+        let path = EPath::default().with_class(CLASS_IDENTITY).with_instance(1);
+        let holder = client.eip()?.get_attribute_all::<BytesHolder>(path).await?;
+        Self::parse(&Bytes::from(holder))
+    }
+
+    /// The attributes come in order: vendor, device type, product code,
+    /// revision, status, serial number and the product name as a SHORT_STRING.
+    fn parse(data: &[u8]) -> Result<Self> {
+        if data.len() < 15 || data.len() < 15 + data[14] as usize {
+            bail!("identity reply too short ({} bytes)", data.len());
+        }
+        let name = &data[15..15 + data[14] as usize];
+        Ok(Self {
+            vendor_id: u16::from_le_bytes([data[0], data[1]]),
+            device_type: u16::from_le_bytes([data[2], data[3]]),
+            product_code: u16::from_le_bytes([data[4], data[5]]),
+            revision: (data[6], data[7]),
+            status: u16::from_le_bytes([data[8], data[9]]),
+            serial_number: u32::from_le_bytes(data[10..14].try_into()?),
+            product_name: String::from_utf8_lossy(name).into_owned(),
+        })
+    }
+
+    /// Operating mode, from the extended device status bits. Logix
+    /// controllers report 6 in Run and 7 in Program.
+    pub fn mode(&self) -> &'static str {
+        match self.status >> 4 & 0x0F {
+            0 => "Self-testing or unknown",
+            1 => "Firmware update in progress",
+            2 => "I/O connection faulted",
+            3 => "No I/O connections",
+            4 => "Non-volatile configuration bad",
+            5 => "Major fault",
+            6 => "Run",
+            7 => "Program",
+            _ => "Unknown",
+        }
+    }
+
+    /// Position of the keyswitch, which Logix controllers report in bits 12
+    /// and 13 of the status.
+    pub fn keyswitch(&self) -> Option<&'static str> {
+        match self.status >> 12 & 0x03 {
+            1 => Some("Run"),
+            2 => Some("Program"),
+            3 => Some("Remote"),
+            _ => None,
+        }
+    }
+
+    /// Faults flagged by bits 8 to 11 of the status.
+    pub fn faults(&self) -> Vec<&'static str> {
+        [
+            (8, "minor recoverable"),
+            (9, "minor unrecoverable"),
+            (10, "major recoverable"),
+            (11, "major unrecoverable"),
+        ]
+        .into_iter()
+        .filter(|(bit, _)| self.status & 1 << bit != 0)
+        .map(|(_, label)| label)
+        .collect()
+    }
+
+    /// Print the identity report.
+    pub fn print(&self) {
+        let vendor = match self.vendor_id {
+            1 => "Rockwell Automation/Allen-Bradley".to_owned(),
+            id => format!("Vendor {}", id),
+        };
+        let device_type = match self.device_type {
+            0x0C => "Communications adapter".to_owned(),
+            0x0E => "Programmable logic controller".to_owned(),
+            id => format!("Device type {:#06x}", id),
+        };
+        println!("Product name:          {}", self.product_name.bold());
+        println!("Vendor:                {}", vendor);
+        println!("Device type:           {}", device_type);
+        println!("Product code:          {}", self.product_code);
+        println!(
+            "Firmware revision:     {}.{:03}",
+            self.revision.0, self.revision.1
+        );
+        println!("Serial number:         {:08X}", self.serial_number);
+        let mode = match self.mode() {
+            "Run" => "Run".bold().green(),
+            "Program" => "Program".bold().yellow(),
+            mode => mode.bold().red(),
+        };
+        println!("Mode:                  {}", mode);
+        if let Some(keyswitch) = self.keyswitch() {
+            println!("Keyswitch:             {}", keyswitch);
+        }
+        let faults = self.faults();
+        if faults.is_empty() {
+            println!("Faults:                {}", "none".green());
+        } else {
+            println!("Faults:                {}", faults.join(", ").bold().red());
+        }
+    }
+}
+
+/// Types of the major and minor faults of Logix controllers.
+fn fault_type_name(fault_type: i32) -> &'static str {
+    match fault_type {
+        1 => "Power-up fault",
+        3 => "I/O fault",
+        4 => "Program fault",
+        6 => "Task watchdog fault",
+        7 => "Nonvolatile memory fault",
+        8 => "Mode change fault",
+        9 => "Serial port fault",
+        10 => "Battery fault",
+        11 => "Motion fault",
+        12 => "Redundancy fault",
+        17 => "Energy storage fault",
+        18 => "CIP Motion fault",
+        _ => "Unknown fault type",
+    }
+}
+
+/// What the fault codes of Logix controllers mean, by type and code, from
+/// the major and minor fault code tables of the controller manuals.
+fn fault_description(fault_type: i32, code: i32) -> Option<&'static str> {
+    let description = match (fault_type, code) {
+        (1, 1) => "the controller powered up in Run mode",
+        (1, 60 | 61) => "a nonrecoverable fault cleared the project from memory",
+        (3, 16) => "the connection to a required I/O module failed",
+        (3, 20) => "possible problem with the chassis",
+        (3, 23) => "a required connection wasn't made before going to Run mode",
+        (4, 4) => "arithmetic overflow",
+        (4, 16) => "unknown instruction",
+        (4, 20) => "array subscript too large, or .POS or .LEN of a control structure invalid",
+        (4, 21) => ".LEN or .POS of a control structure below 0",
+        (4, 31) => "the parameters of a JSR don't match those of its SBR or RET",
+        (4, 34) => "a timer has a negative preset or accumulated value",
+        (4, 42) => "JMP to a label that doesn't exist",
+        (4, 82) => "an SFC called a subroutine that jumped back into the SFC",
+        (4, 83) => "the value tested was outside the required limits",
+        (4, 84) => "stack overflow",
+        (4, 89) => "the target routine of an SFR has no such step",
+        (6, 1) => "a task didn't finish within its watchdog time",
+        (6, 2) => "a task was triggered again before it finished",
+        (7, 40) => "storing the project to nonvolatile memory failed",
+        (7, 41) => "loading from nonvolatile memory failed, the controller type doesn't match",
+        (7, 42) => "loading from nonvolatile memory failed, the firmware revision doesn't match",
+        (7, 43) => "loading from nonvolatile memory failed, the checksum is wrong",
+        (7, 44) => "restoring the project to the controller failed",
+        (8, 1) => "the keyswitch was turned to Run during a download",
+        (9, 5) => "serial port data transfer error",
+        (10, 10) => "the battery is missing or needs replacing",
+        (10, 11) => "the battery or energy storage module can't hold the memory",
+        _ => return None,
+    };
+    Some(description)
+}
+
+/// A major or minor fault record copied into a tag by a fault routine, from
+/// the `MajorFaultRecord` or `MinorFaultRecord` of a GSV instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FaultRecord {
+    pub fault_type: i32,
+    pub code: i32,
+}
+
+impl FaultRecord {
+    /// Members read from a record tag, those of the FAULTRECORD structure
+    /// of the controller manuals.
+    pub const MEMBERS: [&'static str; 2] = ["Type", "Code"];
+
+    /// A record with type 0 holds no fault.
+    pub fn is_empty(&self) -> bool {
+        self.fault_type == 0
+    }
+
+    pub fn describe(&self) -> String {
+        let name = fault_type_name(self.fault_type);
+        match fault_description(self.fault_type, self.code) {
+            Some(description) => format!("{}: {}", name, description),
+            None => format!("{}, code {}", name, self.code),
+        }
+    }
+}
+
+/// Faults of the controller: the ones flagged in its identity status, and
+/// those of the fault record tags.
+pub async fn read_faults(client: &mut Plc, records: &[String]) -> Result<()> {
+    let identity = Identity::read(client).await?;
+    let mode = match identity.mode() {
+        "Major fault" => "Major fault".bold().red(),
+        mode => mode.normal(),
+    };
+    println!("Mode:                  {}", mode);
+    let faults = identity.faults();
+    if faults.is_empty() {
+        println!("Faults:                {}", "none".green());
+    } else {
+        println!("Faults:                {}", faults.join(", ").bold().red());
+    }
+    for record in records {
+        let members: Vec<String> = FaultRecord::MEMBERS
+            .iter()
+            .map(|member| format!("{}.{}", record, member))
+            .collect();
+        let values = value::read_values(client, &members).await;
+        let [fault_type, code] = &values[..] else {
+            unreachable!("one value per member")
+        };
+        let record_fault = match (fault_type, code) {
+            (Ok(fault_type), Ok(code)) => FaultRecord {
+                fault_type: fault_type.as_f64() as i32,
+                code: code.as_f64() as i32,
+            },
+            (Err(e), _) | (_, Err(e)) => bail!("failed to read fault record {}: {:#}", record, e),
+        };
+        if record_fault.is_empty() {
+            println!("{:<23}{}", record, "no fault".green());
+        } else {
+            println!(
+                "{:<23}{}    {}",
+                record,
+                format!(
+                    "Type {:02} Code {:02}",
+                    record_fault.fault_type, record_fault.code
+                )
+                .bold()
+                .red(),
+                record_fault.describe()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Ethernet Link object class.
+const CLASS_ETHERNET_LINK: u16 = 0xF6;
+
+/// Ethernet Link instance attributes: speed in Mbit/s, the interface flags
+/// and the interface counters.
+const ATTR_INTERFACE_SPEED: u16 = 1;
+const ATTR_INTERFACE_FLAGS: u16 = 2;
+const ATTR_INTERFACE_COUNTERS: u16 = 4;
+
+/// Requests timed while the interface counters are sampled.
+const ROUND_TRIP_PROBES: u32 = 10;
+
+/// Average round trip above which the controller is reported as loaded.
+const SLOW_ROUND_TRIP: Duration = Duration::from_millis(50);
+
+fn ethernet_link_path(attribute: u16) -> EPath {
+    EPath::default()
+        .with_class(CLASS_ETHERNET_LINK)
+        .with_instance(1)
+        .with_attribute(attribute)
+}
+
+/// Packet counters of the first Ethernet port of the controller.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterfaceCounters {
+    pub packets_in: u32,
+    pub packets_out: u32,
+    /// Discarded and errored packets, both ways.
+    pub errors: u32,
+}
+
+impl InterfaceCounters {
+    /// The counters are eleven UDINTs: octets, unicast and non-unicast
+    /// packets, discards, errors and unknown protocols coming in, then
+    /// octets, unicast and non-unicast packets, discards and errors going out.
+    fn parse(data: &[u8]) -> Option<Self> {
+        if data.len() < 44 {
+            return None;
+        }
+        let counter = |idx: usize| {
+            u32::from_le_bytes([
+                data[idx * 4],
+                data[idx * 4 + 1],
+                data[idx * 4 + 2],
+                data[idx * 4 + 3],
+            ])
+        };
+        Some(Self {
+            packets_in: counter(1).wrapping_add(counter(2)),
+            packets_out: counter(7).wrapping_add(counter(8)),
+            errors: [3, 4, 9, 10]
+                .into_iter()
+                .fold(0u32, |sum, idx| sum.wrapping_add(counter(idx))),
+        })
+    }
+
+    /// Packets per second in and out, and the errors counted, since an
+    /// earlier sample. The counters wrap around.
+    fn rates(&self, earlier: &Self, elapsed: Duration) -> (f64, f64, u32) {
+        let secs = elapsed.as_secs_f64().max(f64::EPSILON);
+        (
+            self.packets_in.wrapping_sub(earlier.packets_in) as f64 / secs,
+            self.packets_out.wrapping_sub(earlier.packets_out) as f64 / secs,
+            self.errors.wrapping_sub(earlier.errors),
+        )
+    }
+}
+
+async fn read_interface_counters(client: &mut Plc) -> Option<InterfaceCounters> {
+    let path = ethernet_link_path(ATTR_INTERFACE_COUNTERS);
+    let holder = client
+        .eip()
+        .ok()?
+        .get_attribute_single::<BytesHolder>(path)
+        .await
+        .ok()?;
+    InterfaceCounters::parse(&Bytes::from(holder))
+}
+
+/// Health summary of the controller, to judge whether it can take more
+/// polling load: its mode and faults, connection slot usage, link state,
+/// packet rates and request round trip over `sample`, and the task scan
+/// times copied into `scan_times` tags.
+///
+/// Logix controllers expose neither free memory nor task scan times as CIP
+/// objects, only to their own program through GSV instructions, so those
+/// are read from tags a routine copies them into.
+pub async fn read_health(client: &mut Plc, sample: Duration, scan_times: &[String]) -> Result<()> {
+    let mut issues = Vec::new();
+
+    let identity = Identity::read(client).await?;
+    println!("Product name:          {}", identity.product_name.bold());
+    println!("Mode:                  {}", identity.mode());
+    let faults = identity.faults();
+    if faults.is_empty() {
+        println!("Faults:                {}", "none".green());
+    } else {
+        println!("Faults:                {}", faults.join(", ").bold().red());
+        issues.push(format!("faulted ({})", faults.join(", ")));
+    }
+
+    let usage = ConnectionUsage::read(client).await?;
+    usage.print();
+    if let (Some(in_use), Some(limit)) = (usage.in_use, usage.limit) {
+        if limit > 0 && in_use as u32 * 100 / limit as u32 >= 90 {
+            issues.push(format!("{} of {} connections in use", in_use, limit));
+        }
+    }
+
+    let session = client.eip()?;
+    let speed = session
+        .get_attribute_single::<u32>(ethernet_link_path(ATTR_INTERFACE_SPEED))
+        .await
+        .ok();
+    let flags = session
+        .get_attribute_single::<u32>(ethernet_link_path(ATTR_INTERFACE_FLAGS))
+        .await
+        .ok();
+    match (speed, flags) {
+        (Some(speed), Some(flags)) if flags & 1 != 0 => {
+            let duplex = if flags & 2 != 0 { "full" } else { "half" };
+            println!("Link:                  {} Mbit/s, {} duplex", speed, duplex);
+            if flags & 2 == 0 {
+                issues.push("link at half duplex".to_owned());
+            }
+        }
+        (_, Some(_)) => println!("Link:                  {}", "down".bold().red()),
+        _ => {}
+    }
+
+    // Time requests spread over the sample while the counters run, so the
+    // round trip reflects the load the controller is under.
+    let before = read_interface_counters(client).await;
+    let started = Instant::now();
+    let mut round_trips = Vec::with_capacity(ROUND_TRIP_PROBES as usize);
+    let probe_path = EPath::default()
+        .with_class(CLASS_IDENTITY)
+        .with_instance(1)
+        .with_attribute(1);
+    for probe in 0..ROUND_TRIP_PROBES {
+        tokio::time::sleep_until((started + sample * probe / ROUND_TRIP_PROBES).into()).await;
+        let sent = Instant::now();
+        client
+            .eip()?
+            .get_attribute_single::<u16>(probe_path.clone())
+            .await?;
+        round_trips.push(sent.elapsed());
+    }
+    tokio::time::sleep_until((started + sample).into()).await;
+    let after = read_interface_counters(client).await;
+
+    if let (Some(before), Some(after)) = (before, after) {
+        let (rate_in, rate_out, errors) = after.rates(&before, started.elapsed());
+        println!("Packets in:            {:.1}/s", rate_in);
+        println!("Packets out:           {:.1}/s", rate_out);
+        if errors == 0 {
+            println!("Packet errors:         {}", "none".green());
+        } else {
+            println!("Packet errors:         {}", errors.to_string().bold().red());
+            issues.push(format!("{} packet errors or discards", errors));
+        }
+    }
+
+    let average = round_trips.iter().sum::<Duration>() / ROUND_TRIP_PROBES;
+    println!(
+        "Round trip:            avg {:.1} ms, max {:.1} ms ({} requests)",
+        average.as_secs_f64() * 1000.0,
+        round_trips
+            .iter()
+            .max()
+            .copied()
+            .unwrap_or_default()
+            .as_secs_f64()
+            * 1000.0,
+        ROUND_TRIP_PROBES
+    );
+    if average > SLOW_ROUND_TRIP {
+        issues.push(format!(
+            "slow replies, {:.1} ms on average",
+            average.as_secs_f64() * 1000.0
+        ));
+    }
+
+    // GSV Task LastScanTime and MaxScanTime are DINTs in microseconds.
+    let values = value::read_values(client, scan_times).await;
+    for (tag, value) in scan_times.iter().zip(values) {
+        match value {
+            Ok(value) => println!("{:<23}{:.3} ms", tag, value.as_f64() / 1000.0),
+            Err(e) => bail!("failed to read scan time {}: {:#}", tag, e),
+        }
+    }
+
+    if issues.is_empty() {
+        println!("Health:                {}", "ok".bold().green());
+    } else {
+        println!("Health:                {}", issues.join("; ").bold().red());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_identity() {
+        let mut data = vec![0x01, 0x00, 0x0E, 0x00, 0x6C, 0x00, 33, 11];
+        data.extend(0x3060u16.to_le_bytes());
+        data.extend(0x00C0FFEEu32.to_le_bytes());
+        data.push(12);
+        data.extend(b"1769-L33ER/A");
+        let identity = Identity::parse(&data).unwrap();
+        assert_eq!(identity.product_name, "1769-L33ER/A");
+        assert_eq!(identity.revision, (33, 11));
+        assert_eq!(identity.serial_number, 0x00C0FFEE);
+        assert_eq!(identity.mode(), "Run");
+        assert_eq!(identity.keyswitch(), Some("Remote"));
+        assert!(identity.faults().is_empty());
+        assert!(Identity::parse(&data[..20]).is_err());
+    }
+
+    #[test]
+    fn fault_records() {
+        let watchdog = FaultRecord {
+            fault_type: 6,
+            code: 1,
+        };
+        assert_eq!(
+            watchdog.describe(),
+            "Task watchdog fault: a task didn't finish within its watchdog time"
+        );
+        let unknown = FaultRecord {
+            fault_type: 4,
+            code: 990,
+        };
+        assert_eq!(unknown.describe(), "Program fault, code 990");
+        assert!(FaultRecord {
+            fault_type: 0,
+            code: 0
+        }
+        .is_empty());
+    }
+
+    #[test]
+    fn interface_counters() {
+        let counters = |values: [u32; 11]| {
+            let data: Vec<u8> = values.iter().flat_map(|v| v.to_le_bytes()).collect();
+            InterfaceCounters::parse(&data).unwrap()
+        };
+        let before = counters([0, 100, 20, 1, 0, 0, 0, 90, 10, 0, 0]);
+        let after = counters([0, 300, 20, 1, 2, 0, 0, 290, 10, 0, 1]);
+        assert_eq!(before.packets_in, 120);
+        assert_eq!(before.errors, 1);
+        assert_eq!(
+            after.rates(&before, Duration::from_secs(2)),
+            (100.0, 100.0, 3)
+        );
+        let wrapped = counters([0, 50, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        let near_wrap = counters([0, u32::MAX - 49, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(wrapped.rates(&near_wrap, Duration::from_secs(1)).0, 100.0);
+        assert!(InterfaceCounters::parse(&[0; 40]).is_none());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::plc::{self, ConnectionArgs, Plc};
+use crate::tags::TagList;
+use crate::value;
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use clap::Args;
+use colored::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct DiffArgs {
+    /// Tags to compare.
+    pub tags: Vec<String>,
+    /// Tag list file with more tags to compare.
+    #[arg(long = "tags")]
+    pub tag_list: Option<PathBuf>,
+    /// Address of the second controller, reached over the same route.
+    #[arg(long, conflicts_with_all = ["snapshot", "save"])]
+    pub against: Option<String>,
+    /// Snapshot file to compare the controller with. Without tags, every tag
+    /// of the snapshot is compared.
+    #[arg(long, conflicts_with = "save")]
+    pub snapshot: Option<PathBuf>,
+    /// Save the tags to a snapshot file instead of comparing.
+    #[arg(long)]
+    pub save: Option<PathBuf>,
+    /// Largest difference between numbers still taken as equal.
+    #[arg(long, default_value_t = 0.0)]
+    pub tolerance: f64,
+}
+
+/// A tag value as read at some point, stored in snapshot files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reading {
+    #[serde(rename = "type")]
+    pub type_name: String,
+    pub value: serde_json::Value,
+}
+
+/// Tag values of a controller saved by `diff --save`.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub address: String,
+    pub taken: DateTime<Local>,
+    pub tags: BTreeMap<String, Reading>,
+}
+
+impl Snapshot {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read snapshot {}", path.display()))?;
+        serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse snapshot {}", path.display()))
+    }
+}
+
+/// A tag that reads differently on the two sides. A side is `Err` when the
+/// tag couldn't be read there.
+struct Mismatch {
+    tag: String,
+    left: Result<Reading, String>,
+    right: Result<Reading, String>,
+}
+
+/// Whether two readings hold the same value, numbers within `tolerance`.
+fn same(left: &Reading, right: &Reading, tolerance: f64) -> bool {
+    if left.type_name != right.type_name {
+        return false;
+    }
+    match (left.value.as_f64(), right.value.as_f64()) {
+        (Some(l), Some(r)) => (l - r).abs() <= tolerance,
+        _ => left.value == right.value,
+    }
+}
+
+async fn read(client: &mut Plc, tag: &str) -> Result<Reading, String> {
+    match value::read_value(client, tag).await {
+        Ok(value) => Ok(Reading {
+            type_name: value.type_name(),
+            value: value.into(),
+        }),
+        Err(e) => Err(format!("{:#}", e)),
+    }
+}
+
+pub async fn run(
+    client: &mut Plc,
+    address: &str,
+    connection: &ConnectionArgs,
+    args: &DiffArgs,
+) -> Result<()> {
+    let snapshot = match &args.snapshot {
+        Some(path) => Some(Snapshot::load(path)?),
+        None => None,
+    };
+    let mut tags = args.tags.clone();
+    if let Some(path) = &args.tag_list {
+        tags.extend(TagList::load(path)?.tags.into_iter().map(|tag| tag.name));
+    }
+    if tags.is_empty() {
+        match &snapshot {
+            Some(snapshot) => tags.extend(snapshot.tags.keys().cloned()),
+            None => bail!("no tags to compare, pass tags or --tags"),
+        }
+    }
+
+    if let Some(path) = &args.save {
+        let mut readings = BTreeMap::new();
+        for tag in &tags {
+            let reading = read(client, tag)
+                .await
+                .map_err(|e| anyhow::anyhow!("failed to read {}: {}", tag, e))?;
+            readings.insert(tag.clone(), reading);
+        }
+        let snapshot = Snapshot {
+            address: address.to_owned(),
+            taken: Local::now(),
+            tags: readings,
+        };
+        std::fs::write(path, serde_json::to_string_pretty(&snapshot)?)
+            .with_context(|| format!("failed to write snapshot {}", path.display()))?;
+        println!("Saved {} tags to {}.", tags.len(), path.display());
+        return Ok(());
+    }
+
+    let (right_name, mut other) = match (&args.against, &snapshot) {
+        (Some(against), _) => (
+            against.clone(),
+            Some(plc::connect(against, connection).await?),
+        ),
+        (None, Some(snapshot)) => (
+            format!("snapshot of {} at {}", snapshot.address, snapshot.taken),
+            None,
+        ),
+        (None, None) => bail!("nothing to compare with, pass --against or --snapshot"),
+    };
+
+    let mut mismatches = Vec::new();
+    for tag in &tags {
+        let left = read(client, tag).await;
+        let right = match (&mut other, &snapshot) {
+            (Some(other), _) => read(other, tag).await,
+            (None, Some(snapshot)) => snapshot
+                .tags
+                .get(tag)
+                .cloned()
+                .ok_or_else(|| "not in the snapshot".to_owned()),
+            (None, None) => unreachable!(),
+        };
+        let equal = matches!((&left, &right), (Ok(l), Ok(r)) if same(l, r, args.tolerance));
+        if !equal {
+            mismatches.push(Mismatch {
+                tag: tag.clone(),
+                left,
+                right,
+            });
+        }
+    }
+
+    if mismatches.is_empty() {
+        println!("All {} tags match.", tags.len());
+        return Ok(());
+    }
+    println!("{} {}", "-".red(), address);
+    println!("{} {}", "+".green(), right_name);
+    for mismatch in &mismatches {
+        println!();
+        println!("{}", mismatch.tag.bold());
+        println!("    {} {}", "-".red(), describe(&mismatch.left).red());
+        println!("    {} {}", "+".green(), describe(&mismatch.right).green());
+    }
+    bail!("{} of {} tags differ", mismatches.len(), tags.len());
+}
+
+fn describe(reading: &Result<Reading, String>) -> String {
+    match reading {
+        Ok(reading) => format!("{} {}", reading.type_name, reading.value),
+        Err(e) => format!("unreadable: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn compare_readings() {
+        let reading = |type_name: &str, value| Reading {
+            type_name: type_name.to_owned(),
+            value,
+        };
+        let real = reading("Real", json!(12.5));
+        assert!(same(&real, &reading("Real", json!(12.5)), 0.0));
+        assert!(!same(&real, &reading("Real", json!(12.6)), 0.0));
+        assert!(same(&real, &reading("Real", json!(12.6)), 0.2));
+        assert!(!same(&real, &reading("Lreal", json!(12.5)), 0.0));
+        let timer = reading("Timer", json!({"pre": 5000, "acc": 12}));
+        assert!(same(&timer, &timer.clone(), 1.0));
+        assert!(!same(
+            &timer,
+            &reading("Timer", json!({"pre": 5000, "acc": 13})),
+            1.0
+        ));
+
+        let snapshot: Snapshot = serde_json::from_str(
+            r#"{
+                "address": "10.0.0.5",
+                "taken": "2024-03-01T08:00:00+01:00",
+                "tags": {"FT_201": {"type": "Real", "value": 12.5}}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(snapshot.tags["FT_201"], real);
+    }
+}
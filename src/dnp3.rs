@@ -0,0 +1,983 @@
+use crate::plc::Plc;
+use crate::service;
+use crate::settings::Access;
+use crate::value::{self, PlcValue};
+use anyhow::{bail, Context, Result};
+use bytes::{BufMut, BytesMut};
+use clap::Args;
+use serde::Deserialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Start bytes of a link frame.
+const START: [u8; 2] = [0x05, 0x64];
+
+/// Most user data of a link frame, and of a transport segment once its
+/// header is taken out.
+const MAX_LINK_DATA: usize = 250;
+const MAX_SEGMENT: usize = MAX_LINK_DATA - 1;
+
+/// Largest response fragment, which every master takes.
+const MAX_FRAGMENT: usize = 2048;
+
+/// Link control: direction, set by masters, and primary frame.
+const DIR: u8 = 0x80;
+const PRM: u8 = 0x40;
+
+/// Link functions of primary frames, and of the secondary frames answering
+/// them.
+const LINK_RESET: u8 = 0;
+const LINK_TEST: u8 = 2;
+const LINK_CONFIRMED_DATA: u8 = 3;
+const LINK_UNCONFIRMED_DATA: u8 = 4;
+const LINK_STATUS_REQUEST: u8 = 9;
+const LINK_ACK: u8 = 0;
+const LINK_STATUS: u8 = 11;
+const LINK_NOT_SUPPORTED: u8 = 15;
+
+/// Transport header: final and first segment.
+const TRANSPORT_FIN: u8 = 0x80;
+const TRANSPORT_FIR: u8 = 0x40;
+
+/// Application control: first and final fragment, confirm asked.
+const APP_FIR: u8 = 0x80;
+const APP_FIN: u8 = 0x40;
+const APP_CON: u8 = 0x20;
+
+const FUNCTION_CONFIRM: u8 = 0;
+const FUNCTION_READ: u8 = 1;
+const FUNCTION_WRITE: u8 = 2;
+const FUNCTION_RESPONSE: u8 = 129;
+
+/// Internal indications, IIN1 in the high byte.
+const IIN_CLASS_EVENTS: [u16; 3] = [0x0200, 0x0400, 0x0800];
+const IIN_RESTART: u16 = 0x8000;
+const IIN_NO_FUNCTION: u16 = 0x0001;
+const IIN_OBJECT_UNKNOWN: u16 = 0x0002;
+const IIN_PARAMETER_ERROR: u16 = 0x0004;
+const IIN_EVENT_OVERFLOW: u16 = 0x0008;
+
+/// Qualifiers of object headers.
+const QUALIFIER_START_STOP_8: u8 = 0x00;
+const QUALIFIER_START_STOP_16: u8 = 0x01;
+const QUALIFIER_ALL: u8 = 0x06;
+const QUALIFIER_COUNT_8: u8 = 0x07;
+const QUALIFIER_COUNT_16: u8 = 0x08;
+const QUALIFIER_INDEXED_16: u8 = 0x28;
+
+/// Flags of a point.
+const FLAG_ONLINE: u8 = 0x01;
+const FLAG_RESTART: u8 = 0x02;
+const FLAG_COMM_LOST: u8 = 0x04;
+const FLAG_OVER_RANGE: u8 = 0x20;
+const FLAG_STATE: u8 = 0x80;
+
+/// Options of the `dnp3` command.
+#[derive(Args)]
+pub struct Dnp3Args {
+    /// Address masters connect to.
+    #[arg(long, default_value = "0.0.0.0:20000")]
+    pub listen: SocketAddr,
+    /// Point map file tying tags to binary and analog inputs.
+    #[arg(long)]
+    pub points: PathBuf,
+    /// Link address of the outstation.
+    #[arg(long, default_value_t = 10)]
+    pub link_address: u16,
+    /// Polling interval of the tags in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub interval: u64,
+    /// Most events kept until a master confirms them. The oldest are
+    /// dropped past it, and masters are told of the overflow.
+    #[arg(long, default_value_t = 1000)]
+    pub max_events: usize,
+}
+
+/// A TOML file tying tags to the points of the outstation, with one
+/// `[[binary]]` or `[[analog]]` table per point. Binary and analog inputs
+/// are numbered apart, from 0 in the order of the file.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PointMap {
+    #[serde(default)]
+    pub binary: Vec<BinaryPoint>,
+    #[serde(default)]
+    pub analog: Vec<AnalogPoint>,
+}
+
+/// A tag served as a binary input, true when non-zero.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BinaryPoint {
+    pub tag: String,
+    /// Event class of the changes, 0 for none.
+    #[serde(default = "default_binary_class")]
+    pub class: u8,
+}
+
+/// A tag served as an analog input.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AnalogPoint {
+    pub tag: String,
+    /// Event class of the changes, 0 for none.
+    #[serde(default = "default_analog_class")]
+    pub class: u8,
+    /// Change from the last reported value making an event.
+    #[serde(default)]
+    pub deadband: f64,
+}
+
+fn default_binary_class() -> u8 {
+    1
+}
+
+fn default_analog_class() -> u8 {
+    2
+}
+
+impl PointMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read point map {}", path.display()))?;
+        let map: PointMap = toml::from_str(&text)
+            .with_context(|| format!("failed to parse point map {}", path.display()))?;
+        map.validate()
+            .with_context(|| format!("invalid point map {}", path.display()))?;
+        Ok(map)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.binary.is_empty() && self.analog.is_empty() {
+            bail!("no [[binary]] or [[analog]] points");
+        }
+        let classes = self.binary.iter().map(|point| (&point.tag, point.class));
+        for (tag, class) in classes.chain(self.analog.iter().map(|point| (&point.tag, point.class)))
+        {
+            if class > 3 {
+                bail!("{}: event classes are 1 to 3, or 0 for none", tag);
+            }
+        }
+        for point in &self.analog {
+            if !(point.deadband.is_finite() && point.deadband >= 0.0) {
+                bail!("{}: the deadband must be zero or more", point.tag);
+            }
+        }
+        // A class 0 poll answers every point in a single fragment.
+        let size = 4 + 2 * 7 + self.binary.len() + 5 * self.analog.len();
+        if size > MAX_FRAGMENT {
+            bail!("too many points to answer a class 0 poll in one fragment");
+        }
+        Ok(())
+    }
+
+    fn tags(&self) -> Vec<String> {
+        let binary = self.binary.iter().map(|point| point.tag.clone());
+        binary
+            .chain(self.analog.iter().map(|point| point.tag.clone()))
+            .collect()
+    }
+}
+
+/// Current value and flags of a point. Binary inputs keep their state in
+/// the flags.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Point {
+    value: f64,
+    flags: u8,
+}
+
+impl Point {
+    const RESTARTED: Point = Point {
+        value: 0.0,
+        flags: FLAG_RESTART,
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Binary,
+    Analog,
+}
+
+#[derive(Debug, Clone)]
+struct Event {
+    /// Number of the event, telling the confirmed ones apart.
+    id: u64,
+    kind: Kind,
+    index: u16,
+    class: u8,
+    point: Point,
+    /// Milliseconds since the epoch, in UTC.
+    time: u64,
+}
+
+/// Points and events shared by the poller and the masters.
+struct Database {
+    binary: Vec<Point>,
+    analog: Vec<Point>,
+    /// Analog values last reported by an event, for the deadbands.
+    reported: Vec<f64>,
+    events: VecDeque<Event>,
+    next_event: u64,
+    max_events: usize,
+    overflow: bool,
+    /// Set until a master clears it, telling masters to poll everything.
+    restart: bool,
+}
+
+impl Database {
+    fn new(map: &PointMap, max_events: usize) -> Self {
+        Self {
+            binary: vec![Point::RESTARTED; map.binary.len()],
+            analog: vec![Point::RESTARTED; map.analog.len()],
+            reported: vec![0.0; map.analog.len()],
+            events: VecDeque::new(),
+            next_event: 0,
+            max_events: max_events.max(1),
+            overflow: false,
+            restart: true,
+        }
+    }
+
+    /// Take in the values read for the tags of the map, in its order,
+    /// making events of the changes. Failed reads keep the last value with
+    /// the communication lost flag. The first reading makes no events.
+    fn update(&mut self, map: &PointMap, results: Vec<Result<PlcValue>>, time: u64) {
+        let (binary, analog) = results.split_at(map.binary.len());
+        for (index, (config, result)) in map.binary.iter().zip(binary).enumerate() {
+            let old = self.binary[index];
+            let new = match result {
+                Ok(value) if value.as_f64() != 0.0 => Point {
+                    value: 1.0,
+                    flags: FLAG_ONLINE | FLAG_STATE,
+                },
+                Ok(_) => Point {
+                    value: 0.0,
+                    flags: FLAG_ONLINE,
+                },
+                Err(e) => lost(&config.tag, old, e),
+            };
+            self.binary[index] = new;
+            if old.flags & FLAG_RESTART == 0 && new != old {
+                self.push(Kind::Binary, index, config.class, new, time);
+            }
+        }
+        for (index, (config, result)) in map.analog.iter().zip(analog).enumerate() {
+            let old = self.analog[index];
+            let new = match result {
+                Ok(value) => Point {
+                    value: value.as_f64(),
+                    flags: FLAG_ONLINE,
+                },
+                Err(e) => lost(&config.tag, old, e),
+            };
+            self.analog[index] = new;
+            if old.flags & FLAG_RESTART != 0 {
+                self.reported[index] = new.value;
+            } else if new.flags != old.flags
+                || (new.value - self.reported[index]).abs() > config.deadband
+            {
+                self.reported[index] = new.value;
+                self.push(Kind::Analog, index, config.class, new, time);
+            }
+        }
+    }
+
+    fn push(&mut self, kind: Kind, index: usize, class: u8, point: Point, time: u64) {
+        if class == 0 {
+            return;
+        }
+        if self.events.len() == self.max_events {
+            self.events.pop_front();
+            self.overflow = true;
+        }
+        self.events.push_back(Event {
+            id: self.next_event,
+            kind,
+            index: index as u16,
+            class,
+            point,
+            time,
+        });
+        self.next_event += 1;
+    }
+
+    /// Indications of the outstation, leaving out the events about to be
+    /// sent.
+    fn iin(&self, sending: &[u64]) -> u16 {
+        let mut iin = 0;
+        if self.restart {
+            iin |= IIN_RESTART;
+        }
+        if self.overflow {
+            iin |= IIN_EVENT_OVERFLOW;
+        }
+        for event in &self.events {
+            if !sending.contains(&event.id) {
+                iin |= IIN_CLASS_EVENTS[event.class as usize - 1];
+            }
+        }
+        iin
+    }
+}
+
+/// Keep the last value of a point that couldn't be read, flagged as such.
+fn lost(tag: &str, old: Point, error: &anyhow::Error) -> Point {
+    if old.flags & FLAG_COMM_LOST == 0 {
+        tracing::warn!("failed to read {}: {:#}", tag, error);
+    }
+    Point {
+        value: old.value,
+        flags: FLAG_COMM_LOST | (old.flags & FLAG_STATE),
+    }
+}
+
+/// Points asked for by an object header.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Range {
+    All,
+    /// First and last index.
+    Span(u16, u16),
+    /// Most objects.
+    Count(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Header {
+    group: u8,
+    variation: u8,
+    range: Range,
+}
+
+/// Parse the object headers of a read, which carry no objects.
+fn parse_headers(mut data: &[u8]) -> Option<Vec<Header>> {
+    let mut headers = Vec::new();
+    while !data.is_empty() {
+        let [group, variation, qualifier, rest @ ..] = data else {
+            return None;
+        };
+        let (range, rest) = match (*qualifier, rest) {
+            (QUALIFIER_ALL, rest) => (Range::All, rest),
+            (QUALIFIER_START_STOP_8, [start, stop, rest @ ..]) => {
+                (Range::Span(*start as u16, *stop as u16), rest)
+            }
+            (QUALIFIER_START_STOP_16, [a, b, c, d, rest @ ..]) => (
+                Range::Span(u16::from_le_bytes([*a, *b]), u16::from_le_bytes([*c, *d])),
+                rest,
+            ),
+            (QUALIFIER_COUNT_8, [count, rest @ ..]) => (Range::Count(*count as u16), rest),
+            (QUALIFIER_COUNT_16, [a, b, rest @ ..]) => {
+                (Range::Count(u16::from_le_bytes([*a, *b])), rest)
+            }
+            _ => return None,
+        };
+        headers.push(Header {
+            group: *group,
+            variation: *variation,
+            range,
+        });
+        data = rest;
+    }
+    Some(headers)
+}
+
+/// Application layer of a master session: answers requests from the
+/// database and drops the events a master confirms.
+struct Outstation {
+    database: Arc<Mutex<Database>>,
+    /// Sequence number of the last response with events, and its events.
+    unconfirmed: Option<(u8, Vec<u64>)>,
+}
+
+impl Outstation {
+    fn new(database: Arc<Mutex<Database>>) -> Self {
+        Self {
+            database,
+            unconfirmed: None,
+        }
+    }
+
+    /// The response to a request fragment, if it takes one.
+    fn respond(&mut self, request: &[u8]) -> Option<Vec<u8>> {
+        let [control, function, objects @ ..] = request else {
+            return None;
+        };
+        let seq = control & 0x0F;
+        match *function {
+            FUNCTION_CONFIRM => {
+                if let Some((_, ids)) = self.unconfirmed.take_if(|(sent, _)| *sent == seq) {
+                    let mut database = self.database.lock().unwrap();
+                    database.events.retain(|event| !ids.contains(&event.id));
+                    if database.events.is_empty() {
+                        database.overflow = false;
+                    }
+                }
+                None
+            }
+            FUNCTION_READ => Some(self.read(seq, objects)),
+            FUNCTION_WRITE => Some(self.write(seq, objects)),
+            _ => Some(self.response(seq, IIN_NO_FUNCTION, &[], &[])),
+        }
+    }
+
+    fn read(&mut self, seq: u8, objects: &[u8]) -> Vec<u8> {
+        let Some(headers) = parse_headers(objects) else {
+            return self.response(seq, IIN_PARAMETER_ERROR, &[], &[]);
+        };
+        let database = self.database.lock().unwrap();
+        let mut data = BytesMut::new();
+        let mut iin = 0;
+        let mut sending = Vec::new();
+        for header in headers {
+            let result = match (header.group, header.variation) {
+                (60, 1) => put_static(&mut data, Kind::Binary, 2, &database.binary, Range::All)
+                    .and_then(|_| {
+                        put_static(&mut data, Kind::Analog, 5, &database.analog, Range::All)
+                    }),
+                (60, 2..=4) => {
+                    let class = header.variation - 1;
+                    put_events(&mut data, &database, &mut sending, header.range, |event| {
+                        (event.class == class).then_some(match event.kind {
+                            Kind::Binary => (2, 2),
+                            Kind::Analog => (32, 7),
+                        })
+                    });
+                    Ok(())
+                }
+                (1, 0 | 2) => {
+                    put_static(&mut data, Kind::Binary, 2, &database.binary, header.range)
+                }
+                (1, 1) => put_static(&mut data, Kind::Binary, 1, &database.binary, header.range),
+                (30, 0 | 5) => {
+                    put_static(&mut data, Kind::Analog, 5, &database.analog, header.range)
+                }
+                (30, 1) => put_static(&mut data, Kind::Analog, 1, &database.analog, header.range),
+                (2, variation @ 0..=2) => {
+                    let variation = if variation == 0 { 2 } else { variation };
+                    put_events(&mut data, &database, &mut sending, header.range, |event| {
+                        (event.kind == Kind::Binary).then_some((2, variation))
+                    });
+                    Ok(())
+                }
+                (32, variation @ (0 | 5 | 7)) => {
+                    let variation = if variation == 0 { 7 } else { variation };
+                    put_events(&mut data, &database, &mut sending, header.range, |event| {
+                        (event.kind == Kind::Analog).then_some((32, variation))
+                    });
+                    Ok(())
+                }
+                _ => Err(IIN_OBJECT_UNKNOWN),
+            };
+            if let Err(indication) = result {
+                iin |= indication;
+            }
+        }
+        iin |= database.iin(&sending);
+        drop(database);
+        self.unconfirmed = (!sending.is_empty()).then(|| (seq, sending.clone()));
+        self.response(seq, iin, &sending, &data)
+    }
+
+    /// Writes clear the restart indication, and nothing else.
+    fn write(&mut self, seq: u8, objects: &[u8]) -> Vec<u8> {
+        let mut database = self.database.lock().unwrap();
+        // Group 80 variation 1, index 7 only, cleared.
+        let iin = match objects {
+            [80, 1, QUALIFIER_START_STOP_8, 7, 7, bits] if bits & 1 == 0 => {
+                database.restart = false;
+                0
+            }
+            [80, 1, ..] => IIN_PARAMETER_ERROR,
+            _ => IIN_OBJECT_UNKNOWN,
+        };
+        let iin = iin | database.iin(&[]);
+        drop(database);
+        self.response(seq, iin, &[], &[])
+    }
+
+    fn response(&self, seq: u8, iin: u16, events: &[u64], objects: &[u8]) -> Vec<u8> {
+        let mut control = APP_FIR | APP_FIN | seq;
+        if !events.is_empty() {
+            control |= APP_CON;
+        }
+        let mut response = vec![control, FUNCTION_RESPONSE];
+        response.extend_from_slice(&iin.to_be_bytes());
+        response.extend_from_slice(objects);
+        response
+    }
+}
+
+/// Write the static objects of the points in range, as `variation` of
+/// binary (group 1) or analog (group 30) inputs.
+fn put_static(
+    data: &mut BytesMut,
+    kind: Kind,
+    variation: u8,
+    points: &[Point],
+    range: Range,
+) -> Result<(), u16> {
+    let (start, stop) = match range {
+        Range::All | Range::Count(_) if points.is_empty() => return Ok(()),
+        Range::Count(0) => return Ok(()),
+        Range::All => (0, points.len() - 1),
+        Range::Count(count) => (0, (count as usize).min(points.len()) - 1),
+        Range::Span(start, stop) if start <= stop && (stop as usize) < points.len() => {
+            (start as usize, stop as usize)
+        }
+        Range::Span(..) => return Err(IIN_PARAMETER_ERROR),
+    };
+    let points = &points[start..=stop];
+    let group = match kind {
+        Kind::Binary => 1,
+        Kind::Analog => 30,
+    };
+    data.put_slice(&[group, variation, QUALIFIER_START_STOP_16]);
+    data.put_u16_le(start as u16);
+    data.put_u16_le(stop as u16);
+    match (kind, variation) {
+        (Kind::Binary, 1) => {
+            for bits in points.chunks(8) {
+                let byte = bits.iter().enumerate().fold(0, |byte, (bit, point)| {
+                    byte | (((point.flags & FLAG_STATE != 0) as u8) << bit)
+                });
+                data.put_u8(byte);
+            }
+        }
+        (Kind::Binary, _) => points.iter().for_each(|point| data.put_u8(point.flags)),
+        (Kind::Analog, 1) => points.iter().for_each(|point| put_i32(data, point)),
+        (Kind::Analog, _) => points.iter().for_each(|point| {
+            data.put_u8(point.flags);
+            data.put_f32_le(point.value as f32);
+        }),
+    }
+    Ok(())
+}
+
+/// Write a 32-bit analog input with its flags, saturated and flagged over
+/// range when it doesn't fit.
+fn put_i32(data: &mut BytesMut, point: &Point) {
+    let rounded = point.value.round();
+    let mut flags = point.flags;
+    if !(i32::MIN as f64..=i32::MAX as f64).contains(&rounded) {
+        flags |= FLAG_OVER_RANGE;
+    }
+    data.put_u8(flags);
+    data.put_i32_le(rounded as i32);
+}
+
+/// Write the events selected by `object`, which gives their group and
+/// variation, oldest first and as many as the range and the fragment allow.
+/// Events already sent for another header of the request are skipped.
+fn put_events(
+    data: &mut BytesMut,
+    database: &Database,
+    sending: &mut Vec<u64>,
+    range: Range,
+    object: impl Fn(&Event) -> Option<(u8, u8)>,
+) {
+    let limit = match range {
+        Range::Count(count) => count as usize,
+        _ => usize::MAX,
+    };
+    let mut selected: Vec<((u8, u8), &Event)> = Vec::new();
+    let mut size = data.len() + 4;
+    for event in &database.events {
+        if selected.len() == limit {
+            break;
+        }
+        if sending.contains(&event.id) {
+            continue;
+        }
+        let Some((group, variation)) = object(event) else {
+            continue;
+        };
+        // A new header for a change of object, the index and the object.
+        let header = match selected.last() {
+            Some((last, _)) if *last == (group, variation) => 0,
+            _ => 5,
+        };
+        let object_size = match (group, variation) {
+            (2, 1) => 1,
+            (2, _) => 7,
+            (32, 5) => 5,
+            _ => 11,
+        };
+        size += header + 2 + object_size;
+        if size > MAX_FRAGMENT {
+            break;
+        }
+        selected.push(((group, variation), event));
+    }
+    for run in selected.chunk_by(|a, b| a.0 == b.0) {
+        let (group, variation) = run[0].0;
+        data.put_slice(&[group, variation, QUALIFIER_INDEXED_16]);
+        data.put_u16_le(run.len() as u16);
+        for (_, event) in run {
+            data.put_u16_le(event.index);
+            data.put_u8(event.point.flags);
+            if group == 32 {
+                data.put_f32_le(event.point.value as f32);
+            }
+            if variation != 1 && variation != 5 {
+                data.put_slice(&event.time.to_le_bytes()[..6]);
+            }
+            sending.push(event.id);
+        }
+    }
+}
+
+/// CRC of link headers and data blocks.
+fn crc(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for &byte in data {
+        crc ^= byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA6BC
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+/// A link frame, its user data without the CRCs.
+#[derive(Debug, PartialEq)]
+struct Frame {
+    control: u8,
+    destination: u16,
+    source: u16,
+    data: Vec<u8>,
+}
+
+impl Frame {
+    fn encode(&self) -> Vec<u8> {
+        let mut header = START.to_vec();
+        header.push(5 + self.data.len() as u8);
+        header.push(self.control);
+        header.extend_from_slice(&self.destination.to_le_bytes());
+        header.extend_from_slice(&self.source.to_le_bytes());
+        let mut frame = header.clone();
+        frame.extend_from_slice(&crc(&header).to_le_bytes());
+        for block in self.data.chunks(16) {
+            frame.extend_from_slice(block);
+            frame.extend_from_slice(&crc(block).to_le_bytes());
+        }
+        frame
+    }
+
+    async fn read(stream: &mut TcpStream) -> Result<Self> {
+        let mut header = [0; 10];
+        stream.read_exact(&mut header).await?;
+        if header[..2] != START {
+            bail!("not a DNP3 link frame");
+        }
+        if u16::from_le_bytes([header[8], header[9]]) != crc(&header[..8]) || header[2] < 5 {
+            bail!("corrupt link header");
+        }
+        let length = header[2] as usize - 5;
+        let mut blocks = vec![0; length + length.div_ceil(16) * 2];
+        stream.read_exact(&mut blocks).await?;
+        let mut data = Vec::with_capacity(length);
+        for block in blocks.chunks(18) {
+            let (block, check) = block.split_at(block.len() - 2);
+            if u16::from_le_bytes([check[0], check[1]]) != crc(block) {
+                bail!("corrupt link data");
+            }
+            data.extend_from_slice(block);
+        }
+        Ok(Self {
+            control: header[3],
+            destination: u16::from_le_bytes([header[4], header[5]]),
+            source: u16::from_le_bytes([header[6], header[7]]),
+            data,
+        })
+    }
+}
+
+/// Answer the requests of a master until it disconnects.
+async fn serve_master(
+    mut stream: TcpStream,
+    mut outstation: Outstation,
+    link_address: u16,
+) -> Result<()> {
+    let mut fragment = Vec::new();
+    let mut transport_seq = 0;
+    loop {
+        let frame = Frame::read(&mut stream).await?;
+        // Only requests of masters to this outstation are answered.
+        if frame.destination != link_address || frame.control & (DIR | PRM) != DIR | PRM {
+            continue;
+        }
+        let reply = |function: u8| Frame {
+            control: function,
+            destination: frame.source,
+            source: link_address,
+            data: Vec::new(),
+        };
+        let segment = match frame.control & 0x0F {
+            LINK_RESET | LINK_TEST => {
+                stream.write_all(&reply(LINK_ACK).encode()).await?;
+                continue;
+            }
+            LINK_STATUS_REQUEST => {
+                stream.write_all(&reply(LINK_STATUS).encode()).await?;
+                continue;
+            }
+            LINK_CONFIRMED_DATA => {
+                stream.write_all(&reply(LINK_ACK).encode()).await?;
+                &frame.data
+            }
+            LINK_UNCONFIRMED_DATA => &frame.data,
+            _ => {
+                stream
+                    .write_all(&reply(LINK_NOT_SUPPORTED).encode())
+                    .await?;
+                continue;
+            }
+        };
+        let Some((&transport, payload)) = segment.split_first() else {
+            continue;
+        };
+        if transport & TRANSPORT_FIR != 0 {
+            fragment.clear();
+        }
+        fragment.extend_from_slice(payload);
+        if transport & TRANSPORT_FIN == 0 {
+            continue;
+        }
+        let Some(response) = outstation.respond(&std::mem::take(&mut fragment)) else {
+            continue;
+        };
+        let segments = response.chunks(MAX_SEGMENT).count();
+        for (i, chunk) in response.chunks(MAX_SEGMENT).enumerate() {
+            let mut data = vec![transport_seq];
+            if i == 0 {
+                data[0] |= TRANSPORT_FIR;
+            }
+            if i + 1 == segments {
+                data[0] |= TRANSPORT_FIN;
+            }
+            data.extend_from_slice(chunk);
+            transport_seq = (transport_seq + 1) & 0x3F;
+            let frame = Frame {
+                control: PRM | LINK_UNCONFIRMED_DATA,
+                destination: frame.source,
+                source: link_address,
+                data,
+            };
+            stream.write_all(&frame.encode()).await?;
+        }
+    }
+}
+
+fn now_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// Serve the tags of the point map as a DNP3 outstation over TCP. The tags
+/// are polled every interval, and masters read the values polled last along
+/// with the events of the changes. Every tag must be readable by masters
+/// under the access lists of the settings.
+pub async fn run(client: &mut Plc, args: &Dnp3Args, access: &Access) -> Result<()> {
+    let map = PointMap::load(&args.points)?;
+    let tags = map.tags();
+    access.check_readable(tags.iter().map(String::as_str))?;
+    let database = Arc::new(Mutex::new(Database::new(&map, args.max_events)));
+
+    let listener = TcpListener::bind(args.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.listen))?;
+    tracing::info!(
+        "serving {} binary and {} analog inputs as outstation {} on {}",
+        map.binary.len(),
+        map.analog.len(),
+        args.link_address,
+        args.listen
+    );
+    let masters = database.clone();
+    let link_address = args.link_address;
+    let accepting = tokio::spawn(async move {
+        loop {
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    tracing::warn!("failed to accept a master: {}", e);
+                    continue;
+                }
+            };
+            tracing::info!("master connected from {}", peer);
+            let outstation = Outstation::new(masters.clone());
+            tokio::spawn(async move {
+                if let Err(e) = serve_master(stream, outstation, link_address).await {
+                    tracing::info!("master {} disconnected: {:#}", peer, e);
+                }
+            });
+        }
+    });
+
+    let interval = Duration::from_millis(args.interval.max(1));
+    loop {
+        let results = value::read_values(client, &tags).await;
+        if results.iter().any(Result::is_err) {
+            // Start over with a new session on the next poll.
+            let _ = client.close().await;
+        }
+        database.lock().unwrap().update(&map, results, now_ms());
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = service::stopped() => break,
+        }
+    }
+    accepting.abort();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use anyhow::anyhow;
+
+    fn map() -> PointMap {
+        toml::from_str(
+            r#"
+            [[binary]]
+            tag = "Pump_Running"
+
+            [[analog]]
+            tag = "PT_101"
+            deadband = 0.5
+            "#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn frames() {
+        assert_eq!(crc(b"123456789"), 0xEA82);
+        let frame = Frame {
+            control: DIR | PRM | LINK_RESET,
+            destination: 1,
+            source: 1024,
+            data: Vec::new(),
+        };
+        assert_eq!(
+            frame.encode(),
+            [0x05, 0x64, 0x05, 0xC0, 0x01, 0x00, 0x00, 0x04, 0xE9, 0x21]
+        );
+        let frame = Frame {
+            data: (0..20).collect(),
+            ..frame
+        };
+        let encoded = frame.encode();
+        assert_eq!(encoded.len(), 10 + 16 + 2 + 4 + 2);
+        assert_eq!(encoded[2], 25);
+    }
+
+    #[test]
+    fn headers() {
+        assert_eq!(
+            parse_headers(&[60, 2, 0x06, 60, 1, 0x06, 30, 5, 0x01, 2, 0, 4, 0, 2, 0, 0x07, 5]),
+            Some(vec![
+                Header {
+                    group: 60,
+                    variation: 2,
+                    range: Range::All
+                },
+                Header {
+                    group: 60,
+                    variation: 1,
+                    range: Range::All
+                },
+                Header {
+                    group: 30,
+                    variation: 5,
+                    range: Range::Span(2, 4)
+                },
+                Header {
+                    group: 2,
+                    variation: 0,
+                    range: Range::Count(5)
+                },
+            ])
+        );
+        assert_eq!(parse_headers(&[60, 1]), None);
+        assert_eq!(parse_headers(&[30, 5, 0x01, 2, 0]), None);
+    }
+
+    #[test]
+    fn polls_and_events() {
+        let map = map();
+        let database = Arc::new(Mutex::new(Database::new(&map, 10)));
+        let mut outstation = Outstation::new(database.clone());
+
+        // Restarted: flagged so, asking masters to clear the restart.
+        let response = outstation
+            .respond(&[0xC0, FUNCTION_READ, 60, 1, 0x06])
+            .unwrap();
+        assert_eq!(
+            response,
+            [
+                0xC0, 129, 0x80, 0x00, 1, 2, 0x01, 0, 0, 0, 0, 0x02, 30, 5, 0x01, 0, 0, 0, 0, 0x02,
+                0, 0, 0, 0
+            ]
+        );
+        let response = outstation
+            .respond(&[0xC1, FUNCTION_WRITE, 80, 1, 0x00, 7, 7, 0])
+            .unwrap();
+        assert_eq!(response, [0xC1, 129, 0, 0]);
+
+        // The first reading makes no events, the changes after it do.
+        database.lock().unwrap().update(
+            &map,
+            vec![Ok(PlcValue::Bool(false)), Ok(PlcValue::Real(10.0))],
+            1000,
+        );
+        database.lock().unwrap().update(
+            &map,
+            vec![Ok(PlcValue::Bool(true)), Ok(PlcValue::Real(10.25))],
+            2000,
+        );
+        database.lock().unwrap().update(
+            &map,
+            vec![Ok(PlcValue::Bool(true)), Err(anyhow!("timed out"))],
+            3000,
+        );
+        let response = outstation
+            .respond(&[0xC2, FUNCTION_READ, 60, 1, 0x06])
+            .unwrap();
+        assert_eq!(
+            response,
+            [
+                0xC2, 129, 0x06, 0x00, 1, 2, 0x01, 0, 0, 0, 0, 0x81, 30, 5, 0x01, 0, 0, 0, 0, 0x04,
+                0, 0, 0x24, 0x41
+            ]
+        );
+
+        // Class 1 and 2 events, confirmed.
+        let response = outstation
+            .respond(&[0xC3, FUNCTION_READ, 60, 2, 0x06, 60, 3, 0x06])
+            .unwrap();
+        let mut expected = vec![0xE3, 129, 0, 0];
+        expected.extend_from_slice(&[2, 2, 0x28, 1, 0, 0, 0, 0x81]);
+        expected.extend_from_slice(&2000u64.to_le_bytes()[..6]);
+        expected.extend_from_slice(&[32, 7, 0x28, 1, 0, 0, 0, 0x04, 0, 0, 0x24, 0x41]);
+        expected.extend_from_slice(&3000u64.to_le_bytes()[..6]);
+        assert_eq!(response, expected);
+        assert_eq!(database.lock().unwrap().events.len(), 2);
+        assert_eq!(outstation.respond(&[0xC3, FUNCTION_CONFIRM]), None);
+        assert!(database.lock().unwrap().events.is_empty());
+
+        let response = outstation.respond(&[0xC4, 13]).unwrap();
+        assert_eq!(response, [0xC4, 129, 0, 0x01]);
+    }
+}
@@ -0,0 +1,95 @@
+use anyhow::{bail, Context, Result};
+use std::path::Path;
+
+/// Environment file read from the working directory when there is one.
+pub const DOTENV_FILE: &str = ".env";
+
+/// Set the variables of an environment file that aren't set already, so
+/// `COBALT_ADDRESS` and the credentials of a project can stay out of the
+/// shell history. A missing file sets nothing.
+pub fn load(path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let text = match std::fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+    };
+    let variables = parse(&text).with_context(|| format!("failed to parse {}", path.display()))?;
+    for (name, value) in variables {
+        if std::env::var_os(&name).is_none() {
+            std::env::set_var(name, value);
+        }
+    }
+    Ok(())
+}
+
+/// Variables of the `NAME=value` lines of an environment file. Lines may
+/// start with `export`, values may be quoted, and `#` starts a comment
+/// outside quotes.
+fn parse(text: &str) -> Result<Vec<(String, String)>> {
+    let mut variables = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").unwrap_or(line);
+        let Some((name, value)) = line.split_once('=') else {
+            bail!("line {}: expected NAME=value", number + 1);
+        };
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("line {}: `{}` is not a variable name", number + 1, name);
+        }
+        let value = value.trim();
+        let value = match value.chars().next() {
+            Some(quote @ ('"' | '\'')) => match value[1..].find(quote) {
+                Some(end) => value[1..end + 1].to_string(),
+                None => bail!("line {}: unterminated quote", number + 1),
+            },
+            _ => match value.find(" #") {
+                Some(comment) => value[..comment].trim_end().to_string(),
+                None => value.to_string(),
+            },
+        };
+        variables.push((name.to_string(), value));
+    }
+    Ok(variables)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn lines() {
+        let text = "\
+# plant floor
+COBALT_ADDRESS=10.0.0.5
+export COBALT_ROUTE = 1,2   # slot 2
+
+COBALT_API_KEY=\"s3cret # not a comment\"
+INFLUX_TOKEN='a=b'
+EMPTY=
+";
+        let variables = parse(text).unwrap();
+        let pairs: Vec<(&str, &str)> = variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            [
+                ("COBALT_ADDRESS", "10.0.0.5"),
+                ("COBALT_ROUTE", "1,2"),
+                ("COBALT_API_KEY", "s3cret # not a comment"),
+                ("INFLUX_TOKEN", "a=b"),
+                ("EMPTY", ""),
+            ]
+        );
+        let error = parse("COBALT_ADDRESS\n").unwrap_err();
+        assert_eq!(error.to_string(), "line 1: expected NAME=value");
+        assert!(parse("COBALT ADDRESS=1\n").is_err());
+        assert!(parse("KEY=\"open\n").is_err());
+    }
+}
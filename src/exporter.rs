@@ -0,0 +1,246 @@
+use crate::plc::Plc;
+use crate::service;
+use crate::settings::Access;
+use crate::tags::{TagConfig, TagList};
+use crate::value;
+use anyhow::Result;
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use clap::Args;
+use futures_util::future::join_all;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use tracing::Instrument;
+
+/// Options of the `exporter` command.
+#[derive(Args)]
+pub struct ExporterArgs {
+    /// Address to serve the metrics endpoint on.
+    #[arg(long, default_value = "0.0.0.0:9640")]
+    pub listen: SocketAddr,
+    /// Tag list file selecting the exported tags.
+    #[arg(long)]
+    pub tags: PathBuf,
+}
+
+struct Exporter {
+    controllers: Vec<Controller>,
+    tags: TagList,
+}
+
+/// A scraped controller, named when several are exported at once.
+struct Controller {
+    name: Option<String>,
+    client: Mutex<Plc>,
+}
+
+/// Result of scraping a controller.
+struct Scrape<'a> {
+    name: Option<&'a str>,
+    values: Vec<(&'a TagConfig, f64)>,
+    /// Whether all tags could be read.
+    up: bool,
+}
+
+impl Controller {
+    /// Read the tags, returning their values and whether all could be read.
+    async fn scrape<'a>(&'a self, tags: &'a TagList) -> Scrape<'a> {
+        let mut client = self.client.lock().await;
+        let names: Vec<String> = tags.tags.iter().map(|tag| tag.name.clone()).collect();
+        let results = value::read_values(&mut client, &names).await;
+        let mut values = Vec::with_capacity(tags.tags.len());
+        let mut up = true;
+        for (tag, result) in tags.tags.iter().zip(results) {
+            match result {
+                Ok(value) => values.push((tag, value.as_f64())),
+                Err(e) => {
+                    tracing::warn!("failed to read {}: {:#}", tag.name, e);
+                    up = false;
+                }
+            }
+        }
+        if !up {
+            // Start over with a new session on the next scrape.
+            let _ = client.close().await;
+        }
+        Scrape {
+            name: self.name.as_deref(),
+            values,
+            up,
+        }
+    }
+}
+
+/// Serve the selected tags as Prometheus gauges. Tags are read when scraped,
+/// over a session per controller shared by all scrapes. Controllers are
+/// given with their name when several are exported, which then becomes the
+/// `plc` label of their series. Every tag must be readable by clients under
+/// the access lists of the settings.
+pub async fn run(
+    clients: Vec<(Option<String>, Plc)>,
+    args: &ExporterArgs,
+    access: &Access,
+) -> Result<()> {
+    let tags = TagList::load(&args.tags)?;
+    access.check_readable(tags.tags.iter().map(|tag| tag.name.as_str()))?;
+    let exporter = Arc::new(Exporter {
+        controllers: clients
+            .into_iter()
+            .map(|(name, client)| Controller {
+                name,
+                client: Mutex::new(client),
+            })
+            .collect(),
+        tags,
+    });
+    let app = Router::new()
+        .route("/metrics", get(metrics))
+        .with_state(exporter.clone());
+
+    let listener = tokio::net::TcpListener::bind(args.listen).await?;
+    tracing::info!(
+        "serving {} tags of {} controller(s) on http://{}/metrics",
+        exporter.tags.tags.len(),
+        exporter.controllers.len(),
+        args.listen
+    );
+    axum::serve(listener, app)
+        .with_graceful_shutdown(service::stopped())
+        .await?;
+
+    for controller in &exporter.controllers {
+        controller.client.lock().await.close().await?;
+    }
+    Ok(())
+}
+
+async fn metrics(State(exporter): State<Arc<Exporter>>) -> impl IntoResponse {
+    let start = Instant::now();
+    let scrapes = join_all(exporter.controllers.iter().map(|controller| {
+        let span = match &controller.name {
+            Some(name) => tracing::info_span!("target", name = %name),
+            None => tracing::Span::none(),
+        };
+        controller.scrape(&exporter.tags).instrument(span)
+    }))
+    .await;
+    let body = exposition(&scrapes, start.elapsed());
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4")], body)
+}
+
+/// Render scrapes in the Prometheus text exposition format.
+fn exposition(scrapes: &[Scrape], took: Duration) -> String {
+    let mut body = String::new();
+
+    body.push_str("# HELP cobalt_tag_value Value of a PLC tag.\n");
+    body.push_str("# TYPE cobalt_tag_value gauge\n");
+    for scrape in scrapes {
+        // The `plc` label of the controller, followed by a comma, if named.
+        let plc = match scrape.name {
+            Some(name) => format!("plc=\"{}\",", escape(name)),
+            None => String::new(),
+        };
+        for (tag, value) in &scrape.values {
+            let mut labels = format!("{}tag=\"{}\"", plc, escape(&tag.name));
+            for (name, label) in &tag.labels {
+                let _ = write!(labels, ",{}=\"{}\"", name, escape(label));
+            }
+            let _ = writeln!(body, "cobalt_tag_value{{{}}} {}", labels, value);
+        }
+    }
+
+    body.push_str("# HELP cobalt_up Whether every tag could be read from the controller.\n");
+    body.push_str("# TYPE cobalt_up gauge\n");
+    for scrape in scrapes {
+        match scrape.name {
+            Some(name) => {
+                let _ = writeln!(
+                    body,
+                    "cobalt_up{{plc=\"{}\"}} {}",
+                    escape(name),
+                    scrape.up as u8
+                );
+            }
+            None => {
+                let _ = writeln!(body, "cobalt_up {}", scrape.up as u8);
+            }
+        }
+    }
+    body.push_str("# HELP cobalt_scrape_duration_seconds Time spent reading the tags.\n");
+    body.push_str("# TYPE cobalt_scrape_duration_seconds gauge\n");
+    let _ = writeln!(
+        body,
+        "cobalt_scrape_duration_seconds {}",
+        took.as_secs_f64()
+    );
+    body
+}
+
+/// Escape a label value for the text exposition format.
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn exposition_format() {
+        let tags: TagList = toml::from_str(
+            r#"
+            [[tag]]
+            name = "PT_101"
+            labels = { unit = "bar" }
+
+            [[tag]]
+            name = "Motor[1].Speed"
+            "#,
+        )
+        .unwrap();
+        let scrapes = [
+            Scrape {
+                name: Some("line \"1\""),
+                values: vec![(&tags.tags[0], 12.5), (&tags.tags[1], 1450.0)],
+                up: true,
+            },
+            Scrape {
+                name: Some("line2"),
+                values: vec![(&tags.tags[0], 3.0)],
+                up: false,
+            },
+        ];
+        assert_eq!(
+            exposition(&scrapes, Duration::from_millis(250)),
+            "# HELP cobalt_tag_value Value of a PLC tag.\n\
+             # TYPE cobalt_tag_value gauge\n\
+             cobalt_tag_value{plc=\"line \\\"1\\\"\",tag=\"PT_101\",unit=\"bar\"} 12.5\n\
+             cobalt_tag_value{plc=\"line \\\"1\\\"\",tag=\"Motor[1].Speed\"} 1450\n\
+             cobalt_tag_value{plc=\"line2\",tag=\"PT_101\",unit=\"bar\"} 3\n\
+             # HELP cobalt_up Whether every tag could be read from the controller.\n\
+             # TYPE cobalt_up gauge\n\
+             cobalt_up{plc=\"line \\\"1\\\"\"} 1\n\
+             cobalt_up{plc=\"line2\"} 0\n\
+             # HELP cobalt_scrape_duration_seconds Time spent reading the tags.\n\
+             # TYPE cobalt_scrape_duration_seconds gauge\n\
+             cobalt_scrape_duration_seconds 0.25\n"
+        );
+
+        // A single controller goes without the `plc` label.
+        let scrape = Scrape {
+            name: None,
+            values: vec![(&tags.tags[1], -0.5)],
+            up: true,
+        };
+        let body = exposition(&[scrape], Duration::ZERO);
+        assert!(body.contains("\ncobalt_tag_value{tag=\"Motor[1].Speed\"} -0.5\n"));
+        assert!(body.contains("\ncobalt_up 1\n"));
+        assert_eq!(escape("a\\b\nc"), "a\\\\b\\nc");
+    }
+}
@@ -0,0 +1,395 @@
+use crate::gas::{self, Gas};
+use anyhow::{anyhow, bail, Result};
+use std::fmt::Display;
+use std::str::FromStr;
+
+/// Arithmetic over numbers and named values, like
+/// `aga8_rate(velocity, diameter, pressure, temperature) * 0.0353`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Var(String),
+    Neg(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+    Call(Function, Vec<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Pow,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Abs,
+    Sqrt,
+    Min,
+    Max,
+    /// Rate at base conditions of a gas flowing at a velocity in m/s through
+    /// a pipe of a diameter in inches, at a pressure in barg and a
+    /// temperature in degC.
+    Aga8Rate,
+    /// Rate at base conditions through an orifice plate, from the
+    /// differential pressure in mbar, the pressure in barg, the temperature
+    /// in degC and the bore and pipe diameters in inches.
+    Aga3Rate,
+    /// Compressibility factor of the gas at a pressure in barg and a
+    /// temperature in degC.
+    Aga8Z,
+    /// Density of the gas in kg/m3 at a pressure in barg and a temperature
+    /// in degC.
+    Aga8Density,
+    /// Molar mass of the gas, in g/mol.
+    Aga8MolarMass,
+    /// Gross heating value of the gas at base conditions, in MJ/Sm3.
+    Aga8Hv,
+    /// Gross Wobbe index of the gas at base conditions, in MJ/Sm3.
+    Aga8Wobbe,
+}
+
+impl Function {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "abs" => Some(Function::Abs),
+            "sqrt" => Some(Function::Sqrt),
+            "min" => Some(Function::Min),
+            "max" => Some(Function::Max),
+            "aga8_rate" => Some(Function::Aga8Rate),
+            "aga3_rate" => Some(Function::Aga3Rate),
+            "aga8_z" => Some(Function::Aga8Z),
+            "aga8_density" => Some(Function::Aga8Density),
+            "aga8_molar_mass" => Some(Function::Aga8MolarMass),
+            "aga8_hv" => Some(Function::Aga8Hv),
+            "aga8_wobbe" => Some(Function::Aga8Wobbe),
+            _ => None,
+        }
+    }
+
+    fn arity(self) -> usize {
+        match self {
+            Function::Aga8MolarMass | Function::Aga8Hv | Function::Aga8Wobbe => 0,
+            Function::Abs | Function::Sqrt => 1,
+            Function::Min | Function::Max | Function::Aga8Z | Function::Aga8Density => 2,
+            Function::Aga8Rate => 4,
+            Function::Aga3Rate => 5,
+        }
+    }
+
+    fn call(self, gas: &Gas, args: &[f64]) -> f64 {
+        match self {
+            Function::Abs => args[0].abs(),
+            Function::Sqrt => args[0].sqrt(),
+            Function::Min => args[0].min(args[1]),
+            Function::Max => args[0].max(args[1]),
+            Function::Aga8Rate => crate::bridge::velocity_to_rate(
+                gas,
+                args[0] as f32,
+                args[1] as f32,
+                args[2] as f32,
+                args[3] as f32,
+            ) as f64,
+            Function::Aga3Rate => {
+                crate::aga3::orifice_rate(gas, args[0], args[1], args[2], args[3], args[4])
+            }
+            Function::Aga8Z => gas::properties_at_line(gas, args[0], args[1]).z,
+            Function::Aga8Density => gas::properties_at_line(gas, args[0], args[1]).density,
+            Function::Aga8MolarMass => gas::base_properties(&gas.analysis).molar_mass,
+            Function::Aga8Hv => gas::heating_value(&gas.analysis),
+            Function::Aga8Wobbe => gas::wobbe_index(&gas.analysis),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Symbol(char),
+}
+
+impl Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Symbol(c) => write!(f, "{}", c),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() || c == '.' {
+            let mut end = start;
+            while let Some(&(idx, c)) = chars.peek() {
+                // Exponents like `1e-3` carry their own sign.
+                let exponent_sign =
+                    (c == '-' || c == '+') && matches!(text[..idx].chars().last(), Some('e' | 'E'));
+                if c.is_ascii_alphanumeric() || c == '.' || exponent_sign {
+                    end = idx + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            let number = &text[start..end];
+            let number = number
+                .parse()
+                .map_err(|_| anyhow!("invalid number `{}`", number))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut end = start;
+            while let Some(&(idx, c)) = chars.peek() {
+                if c.is_ascii_alphanumeric() || c == '_' {
+                    end = idx + c.len_utf8();
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Ident(text[start..end].to_owned()));
+        } else if "+-*/^(),".contains(c) {
+            tokens.push(Token::Symbol(c));
+            chars.next();
+        } else {
+            bail!("unexpected `{}`", c);
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent over the tokens, one method per precedence level.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<()> {
+        match self.next() {
+            Some(Token::Symbol(c)) if c == symbol => Ok(()),
+            Some(token) => bail!("expected `{}`, found `{}`", symbol, token),
+            None => bail!("expected `{}` at the end", symbol),
+        }
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        let mut expr = self.product()?;
+        loop {
+            let op = if self.eat('+') {
+                Op::Add
+            } else if self.eat('-') {
+                Op::Sub
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = if self.eat('*') {
+                Op::Mul
+            } else if self.eat('/') {
+                Op::Div
+            } else {
+                return Ok(expr);
+            };
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.power()
+    }
+
+    /// Powers bind tighter than negation and group to the right, so
+    /// `-2^2` is -4 and `2^3^2` is 512.
+    fn power(&mut self) -> Result<Expr> {
+        let base = self.atom()?;
+        if self.eat('^') {
+            return Ok(Expr::Binary(
+                Op::Pow,
+                Box::new(base),
+                Box::new(self.unary()?),
+            ));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) if self.eat('(') => {
+                let function = Function::from_name(&name)
+                    .ok_or_else(|| anyhow!("unknown function `{}`", name))?;
+                let mut args = Vec::new();
+                if !self.eat(')') {
+                    loop {
+                        args.push(self.sum()?);
+                        if self.eat(')') {
+                            break;
+                        }
+                        self.expect(',')?;
+                    }
+                }
+                if args.len() != function.arity() {
+                    bail!(
+                        "{} takes {} arguments, not {}",
+                        name,
+                        function.arity(),
+                        args.len()
+                    );
+                }
+                Ok(Expr::Call(function, args))
+            }
+            Some(Token::Ident(name)) => Ok(Expr::Var(name)),
+            Some(Token::Symbol('(')) => {
+                let expr = self.sum()?;
+                self.expect(')')?;
+                Ok(expr)
+            }
+            Some(token) => bail!("unexpected `{}`", token),
+            None => bail!("unexpected end of the expression"),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            pos: 0,
+        };
+        let expr = parser.sum()?;
+        if let Some(token) = parser.peek() {
+            bail!("unexpected `{}` after the expression", token);
+        }
+        Ok(expr)
+    }
+}
+
+impl Expr {
+    /// Evaluate with the values of the variables looked up by name, and the
+    /// gas functions worked out for a gas.
+    pub fn eval(&self, gas: &Gas, vars: &impl Fn(&str) -> Option<f64>) -> Result<f64> {
+        Ok(match self {
+            Expr::Number(n) => *n,
+            Expr::Var(name) => vars(name).ok_or_else(|| anyhow!("unknown value `{}`", name))?,
+            Expr::Neg(expr) => -expr.eval(gas, vars)?,
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(gas, vars)?, right.eval(gas, vars)?);
+                match op {
+                    Op::Add => left + right,
+                    Op::Sub => left - right,
+                    Op::Mul => left * right,
+                    Op::Div => left / right,
+                    Op::Pow => left.powf(right),
+                }
+            }
+            Expr::Call(function, args) => {
+                let args = args
+                    .iter()
+                    .map(|arg| arg.eval(gas, vars))
+                    .collect::<Result<Vec<_>>>()?;
+                function.call(gas, &args)
+            }
+        })
+    }
+
+    /// Names of the variables used, for checking them before evaluating.
+    pub fn variables(&self) -> Vec<&str> {
+        match self {
+            Expr::Number(_) => Vec::new(),
+            Expr::Var(name) => vec![name.as_str()],
+            Expr::Neg(expr) => expr.variables(),
+            Expr::Binary(_, left, right) => {
+                let mut vars = left.variables();
+                vars.extend(right.variables());
+                vars
+            }
+            Expr::Call(_, args) => args.iter().flat_map(Expr::variables).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn eval(text: &str) -> f64 {
+        let vars = |name: &str| match name {
+            "velocity" => Some(2.0),
+            "rate" => Some(100.0),
+            _ => None,
+        };
+        text.parse::<Expr>()
+            .unwrap()
+            .eval(&Gas::default(), &vars)
+            .unwrap()
+    }
+
+    #[test]
+    fn expressions() {
+        assert_eq!(eval("1 + 2 * 3"), 7.0);
+        assert_eq!(eval("(1 + 2) * 3"), 9.0);
+        assert_eq!(eval("-2^2"), -4.0);
+        assert_eq!(eval("2^3^2"), 512.0);
+        assert_eq!(eval("rate / 24 - velocity"), 100.0 / 24.0 - 2.0);
+        assert_eq!(eval("max(velocity, 1e-3) * 1.5E1"), 30.0);
+        assert_eq!(eval("sqrt(abs(-16))"), 4.0);
+
+        let expr: Expr = "aga8_rate(velocity, 4, pressure, temperature) * 2"
+            .parse()
+            .unwrap();
+        assert_eq!(expr.variables(), ["velocity", "pressure", "temperature"]);
+        assert_eq!(
+            eval("aga8_wobbe() / aga8_hv()"),
+            1.0 / gas::relative_density(&Gas::default().analysis).sqrt()
+        );
+        assert!("aga8_density(pressure)".parse::<Expr>().is_err());
+
+        assert!("1 +".parse::<Expr>().is_err());
+        assert!("(1 + 2".parse::<Expr>().is_err());
+        assert!("1 2".parse::<Expr>().is_err());
+        assert!("max(1)".parse::<Expr>().is_err());
+        assert!("log(1)".parse::<Expr>().is_err());
+        assert!("1 $ 2".parse::<Expr>().is_err());
+        let flow: Expr = "flow".parse().unwrap();
+        assert!(flow.eval(&Gas::default(), &|_| None).is_err());
+    }
+}
@@ -0,0 +1,199 @@
+use clap::ValueEnum;
+use rseip::ClientError;
+use serde::Serialize;
+use std::fmt::Display;
+use std::process::ExitCode;
+
+/// Why a command failed, telling its exit code. The codes are stable, so
+/// scripts can branch on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Failure {
+    /// Any other failure.
+    Error,
+    /// The controller couldn't be reached, or dropped the session or the
+    /// connection.
+    Connection,
+    /// The controller has no such tag or object.
+    TagNotFound,
+    /// The value or the request doesn't match the data type of the tag.
+    TypeMismatch,
+    /// The write was refused, by cobalt for a read-only controller or tag
+    /// or by the controller itself.
+    WriteRejected,
+    /// The command line is invalid.
+    Usage,
+}
+
+impl Failure {
+    pub fn code(self) -> u8 {
+        match self {
+            Failure::Error => 1,
+            Failure::Connection => 2,
+            Failure::TagNotFound => 3,
+            Failure::TypeMismatch => 4,
+            Failure::WriteRejected => 5,
+            Failure::Usage => 64,
+        }
+    }
+
+    /// An error of this kind, for the failures cobalt finds itself.
+    pub fn error(self, message: impl Display) -> anyhow::Error {
+        Classified {
+            failure: self,
+            message: message.to_string(),
+        }
+        .into()
+    }
+
+    /// Kind of a failure, from the first error of its chain that tells.
+    pub fn of(error: &anyhow::Error) -> Failure {
+        for cause in error.chain() {
+            if let Some(classified) = cause.downcast_ref::<Classified>() {
+                return classified.failure;
+            }
+            if let Some(error) = cause.downcast_ref::<ClientError>() {
+                return Failure::client(error);
+            }
+            if let Some(error) = cause.downcast_ref::<std::io::Error>() {
+                if unreachable(error) {
+                    return Failure::Connection;
+                }
+            }
+            if cause.is::<tokio::time::error::Elapsed>() {
+                return Failure::Connection;
+            }
+        }
+        Failure::Error
+    }
+
+    /// Kind of an rseip error, from its CIP status when the controller
+    /// replied with one.
+    fn client(error: &ClientError) -> Failure {
+        let status = match error {
+            ClientError::Io { err, .. } if unreachable(err) => return Failure::Connection,
+            ClientError::Encapsulation { .. } | ClientError::Closed { .. } => {
+                return Failure::Connection
+            }
+            ClientError::Status { status, .. } => status,
+            _ => return Failure::Error,
+        };
+        match (status.general, status.extended) {
+            (0x04 | 0x05 | 0x16, _) => Failure::TagNotFound,
+            (0xFF, Some(0x2107)) => Failure::TypeMismatch,
+            (0x0E..=0x10, _) => Failure::WriteRejected,
+            (0x01 | 0x02 | 0x07, _) => Failure::Connection,
+            _ => Failure::Error,
+        }
+    }
+}
+
+/// Whether an I/O error is the network failing, rather than e.g. a file
+/// missing.
+fn unreachable(error: &std::io::Error) -> bool {
+    use std::io::ErrorKind::*;
+    matches!(
+        error.kind(),
+        ConnectionRefused
+            | ConnectionReset
+            | ConnectionAborted
+            | NotConnected
+            | AddrNotAvailable
+            | BrokenPipe
+            | TimedOut
+            | UnexpectedEof
+            | HostUnreachable
+            | NetworkUnreachable
+            | NetworkDown
+    )
+}
+
+/// An error cobalt raised itself, with its kind.
+#[derive(Debug)]
+struct Classified {
+    failure: Failure,
+    message: String,
+}
+
+impl Display for Classified {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Classified {}
+
+/// How a failed command reports its error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Output {
+    /// `Error: ` and the message.
+    Text,
+    /// One JSON object with the kind, exit code and message, for scripts.
+    Json,
+}
+
+#[derive(Serialize)]
+struct Report<'a> {
+    error: Failure,
+    code: u8,
+    message: &'a str,
+}
+
+/// Print the error a command failed with on stderr and return its exit
+/// code.
+pub fn report(error: &anyhow::Error, output: Output) -> ExitCode {
+    let failure = Failure::of(error);
+    let message = format!("{:#}", error);
+    match output {
+        Output::Text => eprintln!("Error: {}", message),
+        Output::Json => {
+            let report = Report {
+                error: failure,
+                code: failure.code(),
+                message: &message,
+            };
+            eprintln!("{}", serde_json::to_string(&report).unwrap());
+        }
+    }
+    ExitCode::from(failure.code())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rseip::cip::Status;
+
+    #[test]
+    fn kinds() {
+        let reply = |general, extended| -> anyhow::Error {
+            ClientError::Status {
+                kind: "custom",
+                status: Status { general, extended },
+            }
+            .into()
+        };
+        assert_eq!(Failure::of(&reply(0x04, None)), Failure::TagNotFound);
+        assert_eq!(
+            Failure::of(&reply(0xFF, Some(0x2107))),
+            Failure::TypeMismatch
+        );
+        assert_eq!(Failure::of(&reply(0xFF, Some(0x2105))), Failure::Error);
+        assert_eq!(Failure::of(&reply(0x0F, None)), Failure::WriteRejected);
+        assert_eq!(Failure::of(&reply(0x01, Some(0x0204))), Failure::Connection);
+
+        let refused = std::io::Error::from(std::io::ErrorKind::ConnectionRefused);
+        let error = anyhow::Error::from(refused).context("failed to connect");
+        assert_eq!(Failure::of(&error), Failure::Connection);
+        let error = Failure::WriteRejected
+            .error("Valve is read-only")
+            .context("failed to write");
+        assert_eq!(Failure::of(&error), Failure::WriteRejected);
+        assert_eq!(
+            format!("{:#}", error),
+            "failed to write: Valve is read-only"
+        );
+        let missing = std::io::Error::from(std::io::ErrorKind::NotFound);
+        let error = anyhow::Error::from(missing).context("failed to read the settings");
+        assert_eq!(Failure::of(&error), Failure::Error);
+    }
+}
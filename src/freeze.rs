@@ -0,0 +1,91 @@
+use crate::monitor::{Quality, Sample};
+use crate::tags::FreezeConfig;
+use chrono::{DateTime, Local};
+use std::collections::HashMap;
+
+/// Reading a frozen tag is compared against.
+struct Reference {
+    value: f64,
+    since: DateTime<Local>,
+}
+
+/// Tracks how long the value of each tag has stayed put.
+pub struct FreezeDetector {
+    configs: HashMap<String, FreezeConfig>,
+    references: HashMap<String, Reference>,
+}
+
+impl FreezeDetector {
+    pub fn new(configs: HashMap<String, FreezeConfig>) -> Self {
+        Self {
+            configs,
+            references: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.configs.is_empty()
+    }
+
+    /// Downgrade the quality of the samples whose value has not moved beyond
+    /// the tolerance for the configured period.
+    pub fn check(&mut self, samples: &mut [Sample]) {
+        for sample in samples {
+            let config = match self.configs.get(&sample.tag) {
+                Some(config) => config,
+                None => continue,
+            };
+            let value = sample.value.as_f64();
+            let reference = self
+                .references
+                .entry(sample.tag.clone())
+                .or_insert(Reference {
+                    value,
+                    since: sample.timestamp,
+                });
+            if (value - reference.value).abs() > config.tolerance {
+                reference.value = value;
+                reference.since = sample.timestamp;
+            }
+            let still = (sample.timestamp - reference.since)
+                .to_std()
+                .unwrap_or_default();
+            if still >= config.after {
+                sample.quality = Quality::Frozen;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::value::PlcValue;
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    #[test]
+    fn frozen_after_period() {
+        let config = FreezeConfig {
+            after: Duration::from_secs(60),
+            tolerance: 0.5,
+        };
+        let mut detector = FreezeDetector::new([("PT_101".to_owned(), config)].into());
+        let mut check = |secs, value| {
+            let mut samples = [Sample {
+                timestamp: Local.timestamp_opt(secs, 0).unwrap(),
+                tag: "PT_101".to_owned(),
+                value: PlcValue::Real(value),
+                quality: Quality::Good,
+            }];
+            detector.check(&mut samples);
+            samples[0].quality
+        };
+        assert_eq!(check(0, 10.0), Quality::Good);
+        assert_eq!(check(30, 10.4), Quality::Good);
+        assert_eq!(check(60, 9.6), Quality::Frozen);
+        assert_eq!(check(61, 11.0), Quality::Good);
+        assert_eq!(check(120, 11.0), Quality::Good);
+        assert_eq!(check(121, 11.0), Quality::Frozen);
+    }
+}
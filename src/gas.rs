@@ -0,0 +1,360 @@
+use aga8::composition::Composition;
+use aga8::detail::Detail;
+use anyhow::{bail, Result};
+use std::cell::RefCell;
+
+/// Base pressure the rates and heating values are given at, 14.73 psia, in
+/// kPa.
+pub const BASE_PRESSURE: f64 = 14.73 * 6.89476;
+
+/// Base temperature, 60 degF, in K.
+pub const BASE_TEMPERATURE: f64 = (60.0 - 32.0) * 5.0 / 9.0 + 273.15;
+
+/// Standard atmospheric pressure, added to gauge pressures unless the site
+/// gives its own, in kPa.
+pub const STANDARD_ATMOSPHERE: f64 = 101.325;
+
+/// Molar gas constant, in J/(mol K).
+const GAS_CONSTANT: f64 = 8.314_462_618;
+
+/// Molar mass of dry air, in g/mol, and its compressibility at base
+/// conditions, as given by ISO 6976.
+const AIR_MOLAR_MASS: f64 = 28.9626;
+const AIR_Z: f64 = 0.99959;
+
+/// Changes of the flowing pressure, in kPa, and temperature, in K, too
+/// small to solve the equation of state again for.
+const PRESSURE_STEP: f64 = 0.05;
+const TEMPERATURE_STEP: f64 = 0.01;
+
+/// Flowing conditions remembered, one per meter of a busy bridge, and
+/// analyses solved for.
+const RECENT: usize = 8;
+
+/// How far the components of a measured analysis may sum from the whole
+/// before it is taken for a bad reading rather than normalized.
+const SUM_TOLERANCE: f64 = 0.02;
+
+/// Components of the AGA8 detail characterization, by the names settings
+/// give them, in the order of an [`Analysis`].
+pub const COMPONENTS: [&str; 21] = [
+    "methane",
+    "nitrogen",
+    "carbon_dioxide",
+    "ethane",
+    "propane",
+    "isobutane",
+    "n_butane",
+    "isopentane",
+    "n_pentane",
+    "hexane",
+    "heptane",
+    "octane",
+    "nonane",
+    "decane",
+    "hydrogen",
+    "oxygen",
+    "carbon_monoxide",
+    "water",
+    "hydrogen_sulfide",
+    "helium",
+    "argon",
+];
+
+/// Properties of the gas at a pressure and temperature.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Properties {
+    /// Compressibility factor.
+    pub z: f64,
+    /// Molar mass, in g/mol.
+    pub molar_mass: f64,
+    /// Density, in kg/m3.
+    pub density: f64,
+}
+
+/// Analysis of the gas, the mole fractions of the [`COMPONENTS`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Analysis(pub [f64; 21]);
+
+/// The analysis used when the settings give none.
+impl Default for Analysis {
+    fn default() -> Self {
+        Self::from_fractions(&[
+            ("methane", 0.79),
+            ("nitrogen", 0.04),
+            ("carbon_dioxide", 0.04),
+            ("propane", 0.13),
+        ])
+        .unwrap()
+    }
+}
+
+impl Analysis {
+    /// An analysis from the amounts of its components, in fractions or
+    /// percent, normalized to a whole. Amounts summing too far from a whole
+    /// of either are refused.
+    pub fn from_fractions(amounts: &[(&str, f64)]) -> Result<Self> {
+        let mut fractions = [0.0; 21];
+        for (name, amount) in amounts {
+            let Some(idx) = COMPONENTS.iter().position(|component| component == name) else {
+                bail!("unknown gas component `{}`", name);
+            };
+            if amount.is_nan() || *amount < 0.0 {
+                bail!("{} of {} in the analysis", amount, name);
+            }
+            fractions[idx] += amount;
+        }
+        let sum: f64 = fractions.iter().sum();
+        if (sum - 1.0).abs() > SUM_TOLERANCE && (sum - 100.0).abs() > 100.0 * SUM_TOLERANCE {
+            bail!("the components of the analysis sum to {}", sum);
+        }
+        for fraction in &mut fractions {
+            *fraction /= sum;
+        }
+        Ok(Self(fractions))
+    }
+
+    fn composition(&self) -> Composition {
+        let [methane, nitrogen, carbon_dioxide, ethane, propane, isobutane, n_butane, isopentane, n_pentane, hexane, heptane, octane, nonane, decane, hydrogen, oxygen, carbon_monoxide, water, hydrogen_sulfide, helium, argon] =
+            self.0;
+        Composition {
+            methane,
+            nitrogen,
+            carbon_dioxide,
+            ethane,
+            propane,
+            isobutane,
+            n_butane,
+            isopentane,
+            n_pentane,
+            hexane,
+            heptane,
+            octane,
+            nonane,
+            decane,
+            hydrogen,
+            oxygen,
+            carbon_monoxide,
+            water,
+            hydrogen_sulfide,
+            helium,
+            argon,
+        }
+    }
+}
+
+/// The AGA8 detail solver, set up with an analysis once, with the
+/// properties at base conditions and at the flowing conditions solved
+/// lately. Bridges evaluate the same conditions every cycle, which a small
+/// edge device shouldn't solve anew each time.
+struct Solver {
+    analysis: Analysis,
+    detail: Detail,
+    base: Properties,
+    /// Pressure and temperature of the flowing conditions solved lately,
+    /// with their properties, the latest last.
+    recent: Vec<(f64, f64, Properties)>,
+}
+
+impl Solver {
+    fn new(analysis: Analysis) -> Self {
+        let mut detail = Detail::new();
+        detail.set_composition(&analysis.composition()).unwrap();
+        let base = solve(&mut detail, BASE_PRESSURE, BASE_TEMPERATURE);
+        Self {
+            analysis,
+            detail,
+            base,
+            recent: Vec::with_capacity(RECENT),
+        }
+    }
+
+    fn at(&mut self, pressure: f64, temperature: f64) -> Properties {
+        let close = self.recent.iter().position(|(p, t, _)| {
+            (p - pressure).abs() < PRESSURE_STEP && (t - temperature).abs() < TEMPERATURE_STEP
+        });
+        if let Some(idx) = close {
+            let recent = self.recent.remove(idx);
+            self.recent.push(recent);
+            return recent.2;
+        }
+        let properties = solve(&mut self.detail, pressure, temperature);
+        if self.recent.len() == RECENT {
+            self.recent.remove(0);
+        }
+        self.recent.push((pressure, temperature, properties));
+        properties
+    }
+}
+
+thread_local! {
+    /// Solvers of the analyses used lately, the latest last.
+    static SOLVERS: RefCell<Vec<Solver>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The gas in a line: its analysis, and the atmospheric pressure of the
+/// site in kPa that gauge pressures are relative to, e.g. lower at
+/// altitude.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gas {
+    pub analysis: Analysis,
+    pub atmosphere: f64,
+}
+
+impl Default for Gas {
+    fn default() -> Self {
+        Self {
+            analysis: Analysis::default(),
+            atmosphere: STANDARD_ATMOSPHERE,
+        }
+    }
+}
+
+fn with_solver<T>(analysis: &Analysis, f: impl FnOnce(&mut Solver) -> T) -> T {
+    SOLVERS.with(|solvers| {
+        let mut solvers = solvers.borrow_mut();
+        match solvers
+            .iter()
+            .position(|solver| solver.analysis == *analysis)
+        {
+            Some(idx) => {
+                let solver = solvers.remove(idx);
+                solvers.push(solver);
+            }
+            None => {
+                if solvers.len() == RECENT {
+                    solvers.remove(0);
+                }
+                solvers.push(Solver::new(*analysis));
+            }
+        }
+        f(solvers.last_mut().unwrap())
+    })
+}
+
+fn solve(detail: &mut Detail, pressure: f64, temperature: f64) -> Properties {
+    detail.p = pressure;
+    detail.t = temperature;
+    detail.density();
+    detail.properties();
+    let molar_mass = detail.molar_mass();
+    Properties {
+        z: detail.z,
+        molar_mass,
+        // mol/l times g/mol is g/l, which is kg/m3.
+        density: detail.d * molar_mass,
+    }
+}
+
+/// Properties of the gas of an analysis at a pressure in kPa absolute and a
+/// temperature in K, from the AGA8 detail characterization.
+pub fn properties(analysis: &Analysis, pressure: f64, temperature: f64) -> Properties {
+    with_solver(analysis, |solver| solver.at(pressure, temperature))
+}
+
+/// Properties of the gas at base conditions.
+pub fn base_properties(analysis: &Analysis) -> Properties {
+    with_solver(analysis, |solver| solver.base)
+}
+
+/// Properties of the gas at a pressure in barg and a temperature in degC.
+pub fn properties_at_line(gas: &Gas, pressure: f64, temperature: f64) -> Properties {
+    properties(
+        &gas.analysis,
+        pressure * 100.0 + gas.atmosphere,
+        temperature + 273.15,
+    )
+}
+
+/// Gross molar heating value of each of the [`COMPONENTS`], in kJ/mol,
+/// from ISO 6976 at a combustion temperature of 15 degC. Inert components
+/// have none.
+const HEATING_VALUES: [f64; 21] = [
+    891.56, 0.0, 0.0, 1562.14, 2221.10, 2870.58, 2879.76, 3531.68, 3538.60, 4198.24, 4857.18,
+    5515.36, 6175.82, 6834.90, 286.15, 0.0, 282.91, 0.0, 562.38, 0.0, 0.0,
+];
+
+/// Gross heating value of the gas at base conditions, in MJ/Sm3.
+pub fn heating_value(analysis: &Analysis) -> f64 {
+    let molar: f64 = analysis
+        .0
+        .iter()
+        .zip(HEATING_VALUES)
+        .map(|(fraction, value)| fraction * value)
+        .sum();
+    let base = base_properties(analysis);
+    // Moles in a cubic meter of the real gas at base conditions.
+    let moles = BASE_PRESSURE * 1000.0 / (base.z * GAS_CONSTANT * BASE_TEMPERATURE);
+    molar * moles / 1000.0
+}
+
+/// Relative density of the gas to air at base conditions, both real
+/// gases.
+pub fn relative_density(analysis: &Analysis) -> f64 {
+    let base = base_properties(analysis);
+    (base.molar_mass / base.z) / (AIR_MOLAR_MASS / AIR_Z)
+}
+
+/// Gross Wobbe index of the gas at base conditions, in MJ/Sm3.
+pub fn wobbe_index(analysis: &Analysis) -> f64 {
+    heating_value(analysis) / relative_density(analysis).sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn gas_properties() {
+        let gas = Gas::default();
+        let analysis = gas.analysis;
+        let base = base_properties(&analysis);
+        let mut detail = Detail::new();
+        detail.set_composition(&analysis.composition()).unwrap();
+        assert_eq!(base, solve(&mut detail, BASE_PRESSURE, BASE_TEMPERATURE));
+        assert!((base.z - 0.996).abs() < 0.003, "{}", base.z);
+        // 0.79 * 16.043 + 0.04 * 28.013 + 0.04 * 44.010 + 0.13 * 44.097
+        assert!((base.molar_mass - 21.3).abs() < 0.05, "{}", base.molar_mass);
+        // Ideal density p M / (R T), a little above it as Z < 1.
+        let ideal = BASE_PRESSURE * base.molar_mass / (GAS_CONSTANT * BASE_TEMPERATURE);
+        assert!(base.density > ideal && base.density < ideal * 1.01);
+
+        let line = properties_at_line(&gas, 50.0, 20.0);
+        assert!(line.z < base.z);
+        assert!(line.density > 40.0 * base.density);
+        // Readings a hair apart reuse the solved properties.
+        assert_eq!(properties_at_line(&gas, 50.0001, 20.001), line);
+        assert_ne!(properties_at_line(&gas, 50.1, 20.0), line);
+        // 0.5 bar less atmosphere at altitude is 0.5 bar less in the line.
+        let high = Gas {
+            atmosphere: STANDARD_ATMOSPHERE - 50.0,
+            ..gas
+        };
+        assert_eq!(properties_at_line(&high, 50.5, 20.0), line);
+
+        // 0.79 * 891.56 + 0.13 * 2221.10 kJ/mol, about 42.3 mol/Sm3.
+        let hv = heating_value(&analysis);
+        let density = relative_density(&analysis);
+        assert!((hv - 42.1).abs() < 0.3, "{}", hv);
+        assert!((density - 0.74).abs() < 0.01);
+        assert!((wobbe_index(&analysis) - hv / density.sqrt()).abs() < 1e-9);
+
+        // A leaner gas, measured in percent.
+        let lean = Analysis::from_fractions(&[
+            ("methane", 95.2),
+            ("ethane", 2.5),
+            ("nitrogen", 1.3),
+            ("carbon_dioxide", 0.9),
+        ])
+        .unwrap();
+        assert!((lean.0.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        let lean_hv = heating_value(&lean);
+        assert!(lean_hv < hv - 2.0, "{}", lean_hv);
+        assert!(base_properties(&lean).molar_mass < base.molar_mass);
+        assert_eq!(heating_value(&analysis), hv);
+
+        assert!(Analysis::from_fractions(&[("methane", 0.5)]).is_err());
+        assert!(Analysis::from_fractions(&[("methane", 1.0), ("butane", 0.0)]).is_err());
+        assert!(Analysis::from_fractions(&[("methane", 1.1), ("ethane", -0.1)]).is_err());
+    }
+}
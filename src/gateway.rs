@@ -0,0 +1,259 @@
+use crate::failure::Failure;
+use crate::historian::Journal;
+use crate::plc::Plc;
+use crate::server::Auth;
+use crate::settings::Access;
+use crate::tags::TagList;
+use crate::value::{self, PlcValue};
+use anyhow::Result;
+use chrono::{DateTime, Local};
+use futures_util::TryStreamExt;
+use rseip::client::ab_eip::*;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, Mutex};
+use tokio::task::JoinHandle;
+
+/// Tag changes buffered for a slow client before it misses some.
+const EVENT_BUFFER: usize = 1024;
+
+/// A polled tag changed value.
+#[derive(Debug, Clone)]
+pub(crate) struct TagEvent {
+    pub(crate) timestamp: DateTime<Local>,
+    pub(crate) tag: String,
+    pub(crate) value: PlcValue,
+}
+
+#[derive(Debug, Serialize)]
+pub(crate) struct TagInfo {
+    pub(crate) name: String,
+    pub(crate) r#type: String,
+}
+
+/// Why a request of a client failed, for the API to reply with.
+#[derive(Debug)]
+pub(crate) enum GatewayError {
+    /// The tag doesn't exist for clients, as it is hidden.
+    NotFound(String),
+    /// The access lists don't let clients make the request.
+    Denied(String),
+    /// The value doesn't fit the type of the tag.
+    Invalid(String),
+    /// The controller failed the request, or cobalt refused it.
+    Plc(anyhow::Error),
+    /// The controller has no tag list.
+    NoTagList(String),
+    /// The write went through but couldn't be journaled.
+    Journal(anyhow::Error),
+}
+
+/// Names of the tags of the tag list file polled for the streams, if any.
+pub(crate) fn polled_tags(path: Option<&Path>) -> Result<Vec<String>> {
+    let Some(path) = path else {
+        return Ok(Vec::new());
+    };
+    Ok(TagList::load(path)?
+        .tags
+        .into_iter()
+        .map(|tag| tag.name)
+        .collect())
+}
+
+/// What the tag APIs of `serve` and `serve-grpc` share: the session all
+/// requests go through, the access lists and credentials, the journal of
+/// the writes and the polling of the tags streamed to clients.
+pub(crate) struct Gateway {
+    client: Mutex<Plc>,
+    journal: Journal,
+    pub(crate) access: Access,
+    pub(crate) auth: Auth,
+    /// Tags polled for the streams, in the order of the tag list.
+    polled: Vec<String>,
+    /// Last value of each polled tag, sent to new streams.
+    latest: std::sync::Mutex<BTreeMap<String, TagEvent>>,
+    events: broadcast::Sender<TagEvent>,
+    /// Polling and keep alive, stopped by `close`.
+    tasks: std::sync::Mutex<Vec<JoinHandle<()>>>,
+}
+
+impl Gateway {
+    /// A gateway to the controller, polling the tags once started. The
+    /// polled tags must be readable by clients.
+    pub(crate) fn new(
+        client: Plc,
+        journal: Journal,
+        access: Access,
+        auth: Auth,
+        polled: Vec<String>,
+    ) -> Result<Arc<Self>> {
+        access.check_readable(polled.iter().map(String::as_str))?;
+        if auth.api_key.is_none() && auth.basic.is_none() {
+            tracing::warn!("serving without authentication, every client can write tags");
+        }
+        Ok(Arc::new(Self {
+            client: Mutex::new(client),
+            journal,
+            access,
+            auth,
+            polled,
+            latest: Default::default(),
+            events: broadcast::channel(EVENT_BUFFER).0,
+            tasks: Default::default(),
+        }))
+    }
+
+    /// Keep the session alive between requests, and poll the tags every
+    /// interval.
+    pub(crate) fn start(self: &Arc<Self>, interval: Duration) {
+        let mut tasks = self.tasks.lock().unwrap();
+        if !self.polled.is_empty() {
+            tasks.push(tokio::spawn(self.clone().poll(interval)));
+        }
+        tasks.push(tokio::spawn(self.clone().keep_alive()));
+    }
+
+    /// Stop polling and close the session, once the API stopped.
+    pub(crate) async fn close(&self) -> Result<()> {
+        for task in self.tasks.lock().unwrap().drain(..) {
+            task.abort();
+        }
+        self.client.lock().await.close().await
+    }
+
+    #[cfg(feature = "grpc")]
+    pub(crate) fn polled(&self) -> &[String] {
+        &self.polled
+    }
+
+    /// The last value of each polled tag, and the changes to come.
+    /// Subscribed before taking the values, so no change is missed.
+    pub(crate) fn subscribe(&self) -> (Vec<TagEvent>, broadcast::Receiver<TagEvent>) {
+        let events = self.events.subscribe();
+        let latest = self.latest.lock().unwrap().values().cloned().collect();
+        (latest, events)
+    }
+
+    pub(crate) async fn list_tags(&self) -> Result<Vec<TagInfo>, GatewayError> {
+        let mut client = self.client.lock().await;
+        // Controllers of other protocols have no tag list.
+        let session = client
+            .eip()
+            .map_err(|e| GatewayError::NoTagList(e.to_string()))?;
+        let tags: Result<Vec<_>, _> = session
+            .list_tag()
+            .call()
+            .try_filter(|item| futures_util::future::ready(self.access.can_read(&item.name)))
+            .map_ok(|item| TagInfo {
+                name: item.name.into_owned(),
+                r#type: format!("{:?}", item.symbol_type),
+            })
+            .try_collect()
+            .await;
+        match tags {
+            Ok(tags) => Ok(tags),
+            Err(e) => Err(failed(&mut client, e.into()).await),
+        }
+    }
+
+    pub(crate) async fn read_tag(&self, tag: &str) -> Result<PlcValue, GatewayError> {
+        if !self.access.can_read(tag) {
+            return Err(self.denied(tag, "read"));
+        }
+        let mut client = self.client.lock().await;
+        match value::read_value(&mut client, tag).await {
+            Ok(value) => Ok(value),
+            Err(e) => Err(failed(&mut client, e).await),
+        }
+    }
+
+    /// Write a value to a tag, converted to the type the controller reports
+    /// for it, and journal the write. Returns the value written.
+    pub(crate) async fn write_tag(
+        &self,
+        tag: &str,
+        json: &serde_json::Value,
+    ) -> Result<PlcValue, GatewayError> {
+        if !self.access.can_write(tag) {
+            return Err(self.denied(tag, "written"));
+        }
+        let mut client = self.client.lock().await;
+        let current = match value::read_value(&mut client, tag).await {
+            Ok(current) => current,
+            Err(e) => return Err(failed(&mut client, e).await),
+        };
+        let value = PlcValue::from_json_like(&current, json)
+            .map_err(|e| GatewayError::Invalid(e.to_string()))?;
+        if let Err(e) = value::write_value(&mut client, tag, value.clone()).await {
+            return Err(failed(&mut client, e).await);
+        }
+        self.journal
+            .record_operator("write", tag, Some(value.to_string()))
+            .map_err(GatewayError::Journal)?;
+        Ok(value)
+    }
+
+    /// Hidden tags are not found, others outside the access lists denied.
+    fn denied(&self, tag: &str, action: &str) -> GatewayError {
+        if self.access.is_hidden(tag) {
+            GatewayError::NotFound(format!("no tag {}", tag))
+        } else {
+            GatewayError::Denied(format!("{} can't be {}", tag, action))
+        }
+    }
+
+    async fn keep_alive(self: Arc<Self>) {
+        loop {
+            let interval = self.client.lock().await.keep_alive_interval();
+            tokio::time::sleep(interval).await;
+            self.client.lock().await.keep_alive().await;
+        }
+    }
+
+    /// Read the polled tags every interval and publish the ones that
+    /// changed.
+    async fn poll(self: Arc<Self>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            let mut client = self.client.lock().await;
+            for tag in &self.polled {
+                let value = match value::read_value(&mut client, tag).await {
+                    Ok(value) => value,
+                    Err(e) => {
+                        tracing::warn!("failed to read {}: {:#}", tag, e);
+                        if Failure::of(&e) == Failure::Connection {
+                            let _ = client.close().await;
+                            break;
+                        }
+                        continue;
+                    }
+                };
+                let mut latest = self.latest.lock().unwrap();
+                if latest.get(tag).map(|event| &event.value) == Some(&value) {
+                    continue;
+                }
+                let event = TagEvent {
+                    timestamp: Local::now(),
+                    tag: tag.clone(),
+                    value,
+                };
+                latest.insert(tag.clone(), event.clone());
+                // No streams is not an error.
+                let _ = self.events.send(event);
+            }
+        }
+    }
+}
+
+/// A request the controller failed. The next request starts over with a
+/// new session when this one was lost; refused requests keep it.
+async fn failed(client: &mut Plc, e: anyhow::Error) -> GatewayError {
+    if Failure::of(&e) == Failure::Connection {
+        let _ = client.close().await;
+    }
+    GatewayError::Plc(e)
+}
@@ -0,0 +1,710 @@
+use crate::failure::Failure;
+use crate::gateway::{self, Gateway, GatewayError, TagEvent};
+use crate::historian::Journal;
+use crate::plc::Plc;
+use crate::server::{parse_listen, Auth};
+use crate::service;
+use crate::settings::Access;
+use crate::value::PlcValue;
+use anyhow::Result;
+use clap::Args;
+use futures_util::{stream, StreamExt};
+use proto::value::Kind;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tonic::body::Body;
+use tonic::codec::ProstCodec;
+use tonic::codegen::{http, BoxFuture, BoxStream, Service};
+use tonic::server::Grpc;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+
+/// Options of the `serve-grpc` command.
+#[derive(Args)]
+pub struct GrpcArgs {
+    /// Port or address to serve the API on.
+    #[arg(long, default_value = "0.0.0.0:50051", value_parser = parse_listen)]
+    pub listen: SocketAddr,
+    /// Tag list file selecting the tags polled for `StreamTagChanges`.
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+    /// Polling interval of the streamed tags in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub interval: u64,
+    /// Key clients must give in the `x-api-key` metadata or as a bearer
+    /// token.
+    #[arg(long, env = "COBALT_API_KEY", hide_env_values = true)]
+    pub api_key: Option<String>,
+}
+
+/// Messages of the `cobalt.v1` package, as described by
+/// `proto/cobalt.proto`.
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Value {
+        #[prost(oneof = "value::Kind", tags = "1, 2, 3, 4, 5, 6")]
+        pub kind: Option<value::Kind>,
+    }
+
+    pub mod value {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Kind {
+            #[prost(bool, tag = "1")]
+            Bool(bool),
+            #[prost(sint64, tag = "2")]
+            Int(i64),
+            #[prost(uint64, tag = "3")]
+            Uint(u64),
+            #[prost(double, tag = "4")]
+            Real(f64),
+            #[prost(message, tag = "5")]
+            Structure(super::Structure),
+            #[prost(string, tag = "6")]
+            Text(String),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Structure {
+        #[prost(message, repeated, tag = "1")]
+        pub members: Vec<Member>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Member {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<Value>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ReadTagRequest {
+        #[prost(string, tag = "1")]
+        pub tag: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteTagRequest {
+        #[prost(string, tag = "1")]
+        pub tag: String,
+        #[prost(message, optional, tag = "2")]
+        pub value: Option<Value>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TagReading {
+        #[prost(string, tag = "1")]
+        pub tag: String,
+        #[prost(string, tag = "2")]
+        pub r#type: String,
+        #[prost(message, optional, tag = "3")]
+        pub value: Option<Value>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListTagsRequest {}
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct ListTagsReply {
+        #[prost(message, repeated, tag = "1")]
+        pub tags: Vec<TagInfo>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TagInfo {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub r#type: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StreamTagChangesRequest {
+        #[prost(string, repeated, tag = "1")]
+        pub tags: Vec<String>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TagChange {
+        #[prost(uint64, tag = "1")]
+        pub timestamp: u64,
+        #[prost(message, optional, tag = "2")]
+        pub reading: Option<TagReading>,
+    }
+}
+
+impl From<PlcValue> for proto::Value {
+    fn from(value: PlcValue) -> Self {
+        let kind = match value {
+            PlcValue::Bool(v) => Kind::Bool(v),
+            PlcValue::Sint(v) => Kind::Int(v.into()),
+            PlcValue::Int(v) => Kind::Int(v.into()),
+            PlcValue::Dint(v) => Kind::Int(v.into()),
+            PlcValue::Lint(v) => Kind::Int(v),
+            PlcValue::Usint(v) => Kind::Uint(v.into()),
+            PlcValue::Uint(v) => Kind::Uint(v.into()),
+            PlcValue::Udint(v) | PlcValue::Dword(v) => Kind::Uint(v.into()),
+            PlcValue::Ulint(v) => Kind::Uint(v),
+            PlcValue::Real(v) => Kind::Real(v.into()),
+            PlcValue::Lreal(v) => Kind::Real(v),
+            PlcValue::String(v) => Kind::Text(v.text),
+            PlcValue::Timer(_) | PlcValue::Counter(_) | PlcValue::Control(_) => {
+                Kind::Structure(proto::Structure {
+                    members: value
+                        .members()
+                        .into_iter()
+                        .map(|(name, value)| proto::Member {
+                            name: name.to_owned(),
+                            value: Some(value.into()),
+                        })
+                        .collect(),
+                })
+            }
+        };
+        Self { kind: Some(kind) }
+    }
+}
+
+/// The JSON counterpart of a value, converted to the type of the tag like
+/// the values written through `serve`. `None` when no value is set.
+fn json(value: &proto::Value) -> Option<serde_json::Value> {
+    Some(match value.kind.as_ref()? {
+        Kind::Bool(v) => (*v).into(),
+        Kind::Int(v) => (*v).into(),
+        Kind::Uint(v) => (*v).into(),
+        Kind::Real(v) => (*v).into(),
+        Kind::Text(v) => v.clone().into(),
+        Kind::Structure(structure) => {
+            let mut members = serde_json::Map::new();
+            for member in &structure.members {
+                let value = member.value.as_ref().and_then(json);
+                members.insert(member.name.clone(), value.unwrap_or_default());
+            }
+            members.into()
+        }
+    })
+}
+
+fn reading(tag: String, value: PlcValue) -> proto::TagReading {
+    proto::TagReading {
+        tag,
+        r#type: value.type_name(),
+        value: Some(value.into()),
+    }
+}
+
+/// The controller failed to answer, or refused the request.
+fn plc_status(e: &anyhow::Error) -> Status {
+    let message = format!("{:#}", e);
+    match Failure::of(e) {
+        Failure::Connection => Status::unavailable(message),
+        Failure::TagNotFound => Status::not_found(message),
+        Failure::TypeMismatch | Failure::Usage => Status::invalid_argument(message),
+        Failure::WriteRejected => Status::permission_denied(message),
+        Failure::Error => Status::unknown(message),
+    }
+}
+
+impl From<GatewayError> for Status {
+    fn from(e: GatewayError) -> Self {
+        match e {
+            GatewayError::NotFound(message) => Status::not_found(message),
+            GatewayError::Denied(message) => Status::permission_denied(message),
+            GatewayError::Invalid(message) => Status::invalid_argument(message),
+            GatewayError::Plc(e) => plc_status(&e),
+            GatewayError::NoTagList(message) => Status::unimplemented(message),
+            GatewayError::Journal(e) => Status::internal(e.to_string()),
+        }
+    }
+}
+
+impl From<TagEvent> for proto::TagChange {
+    fn from(event: TagEvent) -> Self {
+        Self {
+            timestamp: event.timestamp.timestamp_millis() as u64,
+            reading: Some(reading(event.tag, event.value)),
+        }
+    }
+}
+
+/// Serve the `cobalt.v1.Tags` gRPC service of `proto/cobalt.proto`, reading
+/// and writing tags over a session shared by all requests and streaming
+/// changes of the polled tags. Clients see the tags the way they do through
+/// `serve`, once they gave the API key when one is set.
+pub async fn run(client: Plc, args: &GrpcArgs, journal: Journal, access: Access) -> Result<()> {
+    let auth = Auth {
+        api_key: args.api_key.clone(),
+        basic: None,
+    };
+    let polled = gateway::polled_tags(args.tags.as_deref())?;
+    let gateway = Gateway::new(client, journal, access, auth, polled)?;
+    gateway.start(Duration::from_millis(args.interval));
+
+    let listener = TcpListener::bind(args.listen).await?;
+    tracing::info!("serving the gRPC tag API on {}", args.listen);
+    tonic::transport::Server::builder()
+        .serve_with_incoming_shutdown(
+            TagsService(gateway.clone()),
+            TcpIncoming::from(listener),
+            service::stopped(),
+        )
+        .await?;
+
+    gateway.close().await
+}
+
+async fn read_tag(
+    gateway: Arc<Gateway>,
+    request: Request<proto::ReadTagRequest>,
+) -> Result<Response<proto::TagReading>, Status> {
+    let tag = request.into_inner().tag;
+    let value = gateway.read_tag(&tag).await?;
+    Ok(Response::new(reading(tag, value)))
+}
+
+async fn write_tag(
+    gateway: Arc<Gateway>,
+    request: Request<proto::WriteTagRequest>,
+) -> Result<Response<proto::TagReading>, Status> {
+    let request = request.into_inner();
+    let json = request
+        .value
+        .as_ref()
+        .and_then(json)
+        .ok_or_else(|| Status::invalid_argument("no value given"))?;
+    let value = gateway.write_tag(&request.tag, &json).await?;
+    Ok(Response::new(reading(request.tag, value)))
+}
+
+async fn list_tags(
+    gateway: Arc<Gateway>,
+    _: Request<proto::ListTagsRequest>,
+) -> Result<Response<proto::ListTagsReply>, Status> {
+    let tags = gateway
+        .list_tags()
+        .await?
+        .into_iter()
+        .map(|tag| proto::TagInfo {
+            name: tag.name,
+            r#type: tag.r#type,
+        })
+        .collect();
+    Ok(Response::new(proto::ListTagsReply { tags }))
+}
+
+/// Stream the current value of the requested polled tags, then each
+/// change, until the client goes away or cobalt stops.
+async fn stream_tag_changes(
+    gateway: Arc<Gateway>,
+    request: Request<proto::StreamTagChangesRequest>,
+) -> Result<Response<BoxStream<proto::TagChange>>, Status> {
+    let polled = gateway.polled();
+    if polled.is_empty() {
+        return Err(Status::failed_precondition(
+            "no tags are polled, give serve-grpc a --tags file",
+        ));
+    }
+    let wanted = request.into_inner().tags;
+    if let Some(tag) = wanted.iter().find(|tag| !polled.contains(tag)) {
+        return Err(Status::invalid_argument(format!("{} is not polled", tag)));
+    }
+    let wanted = move |event: &TagEvent| wanted.is_empty() || wanted.contains(&event.tag);
+
+    let (snapshot, events) = gateway.subscribe();
+    let changes = stream::unfold(events, |mut events| async move {
+        loop {
+            match events.recv().await {
+                Ok(event) => return Some((event, events)),
+                // The client missed some changes, carry on with the next ones.
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return None,
+            }
+        }
+    });
+    let stream = stream::iter(snapshot)
+        .chain(changes)
+        .filter(move |event| futures_util::future::ready(wanted(event)))
+        .map(proto::TagChange::from)
+        .map(Ok)
+        .take_until(service::stopped());
+    Ok(Response::new(Box::pin(stream)))
+}
+
+/// The `cobalt.v1.Tags` service, routing requests to their method once the
+/// client gave its credentials.
+#[derive(Clone)]
+struct TagsService(Arc<Gateway>);
+
+impl Service<http::Request<Body>> for TagsService {
+    type Response = http::Response<Body>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<Body>) -> Self::Future {
+        let gateway = self.0.clone();
+        Box::pin(async move {
+            if !gateway.auth.allows(request.headers()) {
+                return Ok(Status::unauthenticated("missing or wrong credentials").into_http());
+            }
+            let response = match request.uri().path() {
+                "/cobalt.v1.Tags/ReadTag" => {
+                    let method = Method(|request| read_tag(gateway.clone(), request));
+                    Grpc::new(ProstCodec::default())
+                        .unary(method, request)
+                        .await
+                }
+                "/cobalt.v1.Tags/WriteTag" => {
+                    let method = Method(|request| write_tag(gateway.clone(), request));
+                    Grpc::new(ProstCodec::default())
+                        .unary(method, request)
+                        .await
+                }
+                "/cobalt.v1.Tags/ListTags" => {
+                    let method = Method(|request| list_tags(gateway.clone(), request));
+                    Grpc::new(ProstCodec::default())
+                        .unary(method, request)
+                        .await
+                }
+                "/cobalt.v1.Tags/StreamTagChanges" => {
+                    let method = Method(|request| stream_tag_changes(gateway.clone(), request));
+                    Grpc::new(ProstCodec::default())
+                        .server_streaming(method, request)
+                        .await
+                }
+                path => Status::unimplemented(format!("no method {}", path)).into_http(),
+            };
+            Ok(response)
+        })
+    }
+}
+
+/// A method of the service, called by tonic like a tower service.
+struct Method<F>(F);
+
+impl<F, Fut, M, R> Service<Request<M>> for Method<F>
+where
+    F: FnMut(Request<M>) -> Fut,
+    Fut: Future<Output = Result<Response<R>, Status>>,
+{
+    type Response = Response<R>;
+    type Error = Status;
+    type Future = Fut;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<M>) -> Self::Future {
+        (self.0)(request)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plc::{self, ConnectionArgs};
+    use crate::sim::{SimFile, Simulator};
+    use crate::value::Timer;
+    use bytes::{Buf, BufMut, BytesMut};
+    use clap::Parser;
+    use prost::Message;
+
+    const TAGS: &str = r#"
+        [[tag]]
+        name = "PT_101"
+        type = "REAL"
+        value = 12.5
+
+        [[tag]]
+        name = "Setpoint"
+        type = "DINT"
+
+        [[tag]]
+        name = "Secret"
+        type = "DINT"
+
+        [[tag]]
+        name = "Locked"
+        type = "DINT"
+        external_access = "read-only"
+    "#;
+
+    async fn gateway(api_key: Option<&str>) -> TagsService {
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            connection: ConnectionArgs,
+        }
+        let file: SimFile = toml::from_str(TAGS).unwrap();
+        let address = Simulator::new(&file)
+            .unwrap()
+            .spawn("127.0.0.1:0")
+            .await
+            .unwrap();
+        let connection = Cli::parse_from(["cobalt"]).connection;
+        let client = plc::connect(&address.to_string(), &connection)
+            .await
+            .unwrap();
+        let access = Access {
+            hidden: vec!["Secret".to_owned()],
+            ..Default::default()
+        };
+        let auth = Auth {
+            api_key: api_key.map(str::to_owned),
+            basic: None,
+        };
+        let polled = vec!["PT_101".to_owned(), "Setpoint".to_owned()];
+        let gateway = Gateway::new(client, Journal::disabled(), access, auth, polled).unwrap();
+        gateway.start(Duration::from_millis(20));
+        TagsService(gateway)
+    }
+
+    /// Send a request to a method, returning the body of the reply, or its
+    /// gRPC status code and message.
+    async fn send(
+        service: &mut TagsService,
+        method: &str,
+        message: impl Message,
+        metadata: &[(&'static str, &str)],
+    ) -> Result<Body, (i32, String)> {
+        let mut body = BytesMut::new();
+        body.put_u8(0);
+        body.put_u32(message.encoded_len() as u32);
+        message.encode(&mut body).unwrap();
+        let mut request = http::Request::builder()
+            .uri(format!("http://gateway/cobalt.v1.Tags/{}", method))
+            .header("content-type", "application/grpc");
+        for (name, value) in metadata {
+            request = request.header(*name, *value);
+        }
+        let request = request
+            .body(Body::new(axum::body::Body::from(body.freeze())))
+            .unwrap();
+        let response = service.call(request).await.unwrap();
+        // Failures come without a body, their status in the headers.
+        let header = |name| {
+            let value = response.headers().get(name)?;
+            Some(value.to_str().unwrap().to_owned())
+        };
+        match header("grpc-status") {
+            Some(code) => Err((code.parse().unwrap(), header("grpc-message").unwrap())),
+            None => Ok(response.into_body()),
+        }
+    }
+
+    /// The first `count` messages of a reply.
+    async fn messages<M: Message + Default>(body: Body, count: usize) -> Vec<M> {
+        let mut data = axum::body::Body::new(body).into_data_stream();
+        let mut buffer = BytesMut::new();
+        let mut messages = Vec::new();
+        while messages.len() < count {
+            if buffer.len() >= 5 {
+                let len = u32::from_be_bytes(buffer[1..5].try_into().unwrap()) as usize;
+                if buffer.len() >= 5 + len {
+                    buffer.advance(5);
+                    messages.push(M::decode(buffer.split_to(len)).unwrap());
+                    continue;
+                }
+            }
+            buffer.extend_from_slice(&data.next().await.unwrap().unwrap());
+        }
+        messages
+    }
+
+    async fn call<M: Message + Default>(
+        service: &mut TagsService,
+        method: &str,
+        message: impl Message,
+    ) -> Result<M, (i32, String)> {
+        let body = send(service, method, message, &[]).await?;
+        Ok(messages(body, 1).await.remove(0))
+    }
+
+    fn read(tag: &str) -> proto::ReadTagRequest {
+        proto::ReadTagRequest {
+            tag: tag.to_owned(),
+        }
+    }
+
+    fn write(tag: &str, value: Kind) -> proto::WriteTagRequest {
+        proto::WriteTagRequest {
+            tag: tag.to_owned(),
+            value: Some(proto::Value { kind: Some(value) }),
+        }
+    }
+
+    #[tokio::test]
+    async fn tags() {
+        let mut service = gateway(None).await;
+        let reading: proto::TagReading =
+            call(&mut service, "ReadTag", read("PT_101")).await.unwrap();
+        assert_eq!(reading, reading_of("PT_101", PlcValue::Real(12.5)));
+
+        let written: proto::TagReading =
+            call(&mut service, "WriteTag", write("Setpoint", Kind::Int(-40)))
+                .await
+                .unwrap();
+        assert_eq!(written, reading_of("Setpoint", PlcValue::Dint(-40)));
+        let reading: proto::TagReading = call(&mut service, "ReadTag", read("Setpoint"))
+            .await
+            .unwrap();
+        assert_eq!(reading.r#type, "Dint");
+        assert_eq!(reading.value.unwrap().kind, Some(Kind::Int(-40)));
+
+        // Values are converted to the type of the tag, or refused.
+        let code = |result: Result<proto::TagReading, (i32, String)>| result.unwrap_err().0;
+        let flag = write("Setpoint", Kind::Bool(true));
+        assert_eq!(code(call(&mut service, "WriteTag", flag).await), 3);
+        let huge = write("Setpoint", Kind::Uint(1 << 40));
+        assert_eq!(code(call(&mut service, "WriteTag", huge).await), 3);
+        assert_eq!(
+            code(call(&mut service, "WriteTag", read("Setpoint")).await),
+            3
+        );
+        // A write the controller refuses keeps the session.
+        let locked = write("Locked", Kind::Int(1));
+        assert_eq!(code(call(&mut service, "WriteTag", locked).await), 7);
+        let reading: proto::TagReading =
+            call(&mut service, "ReadTag", read("PT_101")).await.unwrap();
+        assert_eq!(reading, reading_of("PT_101", PlcValue::Real(12.5)));
+        // Hidden tags don't exist for clients, unknown ones neither.
+        assert_eq!(code(call(&mut service, "ReadTag", read("Secret")).await), 5);
+        assert_eq!(
+            code(call(&mut service, "ReadTag", read("Missing")).await),
+            5
+        );
+
+        let list: proto::ListTagsReply = call(&mut service, "ListTags", proto::ListTagsRequest {})
+            .await
+            .unwrap();
+        let names: Vec<_> = list.tags.iter().map(|tag| tag.name.as_str()).collect();
+        assert_eq!(names, ["PT_101", "Setpoint", "Locked"]);
+
+        let unknown = send(&mut service, "DeleteTag", read("PT_101"), &[]).await;
+        assert_eq!(unknown.unwrap_err().0, 12);
+    }
+
+    #[tokio::test]
+    async fn stream() {
+        let mut service = gateway(None).await;
+        let request = |tags: &[&str]| proto::StreamTagChangesRequest {
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+        };
+        let refused = send(&mut service, "StreamTagChanges", request(&["Secret"]), &[]).await;
+        // Messages come percent-encoded.
+        assert_eq!(
+            refused.unwrap_err(),
+            (3, "Secret%20is%20not%20polled".to_owned())
+        );
+
+        // Let the first poll go through, so the stream starts with the
+        // current values.
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let body = send(
+            &mut service,
+            "StreamTagChanges",
+            request(&["Setpoint"]),
+            &[],
+        )
+        .await
+        .unwrap();
+        let written: Result<proto::TagReading, _> =
+            call(&mut service, "WriteTag", write("Setpoint", Kind::Int(7))).await;
+        written.unwrap();
+        let changes: Vec<proto::TagChange> = messages(body, 2).await;
+        let readings: Vec<_> = changes.into_iter().map(|c| c.reading.unwrap()).collect();
+        assert_eq!(
+            readings,
+            [
+                reading_of("Setpoint", PlcValue::Dint(0)),
+                reading_of("Setpoint", PlcValue::Dint(7)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn api_key() {
+        let mut service = gateway(Some("s3cret")).await;
+        let denied = send(&mut service, "ReadTag", read("PT_101"), &[]).await;
+        assert_eq!(denied.unwrap_err().0, 16);
+        let wrong = [("x-api-key", "s3cre")];
+        assert!(send(&mut service, "ReadTag", read("PT_101"), &wrong)
+            .await
+            .is_err());
+        let bearer = [("authorization", "Bearer s3cret")];
+        assert!(send(&mut service, "ReadTag", read("PT_101"), &bearer)
+            .await
+            .is_ok());
+    }
+
+    fn reading_of(tag: &str, value: PlcValue) -> proto::TagReading {
+        reading(tag.to_owned(), value)
+    }
+
+    #[test]
+    fn values() {
+        let timer = PlcValue::Timer(Timer {
+            pre: 5000,
+            acc: 1200,
+            en: true,
+            tt: true,
+            dn: false,
+        });
+        let value = proto::Value::from(timer);
+        let Some(Kind::Structure(structure)) = &value.kind else {
+            panic!("{:?}", value);
+        };
+        let members: Vec<_> = structure
+            .members
+            .iter()
+            .map(|member| {
+                (
+                    member.name.as_str(),
+                    member.value.clone().unwrap().kind.unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            members,
+            [
+                ("PRE", Kind::Int(5000)),
+                ("ACC", Kind::Int(1200)),
+                ("EN", Kind::Bool(true)),
+                ("TT", Kind::Bool(true)),
+                ("DN", Kind::Bool(false)),
+            ]
+        );
+        assert_eq!(
+            json(&value).unwrap(),
+            serde_json::json!({"PRE": 5000, "ACC": 1200, "EN": true, "TT": true, "DN": false})
+        );
+        assert_eq!(
+            proto::Value::from(PlcValue::Udint(u32::MAX)).kind,
+            Some(Kind::Uint(u32::MAX.into()))
+        );
+        assert_eq!(
+            proto::Value::from(PlcValue::Sint(-5)).kind,
+            Some(Kind::Int(-5))
+        );
+        assert_eq!(json(&proto::Value::default()), None);
+
+        // Messages encode like protoc generated ones, e.g. `sint64` zigzag.
+        let mut buf = Vec::new();
+        proto::Value::from(PlcValue::Dint(-2))
+            .encode(&mut buf)
+            .unwrap();
+        assert_eq!(buf, [0x10, 0x03]);
+    }
+}
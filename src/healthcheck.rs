@@ -0,0 +1,205 @@
+use crate::backend::Protocol;
+use crate::diagnostics::Identity;
+use crate::plc::{self, ConnectionArgs, Plc};
+use crate::targets::Target;
+use crate::value;
+use anyhow::{bail, Result};
+use clap::Args;
+use colored::*;
+use std::time::{Duration, Instant};
+
+/// Options of the `healthcheck` command.
+#[derive(Args)]
+pub struct HealthcheckArgs {
+    /// Tag read from every controller, unless its target names another
+    /// `heartbeat`.
+    #[arg(long)]
+    pub heartbeat: Option<String>,
+    /// Read the heartbeat again after this long and fail when it didn't
+    /// change, for counters the logic keeps incrementing.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub heartbeat_interval: Option<Duration>,
+    /// Time each controller gets to answer.
+    #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+    pub timeout: Duration,
+}
+
+/// What a controller answered.
+#[derive(Default)]
+struct Health {
+    /// Round trip of the identity request, opening the session.
+    connect: Option<Duration>,
+    /// Round trip of the heartbeat read.
+    read: Option<Duration>,
+    mode: Option<&'static str>,
+    heartbeat: Option<String>,
+    failure: Option<String>,
+}
+
+/// Check every controller at once: read its identity, which opens the
+/// session, then its heartbeat tag. Fails when any controller does, once
+/// the table of all of them is printed.
+pub async fn run(
+    targets: &[Target],
+    connection: &ConnectionArgs,
+    args: &HealthcheckArgs,
+) -> Result<()> {
+    if connection.protocol != Protocol::Ab
+        && targets
+            .iter()
+            .any(|target| target.heartbeat.is_none() && args.heartbeat.is_none())
+    {
+        bail!(
+            "{} devices have no identity to read, give a --heartbeat tag",
+            connection.protocol
+        );
+    }
+    let checks = targets.iter().map(|target| check(target, connection, args));
+    let results = futures_util::future::join_all(checks).await;
+    print(targets, &results);
+
+    let failed = results
+        .iter()
+        .filter(|health| health.failure.is_some())
+        .count();
+    if failed > 0 {
+        bail!("{} of {} controllers failed", failed, targets.len());
+    }
+    Ok(())
+}
+
+async fn check(target: &Target, connection: &ConnectionArgs, args: &HealthcheckArgs) -> Health {
+    let mut health = Health::default();
+    let timeout = args.timeout + args.heartbeat_interval.unwrap_or_default();
+    let result = tokio::time::timeout(timeout, async {
+        let mut client = plc::connect(&target.address, connection).await?;
+        let result = probe(&mut client, target, args, &mut health).await;
+        let _ = client.close().await;
+        result
+    })
+    .await;
+    health.failure = match result {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => Some(format!("{:#}", e)),
+        Err(_) => Some(format!(
+            "no answer within {}",
+            humantime::format_duration(timeout)
+        )),
+    };
+    health
+}
+
+async fn probe(
+    client: &mut Plc,
+    target: &Target,
+    args: &HealthcheckArgs,
+    health: &mut Health,
+) -> Result<()> {
+    if client.backend().is_none() {
+        let started = Instant::now();
+        let identity = Identity::read(client).await?;
+        health.connect = Some(started.elapsed());
+        health.mode = Some(identity.mode());
+        let faults = identity.faults();
+        if faults.iter().any(|fault| fault.starts_with("major")) {
+            bail!("{} fault", faults.join(", "));
+        }
+    }
+    let Some(tag) = target.heartbeat.as_ref().or(args.heartbeat.as_ref()) else {
+        return Ok(());
+    };
+    let started = Instant::now();
+    let value = value::read_value(client, tag).await?;
+    health.read = Some(started.elapsed());
+    health.heartbeat = Some(value.to_string());
+    if let Some(interval) = args.heartbeat_interval {
+        tokio::time::sleep(interval).await;
+        let next = value::read_value(client, tag).await?;
+        health.heartbeat = Some(next.to_string());
+        if next == value {
+            bail!(
+                "{} stayed at {} for {}",
+                tag,
+                value,
+                humantime::format_duration(interval)
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Print one row per controller, followed by the reasons of the failures.
+fn print(targets: &[Target], results: &[Health]) {
+    let millis = |elapsed: Option<Duration>| match elapsed {
+        Some(elapsed) => format!("{:.1} ms", elapsed.as_secs_f64() * 1000.0),
+        None => "-".to_owned(),
+    };
+    let header = [
+        "CONTROLLER",
+        "ADDRESS",
+        "RESULT",
+        "CONNECT",
+        "READ",
+        "MODE",
+        "HEARTBEAT",
+    ];
+    let rows: Vec<[String; 7]> = targets
+        .iter()
+        .zip(results)
+        .map(|(target, health)| {
+            [
+                target.name.clone(),
+                target.address.clone(),
+                match health.failure {
+                    None => "PASS".to_owned(),
+                    Some(_) => "FAIL".to_owned(),
+                },
+                millis(health.connect),
+                millis(health.read),
+                health.mode.unwrap_or("-").to_owned(),
+                health.heartbeat.clone().unwrap_or_else(|| "-".to_owned()),
+            ]
+        })
+        .collect();
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let line = |cells: [ColoredString; 7]| {
+        let cells: Vec<String> = cells
+            .iter()
+            .zip(widths)
+            .map(|(cell, width)| {
+                let padding = width.saturating_sub(cell.chars().count());
+                format!("{}{}", cell, " ".repeat(padding))
+            })
+            .collect();
+        println!("{}", cells.join("    ").trim_end());
+    };
+    line(header.map(|cell| cell.bold()));
+    for row in rows {
+        let [name, address, result, connect, read, mode, heartbeat] = row;
+        let result = match result.as_str() {
+            "PASS" => result.bold().green(),
+            _ => result.bold().red(),
+        };
+        line([
+            name.normal(),
+            address.normal(),
+            result,
+            connect.normal(),
+            read.normal(),
+            mode.normal(),
+            heartbeat.normal(),
+        ]);
+    }
+
+    for (target, health) in targets.iter().zip(results) {
+        if let Some(failure) = &health.failure {
+            println!("{}: {}", target.name.bold(), failure.red());
+        }
+    }
+}
@@ -0,0 +1,351 @@
+use crate::alarm::{AlarmEvent, AlarmEventKind};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+#[cfg(feature = "historian")]
+use {
+    anyhow::bail,
+    chrono::{Local, NaiveDate, TimeZone},
+    std::io::{BufRead, BufReader},
+    std::path::Path,
+};
+
+/// Default location of the event journal.
+#[cfg(feature = "historian")]
+pub const DEFAULT_JOURNAL: &str = "cobalt-journal.jsonl";
+
+/// What happened, as stored in the journal.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Record {
+    /// An alarm transition or an operator action seen by the alarm engine.
+    Alarm {
+        name: String,
+        event: AlarmEventKind,
+        message: String,
+        value: Option<f64>,
+    },
+    /// An action taken by an operator through cobalt.
+    Operator {
+        user: String,
+        action: String,
+        target: String,
+        detail: Option<String>,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    pub record: Record,
+}
+
+/// Append-only JSON lines journal of alarm transitions and operator actions.
+#[derive(Clone)]
+pub struct Journal {
+    /// `None` when built without the historian, nothing is recorded then.
+    path: Option<PathBuf>,
+}
+
+impl Journal {
+    #[cfg(feature = "historian")]
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: Some(path.to_owned()),
+        }
+    }
+
+    /// A journal recording nothing, for builds without the historian and
+    /// dry runs.
+    pub fn disabled() -> Self {
+        Self { path: None }
+    }
+
+    fn append(&self, record: Record) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let entry = Entry {
+            timestamp: Utc::now(),
+            record,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("failed to open journal {}", path.display()))?;
+        writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+        Ok(())
+    }
+
+    pub fn record_alarm(&self, event: &AlarmEvent) -> Result<()> {
+        self.append(Record::Alarm {
+            name: event.name.clone(),
+            event: event.event,
+            message: event.message.clone(),
+            value: event.value,
+        })
+    }
+
+    pub fn record_operator(
+        &self,
+        action: &str,
+        target: &str,
+        detail: Option<String>,
+    ) -> Result<()> {
+        self.append(Record::Operator {
+            user: current_user(),
+            action: action.to_owned(),
+            target: target.to_owned(),
+            detail,
+        })
+    }
+
+    /// Entries with a timestamp in `[from, to)`, oldest first.
+    #[cfg(feature = "historian")]
+    pub fn read_range(
+        &self,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> Result<Vec<Entry>> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(Vec::new()),
+        };
+        let file = match std::fs::File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => {
+                return Err(e).with_context(|| format!("failed to open journal {}", path.display()))
+            }
+        };
+        let mut entries = Vec::new();
+        for (idx, line) in BufReader::new(file).lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry: Entry = serde_json::from_str(&line).with_context(|| {
+                format!("invalid journal entry at {}:{}", path.display(), idx + 1)
+            })?;
+            if from.map(|from| entry.timestamp >= from).unwrap_or(true)
+                && to.map(|to| entry.timestamp < to).unwrap_or(true)
+            {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Name of the user running cobalt.
+pub fn current_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_owned())
+}
+
+/// Parse an RFC 3339 timestamp or a local `YYYY-MM-DD` date.
+#[cfg(feature = "historian")]
+pub fn parse_time(s: &str) -> Result<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(s) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    if let Ok(date) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        if let Some(time) = Local
+            .from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap())
+            .earliest()
+        {
+            return Ok(time.with_timezone(&Utc));
+        }
+    }
+    bail!("invalid time `{}`, expected YYYY-MM-DD or RFC 3339", s)
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+/// Write journal entries as CSV or JSON.
+#[cfg(feature = "historian")]
+pub fn export(entries: &[Entry], format: ExportFormat, out: &mut dyn Write) -> Result<()> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, entries)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => {
+            writeln!(out, "timestamp,kind,name,event,value,user,message")?;
+            for entry in entries {
+                let timestamp = entry.timestamp.to_rfc3339();
+                let row = match &entry.record {
+                    Record::Alarm {
+                        name,
+                        event,
+                        message,
+                        value,
+                    } => [
+                        timestamp,
+                        "alarm".to_owned(),
+                        name.clone(),
+                        serde_json::to_value(event)?
+                            .as_str()
+                            .unwrap_or_default()
+                            .to_owned(),
+                        value.map(|v| v.to_string()).unwrap_or_default(),
+                        String::new(),
+                        message.clone(),
+                    ],
+                    Record::Operator {
+                        user,
+                        action,
+                        target,
+                        detail,
+                    } => [
+                        timestamp,
+                        "operator".to_owned(),
+                        target.clone(),
+                        action.clone(),
+                        detail.clone().unwrap_or_default(),
+                        user.clone(),
+                        String::new(),
+                    ],
+                };
+                let row: Vec<_> = row.iter().map(|field| csv_field(field)).collect();
+                writeln!(out, "{}", row.join(","))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Quote a CSV field if needed.
+pub fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+#[cfg(all(test, feature = "historian"))]
+mod test {
+    use super::*;
+
+    fn entry(timestamp: &str, record: Record) -> Entry {
+        Entry {
+            timestamp: parse_time(timestamp).unwrap(),
+            record,
+        }
+    }
+
+    fn alarm(name: &str, event: AlarmEventKind) -> Record {
+        Record::Alarm {
+            name: name.to_owned(),
+            event,
+            message: format!("{} high", name),
+            value: Some(97.5),
+        }
+    }
+
+    #[test]
+    fn read_range() {
+        let path =
+            std::env::temp_dir().join(format!("cobalt-journal-{}.jsonl", std::process::id()));
+        let journal = Journal::new(&path);
+        assert!(journal.read_range(None, None).unwrap().is_empty());
+
+        let mut file = std::fs::File::create(&path).unwrap();
+        for (timestamp, name) in [
+            ("2024-03-01T23:59:59Z", "TT_1"),
+            ("2024-03-02T00:00:00Z", "TT_2"),
+            ("2024-03-02T12:00:00Z", "TT_3"),
+            ("2024-03-03T00:00:00Z", "TT_4"),
+        ] {
+            let entry = entry(timestamp, alarm(name, AlarmEventKind::Raised));
+            writeln!(file, "{}", serde_json::to_string(&entry).unwrap()).unwrap();
+        }
+        writeln!(file).unwrap();
+        drop(file);
+
+        let names = |from: Option<&str>, to: Option<&str>| -> Vec<String> {
+            let from = from.map(|t| parse_time(t).unwrap());
+            let to = to.map(|t| parse_time(t).unwrap());
+            journal
+                .read_range(from, to)
+                .unwrap()
+                .into_iter()
+                .map(|entry| match entry.record {
+                    Record::Alarm { name, .. } => name,
+                    Record::Operator { .. } => unreachable!(),
+                })
+                .collect()
+        };
+        assert_eq!(names(None, None), ["TT_1", "TT_2", "TT_3", "TT_4"]);
+        // `from` is included and `to` is not.
+        assert_eq!(
+            names(Some("2024-03-02T00:00:00Z"), Some("2024-03-03T00:00:00Z")),
+            ["TT_2", "TT_3"]
+        );
+        assert_eq!(names(Some("2024-03-02T12:00:00Z"), None), ["TT_3", "TT_4"]);
+        assert_eq!(names(None, Some("2024-03-02T00:00:00Z")), ["TT_1"]);
+
+        std::fs::write(&path, "{\"timestamp\":\"yesterday\"}\n").unwrap();
+        let err = journal.read_range(None, None).unwrap_err();
+        assert!(format!("{}", err).contains(".jsonl:1"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(
+            parse_time("2024-03-02T01:00:00+01:00").unwrap(),
+            parse_time("2024-03-02T00:00:00Z").unwrap()
+        );
+        let midnight = parse_time("2024-03-02").unwrap().with_timezone(&Local);
+        assert_eq!(midnight.format("%F %T").to_string(), "2024-03-02 00:00:00");
+        assert!(parse_time("02/03/2024").is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let entries = [
+            entry(
+                "2024-03-02T00:00:00Z",
+                alarm("PT_101", AlarmEventKind::Acknowledged),
+            ),
+            entry(
+                "2024-03-02T00:00:01Z",
+                Record::Operator {
+                    user: "jo".to_owned(),
+                    action: "write".to_owned(),
+                    target: "SP_1".to_owned(),
+                    detail: Some("1,5 -> \"2\"".to_owned()),
+                },
+            ),
+        ];
+        let mut out = Vec::new();
+        export(&entries, ExportFormat::Csv, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "timestamp,kind,name,event,value,user,message\n\
+             2024-03-02T00:00:00+00:00,alarm,PT_101,acknowledged,97.5,,PT_101 high\n\
+             2024-03-02T00:00:01+00:00,operator,SP_1,write,\"1,5 -> \"\"2\"\"\",jo,\n"
+        );
+
+        let mut out = Vec::new();
+        export(&entries[..1], ExportFormat::Json, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(json[0]["kind"], "alarm");
+        assert_eq!(json[0]["event"], "acknowledged");
+        assert_eq!(json[0]["timestamp"], "2024-03-02T00:00:00Z");
+    }
+}
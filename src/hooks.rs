@@ -0,0 +1,144 @@
+use crate::alarm::{AlarmEvent, AlarmEventKind};
+use crate::historian::Journal;
+use crate::plc::{self, Plc};
+use crate::value::{self, PlcValue};
+use anyhow::Result;
+use colored::*;
+use std::str::FromStr;
+
+/// A `TAG=VALUE` write made when an alarm is raised, e.g. `Pump_Run=false`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlarmWrite {
+    pub tag: String,
+    pub value: serde_json::Value,
+}
+
+impl FromStr for AlarmWrite {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (tag, value) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected TAG=VALUE, got `{}`", s))?;
+        let tag = tag.trim();
+        if tag.is_empty() {
+            return Err(format!("missing tag in `{}`", s));
+        }
+        let value = serde_json::from_str(value.trim())
+            .map_err(|_| format!("`{}` is not a number or boolean", value.trim()))?;
+        Ok(Self {
+            tag: tag.to_owned(),
+            value,
+        })
+    }
+}
+
+/// Actions taken when an alarm is raised, on top of printing it.
+pub struct AlarmHooks {
+    /// Shell command run with the alarm in its environment.
+    pub command: Option<String>,
+    pub writes: Vec<AlarmWrite>,
+}
+
+impl AlarmHooks {
+    /// Run the actions for an event. Only raised alarms trigger them.
+    ///
+    /// The command runs in the background so a slow script never stalls
+    /// polling, and failed actions are reported without stopping the
+    /// monitor: a watchdog that exits on the first hiccup watches nothing.
+    pub async fn run(&self, client: &mut Plc, event: &AlarmEvent, journal: &Journal) {
+        if event.event != AlarmEventKind::Raised {
+            return;
+        }
+        if let Some(command) = &self.command {
+            spawn_command(command, event);
+        }
+        for write in &self.writes {
+            match alarm_write(client, write).await {
+                // The dry run printed it already.
+                Ok(_) if plc::dry_run() => {}
+                Ok(value) => {
+                    println!(
+                        "    {} {} = {} ({})",
+                        "ALARM WRITE".bold().yellow(),
+                        write.tag.bold(),
+                        value.to_string().bold().green(),
+                        event.name
+                    );
+                    let detail = Some(format!("{} on alarm {}", value, event.name));
+                    if let Err(e) = journal.record_operator("alarm write", &write.tag, detail) {
+                        tracing::error!("failed to journal the alarm write: {:#}", e);
+                    }
+                }
+                Err(e) => tracing::error!(
+                    "alarm write to {} on alarm {} failed: {:#}",
+                    write.tag,
+                    event.name,
+                    e
+                ),
+            }
+        }
+    }
+}
+
+async fn alarm_write(client: &mut Plc, write: &AlarmWrite) -> Result<PlcValue> {
+    let current = value::read_value(client, &write.tag).await?;
+    let value = PlcValue::from_json_like(&current, &write.value)?;
+    value::write_value(client, &write.tag, value.clone()).await?;
+    Ok(value)
+}
+
+/// Start the command through the shell with `COBALT_ALARM`, `COBALT_VALUE`,
+/// `COBALT_MESSAGE` and `COBALT_TIMESTAMP` set, reporting a failure once it
+/// exits.
+fn spawn_command(command: &str, event: &AlarmEvent) {
+    #[cfg(windows)]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("cmd");
+        cmd.arg("/C").arg(command);
+        cmd
+    };
+    #[cfg(not(windows))]
+    let mut cmd = {
+        let mut cmd = tokio::process::Command::new("sh");
+        cmd.arg("-c").arg(command);
+        cmd
+    };
+    cmd.env("COBALT_ALARM", &event.name)
+        .env(
+            "COBALT_VALUE",
+            event.value.map(|v| v.to_string()).unwrap_or_default(),
+        )
+        .env("COBALT_MESSAGE", &event.message)
+        .env("COBALT_TIMESTAMP", event.timestamp.to_rfc3339());
+    let name = event.name.clone();
+    match cmd.spawn() {
+        Ok(mut child) => {
+            tokio::spawn(async move {
+                match child.wait().await {
+                    Ok(status) if status.success() => {}
+                    Ok(status) => {
+                        tracing::warn!("alarm command for {} exited with {}", name, status)
+                    }
+                    Err(e) => tracing::error!("alarm command for {} failed: {}", name, e),
+                }
+            });
+        }
+        Err(e) => tracing::error!("failed to start the alarm command for {}: {}", name, e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn alarm_writes() {
+        let write: AlarmWrite = "Pump_Run = false".parse().unwrap();
+        assert_eq!(write.tag, "Pump_Run");
+        assert_eq!(write.value, serde_json::json!(false));
+        assert!("Pump_Run".parse::<AlarmWrite>().is_err());
+        assert!("=1".parse::<AlarmWrite>().is_err());
+        assert!("Pump_Run=off".parse::<AlarmWrite>().is_err());
+    }
+}
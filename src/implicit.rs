@@ -0,0 +1,842 @@
+use crate::cip::{dump, parse_hex};
+use crate::plc::{self, ConnectionArgs, Plc};
+use crate::service;
+use crate::value::{self, PlcValue};
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::Args;
+use colored::*;
+use rseip::client::ab_eip::TagType;
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::Instant;
+
+/// Explicit messaging port of adapters, and the port I/O data travels on.
+const EXPLICIT_PORT: u16 = 44818;
+const IO_PORT: u16 = 2222;
+
+/// Time given to the adapter to accept the session or answer a request.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Wait before opening a lost connection again.
+const RETRY: Duration = Duration::from_secs(1);
+
+const CMD_REGISTER_SESSION: u16 = 0x0065;
+const CMD_UNREGISTER_SESSION: u16 = 0x0066;
+const CMD_SEND_RR_DATA: u16 = 0x006F;
+
+/// Common packet format items.
+const ITEM_NULL: u16 = 0x0000;
+const ITEM_UNCONNECTED: u16 = 0x00B2;
+const ITEM_CONNECTED: u16 = 0x00B1;
+const ITEM_SEQUENCED_ADDRESS: u16 = 0x8002;
+
+const SERVICE_FORWARD_OPEN: u8 = 0x54;
+const SERVICE_FORWARD_CLOSE: u8 = 0x4E;
+
+/// Path of the connection manager.
+const CONNECTION_MANAGER: [u8; 4] = [0x20, 0x06, 0x24, 0x01];
+
+/// Class of the assembly objects.
+const CLASS_ASSEMBLY: u8 = 0x04;
+
+/// Time tick and timeout ticks of the unconnected requests, about 5 s.
+const PRIORITY_TICK_TIME: u8 = 0x0A;
+const TIMEOUT_TICKS: u8 = 0x05;
+
+/// Connection times out after 4 intervals without data.
+const TIMEOUT_MULTIPLIER: u8 = 0;
+const TIMEOUT_INTERVALS: u32 = 4;
+
+/// Point to point, scheduled priority, fixed size.
+const CONNECTION_PARAMETERS: u16 = 0x4000 | 0x0800;
+
+/// Client, cyclic, transport class 1.
+const TRANSPORT_CLASS_1: u8 = 0x01;
+
+/// Largest connection of a regular Forward Open.
+const MAX_CONNECTION_SIZE: u16 = 505;
+
+/// Vendor id of the originator, the one for unregistered vendors.
+const VENDOR_ID: u16 = 0xFFFF;
+
+/// Run/idle header of the output data.
+const RUN: u32 = 1;
+const IDLE: u32 = 0;
+
+/// Options of the `io` command.
+#[derive(Args)]
+pub struct IoArgs {
+    /// Assembly instance of the input data the adapter produces (T→O).
+    #[arg(long)]
+    pub input_instance: u16,
+    /// Size of the input data in bytes.
+    #[arg(long)]
+    pub input_size: u16,
+    /// Assembly instance of the output data sent to the adapter (O→T), or
+    /// its input only heartbeat connection point with an output size of 0.
+    #[arg(long)]
+    pub output_instance: u16,
+    /// Size of the output data in bytes.
+    #[arg(long, default_value_t = 0)]
+    pub output_size: u16,
+    /// Configuration assembly instance.
+    #[arg(long, default_value_t = 1)]
+    pub config_instance: u16,
+    /// Requested packet interval in milliseconds, in both directions.
+    #[arg(
+        long,
+        default_value_t = 10,
+        value_parser = clap::value_parser!(u32).range(1..=10_000)
+    )]
+    pub rpi: u32,
+    /// Output data in hex, e.g. `01 00`, zeros by default.
+    #[arg(long, value_parser = parse_hex)]
+    pub output_data: Option<Bytes>,
+    /// Send the output data in run mode. It is sent idle otherwise, which
+    /// adapters answer by holding their outputs or setting them safe.
+    #[arg(long)]
+    pub run: bool,
+    /// Fields file decoding the input data into named values, printed when
+    /// they change instead of the raw bytes.
+    #[arg(long)]
+    pub fields: Option<PathBuf>,
+    /// Controller the fields are written to as they change, each to the tag
+    /// of its name.
+    #[arg(long, requires = "fields")]
+    pub bridge_to: Option<String>,
+}
+
+impl IoArgs {
+    fn output(&self) -> Result<Vec<u8>> {
+        let size = self.output_size as usize;
+        match &self.output_data {
+            None => Ok(vec![0; size]),
+            Some(data) if data.len() == size => Ok(data.to_vec()),
+            Some(data) => bail!(
+                "--output-data has {} bytes, --output-size is {}",
+                data.len(),
+                size
+            ),
+        }
+    }
+
+    /// Size of the connections, with the sequence count of both and the
+    /// run/idle header of the outputs.
+    fn connection_sizes(&self) -> Result<(u16, u16)> {
+        let o_t = match self.output_size {
+            0 => 2,
+            size => size + 6,
+        };
+        let t_o = self.input_size + 2;
+        if o_t.max(t_o) > MAX_CONNECTION_SIZE {
+            bail!(
+                "I/O connections carry at most {} bytes",
+                MAX_CONNECTION_SIZE - 6
+            );
+        }
+        Ok((o_t, t_o))
+    }
+
+    /// Assembly path: configuration instance, then the output and input
+    /// connection points.
+    fn path(&self) -> Vec<u8> {
+        let mut path = vec![0x20, CLASS_ASSEMBLY];
+        for (segment, id) in [
+            (0x24, self.config_instance),
+            (0x2C, self.output_instance),
+            (0x2C, self.input_instance),
+        ] {
+            match u8::try_from(id) {
+                Ok(id) => path.extend_from_slice(&[segment, id]),
+                Err(_) => {
+                    path.extend_from_slice(&[segment | 0x01, 0]);
+                    path.extend_from_slice(&id.to_le_bytes());
+                }
+            }
+        }
+        path
+    }
+}
+
+/// A TOML file naming values of the input data, with one `[[field]]` table
+/// per value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldList {
+    #[serde(default, rename = "field")]
+    pub fields: Vec<FieldConfig>,
+}
+
+/// A value at a byte offset of the input data, e.g. `offset = 4` and
+/// `type = "REAL"`, or a bit of a byte with `bit = 3`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FieldConfig {
+    pub name: String,
+    pub offset: usize,
+    /// Atomic type of the value, BOOL by default.
+    #[serde(rename = "type", default = "default_field_type")]
+    pub field_type: String,
+    #[serde(default)]
+    pub bit: Option<u8>,
+}
+
+fn default_field_type() -> String {
+    "BOOL".to_owned()
+}
+
+/// Types of the fields, the atomic ones.
+const FIELD_TYPES: [TagType; 12] = [
+    TagType::Bool,
+    TagType::Sint,
+    TagType::Int,
+    TagType::Dint,
+    TagType::Lint,
+    TagType::Usint,
+    TagType::Uint,
+    TagType::Udint,
+    TagType::Ulint,
+    TagType::Real,
+    TagType::Lreal,
+    TagType::Dword,
+];
+
+/// A field checked against the input size.
+#[derive(Debug, Clone)]
+struct Field {
+    name: String,
+    offset: usize,
+    tag_type: TagType,
+    bit: Option<u8>,
+}
+
+impl Field {
+    fn new(config: &FieldConfig, input_size: usize) -> Result<Self> {
+        let tag_type = *FIELD_TYPES
+            .iter()
+            .find(|tag_type| format!("{:?}", tag_type).eq_ignore_ascii_case(&config.field_type))
+            .ok_or_else(|| anyhow!("{}: unknown type {}", config.name, config.field_type))?;
+        let size = match tag_type {
+            TagType::Bool | TagType::Sint | TagType::Usint => 1,
+            TagType::Int | TagType::Uint => 2,
+            TagType::Lint | TagType::Ulint | TagType::Lreal => 8,
+            _ => 4,
+        };
+        match config.bit {
+            Some(_) if tag_type != TagType::Bool => {
+                bail!("{}: only BOOL fields take a bit", config.name)
+            }
+            Some(bit) if bit > 7 => bail!("{}: bits of a byte are 0 to 7", config.name),
+            _ => {}
+        }
+        if config.offset + size > input_size {
+            bail!(
+                "{}: ends past the {} bytes of input data",
+                config.name,
+                input_size
+            );
+        }
+        Ok(Self {
+            name: config.name.clone(),
+            offset: config.offset,
+            tag_type,
+            bit: config.bit,
+        })
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<PlcValue> {
+        let data = data.get(self.offset..).unwrap_or_default();
+        match (self.bit, data.first()) {
+            (Some(bit), Some(byte)) => Ok(PlcValue::Bool(byte & (1 << bit) != 0)),
+            _ => PlcValue::decode(self.tag_type, data),
+        }
+    }
+}
+
+fn load_fields(path: &Path, input_size: usize) -> Result<Vec<Field>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read fields file {}", path.display()))?;
+    let list: FieldList = toml::from_str(&text)
+        .with_context(|| format!("failed to parse fields file {}", path.display()))?;
+    list.fields
+        .iter()
+        .map(|config| Field::new(config, input_size))
+        .collect::<Result<_>>()
+        .with_context(|| format!("invalid fields file {}", path.display()))
+}
+
+/// Add the encapsulation header to the data of a command.
+fn encapsulate(command: u16, session: u32, data: &[u8]) -> Vec<u8> {
+    let mut packet = BytesMut::with_capacity(24 + data.len());
+    packet.put_u16_le(command);
+    packet.put_u16_le(data.len() as u16);
+    packet.put_u32_le(session);
+    packet.put_u32_le(0);
+    packet.put_slice(&[0; 8]);
+    packet.put_u32_le(0);
+    packet.put_slice(data);
+    packet.to_vec()
+}
+
+/// Send a command, returning the session and data of the reply.
+async fn exchange(
+    stream: &mut TcpStream,
+    command: u16,
+    session: u32,
+    data: &[u8],
+) -> Result<(u32, Vec<u8>)> {
+    let request = async {
+        stream
+            .write_all(&encapsulate(command, session, data))
+            .await?;
+        let mut header = [0; 24];
+        stream.read_exact(&mut header).await?;
+        let length = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let mut reply = vec![0; length];
+        stream.read_exact(&mut reply).await?;
+        Ok::<_, std::io::Error>((header, reply))
+    };
+    let (header, reply) = tokio::time::timeout(TIMEOUT, request)
+        .await
+        .map_err(|_| anyhow!("no reply from the adapter"))??;
+    let status = u32::from_le_bytes(header[8..12].try_into()?);
+    if status != 0 {
+        bail!("encapsulation error {:#06x}", status);
+    }
+    Ok((u32::from_le_bytes(header[4..8].try_into()?), reply))
+}
+
+/// Items of a common packet format message.
+fn common_packet(mut data: &[u8]) -> Option<Vec<(u16, &[u8])>> {
+    let count = u16::from_le_bytes(data.get(..2)?.try_into().ok()?);
+    data = &data[2..];
+    let mut items = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let id = u16::from_le_bytes(data.get(..2)?.try_into().ok()?);
+        let length = u16::from_le_bytes(data.get(2..4)?.try_into().ok()?) as usize;
+        items.push((id, data.get(4..4 + length)?));
+        data = &data[4 + length..];
+    }
+    Some(items)
+}
+
+/// Data of the reply to an unconnected request, failing on an error status.
+fn reply_data(service: u8, rr_data: &[u8]) -> Result<Vec<u8>> {
+    let reply = rr_data
+        .get(6..)
+        .and_then(common_packet)
+        .and_then(|items| {
+            items
+                .into_iter()
+                .find(|(id, _)| *id == ITEM_UNCONNECTED)
+                .map(|(_, data)| data)
+        })
+        .ok_or_else(|| anyhow!("malformed reply"))?;
+    let [reply_service, _, status, extended_words, rest @ ..] = reply else {
+        bail!("malformed reply");
+    };
+    if *reply_service != service | 0x80 {
+        bail!(
+            "reply to service {:#04x}, not {:#04x}",
+            reply_service & 0x7F,
+            service
+        );
+    }
+    let extended = rest
+        .get(..*extended_words as usize * 2)
+        .ok_or_else(|| anyhow!("malformed reply"))?;
+    if *status != 0 {
+        let extended = extended
+            .get(..2)
+            .map(|code| u16::from_le_bytes([code[0], code[1]]));
+        bail!(forward_open_error(*status, extended));
+    }
+    Ok(rest[extended.len()..].to_vec())
+}
+
+/// Describe the usual reasons an adapter refuses a connection.
+fn forward_open_error(status: u8, extended: Option<u16>) -> String {
+    let reason = match (status, extended) {
+        (0x01, Some(0x0100)) => "the connection is already in use, another originator owns it",
+        (0x01, Some(0x0109)) => "the adapter rejects the connection size",
+        (0x01, Some(0x0111)) => "the adapter rejects the RPI",
+        (0x01, Some(0x0114)) => "the vendor id or product code doesn't match",
+        (0x01, Some(0x0116)) => "the revision doesn't match",
+        (0x01, Some(0x0117)) => "the connection point is invalid",
+        (0x01, Some(0x0119)) => "an input only connection needs an owning connection first",
+        (0x01, Some(0x0127)) => "the output size doesn't match the assembly",
+        (0x01, Some(0x0128)) => "the input size doesn't match the assembly",
+        (0x01, Some(0x012E | 0x012F)) => "the configuration doesn't match the assembly",
+        (0x01, Some(0x0315)) => "the assembly path is invalid",
+        (0x05, _) => "the adapter has no such assembly",
+        (0x08, _) => "the adapter doesn't take Forward Open requests",
+        _ => "",
+    };
+    let code = match extended {
+        Some(extended) => format!("status {:#04x}, extended status {:#06x}", status, extended),
+        None => format!("status {:#04x}", status),
+    };
+    match reason {
+        "" => format!("the adapter refused the connection ({})", code),
+        reason => format!("{} ({})", reason, code),
+    }
+}
+
+/// Identifiers of a connection, sent again to close it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Originator {
+    connection_serial: u16,
+    serial: u32,
+}
+
+impl Originator {
+    fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_nanos());
+        Self {
+            connection_serial: (nanos >> 8) as u16 | 1,
+            serial: std::process::id(),
+        }
+    }
+
+    fn put(&self, request: &mut BytesMut) {
+        request.put_u16_le(self.connection_serial);
+        request.put_u16_le(VENDOR_ID);
+        request.put_u32_le(self.serial);
+    }
+}
+
+fn forward_open_request(args: &IoArgs, originator: &Originator, t_o_id: u32) -> Result<Vec<u8>> {
+    let (o_t_size, t_o_size) = args.connection_sizes()?;
+    let rpi = args.rpi * 1000;
+    let mut request = BytesMut::new();
+    request.put_u8(SERVICE_FORWARD_OPEN);
+    request.put_u8(CONNECTION_MANAGER.len() as u8 / 2);
+    request.put_slice(&CONNECTION_MANAGER);
+    request.put_u8(PRIORITY_TICK_TIME);
+    request.put_u8(TIMEOUT_TICKS);
+    // The adapter picks the id of the outputs it consumes.
+    request.put_u32_le(0);
+    request.put_u32_le(t_o_id);
+    originator.put(&mut request);
+    request.put_u8(TIMEOUT_MULTIPLIER);
+    request.put_slice(&[0; 3]);
+    request.put_u32_le(rpi);
+    request.put_u16_le(CONNECTION_PARAMETERS | o_t_size);
+    request.put_u32_le(rpi);
+    request.put_u16_le(CONNECTION_PARAMETERS | t_o_size);
+    request.put_u8(TRANSPORT_CLASS_1);
+    let path = args.path();
+    request.put_u8(path.len() as u8 / 2);
+    request.put_slice(&path);
+    Ok(request.to_vec())
+}
+
+fn forward_close_request(args: &IoArgs, originator: &Originator) -> Vec<u8> {
+    let mut request = BytesMut::new();
+    request.put_u8(SERVICE_FORWARD_CLOSE);
+    request.put_u8(CONNECTION_MANAGER.len() as u8 / 2);
+    request.put_slice(&CONNECTION_MANAGER);
+    request.put_u8(PRIORITY_TICK_TIME);
+    request.put_u8(TIMEOUT_TICKS);
+    originator.put(&mut request);
+    let path = args.path();
+    request.put_u8(path.len() as u8 / 2);
+    request.put_u8(0);
+    request.put_slice(&path);
+    request.to_vec()
+}
+
+/// Unconnected request wrapped in SendRRData.
+fn rr_data(request: &[u8]) -> Vec<u8> {
+    let mut data = BytesMut::new();
+    data.put_u32_le(0);
+    data.put_u16_le(TIMEOUT.as_secs() as u16);
+    data.put_u16_le(2);
+    data.put_u16_le(ITEM_NULL);
+    data.put_u16_le(0);
+    data.put_u16_le(ITEM_UNCONNECTED);
+    data.put_u16_le(request.len() as u16);
+    data.put_slice(request);
+    data.to_vec()
+}
+
+/// Output packet: sequenced address and connected data, with the run/idle
+/// header when there is data.
+fn output_packet(o_t_id: u32, sequence: u32, run: bool, data: &[u8]) -> Vec<u8> {
+    let header = if data.is_empty() { 0 } else { 4 };
+    let mut packet = BytesMut::new();
+    packet.put_u16_le(2);
+    packet.put_u16_le(ITEM_SEQUENCED_ADDRESS);
+    packet.put_u16_le(8);
+    packet.put_u32_le(o_t_id);
+    packet.put_u32_le(sequence);
+    packet.put_u16_le(ITEM_CONNECTED);
+    packet.put_u16_le((2 + header + data.len()) as u16);
+    packet.put_u16_le(sequence as u16);
+    if !data.is_empty() {
+        packet.put_u32_le(if run { RUN } else { IDLE });
+    }
+    packet.put_slice(data);
+    packet.to_vec()
+}
+
+/// Input data of a packet of the connection, without its sequence count.
+fn input_data(packet: &[u8], t_o_id: u32) -> Option<&[u8]> {
+    let items = common_packet(packet)?;
+    let (_, address) = items.iter().find(|(id, _)| *id == ITEM_SEQUENCED_ADDRESS)?;
+    if u32::from_le_bytes(address.get(..4)?.try_into().ok()?) != t_o_id {
+        return None;
+    }
+    let (_, data) = items.iter().find(|(id, _)| *id == ITEM_CONNECTED)?;
+    data.get(2..)
+}
+
+/// An open Class 1 connection to an adapter.
+struct IoConnection {
+    stream: TcpStream,
+    session: u32,
+    originator: Originator,
+    o_t_id: u32,
+    t_o_id: u32,
+    /// Actual interval of the inputs.
+    t_o_interval: Duration,
+    peer: SocketAddr,
+    socket: UdpSocket,
+}
+
+impl IoConnection {
+    async fn open(address: &str, args: &IoArgs) -> Result<Self> {
+        let host = match address.contains(':') {
+            true => address.to_owned(),
+            false => format!("{}:{}", address, EXPLICIT_PORT),
+        };
+        let mut stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(&host))
+            .await
+            .map_err(|_| anyhow!("timed out"))
+            .and_then(|stream| Ok(stream?))
+            .with_context(|| format!("failed to connect to {}", host))?;
+        let peer = stream.peer_addr()?;
+        // Bound before the Forward Open, as the inputs start right after it.
+        let socket = UdpSocket::bind(("0.0.0.0", IO_PORT))
+            .await
+            .with_context(|| format!("failed to listen for I/O data on port {}", IO_PORT))?;
+
+        let (session, _) = exchange(&mut stream, CMD_REGISTER_SESSION, 0, &[1, 0, 0, 0]).await?;
+        let originator = Originator::new();
+        let t_o_id = (originator.serial << 16) | originator.connection_serial as u32;
+        let request = forward_open_request(args, &originator, t_o_id)?;
+        let (_, reply) =
+            exchange(&mut stream, CMD_SEND_RR_DATA, session, &rr_data(&request)).await?;
+        let reply = reply_data(SERVICE_FORWARD_OPEN, &reply)?;
+        if reply.len() < 26 {
+            bail!("malformed Forward Open reply");
+        }
+        let o_t_id = u32::from_le_bytes(reply[..4].try_into()?);
+        let t_o_id = u32::from_le_bytes(reply[4..8].try_into()?);
+        // Actual interval of the inputs, the requested one if not reported.
+        let t_o_api = match u32::from_le_bytes(reply[22..26].try_into()?) {
+            0 => args.rpi * 1000,
+            api => api,
+        };
+        tracing::info!(
+            "opened the I/O connection to {}, inputs every {} ms",
+            peer,
+            t_o_api as f64 / 1000.0
+        );
+        Ok(Self {
+            stream,
+            session,
+            originator,
+            o_t_id,
+            t_o_id,
+            t_o_interval: Duration::from_micros(t_o_api as u64),
+            peer,
+            socket,
+        })
+    }
+
+    /// Send the outputs every interval and handle the inputs, until asked
+    /// to stop or until the inputs stop coming.
+    async fn exchange_data(
+        &mut self,
+        args: &IoArgs,
+        output: &[u8],
+        inputs: &mut Inputs<'_>,
+    ) -> Result<()> {
+        let watchdog = self.t_o_interval * TIMEOUT_INTERVALS;
+        let mut ticker = tokio::time::interval(Duration::from_millis(args.rpi as u64));
+        let mut sequence: u32 = 0;
+        let mut last_input = Instant::now();
+        let mut buf = vec![0; 1500];
+        let target = SocketAddr::new(self.peer.ip(), IO_PORT);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    if last_input.elapsed() > watchdog.max(Duration::from_millis(100)) {
+                        bail!("no input data for {} ms", last_input.elapsed().as_millis());
+                    }
+                    sequence = sequence.wrapping_add(1);
+                    let packet = output_packet(self.o_t_id, sequence, args.run, output);
+                    self.socket.send_to(&packet, target).await?;
+                }
+                received = self.socket.recv_from(&mut buf) => {
+                    let (length, from) = received?;
+                    if from.ip() != self.peer.ip() {
+                        continue;
+                    }
+                    if let Some(data) = input_data(&buf[..length], self.t_o_id) {
+                        last_input = Instant::now();
+                        inputs.handle(data).await;
+                    }
+                }
+                _ = service::stopped() => return Ok(()),
+            }
+        }
+    }
+
+    async fn close(mut self, args: &IoArgs) -> Result<()> {
+        let request = forward_close_request(args, &self.originator);
+        let (_, reply) = exchange(
+            &mut self.stream,
+            CMD_SEND_RR_DATA,
+            self.session,
+            &rr_data(&request),
+        )
+        .await?;
+        reply_data(SERVICE_FORWARD_CLOSE, &reply)?;
+        let unregister = encapsulate(CMD_UNREGISTER_SESSION, self.session, &[]);
+        self.stream.write_all(&unregister).await?;
+        Ok(())
+    }
+}
+
+/// Shows the input data when it changes, or its fields, and writes the
+/// fields to the bridged controller.
+struct Inputs<'a> {
+    fields: &'a [Field],
+    bridge: Option<&'a mut Plc>,
+    last: Option<Vec<u8>>,
+    values: Vec<Option<PlcValue>>,
+}
+
+impl Inputs<'_> {
+    async fn handle(&mut self, data: &[u8]) {
+        if self.last.as_deref() == Some(data) {
+            return;
+        }
+        self.last = Some(data.to_vec());
+        let now = chrono::Local::now();
+        if self.fields.is_empty() {
+            print!("[{}]    {}", now, dump(data));
+            return;
+        }
+        for (field, last) in self.fields.iter().zip(&mut self.values) {
+            let value = match field.decode(data) {
+                Ok(value) => value,
+                Err(e) => {
+                    tracing::warn!("failed to decode {}: {:#}", field.name, e);
+                    continue;
+                }
+            };
+            if last.as_ref() == Some(&value) {
+                continue;
+            }
+            *last = Some(value.clone());
+            println!(
+                "[{}]    {}    {}",
+                now,
+                field.name.bold(),
+                value.to_string().bold().green()
+            );
+            if let (Some(client), false) = (self.bridge.as_deref_mut(), plc::dry_run()) {
+                if let Err(e) = value::write_unaudited(client, &field.name, value).await {
+                    tracing::warn!("failed to write {}: {:#}", field.name, e);
+                }
+            }
+        }
+    }
+}
+
+/// Open a Class 1 connection to the adapter at the address and show its
+/// input data, or bridge it to a controller, until stopped. A lost
+/// connection is opened again.
+pub async fn run(address: &str, args: &IoArgs, connection: &ConnectionArgs) -> Result<()> {
+    let output = args.output()?;
+    args.connection_sizes()?;
+    let fields = match &args.fields {
+        Some(path) => load_fields(path, args.input_size as usize)?,
+        None => Vec::new(),
+    };
+    let mut bridge = match &args.bridge_to {
+        Some(address) => Some(plc::connect(address, connection).await?),
+        None => None,
+    };
+    let mut inputs = Inputs {
+        fields: &fields,
+        bridge: bridge.as_mut(),
+        last: None,
+        values: vec![None; fields.len()],
+    };
+
+    let mut opened = Some(IoConnection::open(address, args).await?);
+    while let Some(mut connection) = opened.take() {
+        match connection.exchange_data(args, &output, &mut inputs).await {
+            Ok(()) => {
+                if let Err(e) = connection.close(args).await {
+                    tracing::warn!("failed to close the I/O connection: {:#}", e);
+                }
+                break;
+            }
+            Err(e) => tracing::warn!("lost the I/O connection to {}: {:#}", address, e),
+        }
+        drop(connection);
+        opened = loop {
+            tokio::select! {
+                _ = tokio::time::sleep(RETRY) => {}
+                _ = service::stopped() => break None,
+            }
+            match IoConnection::open(address, args).await {
+                Ok(connection) => break Some(connection),
+                Err(e) => tracing::warn!("failed to open the I/O connection again: {:#}", e),
+            }
+        };
+    }
+    drop(inputs);
+    if let Some(mut client) = bridge {
+        client.close().await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        io: IoArgs,
+    }
+
+    fn args(line: &str) -> IoArgs {
+        let line = format!("io {}", line);
+        Cli::parse_from(line.split_whitespace()).io
+    }
+
+    #[test]
+    fn forward_open() {
+        let args = args(
+            "--input-instance 101 --input-size 32 --output-instance 300 --output-size 8 --rpi 20",
+        );
+        assert_eq!(
+            args.path(),
+            [0x20, 0x04, 0x24, 0x01, 0x2D, 0x00, 0x2C, 0x01, 0x2C, 0x65]
+        );
+        let originator = Originator {
+            connection_serial: 0x1234,
+            serial: 0xC0FFEE,
+        };
+        let request = forward_open_request(&args, &originator, 0xAABBCCDD).unwrap();
+        assert_eq!(request[..8], [0x54, 2, 0x20, 0x06, 0x24, 0x01, 0x0A, 0x05]);
+        assert_eq!(request[12..16], 0xAABBCCDDu32.to_le_bytes());
+        assert_eq!(request[16..18], [0x34, 0x12]);
+        // RPI in microseconds and sizes with the sequence count, and the
+        // run/idle header of the outputs.
+        assert_eq!(request[28..32], 20_000u32.to_le_bytes());
+        assert_eq!(request[32..34], (0x4800u16 | 14).to_le_bytes());
+        assert_eq!(request[38..40], (0x4800u16 | 34).to_le_bytes());
+        assert_eq!(request[40..42], [0x01, 5]);
+
+        let close = forward_close_request(&args, &originator);
+        assert_eq!(close[..8], [0x4E, 2, 0x20, 0x06, 0x24, 0x01, 0x0A, 0x05]);
+        assert_eq!(close[16..18], [5, 0]);
+
+        let args = self::args("--input-instance 1 --input-size 600 --output-instance 2");
+        assert!(args.connection_sizes().is_err());
+    }
+
+    #[test]
+    fn replies() {
+        let rr = |reply: &[u8]| {
+            let mut rr = vec![0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0xB2, 0];
+            rr.extend_from_slice(&(reply.len() as u16).to_le_bytes());
+            rr.extend_from_slice(reply);
+            rr
+        };
+        let refused = rr(&[0xD4, 0, 0x01, 1, 0x27, 0x01]);
+        let error = reply_data(SERVICE_FORWARD_OPEN, &refused)
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            "the output size doesn't match the assembly (status 0x01, extended status 0x0127)"
+        );
+        let opened = rr(&[0xD4, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(
+            reply_data(SERVICE_FORWARD_OPEN, &opened).unwrap(),
+            [1, 2, 3]
+        );
+        assert!(reply_data(SERVICE_FORWARD_CLOSE, &opened).is_err());
+        assert!(reply_data(SERVICE_FORWARD_OPEN, &opened[..10]).is_err());
+    }
+
+    #[test]
+    fn packets() {
+        let packet = output_packet(0x11223344, 7, true, &[0xAA, 0xBB]);
+        assert_eq!(
+            packet,
+            [
+                2, 0, 0x02, 0x80, 8, 0, 0x44, 0x33, 0x22, 0x11, 7, 0, 0, 0, 0xB1, 0, 8, 0, 7, 0, 1,
+                0, 0, 0, 0xAA, 0xBB
+            ]
+        );
+        assert_eq!(output_packet(1, 1, true, &[]).len(), 20);
+
+        let input = [
+            2, 0, 0x02, 0x80, 8, 0, 0x78, 0x56, 0x34, 0x12, 1, 0, 0, 0, 0xB1, 0, 6, 0, 1, 0, 0x00,
+            0x00, 0x20, 0x41,
+        ];
+        assert_eq!(
+            input_data(&input, 0x12345678),
+            Some(&[0, 0, 0x20, 0x41][..])
+        );
+        assert_eq!(input_data(&input, 1), None);
+        assert_eq!(input_data(&input[..20], 0x12345678), None);
+
+        let real = FieldConfig {
+            name: "Speed".to_owned(),
+            offset: 0,
+            field_type: "real".to_owned(),
+            bit: None,
+        };
+        let field = Field::new(&real, 4).unwrap();
+        assert_eq!(field.decode(&input[20..]).unwrap(), PlcValue::Real(10.0));
+        let bit = FieldConfig {
+            name: "Running".to_owned(),
+            offset: 3,
+            field_type: "BOOL".to_owned(),
+            bit: Some(6),
+        };
+        assert_eq!(
+            Field::new(&bit, 4).unwrap().decode(&input[20..]).unwrap(),
+            PlcValue::Bool(true)
+        );
+        assert!(Field::new(&real, 3).is_err());
+        assert!(Field::new(
+            &FieldConfig {
+                bit: Some(1),
+                ..real
+            },
+            4
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::monitor::Sample;
+use crate::value::PlcValue;
+use anyhow::Result;
+use clap::Args;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Points kept in memory while InfluxDB is unreachable; the oldest are
+/// dropped beyond this.
+const MAX_PENDING: usize = 100_000;
+
+/// Longest time a point waits before being written.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest wait between two retries of a failed write.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// InfluxDB output options shared by the polling commands.
+#[derive(Args, Debug, Clone)]
+pub struct InfluxArgs {
+    /// InfluxDB v2 base URL to push samples to, e.g. http://localhost:8086.
+    #[arg(long, requires = "influx_bucket")]
+    pub influx_url: Option<String>,
+    /// InfluxDB bucket.
+    #[arg(long)]
+    pub influx_bucket: Option<String>,
+    /// InfluxDB organization.
+    #[arg(long, default_value = "")]
+    pub influx_org: String,
+    /// InfluxDB API token.
+    #[arg(long, env = "INFLUX_TOKEN", hide_env_values = true)]
+    pub influx_token: Option<String>,
+    /// Measurement name of the points.
+    #[arg(long, default_value = "cobalt")]
+    pub influx_measurement: String,
+    /// Maximum number of points per write request.
+    #[arg(long, default_value_t = 5000)]
+    pub influx_batch: usize,
+}
+
+/// Pushes samples to InfluxDB from a background task, batching points and
+/// retrying failed writes so a slow or unreachable database never stalls
+/// the polling loop.
+pub struct InfluxSink {
+    measurement: String,
+    /// Tag set of the points, naming the controller of a multi-target run.
+    tags: String,
+    tx: mpsc::UnboundedSender<String>,
+}
+
+impl InfluxSink {
+    /// Start the writer task. Returns `None` when InfluxDB output is not
+    /// enabled. Points of a `target` controller carry it as the `plc` tag.
+    pub fn connect(args: &InfluxArgs, target: Option<&str>) -> Result<Option<Self>> {
+        let (url, bucket) = match (&args.influx_url, &args.influx_bucket) {
+            (Some(url), Some(bucket)) => (url, bucket),
+            _ => return Ok(None),
+        };
+        let mut url = reqwest::Url::parse(url)?.join("api/v2/write")?;
+        url.query_pairs_mut()
+            .append_pair("org", &args.influx_org)
+            .append_pair("bucket", bucket)
+            .append_pair("precision", "ns");
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let writer = Writer {
+            client: reqwest::Client::new(),
+            url,
+            token: args.influx_token.clone(),
+            batch: args.influx_batch.max(1),
+        };
+        tokio::spawn(writer.run(rx));
+
+        Ok(Some(Self {
+            measurement: escape(&args.influx_measurement, &[',', ' ']),
+            tags: target
+                .map(|target| format!(",plc={}", escape(target, &[',', '=', ' '])))
+                .unwrap_or_default(),
+            tx,
+        }))
+    }
+
+    /// Queue the samples of a polling cycle.
+    pub fn push(&self, samples: &[Sample]) {
+        for sample in samples {
+            let line = line(&self.measurement, &self.tags, sample);
+            // The writer only stops when the sink is dropped.
+            let _ = self.tx.send(line);
+        }
+    }
+}
+
+/// Format a sample as a line protocol point.
+fn line(measurement: &str, tags: &str, sample: &Sample) -> String {
+    let value = match &sample.value {
+        PlcValue::Bool(v) => v.to_string(),
+        PlcValue::Real(v) => v.to_string(),
+        PlcValue::Lreal(v) => v.to_string(),
+        PlcValue::Timer(_) | PlcValue::Counter(_) | PlcValue::Control(_) => {
+            format!("{}i", sample.value.as_f64())
+        }
+        PlcValue::String(v) => format!("\"{}\"", escape(&v.text, &['"'])),
+        v => format!("{}i", v),
+    };
+    format!(
+        "{}{},tag={} value={} {}",
+        measurement,
+        tags,
+        escape(&sample.tag, &[',', '=', ' ']),
+        value,
+        sample.timestamp.timestamp_nanos_opt().unwrap_or_default()
+    )
+}
+
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+struct Writer {
+    client: reqwest::Client,
+    url: reqwest::Url,
+    token: Option<String>,
+    batch: usize,
+}
+
+impl Writer {
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<String>) {
+        let mut pending = VecDeque::new();
+        let mut last_flush = Instant::now();
+        let mut backoff = Duration::ZERO;
+        let mut retry_at = Instant::now();
+        let mut closed = false;
+
+        while !closed || !pending.is_empty() {
+            match tokio::time::timeout(FLUSH_INTERVAL, rx.recv()).await {
+                Ok(Some(line)) => pending.push_back(line),
+                Ok(None) => closed = true,
+                Err(_) => {}
+            }
+            while let Ok(line) = rx.try_recv() {
+                pending.push_back(line);
+            }
+            if pending.len() > MAX_PENDING {
+                let dropped = pending.len() - MAX_PENDING;
+                pending.drain(..dropped);
+                tracing::warn!("InfluxDB unreachable, dropped {} points", dropped);
+            }
+
+            let due =
+                closed || pending.len() >= self.batch || last_flush.elapsed() >= FLUSH_INTERVAL;
+            if pending.is_empty() || !due || Instant::now() < retry_at {
+                continue;
+            }
+
+            let count = pending.len().min(self.batch);
+            let body = pending
+                .iter()
+                .take(count)
+                .cloned()
+                .collect::<Vec<_>>()
+                .join("\n");
+            match self.write(body).await {
+                Ok(()) => {
+                    pending.drain(..count);
+                    backoff = Duration::ZERO;
+                    last_flush = Instant::now();
+                }
+                Err(Failure::Rejected(e)) => {
+                    tracing::error!("InfluxDB rejected {} points: {}", count, e);
+                    pending.drain(..count);
+                }
+                Err(Failure::Retry(e)) => {
+                    backoff = (backoff * 2).clamp(Duration::from_secs(1), MAX_BACKOFF);
+                    retry_at = Instant::now() + backoff;
+                    tracing::warn!("InfluxDB write failed, retrying in {:?}: {}", backoff, e);
+                    if closed {
+                        // Nobody is left to wait for the retry.
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn write(&self, body: String) -> Result<(), Failure> {
+        let mut request = self.client.post(self.url.clone()).body(body);
+        if let Some(token) = &self.token {
+            request = request.header("Authorization", format!("Token {}", token));
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| Failure::Retry(e.to_string()))?;
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let text = response.text().await.unwrap_or_default();
+        let message = format!("{} {}", status, text.trim());
+        if status.is_client_error() && status.as_u16() != 429 {
+            Err(Failure::Rejected(message))
+        } else {
+            Err(Failure::Retry(message))
+        }
+    }
+}
+
+enum Failure {
+    /// The points are invalid, writing them again won't help.
+    Rejected(String),
+    /// Transient failure, the points are written again later.
+    Retry(String),
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::monitor::Quality;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn line_protocol() {
+        let sample = Sample {
+            timestamp: Local.timestamp_opt(1, 5).unwrap(),
+            tag: "Program:Main.Flow Rate".to_owned(),
+            value: PlcValue::Dint(-3),
+            quality: Quality::Good,
+        };
+        assert_eq!(
+            line("cobalt", "", &sample),
+            "cobalt,tag=Program:Main.Flow\\ Rate value=-3i 1000000005"
+        );
+        assert_eq!(
+            line("cobalt", ",plc=line1", &sample),
+            "cobalt,plc=line1,tag=Program:Main.Flow\\ Rate value=-3i 1000000005"
+        );
+    }
+}
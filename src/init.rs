@@ -0,0 +1,247 @@
+use crate::tags::TagList;
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+/// Kind of deployment a starter configuration is generated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Template {
+    /// Modbus RTU flow meter bridge settings, for `bridge-write --config`.
+    Bridge,
+    /// Tags polled and logged by `monitor`.
+    Logger,
+    /// Tags served to other systems by `exporter` and `monitor --mqtt`.
+    Gateway,
+}
+
+/// Options of the `init` command.
+#[derive(Args)]
+pub struct InitArgs {
+    pub template: Template,
+    /// Tags to prefill the configuration with: a tag list TOML file, or a
+    /// text file with one tag per line such as the output of `cobalt list`.
+    #[arg(long)]
+    pub tags: Option<PathBuf>,
+    /// File to write, cobalt-<template>.toml by default.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Overwrite the file if it already exists.
+    #[arg(long)]
+    pub force: bool,
+}
+
+pub fn run(args: &InitArgs) -> Result<()> {
+    let output = match &args.output {
+        Some(output) => output.clone(),
+        None => {
+            let name = args.template.to_possible_value().unwrap();
+            PathBuf::from(format!("cobalt-{}.toml", name.get_name()))
+        }
+    };
+    if output.exists() && !args.force {
+        bail!(
+            "{} already exists, pass --force to overwrite it",
+            output.display()
+        );
+    }
+    let tags = match &args.tags {
+        Some(path) => read_tag_names(path)?,
+        None => Vec::new(),
+    };
+
+    let text = render(args.template, &tags, &output.display().to_string());
+    std::fs::write(&output, text)
+        .with_context(|| format!("failed to write {}", output.display()))?;
+    println!("Wrote {}.", output.display());
+    Ok(())
+}
+
+/// Tag names from a tag list file or a plain text list.
+fn read_tag_names(path: &Path) -> Result<Vec<String>> {
+    if path.extension().map(|ext| ext == "toml").unwrap_or(false) {
+        let list = TagList::load(path)?;
+        return Ok(list.tags.into_iter().map(|tag| tag.name).collect());
+    }
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read tag list {}", path.display()))?;
+    Ok(text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_whitespace().next())
+        .map(str::to_owned)
+        .collect())
+}
+
+fn quote(s: &str) -> String {
+    toml::Value::String(s.to_owned()).to_string()
+}
+
+fn render(template: Template, tags: &[String], output: &str) -> String {
+    let mut text = String::new();
+    match template {
+        Template::Bridge => {
+            let _ = write!(
+                text,
+                r#"# Modbus RTU flow meter bridge.
+#
+# Run:    cobalt -a <PLC address> bridge-write --config {output}
+# Check:  cobalt config check --bridge {output}
+
+# Serial port of the meter, e.g. COM3 or /dev/ttyUSB0.
+port = "/dev/ttyUSB0"
+# Modbus slave id of the meter, 1 to 247.
+slave = 1
+baudrate = 9600
+# Framing of the line, 8N1 without flow control by default. Parity is none,
+# odd or even, flow control none, software or hardware.
+# parity = "even"
+# data_bits = 8
+# stop_bits = 1
+# flow_control = "none"
+
+# Holding registers of the gas velocity (m/s) and of the meter rate. Each
+# value is a 32-bit float spanning two registers.
+rtu_register_velocity = 0
+rtu_register_rate = 2
+
+# Inner pipe diameter, in inches.
+diameter = 4.0
+
+# REAL tags read from the controller, in barg and degC.
+pressure_tag = "PT_101"
+temperature_tag = "TT_101"
+
+# Pressure of an absolute transmitter, in bara, and the atmospheric
+# pressure of the site, in bar.
+# pressure_mode = "absolute"
+# atmospheric = 1.01325
+
+# REAL tags written by the bridge: the meter rate and the rate at base
+# conditions, in Sm3/d.
+rate_tag = "FT_101_Rate"
+rate_tag_base = "FT_101_RateBase"
+
+# Conversion of register values in device units to engineering units:
+# value = raw * scale + offset, clamped to min and max when given.
+# [scaling.velocity]
+# scale = 1.0
+# offset = 0.0
+# min = 0.0
+# max = 40.0
+
+# Expression of the rate at base conditions, over velocity, rate, pressure,
+# temperature and diameter.
+# rate_base = "aga8_rate(velocity, diameter, pressure, temperature)"
+
+# Further REAL tags written each cycle. Their expressions can also use
+# rate_base and the outputs above them, named by their tags.
+# [[output]]
+# tag = "FT_101_Energy"
+# expr = "rate_base * 0.0381"
+
+# When the meter doesn't answer within the timeout: abort the bridge, hold
+# the last values in the controller, or write the fallback value.
+# [stale]
+# action = "hold"
+# fallback = 0.0
+# timeout = "1s"
+
+# BOOL tags set false while a written value is stale, keyed by its tag.
+# [quality]
+# FT_101_Rate = "FT_101_Rate_OK"
+# FT_101_RateBase = "FT_101_RateBase_OK"
+
+# Analysis of the gas, in mole fractions or percent, each component fixed
+# or read from a REAL tag every interval, e.g. written by a chromatograph.
+# [composition]
+# interval = "5m"
+# [composition.components]
+# methane = "AT_101_C1"
+# ethane = "AT_101_C2"
+# nitrogen = 1.2
+
+# Further meters on the same RS-485 line, polled in turn after the one
+# above. Each takes the same keys as the top level but port, baudrate and
+# stale.
+# [[meter]]
+# slave = 2
+# rtu_register_velocity = 0
+# rtu_register_rate = 2
+# diameter = 6.0
+# pressure_tag = "PT_102"
+# temperature_tag = "TT_102"
+# rate_tag = "FT_102_Rate"
+# rate_tag_base = "FT_102_RateBase"
+"#
+            );
+            if !tags.is_empty() {
+                text.push_str("\n# Controller tags:\n");
+                for tag in tags {
+                    let _ = writeln!(text, "#   {}", tag);
+                }
+            }
+        }
+        Template::Logger => {
+            let _ = write!(
+                text,
+                r#"# Tags polled by the monitor.
+#
+# Run:    cobalt -a <PLC address> monitor --tags {output} --interval 1000
+#         add --influx-url/--influx-bucket or --mqtt to store the samples
+# Check:  cobalt config check --tags {output}
+#
+# Alarm rules go in a separate file passed with --alarms:
+#
+#   [[alarm]]
+#   name = "HighPressure"
+#   condition = "PT_101 > 80"
+#   message = "Line pressure high"
+"#
+            );
+            push_tags(&mut text, tags, "# labels = { unit = \"barg\" }");
+        }
+        Template::Gateway => {
+            let _ = write!(
+                text,
+                r#"# Tags served to other systems.
+#
+# Prometheus:  cobalt -a <PLC address> exporter --tags {output}
+# MQTT:        cobalt -a <PLC address> monitor --tags {output} --mqtt <broker>
+# Check:       cobalt config check --tags {output}
+#
+# Labels are added to the Prometheus series of the tag.
+"#
+            );
+            push_tags(&mut text, tags, "labels = { area = \"\", unit = \"\" }");
+        }
+    }
+    text
+}
+
+fn push_tags(text: &mut String, tags: &[String], labels: &str) {
+    let placeholders = ["PT_101".to_owned(), "TT_101".to_owned()];
+    let tags = if tags.is_empty() { &placeholders } else { tags };
+    for tag in tags {
+        let _ = write!(text, "\n[[tag]]\nname = {}\n{}\n", quote(tag), labels);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bridge::BridgeConfig;
+
+    #[test]
+    fn templates_load() {
+        let tags = ["Program:Main.Flow".to_owned(), "PT \"101\"".to_owned()];
+        let bridge = render(Template::Bridge, &tags, "bridge.toml");
+        toml::from_str::<BridgeConfig>(&bridge).unwrap();
+        for template in [Template::Logger, Template::Gateway] {
+            let list: TagList = toml::from_str(&render(template, &tags, "tags.toml")).unwrap();
+            assert_eq!(list.tags.len(), 2);
+            assert_eq!(list.tags[1].name, "PT \"101\"");
+        }
+    }
+}
@@ -0,0 +1,696 @@
+use crate::monitor::{Quality, Sample};
+use crate::value::PlcValue;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use clap::Args;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// Messages kept in memory while the brokers are unreachable; the oldest
+/// are dropped beyond this.
+const MAX_PENDING: usize = 100_000;
+
+/// Longest wait between two retries of a failed produce.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Time a broker has to answer a request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Time the leaders have to replicate a batch before the produce fails.
+const ACK_TIMEOUT_MS: i32 = 10_000;
+
+/// Largest response accepted from a broker.
+const MAX_RESPONSE: usize = 64 * 1024 * 1024;
+
+const API_PRODUCE: i16 = 0;
+const API_METADATA: i16 = 3;
+
+/// Kafka output options of `monitor`.
+#[derive(Args, Debug, Clone)]
+pub struct KafkaArgs {
+    /// Kafka brokers to publish tag changes to, as comma separated
+    /// host:port. The rest of the cluster is discovered from them.
+    #[arg(long, value_delimiter = ',')]
+    pub kafka: Vec<String>,
+    /// Topic of the tag change messages.
+    #[arg(long, default_value = "cobalt")]
+    pub kafka_topic: String,
+    /// Client id the brokers see in their logs and quotas.
+    #[arg(long, default_value = "cobalt")]
+    pub kafka_client_id: String,
+    /// Maximum number of messages per produce request.
+    #[arg(long, default_value_t = 1000)]
+    pub kafka_batch: usize,
+}
+
+/// Publishes tag changes to a Kafka topic from a background task. Messages
+/// are keyed by tag, so the changes of a tag stay in order on one
+/// partition, and carry the sample as JSON.
+pub struct KafkaSink {
+    plc: Option<String>,
+    /// Last value and quality published for each tag.
+    last: HashMap<String, (PlcValue, Quality)>,
+    tx: mpsc::UnboundedSender<Message>,
+}
+
+impl KafkaSink {
+    /// Start the producer task. Returns `None` when Kafka output is not
+    /// enabled. Messages of a `target` controller carry it as `plc` and
+    /// in their key.
+    pub fn connect(args: &KafkaArgs, target: Option<&str>) -> Result<Option<Self>> {
+        if args.kafka.is_empty() {
+            return Ok(None);
+        }
+        for broker in &args.kafka {
+            match broker.rsplit_once(':') {
+                Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => {}
+                _ => bail!("invalid Kafka broker `{}`, expected host:port", broker),
+            }
+        }
+        if args.kafka_topic.is_empty() {
+            bail!("the Kafka topic can't be empty");
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let producer = Producer {
+            bootstrap: args.kafka.clone(),
+            topic: args.kafka_topic.clone(),
+            client_id: args.kafka_client_id.clone(),
+            batch: args.kafka_batch.max(1),
+            correlation_id: 0,
+            leaders: Vec::new(),
+            brokers: HashMap::new(),
+            connections: HashMap::new(),
+        };
+        tokio::spawn(producer.run(rx));
+
+        Ok(Some(Self {
+            plc: target.map(str::to_owned),
+            last: HashMap::new(),
+            tx,
+        }))
+    }
+
+    /// Queue the samples of a polling cycle that changed since they were
+    /// last published.
+    pub fn push(&mut self, samples: &[Sample]) {
+        for sample in samples {
+            let state = (sample.value.clone(), sample.quality);
+            if self.last.get(&sample.tag) == Some(&state) {
+                continue;
+            }
+            self.last.insert(sample.tag.clone(), state);
+            // The producer only stops when the sink is dropped.
+            let _ = self.tx.send(message(self.plc.as_deref(), sample));
+        }
+    }
+}
+
+/// A record to produce.
+#[derive(Debug, Clone)]
+struct Message {
+    key: String,
+    value: String,
+    /// Milliseconds since the epoch.
+    timestamp: i64,
+}
+
+/// The tag change message of a sample.
+fn message(plc: Option<&str>, sample: &Sample) -> Message {
+    let mut value = serde_json::json!({
+        "timestamp": sample.timestamp.to_rfc3339(),
+        "tag": sample.tag,
+        "type": sample.value.type_name(),
+        "value": serde_json::Value::from(sample.value.clone()),
+        "quality": sample.quality,
+    });
+    let key = match plc {
+        Some(plc) => {
+            value["plc"] = plc.into();
+            format!("{}/{}", plc, sample.tag)
+        }
+        None => sample.tag.clone(),
+    };
+    Message {
+        key,
+        value: value.to_string(),
+        timestamp: sample.timestamp.timestamp_millis(),
+    }
+}
+
+/// Just enough of the Kafka protocol to produce: Metadata v4 to find the
+/// partition leaders, then Produce v3 with record batches to each of them.
+struct Producer {
+    bootstrap: Vec<String>,
+    topic: String,
+    client_id: String,
+    batch: usize,
+    correlation_id: i32,
+    /// Leader of each partition of the topic, by partition index.
+    leaders: Vec<i32>,
+    /// Address of each broker, by node id.
+    brokers: HashMap<i32, String>,
+    /// Open connections by node id, the bootstrap broker being -1.
+    connections: HashMap<i32, TcpStream>,
+}
+
+impl Producer {
+    async fn run(mut self, mut rx: mpsc::UnboundedReceiver<Message>) {
+        let mut pending = VecDeque::new();
+        let mut backoff = Duration::ZERO;
+        let mut retry_at = Instant::now();
+        let mut closed = false;
+
+        while !closed || !pending.is_empty() {
+            if !closed && (pending.is_empty() || Instant::now() < retry_at) {
+                // Wait for changes, or for the next retry of the pending ones.
+                let received = if pending.is_empty() {
+                    Ok(rx.recv().await)
+                } else {
+                    tokio::time::timeout_at(retry_at.into(), rx.recv()).await
+                };
+                match received {
+                    Ok(Some(message)) => pending.push_back(message),
+                    Ok(None) => closed = true,
+                    Err(_) => {}
+                }
+            }
+            while let Ok(message) = rx.try_recv() {
+                pending.push_back(message);
+            }
+            if pending.len() > MAX_PENDING {
+                let dropped = pending.len() - MAX_PENDING;
+                pending.drain(..dropped);
+                tracing::warn!("Kafka unreachable, dropped {} messages", dropped);
+            }
+            if pending.is_empty() || Instant::now() < retry_at {
+                continue;
+            }
+
+            let count = pending.len().min(self.batch);
+            let batch: Vec<&Message> = pending.iter().take(count).collect();
+            match self.produce(&batch).await {
+                Ok(()) => {
+                    pending.drain(..count);
+                    backoff = Duration::ZERO;
+                }
+                Err(Failure::Rejected(e)) => {
+                    tracing::error!("Kafka rejected {} messages: {}", count, e);
+                    pending.drain(..count);
+                }
+                Err(Failure::Retry(e)) => {
+                    // Leaders move and brokers restart, start over from the
+                    // bootstrap brokers.
+                    self.leaders.clear();
+                    self.connections.clear();
+                    backoff = (backoff * 2).clamp(Duration::from_secs(1), MAX_BACKOFF);
+                    retry_at = Instant::now() + backoff;
+                    tracing::warn!("Kafka produce failed, retrying in {:?}: {}", backoff, e);
+                    if closed {
+                        // Nobody is left to wait for the retry.
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Produce the messages to the leaders of their partitions. A failure
+    /// after some leaders took their share produces those again, so
+    /// consumers may see a change twice but never miss one.
+    async fn produce(&mut self, messages: &[&Message]) -> Result<(), Failure> {
+        if self.leaders.is_empty() {
+            self.refresh_metadata().await.map_err(Failure::retry)?;
+        }
+
+        let mut partitions: BTreeMap<usize, Vec<&Message>> = BTreeMap::new();
+        for &message in messages {
+            let partition = partition(message.key.as_bytes(), self.leaders.len());
+            partitions.entry(partition).or_default().push(message);
+        }
+        let mut by_leader: BTreeMap<i32, Vec<(i32, BytesMut)>> = BTreeMap::new();
+        for (partition, messages) in partitions {
+            let leader = self.leaders[partition];
+            if leader < 0 {
+                return Err(Failure::Retry(format!(
+                    "partition {} of {} has no leader",
+                    partition, self.topic
+                )));
+            }
+            by_leader
+                .entry(leader)
+                .or_default()
+                .push((partition as i32, record_batch(&messages)));
+        }
+
+        for (leader, batches) in by_leader {
+            let body = produce_request(&self.topic, &batches);
+            let response = self
+                .request(leader, API_PRODUCE, 3, &body)
+                .await
+                .map_err(Failure::retry)?;
+            for (partition, code) in produce_errors(response).map_err(Failure::retry)? {
+                if code == 0 {
+                    continue;
+                }
+                let message = format!(
+                    "partition {} of {}: {}",
+                    partition,
+                    self.topic,
+                    error_name(code)
+                );
+                // Invalid records fail again however often they are sent.
+                return Err(if matches!(code, 2 | 10 | 17 | 18 | 87) {
+                    Failure::Rejected(message)
+                } else {
+                    Failure::Retry(message)
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Look up the brokers and the partition leaders of the topic, creating
+    /// it when the cluster allows.
+    async fn refresh_metadata(&mut self) -> Result<()> {
+        let mut body = BytesMut::new();
+        body.put_i32(1);
+        put_string(&mut body, &self.topic);
+        // allow_auto_topic_creation
+        body.put_i8(1);
+        let mut response = Reader(self.request(-1, API_METADATA, 4, &body).await?);
+
+        let _throttle_time = response.i32()?;
+        let mut brokers = HashMap::new();
+        for _ in 0..response.array_len()? {
+            let node = response.i32()?;
+            let host = response.string()?.unwrap_or_default();
+            let port = response.i32()?;
+            let _rack = response.string()?;
+            brokers.insert(node, format!("{}:{}", host, port));
+        }
+        let _cluster_id = response.string()?;
+        let _controller = response.i32()?;
+
+        let mut leaders = Vec::new();
+        for _ in 0..response.array_len()? {
+            let code = response.i16()?;
+            let name = response.string()?.unwrap_or_default();
+            let _internal = response.i8()?;
+            let mut topic_leaders = Vec::new();
+            for _ in 0..response.array_len()? {
+                let _code = response.i16()?;
+                let index = response.i32()?;
+                let leader = response.i32()?;
+                for _ in 0..2 {
+                    // replica and in-sync replica nodes
+                    for _ in 0..response.array_len()? {
+                        response.i32()?;
+                    }
+                }
+                let index = usize::try_from(index).context("negative partition index")?;
+                if topic_leaders.len() <= index {
+                    topic_leaders.resize(index + 1, -1);
+                }
+                topic_leaders[index] = leader;
+            }
+            if name != self.topic {
+                continue;
+            }
+            if code != 0 {
+                bail!("topic {}: {}", name, error_name(code));
+            }
+            leaders = topic_leaders;
+        }
+        if leaders.is_empty() {
+            bail!("topic {} has no partitions", self.topic);
+        }
+        self.brokers = brokers;
+        self.leaders = leaders;
+        Ok(())
+    }
+
+    /// Send a request to a broker and return the body of its response.
+    async fn request(
+        &mut self,
+        node: i32,
+        api_key: i16,
+        version: i16,
+        body: &[u8],
+    ) -> Result<Bytes> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let correlation_id = self.correlation_id;
+        let mut frame = BytesMut::new();
+        frame.put_i32(0);
+        frame.put_i16(api_key);
+        frame.put_i16(version);
+        frame.put_i32(correlation_id);
+        put_string(&mut frame, &self.client_id);
+        frame.put_slice(body);
+        let size = (frame.len() - 4) as i32;
+        frame[..4].copy_from_slice(&size.to_be_bytes());
+
+        let stream = self.connection(node).await?;
+        let exchange = async {
+            stream.write_all(&frame).await?;
+            let size = stream.read_i32().await?;
+            let size = usize::try_from(size)
+                .ok()
+                .filter(|size| (4..=MAX_RESPONSE).contains(size))
+                .ok_or_else(|| anyhow!("invalid response size {}", size))?;
+            let mut response = vec![0; size];
+            stream.read_exact(&mut response).await?;
+            anyhow::Ok(response)
+        };
+        let response = match tokio::time::timeout(REQUEST_TIMEOUT, exchange).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(e)) => {
+                self.connections.remove(&node);
+                return Err(e);
+            }
+            Err(_) => {
+                self.connections.remove(&node);
+                bail!("no response within {:?}", REQUEST_TIMEOUT);
+            }
+        };
+        let mut response = Reader(Bytes::from(response));
+        if response.i32()? != correlation_id {
+            self.connections.remove(&node);
+            bail!("response to another request");
+        }
+        Ok(response.0)
+    }
+
+    /// Connection to a broker, opened on first use. Node -1 is the first
+    /// bootstrap broker that answers.
+    async fn connection(&mut self, node: i32) -> Result<&mut TcpStream> {
+        if !self.connections.contains_key(&node) {
+            let addresses = if node < 0 {
+                self.bootstrap.clone()
+            } else {
+                let address = self
+                    .brokers
+                    .get(&node)
+                    .ok_or_else(|| anyhow!("unknown broker {}", node))?;
+                vec![address.clone()]
+            };
+            let mut error = anyhow!("no Kafka broker");
+            for address in addresses {
+                let connect = tokio::time::timeout(REQUEST_TIMEOUT, TcpStream::connect(&address));
+                match connect.await {
+                    Ok(Ok(stream)) => {
+                        stream.set_nodelay(true)?;
+                        self.connections.insert(node, stream);
+                        break;
+                    }
+                    Ok(Err(e)) => error = anyhow!("failed to connect to {}: {}", address, e),
+                    Err(_) => error = anyhow!("failed to connect to {}: timed out", address),
+                }
+            }
+            if !self.connections.contains_key(&node) {
+                return Err(error);
+            }
+        }
+        Ok(self.connections.get_mut(&node).unwrap())
+    }
+}
+
+enum Failure {
+    /// The messages are invalid, producing them again won't help.
+    Rejected(String),
+    /// Transient failure, the messages are produced again later.
+    Retry(String),
+}
+
+impl Failure {
+    fn retry(e: anyhow::Error) -> Self {
+        Failure::Retry(format!("{:#}", e))
+    }
+}
+
+/// Body of a Produce v3 request of one batch per partition.
+fn produce_request(topic: &str, batches: &[(i32, BytesMut)]) -> BytesMut {
+    let mut body = BytesMut::new();
+    // transactional_id
+    body.put_i16(-1);
+    // acks from all in-sync replicas
+    body.put_i16(-1);
+    body.put_i32(ACK_TIMEOUT_MS);
+    body.put_i32(1);
+    put_string(&mut body, topic);
+    body.put_i32(batches.len() as i32);
+    for (partition, batch) in batches {
+        body.put_i32(*partition);
+        body.put_i32(batch.len() as i32);
+        body.put_slice(batch);
+    }
+    body
+}
+
+/// Error code of each partition in a Produce v3 response.
+fn produce_errors(response: Bytes) -> Result<Vec<(i32, i16)>> {
+    let mut response = Reader(response);
+    let mut errors = Vec::new();
+    for _ in 0..response.array_len()? {
+        let _topic = response.string()?;
+        for _ in 0..response.array_len()? {
+            let partition = response.i32()?;
+            let code = response.i16()?;
+            let _base_offset = response.i64()?;
+            let _log_append_time = response.i64()?;
+            errors.push((partition, code));
+        }
+    }
+    Ok(errors)
+}
+
+/// A v2 record batch of messages without headers.
+fn record_batch(messages: &[&Message]) -> BytesMut {
+    let first = messages.iter().map(|m| m.timestamp).min().unwrap_or(0);
+    let last = messages.iter().map(|m| m.timestamp).max().unwrap_or(0);
+    let mut records = BytesMut::new();
+    for (delta, message) in messages.iter().enumerate() {
+        let mut record = BytesMut::new();
+        // attributes
+        record.put_i8(0);
+        put_varint(&mut record, message.timestamp - first);
+        put_varint(&mut record, delta as i64);
+        put_varint(&mut record, message.key.len() as i64);
+        record.put_slice(message.key.as_bytes());
+        put_varint(&mut record, message.value.len() as i64);
+        record.put_slice(message.value.as_bytes());
+        // headers
+        put_varint(&mut record, 0);
+        put_varint(&mut records, record.len() as i64);
+        records.put(record);
+    }
+
+    // The part covered by the CRC.
+    let mut body = BytesMut::new();
+    // attributes: no compression, create time
+    body.put_i16(0);
+    body.put_i32(messages.len() as i32 - 1);
+    body.put_i64(first);
+    body.put_i64(last);
+    // producer id, epoch and base sequence of a non-idempotent producer
+    body.put_i64(-1);
+    body.put_i16(-1);
+    body.put_i32(-1);
+    body.put_i32(messages.len() as i32);
+    body.put(records);
+
+    let mut batch = BytesMut::new();
+    // base offset, assigned by the broker
+    batch.put_i64(0);
+    // length of the rest: leader epoch, magic, CRC and body
+    batch.put_i32((4 + 1 + 4 + body.len()) as i32);
+    batch.put_i32(-1);
+    batch.put_i8(2);
+    batch.put_u32(crc32c(&body));
+    batch.put(body);
+    batch
+}
+
+fn put_string(buf: &mut BytesMut, s: &str) {
+    buf.put_i16(s.len() as i16);
+    buf.put_slice(s.as_bytes());
+}
+
+/// Zigzag varint, as used inside records.
+fn put_varint(buf: &mut BytesMut, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    while value >= 0x80 {
+        buf.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.put_u8(value as u8);
+}
+
+/// Bounds checked reads of a response.
+struct Reader(Bytes);
+
+impl Reader {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N]> {
+        if self.0.len() < N {
+            bail!("truncated response");
+        }
+        Ok(self.0.split_to(N)[..].try_into().unwrap())
+    }
+
+    fn i8(&mut self) -> Result<i8> {
+        Ok(i8::from_be_bytes(self.take()?))
+    }
+
+    fn i16(&mut self) -> Result<i16> {
+        Ok(i16::from_be_bytes(self.take()?))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.take()?))
+    }
+
+    fn i64(&mut self) -> Result<i64> {
+        Ok(i64::from_be_bytes(self.take()?))
+    }
+
+    fn string(&mut self) -> Result<Option<String>> {
+        let len = self.i16()?;
+        if len < 0 {
+            return Ok(None);
+        }
+        let len = len as usize;
+        if self.0.len() < len {
+            bail!("truncated response");
+        }
+        Ok(Some(
+            String::from_utf8_lossy(&self.0.split_to(len)).into_owned(),
+        ))
+    }
+
+    /// Length of an array, null arrays being empty.
+    fn array_len(&mut self) -> Result<i32> {
+        Ok(self.i32()?.max(0))
+    }
+}
+
+/// Partition of a key, the same as the default partitioner of the Java
+/// client picks so other producers of the topic agree on it.
+fn partition(key: &[u8], partitions: usize) -> usize {
+    (murmur2(key) & 0x7fff_ffff) as usize % partitions
+}
+
+/// MurmurHash2 with the seed of the Java client.
+fn murmur2(data: &[u8]) -> i32 {
+    const M: u32 = 0x5bd1_e995;
+    let mut h = 0x9747_b28c ^ data.len() as u32;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes(chunk.try_into().unwrap());
+        k = k.wrapping_mul(M);
+        k ^= k >> 24;
+        k = k.wrapping_mul(M);
+        h = h.wrapping_mul(M) ^ k;
+    }
+    if tail.len() >= 3 {
+        h ^= (tail[2] as u32) << 16;
+    }
+    if tail.len() >= 2 {
+        h ^= (tail[1] as u32) << 8;
+    }
+    if !tail.is_empty() {
+        h ^= tail[0] as u32;
+        h = h.wrapping_mul(M);
+    }
+    h ^= h >> 13;
+    h = h.wrapping_mul(M);
+    h ^= h >> 15;
+    h as i32
+}
+
+/// CRC-32C (Castagnoli) of record batches.
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn error_name(code: i16) -> String {
+    let name = match code {
+        2 => "CORRUPT_MESSAGE",
+        3 => "UNKNOWN_TOPIC_OR_PARTITION",
+        5 => "LEADER_NOT_AVAILABLE",
+        6 => "NOT_LEADER_OR_FOLLOWER",
+        7 => "REQUEST_TIMED_OUT",
+        10 => "MESSAGE_TOO_LARGE",
+        17 => "INVALID_TOPIC_EXCEPTION",
+        18 => "RECORD_LIST_TOO_LARGE",
+        19 => "NOT_ENOUGH_REPLICAS",
+        20 => "NOT_ENOUGH_REPLICAS_AFTER_APPEND",
+        29 => "TOPIC_AUTHORIZATION_FAILED",
+        87 => "INVALID_RECORD",
+        _ => return format!("error {}", code),
+    };
+    name.to_owned()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn checksums() {
+        // Vectors of the Java client.
+        assert_eq!(murmur2(b"21"), -973932308);
+        assert_eq!(murmur2(b"foobar"), -790332482);
+        assert_eq!(murmur2(b"a-little-bit-long-string"), -985981536);
+        assert_eq!(murmur2(b"abc"), 479470107);
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+    }
+
+    #[test]
+    fn records() {
+        let mut buf = BytesMut::new();
+        for value in [0, -1, 1, 63, -64, 64, 300] {
+            put_varint(&mut buf, value);
+        }
+        assert_eq!(&buf[..], [0, 1, 2, 126, 127, 128, 1, 216, 4]);
+
+        let sample = Sample {
+            timestamp: Local.timestamp_opt(1_700_000_000, 0).unwrap(),
+            tag: "PT_101".to_owned(),
+            value: PlcValue::Real(2.5),
+            quality: Quality::Good,
+        };
+        let message = message(Some("line1"), &sample);
+        assert_eq!(message.key, "line1/PT_101");
+        let value: serde_json::Value = serde_json::from_str(&message.value).unwrap();
+        assert_eq!(value["plc"], "line1");
+        assert_eq!(value["type"], "Real");
+        assert_eq!(value["value"], 2.5);
+        assert_eq!(value["quality"], "good");
+
+        let batch = record_batch(&[&message, &message]);
+        let length = i32::from_be_bytes(batch[8..12].try_into().unwrap()) as usize;
+        assert_eq!(length, batch.len() - 12);
+        assert_eq!(batch[16], 2);
+        let crc = u32::from_be_bytes(batch[17..21].try_into().unwrap());
+        assert_eq!(crc, crc32c(&batch[21..]));
+        let mut last = message.value.clone().into_bytes();
+        last.push(0);
+        assert!(batch.ends_with(&last));
+    }
+}
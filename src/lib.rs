@@ -0,0 +1,989 @@
+pub mod aga3;
+pub mod alarm;
+pub mod audit;
+pub mod backend;
+pub mod bench;
+pub mod bridge;
+pub mod check;
+pub mod cip;
+pub mod clock;
+pub mod codegen;
+pub mod completions;
+pub mod config;
+pub mod deadband;
+pub mod describe;
+pub mod diagnostics;
+pub mod diff;
+#[cfg(feature = "dnp3")]
+pub mod dnp3;
+pub mod dotenv;
+#[cfg(feature = "server")]
+pub mod exporter;
+pub mod expr;
+pub mod failure;
+pub mod freeze;
+pub mod gas;
+#[cfg(feature = "server")]
+pub mod gateway;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod healthcheck;
+pub mod historian;
+pub mod hooks;
+pub mod implicit;
+#[cfg(feature = "influx")]
+pub mod influx;
+pub mod init;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+pub mod list;
+pub mod logging;
+pub mod modbus;
+pub mod modbus_tcp;
+pub mod monitor;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+pub mod notify;
+pub mod pccc;
+pub mod plc;
+pub mod ports;
+pub mod pulse;
+pub mod ramp;
+pub mod record;
+pub mod s7;
+pub mod scan;
+pub mod schedule;
+pub mod script;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod service;
+pub mod settings;
+#[cfg(feature = "shell")]
+pub mod shell;
+pub mod sim;
+#[cfg(feature = "snmp")]
+pub mod snmp;
+#[cfg(feature = "mqtt")]
+pub mod sparkplug;
+pub mod tagcache;
+pub mod tags;
+pub mod targets;
+pub mod template;
+pub mod timing;
+pub mod totalizer;
+pub mod udt;
+pub mod value;
+pub mod verify;
+#[cfg(feature = "tui")]
+pub mod watch;
+#[cfg(feature = "zabbix")]
+pub mod zabbix;
+
+use std::collections::BTreeMap;
+use std::fmt::Display;
+use std::process::ExitCode;
+
+use anyhow::{bail, Result};
+use clap::{CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use colored::*;
+use rseip::client::ab_eip::*;
+
+#[derive(Parser)]
+#[command(
+    about = "A command line utility for parsing and reading tags on Allen Bradley CompactLogix PLCs.",
+    long_about = "Cobalt is an open source utility for communicating with Allen Bradley PLCs. That includes reading and writing tag values and listing controller tags."
+)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// PLC address. Monitor, exporter, bridge-write and healthcheck take it
+    /// repeated to reach several controllers at once.
+    #[arg(short, long, env = "COBALT_ADDRESS")]
+    address: Vec<String>,
+
+    /// Address of a standby controller, like the other chassis of a
+    /// redundant pair or a hot spare, switched to when the controller of
+    /// --address stops answering and back once it answers again.
+    #[arg(long, env = "COBALT_FAILOVER")]
+    failover: Option<String>,
+
+    /// Targets file naming the controllers of monitor, exporter,
+    /// bridge-write and healthcheck, with one `[[target]]` table each.
+    #[arg(long, env = "COBALT_TARGETS")]
+    targets: Option<std::path::PathBuf>,
+
+    /// Controller named by a `[plc.NAME]` table of the settings file, which
+    /// gives its address, in place of `--address`, and the options it is
+    /// reached with.
+    #[arg(long, env = "COBALT_PLC", conflicts_with = "targets")]
+    plc: Option<String>,
+
+    #[command(flatten)]
+    connection: plc::ConnectionArgs,
+
+    /// Audit file recording every write to a controller tag.
+    #[arg(long, global = true, default_value = audit::DEFAULT_AUDIT_FILE)]
+    audit_file: std::path::PathBuf,
+
+    /// Site settings file, `cobalt.toml` in the working directory by default.
+    #[arg(long, global = true, env = "COBALT_SETTINGS")]
+    settings: Option<std::path::PathBuf>,
+
+    #[command(flatten)]
+    service: service::ServiceArgs,
+
+    #[command(flatten)]
+    log: logging::LogArgs,
+
+    /// Record the EtherNet/IP traffic with the controllers to this file, for
+    /// `replay` to decode without them.
+    #[arg(long, global = true)]
+    record: Option<std::path::PathBuf>,
+
+    /// How a failing command reports its error on stderr: `text`, or one
+    /// JSON object with the kind of failure, the exit code and the message.
+    #[arg(long = "output", id = "error_output", value_name = "FORMAT", value_enum, default_value_t = failure::Output::Text)]
+    error_output: failure::Output,
+
+    /// Journal recording alarm transitions and operator actions
+    #[cfg(feature = "historian")]
+    #[arg(long, global = true, default_value = historian::DEFAULT_JOURNAL)]
+    journal: std::path::PathBuf,
+
+    /// Commands
+    #[command(subcommand)]
+    command: Commands,
+}
+
+#[derive(Subcommand)]
+enum Commands {
+    /// List controller tags.
+    List(list::ListArgs),
+    /// Show the data type, array dimensions, element count, external access
+    /// and constant flag of a tag, and whether writes to it go through.
+    Describe {
+        /// Controller scope tag; members, elements and bits are described
+        /// by their tag.
+        tag: String,
+    },
+    /// Show the structure types (UDTs) of the controller tags with their
+    /// members, or export their definitions as L5X or JSON.
+    Udts(udt::UdtArgs),
+    /// Generate Rust structs with rseip `Decode` and `Encode` impls for the
+    /// structure types of tags, to read them without hand-written offsets.
+    Codegen(codegen::CodegenArgs),
+    /// Report connection slot usage of the controller.
+    Connections,
+    /// Read or set the controller clock.
+    Clock {
+        #[command(subcommand)]
+        action: clock::ClockAction,
+    },
+    /// Show the identity of the controller: vendor, product, firmware,
+    /// serial number, mode and keyswitch position.
+    Info,
+    /// Show the faults of the controller, and decode the fault records its
+    /// fault routines copy into tags.
+    Faults {
+        /// Tag holding a copy of the MajorFaultRecord or MinorFaultRecord,
+        /// with the Type and Code members of a FAULTRECORD. Can be repeated.
+        #[arg(long)]
+        record: Vec<String>,
+    },
+    /// Check a fleet of controllers at once, reading the identity and a
+    /// heartbeat tag of each, and print a pass or fail table with the
+    /// round trips.
+    Healthcheck(healthcheck::HealthcheckArgs),
+    /// Check tags against warning and critical thresholds like a Nagios or
+    /// Icinga plugin, printing one line of plugin output and exiting with
+    /// 0 for OK, 1 for WARNING, 2 for CRITICAL and 3 for UNKNOWN.
+    Check(check::CheckArgs),
+    /// Time reads, and writes with `--write`, of a tag and print the round
+    /// trips and request rate, to size polling in the messaging mode given.
+    Bench(bench::BenchArgs),
+    /// Summarize the health of the controller before adding polling load:
+    /// mode and faults, connection slots, link state, packet rates and
+    /// request round trip.
+    Diagnostics {
+        /// How long packet rates and round trips are sampled for.
+        #[arg(long, default_value = "5s", value_parser = humantime::parse_duration)]
+        sample: std::time::Duration,
+        /// DINT tag holding a task scan time in microseconds, e.g. copied
+        /// from the LastScanTime or MaxScanTime of a task by a GSV
+        /// instruction. Can be repeated.
+        #[arg(long)]
+        scan_time: Vec<String>,
+    },
+    /// Send CIP services to objects cobalt doesn't model and print the raw
+    /// replies.
+    Cip {
+        #[command(subcommand)]
+        action: cip::CipAction,
+    },
+    /// Read tags of any supported type, including TIMER, COUNTER, CONTROL
+    /// and strings.
+    Read {
+        /// Tags to read, where `MyWord.5` is a bit of an integer and
+        /// `MyBools[12]` an element of a BOOL array.
+        #[arg(required = true)]
+        tags: Vec<String>,
+        /// Name the bits set in integer tags, like status words, by the
+        /// `[bits]` of the settings file.
+        #[arg(long)]
+        decode_bits: bool,
+        /// Print each value as a line like `{{name}}={{value}}` instead,
+        /// from the fields name, value, type, state, quality, timestamp,
+        /// unix and plc.
+        #[arg(long, conflicts_with = "decode_bits")]
+        template: Option<template::OutputTemplate>,
+    },
+    /// Write a value to a tag of any elementary or string type, converted to
+    /// the type the controller reports for the tag.
+    Write {
+        /// Tag to write, addressed like the tags of `read`. Bits are set or
+        /// cleared without touching the rest of their word.
+        tag: String,
+        value: String,
+        #[command(flatten)]
+        read_back: value::ReadBackArgs,
+    },
+    /// Slew a numeric tag to a value at a rate, writing the values in
+    /// between, e.g. to move a setpoint smoothly instead of stepping it.
+    /// Ctrl-C stops at the value reached.
+    WriteRamp(ramp::RampArgs),
+    /// Set a BOOL tag for a moment and clear it again, e.g. to trigger a
+    /// one-shot in the logic. Ctrl-C puts back the value it held before.
+    Pulse(pulse::PulseArgs),
+    /// Read the INT value of a tag.
+    ReadInt { tag: String },
+    /// Read the DINT value of a tag.
+    ReadDint { tag: String },
+    /// Read the REAL value of a tag.
+    ReadReal { tag: String },
+    /// Read the BOOL value of a tag.
+    ReadBool { tag: String },
+    /// Write a BOOL value to the specified tag.
+    WriteBool {
+        tag: String,
+        value: BoolValue,
+        #[command(flatten)]
+        read_back: value::ReadBackArgs,
+    },
+    /// Write an INT value to the specified tag.
+    WriteInt {
+        tag: String,
+        value: i16,
+        #[command(flatten)]
+        read_back: value::ReadBackArgs,
+    },
+    /// Write a DINT value to the specified tag.
+    WriteDint {
+        tag: String,
+        value: i32,
+        #[command(flatten)]
+        read_back: value::ReadBackArgs,
+    },
+    /// Write a REAL value to the specified tag.
+    WriteReal {
+        tag: String,
+        value: f32,
+        #[command(flatten)]
+        read_back: value::ReadBackArgs,
+    },
+    /// Write values to tags at the times or intervals of a schedule file,
+    /// e.g. reset a totalizer at midnight.
+    Schedule(schedule::ScheduleArgs),
+    /// Poll tags periodically and print or publish their values.
+    Monitor(Box<monitor::MonitorArgs>),
+    /// Serve tags as Prometheus gauges.
+    #[cfg(feature = "server")]
+    Exporter(exporter::ExporterArgs),
+    /// Serve a JSON API to read and write tags.
+    #[cfg(feature = "server")]
+    Serve(server::ServeArgs),
+    /// Serve a gRPC API to read, write and stream tags.
+    #[cfg(feature = "grpc")]
+    ServeGrpc(grpc::GrpcArgs),
+    /// Serve tags to DNP3 masters as the binary and analog inputs of an
+    /// outstation, with events of their changes.
+    #[cfg(feature = "dnp3")]
+    Dnp3(dnp3::Dnp3Args),
+    /// Serve tags to SNMP managers as the objects of a v2c agent.
+    #[cfg(feature = "snmp")]
+    Snmp(snmp::SnmpArgs),
+    /// Open an interactive prompt over a single session.
+    #[cfg(feature = "shell")]
+    Shell,
+    /// Run a script of read, write, sleep and assert statements over a
+    /// single session.
+    Run {
+        /// Script file, one statement per line.
+        script: std::path::PathBuf,
+    },
+    /// Check tags against expected values and fail with a report of the
+    /// ones that differ.
+    Verify(verify::VerifyArgs),
+    /// Compare tags with a second controller or a snapshot file and report
+    /// the ones that differ.
+    Diff(diff::DiffArgs),
+    /// Show a live table of tags and edit their values.
+    #[cfg(feature = "tui")]
+    Watch(watch::WatchArgs),
+    /// Acknowledge, shelve or show alarms raised by the monitor.
+    Alarms {
+        /// Alarm state file shared with the monitor.
+        #[arg(long, default_value = alarm::DEFAULT_STATE_FILE)]
+        state: std::path::PathBuf,
+        #[command(subcommand)]
+        action: alarm::AlarmAction,
+    },
+    /// Generate a starter configuration file.
+    Init(init::InitArgs),
+    /// List the serial ports of this machine.
+    Ports,
+    /// Simulate a controller with the tags of a file, to try configurations
+    /// without hardware.
+    Sim(sim::SimArgs),
+    /// Decode a recording of `--record` frame by frame, to look into
+    /// protocol problems without the controller.
+    Replay(record::ReplayArgs),
+    /// Print the script registering shell completions, e.g.
+    /// `source <(cobalt completions bash)`.
+    Completions { shell: completions::Shell },
+    /// Open a Class 1 I/O connection to an EtherNet/IP adapter and show
+    /// its input data, or bridge it to a controller.
+    Io(implicit::IoArgs),
+    /// Modbus RTU tools.
+    Modbus {
+        #[command(subcommand)]
+        action: modbus::ModbusAction,
+    },
+    /// Validate configuration files.
+    Config {
+        #[command(subcommand)]
+        action: config::ConfigAction,
+    },
+    /// Bridge a serial Modbus RTU to the PLC.
+    BridgeWrite {
+        #[command(flatten)]
+        bridge: bridge::BridgeArgs,
+        #[command(flatten)]
+        stats: timing::StatsArgs,
+        #[cfg(feature = "influx")]
+        #[command(flatten)]
+        influx: influx::InfluxArgs,
+    },
+}
+
+impl Args {
+    /// Parse a command line, noting the connection options it gives
+    /// explicitly so a controller profile doesn't override them.
+    pub fn try_parse_line<I, T>(line: I) -> Result<Self, clap::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: Into<std::ffi::OsString> + Clone,
+    {
+        let matches = Self::command().try_get_matches_from(line)?;
+        let mut cli = Self::from_arg_matches(&matches)?;
+        cli.connection.note_given(&matches);
+        Ok(cli)
+    }
+}
+
+impl Commands {
+    /// Tag and value written by a write command, for the journal.
+    fn written_value(&self) -> Option<(&str, String)> {
+        match self {
+            Commands::Write { tag, value, .. } => Some((tag, value.clone())),
+            Commands::WriteBool { tag, value, .. } => Some((tag, value.to_string())),
+            Commands::WriteInt { tag, value, .. } => Some((tag, value.to_string())),
+            Commands::WriteDint { tag, value, .. } => Some((tag, value.to_string())),
+            Commands::WriteReal { tag, value, .. } => Some((tag, value.to_string())),
+            _ => None,
+        }
+    }
+
+    /// What the command is about to change, asked before changing it on a
+    /// protected controller.
+    fn confirmation(&self, address: &str) -> Option<String> {
+        if let Commands::Clock {
+            action: clock::ClockAction::Set { .. },
+        } = self
+        {
+            return Some(format!("Set the clock of {}?", address));
+        }
+        if let Commands::Cip {
+            action: cip::CipAction::Raw { service, .. },
+        } = self
+        {
+            return Some(format!("Send service {:#04x} to {}?", service, address));
+        }
+        if let Commands::WriteRamp(args) = self {
+            return Some(format!(
+                "Ramp {} to {} at {}/s on {}?",
+                args.tag, args.to, args.rate.0, address
+            ));
+        }
+        if let Commands::Bench(args) = self {
+            if args.write {
+                return Some(format!(
+                    "Write {} {} times on {}?",
+                    args.tag, args.iterations, address
+                ));
+            }
+        }
+        if let Commands::Pulse(args) = self {
+            return Some(format!(
+                "Pulse {} for {} on {}?",
+                args.tag,
+                humantime::format_duration(args.duration),
+                address
+            ));
+        }
+        self.written_value()
+            .map(|(tag, value)| format!("Write {} to {} on {}?", value, tag, address))
+    }
+
+    /// Tag and value written by a typed write command, with its read-back.
+    fn typed_value(&self) -> Option<(&str, value::PlcValue, &value::ReadBackArgs)> {
+        use value::PlcValue;
+        match self {
+            Commands::WriteBool {
+                tag,
+                value,
+                read_back,
+            } => Some((
+                tag,
+                PlcValue::Bool(matches!(value, BoolValue::True)),
+                read_back,
+            )),
+            Commands::WriteInt {
+                tag,
+                value,
+                read_back,
+            } => Some((tag, PlcValue::Int(*value), read_back)),
+            Commands::WriteDint {
+                tag,
+                value,
+                read_back,
+            } => Some((tag, PlcValue::Dint(*value), read_back)),
+            Commands::WriteReal {
+                tag,
+                value,
+                read_back,
+            } => Some((tag, PlcValue::Real(*value), read_back)),
+            _ => None,
+        }
+    }
+
+    /// Whether the command polls until it is stopped, so it can run as a
+    /// service.
+    fn runs_until_stopped(&self) -> bool {
+        match self {
+            Commands::Monitor(_)
+            | Commands::BridgeWrite { .. }
+            | Commands::Io(_)
+            | Commands::Schedule(_) => true,
+            #[cfg(feature = "server")]
+            Commands::Exporter(_) | Commands::Serve(_) => true,
+            #[cfg(feature = "grpc")]
+            Commands::ServeGrpc(_) => true,
+            #[cfg(feature = "dnp3")]
+            Commands::Dnp3(_) => true,
+            #[cfg(feature = "snmp")]
+            Commands::Snmp(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Whether the command stops at a safe point on Ctrl-C instead of
+    /// dying in the middle of what it changes.
+    fn stops_on_interrupt(&self) -> bool {
+        self.runs_until_stopped() || matches!(self, Commands::WriteRamp(_) | Commands::Pulse(_))
+    }
+}
+
+#[derive(Clone, Subcommand, ValueEnum)]
+enum BoolValue {
+    False,
+    True,
+}
+
+impl Display for BoolValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BoolValue::False => {
+                write!(f, "false")
+            }
+            BoolValue::True => {
+                write!(f, "true")
+            }
+        }
+    }
+}
+
+/// Entry point of the `cobalt` binary: read the `.env` file, answer shell
+/// completions, parse the command line, set up logging and run the command, exiting with the code
+/// of the failure if it fails.
+pub async fn main() -> ExitCode {
+    #[cfg(windows)]
+    colored::control::set_virtual_terminal(true).ok();
+    if let Err(e) = dotenv::load(dotenv::DOTENV_FILE) {
+        return failure::report(&e, failure::Output::Text);
+    }
+    completions::complete(Args::command);
+    let cli = match Args::try_parse_line(std::env::args_os()) {
+        Ok(cli) => cli,
+        // Help and version aren't failures.
+        Err(e) if !e.use_stderr() => e.exit(),
+        Err(e) => {
+            e.print().ok();
+            return ExitCode::from(failure::Failure::Usage.code());
+        }
+    };
+    let output = cli.error_output;
+    let check = match &cli.command {
+        Commands::Check(args) => Some(args.label.clone()),
+        _ => None,
+    };
+    let result = match logging::init(&cli.log) {
+        Ok(()) => run(cli).await,
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        // Monitoring systems read the state of a check from its output.
+        Err(e) => match check {
+            Some(label) => check::report(&label, &e),
+            None => failure::report(&e, output),
+        },
+    }
+}
+
+/// Run a parsed command line, e.g. one of `Args::try_parse_line`.
+pub async fn run(mut cli: Args) -> Result<()> {
+    audit::init(&cli.audit_file);
+    // Nothing is done in a dry run, so nothing is journaled.
+    #[cfg(feature = "historian")]
+    let journal = if cli.connection.dry_run {
+        historian::Journal::disabled()
+    } else {
+        historian::Journal::new(&cli.journal)
+    };
+    #[cfg(not(feature = "historian"))]
+    let journal = historian::Journal::disabled();
+
+    // Commands that don't talk to a controller.
+    if let Commands::Alarms { state, action } = &cli.command {
+        alarm::run_action(state, &journal, action)?;
+        return Ok(());
+    }
+    if let Commands::Config { action } = &cli.command {
+        config::run_action(action, cli.connection.protocol)?;
+        return Ok(());
+    }
+    if let Commands::Init(args) = &cli.command {
+        init::run(args)?;
+        return Ok(());
+    }
+    if let Commands::Modbus { action } = &cli.command {
+        modbus::run_action(action, &cli.connection).await?;
+        return Ok(());
+    }
+    if let Commands::Ports = &cli.command {
+        ports::run()?;
+        return Ok(());
+    }
+    if let Commands::Sim(args) = &cli.command {
+        sim::run(args).await?;
+        return Ok(());
+    }
+    if let Commands::Completions { shell } = &cli.command {
+        completions::run(*shell, &Args::command())?;
+        return Ok(());
+    }
+    if let Commands::Replay(args) = &cli.command {
+        record::replay(args)?;
+        return Ok(());
+    }
+    let trace = cli.log.trace();
+    if cli.record.is_some() || trace.is_some() {
+        record::tap(cli.record.as_deref(), trace)?;
+    }
+    let settings = settings::Settings::load(cli.settings.as_deref())?;
+    if let Some(name) = &cli.plc {
+        let profile = settings.profile(name)?;
+        cli.connection.apply(name, profile)?;
+        cli.address = vec![profile.address.clone()];
+    }
+    let offline_address = cli.address.first().map(String::as_str);
+    match &cli.command {
+        Commands::List(args) if args.offline => {
+            list::run_offline(offline_address, args)?;
+            return Ok(());
+        }
+        Commands::Verify(args) if args.offline => {
+            verify::run_offline(offline_address, args)?;
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    if cli.command.stops_on_interrupt() {
+        service::handle_signals()?;
+    }
+    if cli.service.daemon && !cli.command.runs_until_stopped() {
+        bail!("only monitor, exporter, serve, serve-grpc, dnp3, snmp, io, schedule and bridge-write run as a daemon");
+    }
+    let service = service::Service::start(&cli.service)?;
+
+    cli.connection.read_only_settings = settings.read_only();
+    cli.connection.protected = settings.protected.clone();
+    let mut targets = targets::resolve(&cli.address, cli.targets.as_deref())?;
+    if let Some(standby) = &cli.failover {
+        match targets.as_mut_slice() {
+            [target] => target.failover = Some(standby.clone()),
+            _ => bail!("--failover takes a single controller, give the others a `failover` in the targets file"),
+        }
+    }
+    if let Commands::Healthcheck(args) = &cli.command {
+        if targets.is_empty() {
+            bail!("give the controllers with --address or --targets");
+        }
+        healthcheck::run(&targets, &cli.connection, args).await?;
+        return Ok(());
+    }
+    if targets.len() > 1 {
+        service.ready();
+        return run_targets(&cli, &targets, &journal, &settings).await;
+    }
+    let target = match targets.into_iter().next() {
+        Some(target) => target,
+        None => bail!("the --address option or COBALT_ADDRESS is required for this command"),
+    };
+    let address = target.address.clone();
+
+    // Adapters are reached over an I/O connection of their own.
+    if let Commands::Io(args) = &cli.command {
+        service.ready();
+        implicit::run(&address, args, &cli.connection).await?;
+        return Ok(());
+    }
+
+    let mut client = targets::connect(&target, &cli.connection).await?;
+    // Commands that change more than a tag say so up front, rather than
+    // asking at their first write.
+    if let (false, Some(question)) = (cli.connection.dry_run, cli.command.confirmation(&address)) {
+        client.confirm_write(|_| question)?;
+    }
+    service.ready();
+
+    match &cli.command {
+        Commands::List(args) => {
+            list::run(&mut client, args).await?;
+        }
+        Commands::Describe { tag } => {
+            describe::run(&mut client, tag).await?;
+        }
+        Commands::Check(args) => {
+            let result = check::run(&mut client, args).await;
+            client.close().await?;
+            return result;
+        }
+        Commands::Udts(args) => {
+            udt::run(&mut client, args).await?;
+        }
+        Commands::Codegen(args) => {
+            codegen::run(&mut client, args).await?;
+        }
+        Commands::Connections => {
+            let usage = diagnostics::ConnectionUsage::read(&mut client).await?;
+            usage.print();
+        }
+        Commands::Clock { action } => {
+            clock::run_action(&mut client, action, &journal).await?;
+        }
+        Commands::Info => {
+            diagnostics::Identity::read(&mut client).await?.print();
+        }
+        Commands::Faults { record } => {
+            diagnostics::read_faults(&mut client, record).await?;
+        }
+        Commands::Bench(args) => {
+            bench::run(&mut client, args).await?;
+        }
+        Commands::Diagnostics { sample, scan_time } => {
+            diagnostics::read_health(&mut client, *sample, scan_time).await?;
+        }
+        Commands::Cip { action } => {
+            cip::run_action(&mut client, action, &journal).await?;
+        }
+        Commands::Read {
+            tags,
+            decode_bits,
+            template,
+        } => {
+            for tag in tags {
+                let value = value::read_value(&mut client, tag).await?;
+                if let Some(template) = template {
+                    let fields = template::Fields {
+                        plc: client.address(),
+                        tag,
+                        value: &value,
+                        state: settings.state_name(tag, &value),
+                        quality: monitor::Quality::Good,
+                        timestamp: chrono::Local::now(),
+                    };
+                    println!("{}", template.render(&fields));
+                    continue;
+                }
+                let bits = if *decode_bits {
+                    let set = settings.decode_bits(tag, &value)?;
+                    let set = if set.is_empty() {
+                        "none".to_owned()
+                    } else {
+                        set.join(", ")
+                    };
+                    format!("    Bits set:    {}", set.bold())
+                } else {
+                    String::new()
+                };
+                let state = settings
+                    .state_name(tag, &value)
+                    .map(|state| format!(" ({})", state))
+                    .unwrap_or_default();
+                println!(
+                    "{}    Tag type:    {}    Tag value:    {}{}{}",
+                    tag.bold(),
+                    value.type_name(),
+                    value.to_string().bold().green(),
+                    state.bold(),
+                    bits
+                );
+            }
+        }
+        Commands::Write {
+            tag,
+            value,
+            read_back,
+        } => {
+            let current = value::read_value(&mut client, tag).await?;
+            let value = value::PlcValue::parse_like(&current, value)?;
+            value::write_value(&mut client, tag, value.clone()).await?;
+            if !plc::dry_run() {
+                println!(
+                    "Tag type:    {}    Tag value:    {}",
+                    value.type_name(),
+                    value.to_string().bold().green(),
+                );
+            }
+            read_back.check(&mut client, tag, value).await?;
+        }
+        Commands::WriteRamp(args) => {
+            let reached = ramp::run(&mut client, args).await?;
+            journal.record_operator("write", &args.tag, Some(reached.to_string()))?;
+        }
+        Commands::Pulse(args) => {
+            pulse::run(&mut client, args).await?;
+            let duration = humantime::format_duration(args.duration).to_string();
+            journal.record_operator("pulse", &args.tag, Some(duration))?;
+        }
+        Commands::ReadInt { tag } => {
+            read_typed(&mut client, tag, TagType::Int).await?;
+        }
+        Commands::ReadDint { tag } => {
+            read_typed(&mut client, tag, TagType::Dint).await?;
+        }
+        Commands::ReadReal { tag } => {
+            read_typed(&mut client, tag, TagType::Real).await?;
+        }
+        Commands::ReadBool { tag } => {
+            read_typed(&mut client, tag, TagType::Bool).await?;
+        }
+        Commands::WriteBool { .. }
+        | Commands::WriteInt { .. }
+        | Commands::WriteDint { .. }
+        | Commands::WriteReal { .. } => {
+            let Some((tag, value, read_back)) = cli.command.typed_value() else {
+                unreachable!("typed write commands have a typed value")
+            };
+            value::write_value(&mut client, tag, value.clone()).await?;
+            if !plc::dry_run() {
+                println!(
+                    "Tag type:    {}    Tag value:    {}",
+                    value.type_name(),
+                    value.to_string().bold().green(),
+                );
+            }
+            read_back.check(&mut client, tag, value).await?;
+        }
+        Commands::Schedule(args) => {
+            let writes = schedule::load(&args.file)?;
+            schedule::run(&mut client, &writes, &journal).await?;
+        }
+        Commands::Monitor(args) => {
+            monitor::run(&mut client, args, &journal, &settings, None).await?;
+        }
+        #[cfg(feature = "server")]
+        Commands::Exporter(args) => {
+            return exporter::run(vec![(None, client)], args, &settings.access).await;
+        }
+        #[cfg(feature = "shell")]
+        Commands::Shell => {
+            shell::run(&mut client, &journal).await?;
+        }
+        Commands::Run { script } => {
+            script::run(&mut client, script, &journal).await?;
+        }
+        Commands::Verify(args) => {
+            verify::run(&mut client, args).await?;
+        }
+        Commands::Diff(args) => {
+            diff::run(&mut client, &address, &cli.connection, args).await?;
+        }
+        #[cfg(feature = "tui")]
+        Commands::Watch(args) => {
+            return watch::run(&mut client, args, &journal).await;
+        }
+        #[cfg(feature = "server")]
+        Commands::Serve(args) => {
+            return server::run(client, args, journal.clone(), settings.access.clone()).await;
+        }
+        #[cfg(feature = "grpc")]
+        Commands::ServeGrpc(args) => {
+            return grpc::run(client, args, journal.clone(), settings.access.clone()).await;
+        }
+        #[cfg(feature = "dnp3")]
+        Commands::Dnp3(args) => {
+            dnp3::run(&mut client, args, &settings.access).await?;
+        }
+        #[cfg(feature = "snmp")]
+        Commands::Snmp(args) => {
+            snmp::run(&mut client, args, &settings.access).await?;
+        }
+        Commands::Alarms { .. }
+        | Commands::Config { .. }
+        | Commands::Init(_)
+        | Commands::Io(_)
+        | Commands::Modbus { .. }
+        | Commands::Ports
+        | Commands::Healthcheck(_)
+        | Commands::Sim(_)
+        | Commands::Replay(_)
+        | Commands::Completions { .. } => {
+            unreachable!("handled before connecting")
+        }
+        Commands::BridgeWrite {
+            bridge,
+            stats,
+            #[cfg(feature = "influx")]
+            influx,
+        } => {
+            bridge::run(
+                &mut client,
+                bridge.resolve()?,
+                stats,
+                #[cfg(feature = "influx")]
+                influx,
+                None,
+            )
+            .await?;
+        }
+    }
+
+    if let Some((tag, value)) = cli.command.written_value() {
+        journal.record_operator("write", tag, Some(value))?;
+    }
+
+    client.close().await?;
+    Ok(())
+}
+
+/// Run a polling mode against several controllers at once.
+async fn run_targets(
+    cli: &Args,
+    targets: &[targets::Target],
+    journal: &historian::Journal,
+    settings: &settings::Settings,
+) -> Result<()> {
+    match &cli.command {
+        Commands::Monitor(args) => {
+            targets::run_all(targets, &cli.connection, |target, mut client| async move {
+                monitor::run(&mut client, args, journal, settings, Some(&target.name)).await?;
+                client.close().await?;
+                Ok(())
+            })
+            .await
+        }
+        #[cfg(feature = "server")]
+        Commands::Exporter(args) => {
+            let mut clients = Vec::with_capacity(targets.len());
+            for target in targets {
+                let client = targets::connect(target, &cli.connection).await?;
+                clients.push((Some(target.name.clone()), client));
+            }
+            exporter::run(clients, args, &settings.access).await
+        }
+        Commands::BridgeWrite {
+            bridge,
+            stats,
+            #[cfg(feature = "influx")]
+            influx,
+        } => {
+            let mut configs = BTreeMap::new();
+            for target in targets {
+                let config = match &target.bridge {
+                    Some(path) => bridge::BridgeConfig::load(path)?,
+                    None => bridge.resolve()?,
+                };
+                let shared = configs
+                    .iter()
+                    .find(|(_, other): &(_, &bridge::BridgeConfig)| other.port == config.port);
+                if let Some((other, _)) = shared {
+                    bail!(
+                        "{} and {} share serial port {}, give each target its own bridge file",
+                        other,
+                        target.name,
+                        config.port
+                    );
+                }
+                configs.insert(target.name.clone(), config);
+            }
+            let configs = &configs;
+            targets::run_all(targets, &cli.connection, |target, mut client| async move {
+                bridge::run(
+                    &mut client,
+                    configs[&target.name].clone(),
+                    stats,
+                    #[cfg(feature = "influx")]
+                    influx,
+                    Some(&target.name),
+                )
+                .await?;
+                client.close().await?;
+                Ok(())
+            })
+            .await
+        }
+        _ => bail!("only monitor, exporter and bridge-write take several controllers"),
+    }
+}
+
+/// Read a tag for a typed read command, which fails on tags of another
+/// type.
+async fn read_typed(client: &mut plc::Plc, tag: &str, tag_type: TagType) -> Result<()> {
+    let value = value::read_value(client, tag).await?;
+    if value.tag_type() != tag_type {
+        return Err(failure::Failure::TypeMismatch.error(format!(
+            "{} is a {}, not a {:?}",
+            tag,
+            value.type_name(),
+            tag_type
+        )));
+    }
+    println!(
+        "Tag type:    {:?}    Tag value:    {}",
+        value.tag_type(),
+        value.to_string().bold().green(),
+    );
+    Ok(())
+}
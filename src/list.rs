@@ -0,0 +1,616 @@
+use crate::historian::{csv_field, ExportFormat};
+use crate::plc::{self, Family, Plc};
+use crate::tagcache::{self, TagCache};
+use crate::tags::glob_match;
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use clap::{Args, ValueEnum};
+use colored::*;
+use futures_util::TryStreamExt;
+use regex::Regex;
+use rseip::cip::codec::decode::message_reply::decode_service_and_status;
+use rseip::cip::error::cip_error_status;
+use rseip::cip::{MessageReply, MessageReplyInterface, MessageRequest, Status};
+use rseip::client::ab_eip::*;
+use rseip::precludes::*;
+use rseip::{BytesHolder, Decoder};
+use serde::{Deserialize, Serialize};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+/// Order of the listed tags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ListSort {
+    Name,
+    Type,
+    /// Controller scope first, then each program, by name within each.
+    Scope,
+}
+
+/// Get Instance Attribute List service, listing symbol instances.
+const SERVICE_GET_INSTANCE_ATTRIBUTE_LIST: u8 = 0x55;
+/// Attributes listed: the symbol name and the symbol type.
+const LIST_ATTRIBUTES: [u8; 6] = [2, 0, 1, 0, 2, 0];
+
+/// Options of the `list` command.
+#[derive(Args)]
+pub struct ListArgs {
+    /// Only list tags matching a pattern like `Pump*`, where `*` matches any
+    /// characters and `?` a single one, ignoring case.
+    #[arg(long)]
+    pub filter: Option<String>,
+    /// Take the filter as a regular expression instead.
+    #[arg(long, requires = "filter")]
+    pub regex: bool,
+    /// Only list tags of a type, e.g. REAL or STRUCT.
+    #[arg(long = "type")]
+    pub tag_type: Option<String>,
+    /// Sort the tags instead of listing them in controller order.
+    #[arg(long, value_enum)]
+    pub sort: Option<ListSort>,
+    /// Write the tags with their type metadata to a file instead, e.g.
+    /// `tags.json` or `tags.csv`.
+    #[arg(long)]
+    pub export: Option<PathBuf>,
+    /// Format of the export, guessed from the file extension by default.
+    #[arg(long, value_enum, requires = "export")]
+    pub format: Option<ExportFormat>,
+    /// List the tags cached for the controller at `--address`, or for the
+    /// only controller cached, without connecting to it.
+    #[arg(long)]
+    pub offline: bool,
+    /// Start listing at a symbol instance, carrying on a listing that
+    /// stopped with the tags it saved so far.
+    #[arg(long, value_name = "INSTANCE", conflicts_with = "offline")]
+    pub resume_from: Option<u16>,
+    /// Also list the tags of every program, as `Program:Main.Flow`, with the
+    /// scope of each tag.
+    #[arg(long, conflicts_with_all = ["offline", "resume_from"])]
+    pub all_scopes: bool,
+}
+
+/// A listed tag with the type metadata reported by the controller.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TagRecord {
+    pub name: String,
+    pub instance_id: u16,
+    pub r#type: String,
+    /// CIP type code of atomic tags.
+    pub type_code: Option<u8>,
+    pub dims: u8,
+    /// Template instance of structured tags.
+    pub structure_handle: Option<u16>,
+    /// Program of program scope tags.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub program: Option<String>,
+}
+
+impl TagRecord {
+    pub fn new(item: SymbolInstance<'_>) -> Self {
+        let symbol_type = item.symbol_type;
+        Self {
+            name: item.name.into_owned(),
+            instance_id: item.id,
+            r#type: type_name(symbol_type),
+            type_code: symbol_type.type_code(),
+            dims: symbol_type.dims(),
+            structure_handle: symbol_type.instance_id(),
+            program: None,
+        }
+    }
+
+    /// `controller`, or the program of a program scope tag.
+    fn scope(&self) -> &str {
+        self.program.as_deref().unwrap_or("controller")
+    }
+
+    /// Atomic type of the tag or of its array elements, or `STRUCT`, as
+    /// matched by `--type`.
+    fn element_type(&self) -> &str {
+        let end = self.r#type.find(['[', '(']).unwrap_or(self.r#type.len());
+        &self.r#type[..end]
+    }
+}
+
+/// How the tag names are matched.
+enum Filter {
+    Glob(String),
+    Regex(Regex),
+}
+
+impl Filter {
+    fn matches(&self, name: &str) -> bool {
+        match self {
+            Filter::Glob(pattern) => glob_match(pattern, name),
+            Filter::Regex(regex) => regex.is_match(name),
+        }
+    }
+}
+
+/// Tags of a listing so far, saved after every page so a listing that
+/// stopped can be resumed with `--resume-from`.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct PartialListing {
+    /// Instance the listing carries on from.
+    next: u16,
+    tags: Vec<TagRecord>,
+}
+
+impl PartialListing {
+    /// File of the listing of the controller at the address.
+    fn path(address: &str) -> Option<PathBuf> {
+        let name: String = address
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+                _ => '_',
+            })
+            .collect();
+        Some(
+            tagcache::cache_dir()?
+                .join("tags")
+                .join("partial")
+                .join(format!("{}.json", name)),
+        )
+    }
+
+    /// The tags listed before the instance, or none when the saved listing
+    /// stopped before it and would leave a gap.
+    fn load(path: &Path, start: u16) -> Result<Option<Self>> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("failed to read {}", path.display())),
+        };
+        let mut listing: Self = serde_json::from_str(&text)
+            .with_context(|| format!("failed to parse {}", path.display()))?;
+        if listing.next < start {
+            return Ok(None);
+        }
+        listing.tags.retain(|tag| tag.instance_id < start);
+        listing.next = start;
+        Ok(Some(listing))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let text = serde_json::to_string(self)?;
+        std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+    }
+}
+
+/// List the controller tags selected by the options. The tags are read a
+/// reply at a time, and saved after each one, so a listing that stops can
+/// be carried on with `--resume-from`. A whole list is cached for
+/// `--offline` and `--tag-cache` on the way.
+pub async fn run(client: &mut Plc, args: &ListArgs) -> Result<()> {
+    if client.family() == Family::MicroLogix {
+        bail!(
+            "{} controllers have no tag list, address their data files like N7:0",
+            client.family()
+        );
+    }
+    let path = PartialListing::path(client.address());
+    let start = args.resume_from.unwrap_or(0);
+    let resumed = match (&path, args.resume_from) {
+        (Some(path), Some(start)) => PartialListing::load(path, start)?,
+        _ => None,
+    };
+    // Without the tags before it, a resumed listing is only a part.
+    let whole = args.resume_from.is_none() || resumed.is_some();
+    let mut listing = resumed.unwrap_or(PartialListing {
+        next: start,
+        tags: Vec::new(),
+    });
+    if !listing.tags.is_empty() {
+        tracing::info!(
+            "carrying on from instance {} after {} tags",
+            listing.next,
+            listing.tags.len()
+        );
+    }
+
+    let progress = std::io::stderr().is_terminal();
+    loop {
+        let page: Vec<TagRecord> = match client
+            .eip()?
+            .list_tag()
+            .start_instance(listing.next)
+            .retrieve_all(false)
+            .call()
+            .map_ok(TagRecord::new)
+            .try_collect()
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => {
+                if progress {
+                    eprintln!();
+                }
+                return Err(anyhow::Error::from(e).context(format!(
+                    "the listing stopped after {} tags, `list --resume-from {}` carries on",
+                    listing.tags.len(),
+                    listing.next
+                )));
+            }
+        };
+        let Some(last) = page.last() else {
+            break;
+        };
+        listing.next = last.instance_id.saturating_add(1);
+        listing.tags.extend(page);
+        if let Some(path) = &path {
+            if let Err(e) = listing.save(path) {
+                tracing::warn!("failed to save the listing so far: {:#}", e);
+            }
+        }
+        if progress {
+            eprint!(
+                "\rListed {} tags, up to instance {}",
+                listing.tags.len(),
+                listing.next - 1
+            );
+        }
+        if listing.next == u16::MAX {
+            break;
+        }
+    }
+    if progress && !listing.tags.is_empty() {
+        eprintln!();
+    }
+    if let Some(path) = &path {
+        let _ = std::fs::remove_file(path);
+    }
+    if whole {
+        TagCache::save_listing(client, &listing.tags).await;
+    }
+    if !args.all_scopes {
+        return show(listing.tags, args);
+    }
+
+    // Programs are listed in the controller scope, their tags within them.
+    let (programs, mut tags): (Vec<TagRecord>, Vec<TagRecord>) = listing
+        .tags
+        .into_iter()
+        .partition(|tag| tag.name.starts_with("Program:"));
+    for (idx, program) in programs.iter().enumerate() {
+        let program = &program.name["Program:".len()..];
+        let listed = list_program(client, program)
+            .await
+            .with_context(|| format!("failed to list the tags of program {}", program))?;
+        tags.extend(listed);
+        if progress {
+            eprint!(
+                "\rListed {} tags, {} of {} programs",
+                tags.len(),
+                idx + 1,
+                programs.len()
+            );
+        }
+    }
+    if progress && !programs.is_empty() {
+        eprintln!();
+    }
+    show(tags, args)
+}
+
+/// A reply of Get Instance Attribute List, whose status tells when
+/// instances are left for another request rather than failing.
+struct SymbolPage(MessageReply<BytesHolder>);
+
+impl MessageReplyInterface for SymbolPage {
+    type Value = BytesHolder;
+
+    fn reply_service(&self) -> u8 {
+        self.0.reply_service
+    }
+
+    fn status(&self) -> &Status {
+        &self.0.status
+    }
+
+    fn value(&self) -> &BytesHolder {
+        &self.0.data
+    }
+
+    fn into_value(self) -> BytesHolder {
+        self.0.data
+    }
+}
+
+impl<'de> Decode<'de> for SymbolPage {
+    fn decode<D: Decoder<'de>>(mut decoder: D) -> Result<Self, D::Error> {
+        let (reply_service, status) = decode_service_and_status(&mut decoder)?;
+        if status.is_err() && !status.has_more() {
+            return Err(cip_error_status(status));
+        }
+        let data = decoder.decode_any()?;
+        Ok(Self(MessageReply::new(reply_service, status, data)))
+    }
+}
+
+/// The tags of a program, named like `Program:Main.Flow`. Their instances
+/// are those of the symbols of the program.
+async fn list_program(client: &mut Plc, program: &str) -> Result<Vec<TagRecord>> {
+    let scope = format!("Program:{}", program);
+    let mut tags: Vec<TagRecord> = Vec::new();
+    let mut next = 0;
+    loop {
+        let path = EPath::from_symbol(scope.as_str())
+            .with_class(CLASS_SYMBOL)
+            .with_instance(next);
+        let request = MessageRequest::new(
+            SERVICE_GET_INSTANCE_ATTRIBUTE_LIST,
+            path,
+            &LIST_ATTRIBUTES[..],
+        );
+        let page: SymbolPage =
+            plc::transaction("list", &scope, client.eip()?.send(request)).await?;
+        let more = page.0.has_more();
+        let mut data = Bytes::from(page.into_value());
+        while !data.is_empty() {
+            let symbol = SymbolInstance::try_from(&mut data)?;
+            let name = format!("{}.{}", scope, symbol.name);
+            tags.push(TagRecord {
+                name,
+                program: Some(program.to_owned()),
+                ..TagRecord::new(symbol)
+            });
+        }
+        match tags.last() {
+            Some(last) if more && last.instance_id < u16::MAX => next = last.instance_id + 1,
+            _ => return Ok(tags),
+        }
+    }
+}
+
+/// Name, instance and type of a controller scope tag, found in the tag
+/// list ignoring case. The list is only read up to the tag.
+pub async fn find_symbol(
+    client: &mut Plc,
+    name: &str,
+) -> Result<Option<(String, u16, SymbolType)>> {
+    let mut symbols = std::pin::pin!(client.eip()?.list_tag().call());
+    while let Some(symbol) = symbols.try_next().await? {
+        if symbol.name.eq_ignore_ascii_case(name) {
+            return Ok(Some((
+                symbol.name.into_owned(),
+                symbol.id,
+                symbol.symbol_type,
+            )));
+        }
+    }
+    Ok(None)
+}
+
+/// List the tags cached for a controller instead of asking it.
+pub fn run_offline(address: Option<&str>, args: &ListArgs) -> Result<()> {
+    let cache = TagCache::find(address)?;
+    tracing::info!(
+        "tags of {} (serial {:08x}) as listed {}",
+        cache.product_name,
+        cache.serial_number,
+        cache
+            .listed
+            .with_timezone(&chrono::Local)
+            .format("%Y-%m-%d %H:%M")
+    );
+    show(cache.tags, args)
+}
+
+fn show(tags: Vec<TagRecord>, args: &ListArgs) -> Result<()> {
+    let filter = match &args.filter {
+        Some(pattern) if args.regex => Some(Filter::Regex(
+            Regex::new(pattern).with_context(|| format!("invalid regex `{}`", pattern))?,
+        )),
+        Some(pattern) => Some(Filter::Glob(pattern.clone())),
+        None => None,
+    };
+
+    let format = match (&args.export, args.format) {
+        (Some(path), None) => Some(guess_format(path)?),
+        (_, format) => format,
+    };
+
+    let mut tags: Vec<TagRecord> = tags
+        .into_iter()
+        .filter(|tag| {
+            filter.as_ref().is_none_or(|f| f.matches(&tag.name))
+                && args
+                    .tag_type
+                    .as_ref()
+                    .is_none_or(|t| tag.element_type().eq_ignore_ascii_case(t))
+        })
+        .collect();
+
+    match args.sort {
+        Some(ListSort::Name) => tags.sort_by_key(|tag| tag.name.to_lowercase()),
+        Some(ListSort::Type) => {
+            tags.sort_by_cached_key(|tag| (tag.r#type.clone(), tag.name.to_lowercase()))
+        }
+        Some(ListSort::Scope) => tags.sort_by_cached_key(|tag| {
+            (
+                tag.program.as_ref().map(|program| program.to_lowercase()),
+                tag.name.to_lowercase(),
+            )
+        }),
+        None => {}
+    }
+
+    if let (Some(path), Some(format)) = (&args.export, format) {
+        let mut file = std::fs::File::create(path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        export(&tags, format, &mut file)?;
+        crate::completions::save_tags(tags.iter().map(|tag| tag.name.as_str()));
+        println!("Exported {} tags to {}.", tags.len(), path.display());
+        return Ok(());
+    }
+    for tag in &tags {
+        if args.all_scopes {
+            println!(
+                "    {}    {}    {}",
+                tag.name.bold(),
+                tag.r#type,
+                tag.scope().dimmed()
+            );
+        } else {
+            println!("    {}    {}", tag.name.bold(), tag.r#type);
+        }
+    }
+    Ok(())
+}
+
+fn guess_format(path: &Path) -> Result<ExportFormat> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("json") => Ok(ExportFormat::Json),
+        Some(ext) if ext.eq_ignore_ascii_case("csv") => Ok(ExportFormat::Csv),
+        _ => bail!(
+            "cannot tell the format of {}, pass --format",
+            path.display()
+        ),
+    }
+}
+
+fn export(tags: &[TagRecord], format: ExportFormat, out: &mut dyn Write) -> Result<()> {
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *out, tags)?;
+            writeln!(out)?;
+        }
+        ExportFormat::Csv => {
+            writeln!(
+                out,
+                "name,instance_id,type,type_code,dims,structure_handle,program"
+            )?;
+            for tag in tags {
+                writeln!(
+                    out,
+                    "{},{},{},{},{},{},{}",
+                    csv_field(&tag.name),
+                    tag.instance_id,
+                    csv_field(&tag.r#type),
+                    tag.type_code.map(|v| v.to_string()).unwrap_or_default(),
+                    tag.dims,
+                    tag.structure_handle
+                        .map(|v| v.to_string())
+                        .unwrap_or_default(),
+                    csv_field(tag.program.as_deref().unwrap_or_default())
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Name of the atomic type of a tag or of its array elements, or `STRUCT`.
+pub fn element_type_name(symbol_type: SymbolType) -> &'static str {
+    match symbol_type.type_code() {
+        None => "STRUCT",
+        Some(0xC1) => "BOOL",
+        Some(0xC2) => "SINT",
+        Some(0xC3) => "INT",
+        Some(0xC4) => "DINT",
+        Some(0xC5) => "LINT",
+        Some(0xC6) => "USINT",
+        Some(0xC7) => "UINT",
+        Some(0xC8) => "UDINT",
+        Some(0xC9) => "ULINT",
+        Some(0xCA) => "REAL",
+        Some(0xCB) => "LREAL",
+        Some(0xD1) => "BYTE",
+        Some(0xD2) => "WORD",
+        Some(0xD3) => "DWORD",
+        Some(0xD4) => "LWORD",
+        Some(_) => "UNKNOWN",
+    }
+}
+
+/// Type of a tag as shown in listings, e.g. `REAL`, `DINT[]` for a one
+/// dimension array or `STRUCT(0x0f3c)` with the structure handle.
+pub fn type_name(symbol_type: SymbolType) -> String {
+    let mut name = match (symbol_type.instance_id(), symbol_type.type_code()) {
+        (Some(id), _) => format!("STRUCT({:#06x})", id),
+        (None, Some(code)) if element_type_name(symbol_type) == "UNKNOWN" => {
+            format!("UNKNOWN({:#04x})", code)
+        }
+        _ => element_type_name(symbol_type).to_owned(),
+    };
+    match symbol_type.dims() {
+        0 => {}
+        dims => {
+            name.push('[');
+            name.push_str(&",".repeat(dims as usize - 1));
+            name.push(']');
+        }
+    }
+    name
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn type_names() {
+        let real = SymbolType::builder().atomic(0xCA).finish();
+        assert_eq!(type_name(real), "REAL");
+        let array = SymbolType::builder().atomic(0xC4).dims(2).finish();
+        assert_eq!(type_name(array), "DINT[,]");
+        assert_eq!(element_type_name(array), "DINT");
+        let udt = SymbolType::builder().structure(0x0f3c).dims(1).finish();
+        assert_eq!(type_name(udt), "STRUCT(0x0f3c)[]");
+        assert_eq!(element_type_name(udt), "STRUCT");
+
+        let record = |symbol_type| TagRecord {
+            name: "Tag".to_owned(),
+            instance_id: 1,
+            r#type: type_name(symbol_type),
+            type_code: symbol_type.type_code(),
+            dims: symbol_type.dims(),
+            structure_handle: symbol_type.instance_id(),
+            program: None,
+        };
+        assert_eq!(record(array).element_type(), "DINT");
+        assert_eq!(record(udt).element_type(), "STRUCT");
+        assert_eq!(record(real).element_type(), "REAL");
+    }
+
+    #[test]
+    fn partial_listing() {
+        let dir = std::env::temp_dir().join(format!("cobalt-list-{}", std::process::id()));
+        let path = dir.join("10.0.0.5.json");
+        let tag = |instance_id| TagRecord {
+            name: format!("Tag_{}", instance_id),
+            instance_id,
+            r#type: "REAL".to_owned(),
+            type_code: Some(0xCA),
+            dims: 0,
+            structure_handle: None,
+            program: None,
+        };
+        assert!(PartialListing::load(&path, 5).unwrap().is_none());
+        let listing = PartialListing {
+            next: 41,
+            tags: vec![tag(2), tag(17), tag(40)],
+        };
+        listing.save(&path).unwrap();
+        // Resuming earlier lists the tags from there again.
+        let resumed = PartialListing::load(&path, 17).unwrap().unwrap();
+        assert_eq!(resumed.next, 17);
+        assert_eq!(resumed.tags.len(), 1);
+        assert_eq!(
+            PartialListing::load(&path, 41).unwrap().unwrap().tags.len(),
+            3
+        );
+        // Past where it stopped, the tags between would be missing.
+        assert!(PartialListing::load(&path, 60).unwrap().is_none());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(PartialListing::path("10.0.0.5:44818")
+            .unwrap()
+            .ends_with("partial/10.0.0.5_44818.json"));
+    }
+}
@@ -0,0 +1,402 @@
+use crate::record::Trace;
+use anyhow::{Context, Result};
+use chrono::Local;
+use clap::{ArgAction, Args, ValueEnum};
+use colored::*;
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::io::{self, IsTerminal, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use tracing::field::{Field, Visit};
+use tracing::level_filters::LevelFilter;
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+#[derive(Args)]
+pub struct LogArgs {
+    /// Least severe messages logged: error, warn, info, debug or trace.
+    /// Debug logs every request sent to the controller, trace also decodes
+    /// every frame.
+    #[arg(long, global = true, default_value = "info")]
+    pub log_level: Level,
+    /// Log more: `-v` every request with its round trip, `-vv` the
+    /// decoding of every frame sent and received, with the statuses of the
+    /// replies, and `-vvv` the bytes of the frames as well.
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+    /// Append the log to this file instead of printing it to stderr.
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+    /// Format of the log lines.
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+}
+
+impl LogArgs {
+    /// Least severe messages logged, lowered by `-v`.
+    pub fn level(&self) -> Level {
+        let verbose = match self.verbose {
+            0 => Level::INFO,
+            1 => Level::DEBUG,
+            _ => Level::TRACE,
+        };
+        self.log_level.max(verbose)
+    }
+
+    /// How much of the traffic with the controllers is logged.
+    pub fn trace(&self) -> Option<Trace> {
+        match (self.verbose, self.level()) {
+            (3.., _) => Some(Trace::Bytes),
+            (_, Level::TRACE) => Some(Trace::Frames),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines, with the spans they happened in.
+    Text,
+    /// One JSON object per line, for log shippers.
+    Json,
+}
+
+/// Fields of a span or event, in the order they were recorded.
+type Fields = Vec<(&'static str, Value)>;
+
+struct Recorder<'a>(&'a mut Fields);
+
+impl Visit for Recorder<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+        self.0
+            .push((field.name(), Value::String(format!("{:?}", value))));
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.push((field.name(), Value::from(value)));
+    }
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Fields,
+    refs: usize,
+}
+
+thread_local! {
+    /// Spans entered on this thread, innermost last.
+    static ENTERED: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Writes events as text or JSON lines, each with the spans it happened in.
+struct Logger {
+    level: Level,
+    format: LogFormat,
+    color: bool,
+    out: Mutex<Box<dyn Write + Send>>,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    next_id: AtomicU64,
+}
+
+impl Logger {
+    /// Most verbose level logged for a target. Dependencies only get to
+    /// report warnings and errors.
+    fn max_level(&self, target: &str) -> Level {
+        if target == "cobalt" || target.starts_with("cobalt::") {
+            self.level
+        } else {
+            self.level.min(Level::WARN)
+        }
+    }
+}
+
+impl Subscriber for Logger {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= self.max_level(metadata.target())
+    }
+
+    fn max_level_hint(&self) -> Option<LevelFilter> {
+        Some(LevelFilter::from_level(self.level))
+    }
+
+    fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+        let mut fields = Fields::new();
+        attrs.record(&mut Recorder(&mut fields));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let span = SpanData {
+            name: attrs.metadata().name(),
+            fields,
+            refs: 1,
+        };
+        self.spans.lock().unwrap().insert(id, span);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut Recorder(&mut span.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut fields = Fields::new();
+        event.record(&mut Recorder(&mut fields));
+        let spans: Vec<(&'static str, Fields)> = {
+            let spans = self.spans.lock().unwrap();
+            ENTERED.with(|entered| {
+                entered
+                    .borrow()
+                    .iter()
+                    .filter_map(|id| spans.get(id))
+                    .map(|span| (span.name, span.fields.clone()))
+                    .collect()
+            })
+        };
+        let metadata = event.metadata();
+        let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string();
+        let line = match self.format {
+            LogFormat::Text => {
+                let level = format!("{:>5}", metadata.level());
+                let level = if self.color {
+                    match *metadata.level() {
+                        Level::ERROR => level.red().to_string(),
+                        Level::WARN => level.yellow().to_string(),
+                        Level::INFO => level.green().to_string(),
+                        _ => level.dimmed().to_string(),
+                    }
+                } else {
+                    level
+                };
+                format_text(&timestamp, &level, &spans, &fields)
+            }
+            LogFormat::Json => format_json(
+                &timestamp,
+                *metadata.level(),
+                metadata.target(),
+                &spans,
+                &fields,
+            ),
+        };
+        let mut out = self.out.lock().unwrap();
+        let _ = out.write_all(line.as_bytes());
+        let _ = out.flush();
+    }
+
+    fn enter(&self, span: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().push(span.into_u64()));
+    }
+
+    fn exit(&self, span: &Id) {
+        ENTERED.with(|entered| {
+            let mut entered = entered.borrow_mut();
+            if let Some(idx) = entered.iter().rposition(|id| *id == span.into_u64()) {
+                entered.remove(idx);
+            }
+        });
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(span) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            span.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let id = span.into_u64();
+        match spans.get_mut(&id) {
+            Some(span) if span.refs > 1 => {
+                span.refs -= 1;
+                false
+            }
+            Some(_) => {
+                spans.remove(&id);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Strings without their quotes, everything else as JSON.
+fn field_text(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+/// `timestamp LEVEL span{field=value}:span: message field=value`
+fn format_text(
+    timestamp: &str,
+    level: &str,
+    spans: &[(&'static str, Fields)],
+    fields: &Fields,
+) -> String {
+    let mut line = format!("{} {} ", timestamp, level);
+    for (name, span_fields) in spans {
+        line.push_str(name);
+        if !span_fields.is_empty() {
+            let span_fields: Vec<String> = span_fields
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, field_text(value)))
+                .collect();
+            line.push_str(&format!("{{{}}}", span_fields.join(" ")));
+        }
+        line.push_str(": ");
+    }
+    let mut rest = Vec::new();
+    for (name, value) in fields {
+        if *name == "message" {
+            line.push_str(&field_text(value));
+        } else {
+            rest.push(format!("{}={}", name, field_text(value)));
+        }
+    }
+    if !rest.is_empty() {
+        line.push(' ');
+        line.push_str(&rest.join(" "));
+    }
+    line.push('\n');
+    line
+}
+
+fn format_json(
+    timestamp: &str,
+    level: Level,
+    target: &str,
+    spans: &[(&'static str, Fields)],
+    fields: &Fields,
+) -> String {
+    let mut object = Map::new();
+    object.insert("timestamp".into(), timestamp.into());
+    object.insert("level".into(), level.as_str().into());
+    object.insert("target".into(), target.into());
+    let mut rest = Map::new();
+    for (name, value) in fields {
+        if *name == "message" {
+            object.insert("message".into(), value.clone());
+        } else {
+            rest.insert((*name).into(), value.clone());
+        }
+    }
+    if !rest.is_empty() {
+        object.insert("fields".into(), Value::Object(rest));
+    }
+    if !spans.is_empty() {
+        let spans = spans
+            .iter()
+            .map(|(name, fields)| {
+                let mut span = Map::new();
+                span.insert("name".into(), (*name).into());
+                for (name, value) in fields {
+                    span.insert((*name).into(), value.clone());
+                }
+                Value::Object(span)
+            })
+            .collect();
+        object.insert("spans".into(), Value::Array(spans));
+    }
+    format!("{}\n", Value::Object(object))
+}
+
+/// Install the logger for the whole run. Without `--log-file` the log goes
+/// to stderr, leaving stdout to the output of the command.
+pub fn init(args: &LogArgs) -> Result<()> {
+    let (out, color): (Box<dyn Write + Send>, bool) = match &args.log_file {
+        Some(path) => {
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| format!("failed to open log file {}", path.display()))?;
+            (Box::new(file), false)
+        }
+        None => (Box::new(io::stderr()), io::stderr().is_terminal()),
+    };
+    let logger = Logger {
+        level: args.level(),
+        format: args.log_format,
+        color,
+        out: Mutex::new(out),
+        spans: Mutex::new(HashMap::new()),
+        next_id: AtomicU64::new(1),
+    };
+    tracing::subscriber::set_global_default(logger).context("failed to install the logger")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn log_lines() {
+        let spans = vec![
+            ("target", vec![("name", json!("line1"))]),
+            (
+                "cip",
+                vec![("service", json!("read")), ("tag", json!("FT_201"))],
+            ),
+        ];
+        let fields = vec![("message", json!("reply")), ("elapsed_ms", json!(12))];
+        assert_eq!(
+            format_text("2024-03-01T08:00:00.000+01:00", "DEBUG", &spans, &fields),
+            "2024-03-01T08:00:00.000+01:00 DEBUG target{name=line1}: cip{service=read tag=FT_201}: reply elapsed_ms=12\n"
+        );
+
+        let line = format_json(
+            "2024-03-01T08:00:00.000+01:00",
+            Level::DEBUG,
+            "cobalt::plc",
+            &spans,
+            &fields,
+        );
+        let object: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(object["level"], "DEBUG");
+        assert_eq!(object["message"], "reply");
+        assert_eq!(object["fields"]["elapsed_ms"], 12);
+        assert_eq!(object["spans"][1]["tag"], "FT_201");
+    }
+
+    #[test]
+    fn verbosity() {
+        let args = |log_level, verbose| LogArgs {
+            log_level,
+            verbose,
+            log_file: None,
+            log_format: LogFormat::Text,
+        };
+        assert_eq!(args(Level::INFO, 0).level(), Level::INFO);
+        assert_eq!(args(Level::INFO, 0).trace(), None);
+        assert_eq!(args(Level::WARN, 1).level(), Level::DEBUG);
+        assert_eq!(args(Level::DEBUG, 0).trace(), None);
+        assert_eq!(args(Level::TRACE, 0).trace(), Some(Trace::Frames));
+        assert_eq!(args(Level::INFO, 2).trace(), Some(Trace::Frames));
+        assert_eq!(args(Level::INFO, 3).level(), Level::TRACE);
+        assert_eq!(args(Level::INFO, 3).trace(), Some(Trace::Bytes));
+    }
+}
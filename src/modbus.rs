@@ -0,0 +1,770 @@
+use crate::audit::{Audit, AuditEntry};
+use crate::bridge::{self, FlowControl, Parity};
+use crate::failure::Failure;
+use crate::plc::ConnectionArgs;
+use crate::value::PlcValue;
+use anyhow::{anyhow, bail, Context as _, Result};
+use clap::{Args, Subcommand, ValueEnum};
+use colored::*;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::time::{Duration, Instant};
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::*;
+use tokio_serial::SerialStream;
+
+#[derive(Subcommand)]
+pub enum ModbusAction {
+    /// Probe slave ids on serial lines and report the ones that respond.
+    Scan(ScanArgs),
+    /// Read holding registers (03) of a slave.
+    ReadHolding(ReadRegistersArgs),
+    /// Read input registers (04) of a slave.
+    ReadInput(ReadRegistersArgs),
+    /// Read coils (01) of a slave.
+    ReadCoils(ReadBitsArgs),
+    /// Read discrete inputs (02) of a slave.
+    ReadDiscrete(ReadBitsArgs),
+    /// Write holding registers of a slave, with 06 for a single register
+    /// and 16 for more.
+    WriteHolding(WriteRegistersArgs),
+    /// Write coils of a slave, with 05 for a single coil and 15 for more.
+    WriteCoils(WriteCoilsArgs),
+}
+
+/// Function used to probe slaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProbeFunction {
+    /// Read coils (01).
+    Coils,
+    /// Read discrete inputs (02).
+    Discrete,
+    /// Read holding registers (03).
+    Holding,
+    /// Read input registers (04).
+    Input,
+}
+
+/// Serial line and slave of the read and write commands.
+#[derive(Args)]
+pub struct SlaveArgs {
+    #[arg(long)]
+    pub port: String,
+    #[arg(long, default_value_t = 9600)]
+    pub baud: u32,
+    #[arg(long, value_enum, default_value_t = Parity::None)]
+    pub parity: Parity,
+    #[arg(long, default_value_t = 8, value_parser = clap::value_parser!(u8).range(5..=8))]
+    pub data_bits: u8,
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=2))]
+    pub stop_bits: u8,
+    #[arg(long, value_parser = clap::value_parser!(u8).range(1..=247))]
+    pub slave: u8,
+    /// Time to wait for the reply, e.g. `500ms`.
+    #[arg(long, default_value = "1s", value_parser = humantime::parse_duration)]
+    pub timeout: Duration,
+}
+
+/// Type of the values held in registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum RegisterType {
+    U16,
+    I16,
+    /// Two registers.
+    U32,
+    /// Two registers.
+    I32,
+    /// IEEE 754 float in two registers.
+    F32,
+}
+
+/// Order of the two registers of a 32-bit value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum WordOrder {
+    /// High word in the first register, as most devices do.
+    HighFirst,
+    LowFirst,
+}
+
+/// Options of `modbus read-holding` and `read-input`.
+#[derive(Args)]
+pub struct ReadRegistersArgs {
+    #[command(flatten)]
+    pub slave: SlaveArgs,
+    /// First register, as an offset from 0 or in Modicon notation, e.g.
+    /// 40001 or 400001 for the first holding register.
+    #[arg(long)]
+    pub address: u32,
+    /// Number of values read.
+    #[arg(long, default_value_t = 1)]
+    pub count: u16,
+    #[arg(long = "type", value_enum, default_value_t = RegisterType::U16)]
+    pub value_type: RegisterType,
+    #[arg(long, value_enum, default_value_t = WordOrder::HighFirst)]
+    pub word_order: WordOrder,
+}
+
+/// Options of `modbus read-coils` and `read-discrete`.
+#[derive(Args)]
+pub struct ReadBitsArgs {
+    #[command(flatten)]
+    pub slave: SlaveArgs,
+    /// First bit, as an offset from 0 or in Modicon notation, e.g. 10001
+    /// for the first discrete input.
+    #[arg(long)]
+    pub address: u32,
+    /// Number of bits read.
+    #[arg(long, default_value_t = 1)]
+    pub count: u16,
+}
+
+/// Options of `modbus write-holding`.
+#[derive(Args)]
+pub struct WriteRegistersArgs {
+    #[command(flatten)]
+    pub slave: SlaveArgs,
+    /// First register, as an offset from 0 or in Modicon notation.
+    #[arg(long)]
+    pub address: u32,
+    #[arg(long = "type", value_enum, default_value_t = RegisterType::U16)]
+    pub value_type: RegisterType,
+    #[arg(long, value_enum, default_value_t = WordOrder::HighFirst)]
+    pub word_order: WordOrder,
+    /// Values written to consecutive registers.
+    #[arg(required = true, allow_negative_numbers = true)]
+    pub values: Vec<String>,
+}
+
+/// Options of `modbus write-coils`.
+#[derive(Args)]
+pub struct WriteCoilsArgs {
+    #[command(flatten)]
+    pub slave: SlaveArgs,
+    /// First coil, as an offset from 0.
+    #[arg(long)]
+    pub address: u32,
+    /// States written to consecutive coils: 1, 0, true, false, on or off.
+    #[arg(required = true, value_parser = parse_bit)]
+    pub values: Vec<bool>,
+}
+
+/// Options of `modbus scan`.
+#[derive(Args)]
+pub struct ScanArgs {
+    /// Serial port to scan; repeat to scan several lines in parallel.
+    #[arg(long, required = true)]
+    pub port: Vec<String>,
+    #[arg(long, default_value_t = 9600)]
+    pub baud: u32,
+    /// First slave id probed.
+    #[arg(long, default_value_t = 1)]
+    pub from: u8,
+    /// Last slave id probed.
+    #[arg(long, default_value_t = 247)]
+    pub to: u8,
+    #[arg(long, value_enum, default_value = "holding")]
+    pub function: ProbeFunction,
+    /// Address of the first register or bit read by the probe.
+    #[arg(long, default_value_t = 0)]
+    pub register: u16,
+    /// Number of registers or bits read by the probe.
+    #[arg(long, default_value_t = 2)]
+    pub count: u16,
+    /// Time to wait for each slave, e.g. `200ms`.
+    #[arg(long, default_value = "200ms", value_parser = humantime::parse_duration)]
+    pub timeout: Duration,
+}
+
+/// How a probed slave answered.
+enum Reply {
+    Registers(Vec<u16>),
+    Bits(Vec<bool>),
+    /// The slave is there but refused the probe.
+    Exception(String),
+}
+
+struct Found {
+    slave: u8,
+    elapsed: Duration,
+    reply: Reply,
+}
+
+pub async fn run_action(action: &ModbusAction, connection: &ConnectionArgs) -> Result<()> {
+    match action {
+        ModbusAction::Scan(args) => scan(args).await,
+        ModbusAction::ReadHolding(args) => read_registers(Table::Holding, args).await,
+        ModbusAction::ReadInput(args) => read_registers(Table::Input, args).await,
+        ModbusAction::ReadCoils(args) => read_bits(Table::Coils, args).await,
+        ModbusAction::ReadDiscrete(args) => read_bits(Table::Discrete, args).await,
+        ModbusAction::WriteHolding(args) => write_registers(args, connection).await,
+        ModbusAction::WriteCoils(args) => write_coils(args, connection).await,
+    }
+}
+
+/// Data tables of a Modbus slave.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Table {
+    Coils,
+    Discrete,
+    Input,
+    Holding,
+}
+
+impl Table {
+    /// Leading digit of the table in Modicon references.
+    fn digit(self) -> u32 {
+        match self {
+            Table::Coils => 0,
+            Table::Discrete => 1,
+            Table::Input => 3,
+            Table::Holding => 4,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Table::Coils => "coil",
+            Table::Discrete => "discrete input",
+            Table::Input => "input register",
+            Table::Holding => "holding register",
+        }
+    }
+}
+
+/// First address of an item, as given and as a zero-based offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Address {
+    pub(crate) offset: u16,
+    /// Reference of the offset 0 when the address was given in Modicon
+    /// notation, e.g. 40001.
+    reference: Option<u32>,
+}
+
+impl Address {
+    /// Take 5 digit references from 10001 and 6 digit ones from 100001 in
+    /// Modicon notation, anything else as an offset. Coil references start
+    /// with 0, so coils only take offsets.
+    pub(crate) fn parse(table: Table, address: u32) -> Result<Self> {
+        let reference = match address {
+            _ if table == Table::Coils => None,
+            10001..=49999 => Some((address / 10000, address / 10000 * 10000 + 1)),
+            100001..=465536 => Some((address / 100000, address / 100000 * 100000 + 1)),
+            _ => None,
+        };
+        match reference {
+            Some((digit, base)) if digit == table.digit() && address - base <= u16::MAX as u32 => {
+                Ok(Self {
+                    offset: (address - base) as u16,
+                    reference: Some(base),
+                })
+            }
+            Some(_) => bail!("{} is not a {} reference", address, table.name()),
+            None => Ok(Self {
+                offset: u16::try_from(address)
+                    .map_err(|_| anyhow!("{} is not a {} address", address, table.name()))?,
+                reference: None,
+            }),
+        }
+    }
+
+    /// Address of the item `index` items further, as it was given.
+    fn label(&self, index: usize) -> u32 {
+        self.reference.unwrap_or(0) + self.offset as u32 + index as u32
+    }
+}
+
+impl RegisterType {
+    /// Registers per value.
+    pub(crate) fn width(self) -> usize {
+        match self {
+            RegisterType::U16 | RegisterType::I16 => 1,
+            RegisterType::U32 | RegisterType::I32 | RegisterType::F32 => 2,
+        }
+    }
+
+    fn decode(self, registers: &[u16], order: WordOrder) -> String {
+        let double = || match order {
+            WordOrder::HighFirst => (registers[0] as u32) << 16 | registers[1] as u32,
+            WordOrder::LowFirst => (registers[1] as u32) << 16 | registers[0] as u32,
+        };
+        match self {
+            RegisterType::U16 => registers[0].to_string(),
+            RegisterType::I16 => (registers[0] as i16).to_string(),
+            RegisterType::U32 => double().to_string(),
+            RegisterType::I32 => (double() as i32).to_string(),
+            RegisterType::F32 => f32::from_bits(double()).to_string(),
+        }
+    }
+
+    fn encode(self, text: &str, order: WordOrder) -> Result<Vec<u16>> {
+        let invalid = || anyhow!("`{}` is not a {:?} value", text, self);
+        let double = match self {
+            RegisterType::U16 => return Ok(vec![text.parse().map_err(|_| invalid())?]),
+            RegisterType::I16 => {
+                return Ok(vec![text.parse::<i16>().map_err(|_| invalid())? as u16])
+            }
+            RegisterType::U32 => text.parse::<u32>().map_err(|_| invalid())?,
+            RegisterType::I32 => text.parse::<i32>().map_err(|_| invalid())? as u32,
+            RegisterType::F32 => text.parse::<f32>().map_err(|_| invalid())?.to_bits(),
+        };
+        let (high, low) = ((double >> 16) as u16, double as u16);
+        Ok(match order {
+            WordOrder::HighFirst => vec![high, low],
+            WordOrder::LowFirst => vec![low, high],
+        })
+    }
+
+    /// Value held in the registers, typed like the tags of a controller.
+    pub(crate) fn value(self, registers: &[u16], order: WordOrder) -> PlcValue {
+        let double = || match order {
+            WordOrder::HighFirst => (registers[0] as u32) << 16 | registers[1] as u32,
+            WordOrder::LowFirst => (registers[1] as u32) << 16 | registers[0] as u32,
+        };
+        match self {
+            RegisterType::U16 => PlcValue::Uint(registers[0]),
+            RegisterType::I16 => PlcValue::Int(registers[0] as i16),
+            RegisterType::U32 => PlcValue::Udint(double()),
+            RegisterType::I32 => PlcValue::Dint(double() as i32),
+            RegisterType::F32 => PlcValue::Real(f32::from_bits(double())),
+        }
+    }
+
+    /// Registers holding a value of the matching type.
+    pub(crate) fn registers(self, value: PlcValue, order: WordOrder) -> Result<Vec<u16>> {
+        let double = match (self, &value) {
+            (RegisterType::U16, &PlcValue::Uint(value)) => return Ok(vec![value]),
+            (RegisterType::I16, &PlcValue::Int(value)) => return Ok(vec![value as u16]),
+            (RegisterType::U32, &PlcValue::Udint(value)) => value,
+            (RegisterType::I32, &PlcValue::Dint(value)) => value as u32,
+            (RegisterType::F32, &PlcValue::Real(value)) => value.to_bits(),
+            _ => bail!(
+                "{:?} registers can't take the {} {}",
+                self,
+                value.type_name(),
+                value
+            ),
+        };
+        let (high, low) = ((double >> 16) as u16, double as u16);
+        Ok(match order {
+            WordOrder::HighFirst => vec![high, low],
+            WordOrder::LowFirst => vec![low, high],
+        })
+    }
+}
+
+fn parse_bit(text: &str) -> Result<bool, String> {
+    match text.to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" => Ok(true),
+        "0" | "false" | "off" => Ok(false),
+        _ => Err(format!("`{}` is not 1, 0, true, false, on or off", text)),
+    }
+}
+
+async fn connect(args: &SlaveArgs) -> Result<Context> {
+    let serial = bridge::serial_port(
+        &args.port,
+        args.baud,
+        args.parity,
+        args.data_bits,
+        args.stop_bits,
+        FlowControl::None,
+    )?;
+    let stream = SerialStream::open(&serial)
+        .with_context(|| format!("failed to open serial port {}", args.port))?;
+    Ok(rtu::connect_slave(stream, Slave(args.slave)).await?)
+}
+
+/// Wait for the reply of the slave to a request.
+async fn request<T>(
+    args: &SlaveArgs,
+    request: impl Future<Output = std::io::Result<T>>,
+) -> Result<T> {
+    match tokio::time::timeout(args.timeout, request).await {
+        Ok(reply) => reply.with_context(|| format!("request to slave {} failed", args.slave)),
+        Err(_) => bail!(
+            "slave {} didn't answer within {}",
+            args.slave,
+            humantime::format_duration(args.timeout)
+        ),
+    }
+}
+
+async fn read_registers(table: Table, args: &ReadRegistersArgs) -> Result<()> {
+    let address = Address::parse(table, args.address)?;
+    let width = args.value_type.width();
+    let count = args.count as usize * width;
+    if count == 0 || count > 125 {
+        bail!("a request reads 1 to 125 registers, not {}", count);
+    }
+    let mut ctx = connect(&args.slave).await?;
+    let registers = match table {
+        Table::Input => {
+            request(
+                &args.slave,
+                ctx.read_input_registers(address.offset, count as u16),
+            )
+            .await?
+        }
+        _ => {
+            request(
+                &args.slave,
+                ctx.read_holding_registers(address.offset, count as u16),
+            )
+            .await?
+        }
+    };
+    for (idx, chunk) in registers.chunks(width).enumerate() {
+        let raw: Vec<String> = chunk.iter().map(|r| format!("{:#06x}", r)).collect();
+        println!(
+            "{:>6}    {}    {}",
+            address.label(idx * width).to_string().bold(),
+            raw.join(" ").dimmed(),
+            args.value_type.decode(chunk, args.word_order).green()
+        );
+    }
+    Ok(())
+}
+
+async fn read_bits(table: Table, args: &ReadBitsArgs) -> Result<()> {
+    let address = Address::parse(table, args.address)?;
+    if args.count == 0 || args.count > 2000 {
+        bail!("a request reads 1 to 2000 bits, not {}", args.count);
+    }
+    let mut ctx = connect(&args.slave).await?;
+    let bits = match table {
+        Table::Discrete => {
+            request(
+                &args.slave,
+                ctx.read_discrete_inputs(address.offset, args.count),
+            )
+            .await?
+        }
+        _ => request(&args.slave, ctx.read_coils(address.offset, args.count)).await?,
+    };
+    // Replies are padded to whole bytes.
+    for (idx, bit) in bits.iter().take(args.count as usize).enumerate() {
+        println!(
+            "{:>6}    {}",
+            address.label(idx).to_string().bold(),
+            if *bit { "1".green() } else { "0".normal() }
+        );
+    }
+    Ok(())
+}
+
+/// Name of the slave standing in for the controller in the audit file and
+/// the messages of writes.
+fn slave_name(args: &SlaveArgs) -> String {
+    format!("{} slave {}", args.port, args.slave)
+}
+
+/// Refuse writes under `--read-only`, like the writes of tags.
+fn check_writable(args: &SlaveArgs, connection: &ConnectionArgs) -> Result<()> {
+    if connection.read_only {
+        return Err(Failure::WriteRejected.error(format!(
+            "{} is read-only, nothing is written to it",
+            slave_name(args)
+        )));
+    }
+    Ok(())
+}
+
+/// Record each value written in the audit file, under the slave and its
+/// address.
+fn record(audit: &mut Audit, name: &str, values: &[(u32, PlcValue)], result: &Result<()>) {
+    for (label, value) in values {
+        let entry = AuditEntry::new(name, &label.to_string(), None, value.clone(), result);
+        audit.record(&entry);
+    }
+}
+
+async fn write_registers(args: &WriteRegistersArgs, connection: &ConnectionArgs) -> Result<()> {
+    let address = Address::parse(Table::Holding, args.address)?;
+    let width = args.value_type.width();
+    let mut registers = Vec::new();
+    for value in &args.values {
+        registers.extend(args.value_type.encode(value, args.word_order)?);
+    }
+    if registers.len() > 123 {
+        bail!(
+            "a request writes up to 123 registers, not {}",
+            registers.len()
+        );
+    }
+    check_writable(&args.slave, connection)?;
+    let values: Vec<(u32, PlcValue)> = registers
+        .chunks(width)
+        .enumerate()
+        .map(|(idx, chunk)| {
+            (
+                address.label(idx * width),
+                args.value_type.value(chunk, args.word_order),
+            )
+        })
+        .collect();
+    if connection.dry_run {
+        for (idx, chunk) in registers.chunks(width).enumerate() {
+            let raw: Vec<String> = chunk.iter().map(|r| format!("{:#06x}", r)).collect();
+            println!(
+                "{}    {} = {} ({})",
+                "DRY RUN".bold().yellow(),
+                values[idx].0,
+                args.value_type.decode(chunk, args.word_order),
+                raw.join(" ")
+            );
+        }
+        return Ok(());
+    }
+    let mut audit = Audit::open()?;
+    let mut ctx = connect(&args.slave).await?;
+    let result = if let [register] = registers[..] {
+        request(
+            &args.slave,
+            ctx.write_single_register(address.offset, register),
+        )
+        .await
+    } else {
+        request(
+            &args.slave,
+            ctx.write_multiple_registers(address.offset, &registers),
+        )
+        .await
+    };
+    record(&mut audit, &slave_name(&args.slave), &values, &result);
+    result?;
+    println!(
+        "{} {} register(s) from {}",
+        "Wrote".green(),
+        registers.len(),
+        address.label(0)
+    );
+    Ok(())
+}
+
+async fn write_coils(args: &WriteCoilsArgs, connection: &ConnectionArgs) -> Result<()> {
+    let address = Address::parse(Table::Coils, args.address)?;
+    if args.values.len() > 1968 {
+        bail!(
+            "a request writes up to 1968 coils, not {}",
+            args.values.len()
+        );
+    }
+    check_writable(&args.slave, connection)?;
+    if connection.dry_run {
+        for (idx, coil) in args.values.iter().enumerate() {
+            println!(
+                "{}    {} = {}",
+                "DRY RUN".bold().yellow(),
+                address.label(idx),
+                *coil as u8
+            );
+        }
+        return Ok(());
+    }
+    let values: Vec<(u32, PlcValue)> = args
+        .values
+        .iter()
+        .enumerate()
+        .map(|(idx, coil)| (address.label(idx), PlcValue::Bool(*coil)))
+        .collect();
+    let mut audit = Audit::open()?;
+    let mut ctx = connect(&args.slave).await?;
+    let result = if let [coil] = args.values[..] {
+        request(&args.slave, ctx.write_single_coil(address.offset, coil)).await
+    } else {
+        request(
+            &args.slave,
+            ctx.write_multiple_coils(address.offset, &args.values),
+        )
+        .await
+    };
+    record(&mut audit, &slave_name(&args.slave), &values, &result);
+    result?;
+    println!(
+        "{} {} coil(s) from {}",
+        "Wrote".green(),
+        args.values.len(),
+        address.label(0)
+    );
+    Ok(())
+}
+
+async fn scan(args: &ScanArgs) -> Result<()> {
+    if args.from == 0 || args.to > 247 || args.from > args.to {
+        bail!(
+            "slave ids must be within 1 to 247, got {} to {}",
+            args.from,
+            args.to
+        );
+    }
+    println!(
+        "Probing slaves {} to {} on {} with {:?} {}+{}...",
+        args.from,
+        args.to,
+        args.port.join(", ").bold(),
+        args.function,
+        args.register,
+        args.count
+    );
+
+    // Slaves share a line, so each line is probed one id at a time, but the
+    // lines themselves are independent.
+    let scans = args.port.iter().map(|port| scan_port(port, args));
+    let results = futures_util::future::join_all(scans).await;
+
+    for (port, result) in args.port.iter().zip(results) {
+        println!();
+        match result {
+            Ok(found) if found.is_empty() => {
+                println!("{}: {}", port.bold(), "no slave responded".yellow())
+            }
+            Ok(found) => {
+                println!("{}: {} slave(s) responded", port.bold(), found.len());
+                for found in found {
+                    let reply = match found.reply {
+                        Reply::Registers(registers) => registers
+                            .iter()
+                            .map(|r| format!("{:#06x}", r))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                            .green(),
+                        Reply::Bits(bits) => bits
+                            .iter()
+                            .map(|&b| if b { "1" } else { "0" })
+                            .collect::<String>()
+                            .green(),
+                        Reply::Exception(e) => e.yellow(),
+                    };
+                    println!(
+                        "    {:>3}    {:>6.1} ms    {}",
+                        found.slave.to_string().bold(),
+                        found.elapsed.as_secs_f64() * 1000.0,
+                        reply
+                    );
+                }
+            }
+            Err(e) => println!("{}: {} {:#}", port.bold(), "failed:".red(), e),
+        }
+    }
+    Ok(())
+}
+
+async fn open(port: &str, baud: u32) -> Result<Context> {
+    let stream = SerialStream::open(&tokio_serial::new(port, baud))?;
+    Ok(rtu::connect(stream).await?)
+}
+
+async fn scan_port(port: &str, args: &ScanArgs) -> Result<Vec<Found>> {
+    let mut ctx = open(port, args.baud).await?;
+    let mut found = Vec::new();
+    for slave in args.from..=args.to {
+        ctx.set_slave(Slave(slave));
+        let start = Instant::now();
+        let reply = match tokio::time::timeout(args.timeout, probe(&mut ctx, args)).await {
+            Ok(Ok(reply)) => reply,
+            Ok(Err(e)) if e.kind() == ErrorKind::Other => Reply::Exception(e.to_string()),
+            // No reply, or a garbled one which is most likely a collision or
+            // a wrong baud rate rather than a slave. Leftover bytes would be
+            // taken for the next slave's reply, so start over with a fresh
+            // frame buffer.
+            Ok(Err(_)) | Err(_) => {
+                drop(ctx);
+                ctx = open(port, args.baud).await?;
+                continue;
+            }
+        };
+        found.push(Found {
+            slave,
+            elapsed: start.elapsed(),
+            reply,
+        });
+    }
+    Ok(found)
+}
+
+async fn probe(ctx: &mut Context, args: &ScanArgs) -> std::io::Result<Reply> {
+    let (register, count) = (args.register, args.count);
+    Ok(match args.function {
+        ProbeFunction::Coils => Reply::Bits(ctx.read_coils(register, count).await?),
+        ProbeFunction::Discrete => Reply::Bits(ctx.read_discrete_inputs(register, count).await?),
+        ProbeFunction::Holding => {
+            Reply::Registers(ctx.read_holding_registers(register, count).await?)
+        }
+        ProbeFunction::Input => Reply::Registers(ctx.read_input_registers(register, count).await?),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[test]
+    fn addresses() {
+        let first = Address::parse(Table::Holding, 40001).unwrap();
+        assert_eq!(first.offset, 0);
+        assert_eq!(first.label(2), 40003);
+        assert_eq!(
+            Address::parse(Table::Holding, 410001).unwrap().offset,
+            10000
+        );
+        assert_eq!(Address::parse(Table::Input, 30010).unwrap().offset, 9);
+        assert_eq!(Address::parse(Table::Discrete, 10001).unwrap().offset, 0);
+        let offset = Address::parse(Table::Holding, 100).unwrap();
+        assert_eq!((offset.offset, offset.label(1)), (100, 101));
+        assert_eq!(Address::parse(Table::Coils, 12000).unwrap().offset, 12000);
+        assert!(Address::parse(Table::Input, 40001).is_err());
+        assert!(Address::parse(Table::Holding, 70000).is_err());
+    }
+
+    #[test]
+    fn register_values() {
+        let order = WordOrder::HighFirst;
+        let registers = RegisterType::F32.encode("12.5", order).unwrap();
+        assert_eq!(registers, [0x4148, 0x0000]);
+        assert_eq!(RegisterType::F32.decode(&registers, order), "12.5");
+        let swapped = RegisterType::F32
+            .encode("12.5", WordOrder::LowFirst)
+            .unwrap();
+        assert_eq!(swapped, [0x0000, 0x4148]);
+        assert_eq!(RegisterType::I16.encode("-2", order).unwrap(), [0xfffe]);
+        assert_eq!(RegisterType::I16.decode(&[0xfffe], order), "-2");
+        assert_eq!(
+            RegisterType::U32.decode(&[0x0001, 0x0002], WordOrder::LowFirst),
+            "131073"
+        );
+        assert!(RegisterType::U16.encode("70000", order).is_err());
+        assert_eq!(parse_bit("ON"), Ok(true));
+        assert!(parse_bit("2").is_err());
+    }
+
+    async fn run(line: &str) -> Result<()> {
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            connection: ConnectionArgs,
+            #[command(subcommand)]
+            action: ModbusAction,
+        }
+        let cli = Cli::parse_from(line.split_whitespace());
+        run_action(&cli.action, &cli.connection).await
+    }
+
+    #[tokio::test]
+    async fn guarded_writes() {
+        // Dry runs and refusals never open the port, which doesn't exist.
+        let slave = "--port /dev/cobalt-none --slave 1";
+        let registers = format!(
+            "cobalt --dry-run write-holding {} --address 40001 --type f32 12.5",
+            slave
+        );
+        run(&registers).await.unwrap();
+        let coils = format!("cobalt --dry-run write-coils {} --address 0 1 0", slave);
+        run(&coils).await.unwrap();
+        let refused = format!("cobalt --read-only write-coils {} --address 0 1", slave);
+        let e = run(&refused).await.unwrap_err();
+        assert_eq!(Failure::of(&e), Failure::WriteRejected);
+        let sent = format!("cobalt write-coils {} --address 0 1", slave);
+        assert!(format!("{:#}", run(&sent).await.unwrap_err()).contains("serial port"));
+    }
+}
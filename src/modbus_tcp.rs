@@ -0,0 +1,396 @@
+use crate::backend::PlcBackend;
+use crate::modbus::{Address, RegisterType, Table, WordOrder};
+use crate::plc;
+use crate::value::PlcValue;
+use anyhow::{anyhow, bail, Context as _, Result};
+use async_trait::async_trait;
+use clap::ValueEnum;
+use std::time::Duration;
+use tokio_modbus::client::Context;
+use tokio_modbus::prelude::*;
+
+/// Modbus TCP port.
+const DEFAULT_PORT: u16 = 502;
+
+/// Time given to the device to accept the connection or answer a request.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Most registers, and bits, a read request takes.
+const MAX_REGISTERS: u16 = 125;
+const MAX_BITS: u16 = 2000;
+
+/// Largest run of unused registers or bits a read spans to reach the next
+/// address rather than making a request of its own.
+const MAX_GAP: u16 = 16;
+
+/// An address of a data table, e.g. `hr:40001:f32`, `ir:0:i16`, `co:12` or
+/// `di:10001`. Registers hold a u16 unless a type follows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Item {
+    table: Table,
+    address: Address,
+    /// Type of the registers, none for coils and discrete inputs.
+    register_type: Option<RegisterType>,
+}
+
+impl Item {
+    fn parse(text: &str) -> Result<Self> {
+        let invalid = || anyhow!("`{}` is not a Modbus address like hr:40001:f32", text);
+        let mut parts = text.trim().split(':');
+        let table = match parts.next().map(str::to_ascii_lowercase).as_deref() {
+            Some("co") => Table::Coils,
+            Some("di") => Table::Discrete,
+            Some("ir") => Table::Input,
+            Some("hr") => Table::Holding,
+            _ => return Err(invalid()),
+        };
+        let number = parts
+            .next()
+            .and_then(|number| number.parse().ok())
+            .ok_or_else(invalid)?;
+        let address = Address::parse(table, number).map_err(|e| anyhow!("`{}`: {}", text, e))?;
+        let register_type = match (table, parts.next()) {
+            (Table::Coils | Table::Discrete, None) => None,
+            (Table::Coils | Table::Discrete, Some(_)) => {
+                bail!("`{}`: {}s are bits and take no type", text, table.name())
+            }
+            (_, None) => Some(RegisterType::U16),
+            (_, Some(name)) => Some(
+                RegisterType::from_str(name, true)
+                    .map_err(|_| anyhow!("`{}`: registers hold u16, i16, u32, i32 or f32", text))?,
+            ),
+        };
+        if parts.next().is_some() {
+            return Err(invalid());
+        }
+        Ok(Self {
+            table,
+            address,
+            register_type,
+        })
+    }
+
+    /// Registers or bits taken by the value.
+    fn width(&self) -> u16 {
+        self.register_type
+            .map_or(1, |register_type| register_type.width() as u16)
+    }
+
+    fn end(&self) -> u32 {
+        self.address.offset as u32 + self.width() as u32
+    }
+}
+
+/// Addresses of one table read with one request, from `start` on.
+#[derive(Debug)]
+struct Span {
+    table: Table,
+    start: u16,
+    count: u16,
+    /// Items of the span, by tag index.
+    items: Vec<(usize, Item)>,
+}
+
+/// Group the items into the fewest reads of neighbouring addresses.
+fn spans(mut items: Vec<(usize, Item)>) -> Vec<Span> {
+    items.sort_by_key(|(_, item)| (item.table, item.address.offset));
+    let mut spans: Vec<Span> = Vec::new();
+    for (idx, item) in items {
+        let max = match item.table {
+            Table::Coils | Table::Discrete => MAX_BITS,
+            _ => MAX_REGISTERS,
+        } as u32;
+        if let Some(span) = spans.last_mut() {
+            let end = span.start as u32 + span.count as u32;
+            let fits = span.table == item.table
+                && item.address.offset as u32 <= end + MAX_GAP as u32
+                && item.end() - span.start as u32 <= max;
+            if fits {
+                span.count = (end.max(item.end()) - span.start as u32) as u16;
+                span.items.push((idx, item));
+                continue;
+            }
+        }
+        spans.push(Span {
+            table: item.table,
+            start: item.address.offset,
+            count: item.width(),
+            items: vec![(idx, item)],
+        });
+    }
+    spans
+}
+
+/// Session with a Modbus TCP device, connected on the first request and
+/// again after a failed one.
+pub struct ModbusTcpClient {
+    host: String,
+    unit: u8,
+    word_order: WordOrder,
+    context: Option<Context>,
+}
+
+impl ModbusTcpClient {
+    /// Nothing is sent until the first request.
+    pub fn new(address: &str, unit: u8, word_order: WordOrder) -> Self {
+        let host = match address.contains(':') {
+            true => address.to_owned(),
+            false => format!("{}:{}", address, DEFAULT_PORT),
+        };
+        Self {
+            host,
+            unit,
+            word_order,
+            context: None,
+        }
+    }
+
+    async fn context(&mut self) -> Result<&mut Context> {
+        if self.context.is_none() {
+            let socket = tokio::net::lookup_host(&self.host)
+                .await?
+                .next()
+                .ok_or_else(|| anyhow!("{} has no address", self.host))?;
+            let context =
+                tokio::time::timeout(TIMEOUT, tcp::connect_slave(socket, Slave(self.unit)))
+                    .await
+                    .map_err(|_| anyhow!("timed out"))
+                    .and_then(|context| Ok(context?))
+                    .with_context(|| format!("failed to connect to {}", self.host))?;
+            self.context = Some(context);
+        }
+        Ok(self.context.as_mut().unwrap())
+    }
+
+    /// Read a span, as registers or as bits. The connection is dropped when
+    /// the request fails, so the next one starts afresh.
+    async fn read_span(&mut self, span: &Span, tags: &str) -> Result<Vec<u16>> {
+        let context = self.context().await?;
+        let (start, count) = (span.start, span.count);
+        let request = async {
+            let read = match span.table {
+                Table::Coils => bits(context.read_coils(start, count).await?),
+                Table::Discrete => bits(context.read_discrete_inputs(start, count).await?),
+                Table::Input => context.read_input_registers(start, count).await?,
+                Table::Holding => context.read_holding_registers(start, count).await?,
+            };
+            Ok::<_, std::io::Error>(read)
+        };
+        let result = plc::transaction("read", tags, timeout(request)).await;
+        if result.is_err() {
+            self.context = None;
+        }
+        result
+    }
+}
+
+fn bits(bits: Vec<bool>) -> Vec<u16> {
+    bits.into_iter().map(u16::from).collect()
+}
+
+async fn timeout<T>(request: impl std::future::Future<Output = std::io::Result<T>>) -> Result<T> {
+    match tokio::time::timeout(TIMEOUT, request).await {
+        Ok(reply) => Ok(reply?),
+        Err(_) => bail!("no reply from the device"),
+    }
+}
+
+/// Check an address without asking the device.
+pub fn check_address(text: &str) -> Result<()> {
+    Item::parse(text).map(|_| ())
+}
+
+#[async_trait]
+impl PlcBackend for ModbusTcpClient {
+    async fn read(&mut self, tag: &str) -> Result<PlcValue> {
+        self.read_many(&[tag.to_owned()]).await.remove(0)
+    }
+
+    /// Neighbouring addresses of a table are read with one request.
+    async fn read_many(&mut self, tags: &[String]) -> Vec<Result<PlcValue>> {
+        let mut values: Vec<Option<Result<PlcValue>>> = tags.iter().map(|_| None).collect();
+        let mut items = Vec::new();
+        for (idx, tag) in tags.iter().enumerate() {
+            match Item::parse(tag) {
+                Ok(item) => items.push((idx, item)),
+                Err(e) => values[idx] = Some(Err(e)),
+            }
+        }
+        for span in spans(items) {
+            let names: Vec<&str> = span
+                .items
+                .iter()
+                .map(|(idx, _)| tags[*idx].as_str())
+                .collect();
+            let read = self.read_span(&span, &names.join(",")).await;
+            for (idx, item) in &span.items {
+                let value = match &read {
+                    Ok(read) => {
+                        let at = (item.address.offset - span.start) as usize;
+                        let words = &read[at..at + item.width() as usize];
+                        Ok(match item.register_type {
+                            Some(register_type) => register_type.value(words, self.word_order),
+                            None => PlcValue::Bool(words[0] != 0),
+                        })
+                    }
+                    Err(e) => Err(anyhow!("{}: {:#}", tags[*idx], e)),
+                };
+                values[*idx] = Some(value);
+            }
+        }
+        values.into_iter().flatten().collect()
+    }
+
+    fn request_count(&self, tags: &[String]) -> usize {
+        let items = tags
+            .iter()
+            .filter_map(|tag| Item::parse(tag).ok())
+            .enumerate()
+            .collect();
+        spans(items).len()
+    }
+
+    async fn write(&mut self, tag: &str, value: PlcValue) -> Result<()> {
+        let item = Item::parse(tag)?;
+        let offset = item.address.offset;
+        let registers = match (item.table, item.register_type, &value) {
+            (Table::Coils, _, PlcValue::Bool(_)) => None,
+            (Table::Coils, _, value) => {
+                bail!(
+                    "{}: coils can't take the {} {}",
+                    tag,
+                    value.type_name(),
+                    value
+                )
+            }
+            (Table::Holding, Some(register_type), value) => Some(
+                register_type
+                    .registers(value.clone(), self.word_order)
+                    .map_err(|e| anyhow!("{}: {}", tag, e))?,
+            ),
+            (table, _, _) => bail!("{}: {}s are read-only", tag, table.name()),
+        };
+        let context = self.context().await?;
+        let request = async {
+            match registers.as_deref() {
+                None => {
+                    timeout(context.write_single_coil(offset, value == PlcValue::Bool(true))).await
+                }
+                Some(&[register]) => timeout(context.write_single_register(offset, register)).await,
+                Some(registers) => {
+                    timeout(context.write_multiple_registers(offset, registers)).await
+                }
+            }
+        };
+        let result = plc::transaction("write", tag, request)
+            .await
+            .map_err(|e| anyhow!("{}: {:#}", tag, e));
+        if result.is_err() {
+            self.context = None;
+        }
+        result
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if let Some(mut context) = self.context.take() {
+            context.disconnect().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    #[test]
+    fn addresses() {
+        let item = Item::parse("hr:40001:f32").unwrap();
+        assert_eq!(item.table, Table::Holding);
+        assert_eq!(item.address.offset, 0);
+        assert_eq!(item.register_type, Some(RegisterType::F32));
+        assert_eq!(item.width(), 2);
+        let item = Item::parse("IR:7").unwrap();
+        assert_eq!(
+            (item.table, item.address.offset, item.register_type),
+            (Table::Input, 7, Some(RegisterType::U16))
+        );
+        assert_eq!(Item::parse("co:12").unwrap().register_type, None);
+        assert_eq!(Item::parse("di:10003").unwrap().address.offset, 2);
+
+        for invalid in [
+            "hr",
+            "hr:x",
+            "hr:30001",
+            "hr:1:f64",
+            "co:1:u16",
+            "xx:1",
+            "hr:1:u16:2",
+            "PT_101",
+        ] {
+            assert!(Item::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn grouping() {
+        let items = [
+            "hr:40001:f32",
+            "hr:40010",
+            "ir:0",
+            "hr:40200",
+            "hr:40003:i32",
+            "co:5",
+            "co:3000",
+        ]
+        .iter()
+        .map(|tag| Item::parse(tag).unwrap())
+        .enumerate()
+        .collect();
+        let spans: Vec<_> = spans(items)
+            .iter()
+            .map(|span| {
+                let tags: Vec<usize> = span.items.iter().map(|(idx, _)| *idx).collect();
+                (span.table, span.start, span.count, tags)
+            })
+            .collect();
+        assert_eq!(
+            spans,
+            [
+                (Table::Coils, 5, 1, vec![5]),
+                (Table::Coils, 3000, 1, vec![6]),
+                (Table::Input, 0, 1, vec![2]),
+                (Table::Holding, 0, 10, vec![0, 4, 1]),
+                (Table::Holding, 199, 1, vec![3]),
+            ]
+        );
+    }
+
+    /// A device answering one read of holding registers 0 and 1, holding
+    /// 12.5 as a float.
+    async fn fake_device(listener: TcpListener) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = [0; 12];
+        stream.read_exact(&mut request).await.unwrap();
+        // Unit 3, read holding registers from 0, two of them.
+        assert_eq!(&request[6..], [3, 0x03, 0, 0, 0, 2]);
+        let mut reply = request[..4].to_vec();
+        reply.extend_from_slice(&[0, 7, 3, 0x03, 4, 0x41, 0x48, 0, 0]);
+        stream.write_all(&reply).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_from_a_device() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let device = tokio::spawn(fake_device(listener));
+        let mut client = ModbusTcpClient::new(&address, 3, WordOrder::HighFirst);
+        assert_eq!(
+            client.read("hr:40001:f32").await.unwrap(),
+            PlcValue::Real(12.5)
+        );
+        device.await.unwrap();
+    }
+}
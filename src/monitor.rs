@@ -0,0 +1,335 @@
+use crate::alarm::{self, AlarmEngine, AlarmRule, Condition, RulesFile};
+use crate::deadband::{Deadband, DeadbandFilter};
+use crate::freeze::FreezeDetector;
+use crate::historian::Journal;
+use crate::hooks::{AlarmHooks, AlarmWrite};
+#[cfg(feature = "influx")]
+use crate::influx::{InfluxArgs, InfluxSink};
+#[cfg(feature = "kafka")]
+use crate::kafka::{KafkaArgs, KafkaSink};
+#[cfg(feature = "mqtt")]
+use crate::mqtt::{MqttArgs, MqttPublisher};
+#[cfg(feature = "notify")]
+use crate::notify::Notifier;
+use crate::plc::{self, Plc};
+use crate::scan::{Scan, ScanClasses, Scheduler};
+use crate::settings::Settings;
+use crate::tags::{FreezeConfig, TagList};
+use crate::template::{Fields, OutputTemplate};
+use crate::timing::{CycleStats, StatsArgs};
+use crate::value::PlcValue;
+#[cfg(feature = "zabbix")]
+use crate::zabbix::{ZabbixArgs, ZabbixSink};
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local};
+use clap::Args;
+use colored::*;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// Options of the `monitor` command.
+#[derive(Args)]
+pub struct MonitorArgs {
+    /// Tags to poll.
+    pub tags: Vec<String>,
+    /// Tag list file with more tags to poll.
+    #[arg(long = "tags")]
+    pub tag_list: Option<PathBuf>,
+    /// Polling interval in milliseconds, of the medium scan class that tags
+    /// belong to by default.
+    #[arg(long, default_value_t = 1000)]
+    pub interval: u64,
+    /// Polling interval of the fast scan class in milliseconds.
+    #[arg(long, default_value_t = 250)]
+    pub fast_interval: u64,
+    /// Polling interval of the slow scan class in milliseconds.
+    #[arg(long, default_value_t = 10_000)]
+    pub slow_interval: u64,
+    /// Alarm rules file; the tags it references are polled as well.
+    #[arg(long)]
+    pub alarms: Option<PathBuf>,
+    /// Alarm condition like `PT_101>80`, named after the condition. Can be
+    /// repeated and combined with --alarms.
+    #[arg(long)]
+    pub alarm: Vec<Condition>,
+    /// Shell command run when an alarm is raised, with COBALT_ALARM,
+    /// COBALT_VALUE, COBALT_MESSAGE and COBALT_TIMESTAMP set.
+    #[arg(long)]
+    pub on_alarm: Option<String>,
+    /// Write a value to a tag when an alarm is raised, e.g. `Pump_Run=false`.
+    /// Can be repeated.
+    #[arg(long)]
+    pub alarm_write: Vec<AlarmWrite>,
+    /// File holding alarm acknowledgment and shelving state.
+    #[arg(long, default_value = alarm::DEFAULT_STATE_FILE)]
+    pub alarm_state: PathBuf,
+    /// Flag tags whose value stays within --freeze-tolerance for this long,
+    /// e.g. `10m`. Tag list entries with a `freeze` table keep their own.
+    #[arg(long, value_parser = humantime::parse_duration)]
+    pub freeze_after: Option<Duration>,
+    /// Change a value has to exceed to not count as frozen.
+    #[arg(long, default_value_t = 0.0, requires = "freeze_after")]
+    pub freeze_tolerance: f64,
+    /// Only report REAL and LREAL values that moved more than this since
+    /// they were last reported, either absolute like `0.5` or relative to
+    /// the last value like `2%`. Alarms still see every reading.
+    #[arg(long)]
+    pub deadband: Option<Deadband>,
+    /// Print each sample as a line like `{{name}}={{value}}` instead, from
+    /// the fields name, value, type, state, quality, timestamp, unix and
+    /// plc.
+    #[arg(long)]
+    pub template: Option<OutputTemplate>,
+    #[command(flatten)]
+    pub stats: StatsArgs,
+    #[cfg(feature = "mqtt")]
+    #[command(flatten)]
+    pub mqtt: MqttArgs,
+    #[cfg(feature = "influx")]
+    #[command(flatten)]
+    pub influx: InfluxArgs,
+    #[cfg(feature = "kafka")]
+    #[command(flatten)]
+    pub kafka: KafkaArgs,
+    #[cfg(feature = "zabbix")]
+    #[command(flatten)]
+    pub zabbix: ZabbixArgs,
+}
+
+/// A tag value read during a polling cycle.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    pub timestamp: DateTime<Local>,
+    pub tag: String,
+    pub value: PlcValue,
+    pub quality: Quality,
+}
+
+/// How much a sample can be trusted.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Quality {
+    #[default]
+    Good,
+    /// The value has not moved for longer than expected, which usually
+    /// means a failed transmitter or a stale communication path.
+    Frozen,
+}
+
+/// Poll the tags every interval, printing each sample and forwarding it to
+/// the configured outputs. Alarm events, including frozen values, are
+/// recorded in the journal. Values of tags with states in the settings
+/// are printed with the name of their state.
+///
+/// `target` names the controller when several are monitored at once. Its
+/// output is prefixed with the name, and its alarms keep their own state
+/// file.
+pub async fn run(
+    client: &mut Plc,
+    args: &MonitorArgs,
+    journal: &Journal,
+    settings: &Settings,
+    target: Option<&str>,
+) -> Result<()> {
+    let (mut rules, notify) = match &args.alarms {
+        Some(path) => {
+            let file = RulesFile::load(path)?;
+            (file.alarms, file.notify)
+        }
+        None => (Vec::new(), None),
+    };
+    rules.extend(args.alarm.iter().map(|condition| AlarmRule {
+        name: condition.to_string(),
+        condition: condition.clone(),
+        message: String::new(),
+    }));
+
+    let mut tags = args.tags.clone();
+    let mut freeze = HashMap::new();
+    let mut scans = HashMap::new();
+    if let Some(path) = &args.tag_list {
+        for tag in TagList::load(path)?.tags {
+            if let Some(config) = tag.freeze {
+                freeze.insert(tag.name.clone(), config);
+            }
+            if let Some(scan) = tag.scan {
+                scans.insert(tag.name.clone(), scan);
+            }
+            if !tags.contains(&tag.name) {
+                tags.push(tag.name);
+            }
+        }
+    }
+    for rule in &rules {
+        if !tags.contains(&rule.condition.tag) {
+            tags.push(rule.condition.tag.clone());
+        }
+    }
+    if tags.is_empty() {
+        bail!("no tags to monitor");
+    }
+    if let Some(after) = args.freeze_after {
+        for tag in &tags {
+            freeze.entry(tag.clone()).or_insert(FreezeConfig {
+                after,
+                tolerance: args.freeze_tolerance,
+            });
+        }
+    }
+    let mut freeze = FreezeDetector::new(freeze);
+    let mut deadband = args.deadband.map(DeadbandFilter::new);
+    let hooks = AlarmHooks {
+        command: args.on_alarm.clone(),
+        writes: args.alarm_write.clone(),
+    };
+    #[cfg(feature = "notify")]
+    let mut notifier = notify.as_ref().map(Notifier::start).transpose()?;
+    #[cfg(not(feature = "notify"))]
+    if notify.is_some() {
+        bail!("alarm notifications need a build with the `notify` feature");
+    }
+
+    // Frozen values raise alarms even without a rules file.
+    let mut alarms = if args.alarms.is_some() || !rules.is_empty() || !freeze.is_empty() {
+        let state = match target {
+            Some(target) => alarm::target_state_file(&args.alarm_state, target),
+            None => args.alarm_state.clone(),
+        };
+        Some(AlarmEngine::new(rules, &state)?)
+    } else {
+        None
+    };
+
+    #[cfg(feature = "mqtt")]
+    let mut mqtt = MqttPublisher::connect(&args.mqtt.for_target(target))?;
+    #[cfg(feature = "influx")]
+    let influx = InfluxSink::connect(&args.influx, target)?;
+    #[cfg(feature = "kafka")]
+    let mut kafka = KafkaSink::connect(&args.kafka, target)?;
+    #[cfg(feature = "zabbix")]
+    let zabbix = ZabbixSink::connect(&args.zabbix, target)?;
+    let prefix = target
+        .map(|target| format!("{}    ", target.cyan()))
+        .unwrap_or_default();
+    let classes = ScanClasses {
+        fast: Duration::from_millis(args.fast_interval.max(1)),
+        medium: Duration::from_millis(args.interval.max(1)),
+        slow: Duration::from_millis(args.slow_interval.max(1)),
+    };
+    let mut scheduler = Scheduler::new(
+        tags.iter().map(|tag| {
+            let scan = scans.get(tag).copied().unwrap_or(Scan::Medium);
+            (tag.clone(), scan)
+        }),
+        &classes,
+    );
+    if let Some(every) = args.stats.stats {
+        scheduler.record_stats(CycleStats::new(every));
+    }
+    match target {
+        Some(target) => plc::check_request_rate(
+            &format!("monitor of {}", target),
+            scheduler.requests_per_second(client),
+        ),
+        None => plc::check_request_rate("monitor", scheduler.requests_per_second(client)),
+    }
+    // Latest sample of every tag, as Sparkplug births carry all of them.
+    #[cfg(feature = "mqtt")]
+    let mut latest: HashMap<String, Sample> = HashMap::new();
+
+    // The session is closed by the caller once the scans stop.
+    while let Some(mut samples) = scheduler.poll(client).await? {
+        freeze.check(&mut samples);
+        let reported: Vec<Sample> = match deadband.as_mut() {
+            Some(filter) => samples
+                .iter()
+                .filter(|s| filter.passes(s))
+                .cloned()
+                .collect(),
+            None => samples.clone(),
+        };
+
+        for sample in &reported {
+            if let Some(template) = &args.template {
+                let fields = Fields {
+                    plc: target.unwrap_or(client.address()),
+                    tag: &sample.tag,
+                    value: &sample.value,
+                    state: settings.state_name(&sample.tag, &sample.value),
+                    quality: sample.quality,
+                    timestamp: sample.timestamp,
+                };
+                println!("{}", template.render(&fields));
+                continue;
+            }
+            let quality = match sample.quality {
+                Quality::Good => "".normal(),
+                Quality::Frozen => "    FROZEN".bold().yellow(),
+            };
+            let state = settings
+                .state_name(&sample.tag, &sample.value)
+                .map(|state| format!(" ({})", state))
+                .unwrap_or_default();
+            println!(
+                "[{}]    {}{}    {}{}{}",
+                sample.timestamp,
+                prefix,
+                sample.tag.bold(),
+                sample.value.to_string().bold().green(),
+                state.bold(),
+                quality
+            );
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(mqtt) = mqtt.as_mut() {
+            for sample in &samples {
+                latest.insert(sample.tag.clone(), sample.clone());
+            }
+            let all: Vec<Sample> = tags
+                .iter()
+                .filter_map(|tag| latest.get(tag).cloned())
+                .collect();
+            mqtt.publish(&all, &reported).await?;
+        }
+        #[cfg(feature = "influx")]
+        if let Some(influx) = &influx {
+            influx.push(&reported);
+        }
+        #[cfg(feature = "kafka")]
+        if let Some(kafka) = kafka.as_mut() {
+            kafka.push(&reported);
+        }
+        #[cfg(feature = "zabbix")]
+        if let Some(zabbix) = &zabbix {
+            zabbix.push(&reported);
+        }
+
+        #[cfg(feature = "notify")]
+        if let Some(notifier) = notifier.as_mut() {
+            notifier.tick();
+        }
+        if let Some(alarms) = alarms.as_mut() {
+            for mut event in alarms.evaluate(&samples)? {
+                // The topic prefix already names the controller.
+                #[cfg(feature = "mqtt")]
+                if let Some(mqtt) = mqtt.as_mut() {
+                    mqtt.publish_alarm(&event).await?;
+                }
+                if let Some(target) = target {
+                    event.name = format!("{}/{}", target, event.name);
+                }
+                event.print();
+                journal.record_alarm(&event)?;
+                hooks.run(client, &event, journal).await;
+                #[cfg(feature = "notify")]
+                if let Some(notifier) = notifier.as_mut() {
+                    notifier.notify(&event);
+                }
+            }
+        }
+    }
+    Ok(())
+}
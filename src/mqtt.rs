@@ -0,0 +1,402 @@
+use crate::alarm::AlarmEvent;
+use crate::monitor::Sample;
+use crate::sparkplug::{self, DataType, Metric, MetricValue, Payload};
+use crate::value::PlcValue;
+use anyhow::{bail, Result};
+use clap::Args;
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// MQTT output options shared by the polling commands.
+#[derive(Args, Debug, Clone)]
+pub struct MqttArgs {
+    /// MQTT broker to publish samples to, as host[:port].
+    #[arg(long)]
+    pub mqtt: Option<String>,
+    /// Topic prefix for plain MQTT publishing.
+    #[arg(long, default_value = "cobalt")]
+    pub mqtt_topic: String,
+    /// MQTT client id.
+    #[arg(long, default_value = "cobalt")]
+    pub mqtt_client_id: String,
+    /// Publish Sparkplug B NBIRTH/NDATA messages instead of plain JSON.
+    #[arg(long, requires = "mqtt")]
+    pub sparkplug: bool,
+    /// Sparkplug group id.
+    #[arg(long, default_value = "cobalt")]
+    pub sparkplug_group: String,
+    /// Sparkplug edge node id.
+    #[arg(long, default_value = "cobalt")]
+    pub sparkplug_node: String,
+}
+
+impl MqttArgs {
+    /// Options for one controller of a multi-target monitor, publishing
+    /// under its own topic prefix, client id and Sparkplug edge node.
+    pub fn for_target(&self, target: Option<&str>) -> Self {
+        let mut args = self.clone();
+        if let Some(target) = target {
+            args.mqtt_topic = format!("{}/{}", self.mqtt_topic.trim_end_matches('/'), target);
+            args.mqtt_client_id = format!("{}-{}", self.mqtt_client_id, target);
+            args.sparkplug_node = format!("{}-{}", self.sparkplug_node, target);
+        }
+        args
+    }
+}
+
+/// Publishes samples to an MQTT broker, either as plain JSON messages or as a
+/// Sparkplug B edge node.
+pub struct MqttPublisher {
+    client: AsyncClient,
+    prefix: String,
+    mode: Mode,
+}
+
+enum Mode {
+    Plain,
+    Sparkplug(SparkplugNode),
+}
+
+impl MqttPublisher {
+    /// Connect to the broker given in `args`. Returns `None` when MQTT output
+    /// is not enabled.
+    pub fn connect(args: &MqttArgs) -> Result<Option<Self>> {
+        let broker = match &args.mqtt {
+            Some(broker) => broker,
+            None => return Ok(None),
+        };
+        let (host, port) = match broker.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse()?),
+            None => (broker.as_str(), 1883),
+        };
+        if host.is_empty() {
+            bail!("invalid MQTT broker address: {}", broker);
+        }
+
+        let mut options = MqttOptions::new(&args.mqtt_client_id, host, port);
+        options.set_keep_alive(Duration::from_secs(30));
+
+        let rebirth = Arc::new(AtomicBool::new(true));
+        let mode = if args.sparkplug {
+            let node =
+                SparkplugNode::new(&args.sparkplug_group, &args.sparkplug_node, rebirth.clone());
+            options.set_last_will(node.death.will(now_millis()));
+            Mode::Sparkplug(node)
+        } else {
+            Mode::Plain
+        };
+
+        let (client, mut eventloop) = AsyncClient::new(options, 64);
+        let (command_topic, death) = match &mode {
+            Mode::Sparkplug(node) => (Some(node.topic("NCMD")), Some(node.death.clone())),
+            Mode::Plain => (None, None),
+        };
+        let subscriber = client.clone();
+        tokio::spawn(async move {
+            loop {
+                match eventloop.poll().await {
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        // A new session needs a new birth certificate.
+                        rebirth.store(true, Ordering::SeqCst);
+                        if let Some(topic) = &command_topic {
+                            let _ = subscriber.try_subscribe(topic, QoS::AtLeastOnce);
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        if sparkplug::is_rebirth_request(&publish.payload) {
+                            rebirth.store(true, Ordering::SeqCst);
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        tracing::warn!("MQTT connection error: {}", e);
+                        // The next session is born and dies with a bdSeq of
+                        // its own.
+                        if let Some(death) = &death {
+                            death.next_session();
+                            eventloop
+                                .mqtt_options
+                                .set_last_will(death.will(now_millis()));
+                        }
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                    }
+                }
+            }
+        });
+
+        Ok(Some(Self {
+            client,
+            prefix: args.mqtt_topic.trim_end_matches('/').to_owned(),
+            mode,
+        }))
+    }
+
+    /// Publish one polling cycle worth of samples. `reported` are the ones
+    /// that passed the deadband; Sparkplug births still carry every sample.
+    pub async fn publish(&mut self, samples: &[Sample], reported: &[Sample]) -> Result<()> {
+        match &mut self.mode {
+            Mode::Plain => {
+                for sample in reported {
+                    let payload = serde_json::json!({
+                        "timestamp": sample.timestamp.to_rfc3339(),
+                        "type": sample.value.type_name(),
+                        "value": serde_json::Value::from(sample.value.clone()),
+                        "quality": sample.quality,
+                    });
+                    self.client
+                        .publish(
+                            format!("{}/{}", self.prefix, sample.tag),
+                            QoS::AtLeastOnce,
+                            false,
+                            payload.to_string(),
+                        )
+                        .await?;
+                }
+            }
+            Mode::Sparkplug(node) => {
+                if let Some((topic, payload)) = node.message(samples, reported, now_millis()) {
+                    self.client
+                        .publish(topic, QoS::AtLeastOnce, false, payload.encode())
+                        .await?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish an alarm event as a retained JSON message under
+    /// `<prefix>/alarms/<name>`, in both plain and Sparkplug modes.
+    pub async fn publish_alarm(&mut self, event: &AlarmEvent) -> Result<()> {
+        self.client
+            .publish(
+                format!("{}/alarms/{}", self.prefix, event.name),
+                QoS::AtLeastOnce,
+                true,
+                serde_json::to_string(event)?,
+            )
+            .await?;
+        Ok(())
+    }
+}
+
+/// Death certificate of a Sparkplug B edge node. Its bdSeq goes up with each
+/// MQTT session, and the birth certificate of the session carries the same,
+/// so host applications can match a death to its birth.
+#[derive(Clone)]
+struct Death {
+    topic: String,
+    bd_seq: Arc<AtomicU64>,
+}
+
+impl Death {
+    fn bd_seq(&self) -> u64 {
+        self.bd_seq.load(Ordering::SeqCst)
+    }
+
+    /// Move on to the bdSeq of the next session, before reconnecting.
+    fn next_session(&self) {
+        self.bd_seq
+            .store((self.bd_seq() + 1) % 256, Ordering::SeqCst);
+    }
+
+    fn metric(&self, timestamp: u64) -> Metric {
+        Metric {
+            name: "bdSeq".to_owned(),
+            timestamp,
+            datatype: DataType::UInt64,
+            value: MetricValue::Long(self.bd_seq()),
+        }
+    }
+
+    /// The NDEATH the broker publishes when the session ends unexpectedly.
+    fn will(&self, timestamp: u64) -> LastWill {
+        let payload = Payload {
+            timestamp,
+            metrics: vec![self.metric(timestamp)],
+            seq: None,
+        };
+        LastWill::new(&self.topic, payload.encode(), QoS::AtLeastOnce, false)
+    }
+}
+
+/// Sparkplug B edge node state: sequence numbers and the last published value
+/// of each metric, so NDATA only carries changes.
+struct SparkplugNode {
+    group: String,
+    node: String,
+    seq: u64,
+    death: Death,
+    rebirth: Arc<AtomicBool>,
+    last: HashMap<String, PlcValue>,
+}
+
+impl SparkplugNode {
+    fn new(group: &str, node: &str, rebirth: Arc<AtomicBool>) -> Self {
+        let mut node = Self {
+            group: group.to_owned(),
+            node: node.to_owned(),
+            seq: 0,
+            death: Death {
+                topic: String::new(),
+                bd_seq: Arc::new(AtomicU64::new(0)),
+            },
+            rebirth,
+            last: HashMap::new(),
+        };
+        node.death.topic = node.topic("NDEATH");
+        node
+    }
+
+    fn topic(&self, message_type: &str) -> String {
+        format!(
+            "{}/{}/{}/{}",
+            sparkplug::NAMESPACE,
+            self.group,
+            message_type,
+            self.node
+        )
+    }
+
+    fn next_seq(&mut self) -> u64 {
+        let seq = self.seq;
+        self.seq = (self.seq + 1) % 256;
+        seq
+    }
+
+    /// Build the next message for a polling cycle: an NBIRTH carrying every
+    /// metric when a (re)birth is pending, otherwise an NDATA with the
+    /// reported metrics that changed. Returns `None` when nothing changed.
+    fn message(
+        &mut self,
+        samples: &[Sample],
+        reported: &[Sample],
+        timestamp: u64,
+    ) -> Option<(String, Payload)> {
+        if self.rebirth.swap(false, Ordering::SeqCst) {
+            self.seq = 0;
+            self.last.clear();
+            let mut metrics = vec![
+                self.death.metric(timestamp),
+                Metric {
+                    name: sparkplug::REBIRTH_METRIC.to_owned(),
+                    timestamp,
+                    datatype: DataType::Boolean,
+                    value: MetricValue::Boolean(false),
+                },
+            ];
+            for sample in samples {
+                metrics.extend(Metric::from_value(
+                    &sample.tag,
+                    sample.timestamp.timestamp_millis() as u64,
+                    sample.value.clone(),
+                ));
+                self.last.insert(sample.tag.clone(), sample.value.clone());
+            }
+            let payload = Payload {
+                timestamp,
+                metrics,
+                seq: Some(self.next_seq()),
+            };
+            return Some((self.topic("NBIRTH"), payload));
+        }
+
+        let mut metrics = Vec::new();
+        for sample in reported {
+            if self.last.get(&sample.tag) != Some(&sample.value) {
+                metrics.extend(Metric::from_value(
+                    &sample.tag,
+                    sample.timestamp.timestamp_millis() as u64,
+                    sample.value.clone(),
+                ));
+                self.last.insert(sample.tag.clone(), sample.value.clone());
+            }
+        }
+        if metrics.is_empty() {
+            return None;
+        }
+        let payload = Payload {
+            timestamp,
+            metrics,
+            seq: Some(self.next_seq()),
+        };
+        Some((self.topic("NDATA"), payload))
+    }
+}
+
+fn now_millis() -> u64 {
+    chrono::Utc::now().timestamp_millis() as u64
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::monitor::Quality;
+    use crate::value::Counter;
+
+    fn sample(tag: &str, value: PlcValue) -> Sample {
+        Sample {
+            timestamp: chrono::Local::now(),
+            tag: tag.to_owned(),
+            value,
+            quality: Quality::Good,
+        }
+    }
+
+    #[test]
+    fn births_and_deaths() {
+        let rebirth = Arc::new(AtomicBool::new(true));
+        let mut node = SparkplugNode::new("plant", "edge", rebirth.clone());
+        let samples = [sample("PT_101", PlcValue::Real(12.5))];
+
+        let (topic, birth) = node.message(&samples, &samples, 5).unwrap();
+        assert_eq!(topic, "spBv1.0/plant/NBIRTH/edge");
+        assert_eq!(birth.seq, Some(0));
+        assert_eq!(birth.metrics[0], node.death.metric(5));
+        assert_eq!(birth.metrics[0].value, MetricValue::Long(0));
+        assert_eq!(birth.metrics[2].name, "PT_101");
+        // Nothing changed, nothing to publish.
+        assert!(node.message(&samples, &samples, 6).is_none());
+        let changed = [sample("PT_101", PlcValue::Real(13.0))];
+        let (topic, data) = node.message(&changed, &changed, 7).unwrap();
+        assert_eq!(topic, "spBv1.0/plant/NDATA/edge");
+        assert_eq!(data.seq, Some(1));
+        assert_eq!(data.metrics.len(), 1);
+
+        // A new session dies with the next bdSeq, and is born with it.
+        let will = node.death.will(8);
+        assert_eq!(will.topic, "spBv1.0/plant/NDEATH/edge");
+        node.death.next_session();
+        let next = node.death.will(8);
+        assert_ne!(next.message, will.message);
+        let death = Payload {
+            timestamp: 8,
+            metrics: vec![node.death.metric(8)],
+            seq: None,
+        };
+        assert_eq!(next.message, death.encode());
+        rebirth.store(true, Ordering::SeqCst);
+        let (_, birth) = node.message(&changed, &changed, 9).unwrap();
+        assert_eq!(birth.seq, Some(0));
+        assert_eq!(birth.metrics[0].value, MetricValue::Long(1));
+
+        // Timers, counters and controls are published member by member.
+        let counter = PlcValue::Counter(Counter {
+            pre: 10,
+            acc: 3,
+            cu: true,
+            cd: false,
+            dn: false,
+            ov: false,
+            un: false,
+        });
+        let counted = [sample("C_1", counter)];
+        let (_, data) = node.message(&counted, &counted, 10).unwrap();
+        assert_eq!(data.metrics[1].name, "C_1/ACC");
+        assert_eq!(data.metrics[1].datatype, DataType::Int32);
+        assert_eq!(data.metrics[1].value, MetricValue::Int(3));
+        assert_eq!(data.metrics[2].datatype, DataType::Boolean);
+    }
+}
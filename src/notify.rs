@@ -0,0 +1,584 @@
+use crate::alarm::AlarmEventKind;
+use crate::tags::deserialize_duration;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+#[cfg(feature = "notify")]
+use {
+    crate::alarm::AlarmEvent,
+    anyhow::{bail, Context, Result},
+    std::collections::{HashMap, HashSet, VecDeque},
+    std::time::Instant,
+    tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader},
+    tokio::net::TcpStream,
+    tokio::sync::mpsc,
+};
+
+/// Environment variable holding the SMTP password when the rules file
+/// doesn't, so it can stay out of version control.
+#[cfg(feature = "notify")]
+const PASSWORD_ENV: &str = "COBALT_SMTP_PASSWORD";
+
+/// Time the webhook and the mail server have to answer.
+#[cfg(feature = "notify")]
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The `[notify]` table of an alarm rules file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotifyConfig {
+    /// Alarm events that are notified.
+    #[serde(default = "default_events")]
+    pub events: Vec<AlarmEventKind>,
+    /// Time an alarm has to stay raised before it is notified. Alarms
+    /// cleared sooner are not notified at all.
+    #[serde(default, deserialize_with = "deserialize_duration")]
+    pub delay: Duration,
+    /// Shortest time between two notifications of the same event of an
+    /// alarm, so a flapping value notifies once.
+    #[serde(default = "default_holdoff", deserialize_with = "deserialize_duration")]
+    pub holdoff: Duration,
+    /// Most notifications sent in an hour, all alarms together.
+    #[serde(default = "default_max_per_hour")]
+    pub max_per_hour: usize,
+    pub webhook: Option<WebhookConfig>,
+    pub email: Option<EmailConfig>,
+}
+
+fn default_events() -> Vec<AlarmEventKind> {
+    vec![AlarmEventKind::Raised, AlarmEventKind::Cleared]
+}
+
+fn default_holdoff() -> Duration {
+    Duration::from_secs(5 * 60)
+}
+
+fn default_max_per_hour() -> usize {
+    30
+}
+
+/// URL the events are posted to as JSON.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Extra request headers, e.g. `Authorization`.
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+/// Mail server and recipients of the events.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EmailConfig {
+    pub server: String,
+    /// 465 with `tls`, 587 with `starttls` and 25 with `none` by default.
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub tls: SmtpTls,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub from: String,
+    pub to: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SmtpTls {
+    /// TLS from the start of the connection.
+    Tls,
+    /// Plain connection upgraded with STARTTLS.
+    #[default]
+    Starttls,
+    /// No encryption, for relays on the local network only.
+    None,
+}
+
+impl EmailConfig {
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(match self.tls {
+            SmtpTls::Tls => 465,
+            SmtpTls::Starttls => 587,
+            SmtpTls::None => 25,
+        })
+    }
+}
+
+/// Decides which alarm events are notified, holding back raised alarms for
+/// the delay and dropping repeats within the holdoff and beyond the hourly
+/// limit.
+#[cfg(feature = "notify")]
+struct Throttle {
+    events: Vec<AlarmEventKind>,
+    delay: Duration,
+    holdoff: Duration,
+    max_per_hour: usize,
+    /// Raised alarms waiting out the delay.
+    delayed: Vec<(Instant, AlarmEvent)>,
+    /// Last notification of each event of each alarm.
+    last: HashMap<(String, AlarmEventKind), Instant>,
+    /// Alarms whose raise wasn't notified, so neither is their clear.
+    silenced: HashSet<String>,
+    /// Notifications sent in the last hour.
+    recent: VecDeque<Instant>,
+    /// Notifications dropped since the hourly limit was reached.
+    dropped: usize,
+}
+
+#[cfg(feature = "notify")]
+impl Throttle {
+    fn new(config: &NotifyConfig) -> Self {
+        Self {
+            events: config.events.clone(),
+            delay: config.delay,
+            holdoff: config.holdoff,
+            max_per_hour: config.max_per_hour,
+            delayed: Vec::new(),
+            last: HashMap::new(),
+            silenced: HashSet::new(),
+            recent: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    /// Whether an event of the alarm engine is notified now.
+    fn event(&mut self, event: AlarmEvent, now: Instant) -> Option<AlarmEvent> {
+        match event.event {
+            AlarmEventKind::Raised if !self.delay.is_zero() => {
+                self.delayed.push((now, event));
+                return None;
+            }
+            AlarmEventKind::Cleared => {
+                if let Some(idx) = self.delayed.iter().position(|(_, e)| e.name == event.name) {
+                    self.delayed.remove(idx);
+                    return None;
+                }
+                if self.silenced.remove(&event.name) {
+                    return None;
+                }
+            }
+            _ => {}
+        }
+        self.admit(event, now)
+    }
+
+    /// Raised alarms whose delay is over.
+    fn due(&mut self, now: Instant) -> Vec<AlarmEvent> {
+        let mut due = Vec::new();
+        let mut idx = 0;
+        while idx < self.delayed.len() {
+            if now.duration_since(self.delayed[idx].0) >= self.delay {
+                let (_, event) = self.delayed.remove(idx);
+                due.extend(self.admit(event, now));
+            } else {
+                idx += 1;
+            }
+        }
+        due
+    }
+
+    fn admit(&mut self, event: AlarmEvent, now: Instant) -> Option<AlarmEvent> {
+        let raised = event.event == AlarmEventKind::Raised;
+        if !self.events.contains(&event.event) {
+            if raised {
+                self.silenced.insert(event.name);
+            }
+            return None;
+        }
+        let key = (event.name.clone(), event.event);
+        if let Some(last) = self.last.get(&key) {
+            if now.duration_since(*last) < self.holdoff {
+                if raised {
+                    self.silenced.insert(event.name);
+                }
+                return None;
+            }
+        }
+        while let Some(sent) = self.recent.front() {
+            if now.duration_since(*sent) < Duration::from_secs(3600) {
+                break;
+            }
+            self.recent.pop_front();
+        }
+        if self.recent.len() >= self.max_per_hour {
+            if self.dropped == 0 {
+                tracing::warn!(
+                    "{} alarm notifications sent in the last hour, dropping more",
+                    self.max_per_hour
+                );
+            }
+            self.dropped += 1;
+            if raised {
+                self.silenced.insert(event.name);
+            }
+            return None;
+        }
+        if self.dropped > 0 {
+            tracing::warn!("{} alarm notifications were dropped", self.dropped);
+            self.dropped = 0;
+        }
+        self.recent.push_back(now);
+        self.last.insert(key, now);
+        Some(event)
+    }
+}
+
+/// Sends alarm events to a webhook and by email from a background task, so
+/// a slow server never stalls polling.
+#[cfg(feature = "notify")]
+pub struct Notifier {
+    throttle: Throttle,
+    tx: mpsc::UnboundedSender<AlarmEvent>,
+}
+
+#[cfg(feature = "notify")]
+impl Notifier {
+    pub fn start(config: &NotifyConfig) -> Result<Self> {
+        if config.webhook.is_none() && config.email.is_none() {
+            bail!("[notify] needs a webhook or an email table");
+        }
+        let webhook = match &config.webhook {
+            Some(webhook) => Some((
+                reqwest::Url::parse(&webhook.url)
+                    .with_context(|| format!("invalid webhook URL {}", webhook.url))?,
+                webhook.headers.clone(),
+            )),
+            None => None,
+        };
+        let email = config.email.clone().map(|mut email| {
+            if email.password.is_none() {
+                email.password = std::env::var(PASSWORD_ENV).ok();
+            }
+            email
+        });
+        let client = reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?;
+
+        let (tx, mut rx) = mpsc::unbounded_channel::<AlarmEvent>();
+        tokio::spawn(async move {
+            while let Some(event) = rx.recv().await {
+                if let Some((url, headers)) = &webhook {
+                    if let Err(e) = post(&client, url, headers, &event).await {
+                        tracing::error!("webhook notification of {} failed: {:#}", event.name, e);
+                    }
+                }
+                if let Some(email) = &email {
+                    let send = tokio::time::timeout(SEND_TIMEOUT, send_mail(email, &event));
+                    match send.await {
+                        Ok(Ok(())) => {}
+                        Ok(Err(e)) => {
+                            tracing::error!("email notification of {} failed: {:#}", event.name, e)
+                        }
+                        Err(_) => tracing::error!(
+                            "email notification of {} failed: no answer from {}",
+                            event.name,
+                            email.server
+                        ),
+                    }
+                }
+            }
+        });
+
+        Ok(Self {
+            throttle: Throttle::new(config),
+            tx,
+        })
+    }
+
+    /// Notify an event of the alarm engine, unless throttled.
+    pub fn notify(&mut self, event: &AlarmEvent) {
+        if let Some(event) = self.throttle.event(event.clone(), Instant::now()) {
+            let _ = self.tx.send(event);
+        }
+    }
+
+    /// Notify the raised alarms whose delay is over, called every cycle.
+    pub fn tick(&mut self) {
+        for event in self.throttle.due(Instant::now()) {
+            let _ = self.tx.send(event);
+        }
+    }
+}
+
+#[cfg(feature = "notify")]
+async fn post(
+    client: &reqwest::Client,
+    url: &reqwest::Url,
+    headers: &BTreeMap<String, String>,
+    event: &AlarmEvent,
+) -> Result<()> {
+    let mut request = client
+        .post(url.clone())
+        .header("Content-Type", "application/json")
+        .body(serde_json::to_string(event)?);
+    for (name, value) in headers {
+        request = request.header(name, value);
+    }
+    let response = request.send().await?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().await.unwrap_or_default();
+        bail!("{} {}", status, text.trim());
+    }
+    Ok(())
+}
+
+#[cfg(feature = "notify")]
+fn label(kind: AlarmEventKind) -> &'static str {
+    match kind {
+        AlarmEventKind::Raised => "ALARM",
+        AlarmEventKind::Cleared => "CLEARED",
+        AlarmEventKind::Acknowledged => "ACKED",
+        AlarmEventKind::Shelved => "SHELVED",
+        AlarmEventKind::Unshelved => "UNSHELVED",
+    }
+}
+
+/// The mail of an event, headers included, with CRLF line endings and
+/// lines starting with a dot doubled for the DATA command.
+#[cfg(feature = "notify")]
+fn mail(config: &EmailConfig, event: &AlarmEvent) -> String {
+    use base64::Engine;
+
+    let subject = format!("[cobalt] {} {}", label(event.event), event.name)
+        .replace(|c: char| c.is_control(), " ");
+    let subject = if subject.is_ascii() {
+        subject
+    } else {
+        let encoded = base64::engine::general_purpose::STANDARD.encode(subject);
+        format!("=?utf-8?B?{}?=", encoded)
+    };
+    let mut body = format!("Alarm:    {}\nEvent:    {:?}\n", event.name, event.event);
+    if let Some(value) = event.value {
+        body.push_str(&format!("Value:    {}\n", value));
+    }
+    if !event.message.is_empty() {
+        body.push_str(&format!("Message:  {}\n", event.message));
+    }
+    body.push_str(&format!(
+        "Time:     {}\n",
+        event.timestamp.with_timezone(&chrono::Local).to_rfc2822()
+    ));
+
+    let mut text = format!(
+        "From: {}\r\nTo: {}\r\nSubject: {}\r\nDate: {}\r\nMIME-Version: 1.0\r\n\
+         Content-Type: text/plain; charset=utf-8\r\nContent-Transfer-Encoding: 8bit\r\n\r\n",
+        config.from,
+        config.to.join(", "),
+        subject,
+        chrono::Local::now().to_rfc2822()
+    );
+    for line in body.lines() {
+        if line.starts_with('.') {
+            text.push('.');
+        }
+        text.push_str(line);
+        text.push_str("\r\n");
+    }
+    text
+}
+
+/// Deliver the mail of an event over SMTP.
+#[cfg(feature = "notify")]
+async fn send_mail(config: &EmailConfig, event: &AlarmEvent) -> Result<()> {
+    let stream = TcpStream::connect((config.server.as_str(), config.port()))
+        .await
+        .with_context(|| format!("failed to connect to {}", config.server))?;
+    match config.tls {
+        SmtpTls::Tls => {
+            let stream = tls(&config.server, stream).await?;
+            let mut smtp = Smtp::new(stream);
+            smtp.expect(&[220]).await?;
+            smtp.command("EHLO localhost", &[250]).await?;
+            smtp.deliver(config, event).await
+        }
+        SmtpTls::Starttls => {
+            let mut smtp = Smtp::new(stream);
+            smtp.expect(&[220]).await?;
+            smtp.command("EHLO localhost", &[250]).await?;
+            smtp.command("STARTTLS", &[220]).await?;
+            let stream = tls(&config.server, smtp.stream.into_inner()).await?;
+            let mut smtp = Smtp::new(stream);
+            smtp.command("EHLO localhost", &[250]).await?;
+            smtp.deliver(config, event).await
+        }
+        SmtpTls::None => {
+            let mut smtp = Smtp::new(stream);
+            smtp.expect(&[220]).await?;
+            smtp.command("EHLO localhost", &[250]).await?;
+            smtp.deliver(config, event).await
+        }
+    }
+}
+
+/// TLS session with the mail server, checked against the Mozilla roots.
+#[cfg(feature = "notify")]
+async fn tls(
+    server: &str,
+    stream: TcpStream,
+) -> Result<tokio_rustls::client::TlsStream<TcpStream>> {
+    use tokio_rustls::rustls::{self, pki_types::ServerName};
+
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    let name = ServerName::try_from(server.to_owned())
+        .with_context(|| format!("invalid mail server name {}", server))?;
+    tokio_rustls::TlsConnector::from(std::sync::Arc::new(config))
+        .connect(name, stream)
+        .await
+        .with_context(|| format!("TLS handshake with {} failed", server))
+}
+
+#[cfg(feature = "notify")]
+struct Smtp<S> {
+    stream: BufReader<S>,
+}
+
+#[cfg(feature = "notify")]
+impl<S: AsyncRead + AsyncWrite + Unpin> Smtp<S> {
+    fn new(stream: S) -> Self {
+        Self {
+            stream: BufReader::new(stream),
+        }
+    }
+
+    /// Read a reply, failing unless its code is one of `codes`.
+    async fn expect(&mut self, codes: &[u16]) -> Result<String> {
+        let mut reply = String::new();
+        loop {
+            let mut line = String::new();
+            if self.stream.read_line(&mut line).await? == 0 {
+                bail!("connection closed by the mail server");
+            }
+            reply.push_str(&line);
+            // `250-` continues a multiline reply, `250 ` ends it.
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        }
+        let code = reply.get(..3).and_then(|code| code.parse::<u16>().ok());
+        match code {
+            Some(code) if codes.contains(&code) => Ok(reply),
+            _ => bail!("{}", reply.trim()),
+        }
+    }
+
+    async fn command(&mut self, command: &str, codes: &[u16]) -> Result<String> {
+        self.stream
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .await?;
+        // Only the verb, as AUTH carries the credentials.
+        let verb = command.split(' ').next().unwrap_or_default();
+        self.expect(codes)
+            .await
+            .with_context(|| format!("{} failed", verb))
+    }
+
+    async fn deliver(&mut self, config: &EmailConfig, event: &AlarmEvent) -> Result<()> {
+        use base64::Engine;
+
+        if let Some(username) = &config.username {
+            let password = config.password.as_deref().unwrap_or_default();
+            let credentials = format!("\0{}\0{}", username, password);
+            let credentials = base64::engine::general_purpose::STANDARD.encode(credentials);
+            self.command(&format!("AUTH PLAIN {}", credentials), &[235])
+                .await?;
+        }
+        self.command(&format!("MAIL FROM:<{}>", config.from), &[250])
+            .await?;
+        for to in &config.to {
+            self.command(&format!("RCPT TO:<{}>", to), &[250, 251])
+                .await?;
+        }
+        self.command("DATA", &[354]).await?;
+        self.stream
+            .write_all(mail(config, event).as_bytes())
+            .await?;
+        self.command(".", &[250]).await?;
+        // The mail is accepted, a failed goodbye doesn't matter.
+        let _ = self.command("QUIT", &[221]).await;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "notify"))]
+mod test {
+    use super::*;
+    use crate::alarm::AlarmState;
+    use chrono::Utc;
+
+    fn event(name: &str, kind: AlarmEventKind) -> AlarmEvent {
+        AlarmEvent {
+            timestamp: Utc::now(),
+            name: name.to_owned(),
+            event: kind,
+            message: ".Line pressure high".to_owned(),
+            value: Some(82.5),
+            state: AlarmState::default(),
+        }
+    }
+
+    fn config(text: &str) -> NotifyConfig {
+        toml::from_str(text).unwrap()
+    }
+
+    #[test]
+    fn throttling() {
+        use AlarmEventKind::*;
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+
+        // Flapping within the holdoff notifies once.
+        let mut throttle = Throttle::new(&config(r#"holdoff = "1m""#));
+        assert!(throttle.event(event("PT", Raised), at(0)).is_some());
+        assert!(throttle.event(event("PT", Cleared), at(1)).is_some());
+        assert!(throttle.event(event("PT", Raised), at(2)).is_none());
+        assert!(throttle.event(event("PT", Cleared), at(3)).is_none());
+        assert!(throttle.event(event("PT", Raised), at(61)).is_some());
+        assert!(throttle.event(event("PT", Acknowledged), at(62)).is_none());
+
+        // Alarms cleared within the delay are not notified.
+        let mut throttle = Throttle::new(&config(r#"delay = "10s""#));
+        assert!(throttle.event(event("PT", Raised), at(0)).is_none());
+        assert!(throttle.event(event("TT", Raised), at(0)).is_none());
+        assert!(throttle.event(event("PT", Cleared), at(5)).is_none());
+        assert!(throttle.due(at(9)).is_empty());
+        let due = throttle.due(at(10));
+        assert_eq!(due.len(), 1);
+        assert_eq!(due[0].name, "TT");
+        assert!(throttle.event(event("TT", Cleared), at(20)).is_some());
+
+        let mut throttle = Throttle::new(&config("max_per_hour = 2"));
+        assert!(throttle.event(event("A", Raised), at(0)).is_some());
+        assert!(throttle.event(event("B", Raised), at(0)).is_some());
+        assert!(throttle.event(event("C", Raised), at(0)).is_none());
+        assert!(throttle.event(event("C", Cleared), at(1)).is_none());
+        assert!(throttle.event(event("D", Raised), at(3600)).is_some());
+    }
+
+    #[test]
+    fn mails() {
+        let email: EmailConfig = toml::from_str(
+            r#"
+            server = "smtp.example.com"
+            from = "cobalt@example.com"
+            to = ["ops@example.com", "night@example.com"]
+            "#,
+        )
+        .unwrap();
+        assert_eq!(email.port(), 587);
+        let text = mail(&email, &event("PT_101 > 80", AlarmEventKind::Raised));
+        assert!(text.contains("\r\nTo: ops@example.com, night@example.com\r\n"));
+        assert!(text.contains("\r\nSubject: [cobalt] ALARM PT_101 > 80\r\n"));
+        assert!(text.contains("\r\nValue:    82.5\r\n"));
+        assert!(text.contains("\r\nMessage:  .Line pressure high\r\n"));
+
+        let text = mail(&email, &event("Tür offen", AlarmEventKind::Cleared));
+        assert!(text.contains("\r\nSubject: =?utf-8?B?"));
+        let mut dotted = event("PT", AlarmEventKind::Raised);
+        dotted.message = "Check the transmitter\n.".to_owned();
+        assert!(mail(&email, &dotted).contains("\r\n..\r\n"));
+    }
+}
@@ -0,0 +1,587 @@
+use crate::failure::Failure;
+use crate::plc::{self, Plc};
+use crate::value::{Control, Counter, PlcValue, Timer};
+use anyhow::{anyhow, bail, Result};
+use bytes::{BufMut, Bytes, BytesMut};
+use rseip::cip::{MessageReply, MessageRequest};
+use rseip::precludes::*;
+use rseip::BytesHolder;
+use std::fmt::Display;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+/// PCCC object class, executing the PCCC commands it is sent.
+const CLASS_PCCC: u16 = 0x67;
+
+/// Execute PCCC service.
+const SERVICE_EXECUTE_PCCC: u8 = 0x4B;
+
+/// Requestor id sent ahead of every command: its length, a vendor id and a
+/// serial number. Processors echo it back.
+const REQUESTOR_ID: [u8; 7] = [7, 0x4D, 0x00, 0x2A, 0x17, 0xC0, 0x0B];
+
+/// Command of the protected typed logical functions.
+const CMD_TYPED: u8 = 0x0F;
+
+/// Protected typed logical read with three address fields.
+const FNC_READ: u8 = 0xA2;
+
+/// Protected typed logical write with three address fields.
+const FNC_WRITE: u8 = 0xAA;
+
+/// Protected typed logical masked write with three address fields, setting
+/// only the bits of its mask.
+const FNC_MASKED_WRITE: u8 = 0xAB;
+
+/// Status of replies carrying an extended status after the transaction
+/// number.
+const STS_EXTENDED: u8 = 0xF0;
+
+static TRANSACTION: AtomicU16 = AtomicU16::new(1);
+
+/// Data file types, with their PCCC codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FileType {
+    Output,
+    Input,
+    Status,
+    Bit,
+    Timer,
+    Counter,
+    Control,
+    Integer,
+    Float,
+    Long,
+}
+
+impl FileType {
+    fn from_prefix(prefix: &str) -> Option<Self> {
+        Some(match prefix {
+            "O" => FileType::Output,
+            "I" => FileType::Input,
+            "S" => FileType::Status,
+            "B" => FileType::Bit,
+            "T" => FileType::Timer,
+            "C" => FileType::Counter,
+            "R" => FileType::Control,
+            "N" => FileType::Integer,
+            "F" => FileType::Float,
+            "L" => FileType::Long,
+            _ => return None,
+        })
+    }
+
+    fn code(self) -> u8 {
+        match self {
+            FileType::Output => 0x8B,
+            FileType::Input => 0x8C,
+            FileType::Status => 0x84,
+            FileType::Bit => 0x85,
+            FileType::Timer => 0x86,
+            FileType::Counter => 0x87,
+            FileType::Control => 0x88,
+            FileType::Integer => 0x89,
+            FileType::Float => 0x8A,
+            FileType::Long => 0x91,
+        }
+    }
+
+    /// File number of the files with a fixed one, which addresses may leave
+    /// out, e.g. `I:0/3`.
+    fn default_file(self) -> Option<u16> {
+        match self {
+            FileType::Output => Some(0),
+            FileType::Input => Some(1),
+            FileType::Status => Some(2),
+            _ => None,
+        }
+    }
+
+    /// Bytes of an element.
+    fn element_size(self) -> u8 {
+        match self {
+            FileType::Timer | FileType::Counter | FileType::Control => 6,
+            FileType::Float | FileType::Long => 4,
+            _ => 2,
+        }
+    }
+
+    /// Sub-element of the named members of timers, counters and controls,
+    /// with the bit of the status bits in word 0.
+    fn member(self, name: &str) -> Option<(u16, Option<u8>)> {
+        let member = match (self, name) {
+            (FileType::Timer | FileType::Counter, "PRE") => (1, None),
+            (FileType::Timer | FileType::Counter, "ACC") => (2, None),
+            (FileType::Control, "LEN") => (1, None),
+            (FileType::Control, "POS") => (2, None),
+            (FileType::Timer | FileType::Control, "EN") => (0, Some(15)),
+            (FileType::Timer, "TT") => (0, Some(14)),
+            (FileType::Timer | FileType::Counter | FileType::Control, "DN") => (0, Some(13)),
+            (FileType::Counter, "CU") => (0, Some(15)),
+            (FileType::Counter, "CD") => (0, Some(14)),
+            (FileType::Counter, "OV") => (0, Some(12)),
+            (FileType::Counter, "UN") => (0, Some(11)),
+            (FileType::Counter, "UA") => (0, Some(10)),
+            (FileType::Control, "EU") => (0, Some(14)),
+            (FileType::Control, "EM") => (0, Some(12)),
+            (FileType::Control, "ER") => (0, Some(11)),
+            (FileType::Control, "UL") => (0, Some(10)),
+            (FileType::Control, "IN") => (0, Some(9)),
+            (FileType::Control, "FD") => (0, Some(8)),
+            _ => return None,
+        };
+        Some(member)
+    }
+}
+
+/// A data table address, e.g. `N7:0`, `F8:3`, `B3:1/4`, `B3/20`, `I:0/3` or
+/// `T4:0.ACC`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Address {
+    file_type: FileType,
+    file: u16,
+    element: u16,
+    sub_element: u16,
+    bit: Option<u8>,
+}
+
+impl Address {
+    fn parse(text: &str) -> Result<Self> {
+        let invalid = || anyhow!("`{}` is not a data table address like N7:0", text);
+        let upper = text.trim().to_ascii_uppercase();
+        let prefix_len = upper
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(upper.len());
+        let file_type = FileType::from_prefix(&upper[..prefix_len]).ok_or_else(invalid)?;
+        let rest = &upper[prefix_len..];
+
+        // `B3/20` numbers the bits across the whole file.
+        if let (FileType::Bit, Some((file, bit))) = (file_type, rest.split_once('/')) {
+            if !file.contains(':') {
+                let file = file.parse().map_err(|_| invalid())?;
+                let bit: u16 = bit.parse().map_err(|_| invalid())?;
+                return Ok(Self {
+                    file_type,
+                    file,
+                    element: bit / 16,
+                    sub_element: 0,
+                    bit: Some((bit % 16) as u8),
+                });
+            }
+        }
+
+        let (file, rest) = rest.split_once(':').ok_or_else(invalid)?;
+        let file = match file {
+            "" => file_type.default_file().ok_or_else(invalid)?,
+            file => file.parse().map_err(|_| invalid())?,
+        };
+        let (rest, bit) = match rest.split_once('/') {
+            Some((rest, bit)) => (rest, Some(bit.parse::<u8>().map_err(|_| invalid())?)),
+            None => (rest, None),
+        };
+        let (element, member) = match rest.split_once('.') {
+            Some((element, member)) => (element, Some(member)),
+            None => (rest, None),
+        };
+        let element = element.parse().map_err(|_| invalid())?;
+        let (sub_element, bit) = match (member, bit) {
+            (None, bit) => (0, bit),
+            (Some(member), None) => file_type.member(member).ok_or_else(|| {
+                anyhow!(
+                    "{:?} files have no member {} in `{}`",
+                    file_type,
+                    member,
+                    text
+                )
+            })?,
+            (Some(_), Some(_)) => return Err(invalid()),
+        };
+        let address = Self {
+            file_type,
+            file,
+            element,
+            sub_element,
+            bit,
+        };
+        let bits = match file_type {
+            FileType::Float => 0,
+            FileType::Long => 32,
+            _ => 16,
+        };
+        if address.bit.is_some_and(|bit| bit >= bits) {
+            bail!("`{}` addresses a bit past the end of its element", text);
+        }
+        Ok(address)
+    }
+
+    /// Whether the address is a whole timer, counter or control.
+    fn is_structure(&self) -> bool {
+        self.sub_element == 0
+            && self.bit.is_none()
+            && matches!(
+                self.file_type,
+                FileType::Timer | FileType::Counter | FileType::Control
+            )
+    }
+
+    /// Bytes read or written at the address.
+    fn size(&self) -> u8 {
+        match self.file_type {
+            _ if self.is_structure() => self.file_type.element_size(),
+            FileType::Float | FileType::Long => 4,
+            _ => 2,
+        }
+    }
+
+    /// File number, file type, element and sub-element, each number taking
+    /// a byte, or 0xFF and two bytes from 255 on.
+    fn encode(&self, out: &mut BytesMut) {
+        fn field(out: &mut BytesMut, value: u16) {
+            if value < 0xFF {
+                out.put_u8(value as u8);
+            } else {
+                out.put_u8(0xFF);
+                out.put_u16_le(value);
+            }
+        }
+        field(out, self.file);
+        out.put_u8(self.file_type.code());
+        field(out, self.element);
+        field(out, self.sub_element);
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<PlcValue> {
+        let size = self.size() as usize;
+        if data.len() < size {
+            bail!("expected {} bytes of data, got {}", size, data.len());
+        }
+        let word = |idx: usize| i16::from_le_bytes([data[idx * 2], data[idx * 2 + 1]]);
+        let bit = |bits: i16, pos: u8| bits & (1 << pos) != 0;
+        let value = match self.file_type {
+            FileType::Float => PlcValue::Real(f32::from_le_bytes(data[..4].try_into()?)),
+            FileType::Long => {
+                let long = i32::from_le_bytes(data[..4].try_into()?);
+                match self.bit {
+                    Some(pos) => PlcValue::Bool(long & (1 << pos) != 0),
+                    None => PlcValue::Dint(long),
+                }
+            }
+            FileType::Timer if self.is_structure() => PlcValue::Timer(Timer {
+                pre: word(1) as i32,
+                acc: word(2) as i32,
+                en: bit(word(0), 15),
+                tt: bit(word(0), 14),
+                dn: bit(word(0), 13),
+            }),
+            FileType::Counter if self.is_structure() => PlcValue::Counter(Counter {
+                pre: word(1) as i32,
+                acc: word(2) as i32,
+                cu: bit(word(0), 15),
+                cd: bit(word(0), 14),
+                dn: bit(word(0), 13),
+                ov: bit(word(0), 12),
+                un: bit(word(0), 11),
+            }),
+            FileType::Control if self.is_structure() => PlcValue::Control(Control {
+                len: word(1) as i32,
+                pos: word(2) as i32,
+                en: bit(word(0), 15),
+                eu: bit(word(0), 14),
+                dn: bit(word(0), 13),
+                em: bit(word(0), 12),
+                er: bit(word(0), 11),
+                ul: bit(word(0), 10),
+                inhibit: bit(word(0), 9),
+                fd: bit(word(0), 8),
+            }),
+            _ => match self.bit {
+                Some(pos) => PlcValue::Bool(bit(word(0), pos)),
+                None => PlcValue::Int(word(0)),
+            },
+        };
+        Ok(value)
+    }
+
+    /// Data of a write of the value: the mask and the value of the bits for
+    /// masked writes, the value for others.
+    fn encode_value(&self, value: PlcValue, out: &mut BytesMut) -> Result<()> {
+        let mismatch = || {
+            anyhow!(
+                "{:?} file elements can't take the {} {}",
+                self.file_type,
+                value.type_name(),
+                value
+            )
+        };
+        match (self.file_type, self.bit, &value) {
+            (FileType::Long, Some(pos), &PlcValue::Bool(set)) => {
+                out.put_u32_le(1 << pos);
+                out.put_u32_le(if set { 1 << pos } else { 0 });
+            }
+            (_, Some(pos), &PlcValue::Bool(set)) => {
+                out.put_u16_le(1 << pos);
+                out.put_u16_le(if set { 1 << pos } else { 0 });
+            }
+            (_, Some(_), _) => return Err(mismatch()),
+            _ if self.is_structure() => {
+                bail!("structures can't be written whole, write their members instead")
+            }
+            (FileType::Float, None, &PlcValue::Real(value)) => out.put_f32_le(value),
+            (FileType::Long, None, &PlcValue::Dint(value)) => out.put_i32_le(value),
+            (FileType::Float | FileType::Long, None, _) => return Err(mismatch()),
+            (_, None, &PlcValue::Int(value)) => out.put_i16_le(value),
+            _ => return Err(mismatch()),
+        }
+        Ok(())
+    }
+}
+
+/// Read a data table address.
+pub async fn read(client: &mut Plc, text: &str) -> Result<PlcValue> {
+    let address = Address::parse(text)?;
+    let mut data = BytesMut::new();
+    data.put_u8(address.size());
+    address.encode(&mut data);
+    let reply = execute(client, "pccc read", text, FNC_READ, data).await?;
+    address.decode(&reply)
+}
+
+/// Write a value to a data table address, with a masked write for bits so
+/// the rest of their word is left alone.
+pub async fn write(client: &mut Plc, text: &str, value: PlcValue) -> Result<()> {
+    let address = Address::parse(text)?;
+    let mut data = BytesMut::new();
+    data.put_u8(address.size());
+    address.encode(&mut data);
+    address.encode_value(value, &mut data)?;
+    let function = match address.bit {
+        Some(_) => FNC_MASKED_WRITE,
+        None => FNC_WRITE,
+    };
+    execute(client, "pccc write", text, function, data).await?;
+    Ok(())
+}
+
+/// Send a typed logical command and return the data of its reply.
+async fn execute(
+    client: &mut Plc,
+    service: &'static str,
+    tag: &str,
+    function: u8,
+    data: BytesMut,
+) -> Result<Bytes> {
+    let tns = TRANSACTION.fetch_add(1, Ordering::Relaxed);
+    let request = command(tns, function, &data);
+    let path = EPath::default().with_class(CLASS_PCCC).with_instance(1);
+    let reply: MessageReply<BytesHolder> = plc::transaction(
+        service,
+        tag,
+        client
+            .eip()?
+            .send(MessageRequest::new(SERVICE_EXECUTE_PCCC, path, request)),
+    )
+    .await
+    .map_err(|e| anyhow!("{} of {} failed: {}", service, tag, e))?;
+    parse_reply(tns, reply.data.into()).map_err(|e| anyhow!("{}: {}", tag, e))
+}
+
+fn command(tns: u16, function: u8, data: &[u8]) -> Bytes {
+    let mut out = BytesMut::with_capacity(REQUESTOR_ID.len() + 5 + data.len());
+    out.put_slice(&REQUESTOR_ID);
+    out.put_u8(CMD_TYPED);
+    out.put_u8(0);
+    out.put_u16_le(tns);
+    out.put_u8(function);
+    out.put_slice(data);
+    out.freeze()
+}
+
+/// Check the status of a reply to the transaction and return its data.
+fn parse_reply(tns: u16, reply: Bytes) -> Result<Bytes> {
+    let id_len = *reply.first().ok_or_else(|| anyhow!("empty PCCC reply"))? as usize;
+    let header = id_len + 4;
+    if reply.len() < header {
+        bail!("PCCC reply too short ({} bytes)", reply.len());
+    }
+    let (cmd, sts) = (reply[id_len], reply[id_len + 1]);
+    let reply_tns = u16::from_le_bytes([reply[id_len + 2], reply[id_len + 3]]);
+    if cmd != CMD_TYPED | 0x40 || reply_tns != tns {
+        bail!(
+            "unexpected PCCC reply (command {:#04x}, transaction {})",
+            cmd,
+            reply_tns
+        );
+    }
+    match sts {
+        0 => Ok(reply.slice(header..)),
+        STS_EXTENDED => match reply.get(header) {
+            Some(&ext) => Err(Status::Extended(ext).into()),
+            None => Err(Status::Remote(sts).into()),
+        },
+        _ => Err(Status::Remote(sts).into()),
+    }
+}
+
+/// Error status of a PCCC reply.
+enum Status {
+    Remote(u8),
+    Extended(u8),
+}
+
+impl From<Status> for anyhow::Error {
+    fn from(status: Status) -> Self {
+        let failure = match status {
+            Status::Remote(0x50..=0x5F) => Failure::TagNotFound,
+            Status::Remote(0x60..=0x6F) => Failure::WriteRejected,
+            Status::Remote(0x30..=0x3F) => Failure::Connection,
+            Status::Extended(0x02..=0x06) => Failure::TagNotFound,
+            Status::Extended(0x0B | 0x10) => Failure::WriteRejected,
+            Status::Extended(0x11 | 0x17) => Failure::TypeMismatch,
+            _ => Failure::Error,
+        };
+        failure.error(status)
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (code, text) = match *self {
+            Status::Remote(sts) => (
+                sts,
+                match sts & 0xF0 {
+                    0x10 => "illegal command or format",
+                    0x20 => "host has a problem and will not communicate",
+                    0x30 => "remote node host is missing, disconnected or shut down",
+                    0x40 => "host could not complete the function due to a hardware fault",
+                    0x50 => "addressing problem or memory protect rungs",
+                    0x60 => "function not allowed due to command protection selection",
+                    0x70 => "processor is in program mode",
+                    0x80 => "compatibility mode file missing or communication zone problem",
+                    0x90 => "remote node cannot buffer the command",
+                    0xB0 => "remote node problem due to download",
+                    _ => "unknown error",
+                },
+            ),
+            Status::Extended(ext) => (
+                ext,
+                match ext {
+                    0x01 => "a field has an illegal value",
+                    0x02 => "fewer levels specified in the address than the minimum",
+                    0x03 => "more levels specified in the address than supported",
+                    0x04 => "symbol not found",
+                    0x05 => "symbol is of improper format",
+                    0x06 => "address doesn't point to something usable",
+                    0x07 => "file is the wrong size",
+                    0x08 => "cannot complete the request",
+                    0x09 => "data or file is too large",
+                    0x0A => "transaction size plus word address is too large",
+                    0x0B => "access denied, improper privilege",
+                    0x0C => "condition cannot be generated",
+                    0x0D => "condition already exists",
+                    0x0E => "command cannot be executed",
+                    0x10 => "no access",
+                    0x11 => "illegal data type",
+                    0x12 => "invalid parameter or invalid data",
+                    0x14 => "command execution failure for unknown reason",
+                    0x15 => "data conversion error",
+                    0x17 => "type mismatch",
+                    0x1A => "file is open, another node owns it",
+                    0x1B => "another node is the program owner",
+                    _ => "unknown error",
+                },
+            ),
+        };
+        write!(f, "{} (PCCC status {:#04x})", text, code)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn request(address: &str) -> Vec<u8> {
+        let address = Address::parse(address).unwrap();
+        let mut out = BytesMut::new();
+        out.put_u8(address.size());
+        address.encode(&mut out);
+        out.to_vec()
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(request("N7:0"), [2, 7, 0x89, 0, 0]);
+        assert_eq!(request("f8:3"), [4, 8, 0x8A, 3, 0]);
+        assert_eq!(request("B3:1/4"), [2, 3, 0x85, 1, 0]);
+        assert_eq!(
+            Address::parse("B3/20").unwrap(),
+            Address::parse("B3:1/4").unwrap()
+        );
+        assert_eq!(request("I:0/3"), [2, 1, 0x8C, 0, 0]);
+        assert_eq!(request("S:1/5"), [2, 2, 0x84, 1, 0]);
+        assert_eq!(request("T4:2.ACC"), [2, 4, 0x86, 2, 2]);
+        assert_eq!(request("T4:2"), [6, 4, 0x86, 2, 0]);
+        assert_eq!(request("L10:1"), [4, 10, 0x91, 1, 0]);
+        assert_eq!(request("N255:300"), [2, 0xFF, 255, 0, 0x89, 0xFF, 44, 1, 0]);
+        assert_eq!(Address::parse("C5:0.DN").unwrap().bit, Some(13),);
+
+        for invalid in [
+            "N7", "X7:0", "N7:0/16", "F8:0/1", "T4:0.LEN", "N:0", "PT_101",
+        ] {
+            assert!(Address::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn values() {
+        let int = Address::parse("N7:0").unwrap();
+        assert_eq!(int.decode(&[0xFE, 0xFF]).unwrap(), PlcValue::Int(-2));
+        let bit = Address::parse("N7:0/15").unwrap();
+        assert_eq!(bit.decode(&[0, 0x80]).unwrap(), PlcValue::Bool(true));
+        let timer = Address::parse("T4:0").unwrap();
+        assert_eq!(
+            timer.decode(&[0, 0xA0, 10, 0, 4, 0]).unwrap(),
+            PlcValue::Timer(Timer {
+                pre: 10,
+                acc: 4,
+                en: true,
+                tt: false,
+                dn: true,
+            })
+        );
+
+        let mut out = BytesMut::new();
+        bit.encode_value(PlcValue::Bool(false), &mut out).unwrap();
+        assert_eq!(&out[..], [0, 0x80, 0, 0]);
+        let mut out = BytesMut::new();
+        let float = Address::parse("F8:0").unwrap();
+        float.encode_value(PlcValue::Real(1.5), &mut out).unwrap();
+        assert_eq!(&out[..], 1.5f32.to_le_bytes());
+        assert!(float
+            .encode_value(PlcValue::Int(1), &mut BytesMut::new())
+            .is_err());
+        assert!(timer
+            .encode_value(PlcValue::Int(1), &mut BytesMut::new())
+            .is_err());
+    }
+
+    #[test]
+    fn replies() {
+        let request = command(9, FNC_READ, &[2, 7, 0x89, 0, 0]);
+        assert_eq!(&request[7..], [0x0F, 0, 9, 0, 0xA2, 2, 7, 0x89, 0, 0]);
+
+        let mut reply = REQUESTOR_ID.to_vec();
+        reply.extend_from_slice(&[0x4F, 0, 9, 0, 0x2A, 0]);
+        assert_eq!(&parse_reply(9, reply.into()).unwrap()[..], [0x2A, 0]);
+
+        let mut reply = REQUESTOR_ID.to_vec();
+        reply.extend_from_slice(&[0x4F, 0xF0, 9, 0, 0x06]);
+        let error = parse_reply(9, reply.into()).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "address doesn't point to something usable (PCCC status 0x06)"
+        );
+        let mut reply = REQUESTOR_ID.to_vec();
+        reply.extend_from_slice(&[0x4F, 0x70, 9, 0]);
+        assert!(parse_reply(9, reply.clone().into()).is_err());
+        assert!(parse_reply(10, reply.into()).is_err());
+    }
+}
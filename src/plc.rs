@@ -0,0 +1,1162 @@
+use crate::backend::{PlcBackend, Protocol};
+use crate::failure::Failure;
+use crate::modbus::WordOrder;
+use crate::modbus_tcp::ModbusTcpClient;
+use crate::s7::S7Client;
+use crate::settings::Profile;
+use crate::tagcache::{self, Symbols};
+use crate::tags::matching_tag;
+use crate::udt::{self, StringType};
+use crate::value;
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use clap::parser::ValueSource;
+use clap::{ArgMatches, Args, ValueEnum};
+use rseip::client::ab_eip::*;
+use rseip::client::{MaybeConnected, OpenOptions};
+use rseip::precludes::*;
+use rseip::ClientError;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+/// Session with the controller, sending requests either unconnected or over
+/// a CIP connection, or over the backend of another protocol.
+pub struct Plc {
+    session: Session,
+    address: String,
+    family: Family,
+    /// Instance ids of the tags under `--tag-cache`.
+    symbols: Option<Symbols>,
+    /// Structure handles read so far, with their string type when they are
+    /// one.
+    string_types: HashMap<u16, Option<StringType>>,
+    /// Whether nothing is written to the controller.
+    read_only: bool,
+    /// Patterns of the tags not written to.
+    read_only_tags: Vec<String>,
+    /// Packet interval of the CIP connection, when requests are connected.
+    rpi: Option<Duration>,
+    /// When the session was last handed a request.
+    last_request: Instant,
+    /// Controller taken over when this one stops answering.
+    failover: Option<Failover>,
+    /// Whether writes are confirmed on the terminal first, and the answer.
+    confirmation: Confirmation,
+}
+
+/// Asking before the writes to a controller, under `--confirm` or for the
+/// protected controllers of the settings file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Confirmation {
+    /// Writes go through without asking.
+    Unprotected,
+    /// The first write asks.
+    Ask,
+    /// The user agreed to write for the rest of the session.
+    Confirmed,
+    /// The user declined; every write of the session fails.
+    Cancelled,
+}
+
+/// The other controller of a redundant pair or of a hot spare, switched to
+/// when the one in use stops answering.
+struct Failover {
+    /// Address of the controller not in use.
+    standby: String,
+    /// Whether the controller in use is the primary one.
+    on_primary: bool,
+    args: ConnectionArgs,
+    /// When to see whether the primary answers again, while on the standby.
+    check_primary: Instant,
+}
+
+enum Session {
+    Eip(Box<MaybeConnected<AbEipDriver>>),
+    Backend(Protocol, Box<dyn PlcBackend>),
+}
+
+impl Plc {
+    /// Address the session was opened with, as given on the command line.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    pub fn protocol(&self) -> Protocol {
+        match &self.session {
+            Session::Eip(_) => Protocol::Ab,
+            Session::Backend(protocol, _) => *protocol,
+        }
+    }
+
+    /// The EtherNet/IP session, for the commands only Allen-Bradley
+    /// controllers answer.
+    pub fn eip(&mut self) -> Result<&mut MaybeConnected<AbEipDriver>> {
+        self.last_request = Instant::now();
+        match &mut self.session {
+            Session::Eip(session) => Ok(session),
+            Session::Backend(protocol, _) => bail!(
+                "{} is reached over {}, which only reads and writes addresses",
+                self.address,
+                protocol
+            ),
+        }
+    }
+
+    /// The backend of a controller reached over another protocol than
+    /// EtherNet/IP.
+    pub fn backend(&mut self) -> Option<&mut dyn PlcBackend> {
+        match &mut self.session {
+            Session::Eip(_) => None,
+            Session::Backend(_, backend) => Some(backend.as_mut()),
+        }
+    }
+
+    /// Requests reading the tags takes, for load estimates.
+    pub fn request_count(&self, tags: &[String]) -> usize {
+        match &self.session {
+            Session::Eip(_) => value::request_count(tags, self.family),
+            Session::Backend(_, backend) => backend.request_count(tags),
+        }
+    }
+
+    pub async fn close(&mut self) -> Result<()> {
+        match &mut self.session {
+            Session::Eip(session) => Ok(session.close().await?),
+            Session::Backend(_, backend) => backend.close().await,
+        }
+    }
+
+    pub fn family(&self) -> Family {
+        self.family
+    }
+
+    /// Whether requests go over a CIP connection, under `--connected` or
+    /// for the families connected to straight away.
+    pub fn connected(&self) -> bool {
+        matches!(self.session, Session::Eip(_)) && self.rpi.is_some()
+    }
+
+    /// Path of a tag, addressed by symbol instance when the tag cache knows
+    /// it and by name otherwise.
+    pub fn tag_path(&self, tag: &str) -> Result<EPath> {
+        let path = EPath::parse_tag(tag)?;
+        Ok(match &self.symbols {
+            Some(symbols) => symbols.resolve(path),
+            None => path,
+        })
+    }
+
+    /// The string type of a structure handle a tag was read with, or none
+    /// when the structure is no string. The templates of the tag are read
+    /// the first time a handle comes up; a handle changes along with the
+    /// definition of its type, so what was found stays true.
+    pub async fn string_type(&mut self, tag: &str, handle: u16) -> Result<Option<StringType>> {
+        if handle == StringType::STRING_HANDLE {
+            return Ok(Some(StringType::standard()));
+        }
+        if !self.string_types.contains_key(&handle) {
+            let found = udt::string_types(self, tag).await?;
+            for string_type in &found {
+                self.string_types
+                    .insert(string_type.handle, Some(string_type.clone()));
+            }
+            self.string_types.entry(handle).or_insert(None);
+        }
+        Ok(self.string_types[&handle].clone())
+    }
+
+    /// Fail when a tag of the controller can't be written, or with no tag,
+    /// when the controller can't be changed at all, under `--read-only` or
+    /// the read-only controllers and tags of the settings file. The members
+    /// and bits of a read-only tag are read-only too.
+    pub fn check_writable(&self, tag: Option<&str>) -> Result<()> {
+        if self.read_only {
+            return Err(Failure::WriteRejected.error(format!(
+                "{} is read-only, nothing is written to it",
+                self.address
+            )));
+        }
+        let Some(tag) = tag else {
+            return Ok(());
+        };
+        if let Some(name) = matching_tag(&self.read_only_tags, tag) {
+            return Err(
+                Failure::WriteRejected.error(format!("{} is read-only on {}", name, self.address))
+            );
+        }
+        Ok(())
+    }
+
+    /// Ask on the terminal before the first write to a protected controller,
+    /// the question, given the address, saying what is about to change. The
+    /// answer holds for the session, so a loop writing every cycle asks once
+    /// and a cancelled controller is written nothing.
+    pub fn confirm_write(&mut self, question: impl FnOnce(&str) -> String) -> Result<()> {
+        match self.confirmation {
+            Confirmation::Unprotected | Confirmation::Confirmed => return Ok(()),
+            Confirmation::Cancelled => {}
+            Confirmation::Ask => {
+                self.confirmation = match ask(&question(&self.address))? {
+                    true => Confirmation::Confirmed,
+                    false => Confirmation::Cancelled,
+                };
+                if self.confirmation == Confirmation::Confirmed {
+                    return Ok(());
+                }
+            }
+        }
+        Err(Failure::WriteRejected.error(format!(
+            "writing to {} was cancelled, nothing was written",
+            self.address
+        )))
+    }
+
+    /// How long the session sits idle before `keep_alive` checks it: a
+    /// quarter of the 32 packet intervals a CIP connection times out after,
+    /// and at most `KEEP_ALIVE`.
+    pub fn keep_alive_interval(&self) -> Duration {
+        match self.rpi {
+            Some(rpi) => (rpi * 8).min(KEEP_ALIVE),
+            None => KEEP_ALIVE,
+        }
+    }
+
+    /// Check a session that sat idle for a while with a cheap request,
+    /// which keeps its CIP connection from timing out, and drop the session
+    /// when the controller doesn't answer so the next request opens a new
+    /// one. Encapsulation NOPs don't count as traffic on the connection,
+    /// hence the request.
+    pub async fn keep_alive(&mut self) {
+        if self.last_request.elapsed() < self.keep_alive_interval() {
+            return;
+        }
+        let address = self.address.clone();
+        let session = match &mut self.session {
+            Session::Eip(session) if !session.closed() => session,
+            _ => return,
+        };
+        self.last_request = Instant::now();
+        let request = session.get_attribute_single::<u16>(vendor_id_path());
+        let result =
+            tokio::time::timeout(KEEP_ALIVE_TIMEOUT, transaction("keep alive", "", request)).await;
+        let error = match result {
+            Ok(Ok(_)) => return,
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => "no answer".to_owned(),
+        };
+        tracing::info!(
+            "{} dropped the idle session, opening a new one: {}",
+            address,
+            error
+        );
+        let _ = self.close().await;
+    }
+
+    /// Close a session the controller dropped, e.g. after it sat idle too
+    /// long, so the next request opens a new one. Returns whether the
+    /// failed request can be sent again: reads always can, writes only when
+    /// the controller refused the session, as nothing was written then.
+    ///
+    /// With a failover address, the other controller is switched to when
+    /// the request failed in transport rather than being answered, as a
+    /// reply shows the controller is still up. A write is then only sent
+    /// to the other controller when it never left the host; one that went
+    /// unanswered may have been carried out all the same.
+    pub async fn recover(&mut self, error: &anyhow::Error, write: bool) -> bool {
+        if self.failover.is_some() {
+            if let Some(unsent) = transport_failure(error) {
+                return self.fail_over(error).await && (unsent || !write);
+            }
+        }
+        if !matches!(self.session, Session::Eip(_)) {
+            return false;
+        }
+        let Some(refused) = session_lost(error) else {
+            return false;
+        };
+        tracing::info!(
+            "{} dropped the session, opening a new one: {}",
+            self.address,
+            error
+        );
+        let _ = self.close().await;
+        refused || !write
+    }
+
+    /// Switch to the controller at `standby` when this one stops answering,
+    /// and back once it answers again. Requests then get `FAILOVER_TIMEOUT`
+    /// to be answered in.
+    pub fn set_failover(&mut self, standby: &str, args: &ConnectionArgs) -> Result<()> {
+        if !matches!(self.session, Session::Eip(_)) {
+            bail!(
+                "{} controllers can't fail over, only Allen-Bradley controllers can",
+                self.protocol()
+            );
+        }
+        self.failover = Some(Failover {
+            standby: standby.to_owned(),
+            on_primary: true,
+            args: args.clone(),
+            check_primary: Instant::now(),
+        });
+        Ok(())
+    }
+
+    /// Longest a request is waited on, when another controller can take
+    /// over from one that stopped answering.
+    pub fn request_timeout(&self) -> Option<Duration> {
+        self.failover.as_ref().map(|_| FAILOVER_TIMEOUT)
+    }
+
+    /// Switch to the standby controller after a request the one in use
+    /// failed to answer. Returns whether the request can be sent to it.
+    async fn fail_over(&mut self, error: &anyhow::Error) -> bool {
+        let Some(failover) = &self.failover else {
+            return false;
+        };
+        let standby = failover.standby.clone();
+        match tokio::time::timeout(
+            FAILOVER_TIMEOUT,
+            Box::pin(connect(&standby, &failover.args)),
+        )
+        .await
+        {
+            Ok(Ok(other)) => {
+                tracing::warn!(
+                    "{} stopped answering, switching to {}: {:#}",
+                    self.address,
+                    standby,
+                    error
+                );
+                self.take_over(other).await;
+                true
+            }
+            _ => {
+                tracing::warn!(
+                    "{} stopped answering and {} can't be reached either",
+                    self.address,
+                    standby
+                );
+                let _ = tokio::time::timeout(FAILOVER_TIMEOUT, self.close()).await;
+                false
+            }
+        }
+    }
+
+    /// Switch back to the primary controller once it answers again, checked
+    /// every `FAILBACK_INTERVAL` while the standby is in use.
+    pub async fn check_primary(&mut self) {
+        let failover = match &mut self.failover {
+            Some(failover) if !failover.on_primary && failover.check_primary <= Instant::now() => {
+                failover
+            }
+            _ => return,
+        };
+        failover.check_primary = Instant::now() + FAILBACK_INTERVAL;
+        let primary = failover.standby.clone();
+        let Ok(Ok(mut other)) = tokio::time::timeout(
+            FAILOVER_TIMEOUT,
+            Box::pin(connect(&primary, &failover.args)),
+        )
+        .await
+        else {
+            return;
+        };
+        let answers = match &mut other.session {
+            Session::Eip(session) => {
+                let request = session.get_attribute_single::<u16>(vendor_id_path());
+                let request = transaction("identity", "", request);
+                matches!(
+                    tokio::time::timeout(FAILOVER_TIMEOUT, request).await,
+                    Ok(Ok(_))
+                )
+            }
+            Session::Backend(..) => false,
+        };
+        if !answers {
+            let _ = tokio::time::timeout(FAILOVER_TIMEOUT, other.close()).await;
+            return;
+        }
+        tracing::info!(
+            "{} answers again, switching back from {}",
+            primary,
+            self.address
+        );
+        self.take_over(other).await;
+    }
+
+    /// Carry on over the session of the other controller, which becomes the
+    /// standby, and close the session of this one.
+    async fn take_over(&mut self, other: Plc) {
+        let mut old = std::mem::replace(self, other);
+        // The other controller of a protected pair counts as answered too.
+        if old.confirmation != Confirmation::Unprotected {
+            self.confirmation = old.confirmation;
+        }
+        if let Some(mut failover) = old.failover.take() {
+            failover.standby = old.address.clone();
+            failover.on_primary = !failover.on_primary;
+            failover.check_primary = Instant::now() + FAILBACK_INTERVAL;
+            self.failover = Some(failover);
+        }
+        let _ = tokio::time::timeout(FAILOVER_TIMEOUT, old.close()).await;
+    }
+
+    /// Make sure the cached symbols still match the controller project
+    /// before a request, always for writes and now and then for reads. Tags
+    /// are addressed by name again once the project changed.
+    pub async fn check_symbols(&mut self, write: bool) {
+        let mut symbols = match self.symbols.take() {
+            Some(symbols) if symbols.due(write) => symbols,
+            symbols => {
+                self.symbols = symbols;
+                return;
+            }
+        };
+        match symbols.recheck(self).await {
+            Ok(true) => self.symbols = Some(symbols),
+            Ok(false) => tracing::warn!(
+                "the controller project changed, tags are addressed by name until the next run"
+            ),
+            Err(e) => tracing::warn!(
+                "failed to read the controller change counters, tags are addressed by name: {:#}",
+                e
+            ),
+        }
+    }
+}
+
+/// Message Router object class, the target of explicit connections.
+const CLASS_MESSAGE_ROUTER: u16 = 0x02;
+
+/// Identity object and its vendor id, read to keep sessions alive.
+const CLASS_IDENTITY: u16 = 0x01;
+const ATTR_VENDOR_ID: u16 = 1;
+
+/// Longest an unconnected session sits idle before `keep_alive` checks it.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+/// Time the controller gets to answer a keep alive.
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time a controller with a failover address gets to answer a request
+/// before the other one is switched to.
+const FAILOVER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How often the primary controller is checked while the standby is in
+/// use.
+const FAILBACK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Vendor id of the Identity object, a cheap request every controller
+/// answers.
+fn vendor_id_path() -> EPath {
+    EPath::default()
+        .with_class(CLASS_IDENTITY)
+        .with_instance(1)
+        .with_attribute(ATTR_VENDOR_ID)
+}
+
+/// CIP general status of a connection failure, with the extended status of
+/// a connection the target doesn't know, and of a lost connection.
+const STATUS_CONNECTION_FAILURE: u8 = 0x01;
+const EXT_CONNECTION_NOT_FOUND: u16 = 0x0107;
+const STATUS_CONNECTION_LOST: u8 = 0x07;
+
+/// Encapsulation status of a session the target doesn't know.
+const ENCAP_INVALID_SESSION: u16 = 0x0064;
+
+/// Whether a request failed because the controller dropped the session or
+/// its connection, and if so, whether it refused the request without
+/// carrying it out.
+fn session_lost(error: &anyhow::Error) -> Option<bool> {
+    match error.downcast_ref::<ClientError>()? {
+        ClientError::Io { err, .. } => matches!(
+            err.kind(),
+            ErrorKind::ConnectionReset
+                | ErrorKind::ConnectionAborted
+                | ErrorKind::BrokenPipe
+                | ErrorKind::UnexpectedEof
+                | ErrorKind::NotConnected
+        )
+        .then_some(false),
+        ClientError::Closed { .. } => Some(false),
+        ClientError::Encapsulation {
+            status: ENCAP_INVALID_SESSION,
+            ..
+        } => Some(true),
+        ClientError::Status { status, .. } => match (status.general, status.extended) {
+            (STATUS_CONNECTION_FAILURE, Some(EXT_CONNECTION_NOT_FOUND))
+            | (STATUS_CONNECTION_LOST, _) => Some(true),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Ask a yes or no question on the terminal, no being the default. The
+/// controllers of several targets ask one at a time.
+fn ask(question: &str) -> Result<bool> {
+    use std::io::Write;
+
+    static ASKING: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _asking = ASKING.lock().unwrap_or_else(|e| e.into_inner());
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+/// Whether a request failed in transport rather than being answered by
+/// the controller: an I/O error, no answer in time, or the session lost at
+/// the encapsulation layer. If so, whether the request never left the
+/// host, so the controller can't have carried it out.
+fn transport_failure(error: &anyhow::Error) -> Option<bool> {
+    for cause in error.chain() {
+        if let Some(error) = cause.downcast_ref::<ClientError>() {
+            return match error {
+                ClientError::Io { err, .. } => io_failure(err),
+                // The target turned the request down before reading it.
+                ClientError::Encapsulation { .. } => Some(true),
+                ClientError::Closed { .. } => Some(false),
+                _ => None,
+            };
+        }
+        if let Some(error) = cause.downcast_ref::<std::io::Error>() {
+            return io_failure(error);
+        }
+        if cause.is::<tokio::time::error::Elapsed>() {
+            return Some(false);
+        }
+    }
+    None
+}
+
+/// Whether an I/O error is the network failing, and if so, whether it
+/// failed before anything was sent.
+fn io_failure(error: &std::io::Error) -> Option<bool> {
+    match error.kind() {
+        ErrorKind::ConnectionRefused
+        | ErrorKind::AddrNotAvailable
+        | ErrorKind::HostUnreachable
+        | ErrorKind::NetworkUnreachable
+        | ErrorKind::NetworkDown => Some(true),
+        ErrorKind::ConnectionReset
+        | ErrorKind::ConnectionAborted
+        | ErrorKind::NotConnected
+        | ErrorKind::BrokenPipe
+        | ErrorKind::TimedOut
+        | ErrorKind::UnexpectedEof => Some(false),
+        _ => None,
+    }
+}
+
+/// Largest connection size a regular Forward Open can request; larger
+/// connections need a Large Forward Open.
+const MAX_CONNECTION_SIZE: u16 = 505;
+
+/// Largest connection size of a Large Forward Open on Logix controllers.
+const MAX_LARGE_CONNECTION_SIZE: u16 = 4002;
+
+/// Requested packet interval of connections, in milliseconds.
+const DEFAULT_RPI: u32 = 2000;
+
+/// Route to a controller in slot 0 of the chassis of the Ethernet module.
+const DEFAULT_ROUTE: &str = "1,0";
+
+/// Controller family, deciding how requests reach the controller and
+/// whether they can be packed together.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Family {
+    /// ControlLogix and CompactLogix, reached through `--route`.
+    #[default]
+    #[value(name = "compactlogix")]
+    CompactLogix,
+    /// Micro820, Micro850 and Micro870, which only take requests over a
+    /// connection opened straight to the controller, one service at a time.
+    Micro800,
+    /// MicroLogix and SLC 5/05 processors, reached like Micro800s and
+    /// addressed by data table addresses like `N7:0` over PCCC.
+    #[value(name = "micrologix")]
+    MicroLogix,
+}
+
+impl Family {
+    /// Whether reads of several tags can share a Multiple Service request.
+    pub fn packs_requests(self) -> bool {
+        self == Family::CompactLogix
+    }
+
+    /// Whether requests are routed through `--route`; the other families
+    /// are connected to straight away.
+    pub fn routed(self) -> bool {
+        self == Family::CompactLogix
+    }
+}
+
+impl Display for Family {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Family::CompactLogix => "CompactLogix",
+            Family::Micro800 => "Micro800",
+            Family::MicroLogix => "MicroLogix",
+        })
+    }
+}
+
+/// Messaging options shared by the commands talking to the controller.
+#[derive(Args, Debug, Clone)]
+pub struct ConnectionArgs {
+    /// Protocol of the controller: `ab` for Allen-Bradley controllers over
+    /// EtherNet/IP, `s7` for Siemens S7-1200 and S7-1500 CPUs over
+    /// ISO-on-TCP, addressed like `DB1.DBW2`, `modbus-tcp` for Modbus TCP
+    /// devices, addressed like `hr:40001:f32`.
+    #[arg(long, global = true, env = "COBALT_PROTOCOL", value_enum, default_value_t = Protocol::Ab)]
+    pub protocol: Protocol,
+    /// Rack of the S7 CPU.
+    #[arg(long, global = true, env = "COBALT_S7_RACK", default_value_t = 0)]
+    pub s7_rack: u8,
+    /// Slot of the S7 CPU, 1 for S7-1200s and S7-1500s.
+    #[arg(long, global = true, env = "COBALT_S7_SLOT", default_value_t = 1)]
+    pub s7_slot: u8,
+    /// Unit identifier of the Modbus TCP device, for gateways to serial
+    /// devices.
+    #[arg(long, global = true, env = "COBALT_MODBUS_UNIT", default_value_t = 1)]
+    pub modbus_unit: u8,
+    /// Order of the two registers of the 32-bit values of Modbus TCP
+    /// devices.
+    #[arg(long, global = true, value_enum, default_value_t = WordOrder::HighFirst)]
+    pub modbus_word_order: WordOrder,
+    /// Controller family. Micro800 and MicroLogix controllers are always
+    /// connected, with a regular Forward Open and without `--route`.
+    #[arg(long, global = true, env = "COBALT_FAMILY", value_enum, default_value_t = Family::CompactLogix)]
+    pub family: Family,
+    /// Open a CIP connection (Forward Open) and send the requests over it.
+    /// The controller then skips routing each request on its own, which
+    /// speeds up polling loops.
+    #[arg(long, global = true, env = "COBALT_CONNECTED")]
+    pub connected: bool,
+    /// Requested packet interval of the connection in milliseconds. The
+    /// controller drops the connection after 32 intervals without requests.
+    #[arg(
+        long,
+        global = true,
+        default_value_t = DEFAULT_RPI,
+        value_parser = clap::value_parser!(u32).range(1..=60_000)
+    )]
+    pub rpi: u32,
+    /// Largest message the connection carries, in bytes. Sizes above 505
+    /// open a Large Forward Open, falling back to a regular one of 505 bytes
+    /// on controllers without support for it.
+    #[arg(
+        long,
+        global = true,
+        default_value_t = MAX_LARGE_CONNECTION_SIZE,
+        value_parser = clap::value_parser!(u16).range(1..=MAX_LARGE_CONNECTION_SIZE as i64)
+    )]
+    pub connection_size: u16,
+    /// Routing path to the controller as port and link address pairs, e.g.
+    /// `1,3,2,10.0.0.5,1,0` to go out of the backplane through the module in
+    /// slot 3 to a remote chassis and reach the controller in its slot 0.
+    #[arg(long, global = true, env = "COBALT_ROUTE", default_value = DEFAULT_ROUTE, value_parser = parse_route)]
+    pub route: Route,
+    /// Most requests sent per second, across all controllers of the run,
+    /// so polling can't use up the communication budget of a controller.
+    #[arg(
+        long,
+        global = true,
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub max_requests_per_second: Option<u32>,
+    /// Resolve the tags and check the values of writes, printing what would
+    /// be written without writing anything.
+    #[arg(long, global = true)]
+    pub dry_run: bool,
+    /// Ask before writing to the controller, as for the protected
+    /// controllers of the settings file.
+    #[arg(long, global = true)]
+    pub confirm: bool,
+    /// Refuse every write to the controller, including those of
+    /// bridge-write, alarm writes, schedules and the servers.
+    #[arg(long, global = true)]
+    pub read_only: bool,
+    /// Read-only controllers and tags of the settings file.
+    #[arg(skip)]
+    pub read_only_settings: ReadOnly,
+    /// Protected controllers of the settings file, asked about like under
+    /// `--confirm`.
+    #[arg(skip)]
+    pub protected: Vec<String>,
+    /// Address tags by their symbol instance rather than by name, from the
+    /// tag list cached for the controller. The list is read again when the
+    /// controller reports changes to its project.
+    #[arg(long, global = true)]
+    pub tag_cache: bool,
+    /// Options given on the command line or in the environment, which a
+    /// controller profile doesn't override.
+    #[arg(skip)]
+    pub given: Vec<&'static str>,
+}
+
+/// Options a controller profile of the settings file can give.
+const PROFILE_OPTIONS: [&str; 7] = [
+    "protocol",
+    "family",
+    "route",
+    "s7_rack",
+    "s7_slot",
+    "modbus_unit",
+    "rpi",
+];
+
+impl ConnectionArgs {
+    /// Take the address and options of a controller named in the settings,
+    /// except for the options given on the command line or in the
+    /// environment, which stay.
+    pub fn apply(&mut self, name: &str, profile: &Profile) -> Result<()> {
+        if let Some(protocol) = profile.protocol {
+            if !self.is_given("protocol") {
+                self.protocol = protocol;
+            }
+        }
+        if let Some(family) = profile.family {
+            if !self.is_given("family") {
+                self.family = family;
+            }
+        }
+        let route = match (&profile.route, profile.slot) {
+            (Some(_), Some(_)) => bail!("controller `{}` has both a route and a slot", name),
+            (Some(route), None) => Some(route.clone()),
+            (None, Some(slot)) if self.protocol == Protocol::Ab => Some(format!("1,{}", slot)),
+            (None, Some(slot)) if self.protocol == Protocol::S7 => {
+                if !self.is_given("s7_slot") {
+                    self.s7_slot = slot;
+                }
+                None
+            }
+            (None, Some(_)) => bail!(
+                "controller `{}` is a Modbus TCP device, which has no slot",
+                name
+            ),
+            (None, None) => None,
+        };
+        if let Some(route) = route {
+            let route = parse_route(&route)
+                .map_err(anyhow::Error::msg)
+                .with_context(|| format!("invalid route of controller `{}`", name))?;
+            if !self.is_given("route") {
+                self.route = route;
+            }
+        }
+        if let Some(rack) = profile.s7_rack {
+            if !self.is_given("s7_rack") {
+                self.s7_rack = rack;
+            }
+        }
+        if let Some(unit) = profile.modbus_unit {
+            if !self.is_given("modbus_unit") {
+                self.modbus_unit = unit;
+            }
+        }
+        if let Some(rpi) = profile.rpi {
+            if !(1..=60_000).contains(&rpi) {
+                bail!("the RPI of controller `{}` is out of 1..=60000 ms", name);
+            }
+            if !self.is_given("rpi") {
+                self.rpi = rpi;
+            }
+        }
+        self.connected |= profile.connected;
+        self.tag_cache |= profile.tag_cache;
+        self.read_only |= profile.read_only;
+        self.confirm |= profile.confirm;
+        Ok(())
+    }
+
+    /// Note which options the matches of the command line gave explicitly,
+    /// rather than by their default.
+    pub fn note_given(&mut self, matches: &ArgMatches) {
+        self.given = PROFILE_OPTIONS
+            .into_iter()
+            .filter(|id| given(matches, id))
+            .collect();
+    }
+
+    fn is_given(&self, id: &str) -> bool {
+        self.given.contains(&id)
+    }
+}
+
+/// Whether an option was given on the command line or in the environment,
+/// before or after the subcommand, as options are global.
+fn given(matches: &ArgMatches, id: &str) -> bool {
+    let explicit = matches!(
+        matches
+            .try_get_raw(id)
+            .ok()
+            .and_then(|_| matches.value_source(id)),
+        Some(ValueSource::CommandLine | ValueSource::EnvVariable)
+    );
+    explicit
+        || matches
+            .subcommand()
+            .is_some_and(|(_, matches)| given(matches, id))
+}
+
+/// Controllers and tags no command writes to, from the settings file.
+#[derive(Debug, Clone, Default)]
+pub struct ReadOnly {
+    /// Addresses of the controllers.
+    pub controllers: Vec<String>,
+    /// Tags on any controller, as patterns like `SIS_*`.
+    pub tags: Vec<String>,
+}
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Whether writes are only shown, not sent, under `--dry-run`.
+pub fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Spaces requests out evenly to stay within `--max-requests-per-second`.
+struct RateLimiter {
+    per_second: u32,
+    next: Mutex<Instant>,
+}
+
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Wait until the request limit allows another request.
+async fn throttle() {
+    let limiter = match RATE_LIMITER.get() {
+        Some(limiter) => limiter,
+        None => return,
+    };
+    let mut next = limiter.next.lock().await;
+    let now = Instant::now();
+    if *next > now {
+        tokio::time::sleep_until(*next).await;
+    }
+    *next = (*next).max(now) + Duration::from_secs(1) / limiter.per_second;
+}
+
+/// Send a request to the controller once the request limit allows it, in a
+/// `cip` span naming the service and the tags, and log how it went. Every
+/// request to a controller goes through here.
+pub async fn transaction<T, E: Display>(
+    service: &'static str,
+    tag: &str,
+    request: impl Future<Output = Result<T, E>>,
+) -> Result<T, E> {
+    let span = tracing::debug_span!("cip", service, tag);
+    async move {
+        throttle().await;
+        let started = Instant::now();
+        let result = request.await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+        match &result {
+            Ok(_) => tracing::debug!(elapsed_ms, "reply"),
+            Err(e) => tracing::debug!(elapsed_ms, error = %e, "failed"),
+        }
+        result
+    }
+    .instrument(span)
+    .await
+}
+
+/// Wait on a request at most `timeout`, the request timeout of the
+/// controller, when it has one.
+pub async fn within<T, E: Into<anyhow::Error>>(
+    timeout: Option<Duration>,
+    request: impl Future<Output = Result<T, E>>,
+) -> Result<T> {
+    let Some(timeout) = timeout else {
+        return request.await.map_err(Into::into);
+    };
+    match tokio::time::timeout(timeout, request).await {
+        Ok(result) => result.map_err(Into::into),
+        Err(elapsed) => Err(anyhow::Error::new(elapsed).context(format!(
+            "no answer within {}",
+            humantime::format_duration(timeout)
+        ))),
+    }
+}
+
+/// Warn when polling `what` takes more requests per second than the limit,
+/// as it then runs slower than configured.
+pub fn check_request_rate(what: &str, per_second: f64) {
+    if let Some(limiter) = RATE_LIMITER.get() {
+        if per_second > limiter.per_second as f64 {
+            tracing::warn!(
+                "{} needs about {:.1} requests per second, above the limit of {}; it will poll slower than configured",
+                what,
+                per_second,
+                limiter.per_second
+            );
+        }
+    }
+}
+
+/// Port segments leading from the device at the address to the controller.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Route(Vec<PortSegment>);
+
+impl From<Route> for EPath {
+    fn from(route: Route) -> Self {
+        route
+            .0
+            .into_iter()
+            .map(Segment::Port)
+            .collect::<Vec<_>>()
+            .into()
+    }
+}
+
+/// Parse port and link address pairs. Links are slot or node numbers, or
+/// addresses like `10.0.0.5` for Ethernet ports.
+fn parse_route(text: &str) -> Result<Route, String> {
+    let parts: Vec<&str> = text.split(',').map(str::trim).collect();
+    if !parts.len().is_multiple_of(2) {
+        return Err("expected pairs of port and link address".to_owned());
+    }
+    let mut route = Vec::with_capacity(parts.len() / 2);
+    for pair in parts.chunks(2) {
+        let port: u16 = match pair[0].parse() {
+            Ok(port) if port > 0 => port,
+            _ => return Err(format!("invalid port `{}`", pair[0])),
+        };
+        let link = match pair[1].parse::<u8>() {
+            Ok(link) => Bytes::copy_from_slice(&[link]),
+            Err(_) if pair[1].contains('.') => Bytes::copy_from_slice(pair[1].as_bytes()),
+            Err(_) => return Err(format!("invalid link address `{}`", pair[1])),
+        };
+        route.push(PortSegment { port, link });
+    }
+    Ok(Route(route))
+}
+
+/// Resolve the controller address. Nothing is sent until the first request,
+/// which also opens the connection in connected mode, except for loading the
+/// symbols under `--tag-cache`. The first call sets up
+/// the request limit and the dry run.
+pub async fn connect(address: &str, args: &ConnectionArgs) -> Result<Plc> {
+    DRY_RUN.store(args.dry_run, Ordering::Relaxed);
+    let read_only = args.read_only
+        || args
+            .read_only_settings
+            .controllers
+            .iter()
+            .any(|controller| controller == address);
+    let read_only_tags = args.read_only_settings.tags.clone();
+    let confirmation = if args.confirm || args.protected.iter().any(|p| p == address) {
+        Confirmation::Ask
+    } else {
+        Confirmation::Unprotected
+    };
+    if let Some(per_second) = args.max_requests_per_second {
+        RATE_LIMITER.get_or_init(|| RateLimiter {
+            per_second,
+            next: Mutex::new(Instant::now()),
+        });
+    }
+    let backend: Option<Box<dyn PlcBackend>> = match args.protocol {
+        Protocol::Ab => None,
+        Protocol::S7 => Some(Box::new(S7Client::new(address, args.s7_rack, args.s7_slot))),
+        Protocol::ModbusTcp => Some(Box::new(ModbusTcpClient::new(
+            address,
+            args.modbus_unit,
+            args.modbus_word_order,
+        ))),
+    };
+    if let Some(backend) = backend {
+        if args.family != Family::CompactLogix {
+            tracing::warn!("--family only applies to Allen-Bradley controllers");
+        }
+        if args.tag_cache {
+            tracing::warn!(
+                "{} devices have no tag cache, their addresses are absolute",
+                args.protocol
+            );
+        }
+        return Ok(Plc {
+            session: Session::Backend(args.protocol, backend),
+            address: address.to_owned(),
+            family: args.family,
+            symbols: None,
+            string_types: HashMap::new(),
+            read_only,
+            read_only_tags,
+            rpi: None,
+            last_request: Instant::now(),
+            failover: None,
+            confirmation,
+        });
+    }
+    let routed = args.family.routed();
+    let session = if args.connected || !routed {
+        let rpi = args.rpi * 1000;
+        // Only Logix controllers sit in a chassis to route through.
+        let (route, connection_size) = match routed {
+            true => (EPath::from(args.route.clone()), args.connection_size),
+            false => (
+                EPath::default(),
+                args.connection_size.min(MAX_CONNECTION_SIZE),
+            ),
+        };
+        let path = route.with_class(CLASS_MESSAGE_ROUTER).with_instance(1);
+        let options = OpenOptions::default()
+            .connection_path(path)
+            .o_t_rpi(rpi)
+            .t_o_rpi(rpi)
+            .connection_size(connection_size)
+            .large_open(connection_size > MAX_CONNECTION_SIZE);
+        AbEipConnection::new_host_lookup(address, options)
+            .await
+            .map_err(|e| Failure::Connection.error(e))?
+            .into()
+    } else {
+        AbEipClient::new_host_lookup(address)
+            .await
+            .map_err(|e| Failure::Connection.error(e))?
+            .with_connection_path(args.route.clone())
+            .into()
+    };
+    let mut plc = Plc {
+        session: Session::Eip(Box::new(session)),
+        address: address.to_owned(),
+        family: args.family,
+        symbols: None,
+        string_types: HashMap::new(),
+        read_only,
+        read_only_tags,
+        rpi: (args.connected || !routed).then(|| Duration::from_millis(args.rpi as u64)),
+        last_request: Instant::now(),
+        failover: None,
+        confirmation,
+    };
+    if args.tag_cache && args.family != Family::CompactLogix {
+        tracing::warn!(
+            "{} controllers have no tag cache, tags are addressed by name",
+            args.family
+        );
+    } else if args.tag_cache {
+        plc.symbols = tagcache::symbols(&mut plc).await;
+    }
+    Ok(plc)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn routes() {
+        let route = parse_route("1,3,2,10.0.0.5,1,0").unwrap();
+        assert_eq!(route.0.len(), 3);
+        assert_eq!(route.0[0].link.as_ref(), &[3]);
+        assert_eq!(route.0[1].port, 2);
+        assert_eq!(route.0[1].link.as_ref(), b"10.0.0.5");
+        assert_eq!(parse_route("1,0").unwrap().0, vec![PortSegment::default()]);
+        assert!(parse_route("1,3,2").is_err());
+        assert!(parse_route("1,300").is_err());
+        assert!(parse_route("0,1").is_err());
+    }
+
+    #[test]
+    fn given_options() {
+        use clap::{CommandFactory, FromArgMatches, Parser, Subcommand};
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            connection: ConnectionArgs,
+            #[command(subcommand)]
+            command: Command,
+        }
+        #[derive(Subcommand)]
+        enum Command {
+            Read { tag: String },
+        }
+        let given = |line: &str| {
+            let matches = Cli::command().get_matches_from(line.split_whitespace());
+            let mut cli = Cli::from_arg_matches(&matches).unwrap();
+            cli.connection.note_given(&matches);
+            cli.connection.given
+        };
+        assert!(given("cobalt read PT_101").is_empty());
+        assert_eq!(given("cobalt --rpi 2000 read PT_101"), ["rpi"]);
+        // Global options also come after the subcommand.
+        assert_eq!(
+            given("cobalt read PT_101 --route 1,0 --family micro800"),
+            ["family", "route"]
+        );
+    }
+
+    #[test]
+    fn lost_sessions() {
+        use rseip::cip::Status;
+
+        let lost = |error: ClientError| session_lost(&error.into());
+        let io = |kind: ErrorKind| ClientError::Io {
+            kind: "io",
+            err: kind.into(),
+        };
+        let reply = |general, extended| ClientError::Status {
+            kind: "custom",
+            status: Status { general, extended },
+        };
+        assert_eq!(lost(io(ErrorKind::ConnectionReset)), Some(false));
+        assert_eq!(lost(io(ErrorKind::TimedOut)), None);
+        assert_eq!(lost(ClientError::Closed { kind: "custom" }), Some(false));
+        let encapsulation = |status| ClientError::Encapsulation {
+            kind: "custom",
+            status,
+            msg: "invalid session handle".into(),
+        };
+        assert_eq!(lost(encapsulation(0x0064)), Some(true));
+        assert_eq!(lost(encapsulation(0x0065)), None);
+        assert_eq!(lost(reply(0x01, Some(0x0107))), Some(true));
+        assert_eq!(lost(reply(0x07, None)), Some(true));
+        assert_eq!(lost(reply(0x01, Some(0x0204))), None);
+        assert_eq!(lost(reply(0x04, None)), None);
+        assert_eq!(session_lost(&anyhow::anyhow!("tag not found")), None);
+    }
+
+    #[test]
+    fn transport_failures() {
+        use rseip::cip::Status;
+
+        let failure = |error: ClientError| transport_failure(&error.into());
+        let io = |kind: ErrorKind| ClientError::Io {
+            kind: "io",
+            err: kind.into(),
+        };
+        assert_eq!(failure(io(ErrorKind::ConnectionRefused)), Some(true));
+        assert_eq!(failure(io(ErrorKind::ConnectionReset)), Some(false));
+        assert_eq!(failure(io(ErrorKind::InvalidData)), None);
+        assert_eq!(failure(ClientError::Closed { kind: "custom" }), Some(false));
+        // A controller replying with an error is still up.
+        let busy = ClientError::Status {
+            kind: "custom",
+            status: Status {
+                general: 0x02,
+                extended: None,
+            },
+        };
+        assert_eq!(failure(busy), None);
+        let error = anyhow::Error::new(std::io::Error::from(ErrorKind::HostUnreachable))
+            .context("failed to connect");
+        assert_eq!(transport_failure(&error), Some(true));
+    }
+}
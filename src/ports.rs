@@ -0,0 +1,141 @@
+use anyhow::{Context, Result};
+use colored::*;
+#[cfg(target_os = "linux")]
+use std::path::Path;
+use tokio_serial::{SerialPortInfo, SerialPortType};
+
+/// List the serial ports of this machine with the adapters behind them, so
+/// the right one can be given to `modbus scan` or the bridge.
+pub fn run() -> Result<()> {
+    let mut ports: Vec<SerialPortInfo> = tokio_serial::available_ports()
+        .context("failed to enumerate the serial ports")?
+        .into_iter()
+        .map(resolve)
+        .collect();
+    if ports.is_empty() {
+        println!("{}", "no serial port found".yellow());
+        return Ok(());
+    }
+    ports.sort_by(|a, b| a.port_name.cmp(&b.port_name));
+    for port in &ports {
+        println!(
+            "{}    {}",
+            format!("{:<16}", port.port_name).bold(),
+            adapter(&port.port_type)
+        );
+        #[cfg(target_os = "linux")]
+        if let Some(alias) = by_id(&port.port_name) {
+            println!("{:<16}    {}", "", alias.dimmed());
+        }
+    }
+    Ok(())
+}
+
+/// Description of the adapter of a port, e.g.
+/// `USB 0403:6001 FTDI FT232R USB UART, serial A50285BI`.
+fn adapter(port_type: &SerialPortType) -> String {
+    match port_type {
+        SerialPortType::UsbPort(usb) => {
+            let mut text = format!("USB {:04x}:{:04x}", usb.vid, usb.pid);
+            for name in [&usb.manufacturer, &usb.product].into_iter().flatten() {
+                text.push(' ');
+                text.push_str(name);
+            }
+            if let Some(serial) = &usb.serial_number {
+                text.push_str(", serial ");
+                text.push_str(serial);
+            }
+            text
+        }
+        SerialPortType::PciPort => "PCI".to_owned(),
+        SerialPortType::BluetoothPort => "Bluetooth".to_owned(),
+        SerialPortType::Unknown => "unknown adapter".to_owned(),
+    }
+}
+
+/// Without udev, ports are enumerated by their sysfs entry and nothing is
+/// known of their adapter, so look up both the device and the USB ids.
+#[cfg(target_os = "linux")]
+fn resolve(port: SerialPortInfo) -> SerialPortInfo {
+    let name = match port.port_name.strip_prefix("/sys/class/tty/") {
+        Some(name) => name.to_owned(),
+        None => return port,
+    };
+    let device = Path::new("/sys/class/tty").join(&name).join("device");
+    SerialPortInfo {
+        port_name: format!("/dev/{}", name),
+        port_type: usb_info(&device)
+            .map(SerialPortType::UsbPort)
+            .unwrap_or(port.port_type),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn resolve(port: SerialPortInfo) -> SerialPortInfo {
+    port
+}
+
+/// Walk up from the tty device to the USB device holding its ids.
+#[cfg(target_os = "linux")]
+fn usb_info(device: &Path) -> Option<tokio_serial::UsbPortInfo> {
+    let mut dir = device.canonicalize().ok()?;
+    while !dir.join("idVendor").is_file() {
+        if !dir.pop() || dir == Path::new("/sys/devices") {
+            return None;
+        }
+    }
+    let read = |attribute: &str| {
+        std::fs::read_to_string(dir.join(attribute))
+            .ok()
+            .map(|text| text.trim().to_owned())
+    };
+    Some(tokio_serial::UsbPortInfo {
+        vid: u16::from_str_radix(&read("idVendor")?, 16).ok()?,
+        pid: u16::from_str_radix(&read("idProduct")?, 16).ok()?,
+        serial_number: read("serial"),
+        manufacturer: read("manufacturer"),
+        product: read("product"),
+    })
+}
+
+/// Alias of a port in `/dev/serial/by-id`, which unlike `ttyUSB` numbers
+/// stays the same across replugging and reboots.
+#[cfg(target_os = "linux")]
+fn by_id(port: &str) -> Option<String> {
+    let device = Path::new(port).canonicalize().ok()?;
+    std::fs::read_dir("/dev/serial/by-id")
+        .ok()?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .find(|alias| alias.canonicalize().ok().as_ref() == Some(&device))
+        .map(|alias| alias.display().to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio_serial::UsbPortInfo;
+
+    #[test]
+    fn adapters() {
+        let usb = SerialPortType::UsbPort(UsbPortInfo {
+            vid: 0x0403,
+            pid: 0x6001,
+            serial_number: Some("A50285BI".to_owned()),
+            manufacturer: Some("FTDI".to_owned()),
+            product: Some("FT232R USB UART".to_owned()),
+        });
+        assert_eq!(
+            adapter(&usb),
+            "USB 0403:6001 FTDI FT232R USB UART, serial A50285BI"
+        );
+        let bare = SerialPortType::UsbPort(UsbPortInfo {
+            vid: 0x1a86,
+            pid: 0x7523,
+            serial_number: None,
+            manufacturer: None,
+            product: None,
+        });
+        assert_eq!(adapter(&bare), "USB 1a86:7523");
+        assert_eq!(adapter(&SerialPortType::PciPort), "PCI");
+    }
+}
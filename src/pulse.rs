@@ -0,0 +1,146 @@
+use crate::plc::{self, Plc};
+use crate::service;
+use crate::value::{self, PlcValue};
+use anyhow::{bail, Result};
+use clap::Args;
+use colored::*;
+use std::future::Future;
+use std::time::Duration;
+
+#[derive(Args, Debug)]
+pub struct PulseArgs {
+    /// BOOL tag or bit to pulse, addressed like the tags of `read`.
+    pub tag: String,
+    /// How long the tag stays true.
+    #[arg(long, default_value = "500ms", value_parser = humantime::parse_duration)]
+    pub duration: Duration,
+}
+
+/// Set a BOOL tag for the duration of the pulse, then clear it, e.g. to
+/// trigger a one-shot in the logic. Stopping with Ctrl-C during the pulse
+/// puts back the value the tag held before instead. Both writes are
+/// audited.
+pub async fn run(client: &mut Plc, args: &PulseArgs) -> Result<()> {
+    pulse(client, args, service::stopped()).await
+}
+
+/// The pulse of `run`, cut short when `stop` completes.
+async fn pulse(client: &mut Plc, args: &PulseArgs, stop: impl Future<Output = ()>) -> Result<()> {
+    let prior = value::read_value(client, &args.tag).await?;
+    let PlcValue::Bool(prior) = prior else {
+        bail!(
+            "{} is a {}, only BOOL tags and bits can be pulsed",
+            args.tag,
+            prior.type_name()
+        );
+    };
+    value::write_value(client, &args.tag, PlcValue::Bool(true)).await?;
+    if plc::dry_run() {
+        return value::write_value(client, &args.tag, PlcValue::Bool(false)).await;
+    }
+    println!("{}    {}", args.tag, "true".bold().green());
+
+    let end = tokio::select! {
+        _ = tokio::time::sleep(args.duration) => false,
+        _ = stop => prior,
+    };
+    value::write_value(client, &args.tag, PlcValue::Bool(end)).await?;
+    println!("{}    {}", args.tag, end.to_string().bold().green());
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::plc::ConnectionArgs;
+    use crate::sim::{SimFile, Simulator};
+    use clap::Parser;
+    use tokio::time::{sleep, Instant};
+
+    const TAGS: &str = r#"
+        [[tag]]
+        name = "Reset"
+        type = "BOOL"
+
+        [[tag]]
+        name = "Enable"
+        type = "BOOL"
+        value = true
+
+        [[tag]]
+        name = "Speed"
+        type = "REAL"
+    "#;
+
+    async fn connect(address: &str) -> Plc {
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            connection: ConnectionArgs,
+        }
+        let args = Cli::parse_from(["cobalt"]).connection;
+        plc::connect(address, &args).await.unwrap()
+    }
+
+    async fn read(client: &mut Plc, tag: &str) -> PlcValue {
+        value::read_value(client, tag).await.unwrap()
+    }
+
+    fn args(tag: &str, millis: u64) -> PulseArgs {
+        PulseArgs {
+            tag: tag.to_owned(),
+            duration: Duration::from_millis(millis),
+        }
+    }
+
+    #[tokio::test]
+    async fn pulses() {
+        let file: SimFile = toml::from_str(TAGS).unwrap();
+        let address = Simulator::new(&file)
+            .unwrap()
+            .spawn("127.0.0.1:0")
+            .await
+            .unwrap()
+            .to_string();
+        let mut client = connect(&address).await;
+        let mut observer = connect(&address).await;
+
+        // True for the duration, then false.
+        let reset = args("Reset", 400);
+        let start = Instant::now();
+        let (result, during) =
+            tokio::join!(pulse(&mut client, &reset, std::future::pending()), async {
+                sleep(Duration::from_millis(200)).await;
+                read(&mut observer, "Reset").await
+            });
+        result.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(400));
+        assert_eq!(during, PlcValue::Bool(true));
+        assert_eq!(read(&mut observer, "Reset").await, PlcValue::Bool(false));
+
+        // Stopped during the pulse, the tag gets back the value it held.
+        let start = Instant::now();
+        pulse(
+            &mut client,
+            &args("Enable", 5000),
+            sleep(Duration::from_millis(100)),
+        )
+        .await
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(read(&mut observer, "Enable").await, PlcValue::Bool(true));
+        pulse(&mut client, &args("Enable", 10), std::future::pending())
+            .await
+            .unwrap();
+        assert_eq!(read(&mut observer, "Enable").await, PlcValue::Bool(false));
+
+        let err = pulse(&mut client, &args("Speed", 10), std::future::pending())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Speed is a Real, only BOOL tags and bits can be pulsed"
+        );
+        assert_eq!(read(&mut observer, "Speed").await, PlcValue::Real(0.0));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::plc::{self, Plc};
+use crate::script;
+use crate::service;
+use crate::value::{self, PlcValue};
+use anyhow::{anyhow, bail, Result};
+use clap::Args;
+use colored::*;
+use rseip::client::ab_eip::TagType;
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Args, Debug)]
+pub struct RampArgs {
+    /// Numeric tag to slew, addressed like the tags of `read`.
+    pub tag: String,
+    /// Value the tag ends at.
+    #[arg(long)]
+    pub to: f64,
+    /// How fast the value moves, in units per second, minute or hour, e.g.
+    /// `2.0/s` or `30/min`.
+    #[arg(long)]
+    pub rate: Rate,
+    /// Time between the intermediate writes.
+    #[arg(long, default_value = "200ms", value_parser = humantime::parse_duration)]
+    pub interval: Duration,
+}
+
+/// Rate of change of a ramp, in units per second.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Rate(pub f64);
+
+impl FromStr for Rate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (amount, unit) = s.split_once('/').unwrap_or((s, "s"));
+        let per = match unit.trim() {
+            "s" | "sec" => 1.0,
+            "m" | "min" => 60.0,
+            "h" | "hr" => 3600.0,
+            other => bail!("unknown rate unit `{}`, use /s, /min or /h", other),
+        };
+        let amount: f64 = amount
+            .trim()
+            .parse()
+            .map_err(|_| anyhow!("`{}` is not a rate like 2.0/s", s))?;
+        if !amount.is_finite() || amount <= 0.0 {
+            bail!("the rate must be above 0");
+        }
+        Ok(Rate(amount / per))
+    }
+}
+
+/// Value of a ramp from `start` to `end` at `rate` after `elapsed`, which
+/// stays at `end` once reached.
+fn ramp_value(start: f64, end: f64, rate: Rate, elapsed: Duration) -> f64 {
+    let moved = rate.0 * elapsed.as_secs_f64();
+    if end >= start {
+        (start + moved).min(end)
+    } else {
+        (start - moved).max(end)
+    }
+}
+
+/// Value of the type of the tag closest to a ramp value. Integers are
+/// rounded.
+fn to_tag_value(tag_type: TagType, number: f64) -> Result<PlcValue> {
+    let number = match tag_type {
+        TagType::Real | TagType::Lreal => number,
+        _ => number.round(),
+    };
+    PlcValue::from_json(tag_type, &script::json(tag_type, number))
+}
+
+/// Slew a tag from its current value to the target, writing the
+/// intermediate values every interval. Stopping with Ctrl-C leaves the tag
+/// at the last value written, which is returned. Each value written goes
+/// to the audit file.
+pub async fn run(client: &mut Plc, args: &RampArgs) -> Result<PlcValue> {
+    let current = value::read_value(client, &args.tag).await?;
+    let tag_type = current.tag_type();
+    if matches!(tag_type, TagType::Bool | TagType::Structure(_)) {
+        bail!(
+            "{} is a {}, only numeric tags can be ramped",
+            args.tag,
+            current.type_name()
+        );
+    }
+    let start = current.as_f64();
+    let target = to_tag_value(tag_type, args.to)?;
+    let duration = Duration::from_secs_f64((args.to - start).abs() / args.rate.0);
+    if plc::dry_run() {
+        println!(
+            "{}    {} from {} to {} ({}) over {}",
+            "DRY RUN".bold().yellow(),
+            args.tag.bold(),
+            current,
+            target.to_string().bold(),
+            target.type_name(),
+            humantime::format_duration(Duration::from_millis(duration.as_millis() as u64))
+        );
+        return Ok(current);
+    }
+
+    let started = Instant::now();
+    let mut written = current.clone();
+    loop {
+        let next = to_tag_value(
+            tag_type,
+            ramp_value(start, args.to, args.rate, started.elapsed()),
+        )?;
+        if next != written {
+            value::write_unaudited(client, &args.tag, next.clone()).await?;
+            written = next;
+            println!("{}    {}", args.tag, written.to_string().bold().green());
+        }
+        if written == target {
+            break;
+        }
+        tokio::select! {
+            _ = tokio::time::sleep(args.interval) => {}
+            _ = service::stopped() => {
+                println!("{}    stopped at {}", args.tag, written.to_string().bold().yellow());
+                break;
+            }
+        }
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_rate() {
+        assert_eq!("2.0/s".parse::<Rate>().unwrap(), Rate(2.0));
+        assert_eq!("30/min".parse::<Rate>().unwrap(), Rate(0.5));
+        assert_eq!("5".parse::<Rate>().unwrap(), Rate(5.0));
+        assert!("0/s".parse::<Rate>().is_err());
+        assert!("2/day".parse::<Rate>().is_err());
+    }
+
+    #[test]
+    fn ramp_values() {
+        let rate = Rate(2.0);
+        assert_eq!(ramp_value(50.0, 75.0, rate, Duration::from_secs(5)), 60.0);
+        assert_eq!(ramp_value(50.0, 75.0, rate, Duration::from_secs(60)), 75.0);
+        assert_eq!(ramp_value(75.0, 50.0, rate, Duration::from_secs(5)), 65.0);
+        assert_eq!(to_tag_value(TagType::Int, 12.6).unwrap(), PlcValue::Int(13));
+        assert_eq!(
+            to_tag_value(TagType::Real, 12.5).unwrap(),
+            PlcValue::Real(12.5)
+        );
+    }
+}
@@ -0,0 +1,662 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use clap::Args;
+use colored::*;
+use rseip::cip::Status;
+use rseip::client::tap::{self, TapEvent};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// First bytes of a recording, followed by the format version.
+const MAGIC: &[u8; 8] = b"COBALTRC";
+const VERSION: u8 = 1;
+
+const OPENED: u8 = 0;
+const SENT: u8 = 1;
+const RECEIVED: u8 = 2;
+
+/// Length of the encapsulation header heading every EtherNet/IP frame.
+const HEADER: usize = 24;
+
+/// Options of the `replay` command.
+#[derive(Args)]
+pub struct ReplayArgs {
+    /// Recording made with `--record`.
+    pub file: PathBuf,
+    /// Print the bytes of every frame under its decoding.
+    #[arg(long)]
+    pub hex: bool,
+}
+
+/// How much of the traffic `-vv` and `-vvv` log.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trace {
+    /// The decoding of every frame.
+    Frames,
+    /// The decoding and the bytes of every frame.
+    Bytes,
+}
+
+/// Watch the EtherNet/IP traffic of this run: every chunk of bytes sent to
+/// and received from the controllers is recorded to `recording` with the
+/// time it was seen, for `replay` to decode later, and its frames are
+/// decoded into the log under `trace`.
+pub fn tap(recording: Option<&Path>, trace: Option<Trace>) -> Result<()> {
+    let recording = match recording {
+        Some(path) => Some((path.to_owned(), Mutex::new(create(path)?))),
+        None => None,
+    };
+    let decoder = Mutex::new(Decoder::default());
+    let tapped = tap::set_tap(move |stream, event| {
+        let (kind, data) = match event {
+            TapEvent::Opened(address) => (OPENED, address.to_string().into_bytes()),
+            TapEvent::Sent(data) => (SENT, data.to_vec()),
+            TapEvent::Received(data) => (RECEIVED, data.to_vec()),
+        };
+        if let Some((path, file)) = &recording {
+            if let Err(e) = write(&mut file.lock().unwrap(), stream, kind, &data) {
+                tracing::warn!("Failed to record to {}: {}", path.display(), e);
+            }
+        }
+        let Some(trace) = trace else {
+            return;
+        };
+        if kind == OPENED {
+            return;
+        }
+        let arrow = if kind == SENT { "->" } else { "<-" };
+        for (frame, lines) in decoder.lock().unwrap().feed(stream, kind, &data) {
+            let text = match lines {
+                Ok(lines) => lines
+                    .iter()
+                    .map(|line| line.trim_start())
+                    .collect::<Vec<_>>()
+                    .join("; "),
+                Err(e) => format!("malformed frame: {:#}", e),
+            };
+            match trace {
+                Trace::Frames => tracing::trace!(stream, "{} {}", arrow, text),
+                Trace::Bytes => {
+                    tracing::trace!(stream, bytes = hex(&frame), "{} {}", arrow, text)
+                }
+            }
+        }
+    });
+    if !tapped {
+        bail!("the traffic of this run is already watched");
+    }
+    Ok(())
+}
+
+/// Create a recording, starting with the version of cobalt that made it.
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    let file = File::create(path)
+        .with_context(|| format!("Failed to create the recording {}", path.display()))?;
+    let mut file = BufWriter::new(file);
+    let version = env!("CARGO_PKG_VERSION");
+    file.write_all(MAGIC)?;
+    file.write_all(&[VERSION, version.len() as u8])?;
+    file.write_all(version.as_bytes())?;
+    file.flush()?;
+    Ok(file)
+}
+
+/// Append a chunk to a recording. Flushed every time, so a crash doesn't
+/// lose what led up to it.
+fn write(file: &mut BufWriter<File>, stream: u32, kind: u8, data: &[u8]) -> std::io::Result<()> {
+    file.write_all(&Utc::now().timestamp_micros().to_le_bytes())?;
+    file.write_all(&stream.to_le_bytes())?;
+    file.write_all(&[kind])?;
+    file.write_all(&(data.len() as u32).to_le_bytes())?;
+    file.write_all(data)?;
+    file.flush()
+}
+
+/// A chunk of the traffic of a recording.
+struct Chunk {
+    time: DateTime<Utc>,
+    stream: u32,
+    kind: u8,
+    data: Vec<u8>,
+}
+
+/// Read a recording made with `--record`.
+fn load(path: &Path) -> Result<(String, Vec<Chunk>)> {
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open the recording {}", path.display()))?;
+    let mut file = BufReader::new(file);
+    let mut magic = [0; 10];
+    if file.read_exact(&mut magic).is_err() || magic[..8] != MAGIC[..] {
+        bail!("{} isn't a recording of cobalt", path.display());
+    }
+    if magic[8] != VERSION {
+        bail!(
+            "{} is a recording of version {}, this cobalt reads version {}",
+            path.display(),
+            magic[8],
+            VERSION
+        );
+    }
+    let mut version = vec![0; magic[9] as usize];
+    file.read_exact(&mut version)?;
+
+    let mut chunks = Vec::new();
+    loop {
+        let mut head = [0; 17];
+        match file.read_exact(&mut head) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let micros = i64::from_le_bytes(head[..8].try_into().unwrap());
+        let mut data = vec![0; u32::from_le_bytes(head[13..].try_into().unwrap()) as usize];
+        file.read_exact(&mut data)
+            .with_context(|| format!("{} ends in the middle of a chunk", path.display()))?;
+        chunks.push(Chunk {
+            time: Utc.timestamp_micros(micros).single().unwrap_or_default(),
+            stream: u32::from_le_bytes(head[8..12].try_into().unwrap()),
+            kind: head[12],
+            data,
+        });
+    }
+    Ok((String::from_utf8_lossy(&version).into_owned(), chunks))
+}
+
+/// Decode a recording frame by frame, as cobalt sent and received it, so
+/// problems seen in the field can be looked into without the controller.
+pub fn replay(args: &ReplayArgs) -> Result<()> {
+    let (version, chunks) = load(&args.file)?;
+    match chunks.first() {
+        Some(first) => println!(
+            "Recorded by cobalt {} on {}",
+            version,
+            first.time.with_timezone(&Local).format("%Y-%m-%d %H:%M:%S")
+        ),
+        None => {
+            println!("Recorded by cobalt {}, without traffic", version);
+            return Ok(());
+        }
+    }
+
+    let mut decoder = Decoder::default();
+    for chunk in &chunks {
+        let time = chunk.time.with_timezone(&Local).format("%H:%M:%S%.6f");
+        let stream = format!("#{}", chunk.stream);
+        if chunk.kind == OPENED {
+            println!(
+                "{}  {}  connected to {}",
+                time,
+                stream.bold(),
+                String::from_utf8_lossy(&chunk.data)
+            );
+            continue;
+        }
+        let arrow = if chunk.kind == SENT { "->" } else { "<-" };
+        for (frame, lines) in decoder.feed(chunk.stream, chunk.kind, &chunk.data) {
+            let (first, rest) = match &lines {
+                Ok(lines) => (lines[0].normal(), &lines[1..]),
+                Err(e) => (format!("malformed frame: {:#}", e).red(), &[][..]),
+            };
+            println!("{}  {}  {} {}", time, stream.bold(), arrow, first);
+            for line in rest {
+                println!("{:28}{}", "", line);
+            }
+            if args.hex || lines.is_err() {
+                for line in hexdump(&frame) {
+                    println!("{:28}{}", "", line.dimmed());
+                }
+            }
+        }
+    }
+    for ((stream, kind), buffer) in decoder.pending {
+        if !buffer.is_empty() {
+            let direction = if kind == SENT { "sent" } else { "received" };
+            println!(
+                "{}",
+                format!(
+                    "#{} {} {} bytes of an incomplete frame",
+                    stream,
+                    direction,
+                    buffer.len()
+                )
+                .red()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Reassembles the frames of the streams of a run to decode them.
+#[derive(Default)]
+struct Decoder {
+    /// Bytes of the frames not complete yet, by stream and direction.
+    pending: HashMap<(u32, u8), Vec<u8>>,
+    /// Requests not answered yet, by stream.
+    requests: HashMap<u32, Vec<Request>>,
+}
+
+impl Decoder {
+    /// Add bytes sent or received on a stream, returning the frames they
+    /// complete with their decoding.
+    fn feed(&mut self, stream: u32, kind: u8, data: &[u8]) -> Vec<(Vec<u8>, Result<Vec<String>>)> {
+        let buffer = self.pending.entry((stream, kind)).or_default();
+        buffer.extend_from_slice(data);
+        let requests = self.requests.entry(stream).or_default();
+        let mut frames = Vec::new();
+        while let Some(frame) = next_frame(buffer) {
+            let lines = describe(&frame, kind == SENT, requests);
+            frames.push((frame, lines));
+        }
+        frames
+    }
+}
+
+/// Take the next complete encapsulation frame off the bytes of a stream.
+fn next_frame(buffer: &mut Vec<u8>) -> Option<Vec<u8>> {
+    if buffer.len() < HEADER {
+        return None;
+    }
+    let length = HEADER + u16::from_le_bytes([buffer[2], buffer[3]]) as usize;
+    if buffer.len() < length {
+        return None;
+    }
+    Some(buffer.drain(..length).collect())
+}
+
+/// Bytes in lines of 16, for the frames `replay` prints in full.
+fn hexdump(data: &[u8]) -> Vec<String> {
+    data.chunks(16)
+        .enumerate()
+        .map(|(i, line)| format!("{:04x}  {}", i * 16, hex(line)))
+        .collect()
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Cursor over the bytes of a frame, failing when they run out.
+struct Reader<'a> {
+    data: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, at: 0 }
+    }
+
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8]> {
+        if self.data.len() - self.at < count {
+            bail!(
+                "ends after {} bytes, {} more expected",
+                self.data.len(),
+                count - (self.data.len() - self.at)
+            );
+        }
+        self.at += count;
+        Ok(&self.data[self.at - count..self.at])
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = &self.data[self.at..];
+        self.at = self.data.len();
+        rest
+    }
+}
+
+/// Service and class of a request, to name the reply answering it.
+#[derive(Clone, Copy)]
+struct Request {
+    service: u8,
+    class: Option<u16>,
+}
+
+/// Lines describing an encapsulation frame, its command first. `sent`
+/// holds the requests of the stream not answered yet.
+fn describe(frame: &[u8], request: bool, sent: &mut Vec<Request>) -> Result<Vec<String>> {
+    let mut reader = Reader::new(frame);
+    let command = reader.u16()?;
+    reader.u16()?;
+    let session = reader.u32()?;
+    let status = reader.u32()?;
+    reader.bytes(12)?;
+    let mut first = format!("{} session {:#010x}", command_name(command), session);
+    if status != 0 {
+        first.push_str(&format!(" status {:#06x}", status));
+    }
+    let mut lines = vec![first];
+    if command != 0x6F && command != 0x70 {
+        return Ok(lines);
+    }
+    reader.u32()?;
+    reader.u16()?;
+    let items = reader.u16()?;
+    for _ in 0..items {
+        let kind = reader.u16()?;
+        let length = reader.u16()? as usize;
+        let mut item = Reader::new(reader.bytes(length)?);
+        match kind {
+            0x00A1 => lines.push(format!("connection {:#010x}", item.u32()?)),
+            0x00B1 => {
+                let sequence = item.u16()?;
+                lines.push(format!("sequence {}", sequence));
+                message(item.rest(), request, sent, 0, &mut lines)?;
+            }
+            0x00B2 => message(item.rest(), request, sent, 0, &mut lines)?,
+            0x0000 => {}
+            _ => lines.push(format!("item {:#06x}: {}", kind, hex(item.rest()))),
+        }
+    }
+    Ok(lines)
+}
+
+fn command_name(command: u16) -> String {
+    match command {
+        0x0000 => "NOP".to_owned(),
+        0x0004 => "ListServices".to_owned(),
+        0x0063 => "ListIdentity".to_owned(),
+        0x0064 => "ListInterfaces".to_owned(),
+        0x0065 => "RegisterSession".to_owned(),
+        0x0066 => "UnRegisterSession".to_owned(),
+        0x006F => "SendRRData".to_owned(),
+        0x0070 => "SendUnitData".to_owned(),
+        _ => format!("command {:#06x}", command),
+    }
+}
+
+/// Describe a Message Router request or reply, and the requests embedded
+/// in Unconnected Send and Multiple Service Packets, indented by `depth`.
+fn message(
+    data: &[u8],
+    request: bool,
+    sent: &mut Vec<Request>,
+    depth: usize,
+    lines: &mut Vec<String>,
+) -> Result<()> {
+    let indent = "  ".repeat(depth);
+    let mut reader = Reader::new(data);
+    let service = reader.u8()?;
+    if request {
+        let words = reader.u8()? as usize;
+        let (target, class) = path(reader.bytes(words * 2)?)?;
+        let data = reader.rest();
+        lines.push(format!(
+            "{}{}  {}",
+            indent,
+            service_name(service, class),
+            target
+        ));
+        match (service, class) {
+            (0x52, Some(0x06)) => {
+                let mut send = Reader::new(data);
+                send.bytes(2)?;
+                let size = send.u16()? as usize;
+                let embedded = send.bytes(size)?;
+                send.bytes(size % 2)?;
+                let words = send.u8()? as usize;
+                send.u8()?;
+                let (route, _) = path(send.bytes(words * 2)?)?;
+                lines.push(format!("{}  route {}", indent, route));
+                return message(embedded, true, sent, depth + 1, lines);
+            }
+            (0x0A, _) => {
+                for embedded in packets(data)? {
+                    message(embedded, true, sent, depth + 1, lines)?;
+                }
+                sent.push(Request { service, class });
+                return Ok(());
+            }
+            _ => {}
+        }
+        sent.push(Request { service, class });
+        if !data.is_empty() {
+            lines.push(format!("{}  data {}", indent, hex(data)));
+        }
+        return Ok(());
+    }
+
+    reader.u8()?;
+    let general = reader.u8()?;
+    let words = reader.u8()? as usize;
+    let mut extended = Vec::with_capacity(words);
+    for _ in 0..words {
+        extended.push(reader.u16()?);
+    }
+    let data = reader.rest();
+    // Replies come in the order of the requests, but those of a Multiple
+    // Service Packet come after it.
+    let service = service & 0x7F;
+    let class = match sent.iter().position(|sent| sent.service == service) {
+        Some(at) => sent.remove(at).class,
+        None => None,
+    };
+    let status = Status {
+        general,
+        extended: extended.first().copied(),
+    };
+    let status = match status.is_ok() {
+        true => "success".to_owned(),
+        false => status.to_string(),
+    };
+    lines.push(format!(
+        "{}{} reply  {}",
+        indent,
+        service_name(service, class),
+        status
+    ));
+    if service == 0x0A && general != 0x08 {
+        for embedded in packets(data)? {
+            message(embedded, false, sent, depth + 1, lines)?;
+        }
+    } else if !data.is_empty() {
+        lines.push(format!("{}  data {}", indent, hex(data)));
+    }
+    Ok(())
+}
+
+/// The services packed in a Multiple Service Packet request or reply.
+fn packets(data: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut reader = Reader::new(data);
+    let count = reader.u16()? as usize;
+    let mut offsets = Vec::with_capacity(count + 1);
+    for _ in 0..count {
+        offsets.push(reader.u16()? as usize);
+    }
+    offsets.push(data.len());
+    offsets
+        .windows(2)
+        .map(|pair| match data.get(pair[0]..pair[1]) {
+            Some(packet) => Ok(packet),
+            None => bail!("service at offset {} is out of the packet", pair[0]),
+        })
+        .collect()
+}
+
+fn service_name(service: u8, class: Option<u16>) -> String {
+    let name = match (service, class) {
+        (0x4E, Some(0x06)) => "Forward Close",
+        (0x52, Some(0x06)) => "Unconnected Send",
+        (0x54, _) => "Forward Open",
+        (0x5B, _) => "Large Forward Open",
+        (0x01, _) => "Get Attributes All",
+        (0x03, _) => "Get Attribute List",
+        (0x0A, _) => "Multiple Service Packet",
+        (0x0E, _) => "Get Attribute Single",
+        (0x10, _) => "Set Attribute Single",
+        (0x4C, Some(0x6C)) => "Read Template",
+        (0x4C, _) => "Read Tag",
+        (0x4D, _) => "Write Tag",
+        (0x4E, _) => "Read Modify Write Tag",
+        (0x52, _) => "Read Tag Fragmented",
+        (0x53, _) => "Write Tag Fragmented",
+        (0x55, _) => "Get Instance Attribute List",
+        _ => return format!("service {:#04x}", service),
+    };
+    name.to_owned()
+}
+
+/// An EPATH as tag names with members and elements, or logical segments,
+/// with the class it addresses.
+fn path(data: &[u8]) -> Result<(String, Option<u16>)> {
+    let mut reader = Reader::new(data);
+    let mut text = String::new();
+    let mut class = None;
+    while reader.at < data.len() {
+        let segment = reader.u8()?;
+        match segment {
+            0x91 => {
+                let length = reader.u8()? as usize;
+                let name = String::from_utf8_lossy(reader.bytes(length)?).into_owned();
+                reader.bytes(length % 2)?;
+                push(&mut text, ".", name);
+            }
+            0x28 => push(&mut text, "", format!("[{}]", reader.u8()?)),
+            0x29 => {
+                reader.u8()?;
+                push(&mut text, "", format!("[{}]", reader.u16()?));
+            }
+            0x2A => {
+                reader.u8()?;
+                push(&mut text, "", format!("[{}]", reader.u32()?));
+            }
+            0x20 | 0x21 => {
+                let value = match segment {
+                    0x20 => reader.u8()? as u16,
+                    _ => {
+                        reader.u8()?;
+                        reader.u16()?
+                    }
+                };
+                class = Some(value);
+                push(&mut text, " ", format!("class {:#04x}", value));
+            }
+            0x24 | 0x25 | 0x30 | 0x31 | 0x2C => {
+                let value = match segment & 0x01 {
+                    0 => reader.u8()? as u16,
+                    _ => {
+                        reader.u8()?;
+                        reader.u16()?
+                    }
+                };
+                let name = match segment & 0xFE {
+                    0x24 => "instance",
+                    0x30 => "attribute",
+                    _ => "connection point",
+                };
+                push(&mut text, " ", format!("{} {}", name, value));
+            }
+            0x00..=0x1F => {
+                let port = segment & 0x0F;
+                let link = if segment & 0x10 != 0 {
+                    let length = reader.u8()? as usize;
+                    let link = String::from_utf8_lossy(reader.bytes(length)?).into_owned();
+                    reader.bytes(length % 2)?;
+                    link
+                } else {
+                    reader.u8()?.to_string()
+                };
+                push(&mut text, ",", format!("{},{}", port, link));
+            }
+            _ => {
+                push(
+                    &mut text,
+                    " ",
+                    format!("segment {:#04x} {}", segment, hex(reader.rest())),
+                );
+            }
+        }
+    }
+    Ok((text, class))
+}
+
+/// Add a part to a path after the separator, or right after the previous
+/// part for elements.
+fn push(text: &mut String, separator: &str, part: String) {
+    if !text.is_empty() && !part.starts_with('[') {
+        text.push_str(separator);
+    }
+    text.push_str(&part);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn paths() {
+        let tag = [
+            0x91, 0x05, b'L', b'i', b'n', b'e', b's', 0x00, 0x28, 0x02, 0x91, 0x05, b'S', b'p',
+            b'e', b'e', b'd', 0x00,
+        ];
+        assert_eq!(path(&tag).unwrap(), ("Lines[2].Speed".to_owned(), None));
+        let identity = [0x20, 0x01, 0x24, 0x01, 0x30, 0x07];
+        assert_eq!(
+            path(&identity).unwrap(),
+            ("class 0x01 instance 1 attribute 7".to_owned(), Some(0x01))
+        );
+        assert_eq!(path(&[0x01, 0x00]).unwrap().0, "1,0");
+        assert!(path(&[0x91, 0x08, b'A']).is_err());
+    }
+
+    #[test]
+    fn frames() {
+        let mut request = vec![0x6F, 0x00, 0, 0, 0x78, 0x56, 0x34, 0x12];
+        request.extend([0; 16]);
+        // Interface handle, timeout and two items, the second reading Speed.
+        request.extend([0, 0, 0, 0, 0, 0, 2, 0, 0, 0, 0, 0, 0xB2, 0, 12, 0]);
+        request.extend([0x4C, 0x04, 0x91, 0x05, b'S', b'p', b'e', b'e', b'd', 0x00]);
+        request.extend([0x01, 0x00]);
+        let length = (request.len() - HEADER) as u16;
+        request[2..4].copy_from_slice(&length.to_le_bytes());
+
+        let mut buffer = request[..30].to_vec();
+        assert!(next_frame(&mut buffer).is_none());
+        buffer.extend(&request[30..]);
+        buffer.extend([0x65, 0x00]);
+        let frame = next_frame(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x65, 0x00]);
+
+        let mut sent = Vec::new();
+        assert_eq!(
+            describe(&frame, true, &mut sent).unwrap(),
+            [
+                "SendRRData session 0x12345678",
+                "Read Tag  Speed",
+                "  data 01 00"
+            ]
+        );
+        let mut reply = frame[..HEADER + 8].to_vec();
+        reply.extend([
+            0, 0, 0, 0, 0xB2, 0, 8, 0, 0xCC, 0, 0x05, 0x01, 0x04, 0x21, 0, 0,
+        ]);
+        let length = (reply.len() - HEADER) as u16;
+        reply[2..4].copy_from_slice(&length.to_le_bytes());
+        assert_eq!(
+            describe(&reply, false, &mut sent).unwrap()[1..],
+            [
+                "Read Tag reply  path destination unknown: the tag or object instance doesn't exist (CIP status 0x05, extended status 0x2104)",
+                "  data 00 00"
+            ]
+        );
+        assert!(sent.is_empty());
+    }
+}
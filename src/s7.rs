@@ -0,0 +1,796 @@
+use crate::backend::PlcBackend;
+use crate::failure::Failure;
+use crate::plc;
+use crate::value::PlcValue;
+use anyhow::{anyhow, bail, Context, Result};
+use async_trait::async_trait;
+use bytes::{BufMut, Bytes, BytesMut};
+use rseip::client::ab_eip::TagType;
+use std::fmt::Display;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// ISO-on-TCP port of S7 CPUs.
+const DEFAULT_PORT: u16 = 102;
+
+/// Time given to the CPU to accept the connection or answer a request.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// PDU size asked for at setup; S7-1200s settle on 240, S7-1500s on 480.
+const PDU_SIZE: u16 = 480;
+
+/// Most items of a Read Var request.
+const MAX_ITEMS: usize = 20;
+
+/// Bytes of the S7 header of a job, and of an acknowledgment with data,
+/// which adds an error class and code.
+const JOB_HEADER: usize = 10;
+const ACK_HEADER: usize = 12;
+
+/// COTP connection request and confirm, and data transfer.
+const COTP_CR: u8 = 0xE0;
+const COTP_CC: u8 = 0xD0;
+const COTP_DT: u8 = 0xF0;
+
+const ROSCTR_JOB: u8 = 0x01;
+const ROSCTR_ACK_DATA: u8 = 0x03;
+
+const FUNCTION_SETUP: u8 = 0xF0;
+const FUNCTION_READ: u8 = 0x04;
+const FUNCTION_WRITE: u8 = 0x05;
+
+/// Transport sizes of the request items, and of the data items.
+const TRANSPORT_BIT: u8 = 0x01;
+const TRANSPORT_BYTE: u8 = 0x02;
+const DATA_BIT: u8 = 0x03;
+const DATA_BYTE: u8 = 0x04;
+
+/// Return code of an item that succeeded.
+const RETURN_SUCCESS: u8 = 0xFF;
+
+/// Memory areas of the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Area {
+    Input,
+    Output,
+    Marker,
+    DataBlock(u16),
+}
+
+impl Area {
+    fn code(self) -> u8 {
+        match self {
+            Area::Input => 0x81,
+            Area::Output => 0x82,
+            Area::Marker => 0x83,
+            Area::DataBlock(_) => 0x84,
+        }
+    }
+}
+
+/// An absolute address, e.g. `DB1.DBX0.3`, `DB1.DBW2`, `DB1.DBD8:REAL`,
+/// `M10.1`, `MW20` or `I0.0`, typed by its size or by a `:TYPE` suffix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Address {
+    area: Area,
+    byte: u16,
+    bit: Option<u8>,
+    tag_type: TagType,
+}
+
+impl Address {
+    fn parse(text: &str) -> Result<Self> {
+        let invalid = || anyhow!("`{}` is not an S7 address like DB1.DBW2 or M10.1", text);
+        let upper = text.trim().to_ascii_uppercase();
+        let (address, suffix) = match upper.split_once(':') {
+            Some((address, suffix)) => (address, Some(suffix)),
+            None => (upper.as_str(), None),
+        };
+
+        let (area, rest) = if let Some(rest) = address.strip_prefix("DB") {
+            let (number, rest) = rest.split_once(".DB").ok_or_else(invalid)?;
+            let number = number.parse().map_err(|_| invalid())?;
+            (Area::DataBlock(number), rest)
+        } else {
+            let mut chars = address.chars();
+            let area = match chars.next() {
+                Some('I' | 'E') => Area::Input,
+                Some('Q' | 'A') => Area::Output,
+                Some('M') => Area::Marker,
+                _ => return Err(invalid()),
+            };
+            (area, chars.as_str())
+        };
+
+        // Bits of the areas other than data blocks leave out the X.
+        let (size, offset) = match rest.chars().next() {
+            Some(size @ ('X' | 'B' | 'W' | 'D')) => (size, &rest[1..]),
+            Some(c) if c.is_ascii_digit() && !matches!(area, Area::DataBlock(_)) => ('X', rest),
+            _ => return Err(invalid()),
+        };
+        let (byte, bit) = match offset.split_once('.') {
+            Some((byte, bit)) => (byte, Some(bit.parse::<u8>().map_err(|_| invalid())?)),
+            None => (offset, None),
+        };
+        let byte = byte.parse().map_err(|_| invalid())?;
+        match (size, bit) {
+            ('X', Some(bit)) if bit < 8 => {}
+            ('X', Some(_)) => bail!("`{}` addresses a bit past the end of its byte", text),
+            ('X', None) => bail!("`{}` is missing the bit of its byte, e.g. .0", text),
+            (_, Some(_)) => return Err(invalid()),
+            (_, None) => {}
+        }
+
+        let types: &[TagType] = match size {
+            'X' => &[TagType::Bool],
+            'B' => &[TagType::Usint, TagType::Sint],
+            'W' => &[TagType::Int, TagType::Uint],
+            _ => &[TagType::Dint, TagType::Udint, TagType::Dword, TagType::Real],
+        };
+        let tag_type = match suffix {
+            None => types[0],
+            Some(name) => *types
+                .iter()
+                .find(|tag_type| format!("{:?}", tag_type).eq_ignore_ascii_case(name))
+                .ok_or_else(|| anyhow!("`{}` can't be read as a {}", text, name))?,
+        };
+        Ok(Self {
+            area,
+            byte,
+            bit,
+            tag_type,
+        })
+    }
+
+    /// Bytes read or written at the address.
+    fn size(&self) -> u16 {
+        match self.tag_type {
+            TagType::Bool | TagType::Usint | TagType::Sint => 1,
+            TagType::Int | TagType::Uint => 2,
+            _ => 4,
+        }
+    }
+
+    /// Item of a Read Var or Write Var request.
+    fn encode(&self, out: &mut BytesMut) {
+        out.put_slice(&[0x12, 0x0A, 0x10]);
+        match self.bit {
+            Some(_) => out.put_u8(TRANSPORT_BIT),
+            None => out.put_u8(TRANSPORT_BYTE),
+        }
+        out.put_u16(self.size());
+        out.put_u16(match self.area {
+            Area::DataBlock(number) => number,
+            _ => 0,
+        });
+        out.put_u8(self.area.code());
+        let bits = self.byte as u32 * 8 + self.bit.unwrap_or(0) as u32;
+        out.put_slice(&bits.to_be_bytes()[1..]);
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<PlcValue> {
+        let size = self.size() as usize;
+        if data.len() < size {
+            bail!("expected {} bytes of data, got {}", size, data.len());
+        }
+        let word = || [data[0], data[1]];
+        let dword = || [data[0], data[1], data[2], data[3]];
+        Ok(match self.tag_type {
+            TagType::Bool => PlcValue::Bool(data[0] & 1 == 1),
+            TagType::Usint => PlcValue::Usint(data[0]),
+            TagType::Sint => PlcValue::Sint(data[0] as i8),
+            TagType::Int => PlcValue::Int(i16::from_be_bytes(word())),
+            TagType::Uint => PlcValue::Uint(u16::from_be_bytes(word())),
+            TagType::Dint => PlcValue::Dint(i32::from_be_bytes(dword())),
+            TagType::Udint => PlcValue::Udint(u32::from_be_bytes(dword())),
+            TagType::Dword => PlcValue::Dword(u32::from_be_bytes(dword())),
+            _ => PlcValue::Real(f32::from_be_bytes(dword())),
+        })
+    }
+
+    /// Data item of a Write Var request, with a length counted in bits.
+    fn encode_value(&self, value: PlcValue, out: &mut BytesMut) -> Result<()> {
+        if value.tag_type() != self.tag_type {
+            bail!(
+                "the address is a {:?}, it can't take the {} {}",
+                self.tag_type,
+                value.type_name(),
+                value
+            );
+        }
+        out.put_u8(0);
+        match self.bit {
+            Some(_) => {
+                out.put_u8(DATA_BIT);
+                out.put_u16(1);
+            }
+            None => {
+                out.put_u8(DATA_BYTE);
+                out.put_u16(self.size() * 8);
+            }
+        }
+        match value {
+            PlcValue::Bool(set) => out.put_u8(set as u8),
+            PlcValue::Usint(value) => out.put_u8(value),
+            PlcValue::Sint(value) => out.put_i8(value),
+            PlcValue::Int(value) => out.put_i16(value),
+            PlcValue::Uint(value) => out.put_u16(value),
+            PlcValue::Dint(value) => out.put_i32(value),
+            PlcValue::Udint(value) | PlcValue::Dword(value) => out.put_u32(value),
+            PlcValue::Real(value) => out.put_f32(value),
+            _ => unreachable!("addresses only take elementary types"),
+        }
+        Ok(())
+    }
+
+    /// Bytes of the reply item of a read, padded to an even length.
+    fn reply_size(&self) -> usize {
+        4 + (self.size() as usize).next_multiple_of(2)
+    }
+}
+
+/// Check an address without asking the CPU.
+pub fn check_address(text: &str) -> Result<()> {
+    Address::parse(text).map(|_| ())
+}
+
+/// Session with an S7 CPU, connected on the first request and again after
+/// a failed one.
+pub struct S7Client {
+    host: String,
+    /// Remote TSAP, naming the rack and slot of the CPU.
+    tsap: u16,
+    connection: Option<Connection>,
+}
+
+impl S7Client {
+    /// Nothing is sent until the first request.
+    pub fn new(address: &str, rack: u8, slot: u8) -> Self {
+        let host = match address.contains(':') {
+            true => address.to_owned(),
+            false => format!("{}:{}", address, DEFAULT_PORT),
+        };
+        Self {
+            host,
+            // A PG connection to the rack and slot.
+            tsap: 0x0100 | (rack as u16 * 0x20 + slot as u16),
+            connection: None,
+        }
+    }
+
+    /// Send a job and return the parameters and data of its
+    /// acknowledgment. The connection is dropped when the job fails, so the
+    /// next one starts afresh.
+    async fn request(
+        &mut self,
+        service: &'static str,
+        tag: &str,
+        function: &[u8],
+        data: &[u8],
+    ) -> Result<(Bytes, Bytes)> {
+        if self.connection.is_none() {
+            let connection = Connection::open(&self.host, self.tsap)
+                .await
+                .with_context(|| format!("failed to connect to {}", self.host))?;
+            self.connection = Some(connection);
+        }
+        let connection = self.connection.as_mut().unwrap();
+        let result = plc::transaction(service, tag, async {
+            tokio::time::timeout(TIMEOUT, connection.job(function, data))
+                .await
+                .unwrap_or_else(|_| Err(anyhow!("no reply from the CPU")))
+        })
+        .await;
+        if result.is_err() {
+            self.connection = None;
+        }
+        result
+    }
+
+    /// Read the addresses with one request, a result each.
+    async fn read_items(&mut self, tags: &[&str], addresses: &[Address]) -> Vec<Result<PlcValue>> {
+        let mut function = BytesMut::new();
+        function.put_u8(FUNCTION_READ);
+        function.put_u8(addresses.len() as u8);
+        for address in addresses {
+            address.encode(&mut function);
+        }
+        let data = match self.request("read", &tags.join(","), &function, &[]).await {
+            Ok((_, data)) => data,
+            Err(e) => {
+                let e = format!("{:#}", e);
+                return tags.iter().map(|_| Err(anyhow!("{}", e))).collect();
+            }
+        };
+        let items = match parse_items(&data, addresses.len()) {
+            Ok(items) => items,
+            Err(e) => return tags.iter().map(|_| Err(anyhow!("{:#}", e))).collect(),
+        };
+        items
+            .into_iter()
+            .zip(addresses)
+            .zip(tags)
+            .map(|((item, address), tag)| {
+                item.and_then(|data| address.decode(&data))
+                    .map_err(|e| anyhow!("{}: {}", tag, e))
+            })
+            .collect()
+    }
+
+    fn pdu_size(&self) -> usize {
+        self.connection
+            .as_ref()
+            .map_or(PDU_SIZE, |connection| connection.pdu_size) as usize
+    }
+}
+
+#[async_trait]
+impl PlcBackend for S7Client {
+    async fn read(&mut self, tag: &str) -> Result<PlcValue> {
+        let address = Address::parse(tag)?;
+        self.read_items(&[tag], &[address]).await.remove(0)
+    }
+
+    /// Read Var requests carry as many items as the PDU size allows.
+    async fn read_many(&mut self, tags: &[String]) -> Vec<Result<PlcValue>> {
+        let mut values: Vec<Option<Result<PlcValue>>> = tags.iter().map(|_| None).collect();
+        let mut parsed = Vec::new();
+        for (idx, tag) in tags.iter().enumerate() {
+            match Address::parse(tag) {
+                Ok(address) => parsed.push((idx, address)),
+                Err(e) => values[idx] = Some(Err(e)),
+            }
+        }
+        for batch in batches(&parsed, self.pdu_size()) {
+            let names: Vec<&str> = batch.iter().map(|(idx, _)| tags[*idx].as_str()).collect();
+            let addresses: Vec<Address> = batch.iter().map(|(_, address)| *address).collect();
+            let read = self.read_items(&names, &addresses).await;
+            for ((idx, _), value) in batch.iter().zip(read) {
+                values[*idx] = Some(value);
+            }
+        }
+        values.into_iter().flatten().collect()
+    }
+
+    fn request_count(&self, tags: &[String]) -> usize {
+        let parsed: Vec<_> = tags
+            .iter()
+            .filter_map(|tag| Address::parse(tag).ok())
+            .enumerate()
+            .collect();
+        batches(&parsed, self.pdu_size()).len()
+    }
+
+    async fn write(&mut self, tag: &str, value: PlcValue) -> Result<()> {
+        let address = Address::parse(tag)?;
+        let mut data = BytesMut::new();
+        address
+            .encode_value(value, &mut data)
+            .map_err(|e| anyhow!("{}: {}", tag, e))?;
+        let mut function = BytesMut::new();
+        function.put_slice(&[FUNCTION_WRITE, 1]);
+        address.encode(&mut function);
+        let (_, reply) = self.request("write", tag, &function, &data).await?;
+        match reply.first() {
+            Some(&RETURN_SUCCESS) => Ok(()),
+            Some(&code) => {
+                let status = Status::Item(code);
+                Err(status.failure().error(format!("{}: {}", tag, status)))
+            }
+            None => bail!("{}: empty Write Var reply", tag),
+        }
+    }
+
+    async fn close(&mut self) -> Result<()> {
+        if let Some(mut connection) = self.connection.take() {
+            connection.stream.shutdown().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Addresses packed into Read Var requests whose request and reply both fit
+/// the PDU size.
+fn batches(addresses: &[(usize, Address)], pdu_size: usize) -> Vec<Vec<(usize, Address)>> {
+    let mut batches = Vec::new();
+    let mut batch: Vec<(usize, Address)> = Vec::new();
+    let (mut request, mut reply) = (JOB_HEADER + 2, ACK_HEADER + 2);
+    for &(idx, address) in addresses {
+        let full = batch.len() == MAX_ITEMS
+            || request + 12 > pdu_size
+            || reply + address.reply_size() > pdu_size;
+        if full {
+            batches.push(std::mem::take(&mut batch));
+            (request, reply) = (JOB_HEADER + 2, ACK_HEADER + 2);
+        }
+        batch.push((idx, address));
+        request += 12;
+        reply += address.reply_size();
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+/// Data of the items of a Read Var reply, or the error of each.
+fn parse_items(mut data: &[u8], count: usize) -> Result<Vec<Result<Bytes>>> {
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        if data.len() < 4 {
+            bail!("Read Var reply too short");
+        }
+        let (code, transport) = (data[0], data[1]);
+        let mut len = u16::from_be_bytes([data[2], data[3]]) as usize;
+        if code != RETURN_SUCCESS {
+            let status = Status::Item(code);
+            items.push(Err(status.failure().error(status)));
+            data = &data[4..];
+            continue;
+        }
+        // Bit and byte lengths count bits, the others bytes.
+        if matches!(transport, DATA_BIT | DATA_BYTE) {
+            len = len.div_ceil(8);
+        }
+        if data.len() < 4 + len {
+            bail!("Read Var reply too short");
+        }
+        items.push(Ok(Bytes::copy_from_slice(&data[4..4 + len])));
+        // Items are padded to an even length, but for the last one.
+        data = &data[(4 + len.next_multiple_of(2)).min(data.len())..];
+    }
+    Ok(items)
+}
+
+/// ISO-on-TCP connection to the CPU.
+struct Connection {
+    stream: TcpStream,
+    pdu_size: u16,
+    pdu_reference: u16,
+}
+
+impl Connection {
+    /// Connect to the TSAP and agree on the PDU size.
+    async fn open(host: &str, tsap: u16) -> Result<Self> {
+        let stream = tokio::time::timeout(TIMEOUT, TcpStream::connect(host))
+            .await
+            .map_err(|_| anyhow!("timed out"))??;
+        stream.set_nodelay(true)?;
+        let mut connection = Self {
+            stream,
+            pdu_size: PDU_SIZE,
+            pdu_reference: 0,
+        };
+        tokio::time::timeout(TIMEOUT, connection.setup(tsap))
+            .await
+            .map_err(|_| anyhow!("timed out"))??;
+        Ok(connection)
+    }
+
+    async fn setup(&mut self, tsap: u16) -> Result<()> {
+        let mut request = vec![17, COTP_CR, 0, 0, 0, 1, 0];
+        // TPDU size of 1024 bytes, then the local and remote TSAPs.
+        request.extend_from_slice(&[0xC0, 1, 0x0A, 0xC1, 2, 0x01, 0x00, 0xC2, 2]);
+        request.extend_from_slice(&tsap.to_be_bytes());
+        self.send(&request).await?;
+        let reply = self.receive().await?;
+        if reply.get(1).map(|code| code & 0xF0) != Some(COTP_CC) {
+            bail!(
+                "the CPU refused the connection to rack {} slot {}",
+                (tsap & 0xFF) / 0x20,
+                (tsap & 0xFF) % 0x20
+            );
+        }
+
+        let mut function = vec![FUNCTION_SETUP, 0];
+        // One job in flight either way.
+        function.extend_from_slice(&[0, 1, 0, 1]);
+        function.extend_from_slice(&PDU_SIZE.to_be_bytes());
+        let (reply, _) = self.job(&function, &[]).await?;
+        if reply.len() < 8 {
+            bail!("setup communication reply too short");
+        }
+        self.pdu_size = u16::from_be_bytes([reply[6], reply[7]]).min(PDU_SIZE);
+        Ok(())
+    }
+
+    /// Send a job and wait for its acknowledgment, returning its parameters
+    /// and data.
+    async fn job(&mut self, function: &[u8], data: &[u8]) -> Result<(Bytes, Bytes)> {
+        self.pdu_reference = self.pdu_reference.wrapping_add(1);
+        let mut packet = vec![2, COTP_DT, 0x80];
+        packet.extend_from_slice(&header(self.pdu_reference, function.len(), data.len()));
+        packet.extend_from_slice(function);
+        packet.extend_from_slice(data);
+        self.send(&packet).await?;
+        let reply = self.receive().await?;
+        parse_ack(self.pdu_reference, &reply)
+    }
+
+    /// Send a COTP packet in a TPKT.
+    async fn send(&mut self, packet: &[u8]) -> Result<()> {
+        let mut frame = Vec::with_capacity(4 + packet.len());
+        frame.extend_from_slice(&[3, 0]);
+        frame.extend_from_slice(&(4 + packet.len() as u16).to_be_bytes());
+        frame.extend_from_slice(packet);
+        self.stream.write_all(&frame).await?;
+        Ok(())
+    }
+
+    /// Receive a COTP packet out of its TPKT.
+    async fn receive(&mut self) -> Result<Vec<u8>> {
+        let mut header = [0; 4];
+        self.stream.read_exact(&mut header).await?;
+        if header[0] != 3 {
+            bail!("not an ISO-on-TCP packet");
+        }
+        let len = u16::from_be_bytes([header[2], header[3]]) as usize;
+        let mut packet = vec![0; len.saturating_sub(4)];
+        self.stream.read_exact(&mut packet).await?;
+        Ok(packet)
+    }
+}
+
+fn header(reference: u16, function_len: usize, data_len: usize) -> [u8; JOB_HEADER] {
+    let mut header = [0x32, ROSCTR_JOB, 0, 0, 0, 0, 0, 0, 0, 0];
+    header[4..6].copy_from_slice(&reference.to_be_bytes());
+    header[6..8].copy_from_slice(&(function_len as u16).to_be_bytes());
+    header[8..10].copy_from_slice(&(data_len as u16).to_be_bytes());
+    header
+}
+
+/// Check the acknowledgment of a job, a COTP data packet, and split it into
+/// parameters and data.
+fn parse_ack(reference: u16, packet: &[u8]) -> Result<(Bytes, Bytes)> {
+    let cotp_len = *packet.first().ok_or_else(|| anyhow!("empty reply"))? as usize + 1;
+    if packet.get(1) != Some(&COTP_DT) || packet.len() < cotp_len + ACK_HEADER {
+        bail!("unexpected reply from the CPU");
+    }
+    let s7 = &packet[cotp_len..];
+    if s7[0] != 0x32 || s7[1] != ROSCTR_ACK_DATA {
+        bail!("unexpected S7 reply (type {:#04x})", s7[1]);
+    }
+    let reply_reference = u16::from_be_bytes([s7[4], s7[5]]);
+    if reply_reference != reference {
+        bail!(
+            "reply to PDU {} while waiting for {}",
+            reply_reference,
+            reference
+        );
+    }
+    let error = u16::from_be_bytes([s7[10], s7[11]]);
+    if error != 0 {
+        bail!("{}", Status::Header(error));
+    }
+    let function_len = u16::from_be_bytes([s7[6], s7[7]]) as usize;
+    let data_len = u16::from_be_bytes([s7[8], s7[9]]) as usize;
+    let body = &s7[ACK_HEADER..];
+    if body.len() < function_len + data_len {
+        bail!("S7 reply too short");
+    }
+    Ok((
+        Bytes::copy_from_slice(&body[..function_len]),
+        Bytes::copy_from_slice(&body[function_len..function_len + data_len]),
+    ))
+}
+
+/// Error of a whole job, or of one of its items.
+enum Status {
+    Header(u16),
+    Item(u8),
+}
+
+impl Status {
+    fn failure(&self) -> Failure {
+        match *self {
+            Status::Item(0x03) => Failure::WriteRejected,
+            Status::Item(0x05 | 0x0A) => Failure::TagNotFound,
+            Status::Item(0x06 | 0x07) => Failure::TypeMismatch,
+            _ => Failure::Error,
+        }
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            Status::Header(code) => {
+                let text = match code {
+                    0x8104 => "service not supported, allow PUT/GET communication in the protection settings of the CPU",
+                    0x8500 => "request too long for the PDU size",
+                    0xD602 => "password protected",
+                    _ => "job failed",
+                };
+                write!(f, "{} (S7 error {:#06x})", text, code)
+            }
+            Status::Item(code) => {
+                let text = match code {
+                    0x01 => "hardware fault",
+                    0x03 => "access to the object not allowed",
+                    0x05 => "address out of range, or the block uses optimized access",
+                    0x06 => "data type not supported",
+                    0x07 => "data type inconsistent",
+                    0x0A => "object does not exist",
+                    _ => "unknown error",
+                };
+                write!(f, "{} (S7 return code {:#04x})", text, code)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn item(address: &str) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        Address::parse(address).unwrap().encode(&mut out);
+        out.to_vec()
+    }
+
+    #[test]
+    fn addresses() {
+        assert_eq!(
+            item("DB1.DBW2"),
+            [0x12, 0x0A, 0x10, 0x02, 0, 2, 0, 1, 0x84, 0, 0, 16]
+        );
+        assert_eq!(
+            item("db10.dbx4.3"),
+            [0x12, 0x0A, 0x10, 0x01, 0, 1, 0, 10, 0x84, 0, 0, 35]
+        );
+        assert_eq!(
+            item("MD100:REAL"),
+            [0x12, 0x0A, 0x10, 0x02, 0, 4, 0, 0, 0x83, 0, 3, 0x20]
+        );
+        assert_eq!(Address::parse("Q0.1").unwrap().area, Area::Output);
+        assert_eq!(Address::parse("IB3:SINT").unwrap().tag_type, TagType::Sint);
+        assert_eq!(Address::parse("DB1.DBD8").unwrap().tag_type, TagType::Dint);
+
+        for invalid in [
+            "DB1.DBX0",
+            "DB1.DBX0.8",
+            "DB1.DBW2.1",
+            "DB1.DBW2:REAL",
+            "DB1.W2",
+            "M10",
+            "X0.0",
+            "PT_101",
+        ] {
+            assert!(Address::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn values() {
+        let real = Address::parse("DB1.DBD0:REAL").unwrap();
+        assert_eq!(
+            real.decode(&1.5f32.to_be_bytes()).unwrap(),
+            PlcValue::Real(1.5)
+        );
+        let int = Address::parse("MW2").unwrap();
+        assert_eq!(int.decode(&[0xFF, 0xFE]).unwrap(), PlcValue::Int(-2));
+
+        let mut out = BytesMut::new();
+        int.encode_value(PlcValue::Int(-2), &mut out).unwrap();
+        assert_eq!(&out[..], [0, DATA_BYTE, 0, 16, 0xFF, 0xFE]);
+        let mut out = BytesMut::new();
+        let bit = Address::parse("M0.7").unwrap();
+        bit.encode_value(PlcValue::Bool(true), &mut out).unwrap();
+        assert_eq!(&out[..], [0, DATA_BIT, 0, 1, 1]);
+        assert!(int
+            .encode_value(PlcValue::Dint(1), &mut BytesMut::new())
+            .is_err());
+    }
+
+    #[test]
+    fn replies() {
+        let data = [
+            0xFF, DATA_BIT, 0, 1, 1, 0, // a bit, padded
+            0x0A, 0, 0, 0, // a missing block
+            0xFF, DATA_BYTE, 0, 32, 0, 0, 0, 7,
+        ];
+        let items = parse_items(&data, 3).unwrap();
+        assert_eq!(&items[0].as_ref().unwrap()[..], [1]);
+        assert_eq!(
+            items[1].as_ref().unwrap_err().to_string(),
+            "object does not exist (S7 return code 0x0a)"
+        );
+        assert_eq!(&items[2].as_ref().unwrap()[..], [0, 0, 0, 7]);
+        assert!(parse_items(&data[..6], 2).is_err());
+
+        let mut ack = vec![
+            2,
+            COTP_DT,
+            0x80,
+            0x32,
+            ROSCTR_ACK_DATA,
+            0,
+            0,
+            0,
+            5,
+            0,
+            2,
+            0,
+            0,
+        ];
+        ack.extend_from_slice(&[0x81, 0x04, FUNCTION_READ, 1]);
+        assert_eq!(
+            parse_ack(5, &ack).unwrap_err().to_string(),
+            "service not supported, allow PUT/GET communication in the protection settings of the CPU (S7 error 0x8104)"
+        );
+        ack[13..15].copy_from_slice(&[0, 0]);
+        assert!(parse_ack(6, &ack).is_err());
+        let (function, data) = parse_ack(5, &ack).unwrap();
+        assert_eq!(&function[..], [FUNCTION_READ, 1]);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn batching() {
+        let addresses: Vec<_> = (0..30)
+            .map(|idx| (idx, Address::parse(&format!("DB1.DBD{}", idx * 4)).unwrap()))
+            .collect();
+        let sizes: Vec<usize> = batches(&addresses, 480).iter().map(Vec::len).collect();
+        assert_eq!(sizes, [20, 10]);
+        // Request items take 12 bytes, so 19 of them fit 240 bytes.
+        let sizes: Vec<usize> = batches(&addresses, 240).iter().map(Vec::len).collect();
+        assert_eq!(sizes, [19, 11]);
+    }
+
+    /// A CPU answering the connection, the setup and one read of DB1.DBW2
+    /// holding 42.
+    async fn fake_cpu(listener: TcpListener) {
+        async fn frame(stream: &mut TcpStream) -> Vec<u8> {
+            let mut header = [0; 4];
+            stream.read_exact(&mut header).await.unwrap();
+            let mut packet = vec![0; u16::from_be_bytes([header[2], header[3]]) as usize - 4];
+            stream.read_exact(&mut packet).await.unwrap();
+            packet
+        }
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let reply = |s7: &[u8]| {
+            let mut frame = vec![3, 0, 0, 0, 2, COTP_DT, 0x80];
+            frame.extend_from_slice(s7);
+            let len = frame.len() as u16;
+            frame[2..4].copy_from_slice(&len.to_be_bytes());
+            frame
+        };
+
+        let request = frame(&mut stream).await;
+        assert_eq!(request[1], COTP_CR);
+        assert_eq!(&request[request.len() - 2..], [0x01, 0x01]);
+        stream
+            .write_all(&[3, 0, 0, 11, 6, COTP_CC, 0, 1, 0, 1, 0])
+            .await
+            .unwrap();
+
+        let request = frame(&mut stream).await;
+        assert_eq!(request[3 + JOB_HEADER], FUNCTION_SETUP);
+        let reference = [request[7], request[8]];
+        let mut ack = vec![0x32, ROSCTR_ACK_DATA, 0, 0, reference[0], reference[1]];
+        ack.extend_from_slice(&[0, 8, 0, 0, 0, 0, FUNCTION_SETUP, 0, 0, 1, 0, 1, 0, 240]);
+        stream.write_all(&reply(&ack)).await.unwrap();
+
+        let request = frame(&mut stream).await;
+        assert_eq!(&request[3 + JOB_HEADER..][..2], [FUNCTION_READ, 1]);
+        let reference = [request[7], request[8]];
+        let mut ack = vec![0x32, ROSCTR_ACK_DATA, 0, 0, reference[0], reference[1]];
+        ack.extend_from_slice(&[0, 2, 0, 6, 0, 0, FUNCTION_READ, 1]);
+        ack.extend_from_slice(&[0xFF, DATA_BYTE, 0, 16, 0, 42]);
+        stream.write_all(&reply(&ack)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn read_from_a_cpu() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let cpu = tokio::spawn(fake_cpu(listener));
+        let mut client = S7Client::new(&address, 0, 1);
+        assert_eq!(client.read("DB1.DBW2").await.unwrap(), PlcValue::Int(42));
+        assert_eq!(client.pdu_size(), 240);
+        cpu.await.unwrap();
+    }
+}
@@ -0,0 +1,214 @@
+use crate::monitor::{Quality, Sample};
+use crate::plc::Plc;
+use crate::service;
+use crate::timing::{self, CycleStats};
+use crate::value;
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use serde::{Deserialize, Deserializer};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How often a tag is polled: one of the `fast`, `medium` and `slow`
+/// classes, or its own interval like `250ms`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scan {
+    Fast,
+    Medium,
+    Slow,
+    Every(Duration),
+}
+
+impl FromStr for Scan {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "fast" => Ok(Scan::Fast),
+            "medium" => Ok(Scan::Medium),
+            "slow" => Ok(Scan::Slow),
+            other => match humantime::parse_duration(other) {
+                Ok(interval) if interval.is_zero() => {
+                    Err("the scan interval must be longer than zero".to_owned())
+                }
+                Ok(interval) => Ok(Scan::Every(interval)),
+                Err(_) => Err(format!(
+                    "invalid scan `{}`, expected fast, medium, slow or an interval like 250ms",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Scan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Intervals of the scan classes.
+#[derive(Debug, Clone, Copy)]
+pub struct ScanClasses {
+    pub fast: Duration,
+    pub medium: Duration,
+    pub slow: Duration,
+}
+
+impl ScanClasses {
+    fn interval(&self, scan: Scan) -> Duration {
+        match scan {
+            Scan::Fast => self.fast,
+            Scan::Medium => self.medium,
+            Scan::Slow => self.slow,
+            Scan::Every(interval) => interval,
+        }
+    }
+}
+
+/// Tags polled at the same interval.
+struct ScanGroup {
+    interval: Duration,
+    tags: Vec<String>,
+    due: Instant,
+}
+
+/// Polls each tag at the interval of its scan class. Groups falling due
+/// together are read in the same batched requests.
+pub struct Scheduler {
+    groups: Vec<ScanGroup>,
+    stats: Option<CycleStats>,
+}
+
+impl Scheduler {
+    /// Schedule the tags with their scan, all of them due right away.
+    pub fn new(tags: impl IntoIterator<Item = (String, Scan)>, classes: &ScanClasses) -> Self {
+        let now = Instant::now();
+        let mut groups: Vec<ScanGroup> = Vec::new();
+        for (tag, scan) in tags {
+            let interval = classes.interval(scan);
+            match groups.iter_mut().find(|group| group.interval == interval) {
+                Some(group) => group.tags.push(tag),
+                None => groups.push(ScanGroup {
+                    interval,
+                    tags: vec![tag],
+                    due: now,
+                }),
+            }
+        }
+        // Fastest first, so their tags lead the batches.
+        groups.sort_by_key(|group| group.interval);
+        Self {
+            groups,
+            stats: None,
+        }
+    }
+
+    /// Keep timing statistics of the scans and reads, logged as they go.
+    pub fn record_stats(&mut self, stats: CycleStats) {
+        self.stats = Some(stats);
+    }
+
+    /// Requests per second the scans take on the controller if every group
+    /// is read on its own, the most they can take.
+    pub fn requests_per_second(&self, client: &Plc) -> f64 {
+        self.groups
+            .iter()
+            .map(|group| client.request_count(&group.tags) as f64 / group.interval.as_secs_f64())
+            .sum()
+    }
+
+    /// Tags due at the next scan, waiting for it.
+    async fn next_due(&mut self) -> Vec<String> {
+        let due = match self.groups.iter().map(|group| group.due).min() {
+            Some(due) => due,
+            None => return std::future::pending().await,
+        };
+        tokio::time::sleep_until(due).await;
+        let now = Instant::now();
+        let mut tags = Vec::new();
+        for group in &mut self.groups {
+            if group.due <= now {
+                if let Some(stats) = &mut self.stats {
+                    stats.cycle(group.interval, now);
+                }
+                tags.extend(group.tags.iter().cloned());
+                // Skip scans missed while the controller was slow to reply.
+                while group.due <= now {
+                    group.due += group.interval;
+                }
+            }
+        }
+        tags
+    }
+
+    /// Wait for the next scan and read the tags due, or `None` when a
+    /// shutdown is asked for in between.
+    pub async fn poll(&mut self, client: &mut Plc) -> Result<Option<Vec<Sample>>> {
+        let tags = tokio::select! {
+            tags = self.next_due() => tags,
+            _ = service::stopped() => {
+                if let Some(stats) = &mut self.stats {
+                    stats.report();
+                }
+                return Ok(None);
+            }
+        };
+        let (values, took) = timing::timed(value::read_values(client, &tags)).await;
+        if let Some(stats) = &mut self.stats {
+            stats.latency("controller read", took);
+            stats.tick();
+        }
+        let timestamp = Local::now();
+        tags.into_iter()
+            .zip(values)
+            .map(|(tag, value)| {
+                let value = value.map_err(|e| anyhow!("failed to read {}: {:#}", tag, e))?;
+                Ok(Sample {
+                    timestamp,
+                    tag,
+                    value,
+                    quality: Quality::Good,
+                })
+            })
+            .collect::<Result<_>>()
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scan_classes() {
+        assert_eq!("fast".parse(), Ok(Scan::Fast));
+        assert_eq!("250ms".parse(), Ok(Scan::Every(Duration::from_millis(250))));
+        assert!("0s".parse::<Scan>().is_err());
+        assert!("sometimes".parse::<Scan>().is_err());
+
+        let classes = ScanClasses {
+            fast: Duration::from_millis(250),
+            medium: Duration::from_secs(1),
+            slow: Duration::from_secs(10),
+        };
+        let scheduler = Scheduler::new(
+            [
+                ("PT_101".to_owned(), Scan::Medium),
+                ("FT_201".to_owned(), Scan::Fast),
+                ("Batch_Count".to_owned(), Scan::Slow),
+                ("TT_301".to_owned(), Scan::Every(Duration::from_secs(1))),
+            ],
+            &classes,
+        );
+        let groups: Vec<_> = scheduler
+            .groups
+            .iter()
+            .map(|group| (group.interval.as_millis(), group.tags.len()))
+            .collect();
+        assert_eq!(groups, [(250, 1), (1000, 2), (10_000, 1)]);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::historian::Journal;
+use crate::plc::Plc;
+use crate::service;
+use crate::value::{self, PlcValue};
+use anyhow::{anyhow, bail, Context, Result};
+use chrono::{DateTime, Local};
+use clap::Args;
+use colored::*;
+use croner::Cron;
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Longest sleep between checks for due writes, so the schedule follows
+/// changes of the system clock.
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+#[derive(Args, Debug)]
+pub struct ScheduleArgs {
+    /// Schedule file with one `[[write]]` table per scheduled write.
+    pub file: PathBuf,
+}
+
+/// A TOML file of writes made at given times or intervals:
+///
+/// ```toml
+/// [[write]]
+/// tag = "FT_201_Total"
+/// value = 0
+/// cron = "0 0 * * *"
+///
+/// [[write]]
+/// tag = "Heartbeat"
+/// value = true
+/// every = "30s"
+/// ```
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ScheduleFile {
+    #[serde(default)]
+    write: Vec<WriteEntry>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct WriteEntry {
+    tag: String,
+    /// Number or boolean, converted to the type of the tag.
+    value: serde_json::Value,
+    /// Crontab expression in local time: minute, hour, day of month, month
+    /// and day of week, with optional seconds in front.
+    cron: Option<String>,
+    /// Interval between writes, the first one an interval after start.
+    every: Option<String>,
+}
+
+/// When a scheduled write is due.
+#[derive(Debug)]
+enum When {
+    Cron(Box<Cron>),
+    Every(Duration),
+}
+
+/// A write made by the scheduler.
+#[derive(Debug)]
+pub struct ScheduledWrite {
+    pub tag: String,
+    value: serde_json::Value,
+    when: When,
+}
+
+impl ScheduledWrite {
+    /// When the write is next due after `last`, or after `now` for the
+    /// first time. Writes missed while the controller didn't answer are
+    /// skipped, not made late.
+    fn next_due(
+        &self,
+        last: Option<DateTime<Local>>,
+        now: DateTime<Local>,
+    ) -> Result<DateTime<Local>> {
+        match &self.when {
+            When::Cron(cron) => cron
+                .find_next_occurrence(&now, false)
+                .map_err(|e| anyhow!("no next time for the schedule of {}: {}", self.tag, e)),
+            When::Every(every) => {
+                let every = chrono::Duration::from_std(*every)?;
+                let mut due = last.unwrap_or(now) + every;
+                while due <= now {
+                    due += every;
+                }
+                Ok(due)
+            }
+        }
+    }
+}
+
+/// Load the scheduled writes of a schedule file.
+pub fn load(path: &Path) -> Result<Vec<ScheduledWrite>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read schedule {}", path.display()))?;
+    parse(&text).with_context(|| format!("failed to parse schedule {}", path.display()))
+}
+
+fn parse(text: &str) -> Result<Vec<ScheduledWrite>> {
+    let file: ScheduleFile = toml::from_str(text)?;
+    if file.write.is_empty() {
+        bail!("no [[write]] entries");
+    }
+    file.write
+        .into_iter()
+        .map(|entry| {
+            if !matches!(
+                entry.value,
+                serde_json::Value::Number(_) | serde_json::Value::Bool(_)
+            ) {
+                bail!(
+                    "the value written to {} must be a number or boolean",
+                    entry.tag
+                );
+            }
+            let when = match (entry.cron, entry.every) {
+                (Some(cron), None) => When::Cron(Box::new(
+                    Cron::new(&cron)
+                        .with_seconds_optional()
+                        .parse()
+                        .with_context(|| {
+                            format!("invalid cron expression `{}` for {}", cron, entry.tag)
+                        })?,
+                )),
+                (None, Some(every)) => {
+                    let every = humantime::parse_duration(&every).with_context(|| {
+                        format!("invalid interval `{}` for {}", every, entry.tag)
+                    })?;
+                    if every.is_zero() {
+                        bail!("the interval of {} must be above 0", entry.tag);
+                    }
+                    When::Every(every)
+                }
+                _ => bail!("{} needs either `cron` or `every`", entry.tag),
+            };
+            Ok(ScheduledWrite {
+                tag: entry.tag,
+                value: entry.value,
+                when,
+            })
+        })
+        .collect()
+}
+
+/// Make the scheduled writes until stopped. Each write is converted to the
+/// type of the tag, audited and journaled; one that fails is logged and
+/// made again when next due.
+pub async fn run(client: &mut Plc, writes: &[ScheduledWrite], journal: &Journal) -> Result<()> {
+    let now = Local::now();
+    let mut due = Vec::with_capacity(writes.len());
+    for write in writes {
+        let next = write.next_due(None, now)?;
+        println!(
+            "{}    {}    next at {}",
+            write.tag.bold(),
+            write.value,
+            next.format("%Y-%m-%d %H:%M:%S")
+        );
+        due.push(next);
+    }
+
+    loop {
+        let earliest = due.iter().min().copied().unwrap_or(now);
+        let wait = (earliest - Local::now()).to_std().unwrap_or_default();
+        tokio::select! {
+            _ = tokio::time::sleep(wait.min(MAX_SLEEP)) => {}
+            _ = service::stopped() => break,
+        }
+
+        let now = Local::now();
+        for (write, due) in writes.iter().zip(due.iter_mut()) {
+            if *due > now {
+                continue;
+            }
+            match write_now(client, write).await {
+                Ok(value) => {
+                    println!(
+                        "[{}] {} = {}",
+                        now.format("%Y-%m-%d %H:%M:%S"),
+                        write.tag.bold(),
+                        value.to_string().bold().green()
+                    );
+                    journal.record_operator("write", &write.tag, Some(value.to_string()))?;
+                }
+                Err(e) => tracing::warn!("scheduled write to {} failed: {:#}", write.tag, e),
+            }
+            *due = write.next_due(Some(*due), now)?;
+        }
+    }
+    Ok(())
+}
+
+async fn write_now(client: &mut Plc, write: &ScheduledWrite) -> Result<PlcValue> {
+    let current = value::read_value(client, &write.tag).await?;
+    let value = PlcValue::from_json_like(&current, &write.value)?;
+    value::write_value(client, &write.tag, value.clone()).await?;
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn parse_schedule() {
+        let writes = parse(
+            r#"
+            [[write]]
+            tag = "FT_201_Total"
+            value = 0
+            cron = "0 0 * * *"
+
+            [[write]]
+            tag = "Heartbeat"
+            value = true
+            every = "30s"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(writes.len(), 2);
+
+        let now = Local.with_ymd_and_hms(2024, 3, 1, 15, 20, 0).unwrap();
+        let midnight = Local.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap();
+        assert_eq!(writes[0].next_due(None, now).unwrap(), midnight);
+        assert_eq!(
+            writes[1].next_due(None, now).unwrap(),
+            now + chrono::Duration::seconds(30)
+        );
+        // A write missed by more than an interval is skipped.
+        let late = now + chrono::Duration::seconds(75);
+        assert_eq!(
+            writes[1].next_due(Some(now), late).unwrap(),
+            now + chrono::Duration::seconds(90)
+        );
+
+        let both = "[[write]]\ntag = \"A\"\nvalue = 1\ncron = \"* * * * *\"\nevery = \"1s\"";
+        assert!(parse(both).is_err());
+        assert!(parse("[[write]]\ntag = \"A\"\nvalue = 1").is_err());
+        assert!(parse("[[write]]\ntag = \"A\"\nvalue = 1\ncron = \"61 * * * *\"").is_err());
+        assert!(parse("[[write]]\ntag = \"A\"\nvalue = \"on\"\nevery = \"1s\"").is_err());
+    }
+}
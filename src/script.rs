@@ -0,0 +1,481 @@
+use crate::alarm::Comparison;
+use crate::historian::Journal;
+use crate::plc::{self, Plc};
+use crate::value::{self, PlcValue};
+use anyhow::{anyhow, bail, Context, Result};
+use colored::*;
+use rseip::client::ab_eip::TagType;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// A value in an expression. Booleans count as 1 and 0.
+#[derive(Debug, Clone, PartialEq)]
+enum Operand {
+    Number(f64),
+    /// A variable set with `let`, written `$name`.
+    Var(String),
+    /// A tag, read from the controller each time it is evaluated.
+    Tag(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Operand(Operand),
+    Binary(Operand, Op, Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Cond {
+    left: Expr,
+    comparison: Comparison,
+    right: Expr,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum PrintItem {
+    Text(String),
+    Expr(Operand),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Stmt {
+    Read(Vec<String>),
+    Write(String, Expr),
+    Let(String, Expr),
+    Sleep(Duration),
+    Assert(Cond, Option<String>),
+    Print(Vec<PrintItem>),
+    If(Cond, Vec<Line>, Vec<Line>),
+}
+
+/// A statement with the line it starts on, for error messages.
+#[derive(Debug, Clone, PartialEq)]
+struct Line {
+    number: usize,
+    stmt: Stmt,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Text(String),
+}
+
+/// Split a line into words and double quoted strings, dropping `#` comments.
+fn tokenize(line: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = line.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c == '#' {
+            break;
+        } else if c == '"' {
+            chars.next();
+            let mut text = String::new();
+            loop {
+                match chars.next() {
+                    Some('"') => break,
+                    Some('\\') => text.extend(chars.next()),
+                    Some(c) => text.push(c),
+                    None => bail!("unterminated string"),
+                }
+            }
+            tokens.push(Token::Text(text));
+        } else {
+            let mut word = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_whitespace() || c == '"' {
+                    break;
+                }
+                word.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Word(word));
+        }
+    }
+    Ok(tokens)
+}
+
+fn words(tokens: &[Token]) -> Result<Vec<&str>> {
+    tokens
+        .iter()
+        .map(|token| match token {
+            Token::Word(word) => Ok(word.as_str()),
+            Token::Text(_) => Err(anyhow!("unexpected string")),
+        })
+        .collect()
+}
+
+fn parse_operand(word: &str) -> Result<Operand> {
+    if let Some(name) = word.strip_prefix('$') {
+        if name.is_empty() {
+            bail!("missing variable name after `$`");
+        }
+        return Ok(Operand::Var(name.to_owned()));
+    }
+    match word {
+        "true" => return Ok(Operand::Number(1.0)),
+        "false" => return Ok(Operand::Number(0.0)),
+        _ => {}
+    }
+    if let Ok(number) = word.parse() {
+        return Ok(Operand::Number(number));
+    }
+    if word.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+        bail!("invalid number `{}`", word);
+    }
+    Ok(Operand::Tag(word.to_owned()))
+}
+
+fn parse_expr(text: &str) -> Result<Expr> {
+    match text.split_whitespace().collect::<Vec<_>>()[..] {
+        [operand] => Ok(Expr::Operand(parse_operand(operand)?)),
+        [left, op, right] => {
+            let op = match op {
+                "+" => Op::Add,
+                "-" => Op::Sub,
+                "*" => Op::Mul,
+                "/" => Op::Div,
+                _ => bail!("unknown operator `{}`", op),
+            };
+            Ok(Expr::Binary(
+                parse_operand(left)?,
+                op,
+                parse_operand(right)?,
+            ))
+        }
+        _ => bail!("expected a value or `a + b`, got `{}`", text),
+    }
+}
+
+fn parse_cond(text: &str) -> Result<Cond> {
+    let (idx, len, comparison) =
+        Comparison::find(text).ok_or_else(|| anyhow!("no comparison operator in `{}`", text))?;
+    Ok(Cond {
+        left: parse_expr(&text[..idx])?,
+        comparison,
+        right: parse_expr(&text[idx + len..])?,
+    })
+}
+
+/// What ended a block of statements.
+enum BlockEnd {
+    Eof,
+    Else,
+    End,
+}
+
+/// Parse a whole script, so mistakes are reported before anything is sent
+/// to the controller.
+fn parse(source: &str) -> Result<Vec<Line>> {
+    let mut lines = source
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx + 1, line));
+    let (block, end) = parse_block(&mut lines)?;
+    match end {
+        (BlockEnd::Eof, _) => Ok(block),
+        (_, number) => bail!("line {}: `else` or `end` without `if`", number),
+    }
+}
+
+fn parse_block<'a>(
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<(Vec<Line>, (BlockEnd, usize))> {
+    let mut block = Vec::new();
+    while let Some((number, text)) = lines.next() {
+        let tokens = tokenize(text).with_context(|| format!("line {}", number))?;
+        match &tokens[..] {
+            [] => continue,
+            [Token::Word(word)] if word == "else" => return Ok((block, (BlockEnd::Else, number))),
+            [Token::Word(word)] if word == "end" => return Ok((block, (BlockEnd::End, number))),
+            _ => {}
+        }
+        let stmt = parse_stmt(&tokens, lines).with_context(|| format!("line {}", number))?;
+        block.push(Line { number, stmt });
+    }
+    Ok((block, (BlockEnd::Eof, 0)))
+}
+
+fn parse_stmt<'a>(
+    tokens: &[Token],
+    lines: &mut impl Iterator<Item = (usize, &'a str)>,
+) -> Result<Stmt> {
+    let (keyword, rest) = match tokens.split_first() {
+        Some((Token::Word(keyword), rest)) => (keyword.as_str(), rest),
+        _ => bail!("expected a statement"),
+    };
+    let stmt = match keyword {
+        "read" => {
+            let tags = words(rest)?;
+            if tags.is_empty() {
+                bail!("usage: read TAG...");
+            }
+            Stmt::Read(tags.into_iter().map(str::to_owned).collect())
+        }
+        "write" => match words(rest)?[..] {
+            [tag, ref value @ ..] if !value.is_empty() => {
+                Stmt::Write(tag.to_owned(), parse_expr(&value.join(" "))?)
+            }
+            _ => bail!("usage: write TAG VALUE"),
+        },
+        "let" => match words(rest)?[..] {
+            [name, "=", ref value @ ..] if !value.is_empty() => {
+                let name = name.strip_prefix('$').unwrap_or(name);
+                Stmt::Let(name.to_owned(), parse_expr(&value.join(" "))?)
+            }
+            _ => bail!("usage: let NAME = VALUE"),
+        },
+        "sleep" => match words(rest)?[..] {
+            [duration] => Stmt::Sleep(
+                humantime::parse_duration(duration)
+                    .with_context(|| format!("invalid duration `{}`", duration))?,
+            ),
+            _ => bail!("usage: sleep DURATION, e.g. sleep 500ms"),
+        },
+        "assert" => {
+            let (cond, message) = match rest.split_last() {
+                Some((Token::Text(message), cond)) => (cond, Some(message.clone())),
+                _ => (rest, None),
+            };
+            Stmt::Assert(parse_cond(&words(cond)?.join(" "))?, message)
+        }
+        "print" => Stmt::Print(
+            rest.iter()
+                .map(|token| match token {
+                    Token::Text(text) => Ok(PrintItem::Text(text.clone())),
+                    Token::Word(word) => parse_operand(word).map(PrintItem::Expr),
+                })
+                .collect::<Result<_>>()?,
+        ),
+        "if" => {
+            let cond = parse_cond(&words(rest)?.join(" "))?;
+            let (then, end) = parse_block(lines)?;
+            let otherwise = match end {
+                (BlockEnd::Else, _) => match parse_block(lines)? {
+                    (block, (BlockEnd::End, _)) => block,
+                    (_, (BlockEnd::Else, line)) => bail!("line {}: second `else`", line),
+                    (_, (BlockEnd::Eof, _)) => bail!("`if` without `end`"),
+                },
+                (BlockEnd::End, _) => Vec::new(),
+                (BlockEnd::Eof, _) => bail!("`if` without `end`"),
+            };
+            Stmt::If(cond, then, otherwise)
+        }
+        _ => bail!("unknown statement `{}`", keyword),
+    };
+    Ok(stmt)
+}
+
+/// Runs a parsed script over one session.
+struct Interpreter<'a> {
+    client: &'a mut Plc,
+    journal: &'a Journal,
+    vars: HashMap<String, f64>,
+}
+
+impl Interpreter<'_> {
+    async fn operand(&mut self, operand: &Operand) -> Result<f64> {
+        match operand {
+            Operand::Number(number) => Ok(*number),
+            Operand::Var(name) => self
+                .vars
+                .get(name)
+                .copied()
+                .ok_or_else(|| anyhow!("variable `${}` is not set", name)),
+            Operand::Tag(tag) => Ok(value::read_value(self.client, tag).await?.as_f64()),
+        }
+    }
+
+    async fn expr(&mut self, expr: &Expr) -> Result<f64> {
+        match expr {
+            Expr::Operand(operand) => self.operand(operand).await,
+            Expr::Binary(left, op, right) => {
+                let left = self.operand(left).await?;
+                let right = self.operand(right).await?;
+                Ok(match op {
+                    Op::Add => left + right,
+                    Op::Sub => left - right,
+                    Op::Mul => left * right,
+                    Op::Div => left / right,
+                })
+            }
+        }
+    }
+
+    async fn cond(&mut self, cond: &Cond) -> Result<(bool, f64, f64)> {
+        let left = self.expr(&cond.left).await?;
+        let right = self.expr(&cond.right).await?;
+        Ok((cond.comparison.compare(left, right), left, right))
+    }
+
+    async fn block(&mut self, block: &[Line]) -> Result<()> {
+        for line in block {
+            Box::pin(self.line(line))
+                .await
+                .with_context(|| format!("line {}", line.number))?;
+        }
+        Ok(())
+    }
+
+    async fn line(&mut self, line: &Line) -> Result<()> {
+        match &line.stmt {
+            Stmt::Read(tags) => {
+                for tag in tags {
+                    let value = value::read_value(self.client, tag).await?;
+                    print_value(tag, value);
+                }
+            }
+            Stmt::Write(tag, expr) => {
+                let number = self.expr(expr).await?;
+                let current = value::read_value(self.client, tag).await?;
+                let value =
+                    PlcValue::from_json(current.tag_type(), &json(current.tag_type(), number))?;
+                value::write_value(self.client, tag, value.clone()).await?;
+                self.journal
+                    .record_operator("write", tag, Some(value.to_string()))?;
+                if !plc::dry_run() {
+                    print_value(tag, value);
+                }
+            }
+            Stmt::Let(name, expr) => {
+                let value = self.expr(expr).await?;
+                self.vars.insert(name.clone(), value);
+            }
+            Stmt::Sleep(duration) => tokio::time::sleep(*duration).await,
+            Stmt::Assert(cond, message) => {
+                let (met, left, right) = self.cond(cond).await?;
+                if !met {
+                    let detail = format!("{} {} {} is false", left, cond.comparison, right);
+                    match message {
+                        Some(message) => bail!("assertion failed: {} ({})", message, detail),
+                        None => bail!("assertion failed: {}", detail),
+                    }
+                }
+            }
+            Stmt::Print(items) => {
+                let mut parts = Vec::with_capacity(items.len());
+                for item in items {
+                    match item {
+                        PrintItem::Text(text) => parts.push(text.clone()),
+                        PrintItem::Expr(operand) => {
+                            parts.push(self.operand(operand).await?.to_string())
+                        }
+                    }
+                }
+                println!("{}", parts.join(" "));
+            }
+            Stmt::If(cond, then, otherwise) => {
+                if self.cond(cond).await?.0 {
+                    self.block(then).await?;
+                } else {
+                    self.block(otherwise).await?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// JSON for a number written to a tag of the given type.
+pub(crate) fn json(tag_type: TagType, number: f64) -> serde_json::Value {
+    match tag_type {
+        TagType::Bool => (number != 0.0).into(),
+        TagType::Real | TagType::Lreal => number.into(),
+        _ if number.fract() == 0.0 && number.abs() < 2f64.powi(63) => (number as i64).into(),
+        _ => number.into(),
+    }
+}
+
+fn print_value(tag: &str, value: PlcValue) {
+    println!(
+        "    {}    {}    {}",
+        tag.bold(),
+        value.type_name(),
+        value.to_string().bold().green()
+    );
+}
+
+/// Run a script file over the session, stopping at the first failed
+/// statement or assertion.
+pub async fn run(client: &mut Plc, path: &Path, journal: &Journal) -> Result<()> {
+    let source = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read script {}", path.display()))?;
+    let script = parse(&source).with_context(|| format!("in {}", path.display()))?;
+    let mut interpreter = Interpreter {
+        client,
+        journal,
+        vars: HashMap::new(),
+    };
+    interpreter
+        .block(&script)
+        .await
+        .with_context(|| format!("in {}", path.display()))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parse_script() {
+        let script = parse(concat!(
+            "# fill the tank\n",
+            "let target = $level + 10\n",
+            "if Tank_Level >= 90\n",
+            "    write Pump_Run false\n",
+            "else\n",
+            "    sleep 500ms\n",
+            "end\n",
+            "assert Tank_Level < 95 \"tank overflowing\"\n",
+            "print \"level\" $level\n",
+        ))
+        .unwrap();
+        assert_eq!(script.len(), 4);
+        assert_eq!(
+            script[0].stmt,
+            Stmt::Let(
+                "target".to_owned(),
+                Expr::Binary(
+                    Operand::Var("level".to_owned()),
+                    Op::Add,
+                    Operand::Number(10.0)
+                )
+            )
+        );
+        match &script[1].stmt {
+            Stmt::If(cond, then, otherwise) => {
+                assert_eq!(cond.comparison, Comparison::Ge);
+                assert_eq!(then[0].number, 4);
+                assert_eq!(otherwise[0].stmt, Stmt::Sleep(Duration::from_millis(500)));
+            }
+            stmt => panic!("expected if, got {:?}", stmt),
+        }
+        assert_eq!(script[2].number, 8);
+        assert!(matches!(&script[2].stmt, Stmt::Assert(_, Some(m)) if m == "tank overflowing"));
+
+        assert!(parse("if A > 1\nread A\n").is_err());
+        assert!(parse("end\n").is_err());
+        assert!(parse("write A\n").is_err());
+        assert!(parse("frobnicate A\n").is_err());
+        assert!(parse("print \"open\n").is_err());
+    }
+
+    #[test]
+    fn write_json() {
+        assert_eq!(json(TagType::Bool, 1.0), serde_json::json!(true));
+        assert_eq!(json(TagType::Dint, 42.0), serde_json::json!(42));
+        assert_eq!(json(TagType::Real, 42.0), serde_json::json!(42.0));
+        assert_eq!(json(TagType::Dint, 0.5), serde_json::json!(0.5));
+    }
+}
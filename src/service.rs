@@ -0,0 +1,178 @@
+use anyhow::{Context, Result};
+use clap::Args;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use tokio::sync::Notify;
+
+#[derive(Args)]
+pub struct ServiceArgs {
+    /// Run monitor, exporter, serve, dnp3, snmp, io, schedule or bridge-write
+    /// as a service: write the PID file and report readiness to systemd when
+    /// it asks for it.
+    #[arg(long, global = true)]
+    pub daemon: bool,
+    /// PID file written while the daemon runs and removed when it stops.
+    #[arg(long, global = true, requires = "daemon")]
+    pub pid_file: Option<PathBuf>,
+}
+
+/// Set once a shutdown was asked for.
+static STOPPING: AtomicBool = AtomicBool::new(false);
+static STOP: Notify = Notify::const_new();
+
+/// Catch SIGINT and SIGTERM so a polling mode stops between its cycles and
+/// closes its sessions, instead of dying in the middle of a write. A second
+/// signal exits right away.
+pub fn handle_signals() -> Result<()> {
+    #[cfg(unix)]
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .context("failed to catch SIGTERM")?;
+    tokio::spawn(async move {
+        for signals in 0.. {
+            #[cfg(unix)]
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {}
+                _ = terminate.recv() => {}
+            }
+            #[cfg(not(unix))]
+            tokio::signal::ctrl_c().await.ok();
+            if signals > 0 {
+                std::process::exit(130);
+            }
+            tracing::info!("stopping after the current cycle, interrupt again to exit right away");
+            notify("STOPPING=1");
+            STOPPING.store(true, Ordering::SeqCst);
+            STOP.notify_waiters();
+        }
+    });
+    Ok(())
+}
+
+/// Wait until a shutdown is asked for.
+pub async fn stopped() {
+    // Registered before checking the flag, so a signal in between isn't missed.
+    let notified = STOP.notified();
+    if STOPPING.load(Ordering::SeqCst) {
+        return;
+    }
+    notified.await;
+}
+
+/// A running daemon, removing its PID file when dropped.
+pub struct Service {
+    pid_file: Option<PathBuf>,
+}
+
+impl Service {
+    /// Write the PID file of a daemon. Does nothing without `--daemon`.
+    pub fn start(args: &ServiceArgs) -> Result<Self> {
+        let pid_file = match &args.pid_file {
+            Some(path) if args.daemon => {
+                write_pid_file(path)?;
+                Some(path.clone())
+            }
+            _ => None,
+        };
+        Ok(Self { pid_file })
+    }
+
+    /// Tell systemd the service is up, once its controllers are connected.
+    pub fn ready(&self) {
+        notify("READY=1");
+    }
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        if let Some(path) = &self.pid_file {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn write_pid_file(path: &Path) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    if let Ok(text) = std::fs::read_to_string(path) {
+        let running = text
+            .trim()
+            .parse::<u32>()
+            .map(|pid| Path::new(&format!("/proc/{}", pid)).exists())
+            .unwrap_or(false);
+        if running {
+            anyhow::bail!(
+                "{} names process {}, which is still running",
+                path.display(),
+                text.trim()
+            );
+        }
+    }
+    std::fs::write(path, format!("{}\n", std::process::id()))
+        .with_context(|| format!("failed to write PID file {}", path.display()))
+}
+
+/// Send a state to the systemd notification socket, if the service manager
+/// gave one. Services of any other kind don't set `NOTIFY_SOCKET`.
+fn notify(state: &str) {
+    #[cfg(unix)]
+    if let Some(socket) = std::env::var_os("NOTIFY_SOCKET") {
+        if let Err(e) = send_notify(&socket, state) {
+            tracing::warn!("failed to notify systemd of {}: {}", state, e);
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = state;
+}
+
+#[cfg(unix)]
+fn send_notify(socket: &std::ffi::OsStr, state: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let sender = UnixDatagram::unbound()?;
+    // Names starting with `@` are in the abstract namespace.
+    #[cfg(target_os = "linux")]
+    if let Some(name) = socket.as_bytes().strip_prefix(b"@") {
+        use std::os::linux::net::SocketAddrExt;
+        let address = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+        sender.send_to_addr(state.as_bytes(), &address)?;
+        return Ok(());
+    }
+    sender.send_to(state.as_bytes(), socket)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn notify_socket() {
+        use std::os::unix::net::UnixDatagram;
+
+        let path = std::env::temp_dir().join(format!("cobalt-notify-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let receiver = UnixDatagram::bind(&path).unwrap();
+        send_notify(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 16];
+        let len = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pid_file() {
+        let path = std::env::temp_dir().join(format!("cobalt-{}.pid", std::process::id()));
+        let args = ServiceArgs {
+            daemon: true,
+            pid_file: Some(path.clone()),
+        };
+        let service = Service::start(&args).unwrap();
+        let pid = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+        #[cfg(target_os = "linux")]
+        assert!(Service::start(&args).is_err());
+        drop(service);
+        assert!(!path.exists());
+    }
+}
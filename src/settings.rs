@@ -0,0 +1,422 @@
+use crate::backend::Protocol;
+use crate::plc::{Family, ReadOnly};
+use crate::tags::{glob_match, matching_tag};
+use crate::value::PlcValue;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Settings file read from the working directory when there is one.
+pub const DEFAULT_SETTINGS: &str = "cobalt.toml";
+
+/// Site settings applying to every command.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Addresses of controllers whose writes are confirmed first, as if
+    /// `--confirm` was given.
+    #[serde(default)]
+    pub protected: Vec<String>,
+    /// Addresses of controllers nothing is written to, as if `--read-only`
+    /// was given.
+    #[serde(default)]
+    pub read_only: Vec<String>,
+    /// Tags nothing is written to on any controller, as patterns like
+    /// `SIS_*`, along with their members and bits.
+    #[serde(default)]
+    pub read_only_tags: Vec<String>,
+    /// Names of the bits of integer tags like status words, by tag and bit
+    /// number, shown by `read --decode-bits`.
+    #[serde(default, deserialize_with = "deserialize_numbered")]
+    pub bits: BTreeMap<String, BTreeMap<u32, String>>,
+    /// Names of the values of integer tags holding a state, like
+    /// `0 = "Stopped"`, by tag and value, shown next to the value by `read`
+    /// and `monitor`.
+    #[serde(default, deserialize_with = "deserialize_numbered")]
+    pub states: BTreeMap<String, BTreeMap<i64, String>>,
+    /// Tags the clients of `serve` and `exporter` can see, read and write.
+    #[serde(default)]
+    pub access: Access,
+    /// Controllers named for `--plc`, by name.
+    #[serde(default)]
+    pub plc: BTreeMap<String, Profile>,
+}
+
+/// A controller named in the settings, with its address and the options it
+/// is reached with, for `--plc` to stand for them. Options left out keep
+/// those of the command line.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub address: String,
+    pub protocol: Option<Protocol>,
+    pub family: Option<Family>,
+    /// Slot of the controller in the chassis of the Ethernet module at the
+    /// address, for a route of `1,<slot>`, or of the S7 CPU.
+    pub slot: Option<u8>,
+    /// Route to the controller, like `--route`.
+    pub route: Option<String>,
+    pub s7_rack: Option<u8>,
+    pub modbus_unit: Option<u8>,
+    #[serde(default)]
+    pub connected: bool,
+    pub rpi: Option<u32>,
+    #[serde(default)]
+    pub tag_cache: bool,
+    /// Refuse every write to the controller, like `--read-only`.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Ask before writing to the controller, like `--confirm`.
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+/// Tags exposed to the clients of the servers, as patterns like `FT_*`
+/// that also take the members and bits of the tags they match. Without
+/// lists, clients read and write every tag.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Access {
+    /// Tags clients can read, every one by default. Writable tags are
+    /// readable too.
+    pub readable: Option<Vec<String>>,
+    /// Tags clients can write, every readable one by default.
+    pub writable: Option<Vec<String>>,
+    /// Tags clients can't read, write or find in listings, even when they
+    /// match the other lists.
+    #[serde(default)]
+    pub hidden: Vec<String>,
+}
+
+impl Access {
+    pub fn is_hidden(&self, tag: &str) -> bool {
+        matching_tag(&self.hidden, tag).is_some()
+    }
+
+    pub fn can_read(&self, tag: &str) -> bool {
+        let listed = |patterns: &Option<Vec<String>>| {
+            patterns
+                .as_deref()
+                .is_some_and(|patterns| matching_tag(patterns, tag).is_some())
+        };
+        !self.is_hidden(tag)
+            && (self.readable.is_none() || listed(&self.readable) || listed(&self.writable))
+    }
+
+    /// Refuse to serve tags from a file the clients can't read.
+    pub fn check_readable<'a>(&self, tags: impl IntoIterator<Item = &'a str>) -> Result<()> {
+        for tag in tags {
+            if !self.can_read(tag) {
+                bail!(
+                    "{} is not readable by clients, see [access] in the settings",
+                    tag
+                );
+            }
+        }
+        Ok(())
+    }
+
+    pub fn can_write(&self, tag: &str) -> bool {
+        match &self.writable {
+            Some(patterns) => !self.is_hidden(tag) && matching_tag(patterns, tag).is_some(),
+            None => self.can_read(tag),
+        }
+    }
+}
+
+/// Names keyed by tag and number, TOML keys being strings even when they
+/// are numbers.
+fn deserialize_numbered<'de, D, N>(
+    deserializer: D,
+) -> Result<BTreeMap<String, BTreeMap<N, String>>, D::Error>
+where
+    D: Deserializer<'de>,
+    N: FromStr + Ord,
+{
+    let tags = BTreeMap::<String, BTreeMap<String, String>>::deserialize(deserializer)?;
+    tags.into_iter()
+        .map(|(tag, names)| {
+            let names = names
+                .into_iter()
+                .map(|(number, name)| match number.parse::<N>() {
+                    Ok(number) => Ok((number, name)),
+                    Err(_) => Err(serde::de::Error::custom(format!(
+                        "{} of {} is not a number",
+                        number, tag
+                    ))),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok((tag, names))
+        })
+        .collect()
+}
+
+impl Settings {
+    /// Load the settings file given, or the default one if it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_SETTINGS), false),
+        };
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if !required && e.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Self::default())
+            }
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("failed to read settings {}", path.display()))
+            }
+        };
+        toml::from_str(&text)
+            .with_context(|| format!("failed to parse settings {}", path.display()))
+    }
+
+    /// The read-only controllers and tags, for the connection options.
+    pub fn read_only(&self) -> ReadOnly {
+        ReadOnly {
+            controllers: self.read_only.clone(),
+            tags: self.read_only_tags.clone(),
+        }
+    }
+
+    /// The controller `--plc` names.
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        match self.plc.get(name) {
+            Some(profile) => Ok(profile),
+            None if self.plc.is_empty() => {
+                bail!(
+                    "no controller is named `{}`, add a [plc.{}] table to the settings",
+                    name,
+                    name
+                )
+            }
+            None => bail!(
+                "no controller is named `{}` in the settings, only {}",
+                name,
+                self.plc
+                    .keys()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        }
+    }
+
+    pub fn is_protected(&self, address: &str) -> bool {
+        self.protected.iter().any(|protected| protected == address)
+    }
+
+    /// Names of the bits of a tag, the tables of the settings being
+    /// matched like `list --filter` patterns, regardless of case.
+    pub fn bit_names(&self, tag: &str) -> Option<&BTreeMap<u32, String>> {
+        named(&self.bits, tag)
+    }
+
+    /// The bits set in a value of a tag, by their names, or as `bit 7` for
+    /// the ones without a name.
+    pub fn decode_bits(&self, tag: &str, value: &PlcValue) -> Result<Vec<String>> {
+        let Some(set) = value.set_bits() else {
+            bail!(
+                "{} is a {}, which has no bits to decode",
+                tag,
+                value.type_name()
+            );
+        };
+        let names = self.bit_names(tag);
+        Ok(set
+            .into_iter()
+            .map(|bit| match names.and_then(|names| names.get(&bit)) {
+                Some(name) => name.clone(),
+                None => format!("bit {}", bit),
+            })
+            .collect())
+    }
+
+    /// Name of the state a tag is in, when its value has one.
+    pub fn state_name(&self, tag: &str, value: &PlcValue) -> Option<&str> {
+        let states = named(&self.states, tag)?;
+        states.get(&value.as_i64()?).map(String::as_str)
+    }
+}
+
+/// Names of a tag, those of a table named after it exactly coming before
+/// those of a pattern matching it.
+fn named<'a, N>(
+    tables: &'a BTreeMap<String, BTreeMap<N, String>>,
+    tag: &str,
+) -> Option<&'a BTreeMap<N, String>> {
+    tables
+        .iter()
+        .find(|(name, _)| name.eq_ignore_ascii_case(tag))
+        .or_else(|| tables.iter().find(|(pattern, _)| glob_match(pattern, tag)))
+        .map(|(_, names)| names)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn protected_controllers() {
+        let settings: Settings = toml::from_str(r#"protected = ["10.1.1.5"]"#).unwrap();
+        assert!(settings.is_protected("10.1.1.5"));
+        assert!(!settings.is_protected("10.1.1.6"));
+        assert!(toml::from_str::<Settings>("protect = []").is_err());
+        assert!(Settings::load(None).is_ok());
+    }
+
+    #[test]
+    fn profiles() {
+        use crate::plc::ConnectionArgs;
+        use clap::{CommandFactory, FromArgMatches, Parser};
+
+        #[derive(Parser)]
+        struct Cli {
+            #[command(flatten)]
+            connection: ConnectionArgs,
+        }
+        let connection = |args: &[&str]| {
+            let matches = Cli::command()
+                .get_matches_from(std::iter::once("cobalt").chain(args.iter().copied()));
+            let mut connection = Cli::from_arg_matches(&matches).unwrap().connection;
+            connection.note_given(&matches);
+            connection
+        };
+        let settings: Settings = toml::from_str(
+            r#"
+            [plc.compressor-station]
+            address = "10.0.0.5"
+            slot = 2
+            connected = true
+            rpi = 500
+
+            [plc.line-3]
+            address = "10.0.3.10"
+            protocol = "s7"
+            slot = 2
+
+            [plc.broken]
+            address = "10.0.0.6"
+            route = "1,2"
+            slot = 2
+            "#,
+        )
+        .unwrap();
+
+        let profile = settings.profile("compressor-station").unwrap();
+        assert_eq!(profile.address, "10.0.0.5");
+        let mut args = connection(&[]);
+        args.apply("compressor-station", profile).unwrap();
+        assert_eq!(args.route, connection(&["--route", "1,2"]).route);
+        assert!(args.connected);
+        assert_eq!(args.rpi, 500);
+        // Options of the command line stay.
+        let mut args = connection(&["--route", "1,5", "--rpi", "100"]);
+        args.apply("compressor-station", profile).unwrap();
+        assert_eq!(args.route, connection(&["--route", "1,5"]).route);
+        assert_eq!(args.rpi, 100);
+        // Even when they give the default.
+        let mut args = connection(&["--route", "1,0", "--rpi", "2000"]);
+        args.apply("compressor-station", profile).unwrap();
+        assert_eq!(args.route, connection(&[]).route);
+        assert_eq!(args.rpi, 2000);
+
+        let mut args = connection(&[]);
+        args.apply("line-3", settings.profile("line-3").unwrap())
+            .unwrap();
+        assert_eq!(args.protocol, Protocol::S7);
+        assert_eq!(args.s7_slot, 2);
+        assert_eq!(args.route, connection(&[]).route);
+
+        let mut args = connection(&[]);
+        assert!(args
+            .apply("broken", settings.profile("broken").unwrap())
+            .is_err());
+        let error = settings.profile("compressor").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "no controller is named `compressor` in the settings, only broken, compressor-station, line-3"
+        );
+        assert!(toml::from_str::<Settings>("[plc.x]\naddress = \"a\"\nport = 1").is_err());
+    }
+
+    #[test]
+    fn bit_names() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [bits.StatusWord]
+            0 = "Running"
+            1 = "Faulted"
+            5 = "Remote"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            settings
+                .decode_bits("statusword", &PlcValue::Dint(0b10_0011 | 1 << 31))
+                .unwrap(),
+            ["Running", "Faulted", "Remote", "bit 31"]
+        );
+        assert_eq!(
+            settings.decode_bits("Other", &PlcValue::Int(4)).unwrap(),
+            ["bit 2"]
+        );
+        assert!(settings
+            .decode_bits("StatusWord", &PlcValue::Real(1.0))
+            .is_err());
+        assert!(toml::from_str::<Settings>("[bits.StatusWord]\nRun = \"Running\"").is_err());
+    }
+
+    #[test]
+    fn access() {
+        let open = Access::default();
+        assert!(open.can_read("SIS_Trip") && open.can_write("SIS_Trip"));
+
+        let settings: Settings = toml::from_str(
+            r#"
+            [access]
+            readable = ["FT_*", "PT_*"]
+            writable = ["SP_*"]
+            hidden = ["PT_9*"]
+            "#,
+        )
+        .unwrap();
+        let access = &settings.access;
+        assert!(access.can_read("FT_101.Total") && !access.can_write("FT_101"));
+        assert!(access.can_read("SP_101") && access.can_write("sp_101"));
+        assert!(access.is_hidden("PT_901") && !access.can_read("PT_901"));
+        assert!(!access.can_read("Recipe") && !access.can_write("Recipe"));
+
+        let settings: Settings = toml::from_str("[access]\nhidden = [\"SIS_*\"]").unwrap();
+        assert!(settings.access.can_write("FT_101"));
+        assert!(!settings.access.can_write("SIS_Trip.2"));
+        assert!(toml::from_str::<Settings>("[access]\nread = []").is_err());
+    }
+
+    #[test]
+    fn state_names() {
+        let settings: Settings = toml::from_str(
+            r#"
+            [states."Motor*_State"]
+            0 = "Stopped"
+            1 = "Starting"
+            2 = "Running"
+            -1 = "Faulted"
+
+            [states.Motor9_State]
+            2 = "Jogging"
+            "#,
+        )
+        .unwrap();
+        let state = |tag, value| settings.state_name(tag, &value);
+        assert_eq!(state("Motor1_State", PlcValue::Dint(2)), Some("Running"));
+        assert_eq!(state("motor2_state", PlcValue::Sint(-1)), Some("Faulted"));
+        assert_eq!(state("Motor9_State", PlcValue::Int(2)), Some("Jogging"));
+        assert_eq!(state("Motor1_State", PlcValue::Dint(7)), None);
+        assert_eq!(state("Motor1_State", PlcValue::Real(2.0)), None);
+        assert_eq!(state("Pump_State", PlcValue::Dint(2)), None);
+    }
+}
@@ -0,0 +1,198 @@
+use crate::historian::Journal;
+use crate::plc::{self, Plc};
+use crate::tags::glob_match;
+use crate::value::{self, PlcValue};
+use anyhow::{bail, Result};
+use colored::*;
+use futures_util::TryStreamExt;
+use rseip::client::ab_eip::*;
+use rustyline::completion::Completer;
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::DefaultHistory;
+use rustyline::validate::Validator;
+use rustyline::{Context, Editor, Helper};
+use std::path::PathBuf;
+
+const COMMANDS: [&str; 6] = ["read", "write", "list", "help", "exit", "quit"];
+
+const HELP: &str = concat!(
+    "    read TAG...        Read tags\n",
+    "    write TAG VALUE    Write a value, converted to the type of the tag\n",
+    "    list [PATTERN]     List controller tags, optionally matching a pattern like prog*\n",
+    "    help               Show this help\n",
+    "    exit               Close the session",
+);
+
+/// Completes command names and controller tag names.
+struct ShellHelper {
+    /// Tag names from the last listing.
+    tags: Vec<String>,
+}
+
+impl Completer for ShellHelper {
+    type Candidate = String;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<String>)> {
+        let start = line[..pos].rfind(' ').map(|idx| idx + 1).unwrap_or(0);
+        let word = line[start..pos].to_lowercase();
+        let candidates: Vec<String> = if start == 0 {
+            COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(&word))
+                .map(|command| command.to_string())
+                .collect()
+        } else {
+            self.tags
+                .iter()
+                .filter(|tag| tag.to_lowercase().starts_with(&word))
+                .cloned()
+                .collect()
+        };
+        Ok((start, candidates))
+    }
+}
+
+impl Hinter for ShellHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ShellHelper {}
+
+impl Validator for ShellHelper {}
+
+impl Helper for ShellHelper {}
+
+enum Flow {
+    Continue,
+    Exit,
+}
+
+/// Run an interactive prompt over a single session.
+pub async fn run(client: &mut Plc, journal: &Journal) -> Result<()> {
+    let mut editor: Editor<ShellHelper, DefaultHistory> = Editor::new()?;
+    let history = history_path();
+    let _ = editor.load_history(&history);
+
+    let tags = match list_tags(client).await {
+        Ok(tags) => tags.into_iter().map(|(name, _)| name).collect(),
+        Err(e) => {
+            eprintln!("Tag name completion unavailable: {}", e);
+            let _ = client.close().await;
+            Vec::new()
+        }
+    };
+    editor.set_helper(Some(ShellHelper { tags }));
+    println!("Type {} for the list of commands.", "help".bold());
+
+    loop {
+        let line = match tokio::task::block_in_place(|| editor.readline("cobalt> ")) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(line);
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        // The session may have timed out while waiting at the prompt.
+        client.keep_alive().await;
+        match execute(client, journal, &mut editor, &words).await {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Exit) => break,
+            Err(e) => {
+                eprintln!("{} {:#}", "Error:".bold().red(), e);
+                // Start over with a new session on the next command.
+                let _ = client.close().await;
+            }
+        }
+    }
+
+    let _ = editor.save_history(&history);
+    client.close().await?;
+    Ok(())
+}
+
+async fn execute(
+    client: &mut Plc,
+    journal: &Journal,
+    editor: &mut Editor<ShellHelper, DefaultHistory>,
+    words: &[&str],
+) -> Result<Flow> {
+    match words {
+        ["read", tags @ ..] if !tags.is_empty() => {
+            for tag in tags {
+                let value = value::read_value(client, tag).await?;
+                print_value(tag, value);
+            }
+        }
+        ["write", tag, new_value] => {
+            let current = value::read_value(client, tag).await?;
+            let value = PlcValue::parse_like(&current, new_value)?;
+            value::write_value(client, tag, value.clone()).await?;
+            journal.record_operator("write", tag, Some(value.to_string()))?;
+            if !plc::dry_run() {
+                print_value(tag, value);
+            }
+        }
+        ["list"] | ["list", _] => {
+            let tags = list_tags(client).await?;
+            for (name, symbol_type) in &tags {
+                if words.len() == 1 || glob_match(words[1], name) {
+                    println!("    {}    {}", name.bold(), symbol_type);
+                }
+            }
+            if let Some(helper) = editor.helper_mut() {
+                helper.tags = tags.into_iter().map(|(name, _)| name).collect();
+            }
+        }
+        ["help"] => println!("{}", HELP),
+        ["exit"] | ["quit"] => return Ok(Flow::Exit),
+        [command, ..] if COMMANDS.contains(command) => {
+            bail!("wrong arguments, usage:\n{}", HELP)
+        }
+        [command, ..] => bail!("unknown command `{}`, type `help` for the list", command),
+        [] => {}
+    }
+    Ok(Flow::Continue)
+}
+
+fn print_value(tag: &str, value: PlcValue) {
+    println!(
+        "    {}    {}    {}",
+        tag.bold(),
+        value.type_name(),
+        value.to_string().bold().green()
+    );
+}
+
+/// Controller tag names with their type.
+async fn list_tags(client: &mut Plc) -> Result<Vec<(String, String)>> {
+    let tags = client
+        .eip()?
+        .list_tag()
+        .call()
+        .map_ok(|item| (item.name.into_owned(), format!("{:?}", item.symbol_type)))
+        .try_collect()
+        .await?;
+    Ok(tags)
+}
+
+/// History file in the home directory, or the working directory.
+fn history_path() -> PathBuf {
+    let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+    match home {
+        Some(home) => PathBuf::from(home).join(".cobalt_history"),
+        None => PathBuf::from(".cobalt_history"),
+    }
+}
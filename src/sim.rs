@@ -0,0 +1,1742 @@
+use crate::describe::ExternalAccess;
+use crate::plc::Family;
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Options of the `sim` command.
+#[derive(Args)]
+pub struct SimArgs {
+    /// Tags file with one `[[tag]]` table per simulated tag.
+    #[arg(long)]
+    pub tags: PathBuf,
+    /// Address and port the simulator listens on.
+    #[arg(long, default_value = "127.0.0.1:44818")]
+    pub listen: String,
+}
+
+/// A TOML file describing the simulated controller and its tags.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimFile {
+    #[serde(default)]
+    pub identity: SimIdentity,
+    #[serde(default, rename = "tag")]
+    pub tags: Vec<SimTag>,
+}
+
+impl SimFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read simulated tags {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("failed to parse simulated tags {}", path.display()))
+    }
+}
+
+/// What the simulator answers to identity requests.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields, default)]
+pub struct SimIdentity {
+    pub product_name: String,
+    pub serial_number: u32,
+    pub revision: (u8, u8),
+    /// `micro800` only takes requests over connections opened straight to
+    /// the controller, one service at a time.
+    pub family: Family,
+}
+
+impl Default for SimIdentity {
+    fn default() -> Self {
+        Self {
+            product_name: "cobalt simulator".to_owned(),
+            serial_number: 0x00c0ba17,
+            revision: (33, 11),
+            family: Family::CompactLogix,
+        }
+    }
+}
+
+/// A simulated tag, e.g. `type = "REAL"`, `type = "DINT[10]"` or
+/// `type = "STRING20"`. Tags named like `Program:Main.Flow` are tags of a
+/// program.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SimTag {
+    pub name: String,
+    pub r#type: String,
+    /// Initial value, a number or boolean, one per element of arrays, or
+    /// the text of strings.
+    #[serde(default)]
+    pub value: Option<toml::Value>,
+    /// Generator changing the value over time.
+    #[serde(default)]
+    pub generate: Option<Generator>,
+    /// External access of the tag: `read-write`, `read-only` refusing
+    /// writes, or `none` leaving it out of the tag list.
+    #[serde(default)]
+    pub external_access: ExternalAccess,
+    /// Refuse writes to the tag, like a constant.
+    #[serde(default)]
+    pub constant: bool,
+}
+
+/// Value generators of simulated tags.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Generator {
+    /// Rises from `from` to `to` over the period, then starts over.
+    Ramp {
+        from: f64,
+        to: f64,
+        #[serde(deserialize_with = "crate::tags::deserialize_duration")]
+        period: Duration,
+    },
+    /// Swings between `min` and `max` once per period.
+    Sine {
+        min: f64,
+        max: f64,
+        #[serde(deserialize_with = "crate::tags::deserialize_duration")]
+        period: Duration,
+    },
+    /// Moves up or down by up to `step` every second, within `min` and `max`.
+    RandomWalk {
+        start: f64,
+        step: f64,
+        min: f64,
+        max: f64,
+    },
+}
+
+/// Atomic types of simulated tags: name, CIP type code and size in bytes.
+const TYPES: [(&str, u16, usize); 12] = [
+    ("BOOL", 0xC1, 1),
+    ("SINT", 0xC2, 1),
+    ("INT", 0xC3, 2),
+    ("DINT", 0xC4, 4),
+    ("LINT", 0xC5, 8),
+    ("USINT", 0xC6, 1),
+    ("UINT", 0xC7, 2),
+    ("UDINT", 0xC8, 4),
+    ("ULINT", 0xC9, 8),
+    ("REAL", 0xCA, 4),
+    ("LREAL", 0xCB, 8),
+    ("DWORD", 0xD3, 4),
+];
+
+/// Type code of structures in tag services, followed by the structure
+/// handle.
+const TYPE_STRUCTURE: u16 = 0x02A0;
+/// Symbol type Logix lists programs with.
+const SYMBOL_PROGRAM: u16 = 0x1068;
+/// Template instance and structure handle of the predefined STRING.
+const STRING_HANDLE: u16 = 0x0FCE;
+/// Characters of the predefined STRING.
+const STRING_CAPACITY: usize = 82;
+/// Most characters of the other simulated string types.
+const MAX_STRING_CAPACITY: usize = 4000;
+/// Template instance of the first string type other than STRING.
+const FIRST_TEMPLATE: u16 = 0x0100;
+/// Bytes of a template definition Logix counts in its object size but
+/// leaves out of Read Template replies.
+const TEMPLATE_HEADER: usize = 23;
+
+/// CIP general status codes answered by the simulator.
+const STATUS_SUCCESS: u8 = 0x00;
+const STATUS_CONNECTION_FAILURE: u8 = 0x01;
+const STATUS_PATH_SEGMENT: u8 = 0x04;
+const STATUS_PATH_UNKNOWN: u8 = 0x05;
+const STATUS_PARTIAL: u8 = 0x06;
+const STATUS_UNSUPPORTED: u8 = 0x08;
+const STATUS_ATTRIBUTE_LIST: u8 = 0x0A;
+const STATUS_PRIVILEGE: u8 = 0x0F;
+const STATUS_NOT_ENOUGH_DATA: u8 = 0x13;
+const STATUS_ATTRIBUTE_UNSUPPORTED: u8 = 0x14;
+const STATUS_TOO_MUCH_DATA: u8 = 0x15;
+const STATUS_EMBEDDED: u8 = 0x1E;
+const STATUS_GENERAL: u8 = 0xFF;
+/// Extended statuses of connection failures (0x01).
+const EXT_INVALID_PORT: u16 = 0x0311;
+const EXT_INVALID_SEGMENT: u16 = 0x0315;
+/// Extended statuses of Logix tag services under the general status 0xFF.
+const EXT_OUT_OF_RANGE: u16 = 0x2105;
+const EXT_TYPE_MISMATCH: u16 = 0x2107;
+
+/// Encapsulation commands and statuses.
+const CMD_NOP: u16 = 0x0000;
+const CMD_LIST_SERVICES: u16 = 0x0004;
+const CMD_REGISTER_SESSION: u16 = 0x0065;
+const CMD_UNREGISTER_SESSION: u16 = 0x0066;
+const CMD_SEND_RR_DATA: u16 = 0x006F;
+const CMD_SEND_UNIT_DATA: u16 = 0x0070;
+const ENCAP_INVALID_COMMAND: u32 = 0x0001;
+const ENCAP_INVALID_SESSION: u32 = 0x0064;
+const ENCAP_INVALID_LENGTH: u32 = 0x0065;
+
+/// Largest encapsulated packet accepted, well above what clients send.
+const MAX_PACKET: usize = 65_511;
+
+/// Symbol instances listed per Get Instance Attribute List reply, about as
+/// many as fit in an unconnected message.
+const LIST_REPLY_BYTES: usize = 480;
+
+/// Run the simulator until stopped.
+pub async fn run(args: &SimArgs) -> Result<()> {
+    let file = SimFile::load(&args.tags)?;
+    let simulator = Simulator::new(&file)?;
+    let listener = TcpListener::bind(&args.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.listen))?;
+    println!(
+        "Simulating {} with {} tags on {}.",
+        file.identity.product_name,
+        file.tags.len(),
+        listener.local_addr()?
+    );
+    simulator.serve(listener).await
+}
+
+/// A simulated tag and its current value.
+#[derive(Debug)]
+struct Tag {
+    name: String,
+    instance: u16,
+    type_code: u16,
+    size: usize,
+    /// Elements of arrays, 0 for single values.
+    len: usize,
+    data: Vec<u8>,
+    generator: Option<(Generator, GeneratorState)>,
+    external_access: ExternalAccess,
+    constant: bool,
+    /// Type of string tags, which are structures.
+    string: Option<StringTemplate>,
+}
+
+/// A string type: STRING holding 82 characters, or STRINGnn types like
+/// STRING20 holding nn, each with a template of a DINT length and a SINT
+/// array of characters.
+#[derive(Debug, Clone, PartialEq)]
+struct StringTemplate {
+    name: String,
+    instance: u16,
+    /// Structure handle of reads and writes, made up for the types other
+    /// than STRING.
+    handle: u16,
+    capacity: usize,
+}
+
+impl StringTemplate {
+    /// The string type named like `STRING` or `STRING20`, from those found
+    /// so far or as a new template.
+    fn find(name: &str, templates: &mut Vec<StringTemplate>) -> Result<Option<Self>> {
+        let name = name.to_uppercase();
+        let Some(length) = name.strip_prefix("STRING") else {
+            return Ok(None);
+        };
+        if let Some(template) = templates.iter().find(|template| template.name == name) {
+            return Ok(Some(template.clone()));
+        }
+        let template = if length.is_empty() {
+            StringTemplate {
+                name,
+                instance: STRING_HANDLE,
+                handle: STRING_HANDLE,
+                capacity: STRING_CAPACITY,
+            }
+        } else {
+            let capacity = length
+                .parse()
+                .ok()
+                .filter(|capacity| (1..=MAX_STRING_CAPACITY).contains(capacity))
+                .with_context(|| {
+                    format!(
+                        "string types hold 1 to {} characters, not `{}`",
+                        MAX_STRING_CAPACITY, length
+                    )
+                })?;
+            let others = templates
+                .iter()
+                .filter(|template| template.instance != STRING_HANDLE)
+                .count();
+            StringTemplate {
+                name,
+                instance: FIRST_TEMPLATE + others as u16,
+                handle: 0x1000 | capacity as u16,
+                capacity,
+            }
+        };
+        templates.push(template.clone());
+        Ok(Some(template))
+    }
+
+    /// Bytes of a string in tag services, the length and the characters
+    /// padded to 32 bits.
+    fn size(&self) -> usize {
+        (4 + self.capacity).next_multiple_of(4)
+    }
+
+    /// Definition returned by Read Template: the LEN and DATA members, then
+    /// the names of the type and of its members.
+    fn definition(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&0xC4u16.to_le_bytes());
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&(self.capacity as u16).to_le_bytes());
+        out.extend_from_slice(&(0xC2u16 | 1 << 13).to_le_bytes());
+        out.extend_from_slice(&4u32.to_le_bytes());
+        out.extend_from_slice(format!("{};n\0LEN\0DATA\0", self.name).as_bytes());
+        out
+    }
+
+    /// Size of the definition in 32 bit words, with the header.
+    fn object_size(&self) -> u32 {
+        (TEMPLATE_HEADER + self.definition().len()).div_ceil(4) as u32
+    }
+}
+
+#[derive(Debug)]
+struct GeneratorState {
+    started: Instant,
+    /// Current value and last step of random walks.
+    walk: f64,
+    stepped: Instant,
+    seed: u64,
+}
+
+impl Tag {
+    fn new(instance: u16, tag: &SimTag, templates: &mut Vec<StringTemplate>) -> Result<Self> {
+        let (type_name, len) = match tag.r#type.trim().split_once('[') {
+            Some((name, dims)) => {
+                let len: usize = dims
+                    .strip_suffix(']')
+                    .and_then(|len| len.trim().parse().ok())
+                    .filter(|len| (1..=65_535).contains(len))
+                    .with_context(|| format!("invalid array type `{}`", tag.r#type))?;
+                (name.trim(), len)
+            }
+            None => (tag.r#type.trim(), 0),
+        };
+        if let Some(string) = StringTemplate::find(type_name, templates)? {
+            return Self::string(instance, tag, string, len);
+        }
+        let (_, type_code, size) = TYPES
+            .iter()
+            .find(|(name, _, _)| name.eq_ignore_ascii_case(type_name))
+            .with_context(|| {
+                format!(
+                    "unsupported type `{}`, the simulator has atomic types, their arrays and \
+                     strings",
+                    type_name
+                )
+            })?;
+        if len > 0 && *type_code == 0xC1 {
+            bail!("BOOL arrays aren't simulated, use a DWORD array");
+        }
+        let mut sim = Self {
+            name: tag.name.clone(),
+            instance,
+            type_code: *type_code,
+            size: *size,
+            len,
+            data: vec![0; size * len.max(1)],
+            generator: None,
+            external_access: tag.external_access,
+            constant: tag.constant,
+            string: None,
+        };
+        match &tag.value {
+            None => {}
+            Some(toml::Value::Array(values)) if len > 0 => {
+                if values.len() > len {
+                    bail!("{} values for {} elements", values.len(), len);
+                }
+                for (idx, value) in values.iter().enumerate() {
+                    sim.store(idx, number(value)?);
+                }
+            }
+            Some(value) => {
+                let value = number(value)?;
+                for idx in 0..len.max(1) {
+                    sim.store(idx, value);
+                }
+            }
+        }
+        if let Some(generator) = &tag.generate {
+            let start = match generator {
+                Generator::Ramp { period, .. } | Generator::Sine { period, .. }
+                    if period.is_zero() =>
+                {
+                    bail!("the period of the generator must be above zero")
+                }
+                Generator::RandomWalk { min, max, .. } if min > max => {
+                    bail!("min of the random walk is above its max")
+                }
+                Generator::RandomWalk { start, .. } => *start,
+                _ => 0.0,
+            };
+            let now = Instant::now();
+            let state = GeneratorState {
+                started: now,
+                walk: start,
+                stepped: now,
+                seed: 0x9E37_79B9_7F4A_7C15 ^ instance as u64,
+            };
+            sim.generator = Some((generator.clone(), state));
+        }
+        Ok(sim)
+    }
+
+    /// A string tag, holding its initial text.
+    fn string(instance: u16, tag: &SimTag, string: StringTemplate, len: usize) -> Result<Self> {
+        if len > 0 {
+            bail!("{} arrays aren't simulated", string.name);
+        }
+        if tag.generate.is_some() {
+            bail!("strings can't be generated");
+        }
+        let text = match &tag.value {
+            None => "",
+            Some(toml::Value::String(text)) => text.as_str(),
+            Some(value) => bail!("expected the text of the string, got {}", value),
+        };
+        if !text.is_ascii() || text.len() > string.capacity {
+            bail!(
+                "a {} holds up to {} ASCII characters",
+                string.name,
+                string.capacity
+            );
+        }
+        let mut data = vec![0; string.size()];
+        data[..4].copy_from_slice(&(text.len() as i32).to_le_bytes());
+        data[4..4 + text.len()].copy_from_slice(text.as_bytes());
+        Ok(Self {
+            name: tag.name.clone(),
+            instance,
+            type_code: TYPE_STRUCTURE,
+            size: string.size(),
+            len,
+            data,
+            generator: None,
+            external_access: tag.external_access,
+            constant: tag.constant,
+            string: Some(string),
+        })
+    }
+
+    /// Program of a program scope tag.
+    fn program(&self) -> Option<&str> {
+        let (program, _) = self.name.strip_prefix("Program:")?.split_once('.')?;
+        Some(program)
+    }
+
+    /// CIP symbol type, with the dimensions of arrays and the template of
+    /// structures.
+    fn symbol_type(&self) -> u16 {
+        if let Some(string) = &self.string {
+            return 1 << 15 | string.instance;
+        }
+        if self.len > 0 {
+            self.type_code | 1 << 13
+        } else {
+            self.type_code
+        }
+    }
+
+    /// Type of the tag in tag services, with the handle of structures.
+    fn tag_type(&self) -> Vec<u8> {
+        let mut out = self.type_code.to_le_bytes().to_vec();
+        if let Some(string) = &self.string {
+            out.extend_from_slice(&string.handle.to_le_bytes());
+        }
+        out
+    }
+
+    /// Store a number into an element, converted to the tag type.
+    fn store(&mut self, idx: usize, value: f64) {
+        let bytes = &mut self.data[idx * self.size..(idx + 1) * self.size];
+        match self.type_code {
+            0xC1 => bytes[0] = if value != 0.0 { 1 } else { 0 },
+            0xC2 => bytes.copy_from_slice(&(value.round() as i8).to_le_bytes()),
+            0xC3 => bytes.copy_from_slice(&(value.round() as i16).to_le_bytes()),
+            0xC4 => bytes.copy_from_slice(&(value.round() as i32).to_le_bytes()),
+            0xC5 => bytes.copy_from_slice(&(value.round() as i64).to_le_bytes()),
+            0xC6 => bytes.copy_from_slice(&(value.round() as u8).to_le_bytes()),
+            0xC7 => bytes.copy_from_slice(&(value.round() as u16).to_le_bytes()),
+            0xC8 | 0xD3 => bytes.copy_from_slice(&(value.round() as u32).to_le_bytes()),
+            0xC9 => bytes.copy_from_slice(&(value.round() as u64).to_le_bytes()),
+            0xCA => bytes.copy_from_slice(&(value as f32).to_le_bytes()),
+            _ => bytes.copy_from_slice(&value.to_le_bytes()),
+        }
+    }
+
+    /// Bring generated values up to date.
+    fn update(&mut self, now: Instant) {
+        let value = match &mut self.generator {
+            Some((generator, state)) => generate(generator, state, now),
+            None => return,
+        };
+        for idx in 0..self.len.max(1) {
+            self.store(idx, value);
+        }
+    }
+}
+
+/// Number of a value of the tags file.
+fn number(value: &toml::Value) -> Result<f64> {
+    match value {
+        toml::Value::Integer(v) => Ok(*v as f64),
+        toml::Value::Float(v) => Ok(*v),
+        toml::Value::Boolean(v) => Ok(*v as u8 as f64),
+        other => bail!("expected a number or a boolean, got {}", other),
+    }
+}
+
+fn generate(generator: &Generator, state: &mut GeneratorState, now: Instant) -> f64 {
+    let elapsed = now.duration_since(state.started).as_secs_f64();
+    match *generator {
+        Generator::Ramp { from, to, period } => {
+            let phase = (elapsed / period.as_secs_f64()).fract();
+            from + (to - from) * phase
+        }
+        Generator::Sine { min, max, period } => {
+            let angle = std::f64::consts::TAU * elapsed / period.as_secs_f64();
+            (min + max) / 2.0 + (max - min) / 2.0 * angle.sin()
+        }
+        Generator::RandomWalk { step, min, max, .. } => {
+            let steps = now.duration_since(state.stepped).as_secs().min(3600);
+            for _ in 0..steps {
+                // xorshift64, plenty for jitter.
+                state.seed ^= state.seed << 13;
+                state.seed ^= state.seed >> 7;
+                state.seed ^= state.seed << 17;
+                let unit = (state.seed >> 11) as f64 / (1u64 << 53) as f64;
+                state.walk = (state.walk + step * (2.0 * unit - 1.0)).clamp(min, max);
+            }
+            state.stepped += Duration::from_secs(steps);
+            state.walk
+        }
+    }
+}
+
+/// Segments of the request paths the simulator understands.
+#[derive(Debug, PartialEq)]
+enum Segment {
+    Port,
+    Class(u16),
+    Instance(u32),
+    Attribute(u16),
+    Element(u32),
+    Symbol(String),
+}
+
+/// Bounds checked little endian reads of a request.
+struct Reader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+        let (head, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_le_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        std::mem::take(&mut self.data)
+    }
+}
+
+/// Parse a padded EPATH.
+fn parse_path(data: &[u8]) -> Option<Vec<Segment>> {
+    let mut reader = Reader::new(data);
+    let mut segments = Vec::new();
+    while !reader.data.is_empty() {
+        let segment = match reader.u8()? {
+            0x20 => Segment::Class(reader.u8()? as u16),
+            0x21 => {
+                reader.u8()?;
+                Segment::Class(reader.u16()?)
+            }
+            0x24 => Segment::Instance(reader.u8()? as u32),
+            0x25 => {
+                reader.u8()?;
+                Segment::Instance(reader.u16()? as u32)
+            }
+            0x26 => {
+                reader.u8()?;
+                Segment::Instance(reader.u32()?)
+            }
+            0x30 => Segment::Attribute(reader.u8()? as u16),
+            0x31 => {
+                reader.u8()?;
+                Segment::Attribute(reader.u16()?)
+            }
+            0x28 => Segment::Element(reader.u8()? as u32),
+            0x29 => {
+                reader.u8()?;
+                Segment::Element(reader.u16()? as u32)
+            }
+            0x2A => {
+                reader.u8()?;
+                Segment::Element(reader.u32()?)
+            }
+            0x91 => {
+                let len = reader.u8()? as usize;
+                let name = std::str::from_utf8(reader.bytes(len)?).ok()?.to_owned();
+                if !len.is_multiple_of(2) {
+                    reader.u8()?;
+                }
+                Segment::Symbol(name)
+            }
+            // Port segments, with a one byte link or an extended one.
+            port if port & 0xE0 == 0 => {
+                if port & 0x10 != 0 {
+                    let len = reader.u8()? as usize;
+                    reader.bytes(len + len % 2)?;
+                } else {
+                    reader.u8()?;
+                }
+                Segment::Port
+            }
+            _ => return None,
+        };
+        segments.push(segment);
+    }
+    Some(segments)
+}
+
+/// A CIP reply: the service, the status and the data.
+struct Reply {
+    service: u8,
+    status: u8,
+    extended: Option<u16>,
+    data: Vec<u8>,
+}
+
+impl Reply {
+    fn ok(service: u8, data: Vec<u8>) -> Self {
+        Self::status(service, STATUS_SUCCESS, data)
+    }
+
+    fn status(service: u8, status: u8, data: Vec<u8>) -> Self {
+        Self {
+            service,
+            status,
+            extended: None,
+            data,
+        }
+    }
+
+    fn error(service: u8, status: u8) -> Self {
+        Self::status(service, status, Vec::new())
+    }
+
+    fn extended(service: u8, extended: u16) -> Self {
+        Self::failure(service, STATUS_GENERAL, extended)
+    }
+
+    fn failure(service: u8, status: u8, extended: u16) -> Self {
+        Self {
+            service,
+            status,
+            extended: Some(extended),
+            data: Vec::new(),
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![self.service | 0x80, 0, self.status];
+        match self.extended {
+            Some(extended) => {
+                out.push(1);
+                out.extend_from_slice(&extended.to_le_bytes());
+            }
+            None => out.push(0),
+        }
+        out.extend_from_slice(&self.data);
+        out
+    }
+}
+
+/// A CIP connection opened by a Forward Open. Like on a controller, it
+/// times out after 32 packet intervals without requests.
+#[derive(Debug, Clone, Copy)]
+struct Connection {
+    o_t_id: u32,
+    t_o_id: u32,
+    serial: u16,
+    timeout: Duration,
+    last_request: Instant,
+}
+
+/// The simulated controller: its identity and its tags, shared by the
+/// client sessions.
+#[derive(Clone)]
+pub struct Simulator {
+    identity: Arc<SimIdentity>,
+    tags: Arc<Mutex<Vec<Tag>>>,
+    templates: Arc<Vec<StringTemplate>>,
+    /// Programs of the program scope tags with their symbol instance,
+    /// listed after the controller tags as `Program:Main`.
+    programs: Arc<Vec<(u16, String)>>,
+}
+
+/// State of one client session.
+#[derive(Default)]
+struct Session {
+    handle: u32,
+    connections: Vec<Connection>,
+}
+
+static NEXT_ID: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(1);
+
+fn next_id() -> u32 {
+    NEXT_ID.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+}
+
+impl Simulator {
+    pub fn new(file: &SimFile) -> Result<Self> {
+        let mut tags: Vec<Tag> = Vec::with_capacity(file.tags.len());
+        let mut names = HashMap::new();
+        let mut templates = Vec::new();
+        for (idx, tag) in file.tags.iter().enumerate() {
+            if !valid_tag_name(&tag.name) {
+                bail!(
+                    "`{}` isn't a valid tag name, or `Program:Name.Tag` for a program tag",
+                    tag.name
+                );
+            }
+            if names.insert(tag.name.to_lowercase(), idx).is_some() {
+                bail!("tag {} is simulated twice", tag.name);
+            }
+            let instance = u16::try_from(idx + 1).context("too many simulated tags")?;
+            let sim = Tag::new(instance, tag, &mut templates)
+                .with_context(|| format!("tag {}", tag.name))?;
+            tags.push(sim);
+        }
+        let mut programs: Vec<(u16, String)> = Vec::new();
+        for tag in &tags {
+            let Some(program) = tag.program() else {
+                continue;
+            };
+            if !programs
+                .iter()
+                .any(|(_, name)| name.eq_ignore_ascii_case(program))
+            {
+                let instance = u16::try_from(tags.len() + programs.len() + 1)
+                    .context("too many simulated tags")?;
+                programs.push((instance, program.to_owned()));
+            }
+        }
+        Ok(Self {
+            identity: Arc::new(SimIdentity {
+                product_name: file.identity.product_name.clone(),
+                serial_number: file.identity.serial_number,
+                revision: file.identity.revision,
+                family: file.identity.family,
+            }),
+            tags: Arc::new(Mutex::new(tags)),
+            templates: Arc::new(templates),
+            programs: Arc::new(programs),
+        })
+    }
+
+    /// Serve the simulator in the background on an address like
+    /// `127.0.0.1:0`, returning the address it listens on. Meant for tests
+    /// running commands against the simulator in process.
+    pub async fn spawn(self, address: &str) -> Result<std::net::SocketAddr> {
+        let listener = TcpListener::bind(address)
+            .await
+            .with_context(|| format!("failed to listen on {}", address))?;
+        let local = listener.local_addr()?;
+        tokio::spawn(self.serve(listener));
+        Ok(local)
+    }
+
+    /// Answer the clients connecting to the listener, each in its own task.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, peer) = listener.accept().await?;
+            tracing::debug!(%peer, "client connected");
+            let simulator = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = simulator.session(stream).await {
+                    tracing::debug!(%peer, "session ended: {:#}", e);
+                }
+            });
+        }
+    }
+
+    async fn session(&self, mut stream: TcpStream) -> Result<()> {
+        let mut session = Session::default();
+        let mut header = [0u8; 24];
+        loop {
+            match stream.read_exact(&mut header).await {
+                Ok(_) => {}
+                Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+            let command = u16::from_le_bytes([header[0], header[1]]);
+            let len = u16::from_le_bytes([header[2], header[3]]) as usize;
+            let handle = u32::from_le_bytes(header[4..8].try_into()?);
+            if len > MAX_PACKET {
+                bail!("packet of {} bytes", len);
+            }
+            let mut data = vec![0; len];
+            stream.read_exact(&mut data).await?;
+
+            let (status, reply) = match command {
+                CMD_NOP => continue,
+                CMD_UNREGISTER_SESSION => return Ok(()),
+                CMD_REGISTER_SESSION if session.handle == 0 => {
+                    session.handle = next_id();
+                    (0, data)
+                }
+                CMD_LIST_SERVICES => (0, list_services()),
+                CMD_SEND_RR_DATA | CMD_SEND_UNIT_DATA
+                    if handle == 0 || handle != session.handle =>
+                {
+                    (ENCAP_INVALID_SESSION, Vec::new())
+                }
+                CMD_SEND_RR_DATA => match self.rr_data(&mut session, &data) {
+                    Some(reply) => (0, reply),
+                    None => (ENCAP_INVALID_LENGTH, Vec::new()),
+                },
+                CMD_SEND_UNIT_DATA => match self.unit_data(&mut session, &data)? {
+                    Some(reply) => (0, reply),
+                    None => (ENCAP_INVALID_LENGTH, Vec::new()),
+                },
+                _ => (ENCAP_INVALID_COMMAND, Vec::new()),
+            };
+            let mut packet = Vec::with_capacity(24 + reply.len());
+            packet.extend_from_slice(&command.to_le_bytes());
+            packet.extend_from_slice(&(reply.len() as u16).to_le_bytes());
+            packet.extend_from_slice(&session.handle.to_le_bytes());
+            packet.extend_from_slice(&status.to_le_bytes());
+            // Sender context and options, echoed.
+            packet.extend_from_slice(&header[12..24]);
+            packet.extend_from_slice(&reply);
+            stream.write_all(&packet).await?;
+        }
+    }
+
+    /// Answer an unconnected message, carried by SendRRData.
+    fn rr_data(&self, session: &mut Session, data: &[u8]) -> Option<Vec<u8>> {
+        let items = common_packet(data)?;
+        let (_, request) = items.iter().find(|(kind, _)| *kind == 0x00B2)?;
+        let reply = self.message(session, request).encode();
+        let mut out = vec![0; 6];
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(&0x00B2u16.to_le_bytes());
+        out.extend_from_slice(&(reply.len() as u16).to_le_bytes());
+        out.extend_from_slice(&reply);
+        Some(out)
+    }
+
+    /// Answer a message over a CIP connection, carried by SendUnitData. A
+    /// message over a connection that timed out ends the session.
+    fn unit_data(&self, session: &mut Session, data: &[u8]) -> Result<Option<Vec<u8>>> {
+        let Some((id, item)) = connected_item(data) else {
+            return Ok(None);
+        };
+        session
+            .connections
+            .retain(|c| c.last_request.elapsed() < c.timeout);
+        let Some(connection) = session.connections.iter_mut().find(|c| c.o_t_id == id) else {
+            bail!(
+                "message over connection {:#x}, which timed out or was never opened",
+                id
+            );
+        };
+        connection.last_request = Instant::now();
+        let connection = *connection;
+        let mut reader = Reader::new(item);
+        let Some(sequence) = reader.u16() else {
+            return Ok(None);
+        };
+        let reply = self.message(session, reader.rest()).encode();
+        let mut out = vec![0; 6];
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&0x00A1u16.to_le_bytes());
+        out.extend_from_slice(&4u16.to_le_bytes());
+        out.extend_from_slice(&connection.t_o_id.to_le_bytes());
+        out.extend_from_slice(&0x00B1u16.to_le_bytes());
+        out.extend_from_slice(&(reply.len() as u16 + 2).to_le_bytes());
+        out.extend_from_slice(&sequence.to_le_bytes());
+        out.extend_from_slice(&reply);
+        Ok(Some(out))
+    }
+
+    /// Answer a CIP message request.
+    fn message(&self, session: &mut Session, request: &[u8]) -> Reply {
+        let mut reader = Reader::new(request);
+        let (service, path) = match (reader.u8(), reader.u8()) {
+            (Some(service), Some(words)) => match reader.bytes(words as usize * 2) {
+                Some(path) => (service, path),
+                None => return Reply::error(service, STATUS_NOT_ENOUGH_DATA),
+            },
+            _ => return Reply::error(0, STATUS_NOT_ENOUGH_DATA),
+        };
+        let path = match parse_path(path) {
+            Some(path) => path,
+            None => return Reply::error(service, STATUS_PATH_SEGMENT),
+        };
+        let data = reader.rest();
+        use Segment::*;
+        match (service, path.as_slice()) {
+            (0x52, [Class(0x06), Instance(1)]) => self.unconnected_send(session, data),
+            (0x54 | 0x5B, [Class(0x06), Instance(1)]) => self.forward_open(session, service, data),
+            (0x4E, [Class(0x06), Instance(1)]) => forward_close(session, data),
+            (0x0A, [Class(0x02), Instance(1)]) if !self.identity.family.packs_requests() => {
+                Reply::error(service, STATUS_UNSUPPORTED)
+            }
+            (0x0A, [Class(0x02), Instance(1)]) => self.multiple_service(session, data),
+            (0x01, [Class(0x01), Instance(1)]) => self.identity(),
+            (0x0E, [Class(0x01), Instance(1), Attribute(attribute)]) => {
+                self.identity_attribute(*attribute)
+            }
+            (0x03, [Class(0xAC), Instance(1)]) => change_counters(data),
+            (0x55, [Class(0x6B), Instance(start)]) => self.list(None, *start, data),
+            (0x55, [Symbol(program), Class(0x6B), Instance(start)]) => {
+                match program.strip_prefix("Program:").and_then(|program| {
+                    self.programs
+                        .iter()
+                        .find(|(_, name)| name.eq_ignore_ascii_case(program))
+                }) {
+                    Some((_, program)) => self.list(Some(program), *start, data),
+                    None => Reply::error(service, STATUS_PATH_UNKNOWN),
+                }
+            }
+            (0x03, [Class(0x6B), Instance(instance)]) => self.symbol_attributes(*instance, data),
+            (0x4C..=0x4E, [Symbol(_), ..] | [Class(0x6B), Instance(_), ..]) => {
+                self.tag_service(service, &path, data)
+            }
+            (0x03, [Class(0x6C), Instance(instance)]) => self.template_attributes(*instance, data),
+            (0x4C, [Class(0x6C), Instance(instance)]) => self.read_template(*instance, data),
+            (_, [Class(0x01 | 0x02 | 0x06 | 0x6B | 0x6C | 0xAC), ..] | [Symbol(_), ..]) => {
+                Reply::error(service, STATUS_UNSUPPORTED)
+            }
+            _ => Reply::error(service, STATUS_PATH_UNKNOWN),
+        }
+    }
+
+    /// Unwrap the request of an Unconnected Send; the route is ignored, the
+    /// simulator is whatever controller it leads to.
+    fn unconnected_send(&self, session: &mut Session, data: &[u8]) -> Reply {
+        let mut reader = Reader::new(data);
+        let request = reader
+            .bytes(2)
+            .and_then(|_| reader.u16())
+            .and_then(|len| reader.bytes(len as usize));
+        let request = match request {
+            Some(request) => request,
+            None => return Reply::error(0x52, STATUS_NOT_ENOUGH_DATA),
+        };
+        if self.identity.family == Family::Micro800 {
+            // The route follows the request, padded to a word.
+            if request.len() % 2 == 1 {
+                reader.u8();
+            }
+            let route = reader
+                .u8()
+                .and_then(|words| reader.u8().and_then(|_| reader.bytes(words as usize * 2)));
+            if route.is_some_and(|route| !route.is_empty()) {
+                return Reply::failure(0x52, STATUS_CONNECTION_FAILURE, EXT_INVALID_PORT);
+            }
+        }
+        self.message(session, request)
+    }
+
+    fn multiple_service(&self, session: &mut Session, data: &[u8]) -> Reply {
+        let mut reader = Reader::new(data);
+        let count = match reader.u16() {
+            Some(count) => count as usize,
+            None => return Reply::error(0x0A, STATUS_NOT_ENOUGH_DATA),
+        };
+        let offsets: Option<Vec<usize>> =
+            (0..count).map(|_| reader.u16().map(usize::from)).collect();
+        let offsets = match offsets {
+            Some(offsets) if offsets.iter().all(|&offset| offset <= data.len()) => offsets,
+            _ => return Reply::error(0x0A, STATUS_NOT_ENOUGH_DATA),
+        };
+        let mut replies = Vec::with_capacity(count);
+        for (idx, &start) in offsets.iter().enumerate() {
+            let end = offsets.get(idx + 1).copied().unwrap_or(data.len());
+            let request = data.get(start..end).unwrap_or_default();
+            replies.push(self.message(session, request));
+        }
+
+        let mut out = (count as u16).to_le_bytes().to_vec();
+        let mut offset = 2 + 2 * count;
+        let encoded: Vec<Vec<u8>> = replies.iter().map(Reply::encode).collect();
+        for reply in &encoded {
+            out.extend_from_slice(&(offset as u16).to_le_bytes());
+            offset += reply.len();
+        }
+        for reply in &encoded {
+            out.extend_from_slice(reply);
+        }
+        let status = if replies.iter().all(|reply| reply.status == STATUS_SUCCESS) {
+            STATUS_SUCCESS
+        } else {
+            STATUS_EMBEDDED
+        };
+        Reply::status(0x0A, status, out)
+    }
+
+    /// Forward Open or Large Forward Open, accepting any connection path
+    /// but routed ones on Micro800 controllers.
+    fn forward_open(&self, session: &mut Session, service: u8, data: &[u8]) -> Reply {
+        // Large Forward Opens have 32 bit connection parameters.
+        let params_len = if service == 0x5B { 4 } else { 2 };
+        let (t_o_id, ids, o_t_rpi, t_o_rpi, path) = match parse_forward_open(data, params_len) {
+            Some(request) => request,
+            None => return Reply::error(service, STATUS_NOT_ENOUGH_DATA),
+        };
+        let routed = parse_path(path)
+            .is_none_or(|path| path.iter().any(|segment| matches!(segment, Segment::Port)));
+        if self.identity.family == Family::Micro800 && routed {
+            return Reply::failure(service, STATUS_CONNECTION_FAILURE, EXT_INVALID_SEGMENT);
+        }
+        let connection = Connection {
+            o_t_id: next_id(),
+            t_o_id,
+            serial: u16::from_le_bytes([ids[0], ids[1]]),
+            timeout: Duration::from_micros(o_t_rpi as u64) * 32,
+            last_request: Instant::now(),
+        };
+        session.connections.push(connection);
+        let mut out = connection.o_t_id.to_le_bytes().to_vec();
+        out.extend_from_slice(&t_o_id.to_le_bytes());
+        out.extend_from_slice(ids);
+        out.extend_from_slice(&o_t_rpi.to_le_bytes());
+        out.extend_from_slice(&t_o_rpi.to_le_bytes());
+        out.extend_from_slice(&[0, 0]);
+        Reply::ok(service, out)
+    }
+
+    /// Identity attributes 1 to 8: vendor, device type, product code,
+    /// revision, status (Run, keyswitch in Remote), serial number, name and
+    /// state.
+    fn identity_attributes(&self) -> Vec<Vec<u8>> {
+        let identity = &self.identity;
+        let name = &identity.product_name.as_bytes()[..identity.product_name.len().min(255)];
+        let mut short_string = vec![name.len() as u8];
+        short_string.extend_from_slice(name);
+        vec![
+            1u16.to_le_bytes().to_vec(),
+            0x0Eu16.to_le_bytes().to_vec(),
+            0xA6u16.to_le_bytes().to_vec(),
+            vec![identity.revision.0, identity.revision.1],
+            0x3060u16.to_le_bytes().to_vec(),
+            identity.serial_number.to_le_bytes().to_vec(),
+            short_string,
+            vec![3],
+        ]
+    }
+
+    fn identity(&self) -> Reply {
+        Reply::ok(0x01, self.identity_attributes().concat())
+    }
+
+    fn identity_attribute(&self, attribute: u16) -> Reply {
+        match self
+            .identity_attributes()
+            .get((attribute as usize).wrapping_sub(1))
+        {
+            Some(value) => Reply::ok(0x0E, value.clone()),
+            None => Reply::error(0x0E, STATUS_ATTRIBUTE_UNSUPPORTED),
+        }
+    }
+
+    /// Get Instance Attribute List of the Symbol class, listing the tags
+    /// from an instance on with their names and types: those of the
+    /// controller scope and the programs, or those of a program.
+    fn list(&self, program: Option<&str>, start: u32, data: &[u8]) -> Reply {
+        let mut reader = Reader::new(data);
+        let attributes: Option<Vec<u16>> = reader
+            .u16()
+            .and_then(|count| (0..count).map(|_| reader.u16()).collect());
+        let attributes = match attributes {
+            Some(attributes) => attributes,
+            None => return Reply::error(0x55, STATUS_NOT_ENOUGH_DATA),
+        };
+        let tags = self.tags.lock().unwrap();
+        let mut out = Vec::new();
+        let mut status = STATUS_SUCCESS;
+        // Program tags are listed by their own name within the program.
+        let listed = tags
+            .iter()
+            .filter(|tag| {
+                tag.external_access != ExternalAccess::None
+                    && match (program, tag.program()) {
+                        (None, None) => true,
+                        (Some(program), Some(scope)) => program.eq_ignore_ascii_case(scope),
+                        _ => false,
+                    }
+            })
+            .map(|tag| {
+                let name = match tag.program() {
+                    Some(program) => &tag.name["Program:".len() + program.len() + 1..],
+                    None => &tag.name,
+                };
+                (tag.instance, name.to_owned(), tag.symbol_type())
+            });
+        let programs = self
+            .programs
+            .iter()
+            .filter(|_| program.is_none())
+            .map(|(instance, name)| (*instance, format!("Program:{}", name), SYMBOL_PROGRAM));
+        for (instance, name, symbol_type) in listed.chain(programs) {
+            if (instance as u32) < start {
+                continue;
+            }
+            if out.len() > LIST_REPLY_BYTES {
+                status = STATUS_PARTIAL;
+                break;
+            }
+            out.extend_from_slice(&(instance as u32).to_le_bytes());
+            for attribute in &attributes {
+                match attribute {
+                    1 => {
+                        out.extend_from_slice(&(name.len() as u16).to_le_bytes());
+                        out.extend_from_slice(name.as_bytes());
+                    }
+                    2 => out.extend_from_slice(&symbol_type.to_le_bytes()),
+                    _ => return Reply::error(0x55, STATUS_UNSUPPORTED),
+                }
+            }
+        }
+        Reply::status(0x55, status, out)
+    }
+
+    /// Get Attribute List of a symbol instance: its name, type, array
+    /// dimensions, constant flag and external access.
+    fn symbol_attributes(&self, instance: u32, data: &[u8]) -> Reply {
+        let mut reader = Reader::new(data);
+        let attributes: Option<Vec<u16>> = reader
+            .u16()
+            .and_then(|count| (0..count).map(|_| reader.u16()).collect());
+        let attributes = match attributes {
+            Some(attributes) => attributes,
+            None => return Reply::error(0x03, STATUS_NOT_ENOUGH_DATA),
+        };
+        let tags = self.tags.lock().unwrap();
+        let tag = match tags.iter().find(|tag| tag.instance as u32 == instance) {
+            Some(tag) => tag,
+            None => return Reply::error(0x03, STATUS_PATH_UNKNOWN),
+        };
+        let mut out = (attributes.len() as u16).to_le_bytes().to_vec();
+        let mut status = STATUS_SUCCESS;
+        for attribute in attributes {
+            let value = match attribute {
+                1 => {
+                    let mut name = (tag.name.len() as u16).to_le_bytes().to_vec();
+                    name.extend_from_slice(tag.name.as_bytes());
+                    name
+                }
+                2 => tag.symbol_type().to_le_bytes().to_vec(),
+                8 => [tag.len as u32, 0, 0]
+                    .iter()
+                    .flat_map(|len| len.to_le_bytes())
+                    .collect(),
+                9 => vec![tag.constant as u8],
+                10 => vec![tag.external_access.code()],
+                _ => {
+                    status = STATUS_ATTRIBUTE_LIST;
+                    out.extend_from_slice(&attribute.to_le_bytes());
+                    out.extend_from_slice(&(STATUS_ATTRIBUTE_UNSUPPORTED as u16).to_le_bytes());
+                    continue;
+                }
+            };
+            out.extend_from_slice(&attribute.to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(&value);
+        }
+        Reply::status(0x03, status, out)
+    }
+
+    fn template(&self, instance: u32) -> Option<&StringTemplate> {
+        self.templates
+            .iter()
+            .find(|template| template.instance as u32 == instance)
+    }
+
+    /// Get Attribute List of a template: its structure handle, member count,
+    /// definition size and structure size.
+    fn template_attributes(&self, instance: u32, data: &[u8]) -> Reply {
+        let mut reader = Reader::new(data);
+        let attributes: Option<Vec<u16>> = reader
+            .u16()
+            .and_then(|count| (0..count).map(|_| reader.u16()).collect());
+        let attributes = match attributes {
+            Some(attributes) => attributes,
+            None => return Reply::error(0x03, STATUS_NOT_ENOUGH_DATA),
+        };
+        let Some(template) = self.template(instance) else {
+            return Reply::error(0x03, STATUS_PATH_UNKNOWN);
+        };
+        let mut out = (attributes.len() as u16).to_le_bytes().to_vec();
+        for attribute in attributes {
+            let value = match attribute {
+                1 => template.handle.to_le_bytes().to_vec(),
+                2 => 2u16.to_le_bytes().to_vec(),
+                4 => template.object_size().to_le_bytes().to_vec(),
+                5 => (template.size() as u32).to_le_bytes().to_vec(),
+                _ => return Reply::error(0x03, STATUS_ATTRIBUTE_LIST),
+            };
+            out.extend_from_slice(&attribute.to_le_bytes());
+            out.extend_from_slice(&0u16.to_le_bytes());
+            out.extend_from_slice(&value);
+        }
+        Reply::ok(0x03, out)
+    }
+
+    /// Read Template, the definition from an offset on.
+    fn read_template(&self, instance: u32, data: &[u8]) -> Reply {
+        let mut reader = Reader::new(data);
+        let (offset, len) = match (reader.u32(), reader.u16()) {
+            (Some(offset), Some(len)) => (offset as usize, len as usize),
+            _ => return Reply::error(0x4C, STATUS_NOT_ENOUGH_DATA),
+        };
+        let Some(template) = self.template(instance) else {
+            return Reply::error(0x4C, STATUS_PATH_UNKNOWN);
+        };
+        let definition = template.definition();
+        let Some(rest) = definition.get(offset..) else {
+            return Reply::error(0x4C, STATUS_TOO_MUCH_DATA);
+        };
+        if rest.len() > len {
+            return Reply::status(0x4C, STATUS_PARTIAL, rest[..len].to_vec());
+        }
+        Reply::ok(0x4C, rest.to_vec())
+    }
+
+    /// Read Tag, Write Tag and Read Modify Write Tag.
+    fn tag_service(&self, service: u8, path: &[Segment], data: &[u8]) -> Reply {
+        let mut tags = self.tags.lock().unwrap();
+        let (tag, rest) = match path {
+            [Segment::Symbol(program), Segment::Symbol(name), rest @ ..]
+                if program.starts_with("Program:") =>
+            {
+                let name = format!("{}.{}", program, name);
+                (
+                    tags.iter_mut()
+                        .find(|tag| tag.name.eq_ignore_ascii_case(&name)),
+                    rest,
+                )
+            }
+            [Segment::Symbol(name), rest @ ..] => (
+                tags.iter_mut()
+                    .find(|tag| tag.name.eq_ignore_ascii_case(name)),
+                rest,
+            ),
+            [_, Segment::Instance(id), rest @ ..] => {
+                (tags.iter_mut().find(|tag| tag.instance as u32 == *id), rest)
+            }
+            _ => (None, path),
+        };
+        let tag = match tag {
+            Some(tag) => tag,
+            None => return Reply::error(service, STATUS_PATH_SEGMENT),
+        };
+        let refused = match tag.external_access {
+            ExternalAccess::None => true,
+            ExternalAccess::ReadOnly => service != 0x4C,
+            ExternalAccess::ReadWrite => service != 0x4C && tag.constant,
+        };
+        if refused {
+            return Reply::error(service, STATUS_PRIVILEGE);
+        }
+        let index = match rest {
+            [] => 0,
+            [Segment::Element(idx)] if tag.len > 0 => *idx as usize,
+            // Members of structures and elements of single values.
+            _ => return Reply::error(service, STATUS_PATH_SEGMENT),
+        };
+        if index >= tag.len.max(1) {
+            return Reply::extended(service, EXT_OUT_OF_RANGE);
+        }
+        tag.update(Instant::now());
+        let mut reader = Reader::new(data);
+        match service {
+            0x4C => {
+                let count = reader.u16().unwrap_or(1).max(1) as usize;
+                if index + count > tag.len.max(1) {
+                    return Reply::extended(service, EXT_OUT_OF_RANGE);
+                }
+                let mut out = tag.tag_type();
+                out.extend_from_slice(&tag.data[index * tag.size..(index + count) * tag.size]);
+                Reply::ok(service, out)
+            }
+            0x4D => {
+                let tag_type = match reader.u16() {
+                    Some(TYPE_STRUCTURE) => reader.u16().map(|handle| {
+                        let mut tag_type = TYPE_STRUCTURE.to_le_bytes().to_vec();
+                        tag_type.extend_from_slice(&handle.to_le_bytes());
+                        tag_type
+                    }),
+                    type_code => type_code.map(|code| code.to_le_bytes().to_vec()),
+                };
+                let (tag_type, count) = match (tag_type, reader.u16()) {
+                    (Some(tag_type), Some(count)) => (tag_type, count.max(1) as usize),
+                    _ => return Reply::error(service, STATUS_NOT_ENOUGH_DATA),
+                };
+                if tag_type != tag.tag_type() {
+                    return Reply::extended(service, EXT_TYPE_MISMATCH);
+                }
+                if index + count > tag.len.max(1) {
+                    return Reply::extended(service, EXT_OUT_OF_RANGE);
+                }
+                let value = reader.rest();
+                if value.len() < count * tag.size {
+                    return Reply::error(service, STATUS_NOT_ENOUGH_DATA);
+                }
+                if value.len() > count * tag.size {
+                    return Reply::error(service, STATUS_TOO_MUCH_DATA);
+                }
+                tag.data[index * tag.size..(index + count) * tag.size].copy_from_slice(value);
+                // The written value sticks, as if the logic left the tag alone.
+                tag.generator = None;
+                Reply::ok(service, Vec::new())
+            }
+            _ => {
+                let size = reader.u16().unwrap_or_default() as usize;
+                let masks = reader.bytes(size * 2);
+                let masks = match masks {
+                    Some(masks) if size > 0 => masks,
+                    _ => return Reply::error(service, STATUS_NOT_ENOUGH_DATA),
+                };
+                let integer = !matches!(tag.type_code, 0xC1 | 0xCA | 0xCB | TYPE_STRUCTURE);
+                if !integer || size > tag.size {
+                    return Reply::extended(service, EXT_TYPE_MISMATCH);
+                }
+                let (or, and) = masks.split_at(size);
+                let word = &mut tag.data[index * tag.size..index * tag.size + size];
+                for ((byte, or), and) in word.iter_mut().zip(or).zip(and) {
+                    *byte = (*byte | or) & and;
+                }
+                tag.generator = None;
+                Reply::ok(service, Vec::new())
+            }
+        }
+    }
+}
+
+/// Whether a name is a valid controller scope tag name.
+/// A controller tag name, or `Program:Name.Tag` for a tag of a program.
+fn valid_tag_name(name: &str) -> bool {
+    match name
+        .strip_prefix("Program:")
+        .and_then(|rest| rest.split_once('.'))
+    {
+        Some((program, tag)) => valid_name(program) && valid_name(tag),
+        None => valid_name(name),
+    }
+}
+
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && name.len() <= 40
+}
+
+/// Items of a common packet following the interface handle and timeout.
+fn common_packet(data: &[u8]) -> Option<Vec<(u16, &[u8])>> {
+    let mut reader = Reader::new(data);
+    reader.bytes(6)?;
+    let count = reader.u16()?;
+    (0..count)
+        .map(|_| {
+            let kind = reader.u16()?;
+            let len = reader.u16()?;
+            Some((kind, reader.bytes(len as usize)?))
+        })
+        .collect()
+}
+
+/// The one service of a Logix controller: CIP messages over TCP.
+fn list_services() -> Vec<u8> {
+    let mut out = 1u16.to_le_bytes().to_vec();
+    out.extend_from_slice(&0x0100u16.to_le_bytes());
+    out.extend_from_slice(&20u16.to_le_bytes());
+    out.extend_from_slice(&1u16.to_le_bytes());
+    out.extend_from_slice(&0x0120u16.to_le_bytes());
+    out.extend_from_slice(b"Communications\0\0");
+    out
+}
+
+/// Accept any Forward Open, echoing the RPIs as actual intervals.
+/// T->O connection id, connection serial with the originator ids, the RPIs
+/// and the connection path of a Forward Open request.
+type ForwardOpen<'a> = (u32, &'a [u8], u32, u32, &'a [u8]);
+
+fn parse_forward_open(data: &[u8], params_len: usize) -> Option<ForwardOpen<'_>> {
+    let mut reader = Reader::new(data);
+    reader.bytes(6)?;
+    let t_o_id = reader.u32()?;
+    let ids = reader.bytes(8)?;
+    reader.bytes(4)?;
+    let o_t_rpi = reader.u32()?;
+    reader.bytes(params_len)?;
+    let t_o_rpi = reader.u32()?;
+    reader.bytes(params_len + 1)?;
+    let words = reader.u8()?;
+    let path = reader.bytes(words as usize * 2)?;
+    Some((t_o_id, ids, o_t_rpi, t_o_rpi, path))
+}
+
+/// Connection id and data item of a connected message.
+fn connected_item(data: &[u8]) -> Option<(u32, &[u8])> {
+    let items = common_packet(data)?;
+    let (_, address) = items.iter().find(|(kind, _)| *kind == 0x00A1)?;
+    let id = Reader::new(address).u32()?;
+    let (_, item) = items.iter().find(|(kind, _)| *kind == 0x00B1)?;
+    Some((id, item))
+}
+
+fn forward_close(session: &mut Session, data: &[u8]) -> Reply {
+    let ids = match data.get(2..10) {
+        Some(ids) => ids,
+        None => return Reply::error(0x4E, STATUS_NOT_ENOUGH_DATA),
+    };
+    let serial = u16::from_le_bytes([ids[0], ids[1]]);
+    session.connections.retain(|c| c.serial != serial);
+    let mut out = ids.to_vec();
+    out.extend_from_slice(&[0, 0]);
+    Reply::ok(0x4E, out)
+}
+
+/// Change counters of the project, which never changes while simulated.
+fn change_counters(data: &[u8]) -> Reply {
+    let mut reader = Reader::new(data);
+    let attributes: Option<Vec<u16>> = reader
+        .u16()
+        .and_then(|count| (0..count).map(|_| reader.u16()).collect());
+    let attributes = match attributes {
+        Some(attributes) => attributes,
+        None => return Reply::error(0x03, STATUS_NOT_ENOUGH_DATA),
+    };
+    let mut out = (attributes.len() as u16).to_le_bytes().to_vec();
+    for attribute in attributes {
+        out.extend_from_slice(&attribute.to_le_bytes());
+        out.extend_from_slice(&0u16.to_le_bytes());
+        out.extend_from_slice(&1u32.to_le_bytes());
+    }
+    Reply::ok(0x03, out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn simulator() -> Simulator {
+        let file: SimFile = toml::from_str(
+            r#"
+            [[tag]]
+            name = "PT_101"
+            type = "REAL"
+            value = 12.5
+
+            [[tag]]
+            name = "Counts"
+            type = "DINT[4]"
+            value = [1, 2, 3]
+
+            [[tag]]
+            name = "Level"
+            type = "INT"
+            generate = { kind = "ramp", from = 0, to = 100, period = "10s" }
+            "#,
+        )
+        .unwrap();
+        Simulator::new(&file).unwrap()
+    }
+
+    fn request(service: u8, path: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut out = vec![service, (path.len() / 2) as u8];
+        out.extend_from_slice(path);
+        out.extend_from_slice(data);
+        out
+    }
+
+    #[test]
+    fn tag_services() {
+        let sim = simulator();
+        let mut session = Session::default();
+        let read = request(0x4C, b"\x91\x06PT_101", &[1, 0]);
+        let reply = sim.message(&mut session, &read);
+        assert_eq!(reply.status, 0);
+        assert_eq!(reply.data[..2], [0xCA, 0]);
+        assert_eq!(reply.data[2..], 12.5f32.to_le_bytes());
+
+        // Third element, then by symbol instance.
+        let read = request(0x4C, b"\x91\x06Counts\x28\x02", &[1, 0]);
+        assert_eq!(
+            sim.message(&mut session, &read).data[2..],
+            3i32.to_le_bytes()
+        );
+        let read = request(0x4C, b"\x20\x6B\x24\x02\x28\x03", &[1, 0]);
+        assert_eq!(
+            sim.message(&mut session, &read).data[2..],
+            0i32.to_le_bytes()
+        );
+
+        let mut write = vec![0xC4, 0, 1, 0];
+        write.extend_from_slice(&(-7i32).to_le_bytes());
+        let write = request(0x4D, b"\x91\x06Counts\x28\x03", &write);
+        assert_eq!(sim.message(&mut session, &write).status, 0);
+        let read = request(0x4C, b"\x91\x06Counts\x28\x03", &[1, 0]);
+        assert_eq!(
+            sim.message(&mut session, &read).data[2..],
+            (-7i32).to_le_bytes()
+        );
+
+        let mismatch = request(0x4D, b"\x91\x06PT_101", &[0xC4, 0, 1, 0, 0, 0, 0, 0]);
+        let reply = sim.message(&mut session, &mismatch);
+        assert_eq!(
+            (reply.status, reply.extended),
+            (0xFF, Some(EXT_TYPE_MISMATCH))
+        );
+        let unknown = request(0x4C, b"\x91\x05Valve\x00", &[1, 0]);
+        assert_eq!(
+            sim.message(&mut session, &unknown).status,
+            STATUS_PATH_SEGMENT
+        );
+        let range = request(0x4C, b"\x91\x06Counts\x28\x04", &[1, 0]);
+        assert_eq!(
+            sim.message(&mut session, &range).extended,
+            Some(EXT_OUT_OF_RANGE)
+        );
+
+        // Set bit 3 of Level, clearing the rest of the low byte.
+        let rmw = request(0x4E, b"\x91\x05Level\x00", &[1, 0, 0x08, 0x08]);
+        assert_eq!(sim.message(&mut session, &rmw).status, 0);
+        let read = request(0x4C, b"\x91\x05Level\x00", &[1, 0]);
+        assert_eq!(sim.message(&mut session, &read).data[2], 0x08);
+
+        let list = request(0x55, b"\x20\x6B\x24\x00", &[2, 0, 1, 0, 2, 0]);
+        let reply = sim.message(&mut session, &list);
+        assert_eq!(reply.status, 0);
+        assert_eq!(reply.data[..12], *b"\x01\x00\x00\x00\x06\x00PT_101");
+        assert_eq!(reply.data[12..14], [0xCA, 0]);
+        assert_eq!(reply.data[26..28], [0xC4, 0x20]);
+
+        // Dimensions and external access of Counts, then an attribute the
+        // simulator doesn't have.
+        let attributes = request(0x03, b"\x20\x6B\x24\x02", &[2, 0, 8, 0, 10, 0]);
+        let reply = sim.message(&mut session, &attributes);
+        assert_eq!(reply.status, 0);
+        assert_eq!(reply.data[..6], [2, 0, 8, 0, 0, 0]);
+        assert_eq!(reply.data[6..18], [4, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(reply.data[18..], [10, 0, 0, 0, 0]);
+        let attributes = request(0x03, b"\x20\x6B\x24\x02", &[1, 0, 7, 0]);
+        let reply = sim.message(&mut session, &attributes);
+        assert_eq!(reply.status, 0x0A);
+        assert_eq!(reply.data, [1, 0, 7, 0, 0x14, 0]);
+    }
+
+    #[test]
+    fn strings() {
+        let file: SimFile = toml::from_str(
+            r#"
+            [[tag]]
+            name = "Status"
+            type = "STRING"
+            value = "Ready"
+
+            [[tag]]
+            name = "Batch"
+            type = "STRING20"
+            "#,
+        )
+        .unwrap();
+        let sim = Simulator::new(&file).unwrap();
+        let mut session = Session::default();
+        let read = request(0x4C, b"\x91\x06Status", &[1, 0]);
+        let reply = sim.message(&mut session, &read);
+        assert_eq!(reply.data[..4], [0xA0, 0x02, 0xCE, 0x0F]);
+        assert_eq!(reply.data.len(), 4 + 88);
+        assert_eq!(reply.data[4..13], *b"\x05\0\0\0Ready");
+
+        let mut write = vec![0xA0, 0x02, 0x14, 0x10, 1, 0];
+        write.extend_from_slice(&2i32.to_le_bytes());
+        write.extend_from_slice(b"B7");
+        write.resize(6 + 24, 0);
+        let batch = request(0x4D, b"\x91\x05Batch\x00", &write);
+        assert_eq!(sim.message(&mut session, &batch).status, 0);
+        // STRING data doesn't go into a STRING20.
+        write[2..4].copy_from_slice(&STRING_HANDLE.to_le_bytes());
+        let mismatch = request(0x4D, b"\x91\x05Batch\x00", &write);
+        assert_eq!(
+            sim.message(&mut session, &mismatch).extended,
+            Some(EXT_TYPE_MISMATCH)
+        );
+
+        // The template of STRING20: handle, 2 members, object and structure
+        // sizes, then its definition.
+        let attributes = request(
+            0x03,
+            b"\x20\x6C\x25\x00\x00\x01",
+            &[4, 0, 1, 0, 2, 0, 4, 0, 5, 0],
+        );
+        let reply = sim.message(&mut session, &attributes);
+        assert_eq!(reply.status, 0);
+        assert_eq!(reply.data[6..8], 0x1014u16.to_le_bytes());
+        assert_eq!(reply.data[26..30], 24u32.to_le_bytes());
+        let read = request(0x4C, b"\x20\x6C\x25\x00\x00\x01", &[0, 0, 0, 0, 0xFF, 0]);
+        let reply = sim.message(&mut session, &read);
+        assert_eq!(reply.data[8..10], 20u16.to_le_bytes());
+        assert!(reply.data.ends_with(b"STRING20;n\0LEN\0DATA\0"));
+
+        let file: SimFile = toml::from_str(
+            r#"
+            [[tag]]
+            name = "Batch"
+            type = "STRING4"
+            value = "too long"
+            "#,
+        )
+        .unwrap();
+        assert!(Simulator::new(&file).is_err());
+    }
+
+    #[test]
+    fn programs() {
+        let file: SimFile = toml::from_str(
+            r#"
+            [[tag]]
+            name = "Program:Main.Flow"
+            type = "REAL"
+            value = 3.5
+
+            [[tag]]
+            name = "PT_101"
+            type = "REAL"
+            "#,
+        )
+        .unwrap();
+        let sim = Simulator::new(&file).unwrap();
+        let mut session = Session::default();
+        // The program is listed after the controller tags, its tags within
+        // it by their own name.
+        let list = request(0x55, b" k$\x00", &[1, 0, 1, 0]);
+        let reply = sim.message(&mut session, &list);
+        assert_eq!(reply.data[..12], *b"\x02\x00\x00\x00\x06\x00PT_101");
+        assert_eq!(reply.data[12..], *b"\x03\x00\x00\x00\x0C\x00Program:Main");
+        let list = request(0x55, b"\x91\x0CProgram:Main\x20\x6B\x24\x00", &[1, 0, 1, 0]);
+        let reply = sim.message(&mut session, &list);
+        assert_eq!(reply.data, *b"\x01\x00\x00\x00\x04\x00Flow");
+        let list = request(0x55, b"\x91\x0BProgram:Aux\x00\x20\x6B\x24\x00", &[1, 0]);
+        assert_eq!(sim.message(&mut session, &list).status, STATUS_PATH_UNKNOWN);
+
+        let read = request(0x4C, b"\x91\x0CProgram:Main\x91\x04Flow", &[1, 0]);
+        let reply = sim.message(&mut session, &read);
+        assert_eq!(reply.data[2..], 3.5f32.to_le_bytes());
+        let read = request(0x4C, b"\x91\x04Flow", &[1, 0]);
+        assert_eq!(sim.message(&mut session, &read).status, STATUS_PATH_SEGMENT);
+    }
+
+    #[test]
+    fn external_access() {
+        let file: SimFile = toml::from_str(
+            r#"
+            [[tag]]
+            name = "Recipe_Max"
+            type = "DINT"
+            constant = true
+
+            [[tag]]
+            name = "Hidden"
+            type = "DINT"
+            external_access = "none"
+            "#,
+        )
+        .unwrap();
+        let sim = Simulator::new(&file).unwrap();
+        let mut session = Session::default();
+        let read = request(0x4C, b"\x91\x0ARecipe_Max", &[1, 0]);
+        assert_eq!(sim.message(&mut session, &read).status, 0);
+        let write = request(0x4D, b"\x91\x0ARecipe_Max", &[0xC4, 0, 1, 0, 7, 0, 0, 0]);
+        assert_eq!(sim.message(&mut session, &write).status, 0x0F);
+        let read = request(0x4C, b"\x91\x06Hidden", &[1, 0]);
+        assert_eq!(sim.message(&mut session, &read).status, 0x0F);
+
+        // Hidden tags aren't listed.
+        let list = request(0x55, b"\x20\x6B\x24\x00", &[1, 0, 1, 0]);
+        let reply = sim.message(&mut session, &list);
+        assert_eq!(reply.data, b"\x01\x00\x00\x00\x0A\x00Recipe_Max");
+    }
+
+    #[test]
+    fn generators() {
+        let now = Instant::now();
+        let mut state = GeneratorState {
+            started: now,
+            walk: 5.0,
+            stepped: now,
+            seed: 1,
+        };
+        let later = state.started + Duration::from_secs(15);
+        let ramp = Generator::Ramp {
+            from: 0.0,
+            to: 100.0,
+            period: Duration::from_secs(10),
+        };
+        assert!((generate(&ramp, &mut state, later) - 50.0).abs() < 1e-9);
+        let sine = Generator::Sine {
+            min: 10.0,
+            max: 30.0,
+            period: Duration::from_secs(60),
+        };
+        assert!((generate(&sine, &mut state, later) - 30.0).abs() < 1e-9);
+        let walk = Generator::RandomWalk {
+            start: 5.0,
+            step: 1.0,
+            min: 0.0,
+            max: 6.0,
+        };
+        let value = generate(&walk, &mut state, later);
+        assert!((0.0..=6.0).contains(&value) && value != 5.0);
+        assert_eq!(state.stepped, later);
+
+        let file: Result<SimFile, _> = toml::from_str(
+            r#"
+            [[tag]]
+            name = "Flow"
+            type = "REAL"
+            generate = { kind = "sine", min = 0, max = 1, period = "1m", phase = 2 }
+            "#,
+        );
+        assert!(file.is_err());
+    }
+}
@@ -0,0 +1,887 @@
+use crate::plc::Plc;
+use crate::service;
+use crate::settings::Access;
+use crate::tags::TagList;
+use crate::value::{self, PlcValue};
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use std::fmt::{Display, Write};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Base of the tag objects when none is given: the playground of the
+/// Net-SNMP enterprise, meant for objects without a registered number.
+const DEFAULT_OID: &str = "1.3.6.1.4.1.8072.9999.9999";
+
+/// Objects of the `system` group answered besides the tags.
+const SYS_DESCR: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 1, 0];
+const SYS_OBJECT_ID: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 2, 0];
+const SYS_UP_TIME: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 3, 0];
+const SYS_NAME: [u32; 9] = [1, 3, 6, 1, 2, 1, 1, 5, 0];
+
+/// SNMPv2c, as the version field of messages tells it.
+const VERSION_2C: i64 = 1;
+
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_NULL: u8 = 0x05;
+const TAG_OID: u8 = 0x06;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_GAUGE32: u8 = 0x42;
+const TAG_TIME_TICKS: u8 = 0x43;
+const TAG_NO_SUCH_OBJECT: u8 = 0x80;
+const TAG_NO_SUCH_INSTANCE: u8 = 0x81;
+const TAG_END_OF_MIB_VIEW: u8 = 0x82;
+
+const PDU_GET: u8 = 0xA0;
+const PDU_GET_NEXT: u8 = 0xA1;
+const PDU_RESPONSE: u8 = 0xA2;
+const PDU_SET: u8 = 0xA3;
+const PDU_GET_BULK: u8 = 0xA5;
+
+const ERROR_GEN_ERR: i64 = 5;
+const ERROR_NOT_WRITABLE: i64 = 17;
+
+/// Options of the `snmp` command.
+#[derive(Args)]
+pub struct SnmpArgs {
+    /// Address managers send their requests to.
+    #[arg(long, default_value = "0.0.0.0:161")]
+    pub listen: SocketAddr,
+    /// Tag list file selecting the tags served, numbered from 1 in its
+    /// order under the base OID.
+    #[arg(long)]
+    pub tags: PathBuf,
+    /// Community managers must give.
+    #[arg(long, default_value = "public")]
+    pub community: String,
+    /// Object identifier the tags are served under, also reported as the
+    /// sysObjectID of the agent.
+    #[arg(long, default_value = DEFAULT_OID)]
+    pub oid: Oid,
+    /// Write the MIB module of the tags to this file, for managers to load.
+    #[arg(long)]
+    pub mib: Option<PathBuf>,
+    /// Polling interval of the tags in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub interval: u64,
+}
+
+/// An object identifier, like `1.3.6.1.2.1.1.1.0`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Oid(Vec<u32>);
+
+impl Oid {
+    fn child(&self, arcs: &[u32]) -> Oid {
+        Oid([&self.0[..], arcs].concat())
+    }
+}
+
+impl FromStr for Oid {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let arcs: Vec<u32> = s
+            .trim_start_matches('.')
+            .split('.')
+            .map(|arc| arc.parse())
+            .collect::<Result<_, _>>()
+            .with_context(|| format!("invalid object identifier `{}`", s))?;
+        if arcs.len() < 2 || arcs[0] > 2 || (arcs[0] < 2 && arcs[1] > 39) {
+            bail!("invalid object identifier `{}`", s);
+        }
+        Ok(Oid(arcs))
+    }
+}
+
+impl Display for Oid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let arcs: Vec<String> = self.0.iter().map(u32::to_string).collect();
+        f.write_str(&arcs.join("."))
+    }
+}
+
+/// How the value of a tag is served, picked from its type when the agent
+/// starts so it matches the MIB.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Syntax {
+    /// Signed integers, BOOL as 0 and 1, and the accumulator or position of
+    /// timers, counters and controls.
+    Integer,
+    /// Unsigned 32-bit integers.
+    Gauge,
+    /// Everything else, REAL included, as text since SNMP has no floating
+    /// point type.
+    Text,
+}
+
+impl Syntax {
+    fn of(value: &PlcValue) -> Self {
+        match value {
+            PlcValue::Bool(_)
+            | PlcValue::Sint(_)
+            | PlcValue::Int(_)
+            | PlcValue::Dint(_)
+            | PlcValue::Usint(_)
+            | PlcValue::Uint(_)
+            | PlcValue::Timer(_)
+            | PlcValue::Counter(_)
+            | PlcValue::Control(_) => Syntax::Integer,
+            PlcValue::Udint(_) | PlcValue::Dword(_) => Syntax::Gauge,
+            _ => Syntax::Text,
+        }
+    }
+
+    fn mib(self) -> &'static str {
+        match self {
+            Syntax::Integer => "Integer32",
+            Syntax::Gauge => "Gauge32",
+            Syntax::Text => "DisplayString",
+        }
+    }
+
+    fn value(self, value: &PlcValue) -> Value {
+        match (self, value) {
+            (Syntax::Integer, PlcValue::Bool(v)) => Value::Integer(*v as i64),
+            (Syntax::Integer, value) => Value::Integer(value.as_f64() as i64),
+            (Syntax::Gauge, PlcValue::Udint(v) | PlcValue::Dword(v)) => Value::Gauge(*v),
+            (Syntax::Gauge, _) => Value::NoSuchInstance,
+            (Syntax::Text, value) => Value::Octets(value.to_string().into_bytes()),
+        }
+    }
+}
+
+/// A tag served as a scalar object.
+#[derive(Debug, Clone)]
+struct Object {
+    tag: String,
+    /// Name of the object in the MIB.
+    descriptor: String,
+    /// Object identifier, the instance being this with `.0`.
+    oid: Oid,
+    syntax: Syntax,
+}
+
+/// The object descriptors of the tags: `tag` and the parts of the tag
+/// name, like `tagPT101` or `tagProgramMainFlow`, made unique by the
+/// number of the object.
+fn descriptors(tags: &[String]) -> Vec<String> {
+    let mut descriptors: Vec<String> = Vec::with_capacity(tags.len());
+    for (index, tag) in tags.iter().enumerate() {
+        let mut descriptor = String::from("tag");
+        for part in tag.split(|c: char| !c.is_ascii_alphanumeric()) {
+            let mut chars = part.chars();
+            if let Some(first) = chars.next() {
+                descriptor.push(first.to_ascii_uppercase());
+                descriptor.extend(chars);
+            }
+        }
+        descriptor.truncate(56);
+        if descriptors.contains(&descriptor) {
+            write!(descriptor, "X{}", index + 1).unwrap();
+        }
+        descriptors.push(descriptor);
+    }
+    descriptors
+}
+
+/// The MIB module of the tags, one read-only scalar each.
+fn mib(base: &Oid, objects: &[Object], plc: &str) -> String {
+    let mut text = String::new();
+    writeln!(text, "COBALT-TAGS-MIB DEFINITIONS ::= BEGIN\n").unwrap();
+    writeln!(text, "IMPORTS").unwrap();
+    writeln!(
+        text,
+        "    MODULE-IDENTITY, OBJECT-TYPE, Integer32, Gauge32 FROM SNMPv2-SMI"
+    )
+    .unwrap();
+    writeln!(text, "    DisplayString FROM SNMPv2-TC;\n").unwrap();
+    writeln!(text, "cobaltTags MODULE-IDENTITY").unwrap();
+    writeln!(
+        text,
+        "    LAST-UPDATED \"{}Z\"",
+        chrono::Utc::now().format("%Y%m%d%H%M")
+    )
+    .unwrap();
+    writeln!(text, "    ORGANIZATION \"cobalt\"").unwrap();
+    writeln!(text, "    CONTACT-INFO \"Generated by cobalt snmp\"").unwrap();
+    writeln!(
+        text,
+        "    DESCRIPTION \"Tags of {} served by cobalt.\"",
+        plc
+    )
+    .unwrap();
+    let root = ["ccitt", "iso", "joint-iso-ccitt"][base.0[0] as usize];
+    let arcs: Vec<String> = base.0[1..].iter().map(u32::to_string).collect();
+    writeln!(text, "    ::= {{ {} {} }}", root, arcs.join(" ")).unwrap();
+    for object in objects {
+        writeln!(text, "\n{} OBJECT-TYPE", object.descriptor).unwrap();
+        writeln!(text, "    SYNTAX      {}", object.syntax.mib()).unwrap();
+        writeln!(text, "    MAX-ACCESS  read-only").unwrap();
+        writeln!(text, "    STATUS      current").unwrap();
+        writeln!(
+            text,
+            "    DESCRIPTION \"Tag {}\"",
+            object.tag.replace('"', "'")
+        )
+        .unwrap();
+        writeln!(
+            text,
+            "    ::= {{ cobaltTags {} }}",
+            object.oid.0.last().unwrap()
+        )
+        .unwrap();
+    }
+    writeln!(text, "\nEND").unwrap();
+    text
+}
+
+/// Value of a variable binding.
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Integer(i64),
+    Gauge(u32),
+    Octets(Vec<u8>),
+    Oid(Oid),
+    TimeTicks(u32),
+    /// The value of bindings that aren't answered.
+    Null,
+    NoSuchObject,
+    NoSuchInstance,
+    EndOfMibView,
+}
+
+impl Value {
+    fn encode(&self, out: &mut Vec<u8>) {
+        match self {
+            Value::Integer(v) => put(out, TAG_INTEGER, &integer(*v)),
+            Value::Gauge(v) => put(out, TAG_GAUGE32, &unsigned(*v)),
+            Value::Octets(v) => put(out, TAG_OCTET_STRING, v),
+            Value::Oid(v) => put(out, TAG_OID, &oid(v)),
+            Value::TimeTicks(v) => put(out, TAG_TIME_TICKS, &unsigned(*v)),
+            Value::Null => put(out, TAG_NULL, &[]),
+            Value::NoSuchObject => put(out, TAG_NO_SUCH_OBJECT, &[]),
+            Value::NoSuchInstance => put(out, TAG_NO_SUCH_INSTANCE, &[]),
+            Value::EndOfMibView => put(out, TAG_END_OF_MIB_VIEW, &[]),
+        }
+    }
+}
+
+/// Append a BER element.
+fn put(out: &mut Vec<u8>, tag: u8, content: &[u8]) {
+    out.push(tag);
+    let len = content.len();
+    if len < 0x80 {
+        out.push(len as u8);
+    } else {
+        let bytes = len.to_be_bytes();
+        let skip = bytes.iter().take_while(|&&b| b == 0).count();
+        out.push(0x80 | (bytes.len() - skip) as u8);
+        out.extend_from_slice(&bytes[skip..]);
+    }
+    out.extend_from_slice(content);
+}
+
+/// Shortest two's complement bytes of an integer.
+fn integer(value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    while start < 7 {
+        let (byte, next) = (bytes[start], bytes[start + 1]);
+        if (byte == 0 && next & 0x80 == 0) || (byte == 0xFF && next & 0x80 != 0) {
+            start += 1;
+        } else {
+            break;
+        }
+    }
+    bytes[start..].to_vec()
+}
+
+fn unsigned(value: u32) -> Vec<u8> {
+    integer(value as i64)
+}
+
+fn oid(oid: &Oid) -> Vec<u8> {
+    let mut out = vec![(oid.0[0] * 40 + oid.0[1]) as u8];
+    for &arc in &oid.0[2..] {
+        let mut bytes = vec![(arc & 0x7F) as u8];
+        let mut rest = arc >> 7;
+        while rest > 0 {
+            bytes.push((rest & 0x7F) as u8 | 0x80);
+            rest >>= 7;
+        }
+        out.extend(bytes.iter().rev());
+    }
+    out
+}
+
+/// Reads the BER elements of a message, `None` standing for malformed data.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Tag and content of the next element.
+    fn element(&mut self) -> Option<(u8, &'a [u8])> {
+        let (&tag, rest) = self.0.split_first()?;
+        let (&first, mut rest) = rest.split_first()?;
+        let len = if first < 0x80 {
+            first as usize
+        } else {
+            let count = (first & 0x7F) as usize;
+            if count == 0 || count > 4 || rest.len() < count {
+                return None;
+            }
+            let len = rest[..count]
+                .iter()
+                .fold(0, |len, &b| len << 8 | b as usize);
+            rest = &rest[count..];
+            len
+        };
+        if rest.len() < len {
+            return None;
+        }
+        self.0 = &rest[len..];
+        Some((tag, &rest[..len]))
+    }
+
+    fn expect(&mut self, tag: u8) -> Option<&'a [u8]> {
+        match self.element()? {
+            (found, content) if found == tag => Some(content),
+            _ => None,
+        }
+    }
+
+    fn integer(&mut self) -> Option<i64> {
+        let content = self.expect(TAG_INTEGER)?;
+        if content.is_empty() || content.len() > 8 {
+            return None;
+        }
+        let sign = if content[0] & 0x80 != 0 { -1 } else { 0 };
+        Some(content.iter().fold(sign, |v, &b| v << 8 | b as i64))
+    }
+
+    fn oid(&mut self) -> Option<Oid> {
+        let content = self.expect(TAG_OID)?;
+        let (&first, rest) = content.split_first()?;
+        let mut arcs = match first {
+            0..=39 => vec![0, first as u32],
+            40..=79 => vec![1, first as u32 - 40],
+            _ => vec![2, first as u32 - 80],
+        };
+        let mut arc: u32 = 0;
+        for &b in rest {
+            arc = arc.checked_mul(128)? | (b & 0x7F) as u32;
+            if b & 0x80 == 0 {
+                arcs.push(arc);
+                arc = 0;
+            }
+        }
+        if rest.last().is_some_and(|b| b & 0x80 != 0) {
+            return None;
+        }
+        Some(Oid(arcs))
+    }
+}
+
+/// A request of a manager.
+#[derive(Debug, PartialEq)]
+struct Request {
+    community: Vec<u8>,
+    pdu: u8,
+    id: i64,
+    oids: Vec<Oid>,
+}
+
+fn parse_request(data: &[u8]) -> Option<Request> {
+    let mut message = Reader(Reader(data).expect(TAG_SEQUENCE)?);
+    if message.integer()? != VERSION_2C {
+        return None;
+    }
+    let community = message.expect(TAG_OCTET_STRING)?.to_vec();
+    let (pdu, content) = message.element()?;
+    let mut fields = Reader(content);
+    let id = fields.integer()?;
+    // Error status and index, or the repetitions of GetBulk.
+    fields.integer()?;
+    fields.integer()?;
+    let mut bindings = Reader(fields.expect(TAG_SEQUENCE)?);
+    let mut oids = Vec::new();
+    while !bindings.0.is_empty() {
+        let mut binding = Reader(bindings.expect(TAG_SEQUENCE)?);
+        oids.push(binding.oid()?);
+    }
+    Some(Request {
+        community,
+        pdu,
+        id,
+        oids,
+    })
+}
+
+fn encode_response(
+    community: &[u8],
+    id: i64,
+    error: (i64, usize),
+    bindings: &[(Oid, Value)],
+) -> Vec<u8> {
+    let mut list = Vec::new();
+    for (name, value) in bindings {
+        let mut binding = Vec::new();
+        put(&mut binding, TAG_OID, &oid(name));
+        value.encode(&mut binding);
+        put(&mut list, TAG_SEQUENCE, &binding);
+    }
+    let mut pdu = Vec::new();
+    put(&mut pdu, TAG_INTEGER, &integer(id));
+    put(&mut pdu, TAG_INTEGER, &integer(error.0));
+    put(&mut pdu, TAG_INTEGER, &integer(error.1 as i64));
+    put(&mut pdu, TAG_SEQUENCE, &list);
+    let mut message = Vec::new();
+    put(&mut message, TAG_INTEGER, &integer(VERSION_2C));
+    put(&mut message, TAG_OCTET_STRING, community);
+    put(&mut message, PDU_RESPONSE, &pdu);
+    let mut out = Vec::new();
+    put(&mut out, TAG_SEQUENCE, &message);
+    out
+}
+
+/// What an instance answered by the agent holds.
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Descr,
+    ObjectId,
+    UpTime,
+    Name,
+    Tag(usize),
+}
+
+/// Answers the requests of managers from the values polled last.
+struct Agent {
+    community: Vec<u8>,
+    base: Oid,
+    plc: String,
+    /// Instances served, in the order of their identifiers.
+    instances: Vec<(Oid, Source)>,
+    objects: Vec<Object>,
+    /// Value polled last of each tag, none when it couldn't be read.
+    values: Arc<Mutex<Vec<Option<PlcValue>>>>,
+    started: Instant,
+}
+
+impl Agent {
+    fn new(
+        community: &str,
+        base: Oid,
+        plc: &str,
+        objects: Vec<Object>,
+        values: Arc<Mutex<Vec<Option<PlcValue>>>>,
+    ) -> Self {
+        let mut instances = vec![
+            (Oid(SYS_DESCR.to_vec()), Source::Descr),
+            (Oid(SYS_OBJECT_ID.to_vec()), Source::ObjectId),
+            (Oid(SYS_UP_TIME.to_vec()), Source::UpTime),
+            (Oid(SYS_NAME.to_vec()), Source::Name),
+        ];
+        for (index, object) in objects.iter().enumerate() {
+            instances.push((object.oid.child(&[0]), Source::Tag(index)));
+        }
+        instances.sort_by(|a, b| a.0.cmp(&b.0));
+        Self {
+            community: community.as_bytes().to_vec(),
+            base,
+            plc: plc.to_owned(),
+            instances,
+            objects,
+            values,
+            started: Instant::now(),
+        }
+    }
+
+    /// The response to a request, none for messages that aren't answered:
+    /// malformed ones, other versions and wrong communities.
+    fn respond(&self, data: &[u8]) -> Option<Vec<u8>> {
+        let request = parse_request(data)?;
+        if request.community != self.community {
+            tracing::debug!("ignored a request with another community");
+            return None;
+        }
+        let mut error = (0, 0);
+        let bindings: Vec<(Oid, Value)> = match request.pdu {
+            PDU_GET => request
+                .oids
+                .into_iter()
+                .map(|oid| {
+                    let value = self.get(&oid);
+                    (oid, value)
+                })
+                .collect(),
+            PDU_GET_NEXT => request
+                .oids
+                .into_iter()
+                .map(|oid| self.next(&oid).unwrap_or((oid, Value::EndOfMibView)))
+                .collect(),
+            PDU_SET => {
+                error = (ERROR_NOT_WRITABLE, 1);
+                request
+                    .oids
+                    .into_iter()
+                    .map(|oid| (oid, Value::Null))
+                    .collect()
+            }
+            PDU_GET_BULK => {
+                error = (ERROR_GEN_ERR, 0);
+                request
+                    .oids
+                    .into_iter()
+                    .map(|oid| (oid, Value::Null))
+                    .collect()
+            }
+            _ => return None,
+        };
+        Some(encode_response(
+            &self.community,
+            request.id,
+            error,
+            &bindings,
+        ))
+    }
+
+    fn get(&self, oid: &Oid) -> Value {
+        match self.instances.iter().find(|(instance, _)| instance == oid) {
+            Some((_, source)) => self.value(*source),
+            // The object exists, not this instance of it.
+            None if self
+                .instances
+                .iter()
+                .any(|(instance, _)| oid.0.starts_with(&instance.0[..instance.0.len() - 1])) =>
+            {
+                Value::NoSuchInstance
+            }
+            None => Value::NoSuchObject,
+        }
+    }
+
+    fn next(&self, oid: &Oid) -> Option<(Oid, Value)> {
+        self.instances
+            .iter()
+            .find(|(instance, _)| instance > oid)
+            .map(|(instance, source)| (instance.clone(), self.value(*source)))
+    }
+
+    fn value(&self, source: Source) -> Value {
+        match source {
+            Source::Descr => Value::Octets(
+                format!(
+                    "cobalt {} agent for {}",
+                    env!("CARGO_PKG_VERSION"),
+                    self.plc
+                )
+                .into_bytes(),
+            ),
+            Source::ObjectId => Value::Oid(self.base.clone()),
+            Source::UpTime => {
+                let ticks = self.started.elapsed().as_millis() / 10;
+                Value::TimeTicks(ticks as u32)
+            }
+            Source::Name => Value::Octets(self.plc.clone().into_bytes()),
+            Source::Tag(index) => {
+                let values = self.values.lock().unwrap();
+                match &values[index] {
+                    Some(value) => self.objects[index].syntax.value(value),
+                    None => Value::NoSuchInstance,
+                }
+            }
+        }
+    }
+}
+
+/// Serve the tags of the tag list as the scalars of an SNMPv2c agent. The
+/// tags are polled every interval and managers get the values polled last,
+/// or noSuchInstance for tags that couldn't be read. Every tag must be
+/// readable by managers under the access lists of the settings.
+pub async fn run(client: &mut Plc, args: &SnmpArgs, access: &Access) -> Result<()> {
+    let list = TagList::load(&args.tags)?;
+    if list.tags.is_empty() {
+        bail!("no [[tag]] in {}", args.tags.display());
+    }
+    let tags: Vec<String> = list.tags.iter().map(|tag| tag.name.clone()).collect();
+    access.check_readable(tags.iter().map(String::as_str))?;
+
+    let socket = UdpSocket::bind(args.listen)
+        .await
+        .with_context(|| format!("failed to listen on {}", args.listen))?;
+
+    // The syntax of each object follows the type of the tag, as read now.
+    let first = value::read_values(client, &tags).await;
+    let mut objects = Vec::with_capacity(tags.len());
+    for (index, ((tag, descriptor), result)) in
+        tags.iter().zip(descriptors(&tags)).zip(&first).enumerate()
+    {
+        let syntax = match result {
+            Ok(value) => Syntax::of(value),
+            Err(e) => {
+                tracing::warn!("failed to read {}, serving it as text: {:#}", tag, e);
+                Syntax::Text
+            }
+        };
+        let object = Object {
+            tag: tag.clone(),
+            descriptor,
+            oid: args.oid.child(&[index as u32 + 1]),
+            syntax,
+        };
+        tracing::info!("{}.0 {} {}", object.oid, object.syntax.mib(), tag);
+        objects.push(object);
+    }
+    if let Some(path) = &args.mib {
+        std::fs::write(path, mib(&args.oid, &objects, client.address()))
+            .with_context(|| format!("failed to write {}", path.display()))?;
+    }
+    let values = Arc::new(Mutex::new(
+        first.into_iter().map(Result::ok).collect::<Vec<_>>(),
+    ));
+
+    let agent = Agent::new(
+        &args.community,
+        args.oid.clone(),
+        client.address(),
+        objects,
+        values.clone(),
+    );
+    tracing::info!(
+        "serving {} tags under {} on {}",
+        tags.len(),
+        args.oid,
+        args.listen
+    );
+    let answering = tokio::spawn(async move {
+        let mut buffer = vec![0; 65535];
+        loop {
+            let (len, peer) = match socket.recv_from(&mut buffer).await {
+                Ok(received) => received,
+                Err(e) => {
+                    tracing::warn!("failed to receive a request: {}", e);
+                    continue;
+                }
+            };
+            let Some(response) = agent.respond(&buffer[..len]) else {
+                continue;
+            };
+            if let Err(e) = socket.send_to(&response, peer).await {
+                tracing::warn!("failed to answer {}: {}", peer, e);
+            }
+        }
+    });
+
+    let interval = Duration::from_millis(args.interval.max(1));
+    loop {
+        tokio::select! {
+            _ = tokio::time::sleep(interval) => {}
+            _ = service::stopped() => break,
+        }
+        let results = value::read_values(client, &tags).await;
+        if results.iter().any(Result::is_err) {
+            // Start over with a new session on the next poll.
+            let _ = client.close().await;
+        }
+        let mut values = values.lock().unwrap();
+        for ((tag, value), result) in tags.iter().zip(values.iter_mut()).zip(results) {
+            match result {
+                Ok(new) => *value = Some(new),
+                Err(e) => {
+                    if value.is_some() {
+                        tracing::warn!("failed to read {}: {:#}", tag, e);
+                    }
+                    *value = None;
+                }
+            }
+        }
+    }
+    answering.abort();
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A GetRequest of `public` for the given names, as snmpget sends it.
+    fn get(pdu: u8, names: &[&str]) -> Vec<u8> {
+        let mut list = Vec::new();
+        for name in names {
+            let mut binding = Vec::new();
+            put(&mut binding, TAG_OID, &oid(&name.parse().unwrap()));
+            put(&mut binding, TAG_NULL, &[]);
+            put(&mut list, TAG_SEQUENCE, &binding);
+        }
+        let mut fields = vec![0x02, 0x02, 0x12, 0x34, 0x02, 0x01, 0x00, 0x02, 0x01, 0x00];
+        put(&mut fields, TAG_SEQUENCE, &list);
+        let mut message = vec![0x02, 0x01, 0x01];
+        put(&mut message, TAG_OCTET_STRING, b"public");
+        put(&mut message, pdu, &fields);
+        let mut out = Vec::new();
+        put(&mut out, TAG_SEQUENCE, &message);
+        out
+    }
+
+    /// The error status and the bindings of a response.
+    fn bindings(response: &[u8]) -> (i64, Vec<(String, u8, Vec<u8>)>) {
+        let mut message = Reader(Reader(response).expect(TAG_SEQUENCE).unwrap());
+        message.integer().unwrap();
+        message.expect(TAG_OCTET_STRING).unwrap();
+        let mut fields = Reader(message.expect(PDU_RESPONSE).unwrap());
+        assert_eq!(fields.integer(), Some(0x1234));
+        let status = fields.integer().unwrap();
+        fields.integer().unwrap();
+        let mut list = Reader(fields.expect(TAG_SEQUENCE).unwrap());
+        let mut bindings = Vec::new();
+        while !list.0.is_empty() {
+            let mut binding = Reader(list.expect(TAG_SEQUENCE).unwrap());
+            let name = binding.oid().unwrap().to_string();
+            let (tag, content) = binding.element().unwrap();
+            bindings.push((name, tag, content.to_vec()));
+        }
+        (status, bindings)
+    }
+
+    fn agent() -> Agent {
+        let tags = ["PT_101", "Program:Main.Count", "Faults"].map(str::to_owned);
+        let values = [
+            PlcValue::Real(12.5),
+            PlcValue::Dint(-129),
+            PlcValue::Udint(0x8000_0000),
+        ];
+        let base: Oid = DEFAULT_OID.parse().unwrap();
+        let objects = tags
+            .iter()
+            .zip(descriptors(&tags))
+            .zip(&values)
+            .enumerate()
+            .map(|(index, ((tag, descriptor), value))| Object {
+                tag: tag.clone(),
+                descriptor,
+                oid: base.child(&[index as u32 + 1]),
+                syntax: Syntax::of(value),
+            })
+            .collect();
+        let mut values = values.map(Some).to_vec();
+        values[2] = None;
+        Agent::new(
+            "public",
+            base,
+            "10.0.0.5",
+            objects,
+            Arc::new(Mutex::new(values)),
+        )
+    }
+
+    #[test]
+    fn encoding() {
+        assert_eq!(integer(0), [0x00]);
+        assert_eq!(integer(128), [0x00, 0x80]);
+        assert_eq!(integer(-129), [0xFF, 0x7F]);
+        let base: Oid = ".1.3.6.1.4.1.8072".parse().unwrap();
+        assert_eq!(oid(&base), [0x2B, 0x06, 0x01, 0x04, 0x01, 0xBF, 0x08]);
+        assert!("1.3.x".parse::<Oid>().is_err());
+        assert!("1.40".parse::<Oid>().is_err());
+
+        let request = get(PDU_GET, &["1.3.6.1.2.1.1.5.0"]);
+        assert_eq!(
+            request[..13],
+            [0x30, 0x27, 0x02, 0x01, 0x01, 0x04, 0x06, b'p', b'u', b'b', b'l', b'i', b'c']
+        );
+        assert_eq!(
+            parse_request(&request),
+            Some(Request {
+                community: b"public".to_vec(),
+                pdu: PDU_GET,
+                id: 0x1234,
+                oids: vec!["1.3.6.1.2.1.1.5.0".parse().unwrap()],
+            })
+        );
+        assert_eq!(parse_request(&request[..20]), None);
+    }
+
+    #[test]
+    fn requests() {
+        let agent = agent();
+        let base = DEFAULT_OID;
+        let (status, values) = bindings(
+            &agent
+                .respond(&get(
+                    PDU_GET,
+                    &[
+                        "1.3.6.1.2.1.1.5.0",
+                        &format!("{}.1.0", base),
+                        &format!("{}.2.0", base),
+                        &format!("{}.3.0", base),
+                        &format!("{}.2", base),
+                        "1.3.6.1.2.1.2.1.0",
+                    ],
+                ))
+                .unwrap(),
+        );
+        assert_eq!(status, 0);
+        assert_eq!(values[0].1, TAG_OCTET_STRING);
+        assert_eq!(values[0].2, b"10.0.0.5");
+        assert_eq!(values[1].2, b"12.5");
+        assert_eq!(
+            (values[2].1, &values[2].2[..]),
+            (TAG_INTEGER, &[0xFF, 0x7F][..])
+        );
+        // Not read, and not an instance.
+        assert_eq!(values[3].1, TAG_NO_SUCH_INSTANCE);
+        assert_eq!(values[4].1, TAG_NO_SUCH_INSTANCE);
+        assert_eq!(values[5].1, TAG_NO_SUCH_OBJECT);
+
+        let (_, values) = bindings(
+            &agent
+                .respond(&get(PDU_GET_NEXT, &["1.3.6.1.2.1.1.9", base]))
+                .unwrap(),
+        );
+        assert_eq!(values[0].0, format!("{}.1.0", base));
+        assert_eq!(values[1].0, format!("{}.1.0", base));
+        let (_, values) = bindings(
+            &agent
+                .respond(&get(PDU_GET_NEXT, &[&format!("{}.3.0", base)]))
+                .unwrap(),
+        );
+        assert_eq!(values[0].1, TAG_END_OF_MIB_VIEW);
+
+        let (status, _) = bindings(
+            &agent
+                .respond(&get(PDU_SET, &[&format!("{}.1.0", base)]))
+                .unwrap(),
+        );
+        assert_eq!(status, ERROR_NOT_WRITABLE);
+        let mut request = get(PDU_GET, &["1.3.6.1.2.1.1.5.0"]);
+        request[7] = b'P';
+        assert_eq!(agent.respond(&request), None);
+    }
+
+    #[test]
+    fn mib_module() {
+        let agent = agent();
+        assert_eq!(
+            agent
+                .objects
+                .iter()
+                .map(|object| object.descriptor.as_str())
+                .collect::<Vec<_>>(),
+            ["tagPT101", "tagProgramMainCount", "tagFaults"]
+        );
+        assert_eq!(
+            descriptors(&["PT_101".to_owned(), "PT.101".to_owned()]),
+            ["tagPT101", "tagPT101X2"]
+        );
+        let text = mib(&agent.base, &agent.objects, "10.0.0.5");
+        assert!(text.contains("    ::= { iso 3 6 1 4 1 8072 9999 9999 }\n"));
+        assert!(text.contains(
+            "tagProgramMainCount OBJECT-TYPE\n    SYNTAX      Integer32\n    MAX-ACCESS  read-only\n"
+        ));
+        assert!(text.contains("    DESCRIPTION \"Tag Faults\"\n    ::= { cobaltTags 3 }\n"));
+    }
+}
@@ -0,0 +1,304 @@
+//! Sparkplug B payload encoding.
+//!
+//! Only the subset of `sparkplug_b.proto` needed by an edge node is
+//! implemented: the payload envelope and scalar metrics. Messages are encoded
+//! by hand to avoid pulling in a protobuf toolchain.
+
+use crate::value::PlcValue;
+
+/// Topic namespace of Sparkplug B.
+pub const NAMESPACE: &str = "spBv1.0";
+
+/// Metric used by host applications to request a rebirth.
+pub const REBIRTH_METRIC: &str = "Node Control/Rebirth";
+
+/// Sparkplug B metric data types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataType {
+    Int8 = 1,
+    Int16 = 2,
+    Int32 = 3,
+    Int64 = 4,
+    UInt8 = 5,
+    UInt16 = 6,
+    UInt32 = 7,
+    UInt64 = 8,
+    Float = 9,
+    Double = 10,
+    Boolean = 11,
+    String = 12,
+}
+
+/// Value carried by a metric.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MetricValue {
+    Int(u32),
+    Long(u64),
+    Float(f32),
+    Double(f64),
+    Boolean(bool),
+    String(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metric {
+    pub name: String,
+    pub timestamp: u64,
+    pub datatype: DataType,
+    pub value: MetricValue,
+}
+
+impl Metric {
+    /// Build the metrics of a PLC value, mapping the CIP type to its
+    /// Sparkplug counterpart. Timers, counters and controls have none, so
+    /// each of their members becomes a metric, named like `T_101/ACC`.
+    pub fn from_value(name: &str, timestamp: u64, value: PlcValue) -> Vec<Self> {
+        let (datatype, value) = match value {
+            PlcValue::Bool(v) => (DataType::Boolean, MetricValue::Boolean(v)),
+            PlcValue::Sint(v) => (DataType::Int8, MetricValue::Int(v as i32 as u32)),
+            PlcValue::Int(v) => (DataType::Int16, MetricValue::Int(v as i32 as u32)),
+            PlcValue::Dint(v) => (DataType::Int32, MetricValue::Int(v as u32)),
+            PlcValue::Lint(v) => (DataType::Int64, MetricValue::Long(v as u64)),
+            PlcValue::Dword(v) => (DataType::UInt32, MetricValue::Int(v)),
+            PlcValue::Usint(v) => (DataType::UInt8, MetricValue::Int(v as u32)),
+            PlcValue::Uint(v) => (DataType::UInt16, MetricValue::Int(v as u32)),
+            PlcValue::Udint(v) => (DataType::UInt32, MetricValue::Int(v)),
+            PlcValue::Ulint(v) => (DataType::UInt64, MetricValue::Long(v)),
+            PlcValue::Real(v) => (DataType::Float, MetricValue::Float(v)),
+            PlcValue::Lreal(v) => (DataType::Double, MetricValue::Double(v)),
+            PlcValue::String(v) => (DataType::String, MetricValue::String(v.text)),
+            PlcValue::Timer(_) | PlcValue::Counter(_) | PlcValue::Control(_) => {
+                return value
+                    .members()
+                    .into_iter()
+                    .flat_map(|(member, value)| {
+                        Self::from_value(&format!("{}/{}", name, member), timestamp, value)
+                    })
+                    .collect();
+            }
+        };
+        vec![Self {
+            name: name.to_owned(),
+            timestamp,
+            datatype,
+            value,
+        }]
+    }
+
+    fn encode(&self, buf: &mut Vec<u8>) {
+        write_string(buf, 1, &self.name);
+        write_varint_field(buf, 3, self.timestamp);
+        write_varint_field(buf, 4, self.datatype as u64);
+        match &self.value {
+            MetricValue::Int(v) => write_varint_field(buf, 10, *v as u64),
+            MetricValue::Long(v) => write_varint_field(buf, 11, *v),
+            MetricValue::Float(v) => {
+                write_key(buf, 12, WIRE_FIXED32);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            MetricValue::Double(v) => {
+                write_key(buf, 13, WIRE_FIXED64);
+                buf.extend_from_slice(&v.to_le_bytes());
+            }
+            MetricValue::Boolean(v) => write_varint_field(buf, 14, *v as u64),
+            MetricValue::String(v) => write_string(buf, 15, v),
+        }
+    }
+}
+
+/// Sparkplug B payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Payload {
+    pub timestamp: u64,
+    pub metrics: Vec<Metric>,
+    pub seq: Option<u64>,
+}
+
+impl Payload {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        write_varint_field(&mut buf, 1, self.timestamp);
+        for metric in &self.metrics {
+            let mut inner = Vec::new();
+            metric.encode(&mut inner);
+            write_bytes(&mut buf, 2, &inner);
+        }
+        if let Some(seq) = self.seq {
+            write_varint_field(&mut buf, 3, seq);
+        }
+        buf
+    }
+}
+
+/// Whether an NCMD payload asks the node to publish its birth certificate
+/// again.
+pub fn is_rebirth_request(payload: &[u8]) -> bool {
+    fields(payload)
+        .filter(|(number, _)| *number == 2)
+        .filter_map(|(_, field)| match field {
+            Field::Bytes(metric) => Some(metric),
+            _ => None,
+        })
+        .any(|metric| {
+            let mut name = None;
+            let mut value = false;
+            for (number, field) in fields(metric) {
+                match (number, field) {
+                    (1, Field::Bytes(v)) => name = std::str::from_utf8(v).ok(),
+                    (14, Field::Varint(v)) => value = v != 0,
+                    _ => {}
+                }
+            }
+            name == Some(REBIRTH_METRIC) && value
+        })
+}
+
+const WIRE_VARINT: u8 = 0;
+const WIRE_FIXED64: u8 = 1;
+const WIRE_BYTES: u8 = 2;
+const WIRE_FIXED32: u8 = 5;
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn write_key(buf: &mut Vec<u8>, number: u32, wire_type: u8) {
+    write_varint(buf, ((number as u64) << 3) | wire_type as u64);
+}
+
+fn write_varint_field(buf: &mut Vec<u8>, number: u32, value: u64) {
+    write_key(buf, number, WIRE_VARINT);
+    write_varint(buf, value);
+}
+
+fn write_bytes(buf: &mut Vec<u8>, number: u32, data: &[u8]) {
+    write_key(buf, number, WIRE_BYTES);
+    write_varint(buf, data.len() as u64);
+    buf.extend_from_slice(data);
+}
+
+fn write_string(buf: &mut Vec<u8>, number: u32, value: &str) {
+    write_bytes(buf, number, value.as_bytes());
+}
+
+enum Field<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    Fixed,
+}
+
+/// Iterate over the top-level fields of a message, stopping at the first
+/// malformed one.
+fn fields(mut data: &[u8]) -> impl Iterator<Item = (u32, Field<'_>)> {
+    std::iter::from_fn(move || {
+        let key = read_varint(&mut data)?;
+        let field = match (key & 0x07) as u8 {
+            WIRE_VARINT => Field::Varint(read_varint(&mut data)?),
+            WIRE_BYTES => {
+                let len = read_varint(&mut data)? as usize;
+                if data.len() < len {
+                    return None;
+                }
+                let (value, rest) = data.split_at(len);
+                data = rest;
+                Field::Bytes(value)
+            }
+            WIRE_FIXED32 | WIRE_FIXED64 => {
+                let len = if key & 0x07 == WIRE_FIXED32 as u64 {
+                    4
+                } else {
+                    8
+                };
+                data = data.get(len..)?;
+                Field::Fixed
+            }
+            _ => return None,
+        };
+        Some(((key >> 3) as u32, field))
+    })
+}
+
+fn read_varint(data: &mut &[u8]) -> Option<u64> {
+    let mut value = 0u64;
+    for (idx, byte) in data.iter().enumerate().take(10) {
+        value |= ((byte & 0x7F) as u64) << (7 * idx);
+        if byte & 0x80 == 0 {
+            *data = &data[idx + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encode_payload() {
+        let payload = Payload {
+            timestamp: 1,
+            metrics: Metric::from_value("a", 2, PlcValue::Int(-1)),
+            seq: Some(3),
+        };
+        assert_eq!(
+            payload.encode(),
+            vec![
+                0x08, 0x01, // timestamp
+                0x12, 0x0D, // metric
+                0x0A, 0x01, b'a', // name
+                0x18, 0x02, // timestamp
+                0x20, 0x02, // datatype Int16
+                0x50, 0xFF, 0xFF, 0xFF, 0xFF, 0x0F, // int_value
+                0x18, 0x03, // seq
+            ]
+        );
+    }
+
+    #[test]
+    fn structure_members() {
+        use crate::value::Timer;
+
+        let timer = PlcValue::Timer(Timer {
+            pre: 5000,
+            acc: 1200,
+            en: true,
+            tt: true,
+            dn: false,
+        });
+        let metrics = Metric::from_value("T_101", 7, timer);
+        let names: Vec<_> = metrics.iter().map(|m| m.name.as_str()).collect();
+        assert_eq!(
+            names,
+            ["T_101/PRE", "T_101/ACC", "T_101/EN", "T_101/TT", "T_101/DN"]
+        );
+        assert_eq!(metrics[1].datatype, DataType::Int32);
+        assert_eq!(metrics[1].value, MetricValue::Int(1200));
+        assert_eq!(metrics[4].datatype, DataType::Boolean);
+        assert_eq!(metrics[4].value, MetricValue::Boolean(false));
+        assert!(metrics.iter().all(|m| m.timestamp == 7));
+    }
+
+    #[test]
+    fn detect_rebirth() {
+        let mut metric = Vec::new();
+        write_string(&mut metric, 1, REBIRTH_METRIC);
+        write_varint_field(&mut metric, 4, DataType::Boolean as u64);
+        write_varint_field(&mut metric, 14, 1);
+        let mut payload = Vec::new();
+        write_varint_field(&mut payload, 1, 0);
+        write_bytes(&mut payload, 2, &metric);
+        assert!(is_rebirth_request(&payload));
+
+        let other = Payload {
+            timestamp: 0,
+            metrics: Metric::from_value("x", 0, PlcValue::Bool(true)),
+            seq: None,
+        };
+        assert!(!is_rebirth_request(&other.encode()));
+    }
+}
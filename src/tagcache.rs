@@ -0,0 +1,359 @@
+use crate::diagnostics::Identity;
+use crate::list::TagRecord;
+use crate::plc::{self, Plc};
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
+use futures_util::TryStreamExt;
+use rseip::client::ab_eip::*;
+use rseip::precludes::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Symbol object class, addressing controller tags by instance.
+const CLASS_SYMBOL: u16 = 0x6B;
+
+/// Logix object counting the changes to the controller project, in its
+/// attributes 1 to 4 and 10. Any edit to the tags changes one of them.
+const CLASS_CHANGES: u16 = 0xAC;
+const CHANGE_ATTRIBUTES: [u16; 5] = [1, 2, 3, 4, 10];
+
+/// How long reads go on with the symbols before the change counters are
+/// read again. Writes always check them first.
+const RECHECK_AFTER: Duration = Duration::from_secs(10);
+
+/// Directory of the files cobalt caches for the user: the tag names for
+/// completion and the tag lists of the controllers.
+pub fn cache_dir() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        PathBuf::from(std::env::var_os("LOCALAPPDATA")?)
+    } else if let Some(dir) = std::env::var_os("XDG_CACHE_HOME").filter(|dir| !dir.is_empty()) {
+        PathBuf::from(dir)
+    } else {
+        PathBuf::from(std::env::var_os("HOME")?).join(".cache")
+    };
+    Some(dir.join("cobalt"))
+}
+
+/// Tag list of a controller as cached on disk, with what identifies the
+/// project it was listed from.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TagCache {
+    pub serial_number: u32,
+    pub revision: (u8, u8),
+    pub product_name: String,
+    /// Address the controller was listed at.
+    #[serde(default)]
+    pub address: Option<String>,
+    /// Change counters of the controller when the tags were listed, in hex,
+    /// unless the controller doesn't report them.
+    pub changes: Option<String>,
+    pub listed: chrono::DateTime<chrono::Utc>,
+    pub tags: Vec<TagRecord>,
+}
+
+impl TagCache {
+    /// File caching the tags of the controller with the serial number.
+    pub fn path(serial_number: u32) -> Option<PathBuf> {
+        Some(
+            cache_dir()?
+                .join("tags")
+                .join(format!("{:08x}.json", serial_number)),
+        )
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("failed to parse {}", path.display()))
+    }
+
+    fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+        }
+        let text = serde_json::to_string(self)?;
+        std::fs::write(path, text).with_context(|| format!("failed to write {}", path.display()))
+    }
+
+    /// Whether the cached list still describes the controller project.
+    fn is_current(&self, identity: &Identity, changes: &str) -> bool {
+        self.revision == identity.revision && self.changes.as_deref() == Some(changes)
+    }
+
+    /// Most recent tag list cached for the controller at the address, or
+    /// for the only controller cached when no address is given, for the
+    /// `--offline` commands.
+    pub fn find(address: Option<&str>) -> Result<Self> {
+        let dir = cache_dir()
+            .context("no cache directory, set HOME or XDG_CACHE_HOME")?
+            .join("tags");
+        let mut caches: Vec<Self> = std::fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| Self::load(&entry.ok()?.path()).ok())
+            .filter(|cache| address.is_none() || cache.address.as_deref() == address)
+            .collect();
+        caches.sort_by_key(|cache| cache.listed);
+        let cache = match (caches.pop(), address) {
+            (Some(cache), _) => cache,
+            (None, Some(address)) => bail!(
+                "no tag list cached for {}, run `cobalt --address {} list` with the controller reachable first",
+                address,
+                address
+            ),
+            (None, None) => bail!(
+                "no tag lists cached in {}, run `list` with a controller reachable first",
+                dir.display()
+            ),
+        };
+        if caches
+            .iter()
+            .any(|other| other.serial_number != cache.serial_number)
+        {
+            bail!("tag lists of several controllers are cached, pick one with --address");
+        }
+        Ok(cache)
+    }
+
+    /// Cached tag holding a tag path like `Pump[3].Speed`, or the program of
+    /// `Program:Main.Flow`, whose own tags aren't listed.
+    pub fn get(&self, tag: &str) -> Option<&TagRecord> {
+        let name = tag.split(['.', '[']).next().unwrap_or(tag).trim();
+        self.tags
+            .iter()
+            .find(|record| record.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Save the tags just listed from a controller, so `--offline` commands
+    /// can use them. Controllers that can't tell their identity aren't
+    /// cached; that doesn't fail the listing.
+    pub async fn save_listing(client: &mut Plc, tags: &[TagRecord]) {
+        let identity = match Identity::read(client).await {
+            Ok(identity) => identity,
+            Err(e) => {
+                tracing::debug!("not caching the tags: {:#}", e);
+                return;
+            }
+        };
+        let changes = read_changes(client).await.ok();
+        let cache = TagCache::new(client, identity, changes, tags.to_vec());
+        if let Some(path) = TagCache::path(cache.serial_number) {
+            if let Err(e) = cache.save(&path) {
+                tracing::warn!("failed to save the tag cache: {:#}", e);
+            }
+        }
+    }
+
+    fn new(
+        client: &Plc,
+        identity: Identity,
+        changes: Option<String>,
+        tags: Vec<TagRecord>,
+    ) -> Self {
+        Self {
+            serial_number: identity.serial_number,
+            revision: identity.revision,
+            product_name: identity.product_name,
+            address: Some(client.address().to_owned()),
+            changes,
+            listed: chrono::Utc::now(),
+            tags,
+        }
+    }
+}
+
+/// Instance ids of the controller scope tags, for addressing them without
+/// sending their names.
+#[derive(Debug)]
+pub struct Symbols {
+    ids: HashMap<String, u16>,
+    changes: String,
+    checked: Instant,
+}
+
+impl Symbols {
+    fn new(cache: &TagCache) -> Self {
+        let ids = cache
+            .tags
+            .iter()
+            .filter(|tag| !tag.name.contains(':'))
+            .map(|tag| (tag.name.to_lowercase(), tag.instance_id))
+            .collect();
+        Self {
+            ids,
+            changes: cache.changes.clone().unwrap_or_default(),
+            checked: Instant::now(),
+        }
+    }
+
+    /// Path of a tag, with its leading name replaced by the instance of the
+    /// symbol when known. Members and elements are still addressed as is.
+    pub fn resolve(&self, mut path: EPath) -> EPath {
+        let id = match path.first() {
+            Some(Segment::Symbol(name)) => self.ids.get(&name.to_lowercase()).copied(),
+            _ => None,
+        };
+        if let Some(id) = id {
+            path[0] = Segment::Instance(id);
+            path.insert(0, Segment::Class(CLASS_SYMBOL));
+        }
+        path
+    }
+
+    /// Whether the change counters need another look before a request.
+    pub fn due(&self, write: bool) -> bool {
+        write || self.checked.elapsed() >= RECHECK_AFTER
+    }
+
+    /// Compare the change counters with the cached ones. False means the
+    /// project changed and the symbols can't be trusted anymore.
+    pub async fn recheck(&mut self, client: &mut Plc) -> Result<bool> {
+        let changes = read_changes(client).await?;
+        self.checked = Instant::now();
+        Ok(changes == self.changes)
+    }
+}
+
+/// Read the change counters of the controller, as an opaque hex string.
+async fn read_changes(client: &mut Plc) -> Result<String> {
+    let path = EPath::default().with_class(CLASS_CHANGES).with_instance(1);
+    let holder: BytesHolder = plc::transaction(
+        "get attribute list",
+        "change counters",
+        client.eip()?.get_attribute_list(path, &CHANGE_ATTRIBUTES),
+    )
+    .await?;
+    let data = Bytes::from(holder);
+    if data.is_empty() {
+        bail!("empty change counters reply");
+    }
+    let mut hex = String::with_capacity(data.len() * 2);
+    for byte in data.iter() {
+        write!(hex, "{:02x}", byte)?;
+    }
+    Ok(hex)
+}
+
+/// Symbols of the controller for `--tag-cache`, from the cached tag list
+/// when it still matches the project and listing the tags again otherwise.
+/// Tags are addressed by name when they can't be had.
+pub async fn symbols(client: &mut Plc) -> Option<Symbols> {
+    match load_symbols(client).await {
+        Ok(symbols) => Some(symbols),
+        Err(e) => {
+            tracing::warn!(
+                "not using the tag cache, tags are addressed by name: {:#}",
+                e
+            );
+            None
+        }
+    }
+}
+
+async fn load_symbols(client: &mut Plc) -> Result<Symbols> {
+    let identity = Identity::read(client)
+        .await
+        .context("failed to read the controller identity")?;
+    let changes = read_changes(client)
+        .await
+        .context("the controller doesn't report changes to its tags")?;
+    let path = TagCache::path(identity.serial_number);
+    if let Some(path) = &path {
+        if let Ok(cache) = TagCache::load(path) {
+            if cache.is_current(&identity, &changes) {
+                tracing::debug!(path = %path.display(), "tag cache is current");
+                return Ok(Symbols::new(&cache));
+            }
+            tracing::info!("controller project changed, listing its tags again");
+        }
+    }
+    let tags: Vec<TagRecord> = client
+        .eip()?
+        .list_tag()
+        .call()
+        .map_ok(TagRecord::new)
+        .try_collect()
+        .await
+        .context("failed to list the controller tags")?;
+    let cache = TagCache::new(client, identity, Some(changes), tags);
+    if let Some(path) = &path {
+        if let Err(e) = cache.save(path) {
+            tracing::warn!("failed to save the tag cache: {:#}", e);
+        }
+    }
+    Ok(Symbols::new(&cache))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn cache() -> TagCache {
+        let tag = |name: &str, instance_id| TagRecord {
+            name: name.to_owned(),
+            instance_id,
+            r#type: "DINT".to_owned(),
+            type_code: Some(0xC4),
+            dims: 0,
+            structure_handle: None,
+            program: None,
+        };
+        TagCache {
+            serial_number: 0x00c0ffee,
+            revision: (33, 11),
+            product_name: "1756-L83E/B".to_owned(),
+            address: Some("10.0.0.5".to_owned()),
+            changes: Some("0500010000".to_owned()),
+            listed: chrono::Utc::now(),
+            tags: vec![tag("PT_101", 12), tag("Pump", 40), tag("Program:Main", 7)],
+        }
+    }
+
+    #[test]
+    fn instance_paths() {
+        let symbols = Symbols::new(&cache());
+        let path = symbols.resolve(EPath::parse_tag("pt_101").unwrap());
+        assert_eq!(
+            path.as_ref(),
+            &[Segment::Class(0x6B), Segment::Instance(12)]
+        );
+        let path = symbols.resolve(EPath::parse_tag("Pump[3].Speed").unwrap());
+        assert_eq!(path[..2], [Segment::Class(0x6B), Segment::Instance(40)]);
+        assert_eq!(path[2..], EPath::parse_tag("Pump[3].Speed").unwrap()[1..]);
+        let unknown = EPath::parse_tag("Program:Main.Flow").unwrap();
+        assert_eq!(symbols.resolve(unknown.clone()), unknown);
+        assert!(!symbols.due(false));
+        assert!(symbols.due(true));
+    }
+
+    #[test]
+    fn round_trip() {
+        let path =
+            std::env::temp_dir().join(format!("cobalt-tagcache-{}.json", std::process::id()));
+        cache().save(&path).unwrap();
+        let loaded = TagCache::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded.tags.len(), 3);
+        assert_eq!(loaded.tags[1].instance_id, 40);
+        let identity = Identity {
+            vendor_id: 1,
+            device_type: 14,
+            product_code: 166,
+            revision: (33, 11),
+            status: 0x3060,
+            serial_number: 0x00c0ffee,
+            product_name: loaded.product_name.clone(),
+        };
+        assert!(loaded.is_current(&identity, "0500010000"));
+        assert!(!loaded.is_current(&identity, "0600010000"));
+
+        assert_eq!(loaded.get("pump[3].Speed").unwrap().instance_id, 40);
+        assert_eq!(loaded.get("Program:Main.Flow").unwrap().instance_id, 7);
+        assert!(loaded.get("Valve").is_none());
+    }
+}
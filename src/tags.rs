@@ -0,0 +1,121 @@
+use crate::scan::Scan;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer, Serializer};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::time::Duration;
+
+/// A tag selected in a tag list file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagConfig {
+    /// Tag name as understood by the controller.
+    pub name: String,
+    /// Extra labels attached to the tag by the outputs that support them.
+    #[serde(default)]
+    pub labels: BTreeMap<String, String>,
+    /// Freeze detection of the tag in monitor mode.
+    #[serde(default)]
+    pub freeze: Option<FreezeConfig>,
+    /// Scan class of the tag in monitor mode, medium by default.
+    #[serde(default)]
+    pub scan: Option<Scan>,
+}
+
+/// Flags a tag as frozen when its value stays within `tolerance` of the same
+/// reading for `after`, e.g. `freeze = { after = "10m", tolerance = 0.05 }`.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FreezeConfig {
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub after: Duration,
+    #[serde(default)]
+    pub tolerance: f64,
+}
+
+pub(crate) fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Duration, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    humantime::parse_duration(&s).map_err(serde::de::Error::custom)
+}
+
+pub(crate) fn serialize_duration<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&humantime::format_duration(*duration).to_string())
+}
+
+/// A TOML file selecting the tags served by the long-running modes, with one
+/// `[[tag]]` table per tag.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagList {
+    #[serde(default, rename = "tag")]
+    pub tags: Vec<TagConfig>,
+}
+
+impl TagList {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read tag list {}", path.display()))?;
+        toml::from_str(&text)
+            .with_context(|| format!("failed to parse tag list {}", path.display()))
+    }
+}
+
+/// Case-insensitive match of a name against a pattern where `*` matches any
+/// run of characters and `?` a single one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let name: Vec<char> = name.to_lowercase().chars().collect();
+    // Position after the last `*` and the name position it was tried at.
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            backtrack = Some((p, n));
+        } else if let Some((bp, bn)) = backtrack {
+            p = bp;
+            n = bn + 1;
+            backtrack = Some((bp, bn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// The tag, or the structure or word it is a member or bit of, that one of
+/// the patterns matches, e.g. `Motor` for `Motor.Speed` and `Motor*`.
+pub fn matching_tag<'a>(patterns: &[String], tag: &'a str) -> Option<&'a str> {
+    tag.match_indices(['.', '['])
+        .map(|(end, _)| &tag[..end])
+        .chain([tag])
+        .find(|name| patterns.iter().any(|pattern| glob_match(pattern, name)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("prog*", "Program:Main"));
+        assert!(glob_match("*flow*", "FT_101_Flow_Rate"));
+        assert!(glob_match("PT_10?", "pt_101"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("prog*", "Xprogram"));
+        assert!(!glob_match("PT_10?", "PT_1011"));
+
+        let patterns = ["Motor*".to_owned(), "SIS_Trip".to_owned()];
+        assert_eq!(matching_tag(&patterns, "Motor1.Speed"), Some("Motor1"));
+        assert_eq!(matching_tag(&patterns, "sis_trip.3"), Some("sis_trip"));
+        assert_eq!(matching_tag(&patterns, "SIS_Trips[2]"), None);
+    }
+}
@@ -0,0 +1,147 @@
+use crate::plc::{self, ConnectionArgs, Plc};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use tracing::Instrument;
+
+/// A controller of a multi-target invocation.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Target {
+    /// Name the output of the controller is prefixed with, its address by
+    /// default.
+    #[serde(default)]
+    pub name: String,
+    pub address: String,
+    /// Bridge settings file used by `bridge-write` for this controller.
+    #[serde(default)]
+    pub bridge: Option<PathBuf>,
+    /// Tag `healthcheck` reads from this controller.
+    #[serde(default)]
+    pub heartbeat: Option<String>,
+    /// Address of the standby controller taken over when this one stops
+    /// answering.
+    #[serde(default)]
+    pub failover: Option<String>,
+}
+
+/// A TOML file listing controllers, with one `[[target]]` table each.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct TargetsFile {
+    #[serde(default, rename = "target")]
+    targets: Vec<Target>,
+}
+
+fn load(path: &Path) -> Result<Vec<Target>> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read targets {}", path.display()))?;
+    let file: TargetsFile = toml::from_str(&text)
+        .with_context(|| format!("failed to parse targets {}", path.display()))?;
+    Ok(file.targets)
+}
+
+/// The controllers given by `--address` options followed by those of the
+/// targets file, with names defaulting to addresses.
+pub fn resolve(addresses: &[String], file: Option<&Path>) -> Result<Vec<Target>> {
+    let mut targets: Vec<Target> = addresses
+        .iter()
+        .map(|address| Target {
+            name: String::new(),
+            address: address.clone(),
+            bridge: None,
+            heartbeat: None,
+            failover: None,
+        })
+        .collect();
+    if let Some(path) = file {
+        targets.extend(load(path)?);
+    }
+    let mut names = BTreeSet::new();
+    for target in &mut targets {
+        if target.name.is_empty() {
+            target.name = target.address.clone();
+        }
+        if !names.insert(target.name.clone()) {
+            bail!(
+                "controller `{}` is given twice, name the targets apart",
+                target.name
+            );
+        }
+    }
+    Ok(targets)
+}
+
+/// Open a session with the controller of a target, which fails over to its
+/// standby controller when it has one.
+pub async fn connect(target: &Target, connection: &ConnectionArgs) -> Result<Plc> {
+    let mut client = plc::connect(&target.address, connection).await?;
+    if let Some(standby) = &target.failover {
+        client.set_failover(standby, connection)?;
+    }
+    Ok(client)
+}
+
+/// Run a polling mode against every target concurrently, each over its own
+/// session. A target that fails is reported right away and the others keep
+/// running; the error is returned once all of them stopped.
+pub async fn run_all<F, Fut>(targets: &[Target], connection: &ConnectionArgs, run: F) -> Result<()>
+where
+    F: Fn(Target, Plc) -> Fut,
+    Fut: Future<Output = Result<()>>,
+{
+    let tasks = targets.iter().map(|target| {
+        let run = &run;
+        let span = tracing::info_span!("target", name = %target.name);
+        async move {
+            let result = match connect(target, connection).await {
+                Ok(client) => run(target.clone(), client).await,
+                Err(e) => Err(e),
+            };
+            if let Err(e) = &result {
+                tracing::error!("stopped: {:#}", e);
+            }
+            result
+        }
+        .instrument(span)
+    });
+    let results = futures_util::future::join_all(tasks).await;
+    let failed = results.iter().filter(|result| result.is_err()).count();
+    if failed > 0 {
+        bail!("{} of {} controllers failed", failed, targets.len());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn targets() {
+        let file: TargetsFile = toml::from_str(
+            r#"
+            [[target]]
+            name = "line1"
+            address = "10.0.0.5"
+            bridge = "bridge-line1.toml"
+
+            [[target]]
+            address = "10.0.0.6"
+            heartbeat = "Heartbeat"
+            failover = "10.0.0.16"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(file.targets[0].bridge, Some("bridge-line1.toml".into()));
+        assert_eq!(file.targets[1].heartbeat.as_deref(), Some("Heartbeat"));
+        assert_eq!(file.targets[1].failover.as_deref(), Some("10.0.0.16"));
+
+        let targets = resolve(&["10.0.0.7".to_owned()], None).unwrap();
+        assert_eq!(targets[0].name, "10.0.0.7");
+        let twice = ["10.0.0.7".to_owned(), "10.0.0.7".to_owned()];
+        assert!(resolve(&twice, None).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use crate::monitor::Quality;
+use crate::value::PlcValue;
+use anyhow::{bail, Result};
+use chrono::{DateTime, Local, SecondsFormat};
+use std::str::FromStr;
+
+/// Fields a template can hold, as `{{name}}`.
+const FIELDS: [(&str, Field); 8] = [
+    ("name", Field::Name),
+    ("value", Field::Value),
+    ("type", Field::Type),
+    ("state", Field::State),
+    ("quality", Field::Quality),
+    ("timestamp", Field::Timestamp),
+    ("unix", Field::Unix),
+    ("plc", Field::Plc),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Name,
+    Value,
+    Type,
+    State,
+    Quality,
+    Timestamp,
+    Unix,
+    Plc,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Part {
+    Text(String),
+    Field(Field),
+}
+
+/// Line printed for each value instead of the usual output, like
+/// `{{name}}={{value}}`, so it can be fed to another tool as is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputTemplate {
+    parts: Vec<Part>,
+}
+
+/// What a template line tells of a value.
+pub struct Fields<'a> {
+    /// Target name, or the address of the controller.
+    pub plc: &'a str,
+    pub tag: &'a str,
+    pub value: &'a PlcValue,
+    /// Name of the state of the value, from the settings.
+    pub state: Option<&'a str>,
+    pub quality: Quality,
+    pub timestamp: DateTime<Local>,
+}
+
+impl OutputTemplate {
+    pub fn render(&self, fields: &Fields) -> String {
+        let mut line = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => line.push_str(text),
+                Part::Field(Field::Name) => line.push_str(fields.tag),
+                Part::Field(Field::Value) => line.push_str(&fields.value.to_string()),
+                Part::Field(Field::Type) => line.push_str(&fields.value.type_name()),
+                Part::Field(Field::State) => line.push_str(fields.state.unwrap_or_default()),
+                Part::Field(Field::Quality) => line.push_str(match fields.quality {
+                    Quality::Good => "good",
+                    Quality::Frozen => "frozen",
+                }),
+                Part::Field(Field::Timestamp) => line.push_str(
+                    &fields
+                        .timestamp
+                        .to_rfc3339_opts(SecondsFormat::Millis, false),
+                ),
+                Part::Field(Field::Unix) => {
+                    line.push_str(&fields.timestamp.timestamp().to_string())
+                }
+                Part::Field(Field::Plc) => line.push_str(fields.plc),
+            }
+        }
+        line
+    }
+}
+
+impl FromStr for OutputTemplate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut rest = s;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(rest[..start].to_owned()));
+            }
+            let Some(len) = rest[start + 2..].find("}}") else {
+                bail!("unclosed `{{{{` in template `{}`", s);
+            };
+            let name = rest[start + 2..start + 2 + len].trim();
+            let Some((_, field)) = FIELDS.iter().find(|(field, _)| *field == name) else {
+                let names: Vec<String> = FIELDS
+                    .iter()
+                    .map(|(name, _)| format!("{{{{{}}}}}", name))
+                    .collect();
+                bail!(
+                    "unknown field `{}` in template, expected one of {}",
+                    name,
+                    names.join(", ")
+                );
+            };
+            parts.push(Part::Field(*field));
+            rest = &rest[start + 2 + len + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest.to_owned()));
+        }
+        Ok(Self { parts })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn render() {
+        let value = PlcValue::Real(12.5);
+        let fields = Fields {
+            plc: "line1",
+            tag: "PT_101",
+            value: &value,
+            state: None,
+            quality: Quality::Frozen,
+            timestamp: Local.timestamp_opt(1_700_000_000, 0).unwrap(),
+        };
+        let template: OutputTemplate = "{{name}}={{ value }}".parse().unwrap();
+        assert_eq!(template.render(&fields), "PT_101=12.5");
+        let template: OutputTemplate =
+            "{{plc}} cobalt[{{name}}] {{unix}} {{value}}{{state}} {{quality}}"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            template.render(&fields),
+            "line1 cobalt[PT_101] 1700000000 12.5 frozen"
+        );
+        let template: OutputTemplate = "no fields".parse().unwrap();
+        assert_eq!(template.render(&fields), "no fields");
+
+        let error = "{{name}}={{val}}".parse::<OutputTemplate>().unwrap_err();
+        assert!(
+            error.to_string().starts_with("unknown field `val`"),
+            "{}",
+            error
+        );
+        assert!(error.to_string().contains("{{value}}"));
+        assert!("{{name".parse::<OutputTemplate>().is_err());
+    }
+}
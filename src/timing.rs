@@ -0,0 +1,228 @@
+use clap::Args;
+use std::future::Future;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Options of the polling modes for their timing statistics.
+#[derive(Args)]
+pub struct StatsArgs {
+    /// Log the actual scan period, its jitter and the time spent talking
+    /// to each device, every interval like `5m` [default: 1m] and once more
+    /// on stop.
+    #[arg(
+        long,
+        value_name = "INTERVAL",
+        num_args = 0..=1,
+        default_missing_value = "1m",
+        value_parser = humantime::parse_duration
+    )]
+    pub stats: Option<Duration>,
+}
+
+/// Durations observed since the last report.
+#[derive(Default)]
+struct Series {
+    durations: Vec<Duration>,
+}
+
+/// Spread of the durations of a series.
+#[derive(Debug, PartialEq)]
+struct Summary {
+    count: usize,
+    min: Duration,
+    avg: Duration,
+    p99: Duration,
+    max: Duration,
+}
+
+impl Series {
+    /// Summarize the series and start it over, `None` if it is empty.
+    fn take(&mut self) -> Option<Summary> {
+        let mut durations = std::mem::take(&mut self.durations);
+        if durations.is_empty() {
+            return None;
+        }
+        durations.sort();
+        let count = durations.len();
+        let p99 = (count * 99).div_ceil(100) - 1;
+        Some(Summary {
+            count,
+            min: durations[0],
+            avg: durations.iter().sum::<Duration>() / count as u32,
+            p99: durations[p99],
+            max: durations[count - 1],
+        })
+    }
+}
+
+/// A scan meant to run at a fixed interval.
+struct Cycle {
+    name: String,
+    interval: Duration,
+    last: Option<Instant>,
+    periods: Series,
+}
+
+/// Timing of a polling loop: the actual period of its scans against the
+/// configured one, and how long each device takes to answer. Summaries are
+/// logged at the end of every reporting interval.
+pub struct CycleStats {
+    every: Duration,
+    since: Instant,
+    cycles: Vec<Cycle>,
+    devices: Vec<(String, Series)>,
+}
+
+impl CycleStats {
+    pub fn new(every: Duration) -> Self {
+        Self {
+            every,
+            since: Instant::now(),
+            cycles: Vec::new(),
+            devices: Vec::new(),
+        }
+    }
+
+    /// Record the start of a scan meant to run every `interval`.
+    pub fn cycle(&mut self, interval: Duration, at: Instant) {
+        let name = format!("scan {}", humantime::format_duration(interval));
+        let cycle = match self.cycles.iter_mut().position(|c| c.name == name) {
+            Some(index) => &mut self.cycles[index],
+            None => {
+                self.cycles.push(Cycle {
+                    name,
+                    interval,
+                    last: None,
+                    periods: Series::default(),
+                });
+                self.cycles.last_mut().unwrap()
+            }
+        };
+        if let Some(last) = cycle.last.replace(at) {
+            cycle.periods.durations.push(at - last);
+        }
+    }
+
+    /// Record how long a device took to answer.
+    pub fn latency(&mut self, device: &str, took: Duration) {
+        match self.devices.iter_mut().find(|(name, _)| name == device) {
+            Some((_, series)) => series.durations.push(took),
+            None => self.devices.push((
+                device.to_owned(),
+                Series {
+                    durations: vec![took],
+                },
+            )),
+        }
+    }
+
+    /// Log the summaries once the reporting interval is over.
+    pub fn tick(&mut self) {
+        if self.since.elapsed() >= self.every {
+            self.report();
+        }
+    }
+
+    /// Log the summaries and start new ones.
+    pub fn report(&mut self) {
+        for line in self.lines() {
+            tracing::info!("{}", line);
+        }
+        self.since = Instant::now();
+    }
+
+    fn lines(&mut self) -> Vec<String> {
+        let mut lines = Vec::new();
+        for cycle in &mut self.cycles {
+            let Some(periods) = cycle.periods.take() else {
+                continue;
+            };
+            // Scans skipped after a slow one show as a longer period.
+            let rate = cycle.interval.as_secs_f64() / periods.avg.as_secs_f64() * 100.0;
+            let jitter = |period: Duration| period.abs_diff(cycle.interval);
+            lines.push(format!(
+                "{}: {} cycles, period {} avg {} min {} max, jitter {} max, {:.1}% of the configured rate",
+                cycle.name,
+                periods.count,
+                millis(periods.avg),
+                millis(periods.min),
+                millis(periods.max),
+                millis(jitter(periods.min).max(jitter(periods.max))),
+                rate
+            ));
+        }
+        for (device, series) in &mut self.devices {
+            if let Some(latency) = series.take() {
+                lines.push(format!(
+                    "{}: {} samples, latency {} avg {} p99 {} max",
+                    device,
+                    latency.count,
+                    millis(latency.avg),
+                    millis(latency.p99),
+                    millis(latency.max)
+                ));
+            }
+        }
+        lines
+    }
+}
+
+/// Run a future, also returning how long it took.
+pub async fn timed<F: Future>(future: F) -> (F::Output, Duration) {
+    let start = Instant::now();
+    let output = future.await;
+    (output, start.elapsed())
+}
+
+fn millis(duration: Duration) -> String {
+    format!("{:.1}ms", duration.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summaries() {
+        let ms = Duration::from_millis;
+        let mut series = Series {
+            durations: (1..=200).rev().map(ms).collect(),
+        };
+        assert_eq!(
+            series.take(),
+            Some(Summary {
+                count: 200,
+                min: ms(1),
+                avg: Duration::from_micros(100_500),
+                p99: ms(198),
+                max: ms(200),
+            })
+        );
+        assert_eq!(series.take(), None);
+
+        let mut stats = CycleStats::new(Duration::from_secs(60));
+        let start = Instant::now();
+        for at in [0, 250, 510, 740, 1000] {
+            stats.cycle(ms(250), start + ms(at));
+        }
+        stats.latency("controller read", ms(4));
+        stats.latency("controller read", ms(6));
+        assert_eq!(
+            stats.lines(),
+            [
+                "scan 250ms: 4 cycles, period 250.0ms avg 230.0ms min 260.0ms max, \
+                 jitter 20.0ms max, 100.0% of the configured rate",
+                "controller read: 2 samples, latency 5.0ms avg 6.0ms p99 6.0ms max",
+            ]
+        );
+        // Each report covers the cycles since the last one.
+        stats.cycle(ms(250), start + ms(1300));
+        assert_eq!(
+            stats.lines(),
+            [
+                "scan 250ms: 1 cycles, period 300.0ms avg 300.0ms min 300.0ms max, \
+              jitter 50.0ms max, 83.3% of the configured rate"
+            ]
+        );
+    }
+}
@@ -0,0 +1,79 @@
+use serde::{Deserialize, Serialize};
+use std::time::Instant;
+
+/// Seconds in the day the rates at base conditions are given per.
+const SECONDS_PER_DAY: f64 = 86_400.0;
+
+/// Accumulation of the rate at base conditions of a meter into a total
+/// volume, written to a tag each cycle.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TotalizerConfig {
+    /// Tag receiving the total at base conditions, in Sm3. Its type is kept,
+    /// so an LREAL or LINT keeps its resolution on large totals.
+    pub tag: String,
+}
+
+/// Running total of a rate in Sm3/d, integrated with the trapezoidal rule
+/// between consecutive readings.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Totalizer {
+    pub total: f64,
+    last: Option<(Instant, f64)>,
+}
+
+impl Totalizer {
+    pub fn new(total: f64) -> Self {
+        Self { total, last: None }
+    }
+
+    /// Add the volume flowed since the previous reading. Reverse flow
+    /// counts as none, so the total never goes down.
+    pub fn add(&mut self, rate: f64, at: Instant) {
+        if !rate.is_finite() {
+            self.last = None;
+            return;
+        }
+        let rate = rate.max(0.0);
+        if let Some((then, previous)) = self.last {
+            let secs = at.saturating_duration_since(then).as_secs_f64();
+            self.total += (previous + rate) / 2.0 * secs / SECONDS_PER_DAY;
+        }
+        self.last = Some((at, rate));
+    }
+
+    /// Leave out the time until the next reading, while the meter doesn't
+    /// answer, rather than guessing what flowed meanwhile.
+    pub fn interrupt(&mut self) {
+        self.last = None;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn integrate() {
+        let start = Instant::now();
+        let mut totalizer = Totalizer::new(100.0);
+        totalizer.add(86_400.0, start);
+        assert_eq!(totalizer.total, 100.0);
+        totalizer.add(86_400.0, start + Duration::from_secs(10));
+        assert_eq!(totalizer.total, 110.0);
+        // Ramping from 1 to 3 Sm3/s for 2 s flows 4 Sm3.
+        let mut ramp = Totalizer::new(0.0);
+        ramp.add(86_400.0, start);
+        ramp.add(3.0 * 86_400.0, start + Duration::from_secs(2));
+        assert_eq!(ramp.total, 4.0);
+
+        totalizer.interrupt();
+        totalizer.add(86_400.0, start + Duration::from_secs(60));
+        assert_eq!(totalizer.total, 110.0);
+        totalizer.add(-50.0, start + Duration::from_secs(70));
+        assert_eq!(totalizer.total, 115.0);
+        totalizer.add(f64::NAN, start + Duration::from_secs(80));
+        assert_eq!(totalizer.total, 115.0);
+    }
+}
@@ -0,0 +1,450 @@
+use crate::list::{element_type_name, TagRecord};
+use crate::plc::{Family, Plc};
+use anyhow::{bail, Context, Result};
+use clap::{Args, ValueEnum};
+use colored::*;
+use futures_util::TryStreamExt;
+use rseip::client::ab_eip::*;
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// Prefix of the hidden SINT members Logix packs the BOOL members of a
+/// structure into.
+const HIDDEN_PREFIX: &str = "ZZZZZZZZZZ";
+
+/// Format of an export of structure definitions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum UdtFormat {
+    /// The `DataTypes` of a Logix Designer L5X export.
+    L5x,
+    Json,
+}
+
+/// Options of the `udts` command.
+#[derive(Args)]
+pub struct UdtArgs {
+    /// Write the definitions to a file instead, e.g. `types.l5x` or
+    /// `types.json`.
+    #[arg(long)]
+    pub export: Option<PathBuf>,
+    /// Format of the export, guessed from the file extension by default.
+    #[arg(long, value_enum, requires = "export")]
+    pub format: Option<UdtFormat>,
+    /// Also take the predefined types, like TIMER or STRING, and those of
+    /// I/O modules.
+    #[arg(long)]
+    pub all: bool,
+}
+
+/// A structure type, as its template describes it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Udt {
+    pub name: String,
+    /// Template instance, which the tags of the type refer to in listings.
+    pub instance_id: u16,
+    /// Structure handle, the tag type parameter of reads and writes.
+    pub handle: u16,
+    /// Bytes of the data of the type in a read or write.
+    pub size: u32,
+    /// Whether Logix predefines the type or an I/O module does.
+    pub predefined: bool,
+    /// Members in the order of their data.
+    pub members: Vec<Member>,
+}
+
+/// A member of a structure type.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Member {
+    pub name: String,
+    /// Atomic type like `REAL`, or the name of a structure type.
+    pub data_type: String,
+    /// Elements of an array member, 0 for a single value.
+    pub dimension: u16,
+    /// Byte offset in the data of the structure.
+    pub offset: u32,
+    /// Bit of a BOOL member in the SINT at its offset.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bit: Option<u8>,
+    /// Whether Logix Designer hides the member, like the SINTs holding
+    /// BOOL members.
+    pub hidden: bool,
+}
+
+impl Udt {
+    /// Whether the type is a string, a DINT length and a SINT array.
+    pub fn is_string(&self) -> bool {
+        self.string_type().is_some()
+    }
+
+    /// The type as a string type, when it is one.
+    pub fn string_type(&self) -> Option<StringType> {
+        match self.members.as_slice() {
+            [len, data]
+                if len.name == "LEN"
+                    && len.data_type == "DINT"
+                    && data.name == "DATA"
+                    && data.data_type == "SINT"
+                    && data.dimension > 0 =>
+            {
+                Some(StringType {
+                    name: self.name.clone(),
+                    handle: self.handle,
+                    capacity: data.dimension as u32,
+                    size: self.size,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A string type, the predefined STRING of 82 characters or one of another
+/// length like STRING20.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StringType {
+    pub name: String,
+    /// Structure handle, the tag type parameter of reads and writes.
+    pub handle: u16,
+    /// Characters the DATA array holds.
+    pub capacity: u32,
+    /// Bytes of the data of the type in a read or write.
+    pub size: u32,
+}
+
+impl StringType {
+    /// Structure handle Logix reports for STRING tags.
+    pub const STRING_HANDLE: u16 = 0x0FCE;
+
+    /// The predefined STRING, known without reading its template.
+    pub fn standard() -> Self {
+        StringType {
+            name: "STRING".to_owned(),
+            handle: Self::STRING_HANDLE,
+            capacity: 82,
+            size: 88,
+        }
+    }
+}
+
+/// The string types of a tag and of its members, found from the templates
+/// of its structure type, for reading strings other than STRING.
+pub async fn string_types(client: &mut Plc, tag: &str) -> Result<Vec<StringType>> {
+    let name = tag.split(['.', '[']).next().unwrap_or(tag);
+    let Some((_, _, symbol_type)) = crate::list::find_symbol(client, name).await? else {
+        return Ok(Vec::new());
+    };
+    let Some(instance_id) = symbol_type.instance_id() else {
+        return Ok(Vec::new());
+    };
+    let udts = read_udts(client, [instance_id]).await?;
+    Ok(udts.iter().filter_map(Udt::string_type).collect())
+}
+
+/// Show or export the structure types of the controller tags.
+pub async fn run(client: &mut Plc, args: &UdtArgs) -> Result<()> {
+    if client.family() == Family::MicroLogix {
+        bail!("{} controllers have no structure types", client.family());
+    }
+    let tags: Vec<TagRecord> = client
+        .eip()?
+        .list_tag()
+        .call()
+        .map_ok(TagRecord::new)
+        .try_collect()
+        .await?;
+    let roots: BTreeSet<u16> = tags.iter().filter_map(|tag| tag.structure_handle).collect();
+    let udts: Vec<Udt> = read_udts(client, roots)
+        .await?
+        .into_iter()
+        .filter(|udt| args.all || !udt.predefined)
+        .collect();
+
+    if let Some(path) = &args.export {
+        let format = match args.format {
+            Some(format) => format,
+            None => guess_format(path)?,
+        };
+        let mut file = std::fs::File::create(path)
+            .with_context(|| format!("failed to create {}", path.display()))?;
+        match format {
+            UdtFormat::L5x => file.write_all(to_l5x(&udts, client.address()).as_bytes())?,
+            UdtFormat::Json => {
+                serde_json::to_writer_pretty(&mut file, &udts)?;
+                writeln!(file)?;
+            }
+        }
+        println!("Exported {} types to {}.", udts.len(), path.display());
+        return Ok(());
+    }
+    for udt in &udts {
+        println!("{}    {} bytes", udt.name.bold(), udt.size);
+        for member in udt.members.iter().filter(|member| !member.hidden) {
+            let dimension = match member.dimension {
+                0 => String::new(),
+                n => format!("[{}]", n),
+            };
+            let bit = member
+                .bit
+                .map(|bit| format!(".{}", bit))
+                .unwrap_or_default();
+            println!(
+                "    {:>5}{:<3} {}    {}{}",
+                member.offset, bit, member.name, member.data_type, dimension
+            );
+        }
+    }
+    Ok(())
+}
+
+fn guess_format(path: &Path) -> Result<UdtFormat> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("l5x") || ext.eq_ignore_ascii_case("xml") => {
+            Ok(UdtFormat::L5x)
+        }
+        Some(ext) if ext.eq_ignore_ascii_case("json") => Ok(UdtFormat::Json),
+        _ => bail!(
+            "cannot tell the format of {}, pass --format",
+            path.display()
+        ),
+    }
+}
+
+/// Read the templates of structure types and of the types of their
+/// members, the types members use coming before the types using them.
+pub async fn read_udts(client: &mut Plc, roots: impl IntoIterator<Item = u16>) -> Result<Vec<Udt>> {
+    let mut names: HashMap<u16, String> = HashMap::new();
+    let mut found = Vec::new();
+    let mut pending: Vec<u16> = roots.into_iter().collect();
+    while let Some(instance_id) = pending.pop() {
+        if names.contains_key(&instance_id) {
+            continue;
+        }
+        let eip = client.eip()?;
+        let template = eip
+            .find_template(instance_id)
+            .await
+            .with_context(|| format!("failed to read template {:#06x}", instance_id))?;
+        let mut read = eip.read_template(&template);
+        let definition = read
+            .call()
+            .await
+            .with_context(|| format!("failed to read template {:#06x}", instance_id))?;
+        let mut members: Vec<(String, u16, SymbolType, u32)> = definition
+            .iter()
+            .map(|(name, member)| {
+                let name = name.to_string();
+                (name, member.array_size, member.type_info, member.offset)
+            })
+            .collect();
+        members.sort_by_key(|(.., offset)| *offset);
+        let name = definition.name().to_owned();
+        drop(read);
+        for (_, _, type_info, _) in &members {
+            if let Some(nested) = type_info.instance_id() {
+                pending.push(nested);
+            }
+        }
+        let predefined = SymbolType::builder()
+            .structure(instance_id)
+            .finish()
+            .is_predefined()
+            || name.contains(':');
+        names.insert(instance_id, name.clone());
+        found.push((
+            Udt {
+                name,
+                instance_id,
+                handle: template.handle,
+                size: template.struct_size,
+                predefined,
+                members: Vec::new(),
+            },
+            members,
+        ));
+    }
+    // Types are found before the types of their members.
+    Ok(found
+        .into_iter()
+        .rev()
+        .map(|(mut udt, members)| {
+            udt.members = members
+                .into_iter()
+                .map(|(name, array_size, type_info, offset)| {
+                    let data_type = match type_info.instance_id() {
+                        Some(id) => names[&id].clone(),
+                        None => element_type_name(type_info).to_owned(),
+                    };
+                    let bool_member = type_info.is_bool();
+                    Member {
+                        hidden: name.starts_with(HIDDEN_PREFIX) || name.starts_with("__"),
+                        name,
+                        data_type,
+                        dimension: if bool_member { 0 } else { array_size },
+                        offset,
+                        bit: bool_member.then_some(array_size as u8),
+                    }
+                })
+                .collect();
+            udt
+        })
+        .collect())
+}
+
+/// The types as the `DataTypes` of an L5X file, which Logix Designer and
+/// tools reading its exports take.
+pub fn to_l5x(udts: &[Udt], controller: &str) -> String {
+    let mut xml = String::new();
+    let _ = writeln!(
+        xml,
+        r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>"#
+    );
+    let _ = writeln!(
+        xml,
+        r#"<RSLogix5000Content SchemaRevision="1.0" TargetType="DataType" ContainsContext="true" ExportDate="{}" ExportOptions="References NoRawData L5KData DecoratedData Context">"#,
+        chrono::Local::now().format("%a %b %e %H:%M:%S %Y")
+    );
+    let _ = writeln!(
+        xml,
+        r#"<Controller Use="Context" Name="{}">"#,
+        escape(controller)
+    );
+    let _ = writeln!(xml, r#"<DataTypes Use="Context">"#);
+    for udt in udts {
+        let family = if udt.is_string() {
+            "StringFamily"
+        } else {
+            "NoFamily"
+        };
+        let _ = writeln!(
+            xml,
+            r#"<DataType Name="{}" Family="{}" Class="User">"#,
+            escape(&udt.name),
+            family
+        );
+        let _ = writeln!(xml, "<Members>");
+        for member in &udt.members {
+            let _ = write!(xml, r#"<Member Name="{}" "#, escape(&member.name));
+            match member.bit {
+                Some(bit) => {
+                    // BOOL members are bits of the hidden SINT at their
+                    // offset.
+                    let target = udt
+                        .members
+                        .iter()
+                        .find(|host| host.hidden && host.offset == member.offset)
+                        .map(|host| host.name.as_str())
+                        .unwrap_or_default();
+                    let _ = write!(
+                        xml,
+                        r#"DataType="BIT" Dimension="0" Radix="Decimal" Hidden="false" Target="{}" BitNumber="{}""#,
+                        escape(target),
+                        bit
+                    );
+                }
+                None => {
+                    let radix = match member.data_type.as_str() {
+                        "REAL" | "LREAL" => "Float",
+                        "SINT" | "INT" | "DINT" | "LINT" | "USINT" | "UINT" | "UDINT" | "ULINT"
+                        | "BYTE" | "WORD" | "DWORD" | "LWORD" | "BOOL" => "Decimal",
+                        _ => "NullType",
+                    };
+                    let _ = write!(
+                        xml,
+                        r#"DataType="{}" Dimension="{}" Radix="{}" Hidden="{}""#,
+                        escape(&member.data_type),
+                        member.dimension,
+                        radix,
+                        member.hidden
+                    );
+                }
+            }
+            let _ = writeln!(xml, r#" ExternalAccess="Read/Write"/>"#);
+        }
+        let _ = writeln!(xml, "</Members>");
+        let _ = writeln!(xml, "</DataType>");
+    }
+    let _ = writeln!(xml, "</DataTypes>");
+    let _ = writeln!(xml, "</Controller>");
+    let _ = writeln!(xml, "</RSLogix5000Content>");
+    xml
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn member(name: &str, data_type: &str, offset: u32) -> Member {
+        Member {
+            name: name.to_owned(),
+            data_type: data_type.to_owned(),
+            dimension: 0,
+            offset,
+            bit: None,
+            hidden: name.starts_with(HIDDEN_PREFIX),
+        }
+    }
+
+    #[test]
+    fn l5x() {
+        let motor = Udt {
+            name: "Motor".to_owned(),
+            instance_id: 0x0f3c,
+            handle: 0x6d2e,
+            size: 12,
+            predefined: false,
+            members: vec![
+                member("ZZZZZZZZZZMotor0", "SINT", 0),
+                Member {
+                    bit: Some(1),
+                    ..member("Run", "BOOL", 0)
+                },
+                member("Speed", "REAL", 4),
+                Member {
+                    dimension: 2,
+                    ..member("Faults", "Fault", 8)
+                },
+            ],
+        };
+        let xml = to_l5x(&[motor], "10.0.0.5");
+        assert!(xml.contains(r#"<Controller Use="Context" Name="10.0.0.5">"#));
+        assert!(xml.contains(r#"<DataType Name="Motor" Family="NoFamily" Class="User">"#));
+        assert!(xml.contains(r#"<Member Name="ZZZZZZZZZZMotor0" DataType="SINT" Dimension="0" Radix="Decimal" Hidden="true" ExternalAccess="Read/Write"/>"#));
+        assert!(xml.contains(r#"<Member Name="Run" DataType="BIT" Dimension="0" Radix="Decimal" Hidden="false" Target="ZZZZZZZZZZMotor0" BitNumber="1" ExternalAccess="Read/Write"/>"#));
+        assert!(xml.contains(r#"<Member Name="Speed" DataType="REAL" Dimension="0" Radix="Float""#));
+        assert!(xml
+            .contains(r#"<Member Name="Faults" DataType="Fault" Dimension="2" Radix="NullType""#));
+
+        let string = Udt {
+            name: "STRING20".to_owned(),
+            instance_id: 0x0f3d,
+            handle: 0x0fce,
+            size: 24,
+            predefined: false,
+            members: vec![
+                member("LEN", "DINT", 0),
+                Member {
+                    dimension: 20,
+                    ..member("DATA", "SINT", 4)
+                },
+            ],
+        };
+        assert!(string.is_string());
+        assert!(to_l5x(&[string], "a&b").contains(r#"Family="StringFamily""#));
+        assert_eq!(escape(r#"<"a" & b>"#), "&lt;&quot;a&quot; &amp; b&gt;");
+        assert_eq!(
+            guess_format(Path::new("types.L5X")).unwrap(),
+            UdtFormat::L5x
+        );
+        assert!(guess_format(Path::new("types.txt")).is_err());
+    }
+}
@@ -0,0 +1,1234 @@
+use crate::audit::{Audit, AuditEntry};
+use crate::failure::Failure;
+use crate::pccc;
+use crate::plc::{self, Family, Plc};
+use crate::udt::StringType;
+use anyhow::{anyhow, bail, Context, Result};
+use bytes::Bytes;
+use clap::Args;
+use colored::*;
+use rseip::cip::MessageRequest;
+use rseip::client::ab_eip::*;
+use rseip::precludes::*;
+use serde::Serialize;
+use std::fmt::Display;
+use std::time::Duration;
+
+/// Largest Multiple Service Packet request sent by `read_values` and
+/// `write_values`, leaving
+/// room for the routing of unconnected messages.
+const MAX_BATCH_BYTES: usize = 480;
+
+/// Structure handle Logix reports for TIMER tags.
+const TIMER_HANDLE: u16 = 0x0F83;
+/// Structure handle Logix reports for COUNTER tags.
+const COUNTER_HANDLE: u16 = 0x0F82;
+/// Structure handle Logix reports for CONTROL tags.
+const CONTROL_HANDLE: u16 = 0x0F81;
+
+/// A tag value whose type is only known once the controller replies.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlcValue {
+    Bool(bool),
+    Sint(i8),
+    Int(i16),
+    Dint(i32),
+    Lint(i64),
+    Dword(u32),
+    Usint(u8),
+    Uint(u16),
+    Udint(u32),
+    Ulint(u64),
+    Real(f32),
+    Lreal(f64),
+    Timer(Timer),
+    Counter(Counter),
+    Control(Control),
+    String(LogixString),
+}
+
+/// Predefined TIMER structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct Timer {
+    pub pre: i32,
+    pub acc: i32,
+    pub en: bool,
+    pub tt: bool,
+    pub dn: bool,
+}
+
+/// Predefined COUNTER structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct Counter {
+    pub pre: i32,
+    pub acc: i32,
+    pub cu: bool,
+    pub cd: bool,
+    pub dn: bool,
+    pub ov: bool,
+    pub un: bool,
+}
+
+/// Predefined CONTROL structure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub struct Control {
+    pub len: i32,
+    pub pos: i32,
+    pub en: bool,
+    pub eu: bool,
+    pub dn: bool,
+    pub em: bool,
+    pub er: bool,
+    pub ul: bool,
+    #[serde(rename = "IN")]
+    pub inhibit: bool,
+    pub fd: bool,
+}
+
+/// A Logix string, of the predefined STRING type or of a string type of
+/// another length like STRING20. Characters are single bytes, taken as
+/// Latin-1.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogixString {
+    pub string_type: StringType,
+    pub text: String,
+}
+
+impl LogixString {
+    /// A string of the type, failing when the text doesn't fit it.
+    pub fn new(string_type: StringType, text: &str) -> Result<Self> {
+        if let Some(c) = text.chars().find(|c| *c as u32 > 0xFF) {
+            return Err(Failure::TypeMismatch.error(format!(
+                "`{}` can't be stored in a {}, it has no single byte character",
+                c, string_type.name
+            )));
+        }
+        let len = text.chars().count();
+        if len > string_type.capacity as usize {
+            return Err(Failure::TypeMismatch.error(format!(
+                "{} characters don't fit a {}, which holds {}",
+                len, string_type.name, string_type.capacity
+            )));
+        }
+        Ok(LogixString {
+            string_type,
+            text: text.to_owned(),
+        })
+    }
+
+    /// Decode the LEN and DATA members of a string.
+    fn decode(string_type: StringType, data: &[u8]) -> Result<Self> {
+        if data.len() < 4 {
+            bail!("expected 4 bytes of string length, got {}", data.len());
+        }
+        let len = i32::from_le_bytes(data[..4].try_into()?);
+        let chars = match usize::try_from(len) {
+            Ok(len) if len as u64 <= string_type.capacity as u64 => data.get(4..4 + len),
+            _ => bail!(
+                "length {} is out of range for a {} of {} characters",
+                len,
+                string_type.name,
+                string_type.capacity
+            ),
+        };
+        let Some(chars) = chars else {
+            bail!(
+                "expected {} characters of string data, got {}",
+                len,
+                data.len() - 4
+            );
+        };
+        Ok(LogixString {
+            text: chars.iter().map(|&c| c as char).collect(),
+            string_type,
+        })
+    }
+
+    /// Data of a write, the length and the characters padded to the size of
+    /// the type.
+    fn encode(&self) -> Bytes {
+        let mut data = vec![0; (self.string_type.size as usize).max(4 + self.text.len())];
+        let chars: Vec<u8> = self.text.chars().map(|c| c as u8).collect();
+        data[..4].copy_from_slice(&(chars.len() as i32).to_le_bytes());
+        data[4..4 + chars.len()].copy_from_slice(&chars);
+        Bytes::from(data)
+    }
+}
+
+/// The three predefined structures are a DINT of status bits, counted from
+/// bit 31 down, followed by two DINTs.
+fn decode_structure(data: &[u8]) -> Result<(u32, i32, i32)> {
+    if data.len() < 12 {
+        bail!("expected 12 bytes of structure data, got {}", data.len());
+    }
+    let bits = u32::from_le_bytes(data[..4].try_into()?);
+    let first = i32::from_le_bytes(data[4..8].try_into()?);
+    let second = i32::from_le_bytes(data[8..12].try_into()?);
+    Ok((bits, first, second))
+}
+
+fn bit(bits: u32, pos: u32) -> bool {
+    bits & (1 << pos) != 0
+}
+
+impl PlcValue {
+    /// Decode the data part of a read reply according to its tag type.
+    pub fn decode(tag_type: TagType, data: &[u8]) -> Result<Self> {
+        if let TagType::Structure(handle) = tag_type {
+            return Self::decode_structure(handle, data);
+        }
+        let size = match tag_type {
+            TagType::Bool | TagType::Sint | TagType::Usint => 1,
+            TagType::Int | TagType::Uint => 2,
+            TagType::Dint | TagType::Dword | TagType::Udint | TagType::Real => 4,
+            TagType::Lint | TagType::Ulint | TagType::Lreal => 8,
+            TagType::Structure(_) => unreachable!("decoded above"),
+        };
+        if data.len() < size {
+            bail!(
+                "expected {} bytes of {:?} data, got {}",
+                size,
+                tag_type,
+                data.len()
+            );
+        }
+        let value = match tag_type {
+            TagType::Bool => PlcValue::Bool(data[0] != 0),
+            TagType::Sint => PlcValue::Sint(data[0] as i8),
+            TagType::Int => PlcValue::Int(i16::from_le_bytes([data[0], data[1]])),
+            TagType::Dint => PlcValue::Dint(i32::from_le_bytes(data[..4].try_into()?)),
+            TagType::Dword => PlcValue::Dword(u32::from_le_bytes(data[..4].try_into()?)),
+            TagType::Real => PlcValue::Real(f32::from_le_bytes(data[..4].try_into()?)),
+            TagType::Lint => PlcValue::Lint(i64::from_le_bytes(data[..8].try_into()?)),
+            TagType::Usint => PlcValue::Usint(data[0]),
+            TagType::Uint => PlcValue::Uint(u16::from_le_bytes([data[0], data[1]])),
+            TagType::Udint => PlcValue::Udint(u32::from_le_bytes(data[..4].try_into()?)),
+            TagType::Ulint => PlcValue::Ulint(u64::from_le_bytes(data[..8].try_into()?)),
+            TagType::Lreal => PlcValue::Lreal(f64::from_le_bytes(data[..8].try_into()?)),
+            TagType::Structure(_) => unreachable!(),
+        };
+        Ok(value)
+    }
+
+    fn decode_structure(handle: u16, data: &[u8]) -> Result<Self> {
+        let value = match handle {
+            TIMER_HANDLE => {
+                let (bits, pre, acc) = decode_structure(data)?;
+                PlcValue::Timer(Timer {
+                    pre,
+                    acc,
+                    en: bit(bits, 31),
+                    tt: bit(bits, 30),
+                    dn: bit(bits, 29),
+                })
+            }
+            COUNTER_HANDLE => {
+                let (bits, pre, acc) = decode_structure(data)?;
+                PlcValue::Counter(Counter {
+                    pre,
+                    acc,
+                    cu: bit(bits, 31),
+                    cd: bit(bits, 30),
+                    dn: bit(bits, 29),
+                    ov: bit(bits, 28),
+                    un: bit(bits, 27),
+                })
+            }
+            CONTROL_HANDLE => {
+                let (bits, len, pos) = decode_structure(data)?;
+                PlcValue::Control(Control {
+                    len,
+                    pos,
+                    en: bit(bits, 31),
+                    eu: bit(bits, 30),
+                    dn: bit(bits, 29),
+                    em: bit(bits, 28),
+                    er: bit(bits, 27),
+                    ul: bit(bits, 26),
+                    inhibit: bit(bits, 25),
+                    fd: bit(bits, 24),
+                })
+            }
+            StringType::STRING_HANDLE => {
+                PlcValue::String(LogixString::decode(StringType::standard(), data)?)
+            }
+            _ => bail!(
+                "structured tags are not supported (handle {:#06x}), read their members instead",
+                handle
+            ),
+        };
+        Ok(value)
+    }
+
+    /// CIP type of the value.
+    pub fn tag_type(&self) -> TagType {
+        match self {
+            PlcValue::Bool(_) => TagType::Bool,
+            PlcValue::Sint(_) => TagType::Sint,
+            PlcValue::Int(_) => TagType::Int,
+            PlcValue::Dint(_) => TagType::Dint,
+            PlcValue::Lint(_) => TagType::Lint,
+            PlcValue::Dword(_) => TagType::Dword,
+            PlcValue::Usint(_) => TagType::Usint,
+            PlcValue::Uint(_) => TagType::Uint,
+            PlcValue::Udint(_) => TagType::Udint,
+            PlcValue::Ulint(_) => TagType::Ulint,
+            PlcValue::Real(_) => TagType::Real,
+            PlcValue::Lreal(_) => TagType::Lreal,
+            PlcValue::Timer(_) => TagType::Structure(TIMER_HANDLE),
+            PlcValue::Counter(_) => TagType::Structure(COUNTER_HANDLE),
+            PlcValue::Control(_) => TagType::Structure(CONTROL_HANDLE),
+            PlcValue::String(v) => TagType::Structure(v.string_type.handle),
+        }
+    }
+
+    /// Name of the type of the value, e.g. `Real` or `Timer`.
+    pub fn type_name(&self) -> String {
+        match self {
+            PlcValue::Timer(_) => "Timer".to_owned(),
+            PlcValue::Counter(_) => "Counter".to_owned(),
+            PlcValue::Control(_) => "Control".to_owned(),
+            PlcValue::String(v) => v.string_type.name.clone(),
+            _ => format!("{:?}", self.tag_type()),
+        }
+    }
+
+    /// Members of a timer, counter or control, by their Logix names. Other
+    /// values have none.
+    pub fn members(&self) -> Vec<(&'static str, PlcValue)> {
+        use PlcValue::{Bool, Dint};
+        match *self {
+            PlcValue::Timer(v) => vec![
+                ("PRE", Dint(v.pre)),
+                ("ACC", Dint(v.acc)),
+                ("EN", Bool(v.en)),
+                ("TT", Bool(v.tt)),
+                ("DN", Bool(v.dn)),
+            ],
+            PlcValue::Counter(v) => vec![
+                ("PRE", Dint(v.pre)),
+                ("ACC", Dint(v.acc)),
+                ("CU", Bool(v.cu)),
+                ("CD", Bool(v.cd)),
+                ("DN", Bool(v.dn)),
+                ("OV", Bool(v.ov)),
+                ("UN", Bool(v.un)),
+            ],
+            PlcValue::Control(v) => vec![
+                ("LEN", Dint(v.len)),
+                ("POS", Dint(v.pos)),
+                ("EN", Bool(v.en)),
+                ("EU", Bool(v.eu)),
+                ("DN", Bool(v.dn)),
+                ("EM", Bool(v.em)),
+                ("ER", Bool(v.er)),
+                ("UL", Bool(v.ul)),
+                ("IN", Bool(v.inhibit)),
+                ("FD", Bool(v.fd)),
+            ],
+            _ => Vec::new(),
+        }
+    }
+
+    /// Convert a JSON value to a value of the given tag type.
+    pub fn from_json(tag_type: TagType, value: &serde_json::Value) -> Result<Self> {
+        fn int<T: TryFrom<i128>>(value: &serde_json::Value) -> Option<T> {
+            let v = match value.as_i64() {
+                Some(v) => v as i128,
+                None => value.as_u64()? as i128,
+            };
+            T::try_from(v).ok()
+        }
+        let converted = match tag_type {
+            TagType::Bool => value.as_bool().map(PlcValue::Bool),
+            TagType::Sint => int(value).map(PlcValue::Sint),
+            TagType::Int => int(value).map(PlcValue::Int),
+            TagType::Dint => int(value).map(PlcValue::Dint),
+            TagType::Lint => int(value).map(PlcValue::Lint),
+            TagType::Dword => int(value).map(PlcValue::Dword),
+            TagType::Usint => int(value).map(PlcValue::Usint),
+            TagType::Uint => int(value).map(PlcValue::Uint),
+            TagType::Udint => int(value).map(PlcValue::Udint),
+            TagType::Ulint => int(value).map(PlcValue::Ulint),
+            TagType::Real => value.as_f64().map(|v| PlcValue::Real(v as f32)),
+            TagType::Lreal => value.as_f64().map(PlcValue::Lreal),
+            TagType::Structure(_) => {
+                bail!("structures can't be written whole, write their members instead")
+            }
+        };
+        match converted {
+            Some(converted) => Ok(converted),
+            None => Err(Failure::TypeMismatch
+                .error(format!("{} is not a valid {:?} value", value, tag_type))),
+        }
+    }
+
+    /// Convert a JSON value to a value of the type of another, like the
+    /// value a tag holds. Strings take JSON strings of the length they hold.
+    pub fn from_json_like(current: &PlcValue, value: &serde_json::Value) -> Result<Self> {
+        match (current, value) {
+            (PlcValue::String(current), serde_json::Value::String(text)) => Ok(PlcValue::String(
+                LogixString::new(current.string_type.clone(), text)?,
+            )),
+            (PlcValue::String(current), _) => Err(Failure::TypeMismatch.error(format!(
+                "{} is not a valid {} value, write a string",
+                value, current.string_type.name
+            ))),
+            _ => Self::from_json(current.tag_type(), value),
+        }
+    }
+
+    /// Convert text typed by an operator to a value of the type of another:
+    /// a number or boolean, or the text itself for strings.
+    pub fn parse_like(current: &PlcValue, text: &str) -> Result<Self> {
+        let json = match current {
+            PlcValue::String(_) => serde_json::Value::String(text.to_owned()),
+            _ => serde_json::from_str(text).map_err(|_| {
+                Failure::TypeMismatch.error(format!("`{}` is not a number or boolean", text))
+            })?,
+        };
+        Self::from_json_like(current, &json)
+    }
+
+    /// Numeric view of the value, booleans map to 0 and 1. Timers and
+    /// counters map to their accumulated value, controls to their position.
+    /// Strings have none and map to NaN.
+    pub fn as_f64(&self) -> f64 {
+        match *self {
+            PlcValue::Bool(v) => v as u8 as f64,
+            PlcValue::Sint(v) => v as f64,
+            PlcValue::Int(v) => v as f64,
+            PlcValue::Dint(v) => v as f64,
+            PlcValue::Lint(v) => v as f64,
+            PlcValue::Dword(v) => v as f64,
+            PlcValue::Usint(v) => v as f64,
+            PlcValue::Uint(v) => v as f64,
+            PlcValue::Udint(v) => v as f64,
+            PlcValue::Ulint(v) => v as f64,
+            PlcValue::Real(v) => v as f64,
+            PlcValue::Lreal(v) => v,
+            PlcValue::Timer(v) => v.acc as f64,
+            PlcValue::Counter(v) => v.acc as f64,
+            PlcValue::Control(v) => v.pos as f64,
+            PlcValue::String(_) => f64::NAN,
+        }
+    }
+
+    /// Value of the integer types and booleans, which can stand for a
+    /// state.
+    pub fn as_i64(&self) -> Option<i64> {
+        match *self {
+            PlcValue::Bool(v) => Some(v as i64),
+            PlcValue::Sint(v) => Some(v as i64),
+            PlcValue::Int(v) => Some(v as i64),
+            PlcValue::Dint(v) => Some(v as i64),
+            PlcValue::Lint(v) => Some(v),
+            PlcValue::Dword(v) | PlcValue::Udint(v) => Some(v as i64),
+            PlcValue::Usint(v) => Some(v as i64),
+            PlcValue::Uint(v) => Some(v as i64),
+            PlcValue::Ulint(v) => i64::try_from(v).ok(),
+            _ => None,
+        }
+    }
+
+    /// Raw bits and width of the integer types, the ones whose bits can be
+    /// addressed on their own.
+    fn bits(&self) -> Option<(u64, u32)> {
+        match *self {
+            PlcValue::Sint(v) => Some((v as u8 as u64, 8)),
+            PlcValue::Usint(v) => Some((v as u64, 8)),
+            PlcValue::Int(v) => Some((v as u16 as u64, 16)),
+            PlcValue::Uint(v) => Some((v as u64, 16)),
+            PlcValue::Dint(v) => Some((v as u32 as u64, 32)),
+            PlcValue::Dword(v) | PlcValue::Udint(v) => Some((v as u64, 32)),
+            PlcValue::Lint(v) => Some((v as u64, 64)),
+            PlcValue::Ulint(v) => Some((v, 64)),
+            _ => None,
+        }
+    }
+
+    /// Positions of the bits set in an integer value, lowest first.
+    pub fn set_bits(&self) -> Option<Vec<u32>> {
+        let (bits, width) = self.bits()?;
+        Some((0..width).filter(|pos| bits >> pos & 1 == 1).collect())
+    }
+
+    /// A single bit of an integer value, with the width of the value.
+    fn get_bit(&self, pos: u32) -> Result<(bool, u32)> {
+        match self.bits() {
+            Some((bits, width)) if pos < width => Ok((bits >> pos & 1 == 1, width)),
+            Some((_, width)) => bail!("bit {} is out of range for a {}-bit value", pos, width),
+            None => bail!("{} values have no addressable bits", self.type_name()),
+        }
+    }
+}
+
+impl Display for PlcValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlcValue::Bool(v) => write!(f, "{}", v),
+            PlcValue::Sint(v) => write!(f, "{}", v),
+            PlcValue::Int(v) => write!(f, "{}", v),
+            PlcValue::Dint(v) => write!(f, "{}", v),
+            PlcValue::Lint(v) => write!(f, "{}", v),
+            PlcValue::Dword(v) => write!(f, "{}", v),
+            PlcValue::Usint(v) => write!(f, "{}", v),
+            PlcValue::Uint(v) => write!(f, "{}", v),
+            PlcValue::Udint(v) => write!(f, "{}", v),
+            PlcValue::Ulint(v) => write!(f, "{}", v),
+            PlcValue::Real(v) => write!(f, "{}", v),
+            PlcValue::Lreal(v) => write!(f, "{}", v),
+            PlcValue::Timer(v) => write!(
+                f,
+                "PRE={} ACC={} EN={} TT={} DN={}",
+                v.pre, v.acc, v.en as u8, v.tt as u8, v.dn as u8
+            ),
+            PlcValue::Counter(v) => write!(
+                f,
+                "PRE={} ACC={} CU={} CD={} DN={} OV={} UN={}",
+                v.pre, v.acc, v.cu as u8, v.cd as u8, v.dn as u8, v.ov as u8, v.un as u8
+            ),
+            PlcValue::Control(v) => write!(
+                f,
+                "LEN={} POS={} EN={} EU={} DN={} EM={} ER={} UL={} IN={} FD={}",
+                v.len,
+                v.pos,
+                v.en as u8,
+                v.eu as u8,
+                v.dn as u8,
+                v.em as u8,
+                v.er as u8,
+                v.ul as u8,
+                v.inhibit as u8,
+                v.fd as u8
+            ),
+            PlcValue::String(v) => f.write_str(&v.text),
+        }
+    }
+}
+
+impl From<PlcValue> for serde_json::Value {
+    fn from(value: PlcValue) -> Self {
+        match value {
+            PlcValue::Bool(v) => v.into(),
+            PlcValue::Sint(v) => v.into(),
+            PlcValue::Int(v) => v.into(),
+            PlcValue::Dint(v) => v.into(),
+            PlcValue::Lint(v) => v.into(),
+            PlcValue::Dword(v) => v.into(),
+            PlcValue::Usint(v) => v.into(),
+            PlcValue::Uint(v) => v.into(),
+            PlcValue::Udint(v) => v.into(),
+            PlcValue::Ulint(v) => v.into(),
+            PlcValue::Real(v) => v.into(),
+            PlcValue::Lreal(v) => v.into(),
+            PlcValue::Timer(v) => serde_json::to_value(v).unwrap_or_default(),
+            PlcValue::Counter(v) => serde_json::to_value(v).unwrap_or_default(),
+            PlcValue::Control(v) => serde_json::to_value(v).unwrap_or_default(),
+            PlcValue::String(v) => v.text.into(),
+        }
+    }
+}
+
+/// Split a bit of an integer tag, e.g. `MyWord.5`, into the tag and bit.
+fn split_bit(tag: &str) -> Option<(&str, u32)> {
+    let (word, bit) = tag.rsplit_once('.')?;
+    if word.is_empty() || bit.is_empty() || !bit.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    Some((word, bit.parse().ok()?))
+}
+
+/// Split an element of a one dimension array, e.g. `MyBools[12]`, into the
+/// array and index.
+fn split_index(tag: &str) -> Option<(&str, u32)> {
+    let (array, index) = tag.strip_suffix(']')?.rsplit_once('[')?;
+    if array.is_empty() || array.ends_with(']') {
+        return None;
+    }
+    Some((array, index.trim().parse().ok()?))
+}
+
+/// BOOL arrays are packed 32 to a DWORD and are the only arrays reported
+/// with DWORD elements. Their element indexes count words rather than bits,
+/// so a bit index past the last word fails and the array is asked instead.
+async fn is_bool_array(client: &mut Plc, array: &str, element: &Result<PlcValue>) -> bool {
+    match element {
+        Ok(value) => matches!(value, PlcValue::Dword(_)),
+        Err(_) => matches!(read_plain(client, array).await, Ok(PlcValue::Dword(_))),
+    }
+}
+
+/// Read a tag without knowing its type in advance. Bits of integers are
+/// addressed as `MyWord.5` and elements of BOOL arrays as `MyBools[12]`.
+/// MicroLogix controllers are read by data table address instead, and the
+/// controllers of other protocols by their backend. The read is sent again
+/// over a new session when the controller dropped the last one, or to the
+/// failover controller when it stopped answering.
+pub async fn read_value(client: &mut Plc, tag: &str) -> Result<PlcValue> {
+    client.check_primary().await;
+    match plc::within(client.request_timeout(), read_once(client, tag)).await {
+        Err(e) if client.recover(&e, false).await => {
+            plc::within(client.request_timeout(), read_once(client, tag)).await
+        }
+        result => result,
+    }
+}
+
+async fn read_once(client: &mut Plc, tag: &str) -> Result<PlcValue> {
+    if let Some(backend) = client.backend() {
+        return backend.read(tag).await;
+    }
+    if client.family() == Family::MicroLogix {
+        return pccc::read(client, tag).await;
+    }
+    client.check_symbols(false).await;
+    if let Some((word, bit)) = split_bit(tag) {
+        let (value, _) = read_plain(client, word).await?.get_bit(bit)?;
+        return Ok(PlcValue::Bool(value));
+    }
+    let element = read_plain(client, tag).await;
+    if let Some((array, index)) = split_index(tag) {
+        if !matches!(element, Ok(PlcValue::Dword(_)) | Err(_)) {
+            return element;
+        }
+        if is_bool_array(client, array, &element).await {
+            let word = format!("{}[{}]", array, index / 32);
+            let (value, _) = read_plain(client, &word).await?.get_bit(index % 32)?;
+            return Ok(PlcValue::Bool(value));
+        }
+    }
+    element
+}
+
+/// Read several tags, addressed like for `read_value`, batching them into
+/// Multiple Service Packets so one request reaches many tags. Tags that
+/// fail in a batch, e.g. because their reply didn't fit, are read again on
+/// their own for a precise error.
+pub async fn read_values(client: &mut Plc, tags: &[String]) -> Vec<Result<PlcValue>> {
+    client.check_primary().await;
+    if let Some(backend) = client.backend() {
+        return backend.read_many(tags).await;
+    }
+    client.check_symbols(false).await;
+    let mut plain: Vec<Option<Result<PlcValue>>> = tags.iter().map(|_| None).collect();
+    if client.family().packs_requests() {
+        for batch in batches(tags, |tag| client.tag_path(tag).ok()) {
+            read_batch(client, tags, batch, &mut plain).await;
+        }
+    }
+
+    let mut values = Vec::with_capacity(tags.len());
+    for (tag, value) in tags.iter().zip(plain) {
+        let value = match (value, split_bit(tag)) {
+            (Some(Ok(word)), Some((_, bit))) => {
+                word.get_bit(bit).map(|(value, _)| PlcValue::Bool(value))
+            }
+            // Possibly a BOOL array, see `is_bool_array`.
+            (Some(Ok(PlcValue::Dword(_))), None) if split_index(tag).is_some() => {
+                read_value(client, tag).await
+            }
+            (Some(Ok(value)), None) => Ok(value),
+            _ => read_value(client, tag).await,
+        };
+        values.push(value);
+    }
+    values
+}
+
+/// Requests `read_values` makes for the tags, for load estimates. Tags
+/// needing a second look, like BOOL array elements, aren't counted.
+pub fn request_count(tags: &[String], family: Family) -> usize {
+    if !family.packs_requests() {
+        return tags.len();
+    }
+    batches(tags, |tag| EPath::parse_tag(tag).ok())
+        .iter()
+        .map(|batch| if batch.len() < 2 { batch.len() } else { 1 })
+        .sum()
+}
+
+/// Read Tag requests for the tags, by tag index, packed into batches that
+/// fit a Multiple Service Packet. Tags that aren't valid are left out.
+fn batches(
+    tags: &[String],
+    tag_path: impl Fn(&str) -> Option<EPath>,
+) -> Vec<Vec<(usize, MessageRequest<EPath, u16>)>> {
+    let requests = tags.iter().enumerate().filter_map(|(idx, tag)| {
+        let word = split_bit(tag).map_or(tag.as_str(), |(word, _)| word);
+        let path = tag_path(word)?;
+        Some((idx, MessageRequest::new(SERVICE_READ_TAG, path, 1u16)))
+    });
+    pack(requests)
+}
+
+/// Requests packed into batches that fit a Multiple Service Packet.
+fn pack<D: Encode>(
+    requests: impl IntoIterator<Item = (usize, MessageRequest<EPath, D>)>,
+) -> Vec<Vec<(usize, MessageRequest<EPath, D>)>> {
+    let mut batches = Vec::new();
+    let mut batch = Vec::new();
+    let mut size = 2;
+    for (idx, request) in requests {
+        // Each service also takes an offset in the packet.
+        let len = 2 + request.bytes_count();
+        if size + len > MAX_BATCH_BYTES {
+            batches.push(std::mem::take(&mut batch));
+            size = 2;
+        }
+        batch.push((idx, request));
+        size += len;
+    }
+    batches.push(batch);
+    batches
+}
+
+/// Read the plain tags of a batch with one request, storing the replies by
+/// index. A batch of one is left to a regular read.
+async fn read_batch(
+    client: &mut Plc,
+    tags: &[String],
+    batch: Vec<(usize, MessageRequest<EPath, u16>)>,
+    plain: &mut [Option<Result<PlcValue>>],
+) {
+    if batch.len() < 2 {
+        return;
+    }
+    let (indexes, requests): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+    let names: Vec<&str> = indexes.iter().map(|idx| tags[*idx].as_str()).collect();
+    let timeout = client.request_timeout();
+    let Ok(session) = client.eip() else {
+        return;
+    };
+    let request = session
+        .multiple_service()
+        .push_all(requests.into_iter())
+        .call();
+    let names = names.join(",");
+    let request = plc::transaction("multiple service", &names, request);
+    let mut replies = match plc::within(timeout, request).await {
+        Ok(replies) => replies,
+        // The tags are then read on their own, from the failover
+        // controller if this one stopped answering.
+        Err(e) => {
+            client.recover(&e, false).await;
+            return;
+        }
+    };
+    for idx in indexes {
+        match replies.next::<TagValue<Bytes>>() {
+            Some(Ok(reply)) => {
+                plain[idx] = Some(PlcValue::decode(reply.data.tag_type, &reply.data.value))
+            }
+            Some(Err(_)) => {}
+            None => break,
+        }
+    }
+}
+
+async fn read_plain(client: &mut Plc, tag: &str) -> Result<PlcValue> {
+    let path = client.tag_path(tag)?;
+    let tag_value: TagValue<Bytes> =
+        plc::transaction("read", tag, client.eip()?.read_tag(path)).await?;
+    if let TagType::Structure(handle) = tag_value.tag_type {
+        // The length of string types other than STRING is in their
+        // template.
+        if !matches!(handle, TIMER_HANDLE | COUNTER_HANDLE | CONTROL_HANDLE) {
+            let string_type = client
+                .string_type(tag, handle)
+                .await
+                .with_context(|| format!("failed to read the structure type of {}", tag))?;
+            if let Some(string_type) = string_type {
+                let value = LogixString::decode(string_type, &tag_value.value)?;
+                return Ok(PlcValue::String(value));
+            }
+        }
+    }
+    PlcValue::decode(tag_value.tag_type, &tag_value.value)
+}
+
+/// Write a value to a tag, with the type of the value. Bits are set or
+/// cleared with the controller's Read Modify Write service, so the other
+/// bits of the word keep whatever the program writes to them meanwhile.
+///
+/// Under `--dry-run` the tag is read instead, and the write only printed
+/// once the value is found to fit it. Other writes are recorded in the
+/// audit file with the value they replace.
+pub async fn write_value(client: &mut Plc, tag: &str, value: PlcValue) -> Result<()> {
+    if let PlcValue::Timer(_) | PlcValue::Counter(_) | PlcValue::Control(_) = value {
+        bail!("structures can't be written whole, write their members instead");
+    }
+    client.check_writable(Some(tag))?;
+    if plc::dry_run() {
+        let current = read_value(client, tag).await?;
+        if current.tag_type() != value.tag_type() {
+            return Err(Failure::TypeMismatch.error(format!(
+                "{} is a {}, it can't take the {} {}",
+                tag,
+                current.type_name(),
+                value.type_name(),
+                value
+            )));
+        }
+        println!(
+            "{}    {} = {} ({}), currently {}",
+            "DRY RUN".bold().yellow(),
+            tag.bold(),
+            value.to_string().bold(),
+            value.type_name(),
+            current
+        );
+        return Ok(());
+    }
+    let mut audit = Audit::open()?;
+    let old = read_value(client, tag).await.ok();
+    let result = write_checked(client, tag, value.clone()).await;
+    audit.record(&AuditEntry::new(client.address(), tag, old, value, &result));
+    result
+}
+
+/// Write a value without the dry run and the read of the old value of
+/// `write_value`, for the loops writing computed values every cycle.
+/// Read-only tags are still refused. The audit file gets one entry per tag
+/// on change: a write is recorded when its value or its outcome differs
+/// from the last one recorded for the tag, so a value written every cycle
+/// is recorded once.
+pub async fn write_unaudited(client: &mut Plc, tag: &str, value: PlcValue) -> Result<()> {
+    client.check_writable(Some(tag))?;
+    let mut audit = Audit::open()?;
+    let result = write_checked(client, tag, value.clone()).await;
+    audit.record_change(client.address(), tag, &value, &result);
+    result
+}
+
+/// Write a value after the checks and the confirmation of the session,
+/// leaving the audit to the caller. The write is sent again over a new
+/// session when the controller refused the last one, as nothing was
+/// written then, or to the failover controller when it couldn't reach
+/// this one.
+async fn write_checked(client: &mut Plc, tag: &str, value: PlcValue) -> Result<()> {
+    client.check_writable(Some(tag))?;
+    client.confirm_write(|address| format!("Write {} to {} on {}?", value, tag, address))?;
+    client.check_primary().await;
+    match plc::within(
+        client.request_timeout(),
+        write_once(client, tag, value.clone()),
+    )
+    .await
+    {
+        Err(e) if client.recover(&e, true).await => {
+            plc::within(client.request_timeout(), write_once(client, tag, value)).await
+        }
+        result => result,
+    }
+}
+
+/// Write several values, addressed and audited like for `write_unaudited`,
+/// batching the plain tags of elementary types into Multiple Service
+/// Packets so one request reaches many tags. Bits, strings and the writes
+/// that fail in a batch are written on their own, the latter for a precise
+/// error.
+pub async fn write_values(client: &mut Plc, values: &[(String, PlcValue)]) -> Vec<Result<()>> {
+    let mut written: Vec<Option<Result<()>>> = values.iter().map(|_| None).collect();
+    let confirmed =
+        match values {
+            [] => Ok(()),
+            [(tag, value)] => client
+                .confirm_write(|address| format!("Write {} to {} on {}?", value, tag, address)),
+            _ => client
+                .confirm_write(|address| format!("Write {} values to {}?", values.len(), address)),
+        };
+    if let Err(e) = confirmed {
+        return values
+            .iter()
+            .map(|_| Err(Failure::WriteRejected.error(&e)))
+            .collect();
+    }
+    let mut audit = match Audit::open() {
+        Ok(audit) => audit,
+        Err(e) => return values.iter().map(|_| Err(anyhow!("{:#}", e))).collect(),
+    };
+    client.check_primary().await;
+    if client.backend().is_none() && client.family().packs_requests() {
+        client.check_symbols(true).await;
+        let requests: Vec<_> = values
+            .iter()
+            .enumerate()
+            .filter_map(|(idx, (tag, value))| {
+                // Bits, and possibly BOOL array elements, are read first.
+                if split_bit(tag).is_some()
+                    || (matches!(value, PlcValue::Bool(_)) && split_index(tag).is_some())
+                    || client.check_writable(Some(tag)).is_err()
+                {
+                    return None;
+                }
+                let data = write_data(value)?;
+                let path = client.tag_path(tag).ok()?;
+                Some((idx, MessageRequest::new(SERVICE_WRITE_TAG, path, data)))
+            })
+            .collect();
+        for batch in pack(requests) {
+            write_batch(client, values, batch, &mut written).await;
+        }
+    }
+
+    let mut results = Vec::with_capacity(values.len());
+    for ((tag, value), result) in values.iter().zip(written) {
+        let result = match result {
+            Some(result) => result,
+            None => write_checked(client, tag, value.clone()).await,
+        };
+        if client.check_writable(Some(tag)).is_ok() {
+            audit.record_change(client.address(), tag, value, &result);
+        }
+        results.push(result);
+    }
+    results
+}
+
+/// Data of a Write Tag request of a value of an elementary type: its type,
+/// one element, and the value.
+fn write_data(value: &PlcValue) -> Option<Bytes> {
+    let (code, bytes): (u16, Vec<u8>) = match *value {
+        PlcValue::Bool(v) => (0xC1, vec![if v { 0xFF } else { 0 }]),
+        PlcValue::Sint(v) => (0xC2, v.to_le_bytes().to_vec()),
+        PlcValue::Int(v) => (0xC3, v.to_le_bytes().to_vec()),
+        PlcValue::Dint(v) => (0xC4, v.to_le_bytes().to_vec()),
+        PlcValue::Lint(v) => (0xC5, v.to_le_bytes().to_vec()),
+        PlcValue::Usint(v) => (0xC6, v.to_le_bytes().to_vec()),
+        PlcValue::Uint(v) => (0xC7, v.to_le_bytes().to_vec()),
+        PlcValue::Udint(v) => (0xC8, v.to_le_bytes().to_vec()),
+        PlcValue::Ulint(v) => (0xC9, v.to_le_bytes().to_vec()),
+        PlcValue::Real(v) => (0xCA, v.to_le_bytes().to_vec()),
+        PlcValue::Lreal(v) => (0xCB, v.to_le_bytes().to_vec()),
+        PlcValue::Dword(v) => (0xD3, v.to_le_bytes().to_vec()),
+        _ => return None,
+    };
+    let mut data = code.to_le_bytes().to_vec();
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&bytes);
+    Some(Bytes::from(data))
+}
+
+/// Write the values of a batch with one request, storing the writes that
+/// went through by index, or all of them as failed when the request may
+/// have reached the controller without being answered. A batch of one is
+/// left to a regular write.
+async fn write_batch(
+    client: &mut Plc,
+    values: &[(String, PlcValue)],
+    batch: Vec<(usize, MessageRequest<EPath, Bytes>)>,
+    written: &mut [Option<Result<()>>],
+) {
+    if batch.len() < 2 {
+        return;
+    }
+    let (indexes, requests): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+    let names: Vec<&str> = indexes.iter().map(|idx| values[*idx].0.as_str()).collect();
+    let timeout = client.request_timeout();
+    let Ok(session) = client.eip() else {
+        return;
+    };
+    let request = session
+        .multiple_service()
+        .push_all(requests.into_iter())
+        .call();
+    let names = names.join(",");
+    let request = plc::transaction("multiple service", &names, request);
+    let mut replies = match plc::within(timeout, request).await {
+        Ok(replies) => replies,
+        // The values are written on their own when the request never
+        // reached the controller, and fail otherwise, as they may have
+        // been written all the same.
+        Err(e) => {
+            if !client.recover(&e, true).await {
+                let failure = Failure::of(&e);
+                for idx in indexes {
+                    written[idx] = Some(Err(failure.error(format!("{:#}", e))));
+                }
+            }
+            return;
+        }
+    };
+    for idx in indexes {
+        match replies.next::<()>() {
+            Some(Ok(_)) => written[idx] = Some(Ok(())),
+            Some(Err(_)) => {}
+            None => break,
+        }
+    }
+}
+
+async fn write_once(client: &mut Plc, tag: &str, value: PlcValue) -> Result<()> {
+    if let Some(backend) = client.backend() {
+        return backend.write(tag, value).await;
+    }
+    if client.family() == Family::MicroLogix {
+        return pccc::write(client, tag, value).await;
+    }
+    client.check_symbols(true).await;
+    if let Some((word, bit)) = split_bit(tag) {
+        let set = match value {
+            PlcValue::Bool(set) => set,
+            _ => {
+                return Err(
+                    Failure::TypeMismatch.error(format!("`{}` is a bit, write true or false", tag))
+                )
+            }
+        };
+        let (_, width) = read_plain(client, word).await?.get_bit(bit)?;
+        return write_bit(client, word, width, bit, set).await;
+    }
+    if let (PlcValue::Bool(set), Some((array, index))) = (&value, split_index(tag)) {
+        let set = *set;
+        let element = read_plain(client, tag).await;
+        if is_bool_array(client, array, &element).await {
+            let word = format!("{}[{}]", array, index / 32);
+            return write_bit(client, &word, 32, index % 32, set).await;
+        }
+    }
+    write_plain(client, tag, value).await
+}
+
+/// Reading a tag back after writing it, for `--verify`.
+#[derive(Args, Debug, Clone)]
+pub struct ReadBackArgs {
+    /// Read the tag back after the write and fail if it holds another
+    /// value, e.g. because the logic overwrote it right away.
+    #[arg(long)]
+    pub verify: bool,
+    /// Time the controller logic gets to act on the write before the tag is
+    /// read back.
+    #[arg(
+        long,
+        default_value = "100ms",
+        value_parser = humantime::parse_duration,
+        requires = "verify"
+    )]
+    pub verify_after: Duration,
+}
+
+impl ReadBackArgs {
+    /// Check that the tag still holds the value written, when asked to.
+    pub async fn check(&self, client: &mut Plc, tag: &str, written: PlcValue) -> Result<()> {
+        if !self.verify || plc::dry_run() {
+            return Ok(());
+        }
+        tokio::time::sleep(self.verify_after).await;
+        let actual = read_value(client, tag).await?;
+        if !holds(&actual, &written) {
+            bail!(
+                "{} reads {} after writing {}; the controller logic may have overwritten it",
+                tag,
+                actual,
+                written
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Whether a tag read back holds the value written, which may have been
+/// written with another integer type than the tag's.
+fn holds(actual: &PlcValue, written: &PlcValue) -> bool {
+    match (actual, written) {
+        (PlcValue::String(actual), PlcValue::String(written)) => actual.text == written.text,
+        _ => actual.as_f64() == written.as_f64(),
+    }
+}
+
+async fn write_bit(client: &mut Plc, word: &str, width: u32, bit: u32, set: bool) -> Result<()> {
+    let path = client.tag_path(word)?;
+    let session = client.eip()?;
+    let request = async move {
+        match width {
+            8 => {
+                session
+                    .read_modify_write(bit_mask::<1>(path, bit, set))
+                    .await
+            }
+            16 => {
+                session
+                    .read_modify_write(bit_mask::<2>(path, bit, set))
+                    .await
+            }
+            32 => {
+                session
+                    .read_modify_write(bit_mask::<4>(path, bit, set))
+                    .await
+            }
+            64 => {
+                session
+                    .read_modify_write(bit_mask::<8>(path, bit, set))
+                    .await
+            }
+            _ => unreachable!("integers are 8 to 64 bits wide"),
+        }
+    };
+    plc::transaction("read modify write", word, request).await?;
+    Ok(())
+}
+
+/// Masks setting or clearing a single bit of a little endian word.
+fn bit_mask<const N: usize>(path: EPath, bit: u32, set: bool) -> ReadModifyWriteRequest<N> {
+    let mut req = ReadModifyWriteRequest::<N>::new().tag(path);
+    let (byte, mask) = ((bit / 8) as usize, 1u8 << (bit % 8));
+    if set {
+        req.or_mask_mut()[byte] |= mask;
+    } else {
+        req.and_mask_mut()[byte] &= !mask;
+    }
+    req
+}
+
+async fn write_plain(client: &mut Plc, tag: &str, value: PlcValue) -> Result<()> {
+    let path = client.tag_path(tag)?;
+    let tag_type = value.tag_type();
+    let session = client.eip()?;
+    let request = async move {
+        match value {
+            PlcValue::Bool(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Sint(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Int(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Dint(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Lint(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Dword(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Usint(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Uint(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Udint(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Ulint(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Real(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            PlcValue::Lreal(value) => session.write_tag(path, TagValue { tag_type, value }).await,
+            // One structure of the raw data.
+            PlcValue::String(value) => {
+                let value = [value.encode()];
+                session.write_tag(path, TagValue { tag_type, value }).await
+            }
+            PlcValue::Timer(_) | PlcValue::Counter(_) | PlcValue::Control(_) => {
+                unreachable!("structures are rejected by write_value")
+            }
+        }
+    };
+    plc::transaction("write", tag, request).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decode_timer() {
+        let mut data = (1u32 << 31 | 1 << 29).to_le_bytes().to_vec();
+        data.extend(5000i32.to_le_bytes());
+        data.extend(1200i32.to_le_bytes());
+        let value = PlcValue::decode(TagType::Structure(TIMER_HANDLE), &data).unwrap();
+        assert_eq!(value.to_string(), "PRE=5000 ACC=1200 EN=1 TT=0 DN=1");
+        assert_eq!(value.as_f64(), 1200.0);
+        let json = serde_json::Value::from(value);
+        assert_eq!(json["PRE"], 5000);
+        assert_eq!(json["DN"], true);
+        assert!(PlcValue::decode(TagType::Structure(0x1234), &data).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let mut data = 5i32.to_le_bytes().to_vec();
+        data.extend_from_slice(b"Ready");
+        data.resize(88, 0);
+        let value = PlcValue::decode(TagType::Structure(StringType::STRING_HANDLE), &data).unwrap();
+        assert_eq!(value.to_string(), "Ready");
+        assert_eq!(value.type_name(), "STRING");
+        assert_eq!(serde_json::Value::from(value.clone()), "Ready");
+
+        let string20 = StringType {
+            name: "STRING20".to_owned(),
+            handle: 0x1014,
+            capacity: 20,
+            size: 24,
+        };
+        let current = PlcValue::String(LogixString::decode(string20.clone(), &data[..24]).unwrap());
+        assert_eq!(current.tag_type(), TagType::Structure(0x1014));
+        let value = PlcValue::parse_like(&current, "Batch 7").unwrap();
+        let PlcValue::String(string) = &value else {
+            panic!("{:?} is no string", value)
+        };
+        let encoded = string.encode();
+        assert_eq!(encoded.len(), 24);
+        assert_eq!(encoded[..11], *b"\x07\0\0\0Batch 7");
+        assert!(!holds(&value, &current));
+
+        let error = PlcValue::parse_like(&current, "twenty one characters").unwrap_err();
+        assert_eq!(Failure::of(&error), Failure::TypeMismatch);
+        assert!(PlcValue::from_json_like(&current, &serde_json::json!(5)).is_err());
+        // A length past the DATA array, or past the data of the reply.
+        let mut long = 21i32.to_le_bytes().to_vec();
+        long.resize(24, 0);
+        assert!(LogixString::decode(string20, &long).is_err());
+        assert!(LogixString::decode(StringType::standard(), &long).is_err());
+    }
+
+    #[test]
+    fn bit_addresses() {
+        assert_eq!(split_bit("MyWord.5"), Some(("MyWord", 5)));
+        assert_eq!(split_bit("Pump.Status.31"), Some(("Pump.Status", 31)));
+        assert_eq!(split_bit("Pump.Speed"), None);
+        assert_eq!(split_index("MyBools[12]"), Some(("MyBools", 12)));
+        assert_eq!(split_index("Grid[1,2]"), None);
+        assert_eq!(split_index("Grid[1][2]"), None);
+        assert_eq!(PlcValue::Int(-1).get_bit(15).unwrap(), (true, 16));
+        assert_eq!(PlcValue::Dint(1 << 5).get_bit(4).unwrap(), (false, 32));
+        assert!(PlcValue::Int(0).get_bit(16).is_err());
+        assert!(PlcValue::Real(1.0).get_bit(0).is_err());
+    }
+
+    #[test]
+    fn elementary_types() {
+        let json = serde_json::json!(u64::MAX);
+        let value = PlcValue::from_json(TagType::Ulint, &json).unwrap();
+        assert_eq!(value, PlcValue::Ulint(u64::MAX));
+        assert!(PlcValue::from_json(TagType::Usint, &serde_json::json!(-1)).is_err());
+        let value = PlcValue::decode(TagType::Lreal, &0.1f64.to_le_bytes()).unwrap();
+        assert_eq!(value, PlcValue::Lreal(0.1));
+        assert_eq!(value.type_name(), "Lreal");
+    }
+
+    #[test]
+    fn batching() {
+        let tags: Vec<String> = (0..100).map(|i| format!("Flow_Totals[{}]", i)).collect();
+        let batches = batches(&tags, |tag| EPath::parse_tag(tag).ok());
+        assert!(batches.len() > 1);
+        assert_eq!(batches.iter().map(Vec::len).sum::<usize>(), 100);
+        assert_eq!(request_count(&tags, Family::CompactLogix), batches.len());
+        assert_eq!(request_count(&tags[..1], Family::CompactLogix), 1);
+        assert_eq!(
+            request_count(
+                &["MyWord.5".to_owned(), "PT_101".to_owned()],
+                Family::CompactLogix
+            ),
+            1
+        );
+        assert_eq!(request_count(&tags, Family::Micro800), tags.len());
+
+        let data = write_data(&PlcValue::Real(1.5)).unwrap();
+        assert_eq!(data[..4], [0xCA, 0x00, 0x01, 0x00]);
+        assert_eq!(data[4..], 1.5f32.to_le_bytes());
+        assert_eq!(
+            write_data(&PlcValue::Bool(true)).unwrap()[..],
+            [0xC1, 0x00, 0x01, 0x00, 0xFF]
+        );
+        let text = LogixString::new(StringType::standard(), "on").unwrap();
+        assert_eq!(write_data(&PlcValue::String(text)), None);
+    }
+
+    #[test]
+    fn read_back() {
+        assert!(holds(&PlcValue::Dint(5), &PlcValue::Int(5)));
+        assert!(holds(&PlcValue::Real(0.1), &PlcValue::Real(0.1)));
+        assert!(holds(&PlcValue::Bool(true), &PlcValue::Bool(true)));
+        assert!(!holds(&PlcValue::Dint(3), &PlcValue::Dint(5)));
+    }
+}
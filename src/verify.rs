@@ -0,0 +1,243 @@
+use crate::plc::Plc;
+use crate::tagcache::TagCache;
+use crate::value;
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use colored::*;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    /// Checks file with one `[[check]]` table per tag.
+    #[arg(long)]
+    pub file: PathBuf,
+    /// Only check that the tags exist, in the tag list cached for the
+    /// controller at `--address`, without connecting to it.
+    #[arg(long)]
+    pub offline: bool,
+}
+
+/// Value a check expects, a number or a boolean.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Expected {
+    Bool(bool),
+    Number(f64),
+}
+
+impl Expected {
+    fn as_f64(self) -> f64 {
+        match self {
+            Expected::Bool(v) => v as u8 as f64,
+            Expected::Number(v) => v,
+        }
+    }
+}
+
+impl Display for Expected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expected::Bool(v) => write!(f, "{}", v),
+            Expected::Number(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// A tag expected to hold a value, within `tolerance` for numbers.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Check {
+    pub tag: String,
+    pub expected: Expected,
+    #[serde(default)]
+    pub tolerance: f64,
+}
+
+/// A TOML file of commissioning checks.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChecksFile {
+    #[serde(default, rename = "check")]
+    pub checks: Vec<Check>,
+}
+
+impl ChecksFile {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read checks {}", path.display()))?;
+        let file: Self = toml::from_str(&text)
+            .with_context(|| format!("failed to parse checks {}", path.display()))?;
+        for check in &file.checks {
+            if !(check.tolerance.is_finite() && check.tolerance >= 0.0) {
+                bail!(
+                    "tolerance {} of {} must be zero or more",
+                    check.tolerance,
+                    check.tag
+                );
+            }
+        }
+        Ok(file)
+    }
+}
+
+/// Why a check failed.
+enum Failure {
+    /// The tag held another value, off by the deviation.
+    Mismatch { actual: String, deviation: f64 },
+    /// The tag couldn't be read.
+    Unreadable(String),
+}
+
+/// Compare a reading against a check, returning the deviation when it is
+/// out of tolerance.
+fn deviation(check: &Check, actual: f64) -> Option<f64> {
+    let deviation = actual - check.expected.as_f64();
+    if deviation.abs() <= check.tolerance {
+        None
+    } else {
+        Some(deviation)
+    }
+}
+
+/// Read every tag of the checks file and report the ones that don't hold
+/// their expected value. Fails if any check does, so test scripts can rely
+/// on the exit status.
+pub async fn run(client: &mut Plc, args: &VerifyArgs) -> Result<()> {
+    let file = ChecksFile::load(&args.file)?;
+    if file.checks.is_empty() {
+        bail!("no [[check]] entries in {}", args.file.display());
+    }
+
+    let mut failures = Vec::new();
+    for check in &file.checks {
+        let failure = match value::read_value(client, &check.tag).await {
+            Ok(actual) => deviation(check, actual.as_f64()).map(|deviation| Failure::Mismatch {
+                actual: actual.to_string(),
+                deviation,
+            }),
+            Err(e) => Some(Failure::Unreadable(format!("{:#}", e))),
+        };
+        match failure {
+            None => println!("    {}    {}", "PASS".bold().green(), check.tag),
+            Some(failure) => {
+                println!("    {}    {}", "FAIL".bold().red(), check.tag.bold());
+                failures.push((check, failure));
+            }
+        }
+    }
+
+    if failures.is_empty() {
+        println!("All {} checks passed.", file.checks.len());
+        return Ok(());
+    }
+
+    println!();
+    for (check, failure) in &failures {
+        println!("{}", check.tag.bold());
+        match failure {
+            Failure::Mismatch { actual, deviation } => {
+                let tolerance = if check.tolerance > 0.0 {
+                    format!(" ± {}", check.tolerance)
+                } else {
+                    String::new()
+                };
+                println!(
+                    "    {} {}{}",
+                    "-".red(),
+                    check.expected.to_string().red(),
+                    tolerance
+                );
+                println!("    {} {}", "+".green(), actual.green());
+                println!("    deviation {:+}", deviation);
+            }
+            Failure::Unreadable(e) => println!("    {}", e.red()),
+        }
+    }
+    bail!("{} of {} checks failed", failures.len(), file.checks.len());
+}
+
+/// Check the tags of the checks file against the cached tag list, so a
+/// checks file can be prepared away from the controller. Values aren't
+/// checked, only that every tag is there.
+pub fn run_offline(address: Option<&str>, args: &VerifyArgs) -> Result<()> {
+    let file = ChecksFile::load(&args.file)?;
+    if file.checks.is_empty() {
+        bail!("no [[check]] entries in {}", args.file.display());
+    }
+    let cache = TagCache::find(address)?;
+
+    let mut missing = 0;
+    for check in &file.checks {
+        match cache.get(&check.tag) {
+            Some(record) => println!(
+                "    {}    {}    {}",
+                "FOUND".bold().green(),
+                check.tag,
+                record.r#type
+            ),
+            None => {
+                println!("    {}  {}", "MISSING".bold().red(), check.tag.bold());
+                missing += 1;
+            }
+        }
+    }
+    let listed = cache
+        .listed
+        .with_timezone(&chrono::Local)
+        .format("%Y-%m-%d %H:%M");
+    if missing > 0 {
+        bail!(
+            "{} of {} tags are missing from the tags of {} listed {}",
+            missing,
+            file.checks.len(),
+            cache.product_name,
+            listed
+        );
+    }
+    println!(
+        "All {} tags exist in the tags of {} listed {}.",
+        file.checks.len(),
+        cache.product_name,
+        listed
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checks() {
+        let file: ChecksFile = toml::from_str(
+            r#"
+            [[check]]
+            tag = "FT_201"
+            expected = 12.5
+            tolerance = 0.1
+
+            [[check]]
+            tag = "Pump_Run"
+            expected = true
+
+            [[check]]
+            tag = "Batch_Count"
+            expected = 3
+            "#,
+        )
+        .unwrap();
+        let [flow, pump, count] = &file.checks[..] else {
+            panic!("expected three checks");
+        };
+        assert_eq!(pump.expected, Expected::Bool(true));
+        assert_eq!(count.expected, Expected::Number(3.0));
+
+        assert_eq!(deviation(flow, 12.55), None);
+        assert!(deviation(flow, 12.7).unwrap() > 0.19);
+        assert_eq!(deviation(pump, 1.0), None);
+        assert_eq!(deviation(pump, 0.0), Some(-1.0));
+        assert_eq!(deviation(count, 4.0), Some(1.0));
+    }
+}
@@ -0,0 +1,402 @@
+use crate::historian::Journal;
+use crate::plc::Plc;
+use crate::tags::TagList;
+use crate::value::{self, PlcValue};
+use anyhow::{bail, Result};
+use clap::Args;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind};
+use futures_util::StreamExt;
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style, Stylize};
+use ratatui::widgets::{Cell, Paragraph, Row, Table, TableState};
+use ratatui::{DefaultTerminal, Frame};
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Window over which the update rate of a tag is computed.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+const KEYS: &str = "↑↓ select   e edit   s sort   r reverse   q quit";
+
+/// Options of the `watch` command.
+#[derive(Args)]
+pub struct WatchArgs {
+    /// Tags to watch.
+    pub tags: Vec<String>,
+    /// Tag list file with more tags to watch.
+    #[arg(long = "tags")]
+    pub tag_list: Option<PathBuf>,
+    /// Polling interval in milliseconds.
+    #[arg(long, default_value_t = 1000)]
+    pub interval: u64,
+}
+
+/// Column the table is sorted by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SortColumn {
+    Tag,
+    Value,
+    Previous,
+    Delta,
+    Rate,
+}
+
+impl SortColumn {
+    fn next(self) -> Self {
+        match self {
+            SortColumn::Tag => SortColumn::Value,
+            SortColumn::Value => SortColumn::Previous,
+            SortColumn::Previous => SortColumn::Delta,
+            SortColumn::Delta => SortColumn::Rate,
+            SortColumn::Rate => SortColumn::Tag,
+        }
+    }
+}
+
+/// A watched tag.
+struct TagRow {
+    tag: String,
+    current: Option<PlcValue>,
+    /// Value before the last change.
+    previous: Option<PlcValue>,
+    /// When the value changed, within the rate window.
+    changes: VecDeque<Instant>,
+    error: Option<String>,
+}
+
+impl TagRow {
+    fn new(tag: String) -> Self {
+        Self {
+            tag,
+            current: None,
+            previous: None,
+            changes: VecDeque::new(),
+            error: None,
+        }
+    }
+
+    fn update(&mut self, value: PlcValue, now: Instant) {
+        self.error = None;
+        if self.current.as_ref() != Some(&value) {
+            // The first reading is not a change.
+            if self.current.is_some() {
+                self.changes.push_back(now);
+            }
+            self.previous = self.current.replace(value);
+        }
+    }
+
+    fn delta(&self) -> Option<f64> {
+        Some(self.current.as_ref()?.as_f64() - self.previous.as_ref()?.as_f64())
+    }
+
+    /// Changes per second over the rate window, or since the watch started.
+    fn rate(&mut self, now: Instant, started: Instant) -> f64 {
+        while let Some(&first) = self.changes.front() {
+            if now.duration_since(first) <= RATE_WINDOW {
+                break;
+            }
+            self.changes.pop_front();
+        }
+        let window = now.duration_since(started).min(RATE_WINDOW).as_secs_f64();
+        if window > 0.0 {
+            self.changes.len() as f64 / window
+        } else {
+            0.0
+        }
+    }
+}
+
+struct Watch {
+    rows: Vec<TagRow>,
+    started: Instant,
+    sort: SortColumn,
+    reverse: bool,
+    state: TableState,
+    /// Text typed for the selected tag while editing.
+    edit: Option<String>,
+    status: String,
+}
+
+impl Watch {
+    fn selected(&self) -> Option<&TagRow> {
+        self.rows.get(self.state.selected()?)
+    }
+
+    /// Sort the rows, keeping the same tag selected.
+    fn sort(&mut self) {
+        let selected = self.selected().map(|row| row.tag.clone());
+        let (now, started) = (Instant::now(), self.started);
+        for row in &mut self.rows {
+            row.rate(now, started);
+        }
+        let sort = self.sort;
+        self.rows.sort_by(|a, b| {
+            let ordering = match sort {
+                SortColumn::Tag => a.tag.cmp(&b.tag),
+                SortColumn::Value => compare(
+                    a.current.as_ref().map(PlcValue::as_f64),
+                    b.current.as_ref().map(PlcValue::as_f64),
+                ),
+                SortColumn::Previous => compare(
+                    a.previous.as_ref().map(PlcValue::as_f64),
+                    b.previous.as_ref().map(PlcValue::as_f64),
+                ),
+                SortColumn::Delta => compare(a.delta(), b.delta()),
+                SortColumn::Rate => a.changes.len().cmp(&b.changes.len()),
+            };
+            ordering.then_with(|| a.tag.cmp(&b.tag))
+        });
+        if self.reverse {
+            self.rows.reverse();
+        }
+        let idx = selected.and_then(|tag| self.rows.iter().position(|row| row.tag == tag));
+        self.state.select(idx.or(Some(0)));
+    }
+}
+
+/// Missing values sort first.
+fn compare(a: Option<f64>, b: Option<f64>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (a, b) => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+/// Show a live table of the tags, polled every interval, with in place
+/// editing of their values.
+pub async fn run(client: &mut Plc, args: &WatchArgs, journal: &Journal) -> Result<()> {
+    let mut tags = args.tags.clone();
+    if let Some(path) = &args.tag_list {
+        for tag in TagList::load(path)?.tags {
+            if !tags.contains(&tag.name) {
+                tags.push(tag.name);
+            }
+        }
+    }
+    if tags.is_empty() {
+        bail!("no tags to watch");
+    }
+
+    let mut watch = Watch {
+        rows: tags.into_iter().map(TagRow::new).collect(),
+        started: Instant::now(),
+        sort: SortColumn::Tag,
+        reverse: false,
+        state: TableState::default().with_selected(0),
+        edit: None,
+        status: String::new(),
+    };
+    watch.sort();
+
+    let mut terminal = ratatui::try_init()?;
+    let result = event_loop(&mut terminal, client, args, journal, &mut watch).await;
+    ratatui::restore();
+    client.close().await?;
+    result
+}
+
+async fn event_loop(
+    terminal: &mut DefaultTerminal,
+    client: &mut Plc,
+    args: &WatchArgs,
+    journal: &Journal,
+    watch: &mut Watch,
+) -> Result<()> {
+    let mut ticker = tokio::time::interval(Duration::from_millis(args.interval));
+    let mut events = EventStream::new();
+    loop {
+        terminal.draw(|frame| draw(frame, watch))?;
+        tokio::select! {
+            _ = ticker.tick() => poll(client, watch).await,
+            event = events.next() => match event {
+                Some(Ok(Event::Key(key))) if key.kind == KeyEventKind::Press => {
+                    if !handle_key(client, journal, watch, key).await {
+                        return Ok(());
+                    }
+                }
+                Some(Ok(_)) => {}
+                Some(Err(e)) => return Err(e.into()),
+                None => return Ok(()),
+            },
+        }
+    }
+}
+
+async fn poll(client: &mut Plc, watch: &mut Watch) {
+    for row in &mut watch.rows {
+        match value::read_value(client, &row.tag).await {
+            Ok(value) => row.update(value, Instant::now()),
+            Err(e) => {
+                row.error = Some(e.to_string());
+                // Start over with a new session on the next poll.
+                let _ = client.close().await;
+            }
+        }
+    }
+    if watch.sort != SortColumn::Tag {
+        watch.sort();
+    }
+}
+
+/// Handle a key press, returning false to quit.
+async fn handle_key(client: &mut Plc, journal: &Journal, watch: &mut Watch, key: KeyEvent) -> bool {
+    if let Some(text) = &mut watch.edit {
+        match key.code {
+            KeyCode::Char(c) => text.push(c),
+            KeyCode::Backspace => {
+                text.pop();
+            }
+            KeyCode::Esc => watch.edit = None,
+            KeyCode::Enter => {
+                let text = watch.edit.take().unwrap_or_default();
+                watch.status = match write(client, journal, watch, text.trim()).await {
+                    Ok(status) => status,
+                    Err(e) => {
+                        let _ = client.close().await;
+                        format!("Error: {:#}", e)
+                    }
+                };
+            }
+            _ => {}
+        }
+        return true;
+    }
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => return false,
+        KeyCode::Up | KeyCode::Char('k') => watch.state.select_previous(),
+        KeyCode::Down | KeyCode::Char('j') => {
+            let last = watch.rows.len() - 1;
+            let next = watch.state.selected().map_or(0, |idx| (idx + 1).min(last));
+            watch.state.select(Some(next));
+        }
+        KeyCode::Char('e') | KeyCode::Enter => {
+            if let Some(row) = watch.selected() {
+                watch.edit = Some(
+                    row.current
+                        .as_ref()
+                        .map(PlcValue::to_string)
+                        .unwrap_or_default(),
+                );
+                watch.status.clear();
+            }
+        }
+        KeyCode::Char('s') => {
+            watch.sort = watch.sort.next();
+            watch.sort();
+        }
+        KeyCode::Char('r') => {
+            watch.reverse = !watch.reverse;
+            watch.sort();
+        }
+        _ => {}
+    }
+    true
+}
+
+/// Write the typed text to the selected tag, converted to its current type.
+async fn write(
+    client: &mut Plc,
+    journal: &Journal,
+    watch: &mut Watch,
+    text: &str,
+) -> Result<String> {
+    let Some(idx) = watch.state.selected() else {
+        return Ok(String::new());
+    };
+    let row = &mut watch.rows[idx];
+    let current = match &row.current {
+        Some(current) => current.clone(),
+        None => value::read_value(client, &row.tag).await?,
+    };
+    let value = PlcValue::parse_like(&current, text)?;
+    value::write_value(client, &row.tag, value.clone()).await?;
+    journal.record_operator("write", &row.tag, Some(value.to_string()))?;
+    let status = format!("Wrote {} to {}", value, row.tag);
+    row.update(value, Instant::now());
+    Ok(status)
+}
+
+fn draw(frame: &mut Frame, watch: &mut Watch) {
+    let [table_area, status_area] =
+        Layout::vertical([Constraint::Min(1), Constraint::Length(1)]).areas(frame.area());
+
+    let (now, started) = (Instant::now(), watch.started);
+    let editing = watch.edit.is_some();
+    let selected = watch.state.selected();
+    let rows: Vec<Row> = watch
+        .rows
+        .iter_mut()
+        .enumerate()
+        .map(|(idx, row)| {
+            let rate = row.rate(now, started);
+            let value = match (&watch.edit, &row.error) {
+                (Some(text), _) if selected == Some(idx) => {
+                    Cell::from(format!("{}▏", text)).yellow().bold()
+                }
+                (_, Some(e)) => Cell::from(e.clone()).red(),
+                _ => Cell::from(display(row.current.as_ref())).green().bold(),
+            };
+            let delta = row.delta().map(|d| format!("{:+}", d)).unwrap_or_default();
+            Row::new(vec![
+                Cell::from(row.tag.clone()).bold(),
+                Cell::from(
+                    row.current
+                        .as_ref()
+                        .map(PlcValue::type_name)
+                        .unwrap_or_default(),
+                ),
+                value,
+                Cell::from(display(row.previous.as_ref())),
+                Cell::from(delta),
+                Cell::from(format!("{:.2}/s", rate)),
+            ])
+        })
+        .collect();
+
+    let titles = [
+        (Some(SortColumn::Tag), "Tag"),
+        (None, "Type"),
+        (Some(SortColumn::Value), "Value"),
+        (Some(SortColumn::Previous), "Previous"),
+        (Some(SortColumn::Delta), "Delta"),
+        (Some(SortColumn::Rate), "Rate"),
+    ];
+    let header = Row::new(titles.map(|(column, title)| {
+        if column == Some(watch.sort) {
+            format!("{} {}", title, if watch.reverse { "▼" } else { "▲" })
+        } else {
+            title.to_owned()
+        }
+    }))
+    .style(Style::new().add_modifier(Modifier::BOLD | Modifier::UNDERLINED));
+
+    let widths = [
+        Constraint::Fill(3),
+        Constraint::Length(8),
+        Constraint::Fill(2),
+        Constraint::Fill(2),
+        Constraint::Fill(1),
+        Constraint::Length(9),
+    ];
+    let table = Table::new(rows, widths)
+        .header(header)
+        .row_highlight_style(Style::new().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ");
+    frame.render_stateful_widget(table, table_area, &mut watch.state);
+
+    let status = if editing {
+        "Enter write   Esc cancel"
+    } else if watch.status.is_empty() {
+        KEYS
+    } else {
+        &watch.status
+    };
+    frame.render_widget(Paragraph::new(status).dim(), status_area);
+}
+
+fn display(value: Option<&PlcValue>) -> String {
+    value.map(|v| v.to_string()).unwrap_or_default()
+}
@@ -0,0 +1,381 @@
+use crate::monitor::Sample;
+use crate::value::PlcValue;
+use anyhow::{bail, Context, Result};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+/// Port of the trapper of Zabbix servers and proxies.
+const DEFAULT_PORT: u16 = 10051;
+
+/// Values kept in memory while the server is unreachable; the oldest are
+/// dropped beyond this.
+const MAX_PENDING: usize = 100_000;
+
+/// Longest wait between two retries of a failed send.
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Time the server has to take the values and answer.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Largest response accepted from the server.
+const MAX_RESPONSE: usize = 1024 * 1024;
+
+/// Zabbix output options of `monitor`.
+#[derive(Args, Debug, Clone)]
+pub struct ZabbixArgs {
+    /// Zabbix server or proxy to send the values to, as host[:port], like
+    /// zabbix_sender.
+    #[arg(long)]
+    pub zabbix: Option<String>,
+    /// Host the values belong to in Zabbix, the target name by default
+    /// when polling several controllers.
+    #[arg(long)]
+    pub zabbix_host: Option<String>,
+    /// Key of the trapper items, taking the tag as its parameter, e.g.
+    /// `cobalt[PT_101]`.
+    #[arg(long, default_value = "cobalt")]
+    pub zabbix_key: String,
+    /// Maximum number of values per request.
+    #[arg(long, default_value_t = 250)]
+    pub zabbix_batch: usize,
+}
+
+/// Sends the samples to Zabbix trapper items from a background task, with
+/// the zabbix_sender protocol.
+pub struct ZabbixSink {
+    host: String,
+    key: String,
+    /// Target of the samples, named in the keys when the host is shared.
+    plc: Option<String>,
+    tx: mpsc::UnboundedSender<Item>,
+}
+
+impl ZabbixSink {
+    /// Start the sender task. Returns `None` when Zabbix output is not
+    /// enabled.
+    pub fn connect(args: &ZabbixArgs, target: Option<&str>) -> Result<Option<Self>> {
+        let Some(server) = &args.zabbix else {
+            return Ok(None);
+        };
+        let server = match server.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => server.clone(),
+            Some(_) => bail!("invalid Zabbix server `{}`, expected host[:port]", server),
+            None => format!("{}:{}", server, DEFAULT_PORT),
+        };
+        let (host, plc) = match (&args.zabbix_host, target) {
+            (Some(host), target) => (host.clone(), target.map(str::to_owned)),
+            (None, Some(target)) => (target.to_owned(), None),
+            (None, None) => bail!("give the Zabbix host of the controller with --zabbix-host"),
+        };
+        if !valid_key(&args.zabbix_key) {
+            bail!(
+                "invalid Zabbix item key `{}`, expected letters, digits, `_`, `-` and `.`",
+                args.zabbix_key
+            );
+        }
+
+        let (tx, rx) = mpsc::unbounded_channel();
+        let sender = Sender {
+            server,
+            batch: args.zabbix_batch.max(1),
+        };
+        tokio::spawn(sender.run(rx));
+
+        Ok(Some(Self {
+            host,
+            key: args.zabbix_key.clone(),
+            plc,
+            tx,
+        }))
+    }
+
+    /// Queue the samples of a polling cycle.
+    pub fn push(&self, samples: &[Sample]) {
+        for sample in samples {
+            let item = Item {
+                host: self.host.clone(),
+                key: item_key(&self.key, self.plc.as_deref(), &sample.tag),
+                value: item_value(&sample.value),
+                clock: sample.timestamp.timestamp(),
+                ns: sample.timestamp.timestamp_subsec_nanos(),
+            };
+            // The sender only stops when the sink is dropped.
+            let _ = self.tx.send(item);
+        }
+    }
+}
+
+/// A value of a trapper item, as zabbix_sender sends it.
+#[derive(Debug, Clone, Serialize)]
+struct Item {
+    host: String,
+    key: String,
+    value: String,
+    clock: i64,
+    ns: u32,
+}
+
+/// Whether a key name is made of the characters Zabbix allows.
+fn valid_key(key: &str) -> bool {
+    !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Item key of a tag, like `cobalt[PT_101]`, or `cobalt[line1,PT_101]`
+/// naming the controller when several share a host. Parameters are quoted
+/// when they hold characters of the key syntax, like the brackets of
+/// array elements.
+fn item_key(key: &str, plc: Option<&str>, tag: &str) -> String {
+    let params: Vec<String> = plc.into_iter().chain([tag]).map(param).collect();
+    format!("{}[{}]", key, params.join(","))
+}
+
+fn param(text: &str) -> String {
+    if text.contains([',', '[', ']', '"', ' ']) {
+        format!("\"{}\"", text.replace('"', "\\\""))
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Value of a sample as Zabbix items take it: BOOL as 0 or 1, and the
+/// accumulator or position of timers, counters and controls.
+fn item_value(value: &PlcValue) -> String {
+    match value {
+        PlcValue::Bool(v) => (*v as u8).to_string(),
+        PlcValue::Timer(_) | PlcValue::Counter(_) | PlcValue::Control(_) => {
+            value.as_f64().to_string()
+        }
+        v => v.to_string(),
+    }
+}
+
+#[derive(Serialize)]
+struct Request<'a> {
+    request: &'static str,
+    data: &'a [Item],
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    response: String,
+    #[serde(default)]
+    info: String,
+}
+
+/// Frame a message with the header of the Zabbix protocol: `ZBXD`, the
+/// flags, then the length of the data.
+fn frame(data: &[u8]) -> Vec<u8> {
+    let mut packet = b"ZBXD\x01".to_vec();
+    packet.extend_from_slice(&(data.len() as u64).to_le_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+/// Number of values the server failed, from the `info` of its response,
+/// like `processed: 3; failed: 1; total: 4; seconds spent: 0.000055`.
+fn failed(info: &str) -> usize {
+    info.split(';')
+        .find_map(|part| part.trim().strip_prefix("failed:"))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+enum Failure {
+    /// The server answered but took none of the values, sending them
+    /// again won't help.
+    Rejected(String),
+    /// Transient failure, the values are sent again later.
+    Retry(String),
+}
+
+struct Sender {
+    server: String,
+    batch: usize,
+}
+
+impl Sender {
+    async fn run(self, mut rx: mpsc::UnboundedReceiver<Item>) {
+        let mut pending = VecDeque::new();
+        let mut backoff = Duration::ZERO;
+        let mut retry_at = Instant::now();
+        let mut closed = false;
+
+        while !closed || !pending.is_empty() {
+            if !closed && (pending.is_empty() || Instant::now() < retry_at) {
+                // Wait for values, or for the next retry of the pending ones.
+                let received = if pending.is_empty() {
+                    Ok(rx.recv().await)
+                } else {
+                    tokio::time::timeout_at(retry_at.into(), rx.recv()).await
+                };
+                match received {
+                    Ok(Some(item)) => pending.push_back(item),
+                    Ok(None) => closed = true,
+                    Err(_) => {}
+                }
+            }
+            while let Ok(item) = rx.try_recv() {
+                pending.push_back(item);
+            }
+            if pending.len() > MAX_PENDING {
+                let dropped = pending.len() - MAX_PENDING;
+                pending.drain(..dropped);
+                tracing::warn!("Zabbix unreachable, dropped {} values", dropped);
+            }
+            if pending.is_empty() || Instant::now() < retry_at {
+                continue;
+            }
+
+            let count = pending.len().min(self.batch);
+            let items: Vec<Item> = pending.iter().take(count).cloned().collect();
+            match self.send(&items).await {
+                Ok(()) => {
+                    pending.drain(..count);
+                    backoff = Duration::ZERO;
+                }
+                Err(Failure::Rejected(e)) => {
+                    tracing::error!("Zabbix rejected {} values: {}", count, e);
+                    pending.drain(..count);
+                }
+                Err(Failure::Retry(e)) => {
+                    backoff = (backoff * 2).clamp(Duration::from_secs(1), MAX_BACKOFF);
+                    retry_at = Instant::now() + backoff;
+                    tracing::warn!("Zabbix send failed, retrying in {:?}: {}", backoff, e);
+                    if closed {
+                        // Nobody is left to wait for the retry.
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Send the values in one request. Values of items the server doesn't
+    /// have, or of the wrong type, are failed by the server and only
+    /// warned about.
+    async fn send(&self, items: &[Item]) -> Result<(), Failure> {
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, self.exchange(items))
+            .await
+            .map_err(|_| Failure::Retry("the server didn't answer in time".to_owned()))?
+            .map_err(|e| Failure::Retry(format!("{:#}", e)))?;
+        if response.response != "success" {
+            return Err(Failure::Rejected(format!(
+                "{}: {}",
+                response.response, response.info
+            )));
+        }
+        match failed(&response.info) {
+            0 => {}
+            count if count == items.len() => return Err(Failure::Rejected(response.info)),
+            count => tracing::warn!(
+                "Zabbix failed {} of {} values, check the trapper items of the host: {}",
+                count,
+                items.len(),
+                response.info
+            ),
+        }
+        Ok(())
+    }
+
+    async fn exchange(&self, items: &[Item]) -> Result<Response> {
+        let request = Request {
+            request: "sender data",
+            data: items,
+        };
+        let body = serde_json::to_vec(&request)?;
+        let mut stream = TcpStream::connect(&self.server)
+            .await
+            .with_context(|| format!("failed to connect to {}", self.server))?;
+        stream.write_all(&frame(&body)).await?;
+
+        let mut header = [0; 13];
+        stream.read_exact(&mut header).await?;
+        if &header[..4] != b"ZBXD" {
+            bail!("not a Zabbix response");
+        }
+        let len = u64::from_le_bytes(header[5..].try_into().unwrap()) as usize;
+        if len > MAX_RESPONSE {
+            bail!("response of {} bytes is too large", len);
+        }
+        let mut data = vec![0; len];
+        stream.read_exact(&mut data).await?;
+        serde_json::from_slice(&data).context("invalid Zabbix response")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::monitor::Quality;
+    use chrono::{Local, TimeZone};
+
+    #[test]
+    fn items() {
+        assert_eq!(item_key("cobalt", None, "PT_101"), "cobalt[PT_101]");
+        assert_eq!(
+            item_key("cobalt", Some("line1"), "Counts[3]"),
+            "cobalt[line1,\"Counts[3]\"]"
+        );
+        assert_eq!(
+            item_key("plc.tag", None, "Program:Main.Flow"),
+            "plc.tag[Program:Main.Flow]"
+        );
+        assert!(!valid_key("cobalt[x]"));
+        assert_eq!(item_value(&PlcValue::Bool(true)), "1");
+        assert_eq!(item_value(&PlcValue::Real(2.5)), "2.5");
+
+        assert_eq!(&frame(b"{}")[..], b"ZBXD\x01\x02\0\0\0\0\0\0\0{}");
+        assert_eq!(
+            failed("processed: 3; failed: 1; total: 4; seconds spent: 0.000055"),
+            1
+        );
+        assert_eq!(failed("unexpected"), 0);
+    }
+
+    #[tokio::test]
+    async fn send() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut header = [0; 13];
+            stream.read_exact(&mut header).await.unwrap();
+            let len = u64::from_le_bytes(header[5..].try_into().unwrap()) as usize;
+            let mut data = vec![0; len];
+            stream.read_exact(&mut data).await.unwrap();
+            let info = r#"{"response":"success","info":"processed: 1; failed: 0; total: 1; seconds spent: 0.000055"}"#;
+            stream.write_all(&frame(info.as_bytes())).await.unwrap();
+            serde_json::from_slice::<serde_json::Value>(&data).unwrap()
+        });
+
+        let args = ZabbixArgs {
+            zabbix: Some(address),
+            zabbix_host: Some("Line 1 PLC".to_owned()),
+            zabbix_key: "cobalt".to_owned(),
+            zabbix_batch: 250,
+        };
+        let sink = ZabbixSink::connect(&args, None).unwrap().unwrap();
+        sink.push(&[Sample {
+            timestamp: Local.timestamp_opt(1_700_000_000, 5).unwrap(),
+            tag: "PT_101".to_owned(),
+            value: PlcValue::Real(12.5),
+            quality: Quality::Good,
+        }]);
+        let request = server.await.unwrap();
+        assert_eq!(request["request"], "sender data");
+        let item = &request["data"][0];
+        assert_eq!(item["host"], "Line 1 PLC");
+        assert_eq!(item["key"], "cobalt[PT_101]");
+        assert_eq!(item["value"], "12.5");
+        assert_eq!(item["clock"], 1_700_000_000);
+        assert_eq!(item["ns"], 5);
+    }
+}
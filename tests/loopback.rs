@@ -0,0 +1,1004 @@
+//! Commands run end to end against the built-in simulator, so they can be
+//! tested without a controller.
+
+use clap::Parser;
+use cobalt::check::{Alert, State};
+use cobalt::failure::Failure;
+use cobalt::plc::{self, ConnectionArgs, Plc};
+use cobalt::sim::{SimFile, Simulator};
+use cobalt::value::{self, PlcValue};
+use std::path::PathBuf;
+use std::sync::Once;
+use std::time::Duration;
+
+const TAGS: &str = r#"
+[identity]
+product_name = "1756-L83E/B"
+serial_number = 0x00c0ffee
+
+[[tag]]
+name = "PT_101"
+type = "REAL"
+value = 12.5
+
+[[tag]]
+name = "TT_101"
+type = "REAL"
+value = 15.0
+
+[[tag]]
+name = "Counts"
+type = "DINT[4]"
+value = [1, 2, 3]
+
+[[tag]]
+name = "Status"
+type = "DINT"
+
+[[tag]]
+name = "Pump_Run"
+type = "BOOL"
+
+[[tag]]
+name = "FT_201"
+type = "REAL"
+
+[[tag]]
+name = "FT_201_Base"
+type = "REAL"
+
+[[tag]]
+name = "Level"
+type = "REAL"
+generate = { kind = "ramp", from = 0, to = 100, period = "1h" }
+"#;
+
+/// Scratch directory of the tests, also taking the cache and audit files.
+fn scratch() -> PathBuf {
+    static SETUP: Once = Once::new();
+    let dir = std::env::temp_dir().join(format!("cobalt-loopback-{}", std::process::id()));
+    SETUP.call_once(|| {
+        std::fs::create_dir_all(&dir).unwrap();
+        std::env::set_var("XDG_CACHE_HOME", dir.join("cache"));
+    });
+    dir
+}
+
+/// Start a simulator with the test tags, returning its address.
+async fn simulator() -> String {
+    serve(TAGS).await
+}
+
+async fn serve(tags: &str) -> String {
+    let file: SimFile = toml::from_str(tags).unwrap();
+    let address = Simulator::new(&file)
+        .unwrap()
+        .spawn("127.0.0.1:0")
+        .await
+        .unwrap();
+    address.to_string()
+}
+
+async fn connect(address: &str, flags: &[&str]) -> Plc {
+    plc::connect(address, &connection(flags)).await.unwrap()
+}
+
+fn connection(flags: &[&str]) -> ConnectionArgs {
+    #[derive(Parser)]
+    struct Cli {
+        #[command(flatten)]
+        connection: ConnectionArgs,
+    }
+    Cli::parse_from(std::iter::once("cobalt").chain(flags.iter().copied())).connection
+}
+
+/// Run a command line against the simulator at the address.
+async fn cobalt(address: &str, args: &[&str]) -> Result<(), String> {
+    run(address, args).await.map_err(|e| e.to_string())
+}
+
+/// Run a command line like `cobalt`, keeping the error to classify it.
+async fn run(address: &str, args: &[&str]) -> anyhow::Result<()> {
+    let dir = scratch();
+    let audit = dir.join("audit.jsonl");
+    let mut line = vec![
+        "cobalt",
+        "-a",
+        address,
+        "--audit-file",
+        audit.to_str().unwrap(),
+    ];
+    #[cfg(feature = "historian")]
+    let journal = dir.join("journal.jsonl");
+    #[cfg(feature = "historian")]
+    line.extend(["--journal", journal.to_str().unwrap()]);
+    line.extend(args);
+    let cli = cobalt::Args::try_parse_line(line)?;
+    cobalt::run(cli).await
+}
+
+async fn read(client: &mut Plc, tag: &str) -> PlcValue {
+    value::read_value(client, tag).await.unwrap()
+}
+
+#[tokio::test]
+async fn read_and_write() {
+    scratch();
+    let address = simulator().await;
+    for flags in [&[][..], &["--connected"], &["--tag-cache"]] {
+        let mut client = connect(&address, flags).await;
+        assert_eq!(read(&mut client, "PT_101").await, PlcValue::Real(12.5));
+        assert_eq!(read(&mut client, "counts[2]").await, PlcValue::Dint(3));
+
+        value::write_value(&mut client, "Counts[3]", PlcValue::Dint(-4))
+            .await
+            .unwrap();
+        assert_eq!(read(&mut client, "Counts[3]").await, PlcValue::Dint(-4));
+        value::write_value(&mut client, "Status.3", PlcValue::Bool(true))
+            .await
+            .unwrap();
+        assert_eq!(read(&mut client, "Status.3").await, PlcValue::Bool(true));
+        value::write_value(&mut client, "Status.3", PlcValue::Bool(false))
+            .await
+            .unwrap();
+        assert_eq!(read(&mut client, "Status").await, PlcValue::Dint(0));
+
+        let error = value::write_value(&mut client, "PT_101", PlcValue::Dint(1)).await;
+        assert!(error.is_err());
+        assert!(value::read_value(&mut client, "Valve").await.is_err());
+        assert!(value::read_value(&mut client, "Counts[4]").await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn idle_sessions() {
+    scratch();
+    let address = simulator().await;
+    // The connection times out after 32 intervals of 10 ms.
+    let mut client = connect(&address, &["--connected", "--rpi", "10"]).await;
+    assert_eq!(client.keep_alive_interval(), Duration::from_millis(80));
+    assert_eq!(read(&mut client, "PT_101").await, PlcValue::Real(12.5));
+
+    // Kept alive past the timeout.
+    for _ in 0..5 {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.keep_alive().await;
+    }
+    value::write_unaudited(&mut client, "Status", PlcValue::Dint(7))
+        .await
+        .unwrap();
+
+    // Timed out, the read goes over a new connection.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(read(&mut client, "Status").await, PlcValue::Dint(7));
+}
+
+#[tokio::test]
+async fn batched_reads() {
+    scratch();
+    let address = simulator().await;
+    let mut client = connect(&address, &[]).await;
+    let tags: Vec<String> = [
+        "PT_101",
+        "Counts[0]",
+        "Valve",
+        "Status.0",
+        "Level",
+        "TT_101",
+    ]
+    .iter()
+    .map(|tag| tag.to_string())
+    .collect();
+    let values = value::read_values(&mut client, &tags).await;
+    assert_eq!(values[0].as_ref().unwrap(), &PlcValue::Real(12.5));
+    assert_eq!(values[1].as_ref().unwrap(), &PlcValue::Dint(1));
+    assert!(values[2].is_err());
+    assert_eq!(values[3].as_ref().unwrap(), &PlcValue::Bool(false));
+    assert!(matches!(values[4], Ok(PlcValue::Real(level)) if (0.0..100.0).contains(&level)));
+    assert_eq!(values[5].as_ref().unwrap(), &PlcValue::Real(15.0));
+}
+
+#[tokio::test]
+async fn micro800() {
+    scratch();
+    let address = serve(&TAGS.replace("[identity]", "[identity]\nfamily = \"micro800\"")).await;
+    let mut client = connect(&address, &["--family", "micro800"]).await;
+    let tags: Vec<String> = ["PT_101", "Counts[2]", "Status.0"]
+        .iter()
+        .map(|tag| tag.to_string())
+        .collect();
+    let values = value::read_values(&mut client, &tags).await;
+    assert_eq!(values[0].as_ref().unwrap(), &PlcValue::Real(12.5));
+    assert_eq!(values[1].as_ref().unwrap(), &PlcValue::Dint(3));
+    assert_eq!(values[2].as_ref().unwrap(), &PlcValue::Bool(false));
+    value::write_value(&mut client, "Status.1", PlcValue::Bool(true))
+        .await
+        .unwrap();
+    assert_eq!(read(&mut client, "Status").await, PlcValue::Dint(2));
+
+    cobalt(&address, &["--family", "micro800", "list"])
+        .await
+        .unwrap();
+    // Routed requests don't reach a Micro800.
+    assert!(cobalt(&address, &["read", "PT_101"]).await.is_err());
+    assert!(cobalt(&address, &["--connected", "read", "PT_101"])
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn healthcheck() {
+    let dir = scratch();
+    let address = simulator().await;
+    let other = simulator().await;
+    let targets = dir.join("healthcheck.toml");
+    std::fs::write(
+        &targets,
+        format!("[[target]]\nname = \"line2\"\naddress = \"{other}\"\nheartbeat = \"Level\"\n"),
+    )
+    .unwrap();
+    let targets = targets.to_str().unwrap();
+    cobalt(
+        &address,
+        &["--targets", targets, "healthcheck", "--heartbeat", "PT_101"],
+    )
+    .await
+    .unwrap();
+    // The ramp moves a little each second.
+    let interval = ["--heartbeat-interval", "1500ms"];
+    cobalt(
+        &other,
+        &[&["healthcheck", "--heartbeat", "Level"][..], &interval].concat(),
+    )
+    .await
+    .unwrap();
+
+    let error = cobalt(
+        &address,
+        &["--targets", targets, "healthcheck", "--heartbeat", "Valve"],
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("1 of 2 controllers failed"), "{}", error);
+    let error = cobalt(
+        &address,
+        &[&["healthcheck", "--heartbeat", "PT_101"][..], &interval].concat(),
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("1 of 1"), "{}", error);
+}
+
+#[tokio::test]
+async fn record_and_replay() {
+    let dir = scratch();
+    let address = simulator().await;
+    let recording = dir.join("session.bin");
+    let recording = recording.to_str().unwrap();
+    cobalt(&address, &["--record", recording, "read", "PT_101"])
+        .await
+        .unwrap();
+    assert!(std::fs::metadata(recording).unwrap().len() > 100);
+    cobalt(&address, &["replay", recording, "--hex"])
+        .await
+        .unwrap();
+
+    let error = cobalt(&address, &["replay", "Cargo.toml"])
+        .await
+        .unwrap_err();
+    assert!(error.contains("isn't a recording"), "{}", error);
+}
+
+#[tokio::test]
+async fn readable_errors() {
+    scratch();
+    let address = simulator().await;
+    let error = cobalt(&address, &["read", "Missing"]).await.unwrap_err();
+    assert_eq!(
+        error,
+        "path segment error: the tag or object doesn't exist, or its path is malformed (CIP status 0x04)"
+    );
+    let error = cobalt(&address, &["write-int", "PT_101", "3"])
+        .await
+        .unwrap_err();
+    assert!(
+        error.ends_with(
+            "the data type of the request doesn't match the tag (CIP status 0xff, extended status 0x2107)"
+        ),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn failures() {
+    scratch();
+    let address = simulator().await;
+    let failure = |result: anyhow::Result<()>| Failure::of(&result.unwrap_err());
+    assert_eq!(
+        failure(run(&address, &["read", "Missing"]).await),
+        Failure::TagNotFound
+    );
+    assert_eq!(
+        failure(run(&address, &["write-int", "PT_101", "3"]).await),
+        Failure::TypeMismatch
+    );
+    assert_eq!(
+        failure(run(&address, &["read-bool", "PT_101"]).await),
+        Failure::TypeMismatch
+    );
+    assert_eq!(
+        failure(run(&address, &["--read-only", "write", "FT_201", "1"]).await),
+        Failure::WriteRejected
+    );
+    assert_eq!(
+        failure(run("127.0.0.1:1", &["read", "PT_101"]).await),
+        Failure::Connection
+    );
+    assert_eq!(Failure::TagNotFound.code(), 3);
+}
+
+#[tokio::test]
+async fn bench() {
+    scratch();
+    let address = simulator().await;
+    let args = ["bench", "--tag", "Status", "--iterations", "20"];
+    cobalt(
+        &address,
+        &[&args[..], &["--multi", "5", "--write"]].concat(),
+    )
+    .await
+    .unwrap();
+    cobalt(&address, &[&["--connected"][..], &args].concat())
+        .await
+        .unwrap();
+
+    let error = cobalt(
+        &address,
+        &[&["--read-only"][..], &args, &["--write"]].concat(),
+    )
+    .await
+    .unwrap_err();
+    assert!(error.contains("read-only"), "{}", error);
+}
+
+#[tokio::test]
+async fn commands() {
+    let dir = scratch();
+    let address = simulator().await;
+    cobalt(&address, &["read", "PT_101", "Counts[1]"])
+        .await
+        .unwrap();
+    cobalt(&address, &["write", "FT_201", "42.5"])
+        .await
+        .unwrap();
+    cobalt(&address, &["write", "Pump_Run", "true", "--verify"])
+        .await
+        .unwrap();
+    assert!(cobalt(&address, &["read", "Valve"]).await.is_err());
+    cobalt(
+        &address,
+        &[
+            "cip",
+            "raw",
+            "--service",
+            "0x4C",
+            "--path",
+            "PT_101",
+            "--data",
+            "01 00",
+        ],
+    )
+    .await
+    .unwrap();
+    let attribute = ["cip", "get-attribute", "--class", "1", "--instance", "1"];
+    cobalt(&address, &[&attribute[..], &["--attribute", "7"]].concat())
+        .await
+        .unwrap();
+    assert!(
+        cobalt(&address, &[&attribute[..], &["--attribute", "99"]].concat())
+            .await
+            .is_err()
+    );
+
+    let checks = dir.join("checks.toml");
+    std::fs::write(
+        &checks,
+        "[[check]]\ntag = \"FT_201\"\nexpected = 42.5\n\n[[check]]\ntag = \"Pump_Run\"\nexpected = true\n",
+    )
+    .unwrap();
+    let checks = checks.to_str().unwrap();
+    cobalt(&address, &["verify", "--file", checks])
+        .await
+        .unwrap();
+    cobalt(&address, &["write", "Pump_Run", "false"])
+        .await
+        .unwrap();
+    assert!(cobalt(&address, &["verify", "--file", checks])
+        .await
+        .is_err());
+
+    let export = dir.join("tags.json");
+    cobalt(&address, &["list", "--export", export.to_str().unwrap()])
+        .await
+        .unwrap();
+    let tags: Vec<serde_json::Value> =
+        serde_json::from_str(&std::fs::read_to_string(&export).unwrap()).unwrap();
+    assert_eq!(tags.len(), 8);
+    assert_eq!(tags[2]["name"], "Counts");
+    assert_eq!(tags[2]["type"], "DINT[]");
+
+    // The listing was cached for offline use.
+    cobalt(&address, &["list", "--offline", "--filter", "FT_*"])
+        .await
+        .unwrap();
+    cobalt(&address, &["verify", "--file", checks, "--offline"])
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn paged_listing() {
+    let dir = scratch();
+    let tags: String = (1..=60)
+        .map(|idx| {
+            format!(
+                "[[tag]]\nname = \"Flow_Transmitter_{:03}\"\ntype = \"REAL\"\n",
+                idx
+            )
+        })
+        .collect();
+    let address = serve(&tags).await;
+    let export = dir.join("paged.json");
+    let export_arg = export.to_str().unwrap();
+    let exported = || -> Vec<serde_json::Value> {
+        serde_json::from_str(&std::fs::read_to_string(&export).unwrap()).unwrap()
+    };
+    // More tags than a reply holds take several pages.
+    cobalt(&address, &["list", "--export", export_arg])
+        .await
+        .unwrap();
+    let tags = exported();
+    assert_eq!(tags.len(), 60);
+    assert_eq!(tags[59]["name"], "Flow_Transmitter_060");
+    cobalt(
+        &address,
+        &["list", "--resume-from", "41", "--export", export_arg],
+    )
+    .await
+    .unwrap();
+    let tags = exported();
+    assert_eq!(tags.len(), 20);
+    assert_eq!(tags[0]["instance_id"], 41);
+}
+
+#[tokio::test]
+async fn read_only() {
+    let dir = scratch();
+    let address = simulator().await;
+    let refused = |result: Result<(), String>| result.unwrap_err().contains("read-only");
+    assert!(refused(
+        cobalt(&address, &["--read-only", "write", "FT_201", "1"]).await
+    ));
+    assert!(refused(
+        cobalt(&address, &["--read-only", "pulse", "Pump_Run"]).await
+    ));
+    let raw = ["cip", "raw", "--service", "0x4C", "--path", "PT_101"];
+    assert!(refused(
+        cobalt(&address, &[&["--read-only"][..], &raw[..]].concat()).await
+    ));
+    cobalt(&address, &["--read-only", "read", "FT_201"])
+        .await
+        .unwrap();
+
+    let settings = dir.join("read-only.toml");
+    let settings_of = |controller: &str| {
+        std::fs::write(
+            &settings,
+            format!(
+                "read_only = [\"{}\"]\nread_only_tags = [\"pt_*\", \"Status\"]\n",
+                controller
+            ),
+        )
+        .unwrap();
+    };
+    let write = |tag, value| {
+        [
+            "--settings",
+            settings.to_str().unwrap(),
+            "write",
+            tag,
+            value,
+        ]
+    };
+    settings_of("10.9.9.9");
+    assert!(refused(cobalt(&address, &write("PT_101", "1")).await));
+    assert!(refused(cobalt(&address, &write("Status.2", "true")).await));
+    cobalt(&address, &write("FT_201", "1")).await.unwrap();
+    settings_of(&address);
+    assert!(refused(cobalt(&address, &write("FT_201", "1")).await));
+}
+
+#[tokio::test]
+async fn describe() {
+    scratch();
+    let address = serve(
+        r#"
+        [[tag]]
+        name = "Setpoints"
+        type = "REAL[10]"
+
+        [[tag]]
+        name = "Recipe_Max"
+        type = "DINT"
+        constant = true
+
+        [[tag]]
+        name = "Secret"
+        type = "DINT"
+        external_access = "none"
+        "#,
+    )
+    .await;
+    cobalt(&address, &["describe", "Setpoints[3]"])
+        .await
+        .unwrap();
+    cobalt(&address, &["describe", "Recipe_Max"]).await.unwrap();
+    let failure = |result: anyhow::Result<()>| Failure::of(&result.unwrap_err());
+    assert_eq!(
+        failure(run(&address, &["describe", "Secret"]).await),
+        Failure::TagNotFound
+    );
+    // The controller refuses what describe tells.
+    assert_eq!(
+        failure(run(&address, &["write", "Recipe_Max", "3"]).await),
+        Failure::WriteRejected
+    );
+}
+
+#[tokio::test]
+async fn strings() {
+    scratch();
+    let address = serve(
+        r#"
+        [[tag]]
+        name = "Status"
+        type = "STRING"
+        value = "Ready"
+
+        [[tag]]
+        name = "Batch_Id"
+        type = "STRING12"
+        value = "B-0001"
+        "#,
+    )
+    .await;
+    let mut client = connect(&address, &[]).await;
+    assert_eq!(read(&mut client, "Status").await.to_string(), "Ready");
+    // The length of STRING12 comes from its template.
+    let batch = read(&mut client, "Batch_Id").await;
+    assert_eq!(batch.type_name(), "STRING12");
+    assert_eq!(batch.to_string(), "B-0001");
+
+    cobalt(&address, &["write", "Batch_Id", "B-0002 night", "--verify"])
+        .await
+        .unwrap();
+    assert_eq!(
+        read(&mut client, "Batch_Id").await.to_string(),
+        "B-0002 night"
+    );
+    let error = run(&address, &["write", "Batch_Id", "B-0003 morning"])
+        .await
+        .unwrap_err();
+    assert_eq!(Failure::of(&error), Failure::TypeMismatch);
+    assert!(
+        error.to_string().contains("STRING12, which holds 12"),
+        "{}",
+        error
+    );
+    cobalt(&address, &["write", "Status", "Running"])
+        .await
+        .unwrap();
+    cobalt(&address, &["read", "Status", "Batch_Id"])
+        .await
+        .unwrap();
+    assert_eq!(read(&mut client, "Status").await.to_string(), "Running");
+}
+
+#[tokio::test]
+async fn all_scopes() {
+    let dir = scratch();
+    let address = serve(
+        r#"
+        [[tag]]
+        name = "Program:Main.Flow"
+        type = "REAL"
+        value = 3.25
+
+        [[tag]]
+        name = "PT_101"
+        type = "REAL"
+
+        [[tag]]
+        name = "Program:Aux.Run"
+        type = "BOOL"
+
+        [[tag]]
+        name = "Program:Main.Count"
+        type = "DINT"
+        "#,
+    )
+    .await;
+    let export = dir.join("scopes.json");
+    let export = export.to_str().unwrap();
+    cobalt(&address, &["list", "--export", export])
+        .await
+        .unwrap();
+    let tags: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(export).unwrap()).unwrap();
+    let names: Vec<&str> = tags
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| tag["name"].as_str().unwrap())
+        .collect();
+    assert_eq!(names, ["PT_101", "Program:Main", "Program:Aux"]);
+
+    cobalt(
+        &address,
+        &[
+            "list",
+            "--all-scopes",
+            "--sort",
+            "scope",
+            "--export",
+            export,
+        ],
+    )
+    .await
+    .unwrap();
+    let tags: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(export).unwrap()).unwrap();
+    let scoped: Vec<(&str, Option<&str>)> = tags
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|tag| (tag["name"].as_str().unwrap(), tag["program"].as_str()))
+        .collect();
+    assert_eq!(
+        scoped,
+        [
+            ("PT_101", None),
+            ("Program:Aux.Run", Some("Aux")),
+            ("Program:Main.Count", Some("Main")),
+            ("Program:Main.Flow", Some("Main")),
+        ]
+    );
+    // The listed names address the tags.
+    let mut client = connect(&address, &[]).await;
+    assert_eq!(
+        read(&mut client, "Program:Main.Flow").await.to_string(),
+        "3.25"
+    );
+}
+
+#[tokio::test]
+async fn plugin_check() {
+    scratch();
+    let address = simulator().await;
+    let check = |args: &'static [&'static str]| {
+        let address = address.clone();
+        async move {
+            let line = [&["check", "--tag", "PT_101"][..], args].concat();
+            match run(&address, &line).await {
+                Ok(()) => State::Ok,
+                Err(e) => e.downcast_ref::<Alert>().expect("an alert").0,
+            }
+        }
+    };
+    // PT_101 holds 12.5.
+    assert_eq!(
+        check(&["--warning", "20", "--critical", "30"]).await,
+        State::Ok
+    );
+    assert_eq!(
+        check(&["--warning", "10", "--critical", "30"]).await,
+        State::Warning
+    );
+    assert_eq!(
+        check(&["--warning", "10", "--critical", "@12:13"]).await,
+        State::Critical
+    );
+    // Reading a missing tag is an error, which the command reports as
+    // UNKNOWN.
+    let error = run(&address, &["check", "--tag", "Missing", "--warning", "1"])
+        .await
+        .unwrap_err();
+    assert!(error.downcast_ref::<Alert>().is_none());
+}
+
+#[tokio::test]
+async fn plc_profiles() {
+    let dir = scratch();
+    let address = simulator().await;
+    let settings = dir.join("profiles.toml");
+    std::fs::write(
+        &settings,
+        format!(
+            "[plc.sim]\naddress = \"{}\"\nconnected = true\nread_only = true\n",
+            address
+        ),
+    )
+    .unwrap();
+    let plc = ["--settings", settings.to_str().unwrap(), "--plc", "sim"];
+    // The profile stands for the address given.
+    cobalt("10.9.9.9", &[&plc[..], &["read", "PT_101"]].concat())
+        .await
+        .unwrap();
+    let error = cobalt("10.9.9.9", &[&plc[..], &["write", "FT_201", "1"]].concat())
+        .await
+        .unwrap_err();
+    assert!(error.contains("read-only"), "{}", error);
+}
+
+/// Run the cobalt binary against the simulator at the address, answering
+/// its questions with `answers`.
+async fn cobalt_answering(address: &str, args: &[&str], answers: &str) -> std::process::Output {
+    use tokio::io::AsyncWriteExt;
+
+    let dir = scratch();
+    let audit = dir.join("audit.jsonl");
+    // Files cobalt keeps by default, like the journal, go to the scratch
+    // directory too.
+    let mut child = tokio::process::Command::new(env!("CARGO_BIN_EXE_cobalt"))
+        .current_dir(&dir)
+        .args(["-a", address, "--audit-file", audit.to_str().unwrap()])
+        .args(args)
+        .stdin(std::process::Stdio::piped())
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(answers.as_bytes()).await.unwrap();
+    drop(stdin);
+    child.wait_with_output().await.unwrap()
+}
+
+#[tokio::test]
+async fn confirmed_writes() {
+    let dir = scratch();
+    let address = simulator().await;
+    let script = dir.join("confirm.cobalt");
+    std::fs::write(&script, "write Counts[0] 41\nwrite Counts[1] 42\n").unwrap();
+    let script = script.to_str().unwrap();
+    let mut client = connect(&address, &[]).await;
+
+    // Scripts ask before their first write, and write nothing when told no.
+    let output = cobalt_answering(&address, &["--confirm", "run", script], "n\n").await;
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains(&format!("Write 41 to Counts[0] on {}? [y/N]", address)),
+        "{}",
+        stdout
+    );
+    assert_eq!(read(&mut client, "Counts[0]").await, PlcValue::Dint(1));
+
+    // One answer covers the session.
+    let output = cobalt_answering(&address, &["--confirm", "run", script], "y\n").await;
+    assert!(output.status.success(), "{:?}", output);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.matches("[y/N]").count(), 1, "{}", stdout);
+    assert_eq!(read(&mut client, "Counts[0]").await, PlcValue::Dint(41));
+    assert_eq!(read(&mut client, "Counts[1]").await, PlcValue::Dint(42));
+
+    // Protected controllers of the settings file ask too.
+    let settings = dir.join("protected.toml");
+    std::fs::write(&settings, format!("protected = [\"{}\"]", address)).unwrap();
+    let settings = settings.to_str().unwrap();
+    let output = cobalt_answering(
+        &address,
+        &["--settings", settings, "write", "Counts[0]", "7"],
+        "",
+    )
+    .await;
+    assert!(!output.status.success());
+    assert_eq!(read(&mut client, "Counts[0]").await, PlcValue::Dint(41));
+}
+
+#[tokio::test]
+async fn failover() {
+    scratch();
+    let standby = simulator().await;
+    // Nothing listens on a port just let go of.
+    let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let refused = closed.local_addr().unwrap().to_string();
+    drop(closed);
+    cobalt(&refused, &["--failover", &standby, "read", "PT_101"])
+        .await
+        .unwrap();
+
+    // A controller taking sessions but never answering them.
+    let silent = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let primary = silent.local_addr().unwrap().to_string();
+    tokio::spawn(async move {
+        let mut streams = Vec::new();
+        while let Ok((stream, _)) = silent.accept().await {
+            streams.push(stream);
+        }
+    });
+    cobalt(
+        &primary,
+        &["--failover", &standby, "write", "FT_201", "2.5"],
+    )
+    .await
+    .unwrap();
+    let mut client = connect(&standby, &[]).await;
+    assert_eq!(read(&mut client, "FT_201").await, PlcValue::Real(2.5));
+
+    // A write that times out may have been carried out by the primary, so
+    // it fails rather than going to the standby too.
+    let mut failing = connect(&primary, &[]).await;
+    failing.set_failover(&standby, &connection(&[])).unwrap();
+    value::write_unaudited(&mut failing, "FT_201", PlcValue::Real(7.5))
+        .await
+        .unwrap_err();
+    assert_eq!(read(&mut client, "FT_201").await, PlcValue::Real(2.5));
+    assert_eq!(failing.address(), standby);
+    value::write_unaudited(&mut failing, "FT_201", PlcValue::Real(7.5))
+        .await
+        .unwrap();
+    assert_eq!(read(&mut client, "FT_201").await, PlcValue::Real(7.5));
+
+    // Neither are the values of a batched write that timed out sent again
+    // one by one.
+    let mut batched = connect(&primary, &[]).await;
+    batched.set_failover(&standby, &connection(&[])).unwrap();
+    let values = [
+        ("FT_201".to_owned(), PlcValue::Real(9.5)),
+        ("FT_201_Base".to_owned(), PlcValue::Real(9.5)),
+    ];
+    for result in value::write_values(&mut batched, &values).await {
+        result.unwrap_err();
+    }
+    assert_eq!(read(&mut client, "FT_201").await, PlcValue::Real(7.5));
+    assert_eq!(read(&mut client, "FT_201_Base").await, PlcValue::Real(0.0));
+
+    let error = cobalt(&primary, &["-a", &standby, "--failover", &refused, "list"])
+        .await
+        .unwrap_err();
+    assert!(error.contains("single controller"), "{}", error);
+}
+
+#[tokio::test]
+async fn monitor_alarm_writes() {
+    let dir = scratch();
+    let address = simulator().await;
+    let state = dir.join("alarm-state.json");
+    let args = [
+        "monitor",
+        "PT_101",
+        "--interval",
+        "100",
+        "--alarm",
+        "PT_101>10",
+        "--alarm-write",
+        "Status=7",
+        "--alarm-state",
+        state.to_str().unwrap(),
+    ];
+    let monitor = cobalt(&address, &args);
+    // The monitor polls until stopped; give it a few cycles.
+    assert!(tokio::time::timeout(Duration::from_millis(1500), monitor)
+        .await
+        .is_err());
+    let mut client = connect(&address, &[]).await;
+    assert_eq!(read(&mut client, "Status").await, PlcValue::Dint(7));
+}
+
+/// Bridge a fake flow meter on a pseudo terminal to the simulator.
+#[cfg(unix)]
+#[tokio::test]
+async fn bridge_writes_rates() {
+    let dir = scratch();
+    let address = simulator().await;
+    let port = fake_meter(3.0, 1250.0);
+    let config = dir.join("bridge.toml");
+    std::fs::write(
+        &config,
+        format!(
+            r#"
+            port = "{}"
+            slave = 1
+            baudrate = 9600
+            rtu_register_velocity = 0
+            rtu_register_rate = 2
+            pressure_tag = "PT_101"
+            temperature_tag = "TT_101"
+            diameter = 8.0
+            rate_tag_base = "FT_201_Base"
+            rate_tag = "FT_201"
+            stale = {{ action = "hold" }}
+            "#,
+            port
+        ),
+    )
+    .unwrap();
+    let args = ["bridge-write", "--config", config.to_str().unwrap()];
+    let bridge = cobalt(&address, &args);
+    assert!(tokio::time::timeout(Duration::from_millis(1500), bridge)
+        .await
+        .is_err());
+
+    let mut client = connect(&address, &[]).await;
+    assert_eq!(read(&mut client, "FT_201").await, PlcValue::Real(1250.0));
+    match read(&mut client, "FT_201_Base").await {
+        PlcValue::Real(base) => assert!(base > 1250.0, "base rate {}", base),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+/// Answer Modbus RTU reads of holding registers 0 to 3 with two floats, on
+/// a pseudo terminal standing in for the serial port, whose name is
+/// returned.
+#[cfg(unix)]
+fn fake_meter(velocity: f32, rate: f32) -> String {
+    use std::ffi::CStr;
+    use std::io::{Read, Write};
+    use std::os::fd::FromRawFd;
+
+    let (mut master, mut slave) = (0, 0);
+    let name = unsafe {
+        let status = libc::openpty(
+            &mut master,
+            &mut slave,
+            std::ptr::null_mut(),
+            std::ptr::null(),
+            std::ptr::null(),
+        );
+        assert_eq!(status, 0, "openpty failed");
+        CStr::from_ptr(libc::ttyname(slave))
+            .to_string_lossy()
+            .into_owned()
+    };
+    let mut line = unsafe { std::fs::File::from_raw_fd(master) };
+    let mut registers = Vec::new();
+    for value in [velocity, rate] {
+        let bits = value.to_bits();
+        registers.extend_from_slice(&((bits >> 16) as u16).to_be_bytes());
+        registers.extend_from_slice(&(bits as u16).to_be_bytes());
+    }
+    std::thread::spawn(move || {
+        // The slave side stays open, so the line survives the bridge
+        // reopening it.
+        let _slave = slave;
+        let mut request = [0u8; 8];
+        while line.read_exact(&mut request).is_ok() {
+            let start = u16::from_be_bytes([request[2], request[3]]) as usize;
+            let count = u16::from_be_bytes([request[4], request[5]]) as usize;
+            let data = &registers[start * 2..(start + count) * 2];
+            let mut reply = vec![request[0], 0x03, data.len() as u8];
+            reply.extend_from_slice(data);
+            reply.extend_from_slice(&crc16(&reply).to_le_bytes());
+            line.write_all(&reply).unwrap();
+        }
+    });
+    name
+}
+
+#[cfg(unix)]
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0xFFFF;
+    for byte in data {
+        crc ^= *byte as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                crc >> 1 ^ 0xA001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}